@@ -21,7 +21,14 @@ pub fn to_font_data_uri(bytes: &[u8]) -> String {
     format!("data:application/x-font-woff;base64,{}", encoded)
 }
 
+// Raw pixels, e.g. of glyph atlas regions, in the layout described alongside them.
+pub fn to_pixels_data_uri(bytes: &[u8]) -> String {
+    let encoded = base64::encode(bytes);
+    format!("data:application/octet-stream;base64,{}", encoded)
+}
+
+// Uris shorter than the skipped prefix decode as empty rather than panicking.
 pub fn from_data_uri(data_uri: &str) -> Result<Vec<u8>, base64::DecodeError> {
     let start = data_uri.find("base64,").unwrap_or(0) + 7;
-    base64::decode(&data_uri.as_bytes()[start..])
+    base64::decode(data_uri.as_bytes().get(start..).unwrap_or_default())
 }
@@ -0,0 +1,28 @@
+/*
+Copyright 2016 Mozilla
+Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+this file except in compliance with the License. You may obtain a copy of the
+License at http://www.apache.org/licenses/LICENSE-2.0
+Unless required by applicable law or agreed to in writing, software distributed
+under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+CONDITIONS OF ANY KIND, either express or implied. See the License for the
+specific language governing permissions and limitations under the License.
+*/
+
+#![no_main]
+
+extern crate base64_util;
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate rsx_fonts;
+extern crate rsx_images;
+
+use std::str;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(data_uri) = str::from_utf8(data) {
+        let _ = base64_util::from_data_uri(data_uri);
+        let _ = rsx_images::fuzz::encoded_image_from_data_uri(data_uri);
+        let _ = rsx_fonts::fuzz::encoded_font_from_data_uri(data_uri);
+    }
+});
@@ -0,0 +1,29 @@
+/*
+Copyright 2016 Mozilla
+Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+this file except in compliance with the License. You may obtain a copy of the
+License at http://www.apache.org/licenses/LICENSE-2.0
+Unless required by applicable law or agreed to in writing, software distributed
+under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+CONDITIONS OF ANY KIND, either express or implied. See the License for the
+specific language governing permissions and limitations under the License.
+*/
+
+#![no_main]
+
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate rsx_fonts;
+
+use rsx_fonts::fuzz;
+
+// The first byte picks the face, so that collections get explored too.
+fuzz_target!(|data: &[u8]| {
+    let (face_index, bytes) = match data.split_first() {
+        Some((&face_index, bytes)) => (face_index as usize % 4, bytes),
+        None => return
+    };
+    let _ = fuzz::encoded_font(bytes);
+    let _ = fuzz::face_info(bytes, face_index);
+    let _ = fuzz::family_name(bytes, face_index);
+});
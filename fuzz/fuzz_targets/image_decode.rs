@@ -0,0 +1,24 @@
+/*
+Copyright 2016 Mozilla
+Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+this file except in compliance with the License. You may obtain a copy of the
+License at http://www.apache.org/licenses/LICENSE-2.0
+Unless required by applicable law or agreed to in writing, software distributed
+under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+CONDITIONS OF ANY KIND, either express or implied. See the License for the
+specific language governing permissions and limitations under the License.
+*/
+
+#![no_main]
+
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate rsx_images;
+
+use rsx_images::fuzz;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = fuzz::encoded_image(data);
+    let _ = fuzz::dimensions(data);
+    let _ = fuzz::decoded_image(data);
+});
@@ -12,6 +12,8 @@ specific language governing permissions and limitations under the License.
 use std::io;
 use std::result;
 
+use rsx_resource_updates::codes::{self, ErrorCode};
+
 pub type Result<T> = result::Result<T, FileError>;
 
 #[derive(Debug)]
@@ -21,6 +23,24 @@ pub enum FileError {
     FileNotFound
 }
 
+impl FileError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            &FileError::IOError(_) => codes::FILE_IO,
+            &FileError::FileAlreadyAdded => codes::FILE_ALREADY_ADDED,
+            &FileError::FileNotFound => codes::FILE_NOT_FOUND
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            &FileError::IOError(ref err) => format!("I/O error: {}", err),
+            &FileError::FileAlreadyAdded => "File already added".to_string(),
+            &FileError::FileNotFound => "File not found".to_string()
+        }
+    }
+}
+
 impl From<io::Error> for FileError {
     fn from(err: io::Error) -> Self {
         FileError::IOError(err)
@@ -9,9 +9,8 @@ CONDITIONS OF ANY KIND, either express or implied. See the License for the
 specific language governing permissions and limitations under the License.
 */
 
-#![feature(never_type)]
-
 extern crate fnv;
+extern crate rsx_resource_updates;
 extern crate rsx_shared;
 
 pub mod error;
@@ -37,6 +37,10 @@ impl FileId {
     }
 }
 
+// File caches send no resource updates, so theirs can't be constructed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum NoUpdates {}
+
 #[derive(Debug, PartialEq)]
 pub struct SharedFiles(Rc<RefCell<FileCache>>);
 
@@ -62,7 +66,7 @@ impl Deref for SharedFiles {
 
 impl TFileCache for SharedFiles {
     type File = Rc<Vec<u8>>;
-    type ResourceUpdates = !;
+    type ResourceUpdates = NoUpdates;
 
     fn add_file<P>(&mut self, src: P) -> Option<()>
     where
@@ -121,4 +125,10 @@ impl FileCache {
             .ok_or(FileError::FileNotFound)
             .map(Rc::clone)
     }
+
+    pub fn iter_files<'a>(&'a self) -> impl Iterator<Item = (FileId, Rc<Vec<u8>>)> + 'a {
+        self.files
+            .iter()
+            .map(|(&file_id, bytes)| (file_id, Rc::clone(bytes)))
+    }
 }
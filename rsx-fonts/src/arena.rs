@@ -0,0 +1,111 @@
+/*
+Copyright 2016 Mozilla
+Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+this file except in compliance with the License. You may obtain a copy of the
+License at http://www.apache.org/licenses/LICENSE-2.0
+Unless required by applicable law or agreed to in writing, software distributed
+under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+CONDITIONS OF ANY KIND, either express or implied. See the License for the
+specific language governing permissions and limitations under the License.
+*/
+
+// A bump arena for glyphs of runs shaped only for the current frame. Runs
+// are ranges into one growing buffer, and resetting the arena once per frame
+// keeps its capacity, so steady state shaping doesn't allocate.
+
+#[derive(Debug, PartialEq)]
+pub struct ShapingArena<GlyphInstance> {
+    glyphs: Vec<GlyphInstance>,
+    runs: usize,
+    generation: u64
+}
+
+impl<GlyphInstance> Default for ShapingArena<GlyphInstance> {
+    fn default() -> Self {
+        ShapingArena::new()
+    }
+}
+
+impl<GlyphInstance> ShapingArena<GlyphInstance> {
+    pub fn new() -> Self {
+        ShapingArena::with_capacity(0)
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        ShapingArena {
+            glyphs: Vec::with_capacity(capacity),
+            runs: 0,
+            generation: 0
+        }
+    }
+
+    // Invalidates every run handed out so far.
+    pub fn reset(&mut self) {
+        self.glyphs.clear();
+        self.runs = 0;
+        self.generation += 1;
+    }
+
+    pub fn len(&self) -> usize {
+        self.glyphs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.glyphs.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.glyphs.capacity()
+    }
+
+    pub fn runs(&self) -> usize {
+        self.runs
+    }
+
+    // Runs shaped before the last reset have no glyphs.
+    pub fn glyphs(&self, run: &TransientRun) -> &[GlyphInstance] {
+        if run.generation != self.generation {
+            return &[];
+        }
+        &self.glyphs[run.start..run.end]
+    }
+
+    pub(crate) fn push(&mut self, glyph: GlyphInstance) {
+        self.glyphs.push(glyph);
+    }
+
+    // Drops glyphs of a run which failed to shape.
+    pub(crate) fn truncate(&mut self, start: usize) {
+        self.glyphs.truncate(start);
+    }
+
+    pub(crate) fn finish(&mut self, start: usize, width_64: i32, height_64: i32) -> TransientRun {
+        self.runs += 1;
+        TransientRun {
+            width_64,
+            height_64,
+            start,
+            end: self.glyphs.len(),
+            generation: self.generation
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct TransientRun {
+    pub width_64: i32,
+    pub height_64: i32,
+    start: usize,
+    end: usize,
+    generation: u64
+}
+
+impl TransientRun {
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+}
@@ -0,0 +1,499 @@
+/*
+Copyright 2016 Mozilla
+Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+this file except in compliance with the License. You may obtain a copy of the
+License at http://www.apache.org/licenses/LICENSE-2.0
+Unless required by applicable law or agreed to in writing, software distributed
+under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+CONDITIONS OF ANY KIND, either express or implied. See the License for the
+specific language governing permissions and limitations under the License.
+*/
+
+use std::hash::{Hash, Hasher};
+
+use fnv::{FnvHashMap, FnvHasher};
+use rsx_resource_updates::pixels;
+use rsx_resource_updates::traits::TFontKeysAPIExt;
+
+use blur;
+use packing::{AtlasRect, ShelfPacker};
+use types::GlyphBitmap;
+
+pub const DEFAULT_PAGE_SIZE: (u32, u32) = (1024, 1024);
+
+// Glyphs are packed a pixel apart, so that sampling one never bleeds into
+// its neighbours.
+const GUTTER: u32 = 1;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct AtlasSlotId(u32);
+
+impl AtlasSlotId {
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+// Horizontal offsets glyphs are rasterized at when positioned at fractional
+// pixels. Pen positions are quantized to the nearest quarter pixel, so that a
+// glyph has at most four rasterizations per instance.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SubpixelOffset {
+    Zero,
+    Quarter,
+    Half,
+    ThreeQuarters
+}
+
+impl Default for SubpixelOffset {
+    fn default() -> Self {
+        SubpixelOffset::Zero
+    }
+}
+
+impl SubpixelOffset {
+    pub const ALL: [SubpixelOffset; 4] = [
+        SubpixelOffset::Zero,
+        SubpixelOffset::Quarter,
+        SubpixelOffset::Half,
+        SubpixelOffset::ThreeQuarters
+    ];
+
+    // Splits a pen position in 26.6 fixed point into the whole pixel the
+    // glyph is drawn at and the offset it's rasterized with. Positions past
+    // seven eighths of a pixel round up to the next whole pixel.
+    pub fn split_x_64(x_64: i32) -> (i32, SubpixelOffset) {
+        let rounded = x_64 + 8;
+        let pixel = rounded >> 6;
+        let offset = match (rounded & 63) >> 4 {
+            0 => SubpixelOffset::Zero,
+            1 => SubpixelOffset::Quarter,
+            2 => SubpixelOffset::Half,
+            _ => SubpixelOffset::ThreeQuarters
+        };
+        (pixel, offset)
+    }
+
+    pub fn x_64(self) -> i32 {
+        self.index() as i32 * 16
+    }
+
+    pub fn index(self) -> usize {
+        match self {
+            SubpixelOffset::Zero => 0,
+            SubpixelOffset::Quarter => 1,
+            SubpixelOffset::Half => 2,
+            SubpixelOffset::ThreeQuarters => 3
+        }
+    }
+}
+
+// How many subpixel variants the atlas holds, to keep an eye on its growth
+// when glyphs are positioned at fractional pixels.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct AtlasVariantStats {
+    // Distinct glyphs, regardless of the offsets they're rasterized at.
+    pub glyphs: usize,
+    pub variants: usize,
+    pub variants_by_offset: [usize; 4],
+    pub max_variants_per_glyph: usize
+}
+
+// Uses FNV rather than the std hasher, so hashes are stable across runs and
+// processes. The glyph index isn't hashed, since distinct glyphs may well
+// rasterize identically (e.g. spaces).
+pub fn raster_hash(bitmap: &GlyphBitmap) -> u64 {
+    let mut hasher = FnvHasher::default();
+    bitmap.size.hash(&mut hasher);
+    bitmap.bearing.hash(&mut hasher);
+    bitmap.pixels.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn same_raster(a: &GlyphBitmap, b: &GlyphBitmap) -> bool {
+    a.format == b.format && a.alpha_mode == b.alpha_mode && a.size == b.size && a.bearing == b.bearing && a.pixels == b.pixels
+}
+
+// Where a rasterization is stored within the atlas pages, in pixels.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct AtlasPlacement {
+    pub page: u32,
+    pub rect: AtlasRect,
+    allocated: AtlasRect
+}
+
+// Normalized texture coordinates of a placement within its page.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct AtlasUvRect {
+    pub u0: f32,
+    pub v0: f32,
+    pub u1: f32,
+    pub v1: f32
+}
+
+#[derive(Debug, PartialEq)]
+struct AtlasSlot {
+    hash: u64,
+    bitmap: GlyphBitmap,
+    refs: usize,
+    placement: Option<AtlasPlacement>
+}
+
+// Pages only hold glyphs of one pixel format.
+#[derive(Debug, PartialEq)]
+struct AtlasPage {
+    bytes_per_pixel: u32,
+    packer: ShelfPacker
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum PendingUpdate {
+    AddPage(u32),
+    Upload(AtlasSlotId),
+    DeletePage(u32)
+}
+
+// Assigns atlas slots to rasterized glyphs, keyed by font instance, glyph
+// index, blur radius and subpixel offset, where unblurred glyphs use a radius
+// of 0. Identical rasterizations share a slot, which is released once no glyph
+// refers to it.
+//
+// Slots are packed into texture pages as they're added. Pages and the regions
+// glyphs take in them are sent to renderers with `flush_updates`. Page ids are
+// never reused, not even after clearing the atlas.
+#[derive(Debug, PartialEq)]
+pub struct GlyphAtlas<FontInstanceKey: Eq + Hash> {
+    slots: Vec<Option<AtlasSlot>>,
+    free_slots: Vec<AtlasSlotId>,
+    by_hash: FnvHashMap<u64, Vec<AtlasSlotId>>,
+    glyphs: FnvHashMap<(FontInstanceKey, u32, u32, SubpixelOffset), AtlasSlotId>,
+    page_size: (u32, u32),
+    pages: FnvHashMap<u32, AtlasPage>,
+    next_page: u32,
+    pending: Vec<PendingUpdate>
+}
+
+impl<FontInstanceKey> Default for GlyphAtlas<FontInstanceKey>
+where
+    FontInstanceKey: Copy + Eq + Hash
+{
+    fn default() -> Self {
+        GlyphAtlas::new()
+    }
+}
+
+impl<FontInstanceKey> GlyphAtlas<FontInstanceKey>
+where
+    FontInstanceKey: Copy + Eq + Hash
+{
+    pub fn new() -> Self {
+        GlyphAtlas::with_page_size(DEFAULT_PAGE_SIZE)
+    }
+
+    // Glyphs larger than a page aren't packed, and have no placement.
+    pub fn with_page_size(page_size: (u32, u32)) -> Self {
+        GlyphAtlas {
+            slots: Vec::new(),
+            free_slots: Vec::new(),
+            by_hash: FnvHashMap::default(),
+            glyphs: FnvHashMap::default(),
+            page_size,
+            pages: FnvHashMap::default(),
+            next_page: 0,
+            pending: Vec::new()
+        }
+    }
+
+    pub fn page_size(&self) -> (u32, u32) {
+        self.page_size
+    }
+
+    pub fn page_count(&self) -> usize {
+        self.pages.len()
+    }
+
+    // Number of distinct rasterizations stored.
+    pub fn len(&self) -> usize {
+        self.slots.len() - self.free_slots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn glyph_count(&self) -> usize {
+        self.glyphs.len()
+    }
+
+    pub fn used_bytes(&self) -> usize {
+        self.slots
+            .iter()
+            .filter_map(|slot| slot.as_ref())
+            .map(|slot| slot.bitmap.pixels.len())
+            .sum()
+    }
+
+    pub fn variant_stats(&self) -> AtlasVariantStats {
+        let mut stats = AtlasVariantStats::default();
+        let mut per_glyph: FnvHashMap<(FontInstanceKey, u32, u32), usize> = FnvHashMap::default();
+        for &(instance_key, glyph_index, radius, offset) in self.glyphs.keys() {
+            stats.variants += 1;
+            stats.variants_by_offset[offset.index()] += 1;
+            *per_glyph.entry((instance_key, glyph_index, radius)).or_insert(0) += 1;
+        }
+        stats.glyphs = per_glyph.len();
+        stats.max_variants_per_glyph = per_glyph.values().cloned().max().unwrap_or(0);
+        stats
+    }
+
+    pub fn lookup(&self, instance_key: FontInstanceKey, glyph_index: u32) -> Option<AtlasSlotId> {
+        self.lookup_blurred(instance_key, glyph_index, 0)
+    }
+
+    pub fn lookup_at_offset(&self, instance_key: FontInstanceKey, glyph_index: u32, offset: SubpixelOffset) -> Option<AtlasSlotId> {
+        self.glyphs.get(&(instance_key, glyph_index, 0, offset)).cloned()
+    }
+
+    pub fn lookup_blurred(&self, instance_key: FontInstanceKey, glyph_index: u32, radius: u32) -> Option<AtlasSlotId> {
+        let radius = radius.min(blur::MAX_BLUR_RADIUS);
+        self.glyphs
+            .get(&(instance_key, glyph_index, radius, SubpixelOffset::Zero))
+            .cloned()
+    }
+
+    pub fn get(&self, slot_id: AtlasSlotId) -> Option<&GlyphBitmap> {
+        self.slots
+            .get(slot_id.index())
+            .and_then(|slot| slot.as_ref())
+            .map(|slot| &slot.bitmap)
+    }
+
+    pub fn placement(&self, slot_id: AtlasSlotId) -> Option<AtlasPlacement> {
+        self.slots
+            .get(slot_id.index())
+            .and_then(|slot| slot.as_ref())
+            .and_then(|slot| slot.placement)
+    }
+
+    pub fn uv_rect(&self, slot_id: AtlasSlotId) -> Option<AtlasUvRect> {
+        let AtlasPlacement { rect, .. } = self.placement(slot_id)?;
+        let (width, height) = (self.page_size.0 as f32, self.page_size.1 as f32);
+        Some(AtlasUvRect {
+            u0: rect.x as f32 / width,
+            v0: rect.y as f32 / height,
+            u1: (rect.x + rect.width) as f32 / width,
+            v1: (rect.y + rect.height) as f32 / height
+        })
+    }
+
+    // Sends pages added or deleted since the last flush, and the pixels of
+    // glyphs packed into them, returning how many updates were sent.
+    pub fn flush_updates<A>(&mut self, api: &mut A) -> usize
+    where
+        A: TFontKeysAPIExt
+    {
+        let pending: Vec<_> = self.pending.drain(..).collect();
+        let mut sent = 0;
+        for update in pending {
+            match update {
+                PendingUpdate::AddPage(page) => match self.pages.get(&page) {
+                    Some(atlas_page) => api.add_glyph_atlas_page(page, self.page_size, atlas_page.bytes_per_pixel),
+                    None => continue
+                },
+                PendingUpdate::Upload(slot_id) => match self.slots[slot_id.index()] {
+                    Some(AtlasSlot {
+                        ref bitmap,
+                        placement: Some(placement),
+                        ..
+                    }) => {
+                        let rect = placement.rect;
+                        api.update_glyph_atlas_region(placement.page, (rect.x, rect.y), (rect.width, rect.height), &bitmap.pixels)
+                    }
+                    _ => continue
+                },
+                PendingUpdate::DeletePage(page) => api.delete_glyph_atlas_page(page)
+            }
+            sent += 1;
+        }
+        sent
+    }
+
+    pub fn insert(&mut self, instance_key: FontInstanceKey, bitmap: GlyphBitmap) -> AtlasSlotId {
+        self.insert_at_offset(instance_key, bitmap, SubpixelOffset::Zero)
+    }
+
+    // Stores a rasterization made at the given offset. Backends which can't
+    // position glyphs at subpixel offsets produce the same raster for every
+    // offset, in which case the variants share a slot.
+    pub fn insert_at_offset(&mut self, instance_key: FontInstanceKey, bitmap: GlyphBitmap, offset: SubpixelOffset) -> AtlasSlotId {
+        self.insert_keyed((instance_key, bitmap.glyph_index, 0, offset), bitmap)
+    }
+
+    // Stores a blurred alpha variant of the given unblurred rasterization,
+    // for renderers to draw text shadows without blurring at runtime.
+    pub fn insert_blurred(&mut self, instance_key: FontInstanceKey, bitmap: &GlyphBitmap, radius: u32) -> AtlasSlotId {
+        let radius = radius.min(blur::MAX_BLUR_RADIUS);
+        if radius == 0 {
+            return self.insert(instance_key, bitmap.clone());
+        }
+        self.insert_keyed(
+            (instance_key, bitmap.glyph_index, radius, SubpixelOffset::Zero),
+            blur::blur_glyph(bitmap, radius)
+        )
+    }
+
+    fn insert_keyed(&mut self, key: (FontInstanceKey, u32, u32, SubpixelOffset), bitmap: GlyphBitmap) -> AtlasSlotId {
+        if let Some(previous) = self.glyphs.remove(&key) {
+            self.release(previous);
+        }
+
+        let hash = raster_hash(&bitmap);
+        let existing = self.by_hash.get(&hash).and_then(|candidates| {
+            candidates
+                .iter()
+                .find(|&&id| self.get(id).map_or(false, |stored| same_raster(stored, &bitmap)))
+                .cloned()
+        });
+
+        let slot_id = match existing {
+            Some(slot_id) => {
+                if let Some(slot) = self.slots[slot_id.index()].as_mut() {
+                    slot.refs += 1;
+                }
+                slot_id
+            }
+            None => {
+                let placement = self.pack(&bitmap);
+                let slot = Some(AtlasSlot {
+                    hash,
+                    bitmap,
+                    refs: 1,
+                    placement
+                });
+                let slot_id = match self.free_slots.pop() {
+                    Some(slot_id) => {
+                        self.slots[slot_id.index()] = slot;
+                        slot_id
+                    }
+                    None => {
+                        self.slots.push(slot);
+                        AtlasSlotId(self.slots.len() as u32 - 1)
+                    }
+                };
+                self.by_hash
+                    .entry(hash)
+                    .or_insert_with(Vec::new)
+                    .push(slot_id);
+                if placement.is_some() {
+                    self.pending.push(PendingUpdate::Upload(slot_id));
+                }
+                slot_id
+            }
+        };
+
+        self.glyphs.insert(key, slot_id);
+        slot_id
+    }
+
+    // Returns the number of slots freed.
+    pub fn remove_instance(&mut self, instance_key: FontInstanceKey) -> usize {
+        let keys: Vec<_> = self.glyphs
+            .keys()
+            .filter(|&&(key, _, _, _)| key == instance_key)
+            .cloned()
+            .collect();
+        let before = self.len();
+        for key in keys {
+            if let Some(slot_id) = self.glyphs.remove(&key) {
+                self.release(slot_id);
+            }
+        }
+        before - self.len()
+    }
+
+    // Deletes every page, which renderers learn about on the next flush.
+    pub fn clear(&mut self) {
+        let mut pages: Vec<u32> = self.pages.keys().cloned().collect();
+        pages.sort();
+        let mut pending: Vec<_> = self.pending
+            .drain(..)
+            .filter(|update| match update {
+                &PendingUpdate::DeletePage(_) => true,
+                _ => false
+            })
+            .collect();
+        pending.extend(pages.into_iter().map(PendingUpdate::DeletePage));
+
+        let next_page = self.next_page;
+        *self = GlyphAtlas::with_page_size(self.page_size);
+        self.next_page = next_page;
+        self.pending = pending;
+    }
+
+    fn pack(&mut self, bitmap: &GlyphBitmap) -> Option<AtlasPlacement> {
+        let bytes_per_pixel = pixels::bytes_per_pixel(bitmap.format) as u32;
+        let size = (bitmap.size.0 + GUTTER, bitmap.size.1 + GUTTER);
+        if bitmap.size.0 == 0 || bitmap.size.1 == 0 || !AtlasRect::new(0, 0, self.page_size.0, self.page_size.1).contains_size(size) {
+            return None;
+        }
+
+        let mut page_ids: Vec<u32> = self.pages
+            .iter()
+            .filter(|&(_, page)| page.bytes_per_pixel == bytes_per_pixel)
+            .map(|(&id, _)| id)
+            .collect();
+        page_ids.sort();
+        for page in page_ids {
+            if let Some(allocated) = self.pages.get_mut(&page).and_then(|atlas_page| atlas_page.packer.allocate(size)) {
+                return Some(place_in(page, allocated, bitmap.size));
+            }
+        }
+
+        let page = self.next_page;
+        let mut packer = ShelfPacker::new(self.page_size);
+        let allocated = packer.allocate(size)?;
+        self.next_page += 1;
+        self.pages.insert(page, AtlasPage { bytes_per_pixel, packer });
+        self.pending.push(PendingUpdate::AddPage(page));
+        Some(place_in(page, allocated, bitmap.size))
+    }
+
+    fn release(&mut self, slot_id: AtlasSlotId) {
+        let freed = match self.slots[slot_id.index()].as_mut() {
+            Some(slot) => {
+                slot.refs -= 1;
+                slot.refs == 0
+            }
+            None => false
+        };
+        if !freed {
+            return;
+        }
+        if let Some(slot) = self.slots[slot_id.index()].take() {
+            if let Some(placement) = slot.placement {
+                if let Some(page) = self.pages.get_mut(&placement.page) {
+                    page.packer.deallocate(placement.allocated);
+                }
+            }
+            let unused_hash = match self.by_hash.get_mut(&slot.hash) {
+                Some(candidates) => {
+                    candidates.retain(|&id| id != slot_id);
+                    candidates.is_empty()
+                }
+                None => false
+            };
+            if unused_hash {
+                self.by_hash.remove(&slot.hash);
+            }
+        }
+        self.free_slots.push(slot_id);
+    }
+}
+
+fn place_in(page: u32, allocated: AtlasRect, size: (u32, u32)) -> AtlasPlacement {
+    AtlasPlacement {
+        page,
+        rect: AtlasRect::new(allocated.x, allocated.y, size.0, size.1),
+        allocated
+    }
+}
@@ -0,0 +1,1184 @@
+/*
+Copyright 2016 Mozilla
+Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+this file except in compliance with the License. You may obtain a copy of the
+License at http://www.apache.org/licenses/LICENSE-2.0
+Unless required by applicable law or agreed to in writing, software distributed
+under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+CONDITIONS OF ANY KIND, either express or implied. See the License for the
+specific language governing permissions and limitations under the License.
+*/
+
+use std::collections::hash_map::Entry;
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+use std::str;
+use std::sync::Arc;
+
+use fnv::{FnvHashMap, FnvHasher};
+use rsx_resource_updates::display::SubpixelLayout;
+use rsx_resource_updates::pixels::{self, AlphaMode};
+use rsx_shared::traits::{TFontInstanceKey, TFontKey, TGlyphInstance};
+
+use arena::{ShapingArena, TransientRun};
+use atlas::SubpixelOffset;
+use bidi::{self, DirectionRun, VisualRun};
+use coverage::CoverageReport;
+use error::{FontError, Result, ShapingError};
+use fixed::Fixed26_6;
+use glyph_load::LcdFilter;
+use gsub;
+#[cfg(feature = "harfbuzz")]
+use harfbuzz;
+use palette::{self, ColorPalette};
+use sfnt;
+#[cfg(feature = "harfbuzz")]
+use shaping::{self, ShapingStrategy};
+use shaping_cache::ShapingKey;
+#[cfg(feature = "shared-shaping-cache")]
+use shared_shaping::{SharedShapingCache, SharedShapingKey};
+use types::{
+    DesignGlyphMetrics,
+    FontId,
+    FontInstance,
+    FontSizeMetrics,
+    GlyphBitmap,
+    GlyphDimensions,
+    GlyphInkBounds,
+    GlyphLinearAdvance,
+    GlyphStore,
+    GlyphVerticalBearings,
+    GlyphsArray,
+    ImagePixelFormat,
+    LinearGlyphMetrics,
+    MEASURED_TEXT_CACHE_CAPACITY
+};
+
+// The font operations caches rely on. `FontContext` implements them using
+// FreeType, while `MockFontBackend` produces deterministic metrics so that
+// layout tests don't depend on the platform's rasterizer.
+pub trait TFontBackend {
+    fn lcd_filter(&self) -> LcdFilter;
+
+    fn set_lcd_filter(&mut self, filter: LcdFilter) -> Result<()>;
+
+    fn add_face(&mut self, font_id: FontId, bytes: &Rc<Vec<u8>>, face_index: usize) -> Result<()>;
+
+    fn remove_face(&mut self, font_id: FontId) -> Result<()>;
+
+    fn font_ids(&self) -> Vec<FontId>;
+
+    fn get_bytes(&self, font_id: FontId) -> Result<Rc<Vec<u8>>>;
+
+    fn get_face_index(&self, font_id: FontId) -> Result<usize>;
+
+    fn get_family_name(&self, font_id: FontId) -> Result<&str>;
+
+    fn get_coverage(&self, font_id: FontId) -> Result<CoverageReport>;
+
+    // Backends holding actual font files have runs shaped by HarfBuzz, others
+    // keep shaping them one char at a time.
+    #[cfg(feature = "harfbuzz")]
+    fn supports_complex_shaping(&self) -> bool {
+        false
+    }
+
+    // Palettes of COLR/CPAL color fonts, none for other faces.
+    fn get_palettes(&self, font_id: FontId) -> Result<Vec<ColorPalette>> {
+        let bytes = self.get_bytes(font_id)?;
+        let face_index = self.get_face_index(font_id)?;
+        Ok(sfnt::face_table(&bytes, face_index, b"CPAL").map_or_else(Vec::new, palette::read_palettes))
+    }
+
+    fn get_glyph_index<FontKey, FontInstanceKey, GlyphInstance>(
+        &self,
+        instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>,
+        c: char
+    ) -> Result<u32>;
+
+    fn get_glyph_dimensions<FontKey, FontInstanceKey, GlyphInstance>(
+        &self,
+        instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>,
+        c: char
+    ) -> Result<GlyphDimensions>;
+
+    // Dimensions of a glyph picked by index rather than by char, e.g. one an
+    // OpenType feature substitutes. Backends only measuring chars return `None`,
+    // leaving chars unsubstituted.
+    fn get_glyph_index_dimensions<FontKey, FontInstanceKey, GlyphInstance>(
+        &self,
+        _: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>,
+        _: u32
+    ) -> Result<Option<GlyphDimensions>> {
+        Ok(None)
+    }
+
+    // Backends without outline metrics approximate the ink with the glyph box
+    // sitting on the baseline.
+    fn get_glyph_ink_bounds<FontKey, FontInstanceKey, GlyphInstance>(
+        &self,
+        instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>,
+        c: char
+    ) -> Result<GlyphInkBounds> {
+        let dimensions = self.get_glyph_dimensions(instance, c)?;
+        Ok(GlyphInkBounds {
+            left_64: 0,
+            top_64: -dimensions.height_64,
+            right_64: dimensions.width_64,
+            bottom_64: 0
+        })
+    }
+
+    // Backends without vertical metrics center glyphs in their column, the
+    // way FreeType does for faces without vertical tables.
+    fn get_glyph_vertical_bearings<FontKey, FontInstanceKey, GlyphInstance>(
+        &self,
+        instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>,
+        c: char
+    ) -> Result<GlyphVerticalBearings> {
+        let dimensions = self.get_glyph_dimensions(instance, c)?;
+        Ok(GlyphVerticalBearings {
+            bearing_x_64: -dimensions.width_64 / 2,
+            bearing_y_64: (dimensions.vert_advance_64 - dimensions.height_64) / 2
+        })
+    }
+
+    fn get_glyph_linear_advance<FontKey, FontInstanceKey, GlyphInstance>(
+        &self,
+        instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>,
+        c: char
+    ) -> Result<GlyphLinearAdvance>;
+
+    // Advances in design units, unscaled. Backends without access to the
+    // face's tables don't have them.
+    fn get_linear_glyph_metrics<FontKey, FontInstanceKey, GlyphInstance>(
+        &self,
+        _: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>,
+        _: char
+    ) -> Result<LinearGlyphMetrics> {
+        Err(FontError::FaceMetricsMissing)
+    }
+
+    // Unscaled metrics of the face, not of any instance of it, so querying
+    // them leaves the face's size alone. Backends without access to the
+    // face's tables don't have them.
+    fn get_design_glyph_metrics(&self, _: FontId, _: &[char]) -> Result<Vec<DesignGlyphMetrics>> {
+        Err(FontError::FaceMetricsMissing)
+    }
+
+    // Adjustment between two glyphs drawn next to each other, in 26.6 pixels
+    // with y growing downwards. It's horizontal unless the instance's
+    // transform rotates glyphs. Backends without kerning data don't adjust anything.
+    fn get_kerning<FontKey, FontInstanceKey, GlyphInstance>(
+        &self,
+        _: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>,
+        _: u32,
+        _: u32
+    ) -> Result<(i32, i32)> {
+        Ok((0, 0))
+    }
+
+    fn get_glyph_bitmap<FontKey, FontInstanceKey, GlyphInstance>(
+        &self,
+        instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>,
+        c: char
+    ) -> Result<GlyphBitmap>;
+
+    fn get_glyph_subpixel_bitmap<FontKey, FontInstanceKey, GlyphInstance>(
+        &self,
+        instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>,
+        c: char,
+        layout: SubpixelLayout
+    ) -> Result<GlyphBitmap>;
+
+    // Alpha coverage of a glyph picked by index rather than by char, e.g. from
+    // a shaped run, so that renderers without a rasterizer can draw it.
+    fn rasterize_glyph<FontKey, FontInstanceKey, GlyphInstance>(
+        &self,
+        _: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>,
+        _: u32
+    ) -> Result<GlyphBitmap> {
+        Err(FontError::RasterizationUnsupported)
+    }
+
+    // Rasterizes the glyph shifted right by the offset. Backends which can't
+    // position glyphs at subpixel offsets ignore it.
+    fn get_glyph_subpixel_bitmap_at_offset<FontKey, FontInstanceKey, GlyphInstance>(
+        &self,
+        instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>,
+        c: char,
+        layout: SubpixelLayout,
+        _: SubpixelOffset
+    ) -> Result<GlyphBitmap> {
+        self.get_glyph_subpixel_bitmap(instance, c, layout)
+    }
+
+    fn get_global_size_metrics<FontKey, FontInstanceKey, GlyphInstance>(
+        &self,
+        instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>
+    ) -> Result<FontSizeMetrics>;
+
+    fn shape_text_h<T, FontKey, FontInstanceKey, GlyphInstance>(
+        &self,
+        instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>,
+        text: T
+    ) -> Result<GlyphStore<FontKey, FontInstanceKey, GlyphInstance>>
+    where
+        T: AsRef<str>,
+        FontKey: TFontKey,
+        FontInstanceKey: TFontInstanceKey,
+        GlyphInstance: TGlyphInstance
+    {
+        let text = text.as_ref();
+
+        let mut hasher = FnvHasher::default();
+        text.hash(&mut hasher);
+
+        let generation_id = hasher.finish();
+        let key = ShapingKey::for_instance(instance, generation_id, false);
+        let cache = instance.shaping_cache();
+        if let Some(store) = cache.get(&key) {
+            return Ok(store);
+        }
+
+        let store = shape_run(self, instance, text, generation_id, false)?.into_store(instance, generation_id);
+        cache.insert(key, GlyphStore::clone(&store));
+        Ok(store)
+    }
+
+    fn shape_text_v<T, FontKey, FontInstanceKey, GlyphInstance>(
+        &self,
+        instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>,
+        text: T
+    ) -> Result<GlyphStore<FontKey, FontInstanceKey, GlyphInstance>>
+    where
+        T: AsRef<str>,
+        FontKey: TFontKey,
+        FontInstanceKey: TFontInstanceKey,
+        GlyphInstance: TGlyphInstance
+    {
+        let text = text.as_ref();
+
+        let mut hasher = FnvHasher::default();
+        text.hash(&mut hasher);
+
+        let generation_id = hasher.finish();
+        let key = ShapingKey::for_instance(instance, generation_id, true);
+        let cache = instance.shaping_cache();
+        if let Some(store) = cache.get(&key) {
+            return Ok(store);
+        }
+
+        let store = shape_run(self, instance, text, generation_id, true)?.into_store(instance, generation_id);
+        cache.insert(key, GlyphStore::clone(&store));
+        Ok(store)
+    }
+
+    // Lays out the text without emitting glyphs, for layout passes only needing
+    // sizes. Sizes are memoized apart from shaped runs, see `measure_text_h`.
+    fn measure_text_h<T, FontKey, FontInstanceKey, GlyphInstance>(
+        &self,
+        instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>,
+        text: T
+    ) -> Result<(i32, i32)>
+    where
+        T: AsRef<str>,
+        GlyphInstance: TGlyphInstance
+    {
+        let text = text.as_ref();
+
+        let mut hasher = FnvHasher::default();
+        text.hash(&mut hasher);
+        let text_hash = hasher.finish();
+
+        let key = ShapingKey::for_instance(instance, text_hash, false);
+        if let Some(size) = instance.shaping_cache().with(&key, |store| (store.width_64, store.height_64)) {
+            return Ok(size);
+        }
+        if let Some(&size) = instance.measured_text_cache.borrow().get(&text_hash) {
+            return Ok(size);
+        }
+
+        let laid_out = layout_glyphs(self, instance, text, false, None, |_, _, _| {})?;
+        let size = (laid_out.width_64, laid_out.height_64);
+        let mut cache = instance.measured_text_cache.borrow_mut();
+        if cache.len() >= MEASURED_TEXT_CACHE_CAPACITY {
+            cache.clear();
+        }
+        cache.insert(text_hash, size);
+        Ok(size)
+    }
+
+    // Shapes into a caller owned arena rather than a glyph store, for runs only
+    // needed during the current frame, e.g. measured during layout. Runs the
+    // instance already cached are copied, but new ones aren't cached anywhere.
+    fn shape_text_transient<T, FontKey, FontInstanceKey, GlyphInstance>(
+        &self,
+        instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>,
+        text: T,
+        vertical: bool,
+        arena: &mut ShapingArena<GlyphInstance>
+    ) -> Result<TransientRun>
+    where
+        T: AsRef<str>,
+        GlyphInstance: TGlyphInstance + Clone
+    {
+        let text = text.as_ref();
+
+        let mut hasher = FnvHasher::default();
+        text.hash(&mut hasher);
+
+        let key = ShapingKey::for_instance(instance, hasher.finish(), vertical);
+        let cached = instance.shaping_cache().with(&key, |store| {
+            let start = arena.len();
+            for glyph in store.glyphs.0.iter() {
+                arena.push(glyph.clone());
+            }
+            arena.finish(start, store.width_64, store.height_64)
+        });
+        if let Some(run) = cached {
+            return Ok(run);
+        }
+
+        layout_transient_run(self, instance, text, vertical, arena)
+    }
+}
+
+// Glyph positions of a shaped run, independent of the keys of the cache that shaped it.
+#[derive(Debug, Clone)]
+pub(crate) struct ShapedRun {
+    width_64: i32,
+    height_64: i32,
+    glyphs: Arc<[(u32, i32, i32)]>,
+    ink_bounds: Option<Arc<Vec<GlyphInkBounds>>>,
+    // Byte offset of each glyph's cluster in the text.
+    clusters: Arc<Vec<u32>>,
+    text_len: u32,
+    direction_runs: Option<Arc<Vec<DirectionRun>>>
+}
+
+impl ShapedRun {
+    fn into_store<FontKey, FontInstanceKey, GlyphInstance>(
+        self,
+        instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>,
+        generation_id: u64
+    ) -> GlyphStore<FontKey, FontInstanceKey, GlyphInstance>
+    where
+        FontKey: TFontKey,
+        FontInstanceKey: TFontInstanceKey,
+        GlyphInstance: TGlyphInstance
+    {
+        instance
+            .used_glyphs
+            .borrow_mut()
+            .extend(self.glyphs.iter().map(|&(glyph_index, _, _)| glyph_index));
+        let glyphs: Vec<GlyphInstance> = self.glyphs
+            .iter()
+            .map(|&(glyph_index, x, y)| GlyphInstance::new(glyph_index, x, y))
+            .collect();
+
+        GlyphStore {
+            generation_id,
+            font_key: instance.external_key(),
+            font_instance_key: instance.external_instance_key(),
+            width_64: self.width_64,
+            height_64: self.height_64,
+            glyphs: GlyphsArray(Rc::from(glyphs.into_boxed_slice())),
+            source: None,
+            ink_bounds: self.ink_bounds.map(|bounds| Rc::new(Vec::clone(&bounds))),
+            clusters: Some(Rc::new(Vec::clone(&self.clusters))),
+            text_len: self.text_len,
+            direction_runs: self.direction_runs.map(|runs| Rc::new(Vec::clone(&runs)))
+        }
+    }
+}
+
+#[cfg(not(feature = "shared-shaping-cache"))]
+fn shape_run<B, FontKey, FontInstanceKey, GlyphInstance>(
+    backend: &B,
+    instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>,
+    text: &str,
+    _text_hash: u64,
+    vertical: bool
+) -> Result<ShapedRun>
+where
+    B: TFontBackend + ?Sized
+{
+    layout_run(backend, instance, text, vertical)
+}
+
+#[cfg(feature = "shared-shaping-cache")]
+fn shape_run<B, FontKey, FontInstanceKey, GlyphInstance>(
+    backend: &B,
+    instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>,
+    text: &str,
+    text_hash: u64,
+    vertical: bool
+) -> Result<ShapedRun>
+where
+    B: TFontBackend + ?Sized
+{
+    let key = match SharedShapingKey::for_instance(instance, text_hash, vertical) {
+        Some(key) => key,
+        None => return layout_run(backend, instance, text, vertical)
+    };
+    let cache = SharedShapingCache::global();
+    if let Some(run) = cache.get(&key) {
+        return Ok(run);
+    }
+    let run = layout_run(backend, instance, text, vertical)?;
+    cache.insert(key, ShapedRun::clone(&run));
+    Ok(run)
+}
+
+fn layout_run<B, FontKey, FontInstanceKey, GlyphInstance>(
+    backend: &B,
+    instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>,
+    text: &str,
+    vertical: bool
+) -> Result<ShapedRun>
+where
+    B: TFontBackend + ?Sized
+{
+    let mut glyphs = Vec::with_capacity(text.len());
+    let mut ink_bounds = if instance.ink_bounds() {
+        Some(Vec::with_capacity(text.len()))
+    } else {
+        None
+    };
+    let laid_out = layout_glyphs(backend, instance, text, vertical, ink_bounds.as_mut(), |glyph_index, x_64, y_64| {
+        glyphs.push((glyph_index, x_64, y_64))
+    })?;
+
+    Ok(ShapedRun {
+        width_64: laid_out.width_64,
+        height_64: laid_out.height_64,
+        glyphs: Arc::from(glyphs.into_boxed_slice()),
+        ink_bounds: ink_bounds.map(Arc::new),
+        clusters: Arc::new(laid_out.clusters),
+        text_len: text.len() as u32,
+        direction_runs: laid_out.direction_runs.map(Arc::new)
+    })
+}
+
+// Shapes into the arena, bypassing every cache. See `TFontBackend::shape_text_transient`.
+fn layout_transient_run<B, FontKey, FontInstanceKey, GlyphInstance>(
+    backend: &B,
+    instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>,
+    text: &str,
+    vertical: bool,
+    arena: &mut ShapingArena<GlyphInstance>
+) -> Result<TransientRun>
+where
+    B: TFontBackend + ?Sized,
+    GlyphInstance: TGlyphInstance
+{
+    let start = arena.len();
+    let mut used_glyphs = instance.used_glyphs.borrow_mut();
+    let laid_out = layout_glyphs(backend, instance, text, vertical, None, |glyph_index, x_64, y_64| {
+        used_glyphs.insert(glyph_index);
+        arena.push(GlyphInstance::new(glyph_index, x_64, y_64))
+    });
+    match laid_out {
+        Ok(laid_out) => Ok(arena.finish(start, laid_out.width_64, laid_out.height_64)),
+        Err(err) => {
+            arena.truncate(start);
+            Err(err)
+        }
+    }
+}
+
+struct LaidOutRun {
+    width_64: i32,
+    height_64: i32,
+    clusters: Vec<u32>,
+    direction_runs: Option<Vec<DirectionRun>>
+}
+
+// Positions the glyphs of a run, handing each one to `emit`. Ink bounds are
+// collected when a vector is given for them.
+fn layout_glyphs<B, F, FontKey, FontInstanceKey, GlyphInstance>(
+    backend: &B,
+    instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>,
+    text: &str,
+    vertical: bool,
+    mut ink_bounds: Option<&mut Vec<GlyphInkBounds>>,
+    mut emit: F
+) -> Result<LaidOutRun>
+where
+    B: TFontBackend + ?Sized,
+    F: FnMut(u32, i32, i32)
+{
+    #[cfg(feature = "harfbuzz")]
+    {
+        if backend.supports_complex_shaping() && needs_complex_shaping(backend, instance, text)? {
+            instance.count_shaped_run(true);
+            return layout_complex_glyphs(backend, instance, text, vertical, ink_bounds, emit);
+        }
+    }
+    instance.count_shaped_run(false);
+
+    let substitutions = feature_substitutions(backend, instance)?;
+    let mut pen_position = Fixed26_6::ZERO;
+    // Where transforms rotate or shear glyphs, the pen drifts across the run too.
+    let mut cross_position = Fixed26_6::ZERO;
+    let font_size_metrics = backend.get_global_size_metrics(instance)?;
+    let pen_baseline_64 = font_size_metrics.ascender_64;
+    let column_center_64 = font_size_metrics.max_advance_64 / 2;
+    let kerning = !vertical && instance.kerning();
+    let mut previous_glyph = None;
+    // With linear advances the pen moves in float math, and only glyph
+    // positions are rounded, so rounding errors don't add up along the run.
+    let linear_advances = instance.linear_advances();
+    let mut linear_pen_64 = 0.0;
+
+    // Vertical runs stack chars top to bottom whatever their direction.
+    let visual_runs = if vertical { None } else { bidi::visual_runs(text) };
+    let reordered = visual_runs.is_some();
+    let logical = [VisualRun::ltr(0..text.len())];
+    let segments = visual_runs.as_ref().map_or(&logical[..], |runs| &runs[..]);
+    let mut clusters = Vec::with_capacity(text.len());
+    let mut direction_runs = Vec::new();
+    let mut glyph_count = 0;
+
+    for segment in segments {
+        let segment_text = &text[segment.range.clone()];
+        let rtl = segment.is_rtl();
+        let mut forward = segment_text.char_indices();
+        let mut backward = segment_text.char_indices().rev();
+        let first_glyph = glyph_count;
+
+        loop {
+            let next = if rtl { backward.next() } else { forward.next() };
+            let (offset, c) = match next {
+                Some((offset, c)) if rtl => (offset, bidi::mirrored(c)),
+                Some(next) => next,
+                None => break
+            };
+            let mut dimensions = backend.get_glyph_dimensions(instance, c)?;
+            let mut substituted = false;
+            if let Some(&substitute) = substitutions.get(&dimensions.glyph_index) {
+                if let Some(substitute_dimensions) = backend.get_glyph_index_dimensions(instance, substitute)? {
+                    dimensions = substitute_dimensions;
+                    substituted = true;
+                }
+            }
+            let GlyphDimensions {
+                glyph_index,
+                hori_advance_64,
+                vert_advance_64,
+                hori_advance_y_64,
+                vert_advance_x_64,
+                ..
+            } = dimensions;
+
+            if let Some(previous_glyph) = previous_glyph {
+                let (kerning_64, kerning_y_64) = backend.get_kerning(instance, previous_glyph, glyph_index)?;
+                pen_position = advance(pen_position, kerning_64)?;
+                cross_position = advance(cross_position, kerning_y_64)?;
+                linear_pen_64 += f64::from(kerning_64);
+            }
+            if kerning {
+                previous_glyph = Some(glyph_index);
+            }
+
+            let (x_64, y_64) = if vertical {
+                let (x_offset_64, y_offset_64) = vertical_origin_offset(backend, instance, c)?;
+                (column_center_64 + cross_position.raw() + x_offset_64, pen_position.raw() + y_offset_64)
+            } else {
+                (pen_position.raw(), pen_baseline_64 + cross_position.raw())
+            };
+            if let Some(ref mut ink_bounds) = ink_bounds {
+                ink_bounds.push(backend.get_glyph_ink_bounds(instance, c)?.translate(x_64, y_64));
+            }
+            clusters.push((segment.range.start + offset) as u32);
+
+            emit(glyph_index, x_64, y_64);
+            glyph_count += 1;
+            cross_position = advance(cross_position, if vertical { vert_advance_x_64 } else { hori_advance_y_64 })?;
+            if linear_advances {
+                // Linear metrics are per char, so substitutes advance by their hinted ones.
+                let (hori_linear_64, vert_linear_64) = if substituted {
+                    (f64::from(hori_advance_64), f64::from(vert_advance_64))
+                } else {
+                    backend
+                        .get_linear_glyph_metrics(instance, c)?
+                        .scaled_64(instance)
+                };
+                linear_pen_64 += if vertical { vert_linear_64 } else { hori_linear_64 };
+                pen_position = Fixed26_6::from_raw_i64(linear_pen_64.round() as i64).ok_or(ShapingError::Overflow)?;
+            } else if vertical {
+                pen_position = advance(pen_position, vert_advance_64)?;
+            } else {
+                pen_position = advance(pen_position, hori_advance_64)?;
+            }
+        }
+
+        direction_runs.push(segment.to_direction_run((first_glyph, glyph_count)));
+    }
+
+    let (width_64, height_64) = if vertical {
+        (font_size_metrics.max_advance_64, pen_position.raw())
+    } else {
+        (pen_position.raw(), font_size_metrics.height_64)
+    };
+
+    Ok(LaidOutRun {
+        width_64,
+        height_64,
+        clusters,
+        direction_runs: if reordered {
+            Some(direction_runs)
+        } else {
+            None
+        }
+    })
+}
+
+// Single substitutions of the instance's enabled features, read from the face
+// once per feature list. Faces without a GSUB table substitute nothing.
+fn feature_substitutions<B, FontKey, FontInstanceKey, GlyphInstance>(
+    backend: &B,
+    instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>
+) -> Result<Rc<FnvHashMap<u32, u32>>>
+where
+    B: TFontBackend + ?Sized
+{
+    if let Some(ref substitutions) = *instance.feature_substitutions.borrow() {
+        return Ok(Rc::clone(substitutions));
+    }
+    let substitutions = if instance.has_enabled_features() {
+        let font_id = instance.font_id();
+        let bytes = backend.get_bytes(font_id)?;
+        let features = instance.features();
+        sfnt::face_table(&bytes, backend.get_face_index(font_id)?, b"GSUB")
+            .map_or_else(FnvHashMap::default, |table| gsub::single_substitutions(table, &features))
+    } else {
+        FnvHashMap::default()
+    };
+    let substitutions = Rc::new(substitutions);
+    *instance.feature_substitutions.borrow_mut() = Some(Rc::clone(&substitutions));
+    Ok(substitutions)
+}
+
+// Glyphs are drawn from their horizontal origin on the baseline, so vertical
+// runs place that relative to the vertical origin, through the ink's top left.
+fn vertical_origin_offset<B, FontKey, FontInstanceKey, GlyphInstance>(
+    backend: &B,
+    instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>,
+    c: char
+) -> Result<(i32, i32)>
+where
+    B: TFontBackend + ?Sized
+{
+    let bearings = backend.get_glyph_vertical_bearings(instance, c)?;
+    let ink_bounds = backend.get_glyph_ink_bounds(instance, c)?;
+    Ok((bearings.bearing_x_64 - ink_bounds.left_64, bearings.bearing_y_64 - ink_bounds.top_64))
+}
+
+// The simple shaper only kerns with legacy kern tables, so faces kerning
+// through GPOS alone need HarfBuzz even for Latin text. Likewise, it only
+// applies the single substitutions of enabled features.
+#[cfg(feature = "harfbuzz")]
+fn needs_complex_shaping<B, FontKey, FontInstanceKey, GlyphInstance>(
+    backend: &B,
+    instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>,
+    text: &str
+) -> Result<bool>
+where
+    B: TFontBackend + ?Sized
+{
+    match instance.shaping_strategy() {
+        ShapingStrategy::Simple => return Ok(false),
+        ShapingStrategy::Complex => return Ok(true),
+        ShapingStrategy::Auto => {}
+    }
+    if !shaping::is_simple_script(text) || instance.has_enabled_features() {
+        return Ok(true);
+    }
+    if !instance.kerning() {
+        return Ok(false);
+    }
+    let legacy_kerning = match instance.legacy_kerning.get() {
+        Some(legacy_kerning) => legacy_kerning,
+        None => {
+            let font_id = instance.font_id();
+            let bytes = backend.get_bytes(font_id)?;
+            let legacy_kerning = sfnt::face_table(&bytes, backend.get_face_index(font_id)?, b"kern").is_some();
+            instance.legacy_kerning.set(Some(legacy_kerning));
+            legacy_kerning
+        }
+    };
+    Ok(!legacy_kerning)
+}
+
+// Lays out the glyphs HarfBuzz picked, with its offsets and advances going
+// through the instance's transform. Ink bounds can only be queried per char,
+// so each glyph gets those of the first char of its cluster.
+#[cfg(feature = "harfbuzz")]
+fn layout_complex_glyphs<B, F, FontKey, FontInstanceKey, GlyphInstance>(
+    backend: &B,
+    instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>,
+    text: &str,
+    vertical: bool,
+    mut ink_bounds: Option<&mut Vec<GlyphInkBounds>>,
+    mut emit: F
+) -> Result<LaidOutRun>
+where
+    B: TFontBackend + ?Sized,
+    F: FnMut(u32, i32, i32)
+{
+    let font_id = instance.font_id();
+    let bytes = backend.get_bytes(font_id)?;
+    let face_index = backend.get_face_index(font_id)?;
+    let visual_runs = if vertical { None } else { bidi::visual_runs(text) };
+    let reordered = visual_runs.is_some();
+    let logical = [VisualRun::ltr(0..text.len())];
+    let segments = visual_runs.as_ref().map_or(&logical[..], |runs| &runs[..]);
+
+    let mut clusters = Vec::with_capacity(text.len());
+    let mut direction_runs = Vec::new();
+    let mut pen_position = Fixed26_6::ZERO;
+    let mut cross_position = Fixed26_6::ZERO;
+    let font_size_metrics = backend.get_global_size_metrics(instance)?;
+    let pen_baseline_64 = font_size_metrics.ascender_64;
+    let transform = instance.transform();
+    let features = instance.features();
+
+    // HarfBuzz orders the glyphs of right to left runs visually, and mirrors them.
+    for segment in segments {
+        let segment_text = &text[segment.range.clone()];
+        let shaped = harfbuzz::shape(
+            &bytes,
+            face_index,
+            instance.pixel_size_64(),
+            segment_text,
+            vertical,
+            segment.is_rtl(),
+            instance.kerning(),
+            &features
+        )?;
+        let first_glyph = clusters.len();
+
+        for glyph in &shaped {
+            let cluster = segment.range.start as u32 + glyph.cluster;
+            let (x_offset, y_offset) = transform.apply_64(glyph.x_offset_64.into(), glyph.y_offset_64.into());
+            let (x_advance, y_advance) = transform.apply_64(glyph.x_advance_64.into(), glyph.y_advance_64.into());
+            let (x_64, y_64) = if vertical {
+                (
+                    checked_i32(i64::from(cross_position.raw()) + x_offset)?,
+                    checked_i32(i64::from(pen_position.raw()) - y_offset)?
+                )
+            } else {
+                (
+                    checked_i32(i64::from(pen_position.raw()) + x_offset)?,
+                    checked_i32(i64::from(pen_baseline_64) + i64::from(cross_position.raw()) - y_offset)?
+                )
+            };
+            if let Some(ref mut ink_bounds) = ink_bounds {
+                let bounds = match text.get(cluster as usize..).and_then(|cluster| cluster.chars().next()) {
+                    Some(c) => backend.get_glyph_ink_bounds(instance, c)?.translate(x_64, y_64),
+                    None => GlyphInkBounds::default()
+                };
+                ink_bounds.push(bounds);
+            }
+
+            emit(glyph.glyph_index, x_64, y_64);
+            clusters.push(cluster);
+            // HarfBuzz's y grows upwards, like the transform's.
+            if vertical {
+                pen_position = advance(pen_position, checked_i32(-y_advance)?)?;
+                cross_position = advance(cross_position, checked_i32(x_advance)?)?;
+            } else {
+                pen_position = advance(pen_position, checked_i32(x_advance)?)?;
+                cross_position = advance(cross_position, checked_i32(-y_advance)?)?;
+            }
+        }
+
+        direction_runs.push(segment.to_direction_run((first_glyph, clusters.len())));
+    }
+
+    let (width_64, height_64) = if vertical {
+        (font_size_metrics.max_advance_64, pen_position.raw())
+    } else {
+        (pen_position.raw(), font_size_metrics.height_64)
+    };
+
+    Ok(LaidOutRun {
+        width_64,
+        height_64,
+        clusters,
+        direction_runs: if reordered {
+            Some(direction_runs)
+        } else {
+            None
+        }
+    })
+}
+
+#[cfg(feature = "harfbuzz")]
+fn checked_i32(value: i64) -> Result<i32> {
+    Ok(Fixed26_6::from_raw_i64(value)
+        .ok_or(ShapingError::Overflow)?
+        .raw())
+}
+
+pub(crate) fn advance(pen_position: Fixed26_6, advance_64: i32) -> Result<Fixed26_6> {
+    Ok(pen_position
+        .checked_add(Fixed26_6::from_raw(advance_64))
+        .ok_or(ShapingError::Overflow)?)
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum MockCharClass {
+    Space,
+    Narrow,
+    Regular,
+    Wide,
+    FullWidth
+}
+
+impl MockCharClass {
+    pub fn of(c: char) -> Self {
+        match c {
+            c if c.is_whitespace() => MockCharClass::Space,
+            'i' | 'j' | 'l' | 't' | 'f' | 'I' | '.' | ',' | ':' | ';' | '!' | '|' | '\'' => MockCharClass::Narrow,
+            'm' | 'w' | 'M' | 'W' | '@' | '%' => MockCharClass::Wide,
+            c if c >= '\u{2e80}' => MockCharClass::FullWidth,
+            _ => MockCharClass::Regular
+        }
+    }
+
+    // In thousandths of an em.
+    pub fn default_advance(self) -> i64 {
+        match self {
+            MockCharClass::Space => 250,
+            MockCharClass::Narrow => 300,
+            MockCharClass::Regular => 500,
+            MockCharClass::Wide => 800,
+            MockCharClass::FullWidth => 1000
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct MockFace {
+    bytes: Rc<Vec<u8>>,
+    face_index: usize,
+    family_name: String
+}
+
+// Mock faces take their family name from the face bytes, so any UTF-8 string
+// can stand in for a font file. Glyph indices are the char's code point.
+#[derive(Debug, PartialEq)]
+pub struct MockFontBackend {
+    lcd_filter: LcdFilter,
+    faces: FnvHashMap<FontId, MockFace>,
+    advances: FnvHashMap<MockCharClass, i64>
+}
+
+impl Default for MockFontBackend {
+    fn default() -> Self {
+        MockFontBackend::new()
+    }
+}
+
+impl MockFontBackend {
+    pub const ASCENDER: i64 = 800;
+    pub const DESCENDER: i64 = -200;
+    pub const LINE_HEIGHT: i64 = 1200;
+
+    pub fn new() -> Self {
+        MockFontBackend {
+            lcd_filter: LcdFilter::None,
+            faces: FnvHashMap::default(),
+            advances: FnvHashMap::default()
+        }
+    }
+
+    // Overrides the advance of a char class, in thousandths of an em.
+    pub fn set_advance(&mut self, class: MockCharClass, advance: i64) {
+        self.advances.insert(class, advance);
+    }
+
+    pub fn advance(&self, class: MockCharClass) -> i64 {
+        self.advances
+            .get(&class)
+            .cloned()
+            .unwrap_or_else(|| class.default_advance())
+    }
+
+    fn face<FontKey, FontInstanceKey, GlyphInstance>(
+        &self,
+        instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>
+    ) -> Result<&MockFace> {
+        self.faces
+            .get(&instance.font_id())
+            .ok_or(FontError::FaceNotFound)
+    }
+
+    fn em_64<FontKey, FontInstanceKey, GlyphInstance>(instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>) -> i64 {
+        instance.pixel_size_64()
+    }
+
+    fn scaled_64<FontKey, FontInstanceKey, GlyphInstance>(
+        instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>,
+        thousandths: i64
+    ) -> Result<i32> {
+        let value = Self::em_64(instance) * thousandths / 1000;
+        Ok(Fixed26_6::from_raw_i64(value)
+            .ok_or(ShapingError::Overflow)?
+            .raw())
+    }
+
+    // Glyphs are rasterized as solid boxes spanning their advance and ascent.
+    fn coverage<FontKey, FontInstanceKey, GlyphInstance>(
+        &self,
+        instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>,
+        c: char,
+        format: ImagePixelFormat
+    ) -> Result<GlyphBitmap> {
+        let dimensions = self.get_glyph_dimensions(instance, c)?;
+        let size = if MockCharClass::of(c) == MockCharClass::Space {
+            (0, 0)
+        } else {
+            (
+                Fixed26_6::from_raw(dimensions.hori_advance_64).ceil().max(0) as u32,
+                Fixed26_6::from_raw(Self::scaled_64(instance, Self::ASCENDER)?).ceil().max(0) as u32
+            )
+        };
+        let mut pixels = vec![255; (size.0 * size.1) as usize * pixels::bytes_per_pixel(format)];
+        let format = pixels::to_canonical(format, AlphaMode::Straight, &mut pixels);
+
+        Ok(GlyphBitmap {
+            glyph_index: dimensions.glyph_index,
+            format,
+            alpha_mode: pixels::CANONICAL_ALPHA_MODE,
+            size,
+            bearing: (0, size.1 as i32),
+            pixels
+        })
+    }
+}
+
+impl TFontBackend for MockFontBackend {
+    fn lcd_filter(&self) -> LcdFilter {
+        self.lcd_filter
+    }
+
+    fn set_lcd_filter(&mut self, filter: LcdFilter) -> Result<()> {
+        self.lcd_filter = filter;
+        Ok(())
+    }
+
+    fn add_face(&mut self, font_id: FontId, bytes: &Rc<Vec<u8>>, face_index: usize) -> Result<()> {
+        let family_name = str::from_utf8(bytes)?.to_string();
+        match self.faces.entry(font_id) {
+            Entry::Occupied(_) => {
+                Err(FontError::FaceAlreadyAdded)?;
+            }
+            Entry::Vacant(e) => {
+                e.insert(MockFace {
+                    bytes: Rc::clone(bytes),
+                    face_index,
+                    family_name
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    fn remove_face(&mut self, font_id: FontId) -> Result<()> {
+        self.faces
+            .remove(&font_id)
+            .ok_or(FontError::FaceNotFound)
+            .map(|_| ())
+    }
+
+    fn font_ids(&self) -> Vec<FontId> {
+        self.faces.keys().cloned().collect()
+    }
+
+    fn get_bytes(&self, font_id: FontId) -> Result<Rc<Vec<u8>>> {
+        self.faces
+            .get(&font_id)
+            .ok_or(FontError::FaceNotFound)
+            .map(|f| Rc::clone(&f.bytes))
+    }
+
+    fn get_face_index(&self, font_id: FontId) -> Result<usize> {
+        self.faces
+            .get(&font_id)
+            .ok_or(FontError::FaceNotFound)
+            .map(|f| f.face_index)
+    }
+
+    fn get_family_name(&self, font_id: FontId) -> Result<&str> {
+        self.faces
+            .get(&font_id)
+            .ok_or(FontError::FaceNotFound)
+            .map(|f| f.family_name.as_str())
+    }
+
+    // Mock faces map every character.
+    fn get_coverage(&self, font_id: FontId) -> Result<CoverageReport> {
+        self.faces.get(&font_id).ok_or(FontError::FaceNotFound)?;
+        Ok(CoverageReport::from_ranges(vec![(0, 0xD7FF), (0xE000, 0x10FFFF)]))
+    }
+
+    fn get_glyph_index<FontKey, FontInstanceKey, GlyphInstance>(
+        &self,
+        instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>,
+        c: char
+    ) -> Result<u32> {
+        self.face(instance)?;
+        Ok(c as u32)
+    }
+
+    fn get_glyph_dimensions<FontKey, FontInstanceKey, GlyphInstance>(
+        &self,
+        instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>,
+        c: char
+    ) -> Result<GlyphDimensions> {
+        let glyph_index = self.get_glyph_index(instance, c)?;
+        let advance_64 = Self::scaled_64(instance, self.advance(MockCharClass::of(c)))?;
+        let height_64 = Self::scaled_64(instance, Self::LINE_HEIGHT)?;
+
+        let transform = instance.transform();
+        let (width, height) = transform.bounds_64(advance_64.into(), height_64.into());
+        let (hori_advance, hori_advance_y) = transform.advance_64(advance_64.into(), false);
+        let (vert_advance_x, vert_advance) = transform.advance_64(height_64.into(), true);
+
+        Ok(GlyphDimensions {
+            glyph_index,
+            width_64: width as i32,
+            height_64: height as i32,
+            hori_advance_64: hori_advance as i32,
+            vert_advance_64: vert_advance as i32,
+            hori_advance_y_64: hori_advance_y as i32,
+            vert_advance_x_64: vert_advance_x as i32,
+            color: false
+        })
+    }
+
+    // Matches the coverage boxes, spaces have no ink.
+    fn get_glyph_ink_bounds<FontKey, FontInstanceKey, GlyphInstance>(
+        &self,
+        instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>,
+        c: char
+    ) -> Result<GlyphInkBounds> {
+        self.face(instance)?;
+        if MockCharClass::of(c) == MockCharClass::Space {
+            return Ok(GlyphInkBounds::default());
+        }
+        let advance_64 = Self::scaled_64(instance, self.advance(MockCharClass::of(c)))?;
+        let ascender_64 = Self::scaled_64(instance, Self::ASCENDER)?;
+
+        Ok(GlyphInkBounds::from_outline_box(instance.transform(), 0, 0, advance_64.into(), ascender_64.into()))
+    }
+
+    fn get_glyph_linear_advance<FontKey, FontInstanceKey, GlyphInstance>(
+        &self,
+        instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>,
+        c: char
+    ) -> Result<GlyphLinearAdvance> {
+        let glyph_index = self.get_glyph_index(instance, c)?;
+        let hori_advance_64 = Self::scaled_64(instance, self.advance(MockCharClass::of(c)))?;
+        let vert_advance_64 = Self::scaled_64(instance, Self::LINE_HEIGHT)?;
+
+        Ok(GlyphLinearAdvance {
+            glyph_index,
+            hori_advance_65536: i64::from(hori_advance_64) << 10,
+            vert_advance_65536: i64::from(vert_advance_64) << 10
+        })
+    }
+
+    fn get_linear_glyph_metrics<FontKey, FontInstanceKey, GlyphInstance>(
+        &self,
+        instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>,
+        c: char
+    ) -> Result<LinearGlyphMetrics> {
+        Ok(LinearGlyphMetrics {
+            glyph_index: self.get_glyph_index(instance, c)?,
+            units_per_em: 1000,
+            hori_advance: self.advance(MockCharClass::of(c)),
+            vert_advance: Self::LINE_HEIGHT
+        })
+    }
+
+    // Matches the ink bounds, sitting on the baseline.
+    fn get_design_glyph_metrics(&self, font_id: FontId, chars: &[char]) -> Result<Vec<DesignGlyphMetrics>> {
+        self.faces.get(&font_id).ok_or(FontError::FaceNotFound)?;
+        Ok(chars
+            .iter()
+            .map(|&c| {
+                let advance = self.advance(MockCharClass::of(c));
+                let ink = MockCharClass::of(c) != MockCharClass::Space;
+                DesignGlyphMetrics {
+                    glyph_index: c as u32,
+                    units_per_em: 1000,
+                    width: if ink { advance } else { 0 },
+                    height: if ink { Self::ASCENDER } else { 0 },
+                    hori_bearing_x: 0,
+                    hori_bearing_y: if ink { Self::ASCENDER } else { 0 },
+                    hori_advance: advance,
+                    vert_advance: Self::LINE_HEIGHT
+                }
+            })
+            .collect())
+    }
+
+    fn get_glyph_bitmap<FontKey, FontInstanceKey, GlyphInstance>(
+        &self,
+        instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>,
+        c: char
+    ) -> Result<GlyphBitmap> {
+        self.coverage(instance, c, ImagePixelFormat::Gray(8))
+    }
+
+    fn get_glyph_subpixel_bitmap<FontKey, FontInstanceKey, GlyphInstance>(
+        &self,
+        instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>,
+        c: char,
+        layout: SubpixelLayout
+    ) -> Result<GlyphBitmap> {
+        match layout {
+            SubpixelLayout::None => self.get_glyph_bitmap(instance, c),
+            _ => self.coverage(instance, c, ImagePixelFormat::RGB(8))
+        }
+    }
+
+    // Glyph indices are code points.
+    fn rasterize_glyph<FontKey, FontInstanceKey, GlyphInstance>(
+        &self,
+        instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>,
+        glyph_index: u32
+    ) -> Result<GlyphBitmap> {
+        let c = ::std::char::from_u32(glyph_index).ok_or(FontError::FaceGlyphMissing)?;
+        self.coverage(instance, c, ImagePixelFormat::Gray(8))
+    }
+
+    fn get_global_size_metrics<FontKey, FontInstanceKey, GlyphInstance>(
+        &self,
+        instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>
+    ) -> Result<FontSizeMetrics> {
+        self.face(instance)?;
+        let em = Self::em_64(instance) / 64;
+        let max_advance = self.advances
+            .values()
+            .cloned()
+            .chain(Some(MockCharClass::FullWidth.default_advance()))
+            .max()
+            .unwrap_or_default();
+
+        Ok(FontSizeMetrics {
+            nominal_width: em as u16,
+            nominal_height: em as u16,
+            ascender_64: Self::scaled_64(instance, Self::ASCENDER)?,
+            descender_64: Self::scaled_64(instance, Self::DESCENDER)?,
+            height_64: Self::scaled_64(instance, Self::LINE_HEIGHT)?,
+            max_advance_64: Self::scaled_64(instance, max_advance)?
+        })
+    }
+}
@@ -0,0 +1,112 @@
+/*
+Copyright 2016 Mozilla
+Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+this file except in compliance with the License. You may obtain a copy of the
+License at http://www.apache.org/licenses/LICENSE-2.0
+Unless required by applicable law or agreed to in writing, software distributed
+under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+CONDITIONS OF ANY KIND, either express or implied. See the License for the
+specific language governing permissions and limitations under the License.
+*/
+
+// Splits text into runs of a single direction, in visual order, following the
+// Unicode Bidirectional Algorithm. Each run is shaped on its own, and glyphs of
+// right to left runs are laid out starting from their last char.
+
+use std::ops::Range;
+
+use unicode_bidi::BidiInfo;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum TextDirection {
+    LeftToRight,
+    RightToLeft
+}
+
+// A run of glyphs laid out in a single direction. The runs of a glyph store
+// are listed in visual order, as are its glyphs, so the glyphs of a right to
+// left run are in the reverse order of the text they come from.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct DirectionRun {
+    pub text_range: (usize, usize),
+    pub glyph_range: (usize, usize),
+    pub direction: TextDirection,
+    pub level: u8
+}
+
+impl DirectionRun {
+    pub fn is_rtl(&self) -> bool {
+        self.direction == TextDirection::RightToLeft
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct VisualRun {
+    pub range: Range<usize>,
+    pub level: u8
+}
+
+impl VisualRun {
+    pub fn ltr(range: Range<usize>) -> Self {
+        VisualRun { range, level: 0 }
+    }
+
+    pub fn is_rtl(&self) -> bool {
+        self.level % 2 == 1
+    }
+
+    pub fn to_direction_run(&self, glyph_range: (usize, usize)) -> DirectionRun {
+        DirectionRun {
+            text_range: (self.range.start, self.range.end),
+            glyph_range,
+            direction: if self.is_rtl() {
+                TextDirection::RightToLeft
+            } else {
+                TextDirection::LeftToRight
+            },
+            level: self.level
+        }
+    }
+}
+
+// Returns nothing for text without any right to left chars, which is laid out
+// in logical order as is.
+pub(crate) fn visual_runs(text: &str) -> Option<Vec<VisualRun>> {
+    if text.is_ascii() {
+        return None;
+    }
+    let info = BidiInfo::new(text, None);
+    if !info.has_rtl() {
+        return None;
+    }
+
+    let mut runs = Vec::new();
+    for paragraph in &info.paragraphs {
+        let (levels, level_runs) = info.visual_runs(paragraph, paragraph.range.clone());
+        for range in level_runs {
+            let level = levels[range.start].number();
+            runs.push(VisualRun { range, level });
+        }
+    }
+    Some(runs)
+}
+
+// Paired punctuation drawn flipped in right to left runs, for shapers which
+// don't mirror glyphs themselves.
+pub(crate) fn mirrored(c: char) -> char {
+    match c {
+        '(' => ')',
+        ')' => '(',
+        '[' => ']',
+        ']' => '[',
+        '{' => '}',
+        '}' => '{',
+        '<' => '>',
+        '>' => '<',
+        '\u{AB}' => '\u{BB}',
+        '\u{BB}' => '\u{AB}',
+        '\u{2039}' => '\u{203A}',
+        '\u{203A}' => '\u{2039}',
+        _ => c
+    }
+}
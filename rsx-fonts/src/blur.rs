@@ -0,0 +1,109 @@
+/*
+Copyright 2016 Mozilla
+Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+this file except in compliance with the License. You may obtain a copy of the
+License at http://www.apache.org/licenses/LICENSE-2.0
+Unless required by applicable law or agreed to in writing, software distributed
+under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+CONDITIONS OF ANY KIND, either express or implied. See the License for the
+specific language governing permissions and limitations under the License.
+*/
+
+use rsx_resource_updates::pixels::{self, AlphaMode};
+use rsx_shared::types::ImagePixelFormat;
+
+use types::GlyphBitmap;
+
+// Keeps kernels, and the padding added around blurred glyphs, within reason.
+pub const MAX_BLUR_RADIUS: u32 = 64;
+
+// Normalized weights for a kernel spanning `2 * radius + 1` pixels. As with
+// CSS shadows, the standard deviation is half the blur radius.
+pub fn gaussian_kernel(radius: u32) -> Vec<f32> {
+    let radius = radius.min(MAX_BLUR_RADIUS) as i32;
+    if radius == 0 {
+        return vec![1.0];
+    }
+    let sigma = radius as f32 / 2.0;
+    let weights: Vec<f32> = (-radius..=radius)
+        .map(|x| (-(x * x) as f32 / (2.0 * sigma * sigma)).exp())
+        .collect();
+    let sum: f32 = weights.iter().sum();
+    weights.into_iter().map(|weight| weight / sum).collect()
+}
+
+// Coverage of each pixel, i.e. the alpha channel of color glyphs and the
+// average of the channels of subpixel ones.
+fn coverage(bitmap: &GlyphBitmap) -> Vec<f32> {
+    let bytes_per_pixel = pixels::bytes_per_pixel(bitmap.format);
+    let pixel_count = bitmap.size.0 as usize * bitmap.size.1 as usize;
+    bitmap
+        .pixels
+        .chunks(bytes_per_pixel)
+        .take(pixel_count)
+        .map(|pixel| match bitmap.format {
+            ImagePixelFormat::RGBA(_) | ImagePixelFormat::BGRA(_) => f32::from(pixel[3]),
+            _ => pixel.iter().map(|&channel| f32::from(channel)).sum::<f32>() / pixel.len() as f32
+        })
+        .collect()
+}
+
+// Blurs the glyph's coverage into an alpha only bitmap, padded by the radius
+// on each side so that the blur isn't clipped, with the bearing adjusted to
+// keep it aligned with the original glyph.
+pub fn blur_glyph(bitmap: &GlyphBitmap, radius: u32) -> GlyphBitmap {
+    let radius = radius.min(MAX_BLUR_RADIUS);
+    let kernel = gaussian_kernel(radius);
+    let pad = radius as usize;
+    let (width, height) = (bitmap.size.0 as usize, bitmap.size.1 as usize);
+    let (blurred_width, blurred_height) = (width + pad * 2, height + pad * 2);
+
+    let mut source = vec![0.0; blurred_width * blurred_height];
+    for (index, value) in coverage(bitmap).into_iter().enumerate() {
+        let (x, y) = (index % width, index / width);
+        source[(y + pad) * blurred_width + x + pad] = value;
+    }
+
+    let mut horizontal = vec![0.0; source.len()];
+    for y in 0..blurred_height {
+        let row = &source[y * blurred_width..(y + 1) * blurred_width];
+        for x in 0..blurred_width {
+            horizontal[y * blurred_width + x] = convolve(&kernel, |offset| {
+                (x + offset).checked_sub(pad).and_then(|x| row.get(x)).cloned()
+            });
+        }
+    }
+
+    let mut pixels = Vec::with_capacity(source.len());
+    for y in 0..blurred_height {
+        for x in 0..blurred_width {
+            let value = convolve(&kernel, |offset| {
+                (y + offset)
+                    .checked_sub(pad)
+                    .filter(|&y| y < blurred_height)
+                    .map(|y| horizontal[y * blurred_width + x])
+            });
+            pixels.push(value.round().max(0.0).min(255.0) as u8);
+        }
+    }
+
+    GlyphBitmap {
+        glyph_index: bitmap.glyph_index,
+        format: ImagePixelFormat::Gray(8),
+        alpha_mode: AlphaMode::Straight,
+        size: (blurred_width as u32, blurred_height as u32),
+        bearing: (bitmap.bearing.0 - pad as i32, bitmap.bearing.1 + pad as i32),
+        pixels
+    }
+}
+
+fn convolve<F>(kernel: &[f32], sample: F) -> f32
+where
+    F: Fn(usize) -> Option<f32>
+{
+    kernel
+        .iter()
+        .enumerate()
+        .map(|(offset, weight)| sample(offset).map_or(0.0, |value| value * weight))
+        .sum()
+}
@@ -0,0 +1,225 @@
+/*
+Copyright 2016 Mozilla
+Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+this file except in compliance with the License. You may obtain a copy of the
+License at http://www.apache.org/licenses/LICENSE-2.0
+Unless required by applicable law or agreed to in writing, software distributed
+under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+CONDITIONS OF ANY KIND, either express or implied. See the License for the
+specific language governing permissions and limitations under the License.
+*/
+
+use std::collections::hash_map::Entry;
+use std::rc::Rc;
+
+use fnv::FnvHashMap;
+use rsx_resource_updates::display::SubpixelLayout;
+
+use backend::TFontBackend;
+use coverage::CoverageReport;
+use error::{FontError, Result};
+use glyph_load::LcdFilter;
+use metrics::FontMetricsTable;
+use types::{FontId, FontInstance, FontSizeMetrics, GlyphBitmap, GlyphDimensions, GlyphLinearAdvance, LinearGlyphMetrics};
+
+// Implemented by the consumer running in a browser, typically by creating a
+// `FontFace` from the bytes and adding it to `document.fonts`. Returns the
+// family name the face was registered under.
+pub trait TFontFaceHost {
+    fn register_font_face(&mut self, font_id: FontId, bytes: &Rc<Vec<u8>>, face_index: usize) -> Result<String>;
+
+    fn unregister_font_face(&mut self, font_id: FontId);
+}
+
+#[derive(Debug, PartialEq)]
+struct BrowserFace {
+    bytes: Rc<Vec<u8>>,
+    face_index: usize,
+    family_name: String,
+    metrics: Option<FontMetricsTable>
+}
+
+// Backend for running in a browser, where the browser loads and draws faces.
+// Faces are registered with the host, and measured by the consumer, e.g. with
+// `CanvasRenderingContext2D.measureText`, which supplies the results as a
+// `FontMetricsTable`. Faces can't be measured until then.
+#[derive(Debug, PartialEq)]
+pub struct BrowserFontBackend<H> {
+    host: H,
+    lcd_filter: LcdFilter,
+    faces: FnvHashMap<FontId, BrowserFace>
+}
+
+impl<H> BrowserFontBackend<H>
+where
+    H: TFontFaceHost
+{
+    pub fn new(host: H) -> Self {
+        BrowserFontBackend {
+            host,
+            lcd_filter: LcdFilter::None,
+            faces: FnvHashMap::default()
+        }
+    }
+
+    pub fn host(&self) -> &H {
+        &self.host
+    }
+
+    pub fn host_mut(&mut self) -> &mut H {
+        &mut self.host
+    }
+
+    // Measurements are expected in the table's own units per em, regardless
+    // of the size they were taken at.
+    pub fn set_measured_metrics(&mut self, font_id: FontId, table: FontMetricsTable) -> Result<()> {
+        let face = self.faces.get_mut(&font_id).ok_or(FontError::FaceNotFound)?;
+        face.metrics = Some(table);
+        Ok(())
+    }
+
+    pub fn has_measured_metrics(&self, font_id: FontId) -> bool {
+        self.faces
+            .get(&font_id)
+            .map_or(false, |f| f.metrics.is_some())
+    }
+
+    pub fn table(&self, font_id: FontId) -> Result<&FontMetricsTable> {
+        self.faces
+            .get(&font_id)
+            .ok_or(FontError::FaceNotFound)?
+            .metrics
+            .as_ref()
+            .ok_or(FontError::FaceMetricsMissing)
+    }
+
+    fn face_table<FontKey, FontInstanceKey, GlyphInstance>(
+        &self,
+        instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>
+    ) -> Result<&FontMetricsTable> {
+        self.table(instance.font_id())
+    }
+}
+
+impl<H> TFontBackend for BrowserFontBackend<H>
+where
+    H: TFontFaceHost
+{
+    fn lcd_filter(&self) -> LcdFilter {
+        self.lcd_filter
+    }
+
+    fn set_lcd_filter(&mut self, filter: LcdFilter) -> Result<()> {
+        self.lcd_filter = filter;
+        Ok(())
+    }
+
+    fn add_face(&mut self, font_id: FontId, bytes: &Rc<Vec<u8>>, face_index: usize) -> Result<()> {
+        if self.faces.contains_key(&font_id) {
+            Err(FontError::FaceAlreadyAdded)?;
+        }
+        let family_name = self.host.register_font_face(font_id, bytes, face_index)?;
+        if let Entry::Vacant(e) = self.faces.entry(font_id) {
+            e.insert(BrowserFace {
+                bytes: Rc::clone(bytes),
+                face_index,
+                family_name,
+                metrics: None
+            });
+        }
+
+        Ok(())
+    }
+
+    fn remove_face(&mut self, font_id: FontId) -> Result<()> {
+        self.faces.remove(&font_id).ok_or(FontError::FaceNotFound)?;
+        self.host.unregister_font_face(font_id);
+        Ok(())
+    }
+
+    fn font_ids(&self) -> Vec<FontId> {
+        self.faces.keys().cloned().collect()
+    }
+
+    fn get_bytes(&self, font_id: FontId) -> Result<Rc<Vec<u8>>> {
+        self.faces
+            .get(&font_id)
+            .ok_or(FontError::FaceNotFound)
+            .map(|f| Rc::clone(&f.bytes))
+    }
+
+    fn get_face_index(&self, font_id: FontId) -> Result<usize> {
+        self.faces
+            .get(&font_id)
+            .ok_or(FontError::FaceNotFound)
+            .map(|f| f.face_index)
+    }
+
+    fn get_family_name(&self, font_id: FontId) -> Result<&str> {
+        self.faces
+            .get(&font_id)
+            .ok_or(FontError::FaceNotFound)
+            .map(|f| f.family_name.as_str())
+    }
+
+    fn get_coverage(&self, font_id: FontId) -> Result<CoverageReport> {
+        Ok(self.table(font_id)?.coverage())
+    }
+
+    fn get_glyph_index<FontKey, FontInstanceKey, GlyphInstance>(
+        &self,
+        instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>,
+        c: char
+    ) -> Result<u32> {
+        Ok(self.face_table(instance)?.glyph(c).glyph_index)
+    }
+
+    fn get_glyph_dimensions<FontKey, FontInstanceKey, GlyphInstance>(
+        &self,
+        instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>,
+        c: char
+    ) -> Result<GlyphDimensions> {
+        self.face_table(instance)?.glyph_dimensions(instance, c)
+    }
+
+    fn get_glyph_linear_advance<FontKey, FontInstanceKey, GlyphInstance>(
+        &self,
+        instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>,
+        c: char
+    ) -> Result<GlyphLinearAdvance> {
+        Ok(self.face_table(instance)?.linear_advance(instance, c))
+    }
+
+    fn get_linear_glyph_metrics<FontKey, FontInstanceKey, GlyphInstance>(
+        &self,
+        instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>,
+        c: char
+    ) -> Result<LinearGlyphMetrics> {
+        Ok(self.face_table(instance)?.linear_metrics(c))
+    }
+
+    // The browser rasterizes text itself.
+    fn get_glyph_bitmap<FontKey, FontInstanceKey, GlyphInstance>(
+        &self,
+        _: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>,
+        _: char
+    ) -> Result<GlyphBitmap> {
+        Err(FontError::RasterizationUnsupported)
+    }
+
+    fn get_glyph_subpixel_bitmap<FontKey, FontInstanceKey, GlyphInstance>(
+        &self,
+        _: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>,
+        _: char,
+        _: SubpixelLayout
+    ) -> Result<GlyphBitmap> {
+        Err(FontError::RasterizationUnsupported)
+    }
+
+    fn get_global_size_metrics<FontKey, FontInstanceKey, GlyphInstance>(
+        &self,
+        instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>
+    ) -> Result<FontSizeMetrics> {
+        self.face_table(instance)?.size_metrics(instance)
+    }
+}
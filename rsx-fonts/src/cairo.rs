@@ -0,0 +1,43 @@
+/*
+Copyright 2016 Mozilla
+Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+this file except in compliance with the License. You may obtain a copy of the
+License at http://www.apache.org/licenses/LICENSE-2.0
+Unless required by applicable law or agreed to in writing, software distributed
+under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+CONDITIONS OF ANY KIND, either express or implied. See the License for the
+specific language governing permissions and limitations under the License.
+*/
+
+use std::os::raw::c_ulong;
+
+use rsx_resource_updates::traits::TGlyphInstanceExt;
+
+use types::GlyphStore;
+
+// Layout compatible with `cairo_glyph_t`, so a slice can be passed to
+// `cairo_show_glyphs` directly.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct CairoGlyph {
+    pub index: c_ulong,
+    pub x: f64,
+    pub y: f64
+}
+
+impl<FontKey, FontInstanceKey, GlyphInstance> GlyphStore<FontKey, FontInstanceKey, GlyphInstance>
+where
+    GlyphInstance: TGlyphInstanceExt
+{
+    pub fn to_cairo_glyphs(&self, origin: (f64, f64)) -> Vec<CairoGlyph> {
+        self.glyphs
+            .0
+            .iter()
+            .map(|glyph| CairoGlyph {
+                index: c_ulong::from(glyph.glyph_index()),
+                x: origin.0 + f64::from(glyph.x_64()) / 64.0,
+                y: origin.1 + f64::from(glyph.y_64()) / 64.0
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,142 @@
+/*
+Copyright 2016 Mozilla
+Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+this file except in compliance with the License. You may obtain a copy of the
+License at http://www.apache.org/licenses/LICENSE-2.0
+Unless required by applicable law or agreed to in writing, software distributed
+under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+CONDITIONS OF ANY KIND, either express or implied. See the License for the
+specific language governing permissions and limitations under the License.
+*/
+
+// Caret placement and hit testing for text editing, over horizontally shaped
+// glyph stores. Offsets are byte offsets into the shaped text. A cluster's
+// leading edge is where its text starts, i.e. its left edge in left to right
+// runs and its right edge in right to left ones. Carets never land inside a
+// cluster when hit testing, but offsets inside one, e.g. between the chars of
+// a ligature, are placed proportionally across it.
+
+use std::ops::Range;
+
+use rsx_shared::traits::{TFontInstanceKey, TFontKey, TGlyphStore};
+
+use types::{GlyphStore, TGlyphInstanceExt, TGlyphStoreExt};
+
+// Horizontal position of the caret before the char at `offset`, in 26.6 fixed
+// point from the start of the store. Offsets past the text are after its end.
+pub fn caret_position_for_offset<FontKey, FontInstanceKey, GlyphInstance>(
+    glyph_store: &GlyphStore<FontKey, FontInstanceKey, GlyphInstance>,
+    offset: u32
+) -> i32
+where
+    FontKey: TFontKey,
+    FontInstanceKey: TFontInstanceKey,
+    GlyphInstance: TGlyphInstanceExt
+{
+    let clusters = match glyph_store.clusters() {
+        Some(clusters) if !clusters.is_empty() => clusters,
+        _ => return 0
+    };
+    let first = clusters.iter().cloned().min().unwrap_or(0);
+    let glyphs = match glyph_store.glyphs_at_offset(offset.max(first)) {
+        Some(glyphs) => glyphs,
+        None => return 0
+    };
+
+    let start = clusters[glyphs.start];
+    let end = cluster_end(glyph_store, clusters, start);
+    let (leading_64, trailing_64) = cluster_edges(glyph_store, glyphs);
+    if offset <= start {
+        leading_64
+    } else if offset >= end {
+        trailing_64
+    } else {
+        let fraction = i64::from(offset - start) * i64::from(trailing_64 - leading_64) / i64::from(end - start);
+        leading_64 + fraction as i32
+    }
+}
+
+// Offset of the caret nearest to `x_64`, in 26.6 fixed point from the start of
+// the store. Positions in the trailing half of a cluster round to its end, so
+// clicking past the middle of the last char puts the caret after it.
+pub fn offset_for_position<FontKey, FontInstanceKey, GlyphInstance>(
+    glyph_store: &GlyphStore<FontKey, FontInstanceKey, GlyphInstance>,
+    x_64: i32
+) -> u32
+where
+    FontKey: TFontKey,
+    FontInstanceKey: TFontInstanceKey,
+    GlyphInstance: TGlyphInstanceExt
+{
+    let clusters = match glyph_store.clusters() {
+        Some(clusters) if !clusters.is_empty() => clusters,
+        _ => return 0
+    };
+    // Positions before the first glyph or past the last one hit those glyphs.
+    let glyph = glyph_store
+        .glyphs()
+        .iter()
+        .rposition(|glyph| glyph.x_64() <= x_64)
+        .unwrap_or(0);
+    let start = clusters[glyph];
+    let glyphs = match glyph_store.glyphs_at_offset(start) {
+        Some(glyphs) => glyphs,
+        None => return start
+    };
+
+    let (leading_64, trailing_64) = cluster_edges(glyph_store, glyphs);
+    let past_middle = (i64::from(x_64) - i64::from(leading_64)).abs() * 2 >= (i64::from(trailing_64) - i64::from(leading_64)).abs();
+    let before_leading = if leading_64 <= trailing_64 {
+        x_64 < leading_64
+    } else {
+        x_64 > leading_64
+    };
+    if past_middle && !before_leading {
+        cluster_end(glyph_store, clusters, start)
+    } else {
+        start
+    }
+}
+
+// Where the text of the cluster starting at `start` ends, which is where the
+// next cluster in text order starts. Stores of unknown text length end at the
+// start of their last cluster.
+fn cluster_end<FontKey, FontInstanceKey, GlyphInstance>(
+    glyph_store: &GlyphStore<FontKey, FontInstanceKey, GlyphInstance>,
+    clusters: &[u32],
+    start: u32
+) -> u32 {
+    clusters
+        .iter()
+        .cloned()
+        .filter(|&cluster| cluster > start)
+        .min()
+        .unwrap_or_else(|| glyph_store.text_len().max(start))
+}
+
+// Leading and trailing edges of a cluster's glyphs, which are contiguous since
+// glyphs are in visual order. A cluster ends where the next glyph starts.
+fn cluster_edges<FontKey, FontInstanceKey, GlyphInstance>(
+    glyph_store: &GlyphStore<FontKey, FontInstanceKey, GlyphInstance>,
+    glyphs: Range<usize>
+) -> (i32, i32)
+where
+    FontKey: TFontKey,
+    FontInstanceKey: TFontInstanceKey,
+    GlyphInstance: TGlyphInstanceExt
+{
+    let left_64 = glyph_store.glyphs()[glyphs.start].x_64();
+    let right_64 = glyph_store
+        .glyphs()
+        .get(glyphs.end)
+        .map_or(glyph_store.width_64(), |glyph| glyph.x_64());
+    let rtl = glyph_store.direction_runs().map_or(false, |runs| {
+        runs.iter()
+            .any(|run| run.is_rtl() && run.glyph_range.0 <= glyphs.start && glyphs.start < run.glyph_range.1)
+    });
+    if rtl {
+        (right_64, left_64)
+    } else {
+        (left_64, right_64)
+    }
+}
@@ -0,0 +1,154 @@
+/*
+Copyright 2016 Mozilla
+Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+this file except in compliance with the License. You may obtain a copy of the
+License at http://www.apache.org/licenses/LICENSE-2.0
+Unless required by applicable law or agreed to in writing, software distributed
+under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+CONDITIONS OF ANY KIND, either express or implied. See the License for the
+specific language governing permissions and limitations under the License.
+*/
+
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use fnv::FnvHashMap;
+use serde_json;
+
+use error::Result;
+use types::FontId;
+
+pub const DEFAULT_FONT_WEIGHT: u16 = 400;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FontStyle {
+    Normal,
+    Italic,
+    Oblique
+}
+
+impl Default for FontStyle {
+    fn default() -> Self {
+        FontStyle::Normal
+    }
+}
+
+impl FontStyle {
+    // Italic and oblique faces are preferred over upright ones when the
+    // requested style isn't available, as per the CSS font matching rules.
+    fn distance(self, other: FontStyle) -> u32 {
+        match (self, other) {
+            (a, b) if a == b => 0,
+            (FontStyle::Normal, _) | (_, FontStyle::Normal) => 2,
+            _ => 1
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct FontDescriptor {
+    pub family: String,
+    pub weight: u16,
+    pub style: FontStyle
+}
+
+impl FontDescriptor {
+    pub fn new<T>(family: T, weight: u16, style: FontStyle) -> Self
+    where
+        T: Into<String>
+    {
+        FontDescriptor {
+            family: family.into(),
+            weight,
+            style
+        }
+    }
+
+    pub(crate) fn distance(&self, weight: u16, style: FontStyle) -> (u32, u16) {
+        let weight_distance = (i32::from(self.weight) - i32::from(weight)).abs() as u16;
+        (self.style.distance(style), weight_distance)
+    }
+}
+
+fn default_weight() -> u16 {
+    DEFAULT_FONT_WEIGHT
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FontFaceConfig {
+    // Used to create the face's `FontId`, defaults to the path as written.
+    #[serde(default)]
+    pub name: Option<String>,
+    pub path: PathBuf,
+    #[serde(default)]
+    pub face_index: usize,
+    pub family: String,
+    #[serde(default = "default_weight")]
+    pub weight: u16,
+    #[serde(default)]
+    pub style: FontStyle
+}
+
+impl FontFaceConfig {
+    pub fn font_id(&self) -> FontId {
+        match self.name {
+            Some(ref name) => FontId::new(name),
+            None => FontId::new(self.path.to_string_lossy())
+        }
+    }
+
+    pub fn descriptor(&self) -> FontDescriptor {
+        FontDescriptor::new(self.family.as_str(), self.weight, self.style)
+    }
+}
+
+// Declares the faces to register along with their metadata, family aliases
+// (e.g. "sans-serif" to a concrete family) and per family fallback chains.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct FontConfig {
+    #[serde(default)]
+    pub fonts: Vec<FontFaceConfig>,
+    #[serde(default)]
+    pub aliases: FnvHashMap<String, String>,
+    #[serde(default)]
+    pub fallbacks: FnvHashMap<String, Vec<String>>,
+    #[serde(skip)]
+    base_dir: Option<PathBuf>
+}
+
+impl FontConfig {
+    pub fn from_json<T>(json: T) -> Result<Self>
+    where
+        T: AsRef<str>
+    {
+        Ok(serde_json::from_str(json.as_ref())?)
+    }
+
+    // Relative font paths are resolved against the config file's directory.
+    pub fn from_path<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>
+    {
+        let mut json = String::new();
+        File::open(path.as_ref())?.read_to_string(&mut json)?;
+
+        let mut config = FontConfig::from_json(json)?;
+        config.base_dir = path.as_ref().parent().map(Path::to_path_buf);
+        Ok(config)
+    }
+
+    pub fn resolve_path(&self, face: &FontFaceConfig) -> PathBuf {
+        match self.base_dir {
+            Some(ref base_dir) => base_dir.join(&face.path),
+            None => face.path.clone()
+        }
+    }
+
+    pub fn load_bytes(&self, face: &FontFaceConfig) -> Result<Vec<u8>> {
+        let mut bytes = vec![];
+        File::open(self.resolve_path(face))?.read_to_end(&mut bytes)?;
+        Ok(bytes)
+    }
+}
@@ -0,0 +1,285 @@
+/*
+Copyright 2016 Mozilla
+Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+this file except in compliance with the License. You may obtain a copy of the
+License at http://www.apache.org/licenses/LICENSE-2.0
+Unless required by applicable law or agreed to in writing, software distributed
+under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+CONDITIONS OF ANY KIND, either express or implied. See the License for the
+specific language governing permissions and limitations under the License.
+*/
+
+use std::cmp::Ordering;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Script {
+    Common,
+    Latin,
+    Greek,
+    Cyrillic,
+    Armenian,
+    Hebrew,
+    Arabic,
+    Syriac,
+    Thaana,
+    Devanagari,
+    Bengali,
+    Gurmukhi,
+    Gujarati,
+    Oriya,
+    Tamil,
+    Telugu,
+    Kannada,
+    Malayalam,
+    Sinhala,
+    Thai,
+    Lao,
+    Tibetan,
+    Myanmar,
+    Georgian,
+    Hangul,
+    Ethiopic,
+    Cherokee,
+    Khmer,
+    Mongolian,
+    Hiragana,
+    Katakana,
+    Bopomofo,
+    Han
+}
+
+#[derive(Debug, PartialEq, Eq, Hash)]
+pub struct UnicodeBlock {
+    pub name: &'static str,
+    pub script: Script,
+    pub start: u32,
+    pub end: u32
+}
+
+impl UnicodeBlock {
+    pub fn of(c: char) -> Option<&'static UnicodeBlock> {
+        let code_point = c as u32;
+        UNICODE_BLOCKS
+            .binary_search_by(|block| range_ordering(block.start, block.end, code_point))
+            .ok()
+            .map(|index| &UNICODE_BLOCKS[index])
+    }
+
+    pub fn size(&self) -> u32 {
+        self.end - self.start + 1
+    }
+
+    pub fn contains(&self, code_point: u32) -> bool {
+        self.start <= code_point && code_point <= self.end
+    }
+}
+
+fn range_ordering(start: u32, end: u32, code_point: u32) -> Ordering {
+    if end < code_point {
+        Ordering::Less
+    } else if start > code_point {
+        Ordering::Greater
+    } else {
+        Ordering::Equal
+    }
+}
+
+macro_rules! blocks {
+    ($(($name:expr, $script:ident, $start:expr, $end:expr)),*) => {
+        &[$(UnicodeBlock { name: $name, script: Script::$script, start: $start, end: $end }),*]
+    };
+}
+
+// The blocks of the scripts fonts are usually picked for, sorted by range.
+// Blocks shared between scripts, e.g. punctuation, are attributed to `Common`.
+#[cfg_attr(rustfmt, rustfmt_skip)]
+pub const UNICODE_BLOCKS: &[UnicodeBlock] = blocks![
+    ("Basic Latin", Latin, 0x0000, 0x007F),
+    ("Latin-1 Supplement", Latin, 0x0080, 0x00FF),
+    ("Latin Extended-A", Latin, 0x0100, 0x017F),
+    ("Latin Extended-B", Latin, 0x0180, 0x024F),
+    ("IPA Extensions", Latin, 0x0250, 0x02AF),
+    ("Greek and Coptic", Greek, 0x0370, 0x03FF),
+    ("Cyrillic", Cyrillic, 0x0400, 0x04FF),
+    ("Cyrillic Supplement", Cyrillic, 0x0500, 0x052F),
+    ("Armenian", Armenian, 0x0530, 0x058F),
+    ("Hebrew", Hebrew, 0x0590, 0x05FF),
+    ("Arabic", Arabic, 0x0600, 0x06FF),
+    ("Syriac", Syriac, 0x0700, 0x074F),
+    ("Thaana", Thaana, 0x0780, 0x07BF),
+    ("Devanagari", Devanagari, 0x0900, 0x097F),
+    ("Bengali", Bengali, 0x0980, 0x09FF),
+    ("Gurmukhi", Gurmukhi, 0x0A00, 0x0A7F),
+    ("Gujarati", Gujarati, 0x0A80, 0x0AFF),
+    ("Oriya", Oriya, 0x0B00, 0x0B7F),
+    ("Tamil", Tamil, 0x0B80, 0x0BFF),
+    ("Telugu", Telugu, 0x0C00, 0x0C7F),
+    ("Kannada", Kannada, 0x0C80, 0x0CFF),
+    ("Malayalam", Malayalam, 0x0D00, 0x0D7F),
+    ("Sinhala", Sinhala, 0x0D80, 0x0DFF),
+    ("Thai", Thai, 0x0E00, 0x0E7F),
+    ("Lao", Lao, 0x0E80, 0x0EFF),
+    ("Tibetan", Tibetan, 0x0F00, 0x0FFF),
+    ("Myanmar", Myanmar, 0x1000, 0x109F),
+    ("Georgian", Georgian, 0x10A0, 0x10FF),
+    ("Hangul Jamo", Hangul, 0x1100, 0x11FF),
+    ("Ethiopic", Ethiopic, 0x1200, 0x137F),
+    ("Cherokee", Cherokee, 0x13A0, 0x13FF),
+    ("Khmer", Khmer, 0x1780, 0x17FF),
+    ("Mongolian", Mongolian, 0x1800, 0x18AF),
+    ("Latin Extended Additional", Latin, 0x1E00, 0x1EFF),
+    ("Greek Extended", Greek, 0x1F00, 0x1FFF),
+    ("General Punctuation", Common, 0x2000, 0x206F),
+    ("Currency Symbols", Common, 0x20A0, 0x20CF),
+    ("Arrows", Common, 0x2190, 0x21FF),
+    ("Mathematical Operators", Common, 0x2200, 0x22FF),
+    ("Box Drawing", Common, 0x2500, 0x257F),
+    ("CJK Symbols and Punctuation", Common, 0x3000, 0x303F),
+    ("Hiragana", Hiragana, 0x3040, 0x309F),
+    ("Katakana", Katakana, 0x30A0, 0x30FF),
+    ("Bopomofo", Bopomofo, 0x3100, 0x312F),
+    ("Hangul Compatibility Jamo", Hangul, 0x3130, 0x318F),
+    ("CJK Unified Ideographs Extension A", Han, 0x3400, 0x4DBF),
+    ("CJK Unified Ideographs", Han, 0x4E00, 0x9FFF),
+    ("Hangul Syllables", Hangul, 0xAC00, 0xD7AF)
+];
+
+impl Script {
+    pub fn of(c: char) -> Option<Script> {
+        UnicodeBlock::of(c).map(|block| block.script)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockCoverage {
+    pub block: &'static UnicodeBlock,
+    pub covered: u32
+}
+
+impl BlockCoverage {
+    // Blocks include unassigned code points, so even complete faces rarely
+    // reach a ratio of 1.
+    pub fn ratio(&self) -> f32 {
+        self.covered as f32 / self.block.size() as f32
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScriptCoverage {
+    pub script: Script,
+    pub covered: u32,
+    pub total: u32
+}
+
+impl ScriptCoverage {
+    pub fn ratio(&self) -> f32 {
+        self.covered as f32 / self.total as f32
+    }
+}
+
+// The code points mapped by a face's character map, as sorted and disjoint
+// inclusive ranges.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CoverageReport {
+    ranges: Vec<(u32, u32)>
+}
+
+impl CoverageReport {
+    pub fn from_ranges(mut ranges: Vec<(u32, u32)>) -> Self {
+        ranges.sort();
+        let mut merged: Vec<(u32, u32)> = Vec::with_capacity(ranges.len());
+        for (start, end) in ranges {
+            match merged.last_mut() {
+                Some(last) if start <= last.1.saturating_add(1) => last.1 = last.1.max(end),
+                _ => merged.push((start, end))
+            }
+        }
+        CoverageReport { ranges: merged }
+    }
+
+    // Collapses sorted code points into ranges, e.g. as iterated from a cmap.
+    pub fn from_code_points<I>(code_points: I) -> Self
+    where
+        I: IntoIterator<Item = u32>
+    {
+        let mut ranges: Vec<(u32, u32)> = Vec::new();
+        for code_point in code_points {
+            match ranges.last_mut() {
+                Some(last) if code_point == last.1 + 1 => last.1 = code_point,
+                _ => ranges.push((code_point, code_point))
+            }
+        }
+        CoverageReport::from_ranges(ranges)
+    }
+
+    pub fn ranges(&self) -> &[(u32, u32)] {
+        &self.ranges
+    }
+
+    pub fn code_point_count(&self) -> u32 {
+        self.ranges.iter().map(|&(start, end)| end - start + 1).sum()
+    }
+
+    pub fn covers(&self, c: char) -> bool {
+        let code_point = c as u32;
+        self.ranges
+            .binary_search_by(|&(start, end)| range_ordering(start, end, code_point))
+            .is_ok()
+    }
+
+    fn count_within(&self, start: u32, end: u32) -> u32 {
+        self.ranges
+            .iter()
+            .filter(|&&(s, e)| s <= end && e >= start)
+            .map(|&(s, e)| e.min(end) - s.max(start) + 1)
+            .sum()
+    }
+
+    // Only blocks with at least one covered code point are listed.
+    pub fn blocks(&self) -> Vec<BlockCoverage> {
+        UNICODE_BLOCKS
+            .iter()
+            .map(|block| BlockCoverage {
+                block,
+                covered: self.count_within(block.start, block.end)
+            })
+            .filter(|coverage| coverage.covered > 0)
+            .collect()
+    }
+
+    pub fn script(&self, script: Script) -> ScriptCoverage {
+        let (covered, total) = UNICODE_BLOCKS
+            .iter()
+            .filter(|block| block.script == script)
+            .fold((0, 0), |(covered, total), block| {
+                (covered + self.count_within(block.start, block.end), total + block.size())
+            });
+        ScriptCoverage { script, covered, total }
+    }
+
+    // In order of first appearance in `UNICODE_BLOCKS`.
+    pub fn scripts(&self) -> Vec<ScriptCoverage> {
+        let mut scripts: Vec<Script> = Vec::new();
+        for coverage in self.blocks() {
+            if !scripts.contains(&coverage.block.script) {
+                scripts.push(coverage.block.script);
+            }
+        }
+        scripts.into_iter().map(|script| self.script(script)).collect()
+    }
+
+    // The characters of a text this face would render as tofu, without
+    // duplicates. Control characters are never expected to have glyphs.
+    pub fn missing_chars<T>(&self, text: T) -> Vec<char>
+    where
+        T: AsRef<str>
+    {
+        let mut missing = Vec::new();
+        for c in text.as_ref().chars() {
+            if !c.is_control() && !self.covers(c) && !missing.contains(&c) {
+                missing.push(c);
+            }
+        }
+        missing
+    }
+}
@@ -12,10 +12,12 @@ specific language governing permissions and limitations under the License.
 use std::rc::Rc;
 
 use base64_util;
+use rsx_resource_updates::shmem::SharedMemory;
 use rsx_shared::traits::TEncodedFont;
 
 use error::{FontError, Result};
 use types::FontEncodedData;
+use woff;
 
 #[derive(Debug, PartialEq)]
 pub enum EncodedFont {
@@ -25,6 +27,48 @@ pub enum EncodedFont {
     BytesAndDataUri {
         bytes: Rc<Vec<u8>>,
         data_uri: Rc<String>
+    },
+    Shared {
+        bytes: Rc<Vec<u8>>,
+        memory: Rc<SharedMemory>
+    }
+}
+
+// WOFF and WOFF2 fonts are unpacked as soon as they're read, so that backends
+// and renderers only ever see sfnt bytes. Their data URIs are dropped, since
+// those would still hold the packed font.
+impl EncodedFont {
+    // Shared memory is mapped by renderers as it is, so it should hold sfnt bytes.
+    pub fn from_shared_memory(memory: SharedMemory) -> Result<Self> {
+        let bytes = Rc::new(memory.read_bytes()?);
+        Ok(EncodedFont::Shared {
+            bytes,
+            memory: Rc::new(memory)
+        })
+    }
+
+    pub fn shared_memory(&self) -> Option<&Rc<SharedMemory>> {
+        match self {
+            &EncodedFont::Shared { ref memory, .. } => Some(memory),
+            _ => None
+        }
+    }
+
+    pub fn from_encoded<E>(encoded: &E) -> Option<Self>
+    where
+        E: TEncodedFont
+    {
+        let bytes = Rc::clone(encoded.bytes()?);
+        if let Some(sfnt) = woff::to_sfnt(&bytes).ok()? {
+            return Some(EncodedFont::Bytes { bytes: Rc::new(sfnt) });
+        }
+        Some(match encoded.data_uri() {
+            Some(data_uri) => EncodedFont::BytesAndDataUri {
+                bytes,
+                data_uri: Rc::clone(data_uri)
+            },
+            None => EncodedFont::Bytes { bytes }
+        })
     }
 }
 
@@ -36,7 +80,10 @@ impl TEncodedFont for EncodedFont {
         T: Into<Rc<Vec<u8>>>
     {
         let bytes = bytes.into();
-        Ok(EncodedFont::Bytes { bytes })
+        let sfnt = woff::to_sfnt(&bytes)?;
+        Ok(EncodedFont::Bytes {
+            bytes: sfnt.map_or(bytes, Rc::new)
+        })
     }
 
     fn from_data_uri<T>(data_uri: T) -> Result<Self>
@@ -44,26 +91,35 @@ impl TEncodedFont for EncodedFont {
         T: Into<Rc<String>>
     {
         let data_uri = data_uri.into();
-        let bytes = Rc::new(base64_util::from_data_uri(&data_uri).map_err(|_| FontError::DataUriDecodeError)?);
-        Ok(EncodedFont::BytesAndDataUri { bytes, data_uri })
+        let bytes = base64_util::from_data_uri(&data_uri).map_err(|_| FontError::DataUriDecodeError)?;
+        let sfnt = woff::to_sfnt(&bytes)?;
+        match sfnt {
+            Some(sfnt) => Ok(EncodedFont::Bytes { bytes: Rc::new(sfnt) }),
+            None => Ok(EncodedFont::BytesAndDataUri {
+                bytes: Rc::new(bytes),
+                data_uri
+            })
+        }
     }
 
     fn bytes(&self) -> Option<&Rc<Vec<u8>>> {
         match self {
-            &EncodedFont::Bytes { ref bytes } | &EncodedFont::BytesAndDataUri { ref bytes, .. } => Some(bytes)
+            &EncodedFont::Bytes { ref bytes }
+            | &EncodedFont::BytesAndDataUri { ref bytes, .. }
+            | &EncodedFont::Shared { ref bytes, .. } => Some(bytes)
         }
     }
 
     fn data_uri(&self) -> Option<&Rc<String>> {
         match self {
-            &EncodedFont::Bytes { .. } => None,
+            &EncodedFont::Bytes { .. } | &EncodedFont::Shared { .. } => None,
             &EncodedFont::BytesAndDataUri { ref data_uri, .. } => Some(data_uri)
         }
     }
 
     fn info(&self) -> FontEncodedData {
         match self {
-            &EncodedFont::Bytes { ref bytes } => FontEncodedData::Bytes { bytes },
+            &EncodedFont::Bytes { ref bytes } | &EncodedFont::Shared { ref bytes, .. } => FontEncodedData::Bytes { bytes },
             &EncodedFont::BytesAndDataUri { ref data_uri, .. } => FontEncodedData::DataUri { data_uri }
         }
     }
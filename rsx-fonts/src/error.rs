@@ -14,6 +14,10 @@ use std::result;
 use std::str;
 
 use freetype::freetype as ft;
+use rsx_files::error::FileError;
+use rsx_resource_updates::codes::{self, ErrorCode};
+use rsx_resource_updates::stats::ErrorCategory;
+use serde_json;
 
 pub type Result<T> = result::Result<T, FontError>;
 
@@ -21,15 +25,122 @@ pub type Result<T> = result::Result<T, FontError>;
 pub enum FontError {
     FTError(ft::FT_Error),
     IOError(io::Error),
+    FileError(FileError),
     Utf8Error(str::Utf8Error),
+    JsonError(serde_json::Error),
     DataUriDecodeError,
+    WoffDecodeError,
+    WoffUnsupported,
     FaceAlreadyAdded,
     FontInstanceAlreadyAdded,
     FaceNotFound,
     FaceNotLoaded,
     FaceFamilyNameMissing,
     FaceSizeMissing,
-    FaceGlyphMissing
+    FaceGlyphMissing,
+    FaceMetricsMissing,
+    FaceGlyphBitmapUnsupported(u8),
+    RasterizationUnsupported,
+    ReplayUnsupported,
+    ThreadUnavailable,
+    ShapingError(ShapingError)
+}
+
+impl FontError {
+    pub fn category(&self) -> Option<ErrorCategory> {
+        match self {
+            &FontError::FTError(_) | &FontError::ShapingError(_) | &FontError::RasterizationUnsupported => {
+                Some(ErrorCategory::FontBackendFailure)
+            }
+            &FontError::IOError(ref err) if err.kind() == io::ErrorKind::NotFound => Some(ErrorCategory::MissingResource),
+            &FontError::FileError(FileError::IOError(ref err)) if err.kind() == io::ErrorKind::NotFound => Some(ErrorCategory::MissingResource),
+            &FontError::Utf8Error(_)
+            | &FontError::JsonError(_)
+            | &FontError::DataUriDecodeError
+            | &FontError::WoffDecodeError
+            | &FontError::WoffUnsupported
+            | &FontError::FaceGlyphBitmapUnsupported(_) => Some(ErrorCategory::DecodeFailure),
+            &FontError::FaceNotFound
+            | &FontError::FaceNotLoaded
+            | &FontError::FaceFamilyNameMissing
+            | &FontError::FaceSizeMissing
+            | &FontError::FaceGlyphMissing
+            | &FontError::FaceMetricsMissing => Some(ErrorCategory::MissingResource),
+            _ => None
+        }
+    }
+
+    // Wrapped file and shaping errors keep their own codes.
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            &FontError::FTError(_) => codes::FONT_FREETYPE,
+            &FontError::IOError(_) => codes::FONT_IO,
+            &FontError::FileError(ref err) => err.code(),
+            &FontError::Utf8Error(_) => codes::FONT_UTF8,
+            &FontError::JsonError(_) => codes::FONT_JSON,
+            &FontError::DataUriDecodeError => codes::FONT_DATA_URI_DECODE,
+            &FontError::WoffDecodeError => codes::FONT_WOFF_DECODE,
+            &FontError::WoffUnsupported => codes::FONT_WOFF_UNSUPPORTED,
+            &FontError::FaceAlreadyAdded => codes::FONT_FACE_ALREADY_ADDED,
+            &FontError::FontInstanceAlreadyAdded => codes::FONT_INSTANCE_ALREADY_ADDED,
+            &FontError::FaceNotFound => codes::FONT_FACE_NOT_FOUND,
+            &FontError::FaceNotLoaded => codes::FONT_FACE_NOT_LOADED,
+            &FontError::FaceFamilyNameMissing => codes::FONT_FACE_FAMILY_NAME_MISSING,
+            &FontError::FaceSizeMissing => codes::FONT_FACE_SIZE_MISSING,
+            &FontError::FaceGlyphMissing => codes::FONT_FACE_GLYPH_MISSING,
+            &FontError::FaceMetricsMissing => codes::FONT_FACE_METRICS_MISSING,
+            &FontError::FaceGlyphBitmapUnsupported(_) => codes::FONT_GLYPH_BITMAP_UNSUPPORTED,
+            &FontError::RasterizationUnsupported => codes::FONT_RASTERIZATION_UNSUPPORTED,
+            &FontError::ReplayUnsupported => codes::FONT_REPLAY_UNSUPPORTED,
+            &FontError::ThreadUnavailable => codes::FONT_THREAD_UNAVAILABLE,
+            &FontError::ShapingError(ref err) => err.code()
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            &FontError::FTError(err) => format!("FreeType error {}", err),
+            &FontError::IOError(ref err) => format!("I/O error: {}", err),
+            &FontError::FileError(ref err) => err.message(),
+            &FontError::Utf8Error(ref err) => format!("Invalid UTF-8: {}", err),
+            &FontError::JsonError(ref err) => format!("Invalid JSON: {}", err),
+            &FontError::DataUriDecodeError => "Invalid data URI".to_string(),
+            &FontError::WoffDecodeError => "Invalid WOFF font".to_string(),
+            &FontError::WoffUnsupported => "Unsupported WOFF font".to_string(),
+            &FontError::FaceAlreadyAdded => "Font face already added".to_string(),
+            &FontError::FontInstanceAlreadyAdded => "Font instance already added".to_string(),
+            &FontError::FaceNotFound => "Font face not found".to_string(),
+            &FontError::FaceNotLoaded => "Font face not loaded".to_string(),
+            &FontError::FaceFamilyNameMissing => "Font face has no family name".to_string(),
+            &FontError::FaceSizeMissing => "Font face has no size".to_string(),
+            &FontError::FaceGlyphMissing => "Glyph missing from font face".to_string(),
+            &FontError::FaceMetricsMissing => "Font face has no metrics".to_string(),
+            &FontError::FaceGlyphBitmapUnsupported(mode) => format!("Unsupported glyph bitmap pixel mode {}", mode),
+            &FontError::RasterizationUnsupported => "Rasterization unsupported".to_string(),
+            &FontError::ReplayUnsupported => "Replay unsupported".to_string(),
+            &FontError::ThreadUnavailable => "Font thread unavailable".to_string(),
+            &FontError::ShapingError(ref err) => err.message()
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum ShapingError {
+    Overflow
+}
+
+impl ShapingError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            &ShapingError::Overflow => codes::SHAPING_OVERFLOW
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            &ShapingError::Overflow => "Shaped run overflowed".to_string()
+        }
+    }
 }
 
 impl From<ft::FT_Error> for FontError {
@@ -44,8 +155,26 @@ impl From<io::Error> for FontError {
     }
 }
 
+impl From<FileError> for FontError {
+    fn from(err: FileError) -> Self {
+        FontError::FileError(err)
+    }
+}
+
 impl From<str::Utf8Error> for FontError {
     fn from(err: str::Utf8Error) -> Self {
         FontError::Utf8Error(err)
     }
 }
+
+impl From<serde_json::Error> for FontError {
+    fn from(err: serde_json::Error) -> Self {
+        FontError::JsonError(err)
+    }
+}
+
+impl From<ShapingError> for FontError {
+    fn from(err: ShapingError) -> Self {
+        FontError::ShapingError(err)
+    }
+}
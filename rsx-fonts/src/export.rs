@@ -9,8 +9,12 @@ CONDITIONS OF ANY KIND, either express or implied. See the License for the
 specific language governing permissions and limitations under the License.
 */
 
+use std::borrow::Cow;
+
+use fnv::FnvHashSet;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
+use sfnt::{self, read_u16, read_u32};
 use types::GlyphsArray;
 
 impl<GlyphInstance> Serialize for GlyphsArray<GlyphInstance>
@@ -33,3 +37,184 @@ impl<'de, GlyphInstance> Deserialize<'de> for GlyphsArray<GlyphInstance> {
         unimplemented!()
     }
 }
+
+const GLYF_TAG: &[u8] = b"glyf";
+const LOCA_TAG: &[u8] = b"loca";
+const HEAD_TAG: &[u8] = b"head";
+const MAXP_TAG: &[u8] = b"maxp";
+// Signatures no longer match once glyphs are dropped.
+const DSIG_TAG: &[u8] = b"DSIG";
+
+const ARG_1_AND_2_ARE_WORDS: u16 = 1;
+const WE_HAVE_A_SCALE: u16 = 1 << 3;
+const MORE_COMPONENTS: u16 = 1 << 5;
+const WE_HAVE_AN_X_AND_Y_SCALE: u16 = 1 << 6;
+const WE_HAVE_A_TWO_BY_TWO: u16 = 1 << 7;
+
+const CHECKSUM_MAGIC: u32 = 0xB1B0_AFBA;
+
+// Subsets a TrueType face down to the given glyphs, `.notdef` and the
+// components of composite glyphs. Other glyphs are emptied rather than
+// removed, so that glyph indices in shaped runs stay valid for the subset.
+// Collections and faces without glyf outlines, e.g. CFF ones, aren't subset.
+pub fn subset_glyphs(bytes: &[u8], glyphs: &FnvHashSet<u32>) -> Option<Vec<u8>> {
+    if sfnt::is_collection(bytes) {
+        return None;
+    }
+    let tables = read_tables(bytes)?;
+    let table = |tag: &[u8]| tables.iter().find(|&&(t, _)| t == tag).map(|&(_, data)| data);
+    let (head, glyf, loca) = (table(HEAD_TAG)?, table(GLYF_TAG)?, table(LOCA_TAG)?);
+    let glyph_count = u32::from(read_u16(table(MAXP_TAG)?, 4)?);
+    let long_offsets = read_u16(head, 50)? != 0;
+    let glyph_data = |glyph: u32| -> Option<&[u8]> {
+        let (start, end) = if long_offsets {
+            (read_u32(loca, glyph as usize * 4)?, read_u32(loca, glyph as usize * 4 + 4)?)
+        } else {
+            (u32::from(read_u16(loca, glyph as usize * 2)?) * 2, u32::from(read_u16(loca, glyph as usize * 2 + 2)?) * 2)
+        };
+        glyf.get(start as usize..end as usize)
+    };
+
+    let mut retained = FnvHashSet::default();
+    let mut pending: Vec<u32> = glyphs.iter().cloned().chain(Some(0)).collect();
+    while let Some(glyph) = pending.pop() {
+        if glyph >= glyph_count || !retained.insert(glyph) {
+            continue;
+        }
+        pending.extend(composite_components(glyph_data(glyph)?)?);
+    }
+
+    let mut subset_glyf = Vec::new();
+    let mut subset_loca = Vec::with_capacity((glyph_count as usize + 1) * 4);
+    for glyph in 0..glyph_count {
+        push_u32(&mut subset_loca, subset_glyf.len() as u32);
+        if retained.contains(&glyph) {
+            subset_glyf.extend_from_slice(glyph_data(glyph)?);
+            pad(&mut subset_glyf);
+        }
+    }
+    push_u32(&mut subset_loca, subset_glyf.len() as u32);
+
+    // Offsets are always long, and the checksum adjustment is redone below.
+    let mut subset_head = head.to_vec();
+    subset_head.get_mut(8..12)?.copy_from_slice(&[0; 4]);
+    subset_head.get_mut(50..52)?.copy_from_slice(&[0, 1]);
+
+    let subset_tables: Vec<(&[u8], Cow<[u8]>)> = tables
+        .iter()
+        .filter(|&&(tag, _)| tag != DSIG_TAG)
+        .map(|&(tag, data)| {
+            let data = if tag == GLYF_TAG {
+                Cow::from(&subset_glyf[..])
+            } else if tag == LOCA_TAG {
+                Cow::from(&subset_loca[..])
+            } else if tag == HEAD_TAG {
+                Cow::from(&subset_head[..])
+            } else {
+                Cow::from(data)
+            };
+            (tag, data)
+        })
+        .collect();
+    Some(write_tables(bytes.get(0..4)?, &subset_tables))
+}
+
+fn read_tables(bytes: &[u8]) -> Option<Vec<(&[u8], &[u8])>> {
+    let num_tables = usize::from(read_u16(bytes, 4)?);
+    (0..num_tables)
+        .map(|i| {
+            let record = 12 + i * 16;
+            let start = read_u32(bytes, record + 8)? as usize;
+            let len = read_u32(bytes, record + 12)? as usize;
+            Some((bytes.get(record..record + 4)?, bytes.get(start..start.checked_add(len)?)?))
+        })
+        .collect()
+}
+
+// Indices of the glyphs a composite glyph is made of, none for simple glyphs.
+fn composite_components(data: &[u8]) -> Option<Vec<u32>> {
+    let mut components = Vec::new();
+    if data.len() < 10 || read_u16(data, 0)? as i16 >= 0 {
+        return Some(components);
+    }
+    let mut offset = 10;
+    loop {
+        let flags = read_u16(data, offset)?;
+        components.push(u32::from(read_u16(data, offset + 2)?));
+        offset += if flags & ARG_1_AND_2_ARE_WORDS != 0 { 8 } else { 6 };
+        offset += if flags & WE_HAVE_A_SCALE != 0 {
+            2
+        } else if flags & WE_HAVE_AN_X_AND_Y_SCALE != 0 {
+            4
+        } else if flags & WE_HAVE_A_TWO_BY_TWO != 0 {
+            8
+        } else {
+            0
+        };
+        if flags & MORE_COMPONENTS == 0 {
+            return Some(components);
+        }
+    }
+}
+
+// Tables keep their order, which sfnt files sort by tag.
+fn write_tables(sfnt_version: &[u8], tables: &[(&[u8], Cow<[u8]>)]) -> Vec<u8> {
+    let num_tables = tables.len() as u16;
+    let entry_selector = 15 - num_tables.max(1).leading_zeros() as u16;
+    let search_range = (1 << entry_selector) * 16;
+
+    let mut bytes = sfnt_version.to_vec();
+    push_u16(&mut bytes, num_tables);
+    push_u16(&mut bytes, search_range);
+    push_u16(&mut bytes, entry_selector);
+    push_u16(&mut bytes, num_tables * 16 - search_range);
+
+    let mut offset = 12 + tables.len() * 16;
+    for &(tag, ref data) in tables {
+        bytes.extend_from_slice(tag);
+        push_u32(&mut bytes, checksum(data));
+        push_u32(&mut bytes, offset as u32);
+        push_u32(&mut bytes, data.len() as u32);
+        offset += (data.len() + 3) & !3;
+    }
+    let mut head_offset = None;
+    for &(tag, ref data) in tables {
+        if tag == HEAD_TAG {
+            head_offset = Some(bytes.len());
+        }
+        bytes.extend_from_slice(data);
+        pad(&mut bytes);
+    }
+
+    if let Some(head_offset) = head_offset {
+        let adjustment = CHECKSUM_MAGIC.wrapping_sub(checksum(&bytes));
+        let mut adjustment_bytes = Vec::with_capacity(4);
+        push_u32(&mut adjustment_bytes, adjustment);
+        bytes[head_offset + 8..head_offset + 12].copy_from_slice(&adjustment_bytes);
+    }
+    bytes
+}
+
+fn checksum(data: &[u8]) -> u32 {
+    data.chunks(4).fold(0u32, |sum, chunk| {
+        let word = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |word, (i, &byte)| word | u32::from(byte) << (24 - i * 8));
+        sum.wrapping_add(word)
+    })
+}
+
+fn pad(bytes: &mut Vec<u8>) {
+    while bytes.len() % 4 != 0 {
+        bytes.push(0);
+    }
+}
+
+fn push_u16(bytes: &mut Vec<u8>, value: u16) {
+    bytes.extend_from_slice(&[(value >> 8) as u8, value as u8]);
+}
+
+fn push_u32(bytes: &mut Vec<u8>, value: u32) {
+    bytes.extend_from_slice(&[(value >> 24) as u8, (value >> 16) as u8, (value >> 8) as u8, value as u8]);
+}
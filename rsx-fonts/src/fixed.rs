@@ -0,0 +1,92 @@
+/*
+Copyright 2016 Mozilla
+Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+this file except in compliance with the License. You may obtain a copy of the
+License at http://www.apache.org/licenses/LICENSE-2.0
+Unless required by applicable law or agreed to in writing, software distributed
+under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+CONDITIONS OF ANY KIND, either express or implied. See the License for the
+specific language governing permissions and limitations under the License.
+*/
+
+use std::i32;
+
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Fixed26_6(i32);
+
+impl Fixed26_6 {
+    pub const ZERO: Fixed26_6 = Fixed26_6(0);
+    pub const MAX: Fixed26_6 = Fixed26_6(i32::MAX);
+    pub const MIN: Fixed26_6 = Fixed26_6(i32::MIN);
+
+    pub fn from_raw(raw: i32) -> Self {
+        Fixed26_6(raw)
+    }
+
+    pub fn from_raw_i64(raw: i64) -> Option<Self> {
+        if raw > i64::from(i32::MAX) || raw < i64::from(i32::MIN) {
+            None
+        } else {
+            Some(Fixed26_6(raw as i32))
+        }
+    }
+
+    pub fn from_int(value: i32) -> Option<Self> {
+        value.checked_mul(64).map(Fixed26_6)
+    }
+
+    pub fn from_f32(value: f32) -> Option<Self> {
+        let raw = (value * 64.0).round();
+        if raw.is_nan() || raw > i32::MAX as f32 || raw < i32::MIN as f32 {
+            None
+        } else {
+            Some(Fixed26_6(raw as i32))
+        }
+    }
+
+    pub fn raw(self) -> i32 {
+        self.0
+    }
+
+    pub fn to_f32(self) -> f32 {
+        self.0 as f32 / 64.0
+    }
+
+    pub fn floor(self) -> i32 {
+        self.0 >> 6
+    }
+
+    pub fn ceil(self) -> i32 {
+        ((i64::from(self.0) + 63) >> 6) as i32
+    }
+
+    pub fn round(self) -> i32 {
+        ((i64::from(self.0) + 32) >> 6) as i32
+    }
+
+    pub fn checked_add(self, other: Self) -> Option<Self> {
+        self.0.checked_add(other.0).map(Fixed26_6)
+    }
+
+    pub fn checked_sub(self, other: Self) -> Option<Self> {
+        self.0.checked_sub(other.0).map(Fixed26_6)
+    }
+
+    pub fn checked_mul_int(self, factor: i32) -> Option<Self> {
+        self.0.checked_mul(factor).map(Fixed26_6)
+    }
+
+    pub fn saturating_add(self, other: Self) -> Self {
+        Fixed26_6(self.0.saturating_add(other.0))
+    }
+
+    pub fn saturating_sub(self, other: Self) -> Self {
+        Fixed26_6(self.0.saturating_sub(other.0))
+    }
+}
+
+impl From<Fixed26_6> for f32 {
+    fn from(value: Fixed26_6) -> Self {
+        value.to_f32()
+    }
+}
@@ -10,21 +10,42 @@ specific language governing permissions and limitations under the License.
 */
 
 use std::collections::hash_map::Entry;
-use std::hash::{Hash, Hasher};
 use std::ptr;
 use std::rc::Rc;
 
-use fnv::{FnvHashMap, FnvHasher};
-use freetype::freetype::{FT_Init_FreeType, FT_Library};
-use rsx_shared::traits::{TFontInstanceKey, TFontKey, TGlyphInstance};
+use fnv::FnvHashMap;
+use freetype::freetype::{FT_Init_FreeType, FT_Library, FT_Library_SetLcdFilter};
+use rsx_resource_updates::display::SubpixelLayout;
+use rsx_resource_updates::pixels;
 
-use error::{FontError, Result};
-use font_face::{FontFace, LoadFlag};
-use types::{FontId, FontInstance, FontSizeMetrics, GlyphDimensions, GlyphStore, GlyphsArray};
+use atlas::SubpixelOffset;
+use backend::TFontBackend;
+use coverage::CoverageReport;
+use error::{FontError, Result, ShapingError};
+use fixed::Fixed26_6;
+use font_face::{FontFace, LoadedGlyph, SizeRequest};
+use glyph_load::{GlyphLoadPurpose, LcdFilter, LoadFlag, RenderTarget};
+use palette::{ColorLayer, ColorPalette};
+use types::{
+    AlphaMode,
+    DesignGlyphMetrics,
+    FontId,
+    FontInstance,
+    FontSizeMetrics,
+    FontSizeUnit,
+    GlyphBitmap,
+    GlyphDimensions,
+    GlyphInkBounds,
+    GlyphLinearAdvance,
+    GlyphVerticalBearings,
+    ImagePixelFormat,
+    LinearGlyphMetrics
+};
 
 #[derive(Debug, PartialEq)]
 pub struct FontContext {
     library: FT_Library,
+    lcd_filter: LcdFilter,
     faces: FnvHashMap<FontId, FontFace>
 }
 
@@ -37,12 +58,151 @@ impl FontContext {
         } else {
             Ok(FontContext {
                 library,
+                lcd_filter: LcdFilter::None,
                 faces: FnvHashMap::default()
             })
         }
     }
 
-    pub fn add_face(&mut self, font_id: FontId, bytes: &Rc<Vec<u8>>, face_index: usize) -> Result<()> {
+    fn load_glyph<FontKey, FontInstanceKey, GlyphInstance>(
+        &self,
+        instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>,
+        c: char,
+        purpose: GlyphLoadPurpose
+    ) -> Result<(&FontFace, u32)> {
+        self.load_glyph_at_offset(instance, c, purpose, SubpixelOffset::Zero)
+    }
+
+    fn load_glyph_at_offset<FontKey, FontInstanceKey, GlyphInstance>(
+        &self,
+        instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>,
+        c: char,
+        purpose: GlyphLoadPurpose,
+        offset: SubpixelOffset
+    ) -> Result<(&FontFace, u32)> {
+        let glyph_index = self.get_glyph_index(instance, c)?;
+        let (face, _) = self.load_glyph_index(instance, glyph_index, purpose, offset)?;
+        Ok((face, glyph_index))
+    }
+
+    fn load_glyph_index<FontKey, FontInstanceKey, GlyphInstance>(
+        &self,
+        instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>,
+        glyph_index: u32,
+        purpose: GlyphLoadPurpose,
+        offset: SubpixelOffset
+    ) -> Result<(&FontFace, LoadedGlyph)> {
+        let font_id = instance.font_id();
+        let face = self.faces.get(&font_id).ok_or(FontError::FaceNotFound)?;
+
+        set_instance_size(face, instance)?;
+        face.set_transform(instance.transform(), offset.x_64());
+        let loaded = face.load_glyph(glyph_index, instance.load_policy().load_flags(purpose))?;
+
+        Ok((face, loaded))
+    }
+
+    // FreeType leaves the metrics untransformed, so positions are derived
+    // from the transformed advances to match the rasterized outlines.
+    fn loaded_glyph_dimensions<FontKey, FontInstanceKey, GlyphInstance>(
+        face: &FontFace,
+        instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>,
+        loaded: LoadedGlyph
+    ) -> Result<GlyphDimensions> {
+        let metrics = face.get_glyph_metrics()?;
+        let transform = instance.transform();
+        let (width, height) = transform.bounds_64(metrics.width.into(), metrics.height.into());
+        let (hori_advance, hori_advance_y) = transform.advance_64(metrics.horiAdvance.into(), false);
+        let (vert_advance_x, vert_advance) = transform.advance_64(metrics.vertAdvance.into(), true);
+
+        Ok(GlyphDimensions {
+            glyph_index: loaded.glyph_index,
+            width_64: checked_pos(width)?,
+            height_64: checked_pos(height)?,
+            hori_advance_64: checked_pos(hori_advance)?,
+            vert_advance_64: checked_pos(vert_advance)?,
+            hori_advance_y_64: checked_pos(hori_advance_y)?,
+            vert_advance_x_64: checked_pos(vert_advance_x)?,
+            color: face.is_color_glyph(loaded)
+        })
+    }
+
+    // Color glyphs are drawn layer by layer, each layer's coverage tinted with
+    // its palette entry and blended over the layers below it. The face must
+    // already be sized and transformed for the instance.
+    fn composite_color_glyph<FontKey, FontInstanceKey, GlyphInstance>(
+        &self,
+        face: &FontFace,
+        instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>,
+        glyph_index: u32,
+        layers: &[ColorLayer]
+    ) -> Result<GlyphBitmap> {
+        let palettes = face.get_color_tables().palettes();
+        let selection = instance.palette();
+        let flags = instance.load_policy().load_flags(GlyphLoadPurpose::RasterGray);
+
+        let mut coverages = Vec::with_capacity(layers.len());
+        for layer in layers {
+            face.load_glyph(layer.glyph_index, flags)?;
+            let coverage = face.get_glyph_bitmap(layer.glyph_index)?;
+            if coverage.format == ImagePixelFormat::Gray(8) && coverage.size.0 > 0 && coverage.size.1 > 0 {
+                coverages.push((coverage, selection.resolve(palettes, layer.palette_entry)));
+            }
+        }
+
+        let left = coverages.iter().map(|&(ref c, _)| c.bearing.0).min().unwrap_or(0);
+        let top = coverages.iter().map(|&(ref c, _)| c.bearing.1).max().unwrap_or(0);
+        let right = coverages.iter().map(|&(ref c, _)| c.bearing.0 + c.size.0 as i32).max().unwrap_or(0);
+        let bottom = coverages.iter().map(|&(ref c, _)| c.bearing.1 - c.size.1 as i32).min().unwrap_or(0);
+        let (width, height) = ((right - left) as usize, (top - bottom) as usize);
+
+        let mut pixels = vec![0; width * height * 4];
+        for &(ref coverage, color) in &coverages {
+            let x_start = (coverage.bearing.0 - left) as usize;
+            let y_start = (top - coverage.bearing.1) as usize;
+            let coverage_width = coverage.size.0 as usize;
+            for (row, values) in coverage.pixels.chunks(coverage_width).enumerate() {
+                for (column, &value) in values.iter().enumerate() {
+                    let alpha = (u32::from(value) * u32::from(color.a) + 127) / 255;
+                    let start = ((y_start + row) * width + x_start + column) * 4;
+                    let pixel = &mut pixels[start..start + 4];
+                    let source = [color.r, color.g, color.b, 255];
+                    for (channel, &source) in pixel.iter_mut().zip(source.iter()) {
+                        let over = (u32::from(*channel) * (255 - alpha) + 127) / 255;
+                        *channel = ((u32::from(source) * alpha + 127) / 255 + over).min(255) as u8;
+                    }
+                }
+            }
+        }
+
+        let format = pixels::to_canonical(ImagePixelFormat::RGBA(8), AlphaMode::Premultiplied, &mut pixels);
+        Ok(GlyphBitmap {
+            glyph_index,
+            format,
+            alpha_mode: pixels::CANONICAL_ALPHA_MODE,
+            size: (width as u32, height as u32),
+            bearing: (left, top),
+            pixels
+        })
+    }
+}
+
+impl TFontBackend for FontContext {
+    fn lcd_filter(&self) -> LcdFilter {
+        self.lcd_filter
+    }
+
+    fn set_lcd_filter(&mut self, filter: LcdFilter) -> Result<()> {
+        let result = unsafe { FT_Library_SetLcdFilter(self.library, filter.to_ft()) };
+        if !result.succeeded() {
+            Err(result)?
+        } else {
+            self.lcd_filter = filter;
+            Ok(())
+        }
+    }
+
+    fn add_face(&mut self, font_id: FontId, bytes: &Rc<Vec<u8>>, face_index: usize) -> Result<()> {
         match self.faces.entry(font_id) {
             Entry::Occupied(_) => {
                 Err(FontError::FaceAlreadyAdded)?;
@@ -55,28 +215,58 @@ impl FontContext {
         Ok(())
     }
 
-    pub fn get_bytes(&self, font_id: FontId) -> Result<Rc<Vec<u8>>> {
+    fn remove_face(&mut self, font_id: FontId) -> Result<()> {
+        self.faces
+            .remove(&font_id)
+            .ok_or(FontError::FaceNotFound)
+            .map(|_| ())
+    }
+
+    fn font_ids(&self) -> Vec<FontId> {
+        self.faces.keys().cloned().collect()
+    }
+
+    fn get_bytes(&self, font_id: FontId) -> Result<Rc<Vec<u8>>> {
         self.faces
             .get(&font_id)
             .ok_or(FontError::FaceNotFound)
             .map(|f| f.get_bytes())
     }
 
-    pub fn get_face_index(&self, font_id: FontId) -> Result<usize> {
+    fn get_face_index(&self, font_id: FontId) -> Result<usize> {
         self.faces
             .get(&font_id)
             .ok_or(FontError::FaceNotFound)
             .map(|f| f.get_face_index())
     }
 
-    pub fn get_family_name(&self, font_id: FontId) -> Result<&str> {
+    fn get_family_name(&self, font_id: FontId) -> Result<&str> {
         self.faces
             .get(&font_id)
             .ok_or(FontError::FaceNotFound)
             .and_then(|f| f.get_family_name())
     }
 
-    pub fn get_glyph_index<FontKey, FontInstanceKey, GlyphInstance>(
+    fn get_coverage(&self, font_id: FontId) -> Result<CoverageReport> {
+        self.faces
+            .get(&font_id)
+            .ok_or(FontError::FaceNotFound)
+            .map(|f| CoverageReport::from_code_points(f.get_char_codes()))
+    }
+
+    #[cfg(feature = "harfbuzz")]
+    fn supports_complex_shaping(&self) -> bool {
+        true
+    }
+
+    fn get_palettes(&self, font_id: FontId) -> Result<Vec<ColorPalette>> {
+        self.faces
+            .get(&font_id)
+            .ok_or(FontError::FaceNotFound)
+            .map(|f| f.get_color_tables().palettes().to_vec())
+    }
+
+    fn get_glyph_index<FontKey, FontInstanceKey, GlyphInstance>(
         &self,
         instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>,
         c: char
@@ -87,175 +277,286 @@ impl FontContext {
         Ok(face.get_char_index(c))
     }
 
-    pub fn get_glyph_dimensions<FontKey, FontInstanceKey, GlyphInstance>(
+    fn get_glyph_dimensions<FontKey, FontInstanceKey, GlyphInstance>(
         &self,
         instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>,
         c: char
     ) -> Result<GlyphDimensions> {
-        let font_id = instance.font_id();
-        let face = self.faces.get(&font_id).ok_or(FontError::FaceNotFound)?;
-        let point_size = (instance.size() * 64) as usize;
         let glyph_index = self.get_glyph_index(instance, c)?;
+        let (face, loaded) = self.load_glyph_index(instance, glyph_index, GlyphLoadPurpose::Measure, SubpixelOffset::Zero)?;
+        Self::loaded_glyph_dimensions(face, instance, loaded)
+    }
+
+    fn get_glyph_index_dimensions<FontKey, FontInstanceKey, GlyphInstance>(
+        &self,
+        instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>,
+        glyph_index: u32
+    ) -> Result<Option<GlyphDimensions>> {
+        let (face, loaded) = self.load_glyph_index(instance, glyph_index, GlyphLoadPurpose::Measure, SubpixelOffset::Zero)?;
+        Ok(Some(Self::loaded_glyph_dimensions(face, instance, loaded)?))
+    }
 
-        face.set_char_size(point_size, 0, instance.dpi(), 0)?;
-        face.load_glyph(glyph_index, LoadFlag::NO_HINTING | LoadFlag::NO_BITMAP)?;
+    fn get_glyph_ink_bounds<FontKey, FontInstanceKey, GlyphInstance>(
+        &self,
+        instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>,
+        c: char
+    ) -> Result<GlyphInkBounds> {
+        let (face, _) = self.load_glyph(instance, c, GlyphLoadPurpose::Measure)?;
         let metrics = face.get_glyph_metrics()?;
 
-        Ok(GlyphDimensions {
+        let x_min: i64 = metrics.horiBearingX.into();
+        let y_max: i64 = metrics.horiBearingY.into();
+        let x_max = x_min + i64::from(metrics.width);
+        let y_min = y_max - i64::from(metrics.height);
+
+        Ok(GlyphInkBounds::from_outline_box(instance.transform(), x_min, y_min, x_max, y_max))
+    }
+
+    // From vmtx, or synthesized by FreeType for faces without vertical
+    // metrics. Vertical origins from VORG tables take precedence, placing the
+    // top of the glyph's box below the origin by its distance from the box.
+    fn get_glyph_vertical_bearings<FontKey, FontInstanceKey, GlyphInstance>(
+        &self,
+        instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>,
+        c: char
+    ) -> Result<GlyphVerticalBearings> {
+        let (face, glyph_index) = self.load_glyph(instance, c, GlyphLoadPurpose::Measure)?;
+        let (bearing_x, bearing_y) = face.get_glyph_vertical_bearings()?;
+        let bearing_y: i64 = match face.get_vertical_origin_y(glyph_index) {
+            Some(origin_y) => {
+                let y_scale: i64 = face.get_size_metrics()?.y_scale.into();
+                let origin_y_64 = (i64::from(origin_y) * y_scale + 0x8000) >> 16;
+                origin_y_64 - i64::from(face.get_glyph_metrics()?.horiBearingY)
+            }
+            None => bearing_y.into()
+        };
+        let (bearing_x, bearing_y) = instance.transform().apply_64(bearing_x.into(), bearing_y);
+        Ok(GlyphVerticalBearings {
+            bearing_x_64: checked_pos(bearing_x)?,
+            bearing_y_64: checked_pos(bearing_y)?
+        })
+    }
+
+    fn get_glyph_linear_advance<FontKey, FontInstanceKey, GlyphInstance>(
+        &self,
+        instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>,
+        c: char
+    ) -> Result<GlyphLinearAdvance> {
+        let (face, glyph_index) = self.load_glyph(instance, c, GlyphLoadPurpose::LinearMetrics)?;
+        let (hori_advance, vert_advance) = face.get_glyph_linear_advance()?;
+
+        Ok(GlyphLinearAdvance {
             glyph_index,
-            width_64: metrics.width as i32,
-            height_64: metrics.height as i32,
-            hori_advance_64: metrics.horiAdvance as i32,
-            vert_advance_64: metrics.vertAdvance as i32
+            hori_advance_65536: hori_advance as i64,
+            vert_advance_65536: vert_advance as i64
         })
     }
 
-    pub fn get_global_size_metrics<FontKey, FontInstanceKey, GlyphInstance>(
+    // Loaded unscaled, so the size and transform of the instance don't matter,
+    // only its load policy.
+    fn get_linear_glyph_metrics<FontKey, FontInstanceKey, GlyphInstance>(
         &self,
-        instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>
-    ) -> Result<FontSizeMetrics> {
-        let font_id = instance.font_id();
+        instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>,
+        c: char
+    ) -> Result<LinearGlyphMetrics> {
+        let face = self.faces.get(&instance.font_id()).ok_or(FontError::FaceNotFound)?;
+        let glyph_index = self.get_glyph_index(instance, c)?;
+        let flags = instance.load_policy().load_flags(GlyphLoadPurpose::LinearMetrics) | LoadFlag::LINEAR_DESIGN;
+        face.load_glyph(glyph_index, flags)?;
+        let (hori_advance, vert_advance) = face.get_glyph_linear_advance()?;
+
+        Ok(LinearGlyphMetrics {
+            glyph_index,
+            units_per_em: face.get_units_per_em()?,
+            hori_advance: hori_advance as i64,
+            vert_advance: vert_advance as i64
+        })
+    }
 
+    // Loaded without scaling, hinting or the instance transform, so the face
+    // is never sized for these, whatever instances of it were used before.
+    fn get_design_glyph_metrics(&self, font_id: FontId, chars: &[char]) -> Result<Vec<DesignGlyphMetrics>> {
         let face = self.faces.get(&font_id).ok_or(FontError::FaceNotFound)?;
-        let point_size = (instance.size() * 64) as usize;
+        let units_per_em = face.get_units_per_em()?;
+        let mut metrics = Vec::with_capacity(chars.len());
+        for &c in chars {
+            let glyph_index = face.get_char_index(c);
+            face.load_glyph(glyph_index, LoadFlag::NO_SCALE | LoadFlag::IGNORE_TRANSFORM)?;
+            let glyph_metrics = face.get_glyph_metrics()?;
+            metrics.push(DesignGlyphMetrics {
+                glyph_index,
+                units_per_em,
+                width: glyph_metrics.width as i64,
+                height: glyph_metrics.height as i64,
+                hori_bearing_x: glyph_metrics.horiBearingX as i64,
+                hori_bearing_y: glyph_metrics.horiBearingY as i64,
+                hori_advance: glyph_metrics.horiAdvance as i64,
+                vert_advance: glyph_metrics.vertAdvance as i64
+            });
+        }
+        Ok(metrics)
+    }
 
-        face.set_char_size(point_size, 0, instance.dpi(), 0)?;
-        let face_metrics = face.get_size_metrics()?;
+    // Adjustments are in the face's coordinates, so they go through the
+    // transform like advances do.
+    fn get_kerning<FontKey, FontInstanceKey, GlyphInstance>(
+        &self,
+        instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>,
+        left_glyph: u32,
+        right_glyph: u32
+    ) -> Result<(i32, i32)> {
+        let face = self.faces.get(&instance.font_id()).ok_or(FontError::FaceNotFound)?;
+        set_instance_size(face, instance)?;
+        let (kerning_x, kerning_y) = instance
+            .transform()
+            .advance_64(face.get_kerning(left_glyph, right_glyph)?.into(), false);
+        Ok((checked_pos(kerning_x)?, checked_pos(kerning_y)?))
+    }
 
-        Ok(FontSizeMetrics {
-            nominal_width: face_metrics.x_ppem,
-            nominal_height: face_metrics.y_ppem,
-            ascender_64: face_metrics.ascender as i32,
-            descender_64: face_metrics.descender as i32,
-            height_64: face_metrics.height as i32,
-            max_advance_64: face_metrics.max_advance as i32
-        })
+    fn get_glyph_bitmap<FontKey, FontInstanceKey, GlyphInstance>(
+        &self,
+        instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>,
+        c: char
+    ) -> Result<GlyphBitmap> {
+        self.get_glyph_subpixel_bitmap_at_offset(instance, c, SubpixelLayout::None, SubpixelOffset::Zero)
     }
 
-    pub fn shape_text_h<T, FontKey, FontInstanceKey, GlyphInstance>(
+    fn get_glyph_subpixel_bitmap<FontKey, FontInstanceKey, GlyphInstance>(
         &self,
         instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>,
-        text: T
-    ) -> Result<GlyphStore<FontKey, FontInstanceKey, GlyphInstance>>
-    where
-        T: AsRef<str>,
-        FontKey: TFontKey,
-        FontInstanceKey: TFontInstanceKey,
-        GlyphInstance: TGlyphInstance
-    {
-        let text = text.as_ref();
-
-        let mut hasher = FnvHasher::default();
-        // instance.external_key().hash(&mut hasher);
-        // instance.external_instance_key().hash(&mut hasher);
-        text.hash(&mut hasher);
-
-        let generation_id = hasher.finish();
-        let mut cache = instance.shaped_text_h_cache.borrow_mut();
-
-        match cache.entry(generation_id) {
-            Entry::Occupied(e) => Ok(GlyphStore::clone(e.get())),
-            Entry::Vacant(e) => {
-                let mut glyphs = Vec::with_capacity(text.len());
-                let mut pen_position_64 = 0;
-                let font_size_metrics = self.get_global_size_metrics(instance)?;
-                let pen_baseline_64 = font_size_metrics.ascender_64;
-
-                for c in text.chars() {
-                    let GlyphDimensions {
-                        glyph_index,
-                        hori_advance_64,
-                        ..
-                    } = self.get_glyph_dimensions(instance, c)?;
-
-                    glyphs.push(GlyphInstance::new(
-                        glyph_index,
-                        pen_position_64,
-                        pen_baseline_64
-                    ));
-                    pen_position_64 += hori_advance_64;
-                }
+        c: char,
+        layout: SubpixelLayout
+    ) -> Result<GlyphBitmap> {
+        self.get_glyph_subpixel_bitmap_at_offset(instance, c, layout, SubpixelOffset::Zero)
+    }
 
-                Ok(GlyphStore::clone(
-                    e.insert(GlyphStore {
-                        generation_id,
-                        font_key: instance.external_key(),
-                        font_instance_key: instance.external_instance_key(),
-                        width_64: pen_position_64,
-                        height_64: font_size_metrics.height_64,
-                        glyphs: GlyphsArray(Rc::from(glyphs.into_boxed_slice()))
-                    })
-                ))
+    // Coverage channels are always ordered red, green, blue, regardless of the
+    // physical order of the subpixels.
+    fn get_glyph_subpixel_bitmap_at_offset<FontKey, FontInstanceKey, GlyphInstance>(
+        &self,
+        instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>,
+        c: char,
+        layout: SubpixelLayout,
+        offset: SubpixelOffset
+    ) -> Result<GlyphBitmap> {
+        let purpose = match layout {
+            SubpixelLayout::None => GlyphLoadPurpose::RasterGray,
+            _ => GlyphLoadPurpose::RasterLCD
+        };
+        let (face, glyph_index) = self.load_glyph_at_offset(instance, c, purpose, offset)?;
+        if layout == SubpixelLayout::None {
+            let layers = face.get_color_tables().layers(glyph_index);
+            if !layers.is_empty() {
+                return self.composite_color_glyph(face, instance, glyph_index, &layers);
             }
         }
+        let mut bitmap = face.get_glyph_bitmap(glyph_index)?;
+
+        if bitmap.format == ImagePixelFormat::RGB(8) && layout == SubpixelLayout::BGR {
+            pixels::swap_red_blue(&mut bitmap.pixels, 3);
+        }
+        bitmap.format = pixels::to_canonical(bitmap.format, bitmap.alpha_mode, &mut bitmap.pixels);
+        bitmap.alpha_mode = pixels::CANONICAL_ALPHA_MODE;
+
+        Ok(bitmap)
     }
 
-    pub fn shape_text_v<T, FontKey, FontInstanceKey, GlyphInstance>(
+    // Loads the outline and has FreeType render it, with the gray rasterization
+    // options of the instance. Color bitmaps are reduced to their alpha.
+    fn rasterize_glyph<FontKey, FontInstanceKey, GlyphInstance>(
         &self,
         instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>,
-        text: T
-    ) -> Result<GlyphStore<FontKey, FontInstanceKey, GlyphInstance>>
-    where
-        T: AsRef<str>,
-        FontKey: TFontKey,
-        FontInstanceKey: TFontInstanceKey,
-        GlyphInstance: TGlyphInstance
-    {
-        let text = text.as_ref();
-
-        let mut hasher = FnvHasher::default();
-        // instance.external_key().hash(&mut hasher);
-        // instance.external_instance_key().hash(&mut hasher);
-        text.hash(&mut hasher);
-
-        let generation_id = hasher.finish();
-        let mut cache = instance.shaped_text_v_cache.borrow_mut();
-
-        match cache.entry(generation_id) {
-            Entry::Occupied(e) => Ok(GlyphStore::clone(e.get())),
-            Entry::Vacant(e) => {
-                let mut glyphs = Vec::with_capacity(text.len());
-                let mut pen_position_64 = 0;
-                let font_size_metrics = self.get_global_size_metrics(instance)?;
-
-                for c in text.chars() {
-                    let GlyphDimensions {
-                        glyph_index,
-                        vert_advance_64,
-                        ..
-                    } = self.get_glyph_dimensions(instance, c)?;
-
-                    glyphs.push(GlyphInstance::new(glyph_index, 0, pen_position_64));
-                    pen_position_64 += vert_advance_64;
-                }
+        glyph_index: u32
+    ) -> Result<GlyphBitmap> {
+        let face = self.faces.get(&instance.font_id()).ok_or(FontError::FaceNotFound)?;
+        let options = instance.load_policy().get(GlyphLoadPurpose::RasterGray);
+        let target = match options.target {
+            RenderTarget::LCD | RenderTarget::LCDVertical => RenderTarget::Normal,
+            target => target
+        };
 
-                Ok(GlyphStore::clone(
-                    e.insert(GlyphStore {
-                        generation_id: hasher.finish(),
-                        font_key: instance.external_key(),
-                        font_instance_key: instance.external_instance_key(),
-                        width_64: font_size_metrics.max_advance_64,
-                        height_64: pen_position_64,
-                        glyphs: GlyphsArray(Rc::from(glyphs.into_boxed_slice()))
-                    })
-                ))
-            }
+        set_instance_size(face, instance)?;
+        face.set_transform(instance.transform(), 0);
+        face.load_glyph(glyph_index, options.load_flags() - LoadFlag::RENDER)?;
+        face.render_glyph(target.render_mode())?;
+        let mut bitmap = face.get_glyph_bitmap(glyph_index)?;
+
+        if let ImagePixelFormat::BGRA(8) = bitmap.format {
+            bitmap.pixels = bitmap.pixels.chunks(4).map(|pixel| pixel[3]).collect();
+            bitmap.format = ImagePixelFormat::Gray(8);
+            bitmap.alpha_mode = AlphaMode::Straight;
         }
+        Ok(bitmap)
+    }
+
+    fn get_global_size_metrics<FontKey, FontInstanceKey, GlyphInstance>(
+        &self,
+        instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>
+    ) -> Result<FontSizeMetrics> {
+        let font_id = instance.font_id();
+
+        let face = self.faces.get(&font_id).ok_or(FontError::FaceNotFound)?;
+
+        set_instance_size(face, instance)?;
+        let face_metrics = face.get_size_metrics()?;
+
+        Ok(FontSizeMetrics {
+            nominal_width: face_metrics.x_ppem,
+            nominal_height: face_metrics.y_ppem,
+            ascender_64: checked_pos(face_metrics.ascender)?,
+            descender_64: checked_pos(face_metrics.descender)?,
+            height_64: checked_pos(face_metrics.height)?,
+            max_advance_64: checked_pos(face_metrics.max_advance)?
+        })
     }
 }
 
+fn char_size(size: u32) -> Result<usize> {
+    Ok(size.checked_mul(64).ok_or(ShapingError::Overflow)? as usize)
+}
+
+fn set_instance_size<FontKey, FontInstanceKey, GlyphInstance>(
+    face: &FontFace,
+    instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>
+) -> Result<()> {
+    let request = match instance.unit() {
+        FontSizeUnit::Points => SizeRequest::CharSize {
+            size_64: char_size(instance.size())?,
+            dpi: instance.dpi().0
+        },
+        FontSizeUnit::Pixels => SizeRequest::PixelSize(instance.size())
+    };
+    face.activate_size(request)
+}
+
+fn checked_pos<T>(value: T) -> Result<i32>
+where
+    T: Into<i64>
+{
+    Ok(Fixed26_6::from_raw_i64(value.into())
+        .ok_or(ShapingError::Overflow)?
+        .raw())
+}
+
 #[cfg(test)]
 #[allow(unused_imports)]
 mod tests {
     extern crate rsx_resource_updates;
 
     use super::*;
+    use backend::advance;
+    use export;
     use rsx_resource_updates::types::{
         DefaultFontInstanceKey as FontInstanceKey,
         DefaultFontKey as FontKey,
         DefaultGlyphInstance as GlyphInstance
     };
     use rsx_shared::traits::TGlyphStore;
+    use glyph_load::{GlyphLoadOptions, GlyphLoadPolicy, LoadFlag, RenderTarget};
+    use gsub;
+    use placeholder::{self, PlaceholderMetrics};
+    use sfnt;
+    use types::{AlphaMode, Dpi, FontFeature, ImagePixelFormat, Pt, ShapingStrategy};
 
     #[test]
     fn test_fonts_context_1() {
@@ -291,6 +592,143 @@ mod tests {
         assert_eq!(font_context.get_face_index(font_id_2).unwrap(), 0);
     }
 
+    #[test]
+    fn test_fonts_fixed_overflow() {
+        assert_eq!(
+            advance(Fixed26_6::ZERO, 64).unwrap(),
+            Fixed26_6::from_int(1).unwrap()
+        );
+        assert!(advance(Fixed26_6::MAX, 64).is_err());
+        assert!(checked_pos(i64::from(i32::max_value()) + 1).is_err());
+        assert!(char_size(u32::max_value()).is_err());
+    }
+
+    #[test]
+    fn test_fonts_vertical_bearings() {
+        let mut font_context = FontContext::new().unwrap();
+
+        let font_id = FontId::new("FreeSans");
+        let font_bytes = Rc::new(include_bytes!("../../rsx-resource-group/tests/fixtures/FreeSans.ttf").to_vec());
+        assert!(font_context.add_face(font_id, &font_bytes, 0).is_ok());
+        assert!(!font_context.faces[&font_id].has_vertical_metrics());
+
+        // Without vertical metrics, glyphs are centered in their column.
+        let instance = FontInstance::<FontKey, FontInstanceKey, GlyphInstance>::new(font_id, Pt(32), Dpi(72), FontKey(0), FontInstanceKey(0));
+        let bearings = font_context.get_glyph_vertical_bearings(&instance, 'H').unwrap();
+        let dimensions = font_context.get_glyph_dimensions(&instance, 'H').unwrap();
+        assert!(bearings.bearing_x_64 < 0 && bearings.bearing_y_64 > 0);
+        assert!((bearings.bearing_x_64 * 2 + dimensions.width_64).abs() <= 64);
+        assert!((bearings.bearing_y_64 * 2 + dimensions.height_64 - dimensions.vert_advance_64).abs() <= 64);
+
+        // VORG records are sorted by glyph, others use the default origin.
+        let vorg = [0, 1, 0, 0, 3, 0x70, 0, 2, 0, 5, 3, 0x20, 0, 9, 0xFF, 0xF0];
+        assert_eq!(sfnt::vertical_origin_y(&vorg, 5), Some(800));
+        assert_eq!(sfnt::vertical_origin_y(&vorg, 9), Some(-16));
+        assert_eq!(sfnt::vertical_origin_y(&vorg, 7), Some(880));
+        assert_eq!(sfnt::vertical_origin_y(&vorg[..4], 7), None);
+    }
+
+    #[test]
+    fn test_fonts_glyph_bitmap() {
+        let mut font_context = FontContext::new().unwrap();
+
+        let font_id = FontId::new("FreeSans");
+        let font_bytes = Rc::new(include_bytes!("../../rsx-resource-group/tests/fixtures/FreeSans.ttf").to_vec());
+        assert!(font_context.add_face(font_id, &font_bytes, 0).is_ok());
+
+        let instance = FontInstance::<FontKey, FontInstanceKey, GlyphInstance>::new(font_id, Pt(32), Dpi(72), FontKey(0), FontInstanceKey(0));
+        let bitmap = font_context.get_glyph_bitmap(&instance, 'A').unwrap();
+        assert_eq!(bitmap.format, ImagePixelFormat::Gray(8));
+        assert_eq!(bitmap.alpha_mode, AlphaMode::Straight);
+        assert!(bitmap.size.0 > 0 && bitmap.size.1 > 0);
+        assert_eq!(
+            bitmap.pixels.len(),
+            (bitmap.size.0 * bitmap.size.1) as usize
+        );
+        assert!(bitmap.pixels.iter().any(|&p| p == 255));
+
+        let space = font_context.get_glyph_bitmap(&instance, ' ').unwrap();
+        assert!(space.pixels.is_empty());
+    }
+
+    #[test]
+    fn test_fonts_glyph_subpixel_bitmap() {
+        let mut font_context = FontContext::new().unwrap();
+        assert_eq!(font_context.lcd_filter(), LcdFilter::None);
+        if font_context.set_lcd_filter(LcdFilter::Default).is_ok() {
+            assert_eq!(font_context.lcd_filter(), LcdFilter::Default);
+        }
+
+        let font_id = FontId::new("FreeSans");
+        let font_bytes = Rc::new(include_bytes!("../../rsx-resource-group/tests/fixtures/FreeSans.ttf").to_vec());
+        assert!(font_context.add_face(font_id, &font_bytes, 0).is_ok());
+
+        let instance = FontInstance::<FontKey, FontInstanceKey, GlyphInstance>::new(font_id, Pt(32), Dpi(72), FontKey(0), FontInstanceKey(0));
+        let gray = font_context.get_glyph_bitmap(&instance, 'A').unwrap();
+
+        let rgb = font_context
+            .get_glyph_subpixel_bitmap(&instance, 'A', SubpixelLayout::RGB)
+            .unwrap();
+        assert_eq!(rgb.format, ImagePixelFormat::RGB(8));
+        assert_eq!(
+            rgb.pixels.len(),
+            (rgb.size.0 * rgb.size.1 * 3) as usize
+        );
+        assert!((rgb.size.0 as i32 - gray.size.0 as i32).abs() <= 2);
+
+        let bgr = font_context
+            .get_glyph_subpixel_bitmap(&instance, 'A', SubpixelLayout::BGR)
+            .unwrap();
+        assert_eq!(bgr.size, rgb.size);
+        assert_eq!(bgr.pixels[0], rgb.pixels[2]);
+        assert_eq!(bgr.pixels[2], rgb.pixels[0]);
+
+        let mut policy = instance.load_policy();
+        policy.raster_lcd.target = RenderTarget::LCDVertical;
+        instance.set_load_policy(policy);
+        let vertical = font_context
+            .get_glyph_subpixel_bitmap(&instance, 'A', SubpixelLayout::Vertical)
+            .unwrap();
+        assert_eq!(vertical.format, ImagePixelFormat::RGB(8));
+        assert!((vertical.size.1 as i32 - gray.size.1 as i32).abs() <= 2);
+
+        let none = font_context
+            .get_glyph_subpixel_bitmap(&instance, 'A', SubpixelLayout::None)
+            .unwrap();
+        assert_eq!(none, gray);
+    }
+
+    #[test]
+    fn test_fonts_glyph_load_policy() {
+        let mut font_context = FontContext::new().unwrap();
+
+        let font_id = FontId::new("FreeSans");
+        let font_bytes = Rc::new(include_bytes!("../../rsx-resource-group/tests/fixtures/FreeSans.ttf").to_vec());
+        assert!(font_context.add_face(font_id, &font_bytes, 0).is_ok());
+
+        let instance = FontInstance::<FontKey, FontInstanceKey, GlyphInstance>::new(font_id, Pt(12), Dpi(72), FontKey(0), FontInstanceKey(0));
+        assert_eq!(instance.load_policy(), GlyphLoadPolicy::default());
+        assert_eq!(
+            RenderTarget::LCD.load_flags().bits(),
+            3 << 16
+        );
+
+        let linear = font_context.get_glyph_linear_advance(&instance, 'a').unwrap();
+        assert_eq!(linear.glyph_index, 68);
+        assert_eq!((linear.hori_advance_65536 + 512) >> 10, 427);
+
+        assert!(font_context.shape_text_h(&instance, "a").is_ok());
+        assert_eq!(instance.shaped_text_entries(), 1);
+
+        let hinted = GlyphLoadOptions::new(LoadFlag::NO_BITMAP, RenderTarget::Light);
+        instance.set_load_policy(instance.load_policy().with(GlyphLoadPurpose::Measure, hinted));
+        assert_eq!(instance.load_policy().get(GlyphLoadPurpose::Measure), hinted);
+        assert_eq!(instance.shaped_text_entries(), 0);
+
+        let dimensions = font_context.get_glyph_dimensions(&instance, 'a').unwrap();
+        assert_eq!(dimensions.hori_advance_64 % 64, 0);
+    }
+
     #[test]
     fn test_fonts_raw_simple() {
         let mut font_context = FontContext::new().unwrap();
@@ -312,7 +750,7 @@ mod tests {
         let font_bytes = Rc::new(include_bytes!("../../rsx-resource-group/tests/fixtures/FreeSans.ttf").to_vec());
         assert!(font_context.add_face(font_id, &font_bytes, 0).is_ok());
 
-        let instance = FontInstance::<_, _, ()>::new(font_id, 12, 72, (), ());
+        let instance = FontInstance::<_, _, ()>::new(font_id, Pt(12), Dpi(72), (), ());
         assert_eq!(font_context.get_glyph_index(&instance, 'a').unwrap(), 68);
 
         assert_eq!(
@@ -322,7 +760,10 @@ mod tests {
                 width_64: 379,
                 height_64: 432,
                 hori_advance_64: 427,
-                vert_advance_64: 768
+                vert_advance_64: 768,
+                hori_advance_y_64: 0,
+                vert_advance_x_64: 0,
+                color: false
             }
         );
 
@@ -347,7 +788,7 @@ mod tests {
         let font_bytes = Rc::new(include_bytes!("../../rsx-resource-group/tests/fixtures/FreeSans.ttf").to_vec());
         assert!(font_context.add_face(font_id, &font_bytes, 0).is_ok());
 
-        let instance = FontInstance::<_, _, ()>::new(font_id, 16, 72, (), ());
+        let instance = FontInstance::<_, _, ()>::new(font_id, Pt(16), Dpi(72), (), ());
         assert_eq!(font_context.get_glyph_index(&instance, 'a').unwrap(), 68);
 
         assert_eq!(
@@ -357,7 +798,10 @@ mod tests {
                 width_64: 505,
                 height_64: 576,
                 hori_advance_64: 569,
-                vert_advance_64: 1024
+                vert_advance_64: 1024,
+                hori_advance_y_64: 0,
+                vert_advance_x_64: 0,
+                color: false
             }
         );
 
@@ -374,6 +818,34 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_fonts_interleaved_sizes() {
+        let mut font_context = FontContext::new().unwrap();
+
+        let font_id = FontId::new("FreeSans");
+        let font_bytes = Rc::new(include_bytes!("../../rsx-resource-group/tests/fixtures/FreeSans.ttf").to_vec());
+        assert!(font_context.add_face(font_id, &font_bytes, 0).is_ok());
+
+        let small = FontInstance::<_, _, ()>::new(font_id, Pt(12), Dpi(72), (), ());
+        let large = FontInstance::<_, _, ()>::new(font_id, Pt(16), Dpi(72), (), ());
+
+        for _ in 0..2 {
+            assert_eq!(font_context.get_glyph_dimensions(&small, 'a').unwrap().hori_advance_64, 427);
+            assert_eq!(font_context.get_glyph_dimensions(&large, 'a').unwrap().hori_advance_64, 569);
+            assert_eq!(font_context.get_global_size_metrics(&small).unwrap().height_64, 1088);
+            assert_eq!(font_context.get_glyph_dimensions(&small, 'a').unwrap().width_64, 379);
+            assert_eq!(font_context.get_global_size_metrics(&large).unwrap().height_64, 1408);
+        }
+
+        let small_bitmap = font_context.get_glyph_bitmap(&small, 'a').unwrap();
+        let large_bitmap = font_context.get_glyph_bitmap(&large, 'a').unwrap();
+        assert_eq!(font_context.get_glyph_bitmap(&small, 'a').unwrap(), small_bitmap);
+        assert!(large_bitmap.size.1 > small_bitmap.size.1);
+
+        let face = font_context.faces.get(&font_id).unwrap();
+        assert_eq!(face.size_count(), 2);
+    }
+
     #[test]
     fn test_fonts_simple_3a() {
         let mut font_context = FontContext::new().unwrap();
@@ -382,7 +854,7 @@ mod tests {
         let font_bytes = Rc::new(include_bytes!("../../rsx-resource-group/tests/fixtures/FreeSans.ttf").to_vec());
         assert!(font_context.add_face(font_id, &font_bytes, 0).is_ok());
 
-        let instance = FontInstance::new(font_id, 16, 72, FontKey(0), FontInstanceKey(0));
+        let instance = FontInstance::new(font_id, Pt(16), Dpi(72), FontKey(0), FontInstanceKey(0));
         assert_eq!(font_context.get_glyph_index(&instance, 'a').unwrap(), 68);
 
         let shaped_text = font_context.shape_text_h(&instance, "Hello world").unwrap();
@@ -465,7 +937,7 @@ mod tests {
         let font_bytes = Rc::new(include_bytes!("../../rsx-resource-group/tests/fixtures/FreeSans.ttf").to_vec());
         assert!(font_context.add_face(font_id, &font_bytes, 0).is_ok());
 
-        let instance = FontInstance::new(font_id, 16, 72, FontKey(0), FontInstanceKey(0));
+        let instance = FontInstance::new(font_id, Pt(16), Dpi(72), FontKey(0), FontInstanceKey(0));
         assert_eq!(font_context.get_glyph_index(&instance, 'a').unwrap(), 68);
         let shaped_text = font_context.shape_text_v(&instance, "Hello world").unwrap();
         assert_eq!(shaped_text.width_f(), 24.0);
@@ -538,4 +1010,222 @@ mod tests {
             )
         );
     }
+
+    #[test]
+    fn test_fonts_feature_substitutions() {
+        let mut font_context = FontContext::new().unwrap();
+
+        let font_id = FontId::new("FiraMono");
+        let font_bytes = Rc::new(include_bytes!("../../rsx-resource-group/tests/fixtures/FiraMono-Regular.ttf").to_vec());
+        assert!(font_context.add_face(font_id, &font_bytes, 0).is_ok());
+
+        let table = sfnt::face_table(&font_bytes, 0, b"GSUB").unwrap();
+        assert_eq!(gsub::single_substitutions(table, &[FontFeature::enabled(b"zero")]).get(&898), Some(&918));
+        assert_eq!(gsub::single_substitutions(table, &[FontFeature::disabled(b"zero")]).get(&898), None);
+        assert_eq!(
+            gsub::single_substitutions(table, &[FontFeature::enabled(b"zero"), FontFeature::disabled(b"zero")]).get(&898),
+            None
+        );
+
+        let instance = FontInstance::new(font_id, Pt(16), Dpi(72), FontKey(0), FontInstanceKey(0));
+        instance.set_shaping_strategy(ShapingStrategy::Simple);
+        assert_eq!(font_context.get_glyph_index(&instance, '0').unwrap(), 898);
+
+        let plain = font_context.shape_text_h(&instance, "10").unwrap();
+        assert_eq!(plain.glyphs.0[1].glyph_index, 898);
+
+        instance.set_features(vec![FontFeature::enabled(b"zero")]);
+        let slashed = font_context.shape_text_h(&instance, "10").unwrap();
+        assert_eq!(slashed.glyphs.0[0].glyph_index, plain.glyphs.0[0].glyph_index);
+        assert_eq!(slashed.glyphs.0[1].glyph_index, 918);
+        assert_eq!(slashed.width_64, plain.width_64);
+        assert_eq!(instance.shaped_text_entries(), 2);
+
+        instance.set_features(vec![]);
+        assert_eq!(font_context.shape_text_h(&instance, "10").unwrap().glyphs.0[1].glyph_index, 898);
+        assert_eq!(instance.shaped_text_entries(), 2);
+    }
+
+    #[test]
+    fn test_fonts_feature_substitutions_overlapping_ranges() {
+        // A single substitution whose coverage lists the whole glyph range many times.
+        let ranges = 1000;
+        let mut table = vec![0, 1, 0, 0, 0, 0, 0, 10, 0, 24];
+        table.extend_from_slice(&[0, 1, b't', b'e', b's', b't', 0, 8, 0, 0, 0, 1, 0, 0]);
+        table.extend_from_slice(&[0, 1, 0, 4, 0, 1, 0, 0, 0, 1, 0, 8, 0, 1, 0, 6, 0, 1]);
+        table.extend_from_slice(&[0, 2, (ranges >> 8) as u8, ranges as u8]);
+        for _ in 0..ranges {
+            table.extend_from_slice(&[0, 0, 0xff, 0xff, 0, 0]);
+        }
+
+        let substitutions = gsub::single_substitutions(&table, &[FontFeature::enabled(b"test")]);
+        assert_eq!(substitutions.len(), 0x10000);
+        assert_eq!(substitutions.get(&0), Some(&1));
+        assert_eq!(substitutions.get(&0xffff), Some(&0));
+    }
+
+    #[test]
+    fn test_fonts_subset_glyphs() {
+        let mut font_context = FontContext::new().unwrap();
+
+        let font_id = FontId::new("FreeSans");
+        let font_bytes = Rc::new(include_bytes!("../../rsx-resource-group/tests/fixtures/FreeSans.ttf").to_vec());
+        assert!(font_context.add_face(font_id, &font_bytes, 0).is_ok());
+
+        let instance = FontInstance::new(font_id, Pt(16), Dpi(72), FontKey(0), FontInstanceKey(0));
+        let a = font_context.get_glyph_index(&instance, 'a').unwrap();
+        let e_acute = font_context.get_glyph_index(&instance, 'é').unwrap();
+        let glyphs = [a, e_acute].iter().cloned().collect();
+        let subset = Rc::new(export::subset_glyphs(&font_bytes, &glyphs).unwrap());
+        assert!(subset.len() < font_bytes.len() / 2);
+
+        let subset_id = FontId::new("FreeSans subset");
+        assert!(font_context.add_face(subset_id, &subset, 0).is_ok());
+        let subset_instance = FontInstance::new(subset_id, Pt(16), Dpi(72), FontKey(1), FontInstanceKey(1));
+
+        // Kept glyphs, including the components of composite ones, keep their
+        // indices and outlines. Dropped ones are still mapped but empty.
+        for &c in &['a', 'é'] {
+            assert_eq!(
+                font_context.get_glyph_dimensions(&subset_instance, c).unwrap(),
+                font_context.get_glyph_dimensions(&instance, c).unwrap()
+            );
+        }
+        let b = font_context.get_glyph_dimensions(&subset_instance, 'b').unwrap();
+        assert_eq!(b.glyph_index, font_context.get_glyph_index(&instance, 'b').unwrap());
+        assert_eq!(b.width_64, 0);
+    }
+
+    #[test]
+    fn test_fonts_color_bitmaps() {
+        let mut font_context = FontContext::new().unwrap();
+
+        let font_id = FontId::new("FreeSans");
+        let font_bytes = Rc::new(include_bytes!("../../rsx-resource-group/tests/fixtures/FreeSans.ttf").to_vec());
+        assert!(!sfnt::has_color_bitmaps(&font_bytes, 0));
+        assert!(font_context.add_face(font_id, &font_bytes, 0).is_ok());
+
+        let instance = FontInstance::new(font_id, Pt(16), Dpi(72), FontKey(0), FontInstanceKey(0));
+        assert!(!font_context.get_glyph_dimensions(&instance, 'a').unwrap().color);
+        assert!(!font_context.get_glyph_bitmap(&instance, 'a').unwrap().is_color());
+
+        // CBDT strikes need their CBLC index, sbix images don't.
+        let retagged = |tags: &[&[u8]]| {
+            let mut bytes = font_bytes.to_vec();
+            let num_tables = sfnt::read_u16(&bytes, 4).unwrap() as usize;
+            for (index, tag) in tags.iter().enumerate() {
+                let record = 12 + (num_tables - 1 - index) * 16;
+                bytes[record..record + 4].copy_from_slice(tag);
+            }
+            bytes
+        };
+        assert!(!sfnt::has_color_bitmaps(&retagged(&[b"CBDT"]), 0));
+        assert!(sfnt::has_color_bitmaps(&retagged(&[b"CBDT", b"CBLC"]), 0));
+        assert!(sfnt::has_color_bitmaps(&retagged(&[b"sbix"]), 0));
+    }
+
+    // The tables of the placeholder face, by tag.
+    fn placeholder_tables() -> Vec<([u8; 4], Vec<u8>)> {
+        let bytes = placeholder::synthesize(&PlaceholderMetrics::default());
+        let num_tables = sfnt::read_u16(&bytes, 4).unwrap() as usize;
+        let mut tables = Vec::new();
+        for index in 0..num_tables {
+            let record = 12 + index * 16;
+            let mut tag = [0; 4];
+            tag.copy_from_slice(&bytes[record..record + 4]);
+            let offset = sfnt::read_u32(&bytes, record + 8).unwrap() as usize;
+            let len = sfnt::read_u32(&bytes, record + 12).unwrap() as usize;
+            tables.push((tag, bytes[offset..offset + len].to_vec()));
+        }
+        tables
+    }
+
+    #[test]
+    fn test_fonts_script_metrics_clamped() {
+        // Patches the scale and shift fields of the OS/2 table at 16 units per em.
+        let patched = |superscript_scale: [u8; 2]| {
+            let mut tables = placeholder_tables();
+            for &mut (ref tag, ref mut table) in &mut tables {
+                match tag {
+                    b"head" => table[18..20].copy_from_slice(&[0, 16]),
+                    b"OS/2" => {
+                        table[12..14].copy_from_slice(&[0x7F, 0xFF]);
+                        table[16..18].copy_from_slice(&[0x80, 0x00]);
+                        table[20..22].copy_from_slice(&superscript_scale);
+                        table[24..26].copy_from_slice(&[0x80, 0x00]);
+                    }
+                    _ => {}
+                }
+            }
+            placeholder::sfnt(&mut tables)
+        };
+
+        // Values past 16 bits saturate rather than wrap.
+        assert_eq!(sfnt::script_metrics(&patched([0x80, 0x00]), 0), None);
+        assert_eq!(
+            sfnt::script_metrics(&patched([0, 1]), 0),
+            Some(((i16::min_value(), 62), (i16::max_value(), u16::max_value())))
+        );
+    }
+
+    // The placeholder face with its outlines swapped for a single strike of
+    // `ppem` pixels, in which the box glyph is an opaque red square.
+    fn color_bitmap_face(ppem: u8) -> Vec<u8> {
+        let mut tables: Vec<_> = placeholder_tables()
+            .into_iter()
+            .filter(|&(ref tag, _)| tag != b"glyf" && tag != b"loca")
+            .collect();
+
+        // Image format 1: small metrics, then the rows of BGRA pixels.
+        let mut cbdt = vec![0, 3, 0, 0];
+        cbdt.extend_from_slice(&[ppem, ppem, 0, ppem * 4 / 5, ppem]);
+        for _ in 0..u32::from(ppem) * u32::from(ppem) {
+            cbdt.extend_from_slice(&[0, 0, 255, 255]);
+        }
+        let image_len = cbdt.len() as u32 - 4;
+
+        // A 32 bit deep strike, with an index subtable of format 1 for the box.
+        let mut cblc = vec![0, 3, 0, 0, 0, 0, 0, 1];
+        cblc.extend_from_slice(&[0, 0, 0, 56, 0, 0, 0, 24, 0, 0, 0, 1, 0, 0, 0, 0]);
+        let line_metrics = [ppem * 4 / 5, (ppem / 5).wrapping_neg(), ppem, 1, 0, 0, 0, 0, 0, 0, 0, 0];
+        cblc.extend_from_slice(&line_metrics);
+        cblc.extend_from_slice(&line_metrics);
+        cblc.extend_from_slice(&[0, 1, 0, 1, ppem, ppem, 32, 1]);
+        cblc.extend_from_slice(&[0, 1, 0, 1, 0, 0, 0, 8]);
+        cblc.extend_from_slice(&[0, 1, 0, 1, 0, 0, 0, 4]);
+        cblc.extend_from_slice(&[0, 0, 0, 0]);
+        cblc.extend_from_slice(&[(image_len >> 24) as u8, (image_len >> 16) as u8, (image_len >> 8) as u8, image_len as u8]);
+
+        tables.insert(0, (*b"CBLC", cblc));
+        tables.insert(0, (*b"CBDT", cbdt));
+        placeholder::sfnt(&mut tables)
+    }
+
+    #[test]
+    fn test_fonts_color_bitmap_strikes() {
+        let mut font_context = FontContext::new().unwrap();
+
+        let font_id = FontId::new("Emoji");
+        let font_bytes = Rc::new(color_bitmap_face(32));
+        assert!(sfnt::has_color_bitmaps(&font_bytes, 0));
+        assert!(font_context.add_face(font_id, &font_bytes, 0).is_ok());
+
+        // The strike is scaled down to smaller sizes, and up to larger ones.
+        for &(size, scale) in &[(16, 0.5), (32, 1.0), (64, 2.0)] {
+            let instance = FontInstance::new(font_id, Pt(size), Dpi(72), FontKey(0), FontInstanceKey(0));
+            let dimensions = font_context.get_glyph_dimensions(&instance, 'A').unwrap();
+            assert_eq!(dimensions.glyph_index, 1);
+            assert!(dimensions.color);
+            assert_eq!(dimensions.hori_advance_64, size as i32 * 64);
+            assert_eq!(dimensions.width_64, size as i32 * 64);
+            assert_eq!(font_context.faces[&font_id].get_strike_scale(), scale);
+            assert_eq!(font_context.get_global_size_metrics(&instance).unwrap().nominal_height, size as u16);
+
+            let bitmap = font_context.get_glyph_bitmap(&instance, 'A').unwrap();
+            assert!(bitmap.is_color());
+            assert_eq!(bitmap.size, (size, size));
+            assert_eq!(bitmap.pixels.len(), (size * size * 4) as usize);
+            assert!(bitmap.pixels.chunks(4).all(|pixel| pixel == &bitmap.pixels[..4]));
+        }
+    }
 }
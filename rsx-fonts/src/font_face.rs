@@ -9,58 +9,82 @@ CONDITIONS OF ANY KIND, either express or implied. See the License for the
 specific language governing permissions and limitations under the License.
 */
 
+use std::cell::{Cell, RefCell};
 use std::ffi::CStr;
-use std::os::raw::c_uint;
 use std::ptr;
 use std::rc::Rc;
+use std::slice;
 
+use fnv::FnvHashMap;
 use freetype::freetype::{
-    self,
+    FT_Activate_Size,
+    FT_Done_Size,
     FT_F26Dot6,
     FT_Face,
+    FT_Fixed,
     FT_Get_Char_Index,
+    FT_Get_First_Char,
+    FT_Get_Next_Char,
+    FT_Get_Kerning,
+    FT_Glyph_Format,
     FT_Glyph_Metrics,
+    FT_Int,
     FT_Int32,
+    FT_Kerning_Mode,
     FT_Library,
     FT_Load_Glyph,
     FT_Long,
+    FT_Matrix,
     FT_New_Memory_Face,
+    FT_New_Size,
+    FT_Pixel_Mode,
+    FT_Pos,
+    FT_Render_Glyph,
+    FT_Render_Mode,
+    FT_Select_Size,
     FT_Set_Char_Size,
+    FT_Set_Pixel_Sizes,
+    FT_Set_Transform,
+    FT_Size,
     FT_Size_Metrics,
     FT_UInt,
-    FT_ULong
+    FT_ULong,
+    FT_UShort,
+    FT_Vector
 };
 
 use error::{FontError, Result};
+use glyph_load::LoadFlag;
+use palette::ColorTables;
+use sfnt;
+use types::{AlphaMode, GlyphBitmap, GlyphTransform, ImagePixelFormat};
 
-bitflags! {
-    pub struct LoadFlag: c_uint {
-        const DEFAULT = freetype::FT_LOAD_DEFAULT;
-        const NO_SCALE = freetype::FT_LOAD_NO_SCALE;
-        const NO_HINTING = freetype::FT_LOAD_NO_HINTING;
-        const RENDER = freetype::FT_LOAD_RENDER;
-        const NO_BITMAP = freetype::FT_LOAD_NO_BITMAP;
-        const VERTICAL_LAYOUT = freetype::FT_LOAD_VERTICAL_LAYOUT;
-        const FORCE_AUTOHINT = freetype::FT_LOAD_FORCE_AUTOHINT;
-        const CROP_BITMAP = freetype::FT_LOAD_CROP_BITMAP;
-        const PEDANTIC = freetype::FT_LOAD_PEDANTIC;
-        const IGNORE_GLOBAL_ADVANCE_WIDTH = freetype::FT_LOAD_IGNORE_GLOBAL_ADVANCE_WIDTH;
-        const NO_RECURSE = freetype::FT_LOAD_NO_RECURSE;
-        const IGNORE_TRANSFORM = freetype::FT_LOAD_IGNORE_TRANSFORM;
-        const MONOCHROME = freetype::FT_LOAD_MONOCHROME;
-        const LINEAR_DESIGN = freetype::FT_LOAD_LINEAR_DESIGN;
-        const NO_AUTOHINT = freetype::FT_LOAD_NO_AUTOHINT;
-        const COLOR = freetype::FT_LOAD_COLOR;
-        const COMPUTE_METRICS = freetype::FT_LOAD_COMPUTE_METRICS;
-        const SBITS_ONLY = freetype::FT_LOAD_SBITS_ONLY;
-    }
+// Equivalent of the `FT_FACE_FLAG_SCALABLE` macro, which isn't exported.
+const FACE_FLAG_SCALABLE: FT_Long = 1;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum SizeRequest {
+    CharSize { size_64: usize, dpi: u32 },
+    PixelSize(u32)
+}
+
+// What a glyph load left in the face's glyph slot.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct LoadedGlyph {
+    pub glyph_index: u32,
+    pub color_bitmap: bool
 }
 
 #[derive(Debug, PartialEq)]
 pub struct FontFace {
     raw: FT_Face,
     bytes: Rc<Vec<u8>>,
-    face_index: usize
+    face_index: usize,
+    color_tables: ColorTables,
+    color_bitmaps: bool,
+    // Along with how much the strike used for the size is scaled, see `get_strike`.
+    sizes: RefCell<FnvHashMap<SizeRequest, (FT_Size, Option<f64>)>>,
+    strike_scale: Cell<Option<f64>>
 }
 
 impl FontFace {
@@ -81,7 +105,11 @@ impl FontFace {
             Ok(FontFace {
                 raw,
                 bytes: Rc::clone(bytes),
-                face_index
+                face_index,
+                color_tables: ColorTables::from_face(bytes, face_index),
+                color_bitmaps: sfnt::has_color_bitmaps(bytes, face_index),
+                sizes: RefCell::new(FnvHashMap::default()),
+                strike_scale: Cell::new(None)
             })
         }
     }
@@ -94,6 +122,22 @@ impl FontFace {
         self.face_index
     }
 
+    // FreeType doesn't composite COLR layers itself, so they're read upfront.
+    pub fn get_color_tables(&self) -> &ColorTables {
+        &self.color_tables
+    }
+
+    // Emoji faces often only have color bitmaps, so glyphs of these faces are
+    // always loaded from their bitmaps, in color.
+    pub fn has_color_bitmaps(&self) -> bool {
+        self.color_bitmaps
+    }
+
+    // Whether the glyph has COLR layers, or was loaded as a color bitmap.
+    pub fn is_color_glyph(&self, loaded: LoadedGlyph) -> bool {
+        loaded.color_bitmap || !self.color_tables.layers(loaded.glyph_index).is_empty()
+    }
+
     pub fn get_family_name(&self) -> Result<&str> {
         let face = unsafe { self.raw.as_ref() }.ok_or(FontError::FaceNotLoaded)?;
         let family_name_ptr = unsafe { face.family_name.as_ref() }.ok_or(FontError::FaceFamilyNameMissing)?;
@@ -101,11 +145,118 @@ impl FontFace {
         Ok(family_name_str)
     }
 
+    // Faces without scalable outlines, such as bitmap only faces, have no design units.
+    pub fn get_units_per_em(&self) -> Result<u32> {
+        let face = unsafe { self.raw.as_ref() }.ok_or(FontError::FaceNotLoaded)?;
+        match face.units_per_EM {
+            0 => Err(FontError::FaceMetricsMissing),
+            units_per_em => Ok(u32::from(units_per_em))
+        }
+    }
+
     pub fn get_char_index(&self, c: char) -> u32 {
         unsafe { FT_Get_Char_Index(self.raw, c as FT_ULong) }
     }
 
-    pub fn set_char_size(&self, w: usize, h: usize, h_res: u32, v_res: u32) -> Result<()> {
+    // Iterates the face's character map, yielding code points in ascending order.
+    pub fn get_char_codes(&self) -> Vec<u32> {
+        let mut char_codes = Vec::new();
+        let mut glyph_index: FT_UInt = 0;
+        let mut char_code = unsafe { FT_Get_First_Char(self.raw, &mut glyph_index) };
+        while glyph_index != 0 {
+            char_codes.push(char_code as u32);
+            char_code = unsafe { FT_Get_Next_Char(self.raw, char_code, &mut glyph_index) };
+        }
+        char_codes
+    }
+
+    // Every requested size is kept in its own FT_Size, scaled once when it's
+    // created, so instances of different sizes sharing the face can be used
+    // in any order without metrics leaking from one into another. Sizes are
+    // freed along with the face.
+    pub fn activate_size(&self, request: SizeRequest) -> Result<()> {
+        let mut sizes = self.sizes.borrow_mut();
+        if let Some(&(size, strike_scale)) = sizes.get(&request) {
+            self.strike_scale.set(strike_scale);
+            return activate(size);
+        }
+
+        let mut size: FT_Size = ptr::null_mut();
+        let result = unsafe { FT_New_Size(self.raw, &mut size) };
+        if !result.succeeded() || size.is_null() {
+            Err(result)?
+        }
+        let strike = self.get_strike(request);
+        let scaled = activate(size).and_then(|_| match (strike, request) {
+            (Some((strike_index, _)), _) => self.select_size(strike_index),
+            (None, SizeRequest::CharSize { size_64, dpi }) => self.set_char_size(size_64, 0, dpi, 0),
+            (None, SizeRequest::PixelSize(pixels)) => self.set_pixel_sizes(0, pixels)
+        });
+        if let Err(err) = scaled {
+            unsafe { FT_Done_Size(size) };
+            Err(err)?
+        }
+
+        let strike_scale = strike.map(|(_, strike_scale)| strike_scale);
+        self.strike_scale.set(strike_scale);
+        sizes.insert(request, (size, strike_scale));
+        Ok(())
+    }
+
+    // How much metrics and bitmaps of the active size's strike are scaled to
+    // the size requested, 1 for scalable faces.
+    pub fn get_strike_scale(&self) -> f64 {
+        self.strike_scale.get().unwrap_or(1.0)
+    }
+
+    fn scale_pos(&self, pos: FT_Pos) -> FT_Pos {
+        match self.strike_scale.get() {
+            Some(scale) => (pos as f64 * scale).round() as FT_Pos,
+            None => pos
+        }
+    }
+
+    pub fn size_count(&self) -> usize {
+        self.sizes.borrow().len()
+    }
+
+    // Faces without outlines, e.g. CBDT emoji faces, can't be scaled. The
+    // smallest strike at least as large as requested is used instead, or the
+    // largest one, along with the scale from its size to the requested one.
+    // Glyphs are measured and rendered at its size, then scaled.
+    fn get_strike(&self, request: SizeRequest) -> Option<(usize, f64)> {
+        let face = unsafe { self.raw.as_ref() }?;
+        if face.face_flags & FACE_FLAG_SCALABLE != 0 || face.num_fixed_sizes <= 0 || face.available_sizes.is_null() {
+            return None;
+        }
+        let ppem_64 = match request {
+            SizeRequest::CharSize { size_64, dpi } => size_64 as i64 * i64::from(dpi) / 72,
+            SizeRequest::PixelSize(pixels) => i64::from(pixels) * 64
+        };
+        let strikes: Vec<i64> = unsafe { slice::from_raw_parts(face.available_sizes, face.num_fixed_sizes as usize) }
+            .iter()
+            .map(|strike| i64::from(strike.y_ppem))
+            .collect();
+        let index = (0..strikes.len())
+            .filter(|&index| strikes[index] >= ppem_64)
+            .min_by_key(|&index| strikes[index])
+            .or_else(|| (0..strikes.len()).max_by_key(|&index| strikes[index]))?;
+        match strikes[index] {
+            0 => None,
+            strike_ppem_64 => Some((index, ppem_64 as f64 / strike_ppem_64 as f64))
+        }
+    }
+
+    fn select_size(&self, strike_index: usize) -> Result<()> {
+        let result = unsafe { FT_Select_Size(self.raw, strike_index as FT_Int) };
+        if !result.succeeded() {
+            Err(result)?
+        } else {
+            Ok(())
+        }
+    }
+
+    fn set_char_size(&self, w: usize, h: usize, h_res: u32, v_res: u32) -> Result<()> {
         let result = unsafe { FT_Set_Char_Size(self.raw, w as FT_F26Dot6, h as FT_F26Dot6, h_res, v_res) };
         if !result.succeeded() {
             Err(result)?
@@ -114,8 +265,59 @@ impl FontFace {
         }
     }
 
-    pub fn load_glyph(&self, c: u32, flags: LoadFlag) -> Result<()> {
-        let result = unsafe { FT_Load_Glyph(self.raw, c as FT_UInt, flags.bits as FT_Int32) };
+    fn set_pixel_sizes(&self, w: u32, h: u32) -> Result<()> {
+        let result = unsafe { FT_Set_Pixel_Sizes(self.raw, w as FT_UInt, h as FT_UInt) };
+        if !result.succeeded() {
+            Err(result)?
+        } else {
+            Ok(())
+        }
+    }
+
+    // The transform is face state, so it's set before every load. The delta
+    // shifts outlines right by a fraction of a pixel, in 26.6 fixed point.
+    pub fn set_transform(&self, transform: GlyphTransform, x_delta_64: i32) {
+        let mut matrix = FT_Matrix {
+            xx: FT_Fixed::from(transform.xx),
+            xy: FT_Fixed::from(transform.xy),
+            yx: FT_Fixed::from(transform.yx),
+            yy: FT_Fixed::from(transform.yy)
+        };
+        let mut delta = FT_Vector {
+            x: FT_Pos::from(x_delta_64),
+            y: 0
+        };
+        unsafe { FT_Set_Transform(self.raw, &mut matrix, &mut delta) }
+    }
+
+    pub fn load_glyph(&self, c: u32, flags: LoadFlag) -> Result<LoadedGlyph> {
+        let flags = if self.color_bitmaps {
+            (flags - LoadFlag::NO_BITMAP) | LoadFlag::COLOR
+        } else {
+            flags
+        };
+        let result = unsafe { FT_Load_Glyph(self.raw, c as FT_UInt, flags.bits() as FT_Int32) };
+        if !result.succeeded() {
+            Err(result)?
+        }
+        let face = unsafe { self.raw.as_ref() }.ok_or(FontError::FaceNotLoaded)?;
+        let glyph_slot = unsafe { face.glyph.as_ref() }.ok_or(FontError::FaceGlyphMissing)?;
+        Ok(LoadedGlyph {
+            glyph_index: c,
+            color_bitmap: glyph_slot.format == FT_Glyph_Format::FT_GLYPH_FORMAT_BITMAP
+                && glyph_slot.bitmap.pixel_mode == FT_Pixel_Mode::FT_PIXEL_MODE_BGRA as u8
+        })
+    }
+
+    // Loads done with `LoadFlag::RENDER`, or of embedded bitmaps, are already
+    // rendered, so only outlines go through the rasterizer.
+    pub fn render_glyph(&self, mode: FT_Render_Mode) -> Result<()> {
+        let face = unsafe { self.raw.as_ref() }.ok_or(FontError::FaceNotLoaded)?;
+        let glyph_slot = unsafe { face.glyph.as_ref() }.ok_or(FontError::FaceGlyphMissing)?;
+        if glyph_slot.format == FT_Glyph_Format::FT_GLYPH_FORMAT_BITMAP {
+            return Ok(());
+        }
+        let result = unsafe { FT_Render_Glyph(face.glyph, mode) };
         if !result.succeeded() {
             Err(result)?
         } else {
@@ -123,15 +325,217 @@ impl FontFace {
         }
     }
 
+    // Grid fitted, in 26.6 units at the current size. Faces without a kern
+    // table have no adjustments.
+    pub fn get_kerning(&self, left_glyph: u32, right_glyph: u32) -> Result<FT_Pos> {
+        let mut kerning = FT_Vector { x: 0, y: 0 };
+        let result = unsafe {
+            FT_Get_Kerning(
+                self.raw,
+                left_glyph as FT_UInt,
+                right_glyph as FT_UInt,
+                FT_Kerning_Mode::FT_KERNING_DEFAULT as FT_UInt,
+                &mut kerning
+            )
+        };
+        if !result.succeeded() {
+            Err(result)?
+        } else {
+            Ok(self.scale_pos(kerning.x))
+        }
+    }
+
+    // Metrics of strikes are scaled to the requested size, see `get_strike_scale`.
     pub fn get_size_metrics(&self) -> Result<FT_Size_Metrics> {
         let face = unsafe { self.raw.as_ref() }.ok_or(FontError::FaceNotLoaded)?;
         let size = unsafe { face.size.as_ref() }.ok_or(FontError::FaceSizeMissing)?;
-        Ok(size.metrics)
+        let scale = self.get_strike_scale();
+        Ok(FT_Size_Metrics {
+            x_ppem: (f64::from(size.metrics.x_ppem) * scale).round() as FT_UShort,
+            y_ppem: (f64::from(size.metrics.y_ppem) * scale).round() as FT_UShort,
+            ascender: self.scale_pos(size.metrics.ascender),
+            descender: self.scale_pos(size.metrics.descender),
+            height: self.scale_pos(size.metrics.height),
+            max_advance: self.scale_pos(size.metrics.max_advance),
+            ..size.metrics
+        })
     }
 
     pub fn get_glyph_metrics(&self) -> Result<FT_Glyph_Metrics> {
         let face = unsafe { self.raw.as_ref() }.ok_or(FontError::FaceNotLoaded)?;
         let glyph_slot = unsafe { face.glyph.as_ref() }.ok_or(FontError::FaceGlyphMissing)?;
-        Ok(glyph_slot.metrics)
+        let metrics = &glyph_slot.metrics;
+        Ok(FT_Glyph_Metrics {
+            width: self.scale_pos(metrics.width),
+            height: self.scale_pos(metrics.height),
+            horiBearingX: self.scale_pos(metrics.horiBearingX),
+            horiBearingY: self.scale_pos(metrics.horiBearingY),
+            horiAdvance: self.scale_pos(metrics.horiAdvance),
+            vertBearingX: self.scale_pos(metrics.vertBearingX),
+            vertBearingY: self.scale_pos(metrics.vertBearingY),
+            vertAdvance: self.scale_pos(metrics.vertAdvance)
+        })
+    }
+
+    // Faces with a vhea table have vertical metrics of their own. FreeType
+    // synthesizes them for other faces, centering glyphs in their column.
+    pub fn has_vertical_metrics(&self) -> bool {
+        sfnt::face_table(&self.bytes, self.face_index, b"vhea").is_some()
+    }
+
+    // From the vertical origin of the last loaded glyph, at the top center of
+    // its column, to the top left of its box, in 26.6 units at the current size.
+    pub fn get_glyph_vertical_bearings(&self) -> Result<(FT_Pos, FT_Pos)> {
+        let metrics = self.get_glyph_metrics()?;
+        Ok((metrics.vertBearingX, metrics.vertBearingY))
+    }
+
+    // CFF faces keep their glyphs' vertical origins in a VORG table, which
+    // FreeType doesn't read. In design units, y growing upwards.
+    pub fn get_vertical_origin_y(&self, glyph_index: u32) -> Option<i16> {
+        sfnt::face_table(&self.bytes, self.face_index, b"VORG").and_then(|table| sfnt::vertical_origin_y(table, glyph_index))
+    }
+
+    pub fn get_glyph_linear_advance(&self) -> Result<(FT_Fixed, FT_Fixed)> {
+        let face = unsafe { self.raw.as_ref() }.ok_or(FontError::FaceNotLoaded)?;
+        let glyph_slot = unsafe { face.glyph.as_ref() }.ok_or(FontError::FaceGlyphMissing)?;
+        Ok((self.scale_pos(glyph_slot.linearHoriAdvance), self.scale_pos(glyph_slot.linearVertAdvance)))
+    }
+
+    pub fn get_glyph_bitmap(&self, glyph_index: u32) -> Result<GlyphBitmap> {
+        let face = unsafe { self.raw.as_ref() }.ok_or(FontError::FaceNotLoaded)?;
+        let glyph_slot = unsafe { face.glyph.as_ref() }.ok_or(FontError::FaceGlyphMissing)?;
+        let bitmap = &glyph_slot.bitmap;
+
+        let (format, alpha_mode, bytes_per_pixel) = match bitmap.pixel_mode {
+            mode if mode == FT_Pixel_Mode::FT_PIXEL_MODE_MONO as u8 => (ImagePixelFormat::Gray(8), AlphaMode::Straight, 0),
+            mode if mode == FT_Pixel_Mode::FT_PIXEL_MODE_GRAY as u8 => (ImagePixelFormat::Gray(8), AlphaMode::Straight, 1),
+            mode if mode == FT_Pixel_Mode::FT_PIXEL_MODE_BGRA as u8 => (ImagePixelFormat::BGRA(8), AlphaMode::Premultiplied, 4),
+            mode if mode == FT_Pixel_Mode::FT_PIXEL_MODE_LCD as u8 => (ImagePixelFormat::RGB(8), AlphaMode::Straight, 1),
+            mode if mode == FT_Pixel_Mode::FT_PIXEL_MODE_LCD_V as u8 => (ImagePixelFormat::RGB(8), AlphaMode::Straight, 1),
+            mode => Err(FontError::FaceGlyphBitmapUnsupported(mode))?
+        };
+
+        let width = bitmap.width as usize;
+        let rows = bitmap.rows as usize;
+        let pitch = bitmap.pitch as isize;
+        let top = if pitch < 0 {
+            -pitch * (rows as isize - 1)
+        } else {
+            0
+        };
+
+        let mut pixels = Vec::with_capacity(width * rows * bytes_per_pixel.max(1));
+        for row in 0..rows {
+            let start = unsafe { bitmap.buffer.offset(top + row as isize * pitch) };
+            if bytes_per_pixel == 0 {
+                let packed = unsafe { slice::from_raw_parts(start, (width + 7) / 8) };
+                pixels.extend((0..width).map(|x| {
+                    if packed[x / 8] & (0x80 >> (x % 8)) != 0 {
+                        255
+                    } else {
+                        0
+                    }
+                }));
+            } else {
+                pixels.extend_from_slice(unsafe { slice::from_raw_parts(start, width * bytes_per_pixel) });
+            }
+        }
+
+        // Subpixel coverage is rendered at 3x the width or height. Interleave
+        // it so that each pixel holds one coverage value per channel.
+        let size = match bitmap.pixel_mode {
+            mode if mode == FT_Pixel_Mode::FT_PIXEL_MODE_LCD as u8 => (bitmap.width / 3, bitmap.rows),
+            mode if mode == FT_Pixel_Mode::FT_PIXEL_MODE_LCD_V as u8 => {
+                pixels = interleave_rows(&pixels, width);
+                (bitmap.width, bitmap.rows / 3)
+            }
+            _ => (bitmap.width, bitmap.rows)
+        };
+
+        let bitmap = GlyphBitmap {
+            glyph_index,
+            format,
+            alpha_mode,
+            size,
+            bearing: (glyph_slot.bitmap_left, glyph_slot.bitmap_top),
+            pixels
+        };
+        match self.strike_scale.get() {
+            Some(scale) if glyph_slot.format == FT_Glyph_Format::FT_GLYPH_FORMAT_BITMAP => Ok(scale_bitmap(bitmap, scale)),
+            _ => Ok(bitmap)
+        }
+    }
+}
+
+// Averages the source pixels each scaled pixel covers, which is also right for
+// premultiplied colors. Bearings are scaled along, rounded to whole pixels.
+fn scale_bitmap(bitmap: GlyphBitmap, scale: f64) -> GlyphBitmap {
+    let (width, rows) = (bitmap.size.0 as usize, bitmap.size.1 as usize);
+    if width == 0 || rows == 0 {
+        return bitmap;
+    }
+    let channels = bitmap.pixels.len() / (width * rows);
+    let scaled_width = ((width as f64 * scale).round() as usize).max(1);
+    let scaled_rows = ((rows as f64 * scale).round() as usize).max(1);
+    if (scaled_width, scaled_rows) == (width, rows) {
+        return bitmap;
+    }
+    let span = |scaled: usize, scaled_len: usize, len: usize| {
+        let start = scaled * len / scaled_len;
+        (start, ((scaled + 1) * len / scaled_len).max(start + 1).min(len))
+    };
+
+    let mut pixels = Vec::with_capacity(scaled_width * scaled_rows * channels);
+    for y in 0..scaled_rows {
+        let (y_start, y_end) = span(y, scaled_rows, rows);
+        for x in 0..scaled_width {
+            let (x_start, x_end) = span(x, scaled_width, width);
+            let count = ((y_end - y_start) * (x_end - x_start)) as u32;
+            for channel in 0..channels {
+                let mut sum = 0u32;
+                for source_y in y_start..y_end {
+                    for source_x in x_start..x_end {
+                        sum += u32::from(bitmap.pixels[(source_y * width + source_x) * channels + channel]);
+                    }
+                }
+                pixels.push(((sum + count / 2) / count) as u8);
+            }
+        }
+    }
+
+    GlyphBitmap {
+        size: (scaled_width as u32, scaled_rows as u32),
+        bearing: (
+            (f64::from(bitmap.bearing.0) * scale).round() as i32,
+            (f64::from(bitmap.bearing.1) * scale).round() as i32
+        ),
+        pixels,
+        ..bitmap
+    }
+}
+
+fn activate(size: FT_Size) -> Result<()> {
+    let result = unsafe { FT_Activate_Size(size) };
+    if !result.succeeded() {
+        Err(result)?
+    } else {
+        Ok(())
+    }
+}
+
+fn interleave_rows(pixels: &[u8], width: usize) -> Vec<u8> {
+    let mut interleaved = Vec::with_capacity(pixels.len());
+    if width == 0 {
+        return interleaved;
+    }
+    for rows in pixels.chunks(width * 3) {
+        if rows.len() < width * 3 {
+            break;
+        }
+        for x in 0..width {
+            interleaved.extend_from_slice(&[rows[x], rows[width + x], rows[width * 2 + x]]);
+        }
     }
+    interleaved
 }
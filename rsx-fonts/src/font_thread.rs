@@ -0,0 +1,119 @@
+/*
+Copyright 2016 Mozilla
+Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+this file except in compliance with the License. You may obtain a copy of the
+License at http://www.apache.org/licenses/LICENSE-2.0
+Unless required by applicable law or agreed to in writing, software distributed
+under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+CONDITIONS OF ANY KIND, either express or implied. See the License for the
+specific language governing permissions and limitations under the License.
+*/
+
+// Runs a font cache on a thread of its own, so that it can be used from any
+// other thread, e.g. loading fonts off the main thread or shaping on a layout
+// thread separate from the render thread. Instances are shared through `Rc`
+// and a FreeType library mustn't be used from two threads at once, so instead
+// of locking around every call the cache never leaves the thread it's created
+// on: work is sent to it as closures, and only `Send` results come back.
+// Glyph stores are brought back as `SendGlyphStore`s, with `to_send`.
+
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use rsx_shared::traits::TFontKeysAPI;
+
+use backend::TFontBackend;
+use error::{FontError, Result};
+use font_context::FontContext;
+use types::FontCache;
+
+type Job<A, B> = Box<FnMut(&mut FontCache<A, B>) + Send>;
+
+pub struct FontThread<A, B = FontContext>
+where
+    A: TFontKeysAPI
+{
+    sender: Arc<Mutex<Sender<Job<A, B>>>>
+}
+
+impl<A, B> Clone for FontThread<A, B>
+where
+    A: TFontKeysAPI
+{
+    fn clone(&self) -> Self {
+        FontThread {
+            sender: Arc::clone(&self.sender)
+        }
+    }
+}
+
+impl<A, B> FontThread<A, B>
+where
+    A: TFontKeysAPI + 'static,
+    B: TFontBackend + 'static
+{
+    // The cache, along with its keys API and backend, is created on the font
+    // thread, since neither can be moved there afterwards. The thread exits
+    // once every handle is dropped.
+    pub fn spawn<F>(create: F) -> Result<Self>
+    where
+        F: FnOnce() -> Result<FontCache<A, B>> + Send + 'static
+    {
+        let (sender, receiver) = mpsc::channel::<Job<A, B>>();
+        let (created_sender, created) = mpsc::channel();
+        thread::Builder::new()
+            .name("rsx-fonts".to_string())
+            .spawn(move || {
+                let mut cache = match create() {
+                    Ok(cache) => cache,
+                    Err(err) => {
+                        let _ = created_sender.send(Err(err));
+                        return;
+                    }
+                };
+                let _ = created_sender.send(Ok(()));
+                for mut job in receiver {
+                    job(&mut cache);
+                }
+            })?;
+
+        created.recv().map_err(|_| FontError::ThreadUnavailable)??;
+        Ok(FontThread {
+            sender: Arc::new(Mutex::new(sender))
+        })
+    }
+
+    // Queues work without waiting for it. Jobs run in the order they're
+    // posted in, from whichever thread.
+    pub fn post<F>(&self, f: F) -> Result<()>
+    where
+        F: FnOnce(&mut FontCache<A, B>) + Send + 'static
+    {
+        let mut f = Some(f);
+        let job: Job<A, B> = Box::new(move |cache| {
+            if let Some(f) = f.take() {
+                f(cache)
+            }
+        });
+        self.sender
+            .lock()
+            .map_err(|_| FontError::ThreadUnavailable)?
+            .send(job)
+            .map_err(|_| FontError::ThreadUnavailable)
+    }
+
+    // Waits for the work to run after everything posted before it. Mustn't be
+    // called from a job, which would wait on itself.
+    pub fn run<F, R>(&self, f: F) -> Result<R>
+    where
+        F: FnOnce(&mut FontCache<A, B>) -> R + Send + 'static,
+        R: Send + 'static
+    {
+        let (result_sender, result) = mpsc::channel();
+        self.post(move |cache| {
+            let _ = result_sender.send(f(cache));
+        })?;
+        result.recv().map_err(|_| FontError::ThreadUnavailable)
+    }
+}
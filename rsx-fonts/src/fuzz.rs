@@ -0,0 +1,43 @@
+/*
+Copyright 2016 Mozilla
+Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+this file except in compliance with the License. You may obtain a copy of the
+License at http://www.apache.org/licenses/LICENSE-2.0
+Unless required by applicable law or agreed to in writing, software distributed
+under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+CONDITIONS OF ANY KIND, either express or implied. See the License for the
+specific language governing permissions and limitations under the License.
+*/
+
+// Entry points for fuzzers, taking plain slices rather than shared buffers.
+
+use std::rc::Rc;
+
+use rsx_shared::traits::TEncodedFont;
+
+use backend::TFontBackend;
+use encoded::EncodedFont;
+use error::Result;
+use sfnt::{self, FaceInfo};
+use types::{FontContext, FontId};
+
+pub fn encoded_font(bytes: &[u8]) -> Result<EncodedFont> {
+    EncodedFont::from_bytes(bytes.to_vec())
+}
+
+pub fn encoded_font_from_data_uri(data_uri: &str) -> Result<EncodedFont> {
+    EncodedFont::from_data_uri(data_uri.to_string())
+}
+
+// Parses the tables read before a face is handed to a backend.
+pub fn face_info(bytes: &[u8], face_index: usize) -> Option<FaceInfo> {
+    sfnt::face_info(bytes, face_index)
+}
+
+// Loads the face with FreeType, returning its family name.
+pub fn family_name(bytes: &[u8], face_index: usize) -> Result<String> {
+    let font_id = FontId::new("Fuzz");
+    let mut context = FontContext::new()?;
+    context.add_face(font_id, &Rc::new(bytes.to_vec()), face_index)?;
+    context.get_family_name(font_id).map(String::from)
+}
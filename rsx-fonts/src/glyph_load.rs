@@ -0,0 +1,174 @@
+/*
+Copyright 2016 Mozilla
+Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+this file except in compliance with the License. You may obtain a copy of the
+License at http://www.apache.org/licenses/LICENSE-2.0
+Unless required by applicable law or agreed to in writing, software distributed
+under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+CONDITIONS OF ANY KIND, either express or implied. See the License for the
+specific language governing permissions and limitations under the License.
+*/
+
+use std::os::raw::c_uint;
+
+use freetype::freetype::{self, FT_LcdFilter, FT_Render_Mode};
+use rsx_resource_updates::display::{DisplayProfile, SubpixelLayout};
+
+bitflags! {
+    pub struct LoadFlag: c_uint {
+        const DEFAULT = freetype::FT_LOAD_DEFAULT;
+        const NO_SCALE = freetype::FT_LOAD_NO_SCALE;
+        const NO_HINTING = freetype::FT_LOAD_NO_HINTING;
+        const RENDER = freetype::FT_LOAD_RENDER;
+        const NO_BITMAP = freetype::FT_LOAD_NO_BITMAP;
+        const VERTICAL_LAYOUT = freetype::FT_LOAD_VERTICAL_LAYOUT;
+        const FORCE_AUTOHINT = freetype::FT_LOAD_FORCE_AUTOHINT;
+        const CROP_BITMAP = freetype::FT_LOAD_CROP_BITMAP;
+        const PEDANTIC = freetype::FT_LOAD_PEDANTIC;
+        const IGNORE_GLOBAL_ADVANCE_WIDTH = freetype::FT_LOAD_IGNORE_GLOBAL_ADVANCE_WIDTH;
+        const NO_RECURSE = freetype::FT_LOAD_NO_RECURSE;
+        const IGNORE_TRANSFORM = freetype::FT_LOAD_IGNORE_TRANSFORM;
+        const MONOCHROME = freetype::FT_LOAD_MONOCHROME;
+        const LINEAR_DESIGN = freetype::FT_LOAD_LINEAR_DESIGN;
+        const NO_AUTOHINT = freetype::FT_LOAD_NO_AUTOHINT;
+        const COLOR = freetype::FT_LOAD_COLOR;
+        const COMPUTE_METRICS = freetype::FT_LOAD_COMPUTE_METRICS;
+        const SBITS_ONLY = freetype::FT_LOAD_SBITS_ONLY;
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum GlyphLoadPurpose {
+    Measure,
+    RasterGray,
+    RasterLCD,
+    LinearMetrics
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum RenderTarget {
+    Normal,
+    Light,
+    Mono,
+    LCD,
+    LCDVertical
+}
+
+impl RenderTarget {
+    pub fn render_mode(&self) -> FT_Render_Mode {
+        match self {
+            &RenderTarget::Normal => FT_Render_Mode::FT_RENDER_MODE_NORMAL,
+            &RenderTarget::Light => FT_Render_Mode::FT_RENDER_MODE_LIGHT,
+            &RenderTarget::Mono => FT_Render_Mode::FT_RENDER_MODE_MONO,
+            &RenderTarget::LCD => FT_Render_Mode::FT_RENDER_MODE_LCD,
+            &RenderTarget::LCDVertical => FT_Render_Mode::FT_RENDER_MODE_LCD_V
+        }
+    }
+
+    // Equivalent of the `FT_LOAD_TARGET_XXX` macros, which aren't exported.
+    pub fn load_flags(&self) -> LoadFlag {
+        LoadFlag {
+            bits: (self.render_mode() as c_uint & 15) << 16
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum LcdFilter {
+    None,
+    Default,
+    Light,
+    Legacy
+}
+
+impl LcdFilter {
+    pub fn to_ft(&self) -> FT_LcdFilter {
+        match self {
+            &LcdFilter::None => FT_LcdFilter::FT_LCD_FILTER_NONE,
+            &LcdFilter::Default => FT_LcdFilter::FT_LCD_FILTER_DEFAULT,
+            &LcdFilter::Light => FT_LcdFilter::FT_LCD_FILTER_LIGHT,
+            &LcdFilter::Legacy => FT_LcdFilter::FT_LCD_FILTER_LEGACY
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct GlyphLoadOptions {
+    pub flags: LoadFlag,
+    pub target: RenderTarget
+}
+
+impl GlyphLoadOptions {
+    pub fn new(flags: LoadFlag, target: RenderTarget) -> Self {
+        GlyphLoadOptions { flags, target }
+    }
+
+    pub fn load_flags(&self) -> LoadFlag {
+        self.flags | self.target.load_flags()
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct GlyphLoadPolicy {
+    pub measure: GlyphLoadOptions,
+    pub raster_gray: GlyphLoadOptions,
+    pub raster_lcd: GlyphLoadOptions,
+    pub linear_metrics: GlyphLoadOptions
+}
+
+impl Default for GlyphLoadPolicy {
+    fn default() -> Self {
+        GlyphLoadPolicy {
+            measure: GlyphLoadOptions::new(
+                LoadFlag::NO_HINTING | LoadFlag::NO_BITMAP,
+                RenderTarget::Normal
+            ),
+            raster_gray: GlyphLoadOptions::new(
+                LoadFlag::NO_HINTING | LoadFlag::RENDER | LoadFlag::COLOR,
+                RenderTarget::Normal
+            ),
+            raster_lcd: GlyphLoadOptions::new(LoadFlag::RENDER | LoadFlag::COLOR, RenderTarget::LCD),
+            linear_metrics: GlyphLoadOptions::new(
+                LoadFlag::NO_HINTING | LoadFlag::NO_BITMAP | LoadFlag::IGNORE_TRANSFORM,
+                RenderTarget::Normal
+            )
+        }
+    }
+}
+
+impl GlyphLoadPolicy {
+    pub fn for_display(profile: &DisplayProfile) -> Self {
+        let mut policy = GlyphLoadPolicy::default();
+        if profile.subpixel_layout == SubpixelLayout::Vertical {
+            policy.raster_lcd.target = RenderTarget::LCDVertical;
+        }
+        policy
+    }
+
+    pub fn get(&self, purpose: GlyphLoadPurpose) -> GlyphLoadOptions {
+        match purpose {
+            GlyphLoadPurpose::Measure => self.measure,
+            GlyphLoadPurpose::RasterGray => self.raster_gray,
+            GlyphLoadPurpose::RasterLCD => self.raster_lcd,
+            GlyphLoadPurpose::LinearMetrics => self.linear_metrics
+        }
+    }
+
+    pub fn set(&mut self, purpose: GlyphLoadPurpose, options: GlyphLoadOptions) {
+        match purpose {
+            GlyphLoadPurpose::Measure => self.measure = options,
+            GlyphLoadPurpose::RasterGray => self.raster_gray = options,
+            GlyphLoadPurpose::RasterLCD => self.raster_lcd = options,
+            GlyphLoadPurpose::LinearMetrics => self.linear_metrics = options
+        }
+    }
+
+    pub fn with(mut self, purpose: GlyphLoadPurpose, options: GlyphLoadOptions) -> Self {
+        self.set(purpose, options);
+        self
+    }
+
+    pub fn load_flags(&self, purpose: GlyphLoadPurpose) -> LoadFlag {
+        self.get(purpose).load_flags()
+    }
+}
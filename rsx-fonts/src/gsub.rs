@@ -0,0 +1,140 @@
+/*
+Copyright 2016 Mozilla
+Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+this file except in compliance with the License. You may obtain a copy of the
+License at http://www.apache.org/licenses/LICENSE-2.0
+Unless required by applicable law or agreed to in writing, software distributed
+under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+CONDITIONS OF ANY KIND, either express or implied. See the License for the
+specific language governing permissions and limitations under the License.
+*/
+
+// Reads the single substitutions of GSUB tables, which swap one glyph for
+// another, e.g. proportional figures for tabular ones. That's enough for the
+// simple shaper to honor features like `tnum`, `zero` or `smcp`. Ligatures
+// and contextual substitutions are left to HarfBuzz.
+
+use fnv::FnvHashMap;
+
+use sfnt::{read_u16, read_u32};
+use shaping::FontFeature;
+
+const SINGLE_SUBSTITUTION: u16 = 1;
+const EXTENSION_SUBSTITUTION: u16 = 7;
+
+// Coverage tables can't cover more glyphs than there are glyph ids, however
+// many ranges they list.
+const MAX_COVERAGE_GLYPHS: usize = 0x10000;
+
+// Maps glyphs to their substitutes under the enabled features. Features are
+// looked up under every script, and when listed more than once, the last
+// value wins. Lookups apply in the table's order, each to the output of the
+// ones before it.
+pub(crate) fn single_substitutions(table: &[u8], features: &[FontFeature]) -> FnvHashMap<u32, u32> {
+    let mut lookups = feature_lookups(table, features).unwrap_or_default();
+    lookups.sort();
+    lookups.dedup();
+
+    let mut substitutions = FnvHashMap::default();
+    for lookup in lookups {
+        let mut mapping = FnvHashMap::default();
+        if read_lookup(table, lookup, &mut mapping).is_none() {
+            continue;
+        }
+        for substitute in substitutions.values_mut() {
+            if let Some(&next) = mapping.get(substitute) {
+                *substitute = next;
+            }
+        }
+        for (glyph, substitute) in mapping {
+            substitutions.entry(glyph).or_insert(substitute);
+        }
+    }
+    substitutions
+}
+
+fn is_enabled(features: &[FontFeature], tag: &[u8]) -> bool {
+    features
+        .iter()
+        .rev()
+        .find(|feature| feature.tag == tag)
+        .map_or(false, FontFeature::is_enabled)
+}
+
+fn feature_lookups(table: &[u8], features: &[FontFeature]) -> Option<Vec<u16>> {
+    let feature_list = usize::from(read_u16(table, 6)?);
+    let mut lookups = Vec::new();
+    for record in 0..usize::from(read_u16(table, feature_list)?) {
+        let record = feature_list + 2 + record * 6;
+        if !is_enabled(features, table.get(record..record + 4)?) {
+            continue;
+        }
+        let feature = feature_list + usize::from(read_u16(table, record + 4)?);
+        for index in 0..usize::from(read_u16(table, feature + 2)?) {
+            lookups.push(read_u16(table, feature + 4 + index * 2)?);
+        }
+    }
+    Some(lookups)
+}
+
+// Extension subtables only point at subtables of another type, stored past
+// the 16 bit offsets of large tables.
+fn read_lookup(table: &[u8], lookup_index: u16, mapping: &mut FnvHashMap<u32, u32>) -> Option<()> {
+    let lookup_list = usize::from(read_u16(table, 8)?);
+    let lookup = lookup_list + usize::from(read_u16(table, lookup_list + 2 + usize::from(lookup_index) * 2)?);
+    let lookup_type = read_u16(table, lookup)?;
+    for subtable in 0..usize::from(read_u16(table, lookup + 4)?) {
+        let mut subtable = lookup + usize::from(read_u16(table, lookup + 6 + subtable * 2)?);
+        let mut subtable_type = lookup_type;
+        if lookup_type == EXTENSION_SUBSTITUTION {
+            subtable_type = read_u16(table, subtable + 2)?;
+            subtable += read_u32(table, subtable + 4)? as usize;
+        }
+        if subtable_type == SINGLE_SUBSTITUTION {
+            read_single_substitution(table, subtable, mapping)?;
+        }
+    }
+    Some(())
+}
+
+// The first subtable covering a glyph substitutes it.
+fn read_single_substitution(table: &[u8], subtable: usize, mapping: &mut FnvHashMap<u32, u32>) -> Option<()> {
+    let glyphs = read_coverage(table, subtable + usize::from(read_u16(table, subtable + 2)?))?;
+    match read_u16(table, subtable)? {
+        1 => {
+            let delta = read_u16(table, subtable + 4)?;
+            for glyph in glyphs {
+                mapping
+                    .entry(u32::from(glyph))
+                    .or_insert_with(|| u32::from(glyph.wrapping_add(delta)));
+            }
+        }
+        2 => for (index, glyph) in glyphs.into_iter().enumerate() {
+            let substitute = u32::from(read_u16(table, subtable + 6 + index * 2)?);
+            mapping.entry(u32::from(glyph)).or_insert(substitute);
+        },
+        _ => {}
+    }
+    Some(())
+}
+
+// Covered glyphs in coverage index order.
+fn read_coverage(table: &[u8], coverage: usize) -> Option<Vec<u16>> {
+    let count = usize::from(read_u16(table, coverage + 2)?);
+    let mut glyphs = Vec::new();
+    match read_u16(table, coverage)? {
+        1 => for index in 0..count {
+            glyphs.push(read_u16(table, coverage + 4 + index * 2)?);
+        },
+        2 => for index in 0..count {
+            let record = coverage + 4 + index * 6;
+            let remaining = MAX_COVERAGE_GLYPHS - glyphs.len();
+            glyphs.extend((read_u16(table, record)?..=read_u16(table, record + 2)?).take(remaining));
+            if glyphs.len() == MAX_COVERAGE_GLYPHS {
+                break;
+            }
+        },
+        _ => {}
+    }
+    Some(glyphs)
+}
@@ -0,0 +1,145 @@
+/*
+Copyright 2016 Mozilla
+Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+this file except in compliance with the License. You may obtain a copy of the
+License at http://www.apache.org/licenses/LICENSE-2.0
+Unless required by applicable law or agreed to in writing, software distributed
+under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+CONDITIONS OF ANY KIND, either express or implied. See the License for the
+specific language governing permissions and limitations under the License.
+*/
+
+// Shapes runs with HarfBuzz, which handles joining, reordering and ligatures
+// that per-char shaping can't. Glyphs are positioned by the face's own
+// tables, so advances are unhinted.
+
+use std::os::raw::{c_char, c_int, c_uint};
+use std::ptr;
+use std::slice;
+
+use harfbuzz_sys::{
+    hb_blob_create,
+    hb_blob_destroy,
+    hb_buffer_add_utf8,
+    hb_buffer_create,
+    hb_buffer_destroy,
+    hb_buffer_get_glyph_infos,
+    hb_buffer_get_glyph_positions,
+    hb_buffer_guess_segment_properties,
+    hb_buffer_set_direction,
+    hb_face_create,
+    hb_face_destroy,
+    hb_feature_t,
+    hb_font_create,
+    hb_font_destroy,
+    hb_font_set_scale,
+    hb_shape,
+    HB_DIRECTION_LTR,
+    HB_DIRECTION_RTL,
+    HB_DIRECTION_TTB,
+    HB_MEMORY_MODE_READONLY
+};
+
+use error::{Result, ShapingError};
+use shaping::FontFeature;
+
+const KERN_TAG: u32 = (b'k' as u32) << 24 | (b'e' as u32) << 16 | (b'r' as u32) << 8 | b'n' as u32;
+
+// One glyph of a shaped run, in 26.6 pixels, with y pointing up as in the
+// face's coordinates.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) struct ShapedGlyph {
+    pub glyph_index: u32,
+    pub cluster: u32,
+    pub x_advance_64: i32,
+    pub y_advance_64: i32,
+    pub x_offset_64: i32,
+    pub y_offset_64: i32
+}
+
+// Clusters are byte offsets in `text`. The scale makes HarfBuzz report
+// positions in 26.6 pixels for the given em size. Features apply to the whole
+// run, with disabled kerning overriding any `kern` feature.
+pub(crate) fn shape(
+    bytes: &[u8],
+    face_index: usize,
+    pixel_size_64: i64,
+    text: &str,
+    vertical: bool,
+    rtl: bool,
+    kerning: bool,
+    features: &[FontFeature]
+) -> Result<Vec<ShapedGlyph>> {
+    if pixel_size_64 > i64::from(c_int::max_value()) {
+        Err(ShapingError::Overflow)?;
+    }
+    let scale = pixel_size_64 as c_int;
+    let mut features: Vec<hb_feature_t> = features
+        .iter()
+        .map(|feature| hb_feature_t {
+            tag: feature.tag_u32(),
+            value: feature.value,
+            start: 0,
+            end: c_uint::max_value()
+        })
+        .collect();
+    if !kerning {
+        features.push(hb_feature_t {
+            tag: KERN_TAG,
+            value: 0,
+            start: 0,
+            end: c_uint::max_value()
+        });
+    }
+
+    unsafe {
+        let blob = hb_blob_create(
+            bytes.as_ptr() as *const c_char,
+            bytes.len() as c_uint,
+            HB_MEMORY_MODE_READONLY,
+            ptr::null_mut(),
+            None
+        );
+        let face = hb_face_create(blob, face_index as c_uint);
+        let font = hb_font_create(face);
+        hb_font_set_scale(font, scale, scale);
+
+        let buffer = hb_buffer_create();
+        hb_buffer_add_utf8(buffer, text.as_ptr() as *const c_char, text.len() as c_int, 0, text.len() as c_int);
+        hb_buffer_guess_segment_properties(buffer);
+        if vertical {
+            hb_buffer_set_direction(buffer, HB_DIRECTION_TTB);
+        } else if rtl {
+            hb_buffer_set_direction(buffer, HB_DIRECTION_RTL);
+        } else {
+            hb_buffer_set_direction(buffer, HB_DIRECTION_LTR);
+        }
+        hb_shape(font, buffer, features.as_ptr(), features.len() as c_uint);
+
+        let mut len = 0;
+        let infos = hb_buffer_get_glyph_infos(buffer, &mut len);
+        let positions = hb_buffer_get_glyph_positions(buffer, &mut len);
+        let glyphs = if infos.is_null() || positions.is_null() {
+            Vec::new()
+        } else {
+            slice::from_raw_parts(infos, len as usize)
+                .iter()
+                .zip(slice::from_raw_parts(positions, len as usize))
+                .map(|(info, position)| ShapedGlyph {
+                    glyph_index: info.codepoint,
+                    cluster: info.cluster,
+                    x_advance_64: position.x_advance,
+                    y_advance_64: position.y_advance,
+                    x_offset_64: position.x_offset,
+                    y_offset_64: position.y_offset
+                })
+                .collect()
+        };
+
+        hb_buffer_destroy(buffer);
+        hb_font_destroy(font);
+        hb_face_destroy(face);
+        hb_blob_destroy(blob);
+        Ok(glyphs)
+    }
+}
@@ -10,25 +10,70 @@ specific language governing permissions and limitations under the License.
 */
 
 #![cfg_attr(feature = "cargo-clippy", allow(match_ref_pats, type_complexity))]
-#![feature(integer_atomics)]
 
 extern crate base64_util;
 #[macro_use]
 extern crate bitflags;
+#[cfg(feature = "woff2")]
+extern crate brotli_decompressor;
+extern crate flate2;
 extern crate fnv;
 extern crate freetype;
+#[cfg(feature = "harfbuzz")]
+extern crate harfbuzz_sys;
+#[cfg(feature = "shared-shaping-cache")]
+#[macro_use]
+extern crate lazy_static;
+extern crate rsx_files;
 extern crate rsx_resource_updates;
 extern crate rsx_shared;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
+extern crate serde_json;
+extern crate unicode_bidi;
+extern crate unicode_normalization;
 extern crate uuid;
 
+pub mod arena;
+pub mod atlas;
+pub mod backend;
+pub mod bidi;
+pub mod blur;
+pub mod browser;
+pub mod caret;
+pub mod config;
+pub mod coverage;
 pub mod error;
+pub mod fixed;
+pub mod font_thread;
+pub mod fuzz;
+pub mod glyph_load;
+pub mod metrics;
+pub mod packing;
+pub mod palette;
+pub mod paragraph;
+pub mod placeholder;
+pub mod sfnt;
+pub mod shaping;
+pub mod shaping_cache;
 pub mod types;
+pub mod woff;
 pub mod encoded;
 pub mod decoded;
 pub mod export;
+#[cfg(feature = "cairo-interop")]
+pub mod cairo;
+#[cfg(feature = "skia-interop")]
+pub mod skia;
+#[cfg(feature = "shared-shaping-cache")]
+pub mod shared_shaping;
+
+#[cfg(feature = "harfbuzz")]
+mod harfbuzz;
 
 mod font_context;
 mod font_face;
+mod gsub;
+#[cfg(feature = "woff2")]
+mod woff2;
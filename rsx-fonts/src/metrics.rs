@@ -0,0 +1,391 @@
+/*
+Copyright 2016 Mozilla
+Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+this file except in compliance with the License. You may obtain a copy of the
+License at http://www.apache.org/licenses/LICENSE-2.0
+Unless required by applicable law or agreed to in writing, software distributed
+under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+CONDITIONS OF ANY KIND, either express or implied. See the License for the
+specific language governing permissions and limitations under the License.
+*/
+
+use std::collections::hash_map::Entry;
+use std::rc::Rc;
+use std::str;
+
+use fnv::FnvHashMap;
+use rsx_resource_updates::display::SubpixelLayout;
+use serde_json;
+
+use backend::TFontBackend;
+use coverage::CoverageReport;
+use error::{FontError, Result, ShapingError};
+use fixed::Fixed26_6;
+use glyph_load::LcdFilter;
+use types::{
+    FontId,
+    FontInstance,
+    FontSizeMetrics,
+    FontSizeUnit,
+    GlyphBitmap,
+    GlyphDimensions,
+    GlyphLinearAdvance,
+    GlyphTransform,
+    LinearGlyphMetrics,
+    PIXEL_SIZE_DPI
+};
+
+// Faces are measured at this many pixels per em when exported, so that
+// measurements in whole pixels are font units of the same em size.
+pub const EXPORT_UNITS_PER_EM: u32 = 1000;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GlyphMetrics {
+    pub code_point: u32,
+    pub glyph_index: u32,
+    pub hori_advance: i32,
+    pub vert_advance: i32,
+    pub width: i32,
+    pub height: i32
+}
+
+// Measurements of a face in font units, enough to lay out text without the
+// font file. Glyphs are sorted by code point, and chars missing from the
+// table are measured as the face's `.notdef` glyph.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FontMetricsTable {
+    pub family_name: String,
+    pub units_per_em: u32,
+    pub ascender: i32,
+    pub descender: i32,
+    pub height: i32,
+    pub max_advance: i32,
+    pub notdef: GlyphMetrics,
+    pub glyphs: Vec<GlyphMetrics>
+}
+
+impl FontMetricsTable {
+    // Measures chars of a face added to any backend, typically `FontContext`.
+    // Chars the face doesn't map are left out.
+    pub fn export<B, I>(backend: &B, font_id: FontId, chars: I) -> Result<Self>
+    where
+        B: TFontBackend,
+        I: IntoIterator<Item = char>
+    {
+        let instance = FontInstance::<(), (), ()>::new_with_unit(
+            font_id,
+            EXPORT_UNITS_PER_EM,
+            PIXEL_SIZE_DPI,
+            GlyphTransform::IDENTITY,
+            FontSizeUnit::Pixels,
+            (),
+            ()
+        );
+        let measure = |c: char| -> Result<GlyphMetrics> {
+            let dimensions = backend.get_glyph_dimensions(&instance, c)?;
+            Ok(GlyphMetrics {
+                code_point: c as u32,
+                glyph_index: dimensions.glyph_index,
+                hori_advance: Fixed26_6::from_raw(dimensions.hori_advance_64).round(),
+                vert_advance: Fixed26_6::from_raw(dimensions.vert_advance_64).round(),
+                width: Fixed26_6::from_raw(dimensions.width_64).round(),
+                height: Fixed26_6::from_raw(dimensions.height_64).round()
+            })
+        };
+
+        let mut glyphs = Vec::new();
+        for c in chars {
+            if backend.get_glyph_index(&instance, c)? != 0 {
+                glyphs.push(measure(c)?);
+            }
+        }
+        glyphs.sort_by_key(|glyph| glyph.code_point);
+        glyphs.dedup_by_key(|glyph| glyph.code_point);
+
+        let size_metrics = backend.get_global_size_metrics(&instance)?;
+        Ok(FontMetricsTable {
+            family_name: backend.get_family_name(font_id)?.to_string(),
+            units_per_em: EXPORT_UNITS_PER_EM,
+            ascender: Fixed26_6::from_raw(size_metrics.ascender_64).round(),
+            descender: Fixed26_6::from_raw(size_metrics.descender_64).round(),
+            height: Fixed26_6::from_raw(size_metrics.height_64).round(),
+            max_advance: Fixed26_6::from_raw(size_metrics.max_advance_64).round(),
+            // Noncharacters are never mapped, so this measures the missing glyph.
+            notdef: measure('\u{FFFF}')?,
+            glyphs
+        })
+    }
+
+    pub fn from_json<T>(json: T) -> Result<Self>
+    where
+        T: AsRef<str>
+    {
+        Ok(serde_json::from_str(json.as_ref())?)
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string(self)?)
+    }
+
+    pub fn glyph(&self, c: char) -> &GlyphMetrics {
+        match self.glyphs
+            .binary_search_by_key(&(c as u32), |glyph| glyph.code_point)
+        {
+            Ok(index) => &self.glyphs[index],
+            Err(_) => &self.notdef
+        }
+    }
+
+    pub(crate) fn coverage(&self) -> CoverageReport {
+        CoverageReport::from_ranges(
+            self.glyphs
+                .iter()
+                .map(|glyph| (glyph.code_point, glyph.code_point))
+                .collect()
+        )
+    }
+
+    fn scaled_64<FontKey, FontInstanceKey, GlyphInstance>(
+        &self,
+        instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>,
+        units: i32
+    ) -> Result<i32> {
+        let value = i64::from(units) * instance.pixel_size_64() / i64::from(self.units_per_em.max(1));
+        Ok(Fixed26_6::from_raw_i64(value)
+            .ok_or(ShapingError::Overflow)?
+            .raw())
+    }
+
+    pub(crate) fn glyph_dimensions<FontKey, FontInstanceKey, GlyphInstance>(
+        &self,
+        instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>,
+        c: char
+    ) -> Result<GlyphDimensions> {
+        let glyph = self.glyph(c);
+        let width_64 = self.scaled_64(instance, glyph.width)?;
+        let height_64 = self.scaled_64(instance, glyph.height)?;
+        let hori_advance_64 = self.scaled_64(instance, glyph.hori_advance)?;
+        let vert_advance_64 = self.scaled_64(instance, glyph.vert_advance)?;
+
+        let transform = instance.transform();
+        let (width, height) = transform.bounds_64(width_64.into(), height_64.into());
+        let (hori_advance, hori_advance_y) = transform.advance_64(hori_advance_64.into(), false);
+        let (vert_advance_x, vert_advance) = transform.advance_64(vert_advance_64.into(), true);
+
+        Ok(GlyphDimensions {
+            glyph_index: glyph.glyph_index,
+            width_64: width as i32,
+            height_64: height as i32,
+            hori_advance_64: hori_advance as i32,
+            vert_advance_64: vert_advance as i32,
+            hori_advance_y_64: hori_advance_y as i32,
+            vert_advance_x_64: vert_advance_x as i32,
+            color: false
+        })
+    }
+
+    pub(crate) fn linear_advance<FontKey, FontInstanceKey, GlyphInstance>(
+        &self,
+        instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>,
+        c: char
+    ) -> GlyphLinearAdvance {
+        let glyph = self.glyph(c);
+        let units_per_em = i64::from(self.units_per_em.max(1));
+        let linear = |units: i32| ((i64::from(units) * instance.pixel_size_64()) << 10) / units_per_em;
+
+        GlyphLinearAdvance {
+            glyph_index: glyph.glyph_index,
+            hori_advance_65536: linear(glyph.hori_advance),
+            vert_advance_65536: linear(glyph.vert_advance)
+        }
+    }
+
+    pub(crate) fn linear_metrics(&self, c: char) -> LinearGlyphMetrics {
+        let glyph = self.glyph(c);
+        LinearGlyphMetrics {
+            glyph_index: glyph.glyph_index,
+            units_per_em: self.units_per_em,
+            hori_advance: i64::from(glyph.hori_advance),
+            vert_advance: i64::from(glyph.vert_advance)
+        }
+    }
+
+    pub(crate) fn size_metrics<FontKey, FontInstanceKey, GlyphInstance>(
+        &self,
+        instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>
+    ) -> Result<FontSizeMetrics> {
+        let em = instance.pixel_size_64() / 64;
+
+        Ok(FontSizeMetrics {
+            nominal_width: em as u16,
+            nominal_height: em as u16,
+            ascender_64: self.scaled_64(instance, self.ascender)?,
+            descender_64: self.scaled_64(instance, self.descender)?,
+            height_64: self.scaled_64(instance, self.height)?,
+            max_advance_64: self.scaled_64(instance, self.max_advance)?
+        })
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct MetricsFace {
+    bytes: Rc<Vec<u8>>,
+    face_index: usize,
+    table: FontMetricsTable
+}
+
+// Measurement only backend for when FreeType isn't available, e.g. for server
+// side layout. Faces are added as exported `FontMetricsTable` JSON instead of
+// font files, and glyphs can't be rasterized.
+#[derive(Debug, PartialEq)]
+pub struct MetricsFontBackend {
+    lcd_filter: LcdFilter,
+    faces: FnvHashMap<FontId, MetricsFace>
+}
+
+impl Default for MetricsFontBackend {
+    fn default() -> Self {
+        MetricsFontBackend::new()
+    }
+}
+
+impl MetricsFontBackend {
+    pub fn new() -> Self {
+        MetricsFontBackend {
+            lcd_filter: LcdFilter::None,
+            faces: FnvHashMap::default()
+        }
+    }
+
+    pub fn table(&self, font_id: FontId) -> Result<&FontMetricsTable> {
+        self.faces
+            .get(&font_id)
+            .ok_or(FontError::FaceNotFound)
+            .map(|f| &f.table)
+    }
+
+    fn face_table<FontKey, FontInstanceKey, GlyphInstance>(
+        &self,
+        instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>
+    ) -> Result<&FontMetricsTable> {
+        self.table(instance.font_id())
+    }
+}
+
+impl TFontBackend for MetricsFontBackend {
+    fn lcd_filter(&self) -> LcdFilter {
+        self.lcd_filter
+    }
+
+    fn set_lcd_filter(&mut self, filter: LcdFilter) -> Result<()> {
+        self.lcd_filter = filter;
+        Ok(())
+    }
+
+    fn add_face(&mut self, font_id: FontId, bytes: &Rc<Vec<u8>>, face_index: usize) -> Result<()> {
+        let table = FontMetricsTable::from_json(str::from_utf8(bytes)?)?;
+        match self.faces.entry(font_id) {
+            Entry::Occupied(_) => {
+                Err(FontError::FaceAlreadyAdded)?;
+            }
+            Entry::Vacant(e) => {
+                e.insert(MetricsFace {
+                    bytes: Rc::clone(bytes),
+                    face_index,
+                    table
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    fn remove_face(&mut self, font_id: FontId) -> Result<()> {
+        self.faces
+            .remove(&font_id)
+            .ok_or(FontError::FaceNotFound)
+            .map(|_| ())
+    }
+
+    fn font_ids(&self) -> Vec<FontId> {
+        self.faces.keys().cloned().collect()
+    }
+
+    fn get_bytes(&self, font_id: FontId) -> Result<Rc<Vec<u8>>> {
+        self.faces
+            .get(&font_id)
+            .ok_or(FontError::FaceNotFound)
+            .map(|f| Rc::clone(&f.bytes))
+    }
+
+    fn get_face_index(&self, font_id: FontId) -> Result<usize> {
+        self.faces
+            .get(&font_id)
+            .ok_or(FontError::FaceNotFound)
+            .map(|f| f.face_index)
+    }
+
+    fn get_family_name(&self, font_id: FontId) -> Result<&str> {
+        self.table(font_id).map(|table| table.family_name.as_str())
+    }
+
+    fn get_coverage(&self, font_id: FontId) -> Result<CoverageReport> {
+        Ok(self.table(font_id)?.coverage())
+    }
+
+    fn get_glyph_index<FontKey, FontInstanceKey, GlyphInstance>(
+        &self,
+        instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>,
+        c: char
+    ) -> Result<u32> {
+        Ok(self.face_table(instance)?.glyph(c).glyph_index)
+    }
+
+    fn get_glyph_dimensions<FontKey, FontInstanceKey, GlyphInstance>(
+        &self,
+        instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>,
+        c: char
+    ) -> Result<GlyphDimensions> {
+        self.face_table(instance)?.glyph_dimensions(instance, c)
+    }
+
+    fn get_glyph_linear_advance<FontKey, FontInstanceKey, GlyphInstance>(
+        &self,
+        instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>,
+        c: char
+    ) -> Result<GlyphLinearAdvance> {
+        Ok(self.face_table(instance)?.linear_advance(instance, c))
+    }
+
+    fn get_linear_glyph_metrics<FontKey, FontInstanceKey, GlyphInstance>(
+        &self,
+        instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>,
+        c: char
+    ) -> Result<LinearGlyphMetrics> {
+        Ok(self.face_table(instance)?.linear_metrics(c))
+    }
+
+    fn get_glyph_bitmap<FontKey, FontInstanceKey, GlyphInstance>(
+        &self,
+        _: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>,
+        _: char
+    ) -> Result<GlyphBitmap> {
+        Err(FontError::RasterizationUnsupported)
+    }
+
+    fn get_glyph_subpixel_bitmap<FontKey, FontInstanceKey, GlyphInstance>(
+        &self,
+        _: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>,
+        _: char,
+        _: SubpixelLayout
+    ) -> Result<GlyphBitmap> {
+        Err(FontError::RasterizationUnsupported)
+    }
+
+    fn get_global_size_metrics<FontKey, FontInstanceKey, GlyphInstance>(
+        &self,
+        instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>
+    ) -> Result<FontSizeMetrics> {
+        self.face_table(instance)?.size_metrics(instance)
+    }
+}
@@ -0,0 +1,127 @@
+/*
+Copyright 2016 Mozilla
+Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+this file except in compliance with the License. You may obtain a copy of the
+License at http://www.apache.org/licenses/LICENSE-2.0
+Unless required by applicable law or agreed to in writing, software distributed
+under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+CONDITIONS OF ANY KIND, either express or implied. See the License for the
+specific language governing permissions and limitations under the License.
+*/
+
+// Packs rectangles into fixed size pages in rows of similar height, which
+// suits glyphs well since those of one instance are mostly as tall. Freed
+// rectangles are reused by later ones fitting in them.
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub struct AtlasRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32
+}
+
+impl AtlasRect {
+    pub fn new(x: u32, y: u32, width: u32, height: u32) -> Self {
+        AtlasRect { x, y, width, height }
+    }
+
+    pub fn contains_size(&self, size: (u32, u32)) -> bool {
+        size.0 <= self.width && size.1 <= self.height
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Shelf {
+    y: u32,
+    height: u32,
+    used_width: u32
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShelfPacker {
+    size: (u32, u32),
+    shelves: Vec<Shelf>,
+    free: Vec<AtlasRect>,
+    used_area: u64
+}
+
+impl ShelfPacker {
+    pub fn new(size: (u32, u32)) -> Self {
+        ShelfPacker {
+            size,
+            shelves: Vec::new(),
+            free: Vec::new(),
+            used_area: 0
+        }
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        self.size
+    }
+
+    pub fn used_area(&self) -> u64 {
+        self.used_area
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.used_area == 0
+    }
+
+    // Prefers the smallest freed rectangle fitting the size, then the
+    // shortest shelf tall enough for it, before opening a new shelf.
+    pub fn allocate(&mut self, size: (u32, u32)) -> Option<AtlasRect> {
+        if size.0 == 0 || size.1 == 0 || size.0 > self.size.0 || size.1 > self.size.1 {
+            return None;
+        }
+
+        let reused = self.free
+            .iter()
+            .enumerate()
+            .filter(|&(_, rect)| rect.contains_size(size))
+            .min_by_key(|&(_, rect)| u64::from(rect.width) * u64::from(rect.height))
+            .map(|(index, _)| index);
+        if let Some(index) = reused {
+            let rect = self.free.swap_remove(index);
+            self.used_area += u64::from(rect.width) * u64::from(rect.height);
+            return Some(rect);
+        }
+
+        let page_width = self.size.0;
+        let shelf = self.shelves
+            .iter_mut()
+            .filter(|shelf| shelf.height >= size.1 && page_width - shelf.used_width >= size.0)
+            .min_by_key(|shelf| shelf.height);
+        let rect = match shelf {
+            Some(shelf) => {
+                let rect = AtlasRect::new(shelf.used_width, shelf.y, size.0, shelf.height);
+                shelf.used_width += size.0;
+                rect
+            }
+            None => {
+                let y = self.shelves.last().map_or(0, |shelf| shelf.y + shelf.height);
+                if self.size.1 - y < size.1 {
+                    return None;
+                }
+                self.shelves.push(Shelf {
+                    y,
+                    height: size.1,
+                    used_width: size.0
+                });
+                AtlasRect::new(0, y, size.0, size.1)
+            }
+        };
+        self.used_area += u64::from(rect.width) * u64::from(rect.height);
+        Some(rect)
+    }
+
+    pub fn deallocate(&mut self, rect: AtlasRect) {
+        self.used_area -= u64::from(rect.width) * u64::from(rect.height);
+        if self.used_area == 0 {
+            self.shelves.clear();
+            self.free.clear();
+        } else {
+            self.free.push(rect);
+        }
+    }
+}
@@ -0,0 +1,209 @@
+/*
+Copyright 2016 Mozilla
+Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+this file except in compliance with the License. You may obtain a copy of the
+License at http://www.apache.org/licenses/LICENSE-2.0
+Unless required by applicable law or agreed to in writing, software distributed
+under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+CONDITIONS OF ANY KIND, either express or implied. See the License for the
+specific language governing permissions and limitations under the License.
+*/
+
+// Reads the palettes of COLR/CPAL color fonts, and the layers their color
+// glyphs are made of, so that backends can composite them in any palette.
+
+use sfnt::{self, read_u16, read_u32};
+
+// Layers referencing this entry are drawn in the instance's foreground color.
+pub const FOREGROUND_ENTRY: u16 = 0xFFFF;
+
+const PALETTE_TYPE_LIGHT_BACKGROUND: u32 = 1;
+const PALETTE_TYPE_DARK_BACKGROUND: u32 = 1 << 1;
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct PaletteColor {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8
+}
+
+impl PaletteColor {
+    pub const BLACK: PaletteColor = PaletteColor {
+        r: 0,
+        g: 0,
+        b: 0,
+        a: 255
+    };
+
+    pub fn new(r: u8, g: u8, b: u8, a: u8) -> Self {
+        PaletteColor { r, g, b, a }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ColorPalette {
+    pub colors: Vec<PaletteColor>,
+    pub light_background: bool,
+    pub dark_background: bool
+}
+
+// Which palette a font instance draws color glyphs with. Instances only
+// differing in their selection are different instances.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct PaletteSelection {
+    pub index: u16,
+    pub foreground: PaletteColor
+}
+
+impl Default for PaletteSelection {
+    fn default() -> Self {
+        PaletteSelection {
+            index: 0,
+            foreground: PaletteColor::BLACK
+        }
+    }
+}
+
+impl PaletteSelection {
+    pub fn new(index: u16, foreground: PaletteColor) -> Self {
+        PaletteSelection { index, foreground }
+    }
+
+    // Out of range palettes fall back to the first one, like CSS does, and
+    // entries missing from it are transparent.
+    pub fn resolve(&self, palettes: &[ColorPalette], entry: u16) -> PaletteColor {
+        if entry == FOREGROUND_ENTRY {
+            return self.foreground;
+        }
+        palettes
+            .get(self.index as usize)
+            .or_else(|| palettes.first())
+            .and_then(|palette| palette.colors.get(entry as usize))
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ColorLayer {
+    pub glyph_index: u32,
+    pub palette_entry: u16
+}
+
+// The palettes and layers of a face, empty for faces without color glyphs.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ColorTables {
+    palettes: Vec<ColorPalette>,
+    colr: Vec<u8>
+}
+
+impl ColorTables {
+    pub fn from_face(bytes: &[u8], face_index: usize) -> Self {
+        let palettes = sfnt::face_table(bytes, face_index, b"CPAL").map_or_else(Vec::new, read_palettes);
+        let colr = sfnt::face_table(bytes, face_index, b"COLR").map_or_else(Vec::new, <[u8]>::to_vec);
+        ColorTables { palettes, colr }
+    }
+
+    pub fn palettes(&self) -> &[ColorPalette] {
+        &self.palettes
+    }
+
+    pub fn has_color_glyphs(&self) -> bool {
+        !self.palettes.is_empty() && !self.colr.is_empty()
+    }
+
+    pub fn layers(&self, glyph_index: u32) -> Vec<ColorLayer> {
+        if self.palettes.is_empty() {
+            return Vec::new();
+        }
+        read_layers(&self.colr, glyph_index).unwrap_or_default()
+    }
+}
+
+// Malformed tables yield as many palettes as could be read.
+pub fn read_palettes(cpal: &[u8]) -> Vec<ColorPalette> {
+    let mut palettes = Vec::new();
+    let (version, entries, count, records, records_offset) = match (
+        read_u16(cpal, 0),
+        read_u16(cpal, 2),
+        read_u16(cpal, 4),
+        read_u16(cpal, 6),
+        read_u32(cpal, 8)
+    ) {
+        (Some(version), Some(entries), Some(count), Some(records), Some(records_offset)) => {
+            (version, entries as usize, count as usize, records as usize, records_offset as usize)
+        }
+        _ => return palettes
+    };
+    let types_offset = if version >= 1 {
+        read_u32(cpal, 12 + count * 2).map_or(0, |offset| offset as usize)
+    } else {
+        0
+    };
+
+    for i in 0..count {
+        let first = match read_u16(cpal, 12 + i * 2) {
+            Some(first) if first as usize + entries <= records => first as usize,
+            _ => break
+        };
+        let colors: Option<Vec<_>> = (first..first + entries)
+            .map(|record| {
+                let bgra = cpal.get(records_offset + record * 4..records_offset + record * 4 + 4)?;
+                Some(PaletteColor::new(bgra[2], bgra[1], bgra[0], bgra[3]))
+            })
+            .collect();
+        let colors = match colors {
+            Some(colors) => colors,
+            None => break
+        };
+        let palette_type = if types_offset != 0 {
+            read_u32(cpal, types_offset + i * 4).unwrap_or(0)
+        } else {
+            0
+        };
+        palettes.push(ColorPalette {
+            colors,
+            light_background: palette_type & PALETTE_TYPE_LIGHT_BACKGROUND != 0,
+            dark_background: palette_type & PALETTE_TYPE_DARK_BACKGROUND != 0
+        });
+    }
+    palettes
+}
+
+// Base glyph records are sorted by glyph id. Glyphs without one aren't color
+// glyphs, and are drawn as usual.
+pub fn read_layers(colr: &[u8], glyph_index: u32) -> Option<Vec<ColorLayer>> {
+    let count = read_u16(colr, 2)? as usize;
+    let base_offset = read_u32(colr, 4)? as usize;
+    let layers_offset = read_u32(colr, 8)? as usize;
+    let layer_count = read_u16(colr, 12)? as usize;
+
+    let (mut low, mut high) = (0, count);
+    while low < high {
+        let middle = (low + high) / 2;
+        let record = base_offset + middle * 6;
+        let base_glyph = u32::from(read_u16(colr, record)?);
+        if base_glyph < glyph_index {
+            low = middle + 1;
+        } else if base_glyph > glyph_index {
+            high = middle;
+        } else {
+            let first = read_u16(colr, record + 2)? as usize;
+            let len = read_u16(colr, record + 4)? as usize;
+            if first + len > layer_count {
+                return None;
+            }
+            return (first..first + len)
+                .map(|layer| {
+                    let layer = layers_offset + layer * 4;
+                    Some(ColorLayer {
+                        glyph_index: u32::from(read_u16(colr, layer)?),
+                        palette_entry: read_u16(colr, layer + 2)?
+                    })
+                })
+                .collect();
+        }
+    }
+    Some(Vec::new())
+}
@@ -0,0 +1,568 @@
+/*
+Copyright 2016 Mozilla
+Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+this file except in compliance with the License. You may obtain a copy of the
+License at http://www.apache.org/licenses/LICENSE-2.0
+Unless required by applicable law or agreed to in writing, software distributed
+under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+CONDITIONS OF ANY KIND, either express or implied. See the License for the
+specific language governing permissions and limitations under the License.
+*/
+
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+
+use fnv::{FnvHashMap, FnvHasher};
+use rsx_resource_updates::traits::TGlyphInstanceExt;
+use rsx_shared::traits::TDimensionsInfo;
+
+use bidi::DirectionRun;
+use error::Result;
+use fixed::Fixed26_6;
+use types::{GlyphStore, GlyphsArray};
+
+pub const DEFAULT_PARAGRAPH_CACHE_CAPACITY: usize = 256;
+
+// Wrapping constraints are snapped down to multiples of this width (in 26.6
+// units), so that small resizes keep hitting the same cached layouts.
+pub const PARAGRAPH_WIDTH_BUCKET_64: i32 = 4 * 64;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct ParagraphKey {
+    style: u64,
+    text: u64,
+    width_bucket: Option<i32>
+}
+
+impl ParagraphKey {
+    pub fn new<S, T>(style: &S, text: T, max_width_64: Option<i32>) -> Self
+    where
+        S: Hash,
+        T: AsRef<str>
+    {
+        let mut style_hasher = FnvHasher::default();
+        style.hash(&mut style_hasher);
+
+        let mut text_hasher = FnvHasher::default();
+        text.as_ref().hash(&mut text_hasher);
+
+        ParagraphKey {
+            style: style_hasher.finish(),
+            text: text_hasher.finish(),
+            width_bucket: max_width_64.map(|w| w.max(0) / PARAGRAPH_WIDTH_BUCKET_64)
+        }
+    }
+
+    pub fn max_width_64(&self) -> Option<i32> {
+        self.width_bucket
+            .map(|bucket| bucket * PARAGRAPH_WIDTH_BUCKET_64)
+    }
+}
+
+// Stands in for an inline object in laid out text, see `InlineObject`.
+pub const OBJECT_REPLACEMENT: char = '\u{FFFC}';
+
+// Where an inline object sits vertically, relative to the text around it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum InlineAlignment {
+    Baseline,
+    Middle,
+    TextTop,
+    TextBottom
+}
+
+impl Default for InlineAlignment {
+    fn default() -> Self {
+        InlineAlignment::Baseline
+    }
+}
+
+// A fixed size box laid out in place of an object replacement char, e.g. an
+// image or an emoji drawn by the caller. Dimensions are in 26.6 pixels.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct InlineObject {
+    pub width_64: i32,
+    pub height_64: i32,
+    pub alignment: InlineAlignment
+}
+
+impl InlineObject {
+    pub fn new(width_64: i32, height_64: i32, alignment: InlineAlignment) -> Self {
+        InlineObject {
+            width_64,
+            height_64,
+            alignment
+        }
+    }
+
+    // Sized after e.g. `ImageCache::measure_image`, one image pixel per pixel.
+    pub fn from_dimensions<D>(dimensions: &D, alignment: InlineAlignment) -> Self
+    where
+        D: TDimensionsInfo
+    {
+        let to_64 = |px: u32| (i64::from(px) * 64).min(i64::from(i32::max_value())) as i32;
+        InlineObject::new(to_64(dimensions.width()), to_64(dimensions.height()), alignment)
+    }
+
+    // How far the object extends above the baseline.
+    fn ascent_64(&self, ascender_64: i32, descender_64: i32) -> i32 {
+        match self.alignment {
+            InlineAlignment::Baseline => self.height_64,
+            InlineAlignment::Middle => (ascender_64 + descender_64) / 2 + self.height_64 / 2,
+            InlineAlignment::TextTop => ascender_64,
+            InlineAlignment::TextBottom => self.height_64.saturating_add(descender_64)
+        }
+    }
+}
+
+// Where an inline object ended up, relative to the top left of its line.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct InlineObjectPlacement {
+    pub index: usize,
+    pub text_offset: usize,
+    pub x_64: i32,
+    pub y_64: i32,
+    pub width_64: i32,
+    pub height_64: i32
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct LineLayout<FontKey, FontInstanceKey, GlyphInstance> {
+    pub text_range: (usize, usize),
+    pub offset_64: i32,
+    pub glyphs: GlyphStore<FontKey, FontInstanceKey, GlyphInstance>,
+    pub objects: Vec<InlineObjectPlacement>
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct ParagraphLayout<FontKey, FontInstanceKey, GlyphInstance> {
+    pub lines: Vec<LineLayout<FontKey, FontInstanceKey, GlyphInstance>>,
+    pub width_64: i32,
+    pub height_64: i32
+}
+
+impl<FontKey, FontInstanceKey, GlyphInstance> ParagraphLayout<FontKey, FontInstanceKey, GlyphInstance> {
+    // Greedily breaks the text into lines at whitespace and hard line breaks.
+    // Words wider than the constraint are placed on a line of their own.
+    pub fn layout<F>(text: &str, max_width_64: Option<i32>, mut shape: F) -> Result<Self>
+    where
+        F: FnMut(&str) -> Result<GlyphStore<FontKey, FontInstanceKey, GlyphInstance>>
+    {
+        let ranges = break_lines(text, max_width_64, false, |(start, end)| Ok(shape(&text[start..end])?.width_64))?;
+        let lines = ranges
+            .into_iter()
+            .map(|(start, end)| {
+                Ok(LineLayout {
+                    text_range: (start, end),
+                    offset_64: 0,
+                    glyphs: shape(&text[start..end])?,
+                    objects: Vec::new()
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self::stack(lines))
+    }
+
+    // Like `layout`, with the object replacement chars in the text laid out as
+    // the given objects, in order. Objects are breaking opportunities, and
+    // lines grow to fit them around the baseline the font's metrics place.
+    // Replacement chars past the last object are shaped as text.
+    pub fn layout_with_objects<F>(
+        text: &str,
+        objects: &[InlineObject],
+        font_extents_64: (i32, i32),
+        max_width_64: Option<i32>,
+        mut shape: F
+    ) -> Result<Self>
+    where
+        F: FnMut(&str) -> Result<GlyphStore<FontKey, FontInstanceKey, GlyphInstance>>,
+        FontKey: Copy,
+        FontInstanceKey: Copy,
+        GlyphInstance: TGlyphInstanceExt
+    {
+        let object_offsets: Vec<usize> = text.match_indices(OBJECT_REPLACEMENT)
+            .map(|(offset, _)| offset)
+            .take(objects.len())
+            .collect();
+
+        let ranges = break_lines(text, max_width_64, !object_offsets.is_empty(), |range| {
+            let mut width_64 = 0i32;
+            for piece in pieces(range, &object_offsets) {
+                width_64 = width_64.saturating_add(match piece {
+                    LinePiece::Text(start, end) => shape(&text[start..end])?.width_64,
+                    LinePiece::Object(index, _) => objects[index].width_64
+                });
+            }
+            Ok(width_64)
+        })?;
+
+        let mut lines = Vec::with_capacity(ranges.len());
+        for (start, end) in ranges {
+            let has_objects = object_offsets
+                .iter()
+                .any(|&offset| offset >= start && offset < end);
+            if has_objects {
+                lines.push(layout_line(text, (start, end), objects, &object_offsets, font_extents_64, &mut shape)?);
+            } else {
+                lines.push(LineLayout {
+                    text_range: (start, end),
+                    offset_64: 0,
+                    glyphs: shape(&text[start..end])?,
+                    objects: Vec::new()
+                });
+            }
+        }
+        Ok(Self::stack(lines))
+    }
+
+    pub fn line_widths_64(&self) -> Vec<i32> {
+        self.lines
+            .iter()
+            .map(|line| line.glyphs.width_64)
+            .collect()
+    }
+
+    fn stack(mut lines: Vec<LineLayout<FontKey, FontInstanceKey, GlyphInstance>>) -> Self {
+        let mut width_64 = 0;
+        let mut height_64 = 0;
+
+        for line in &mut lines {
+            width_64 = width_64.max(line.glyphs.width_64);
+            line.offset_64 = height_64;
+            height_64 = Fixed26_6::from_raw(height_64)
+                .saturating_add(Fixed26_6::from_raw(line.glyphs.height_64))
+                .raw();
+        }
+
+        ParagraphLayout {
+            lines,
+            width_64,
+            height_64
+        }
+    }
+}
+
+fn break_lines<F>(text: &str, max_width_64: Option<i32>, break_at_objects: bool, mut measure: F) -> Result<Vec<(usize, usize)>>
+where
+    F: FnMut((usize, usize)) -> Result<i32>
+{
+    let mut ranges = Vec::new();
+
+    let mut offset = 0;
+    for hard_line in text.split('\n') {
+        let mut line_start = offset;
+        let mut line_end = offset;
+        let mut line_width_64 = 0;
+
+        for (word_start, word_end, trimmed_end) in words(hard_line, offset, break_at_objects) {
+            let word_width_64 = measure((word_start, trimmed_end))?;
+            let fits = max_width_64.map_or(true, |max| line_width_64.saturating_add(word_width_64) <= max);
+            if !fits && line_end > line_start {
+                ranges.push((line_start, line_end));
+                line_start = word_start;
+                line_width_64 = 0;
+            }
+            line_end = trimmed_end;
+            line_width_64 = line_width_64.saturating_add(measure((word_start, word_end))?);
+        }
+
+        ranges.push((line_start, line_end));
+        offset += hard_line.len() + 1;
+    }
+
+    Ok(ranges)
+}
+
+// Yields (start, end including trailing whitespace, end excluding it) for each
+// word. Object replacement chars are words of their own when breaking at them.
+// Besides at spaces, words end after hyphens inside words and between CJK
+// ideographs, roughly following UAX #14. No-break spaces join words.
+fn words(line: &str, offset: usize, break_at_objects: bool) -> Vec<(usize, usize, usize)> {
+    let mut words = Vec::new();
+    let mut start = 0;
+    let mut trimmed_end = None;
+    let mut prev = None;
+
+    for (i, c) in line.char_indices() {
+        if c.is_whitespace() && !is_no_break_space(c) {
+            trimmed_end.get_or_insert(i);
+        } else if let Some(end) = trimmed_end.take() {
+            words.push((offset + start, offset + i, offset + end));
+            start = i;
+        } else if i > start && prev.map_or(false, |prev| is_break_between(&line[start..i], prev, c, break_at_objects)) {
+            words.push((offset + start, offset + i, offset + i));
+            start = i;
+        }
+        prev = Some(c);
+    }
+    if start < line.len() {
+        let end = trimmed_end.unwrap_or(line.len());
+        words.push((offset + start, offset + line.len(), offset + end));
+    }
+
+    words
+}
+
+fn is_no_break_space(c: char) -> bool {
+    match c {
+        '\u{00A0}' | '\u{2007}' | '\u{202F}' => true,
+        _ => false
+    }
+}
+
+fn is_ideographic(c: char) -> bool {
+    match c {
+        '\u{3040}'..='\u{30FF}' | '\u{3400}'..='\u{4DBF}' | '\u{4E00}'..='\u{9FFF}' | '\u{F900}'..='\u{FAFF}' => true,
+        _ => false
+    }
+}
+
+// Whether a line may break before `c`, which follows `prev` in a word made of
+// `word` so far (without whitespace).
+fn is_break_between(word: &str, prev: char, c: char, break_at_objects: bool) -> bool {
+    if break_at_objects && (c == OBJECT_REPLACEMENT || prev == OBJECT_REPLACEMENT) {
+        return true;
+    }
+    let after_hyphen = (prev == '-' || prev == '\u{2010}') && word.len() > prev.len_utf8() && c.is_alphabetic();
+    let cjk_closing = prev == '\u{3001}' || prev == '\u{3002}' || prev == '\u{FF0C}' || prev == '\u{FF0E}';
+    after_hyphen || ((is_ideographic(prev) || cjk_closing) && is_ideographic(c))
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum LinePiece {
+    Text(usize, usize),
+    Object(usize, usize)
+}
+
+// Splits a range of the text into runs of text and the objects between them.
+fn pieces((start, end): (usize, usize), object_offsets: &[usize]) -> Vec<LinePiece> {
+    let mut pieces = Vec::new();
+    let mut piece_start = start;
+    for (index, &offset) in object_offsets.iter().enumerate() {
+        if offset < start || offset >= end {
+            continue;
+        }
+        if piece_start < offset {
+            pieces.push(LinePiece::Text(piece_start, offset));
+        }
+        pieces.push(LinePiece::Object(index, offset));
+        piece_start = offset + OBJECT_REPLACEMENT.len_utf8();
+    }
+    if piece_start < end || pieces.is_empty() {
+        pieces.push(LinePiece::Text(piece_start, end.max(piece_start)));
+    }
+    pieces
+}
+
+// Shapes the text between objects separately, and joins the runs into a
+// single store, moved down when objects rise above the font's ascender.
+fn layout_line<F, FontKey, FontInstanceKey, GlyphInstance>(
+    text: &str,
+    range: (usize, usize),
+    objects: &[InlineObject],
+    object_offsets: &[usize],
+    (ascender_64, descender_64): (i32, i32),
+    shape: &mut F
+) -> Result<LineLayout<FontKey, FontInstanceKey, GlyphInstance>>
+where
+    F: FnMut(&str) -> Result<GlyphStore<FontKey, FontInstanceKey, GlyphInstance>>,
+    FontKey: Copy,
+    FontInstanceKey: Copy,
+    GlyphInstance: TGlyphInstanceExt
+{
+    let mut runs = Vec::new();
+    let mut placed = Vec::new();
+    let mut pen_64 = 0i32;
+
+    for piece in pieces(range, object_offsets) {
+        match piece {
+            LinePiece::Text(start, end) => {
+                let glyphs = shape(&text[start..end])?;
+                let width_64 = glyphs.width_64;
+                runs.push((pen_64, start - range.0, glyphs));
+                pen_64 = pen_64.saturating_add(width_64);
+            }
+            LinePiece::Object(index, offset) => {
+                placed.push((index, offset, pen_64));
+                pen_64 = pen_64.saturating_add(objects[index].width_64);
+            }
+        }
+    }
+    if runs.is_empty() {
+        runs.push((0, 0, shape("")?));
+    }
+
+    let max_ascent_64 = placed
+        .iter()
+        .map(|&(index, _, _)| objects[index].ascent_64(ascender_64, descender_64))
+        .max()
+        .unwrap_or(0);
+    let shift_64 = (max_ascent_64 - ascender_64).max(0);
+    let baseline_64 = shift_64.saturating_add(ascender_64);
+
+    let placements: Vec<InlineObjectPlacement> = placed
+        .into_iter()
+        .map(|(index, text_offset, x_64)| {
+            let object = objects[index];
+            InlineObjectPlacement {
+                index,
+                text_offset,
+                x_64,
+                y_64: baseline_64 - object.ascent_64(ascender_64, descender_64),
+                width_64: object.width_64,
+                height_64: object.height_64
+            }
+        })
+        .collect();
+
+    let text_height_64 = runs.iter().map(|&(_, _, ref glyphs)| glyphs.height_64).max().unwrap_or(0);
+    let height_64 = placements
+        .iter()
+        .map(|placement| placement.y_64.saturating_add(placement.height_64))
+        .fold(shift_64.saturating_add(text_height_64), i32::max);
+
+    let mut hasher = FnvHasher::default();
+    let mut glyphs = Vec::new();
+    let mut ink_bounds = Some(Vec::new());
+    let mut clusters = Some(Vec::new());
+    let mut direction_runs = Vec::new();
+    for &(x_64, text_offset, ref run) in &runs {
+        (x_64, run.generation_id).hash(&mut hasher);
+        glyphs.extend(run.glyphs.0.iter().map(|glyph| {
+            GlyphInstance::new(glyph.glyph_index(), glyph.x_64().saturating_add(x_64), glyph.y_64().saturating_add(shift_64))
+        }));
+        // Runs shaped apart keep their own directions, with ranges into the line.
+        let first_glyph = glyphs.len() - run.glyphs.0.len();
+        direction_runs.extend(run.direction_runs().unwrap_or(&[]).iter().map(|direction_run| DirectionRun {
+            text_range: (direction_run.text_range.0 + text_offset, direction_run.text_range.1 + text_offset),
+            glyph_range: (direction_run.glyph_range.0 + first_glyph, direction_run.glyph_range.1 + first_glyph),
+            ..*direction_run
+        }));
+        ink_bounds = match (ink_bounds, run.ink_bounds()) {
+            (Some(mut joined), Some(bounds)) => {
+                joined.extend(bounds.iter().map(|b| b.translate(x_64, shift_64)));
+                Some(joined)
+            }
+            _ => None
+        };
+        clusters = match (clusters, run.clusters.as_ref()) {
+            (Some(mut joined), Some(run_clusters)) => {
+                joined.extend(run_clusters.iter().map(|&cluster| cluster + text_offset as u32));
+                Some(joined)
+            }
+            _ => None
+        };
+    }
+    shift_64.hash(&mut hasher);
+
+    let first = &runs[0].2;
+    Ok(LineLayout {
+        text_range: range,
+        offset_64: 0,
+        glyphs: GlyphStore {
+            font_key: first.font_key,
+            font_instance_key: first.font_instance_key,
+            width_64: pen_64,
+            height_64,
+            glyphs: GlyphsArray(Rc::from(glyphs.into_boxed_slice())),
+            generation_id: hasher.finish(),
+            source: None,
+            ink_bounds: ink_bounds.map(Rc::new),
+            clusters: clusters.map(Rc::new),
+            text_len: (range.1 - range.0) as u32,
+            direction_runs: if direction_runs.is_empty() {
+                None
+            } else {
+                Some(Rc::new(direction_runs))
+            }
+        },
+        objects: placements
+    })
+}
+
+#[derive(Debug)]
+pub struct ParagraphCache<FontKey, FontInstanceKey, GlyphInstance> {
+    capacity: usize,
+    clock: u64,
+    entries: FnvHashMap<ParagraphKey, (u64, Rc<ParagraphLayout<FontKey, FontInstanceKey, GlyphInstance>>)>
+}
+
+// Cached layouts are derived data, so only compare which paragraphs are cached.
+impl<FontKey, FontInstanceKey, GlyphInstance> PartialEq for ParagraphCache<FontKey, FontInstanceKey, GlyphInstance> {
+    fn eq(&self, other: &Self) -> bool {
+        self.capacity == other.capacity
+            && self.entries.len() == other.entries.len()
+            && self.entries
+                .keys()
+                .all(|key| other.entries.contains_key(key))
+    }
+}
+
+impl<FontKey, FontInstanceKey, GlyphInstance> Default for ParagraphCache<FontKey, FontInstanceKey, GlyphInstance> {
+    fn default() -> Self {
+        ParagraphCache::with_capacity(DEFAULT_PARAGRAPH_CACHE_CAPACITY)
+    }
+}
+
+impl<FontKey, FontInstanceKey, GlyphInstance> ParagraphCache<FontKey, FontInstanceKey, GlyphInstance> {
+    pub fn with_capacity(capacity: usize) -> Self {
+        ParagraphCache {
+            capacity,
+            clock: 0,
+            entries: FnvHashMap::default()
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.entries.len() > capacity {
+            self.evict_least_recently_used();
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    pub fn get(&mut self, key: &ParagraphKey) -> Option<Rc<ParagraphLayout<FontKey, FontInstanceKey, GlyphInstance>>> {
+        self.clock += 1;
+        let clock = self.clock;
+        self.entries.get_mut(key).map(|entry| {
+            entry.0 = clock;
+            Rc::clone(&entry.1)
+        })
+    }
+
+    pub fn insert(&mut self, key: ParagraphKey, layout: Rc<ParagraphLayout<FontKey, FontInstanceKey, GlyphInstance>>) {
+        if self.capacity == 0 {
+            return;
+        }
+        if !self.entries.contains_key(&key) && self.entries.len() >= self.capacity {
+            self.evict_least_recently_used();
+        }
+        self.clock += 1;
+        self.entries.insert(key, (self.clock, layout));
+    }
+
+    fn evict_least_recently_used(&mut self) {
+        let oldest = self.entries
+            .iter()
+            .min_by_key(|&(_, &(last_used, _))| last_used)
+            .map(|(&key, _)| key);
+        if let Some(key) = oldest {
+            self.entries.remove(&key);
+        }
+    }
+}
@@ -0,0 +1,321 @@
+/*
+Copyright 2016 Mozilla
+Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+this file except in compliance with the License. You may obtain a copy of the
+License at http://www.apache.org/licenses/LICENSE-2.0
+Unless required by applicable law or agreed to in writing, software distributed
+under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+CONDITIONS OF ANY KIND, either express or implied. See the License for the
+specific language governing permissions and limitations under the License.
+*/
+
+// Synthesizes a TrueType face drawing every char as a hollow box, used in
+// place of families which are entirely missing. Being a real font file, it is
+// measured and rasterized by any backend reading those, and sent to renderers
+// like any other font.
+
+pub const PLACEHOLDER_FAMILY: &str = "RSX Placeholder";
+
+const UNITS_PER_EM: u16 = 1000;
+
+const GLYPH_NOTDEF: u32 = 0;
+const GLYPH_BOX: u32 = 1;
+const GLYPH_SPACE: u32 = 2;
+
+// Metric profile of the placeholder face, in thousandths of an em. Descender
+// is negative when below the baseline, like in font files.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct PlaceholderMetrics {
+    pub ascender: i16,
+    pub descender: i16,
+    pub line_gap: i16,
+    pub advance: u16,
+    pub stroke: u16
+}
+
+impl Default for PlaceholderMetrics {
+    fn default() -> Self {
+        PlaceholderMetrics {
+            ascender: 800,
+            descender: -200,
+            line_gap: 0,
+            advance: 600,
+            stroke: 60
+        }
+    }
+}
+
+impl PlaceholderMetrics {
+    // Outer bounds of the box, from the baseline to below the ascender and
+    // inset by one stroke on both sides of the advance.
+    fn box_bounds(&self) -> (i16, i16, i16, i16) {
+        let advance = to_i16(self.advance);
+        let ascender = self.ascender.max(0);
+        let stroke = self.stroke_width();
+        (stroke, 0, (advance - stroke).max(stroke), (ascender - stroke).max(0))
+    }
+
+    // Keeps the hole open however thick the stroke is asked to be.
+    fn stroke_width(&self) -> i16 {
+        let limit = (to_i16(self.advance) / 5).min(self.ascender.max(0) / 5);
+        to_i16(self.stroke).min(limit).max(1)
+    }
+}
+
+fn to_i16(value: u16) -> i16 {
+    value.min(0x7FFF) as i16
+}
+
+pub fn synthesize(metrics: &PlaceholderMetrics) -> Vec<u8> {
+    let (x_min, y_min, x_max, y_max) = metrics.box_bounds();
+    let advance = metrics.advance;
+    let box_glyph = box_glyph(metrics);
+
+    let mut glyf = Vec::new();
+    let mut loca = Vec::new();
+    for glyph in &[GLYPH_NOTDEF, GLYPH_BOX, GLYPH_SPACE] {
+        push_u32(&mut loca, glyf.len() as u32);
+        if *glyph != GLYPH_SPACE {
+            glyf.extend_from_slice(&box_glyph);
+        }
+    }
+    push_u32(&mut loca, glyf.len() as u32);
+
+    let mut head = Vec::new();
+    push_u32(&mut head, 0x0001_0000);
+    push_u32(&mut head, 0x0001_0000);
+    push_u32(&mut head, 0);
+    push_u32(&mut head, 0x5F0F_3CF5);
+    push_u16(&mut head, 0b11);
+    push_u16(&mut head, UNITS_PER_EM);
+    head.extend_from_slice(&[0; 16]);
+    for &bound in &[x_min, y_min, x_max, y_max] {
+        push_i16(&mut head, bound);
+    }
+    push_u16(&mut head, 0);
+    push_u16(&mut head, 8);
+    push_i16(&mut head, 2);
+    push_i16(&mut head, 1);
+    push_i16(&mut head, 0);
+
+    let mut hhea = Vec::new();
+    push_u32(&mut hhea, 0x0001_0000);
+    push_i16(&mut hhea, metrics.ascender);
+    push_i16(&mut hhea, metrics.descender);
+    push_i16(&mut hhea, metrics.line_gap);
+    push_u16(&mut hhea, advance);
+    push_i16(&mut hhea, 0);
+    push_i16(&mut hhea, advance as i16 - x_max);
+    push_i16(&mut hhea, x_max);
+    push_i16(&mut hhea, 1);
+    hhea.extend_from_slice(&[0; 14]);
+    push_u16(&mut hhea, 3);
+
+    let mut maxp = Vec::new();
+    push_u32(&mut maxp, 0x0001_0000);
+    push_u16(&mut maxp, 3);
+    push_u16(&mut maxp, 8);
+    push_u16(&mut maxp, 2);
+    push_u16(&mut maxp, 0);
+    push_u16(&mut maxp, 0);
+    push_u16(&mut maxp, 2);
+    maxp.extend_from_slice(&[0; 16]);
+
+    let mut hmtx = Vec::new();
+    for &lsb in &[x_min, x_min, 0] {
+        push_u16(&mut hmtx, advance);
+        push_i16(&mut hmtx, lsb);
+    }
+
+    // A single many-to-one subtable maps every char but the space to the box.
+    let groups = [(0x20, 0x20, GLYPH_SPACE), (0x21, 0x10_FFFF, GLYPH_BOX)];
+    let mut cmap = Vec::new();
+    push_u16(&mut cmap, 0);
+    push_u16(&mut cmap, 1);
+    push_u16(&mut cmap, 3);
+    push_u16(&mut cmap, 10);
+    push_u32(&mut cmap, 12);
+    push_u16(&mut cmap, 13);
+    push_u16(&mut cmap, 0);
+    push_u32(&mut cmap, 16 + 12 * groups.len() as u32);
+    push_u32(&mut cmap, 0);
+    push_u32(&mut cmap, groups.len() as u32);
+    for &(start, end, glyph) in &groups {
+        push_u32(&mut cmap, start);
+        push_u32(&mut cmap, end);
+        push_u32(&mut cmap, glyph);
+    }
+
+    let ascender = metrics.ascender;
+    let descender = metrics.descender;
+    let mut os2 = Vec::new();
+    push_u16(&mut os2, 4);
+    push_i16(&mut os2, advance as i16);
+    push_u16(&mut os2, 400);
+    push_u16(&mut os2, 5);
+    push_u16(&mut os2, 0);
+    os2.extend_from_slice(&[0; 16]);
+    push_i16(&mut os2, metrics.stroke_width());
+    push_i16(&mut os2, ascender / 4);
+    push_i16(&mut os2, 0);
+    os2.extend_from_slice(&[0; 26]);
+    os2.extend_from_slice(b"NONE");
+    push_u16(&mut os2, 0x40);
+    push_u16(&mut os2, 0x20);
+    push_u16(&mut os2, 0xFFFF);
+    push_i16(&mut os2, ascender);
+    push_i16(&mut os2, descender);
+    push_i16(&mut os2, metrics.line_gap);
+    push_u16(&mut os2, ascender.max(0) as u16);
+    push_u16(&mut os2, descender.min(0).wrapping_neg() as u16);
+    os2.extend_from_slice(&[0; 8]);
+    push_i16(&mut os2, y_max);
+    push_i16(&mut os2, y_max);
+    push_u16(&mut os2, 0);
+    push_u16(&mut os2, 0x20);
+    push_u16(&mut os2, 0);
+
+    let mut post = Vec::new();
+    push_u32(&mut post, 0x0003_0000);
+    push_u32(&mut post, 0);
+    push_i16(&mut post, descender / 2);
+    push_i16(&mut post, metrics.stroke_width());
+    push_u32(&mut post, 1);
+    post.extend_from_slice(&[0; 16]);
+
+    let full_name = format!("{} Regular", PLACEHOLDER_FAMILY);
+    let postscript_name = format!("{}-Regular", PLACEHOLDER_FAMILY.replace(' ', ""));
+    let name = name_table(&[(1, PLACEHOLDER_FAMILY), (2, "Regular"), (4, &full_name), (6, &postscript_name)]);
+
+    sfnt(&mut [
+        (*b"OS/2", os2),
+        (*b"cmap", cmap),
+        (*b"glyf", glyf),
+        (*b"head", head),
+        (*b"hhea", hhea),
+        (*b"hmtx", hmtx),
+        (*b"loca", loca),
+        (*b"maxp", maxp),
+        (*b"name", name),
+        (*b"post", post),
+    ])
+}
+
+// Two contours of on-curve points: the outer one clockwise and the inner one
+// counter-clockwise, leaving a hole.
+fn box_glyph(metrics: &PlaceholderMetrics) -> Vec<u8> {
+    let (x_min, y_min, x_max, y_max) = metrics.box_bounds();
+    let stroke = metrics.stroke_width();
+    let (ix_min, iy_min, ix_max, iy_max) = (x_min + stroke, y_min + stroke, x_max - stroke, y_max - stroke);
+    let points = [
+        (x_min, y_min),
+        (x_min, y_max),
+        (x_max, y_max),
+        (x_max, y_min),
+        (ix_min, iy_min),
+        (ix_max, iy_min),
+        (ix_max, iy_max),
+        (ix_min, iy_max),
+    ];
+
+    let mut glyph = Vec::new();
+    push_i16(&mut glyph, 2);
+    for &bound in &[x_min, y_min, x_max, y_max] {
+        push_i16(&mut glyph, bound);
+    }
+    push_u16(&mut glyph, 3);
+    push_u16(&mut glyph, 7);
+    push_u16(&mut glyph, 0);
+    glyph.extend_from_slice(&[1; 8]);
+    let mut previous = 0;
+    for &(x, _) in &points {
+        push_i16(&mut glyph, x - previous);
+        previous = x;
+    }
+    previous = 0;
+    for &(_, y) in &points {
+        push_i16(&mut glyph, y - previous);
+        previous = y;
+    }
+    glyph
+}
+
+fn name_table(names: &[(u16, &str)]) -> Vec<u8> {
+    let mut records = Vec::new();
+    let mut storage = Vec::new();
+    for &(name_id, value) in names {
+        let start = storage.len();
+        for unit in value.encode_utf16() {
+            push_u16(&mut storage, unit);
+        }
+        push_u16(&mut records, 3);
+        push_u16(&mut records, 1);
+        push_u16(&mut records, 0x409);
+        push_u16(&mut records, name_id);
+        push_u16(&mut records, (storage.len() - start) as u16);
+        push_u16(&mut records, start as u16);
+    }
+
+    let mut table = Vec::new();
+    push_u16(&mut table, 0);
+    push_u16(&mut table, names.len() as u16);
+    push_u16(&mut table, 6 + records.len() as u16);
+    table.extend(records);
+    table.extend(storage);
+    table
+}
+
+// Tables must be sorted by tag.
+pub(crate) fn sfnt(tables: &mut [([u8; 4], Vec<u8>)]) -> Vec<u8> {
+    let count = tables.len() as u16;
+    let mut entry_selector = 0;
+    while 2u16 << entry_selector <= count {
+        entry_selector += 1;
+    }
+    let search_range = 16u16 << entry_selector;
+
+    let mut bytes = Vec::new();
+    push_u32(&mut bytes, 0x0001_0000);
+    push_u16(&mut bytes, count);
+    push_u16(&mut bytes, search_range);
+    push_u16(&mut bytes, entry_selector);
+    push_u16(&mut bytes, count * 16 - search_range);
+
+    let mut offset = 12 + 16 * tables.len();
+    for &mut (tag, ref mut data) in tables.iter_mut() {
+        bytes.extend_from_slice(&tag);
+        push_u32(&mut bytes, checksum(data));
+        push_u32(&mut bytes, offset as u32);
+        push_u32(&mut bytes, data.len() as u32);
+        while data.len() % 4 != 0 {
+            data.push(0);
+        }
+        offset += data.len();
+    }
+    for &(_, ref data) in tables.iter() {
+        bytes.extend_from_slice(data);
+    }
+    bytes
+}
+
+fn checksum(data: &[u8]) -> u32 {
+    data.chunks(4).fold(0u32, |sum, chunk| {
+        let mut word = [0; 4];
+        word[..chunk.len()].copy_from_slice(chunk);
+        sum.wrapping_add(u32::from(word[0]) << 24 | u32::from(word[1]) << 16 | u32::from(word[2]) << 8 | u32::from(word[3]))
+    })
+}
+
+fn push_u16(bytes: &mut Vec<u8>, value: u16) {
+    bytes.push((value >> 8) as u8);
+    bytes.push(value as u8);
+}
+
+fn push_i16(bytes: &mut Vec<u8>, value: i16) {
+    push_u16(bytes, value as u16);
+}
+
+fn push_u32(bytes: &mut Vec<u8>, value: u32) {
+    push_u16(bytes, (value >> 16) as u16);
+    push_u16(bytes, value as u16);
+}
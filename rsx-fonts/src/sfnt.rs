@@ -0,0 +1,207 @@
+/*
+Copyright 2016 Mozilla
+Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+this file except in compliance with the License. You may obtain a copy of the
+License at http://www.apache.org/licenses/LICENSE-2.0
+Unless required by applicable law or agreed to in writing, software distributed
+under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+CONDITIONS OF ANY KIND, either express or implied. See the License for the
+specific language governing permissions and limitations under the License.
+*/
+
+// Reads just enough of TrueType and OpenType files to tell how many faces they
+// hold and how those are named, before handing them to a backend. Other
+// modules look up the tables they need through `face_table`.
+
+use config::{FontStyle, DEFAULT_FONT_WEIGHT};
+
+const COLLECTION_TAG: &[u8] = b"ttcf";
+
+const NAME_FAMILY: u16 = 1;
+const NAME_SUBFAMILY: u16 = 2;
+const NAME_TYPOGRAPHIC_FAMILY: u16 = 16;
+const NAME_TYPOGRAPHIC_SUBFAMILY: u16 = 17;
+
+const FS_SELECTION_ITALIC: u16 = 1;
+const FS_SELECTION_OBLIQUE: u16 = 1 << 9;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FaceInfo {
+    pub family: Option<String>,
+    pub subfamily: Option<String>,
+    pub weight: u16,
+    pub style: FontStyle
+}
+
+pub fn is_collection(bytes: &[u8]) -> bool {
+    bytes.starts_with(COLLECTION_TAG)
+}
+
+// Files which aren't collections hold a single face, even when they aren't
+// sfnt files at all, since backends may well read other formats.
+pub fn face_count(bytes: &[u8]) -> usize {
+    if is_collection(bytes) {
+        read_u32(bytes, 8).map_or(0, |count| count as usize)
+    } else {
+        1
+    }
+}
+
+// Names prefer the typographic family over the legacy one, which is limited
+// to four styles and so often carries the weight, e.g. "Noto Sans Light".
+pub fn face_info(bytes: &[u8], face_index: usize) -> Option<FaceInfo> {
+    let offset = face_offset(bytes, face_index)?;
+    let name = find_table(bytes, offset, b"name");
+    let os2 = find_table(bytes, offset, b"OS/2");
+    if name.is_none() && os2.is_none() {
+        return None;
+    }
+
+    let family = name.and_then(|name| read_name(name, NAME_TYPOGRAPHIC_FAMILY).or_else(|| read_name(name, NAME_FAMILY)));
+    let subfamily = name.and_then(|name| read_name(name, NAME_TYPOGRAPHIC_SUBFAMILY).or_else(|| read_name(name, NAME_SUBFAMILY)));
+    let weight = os2.and_then(|os2| read_u16(os2, 4))
+        .filter(|&weight| weight > 0)
+        .unwrap_or(DEFAULT_FONT_WEIGHT);
+    let style = match os2.and_then(|os2| read_u16(os2, 62)) {
+        Some(selection) if selection & FS_SELECTION_OBLIQUE != 0 => FontStyle::Oblique,
+        Some(selection) if selection & FS_SELECTION_ITALIC != 0 => FontStyle::Italic,
+        _ => FontStyle::Normal
+    };
+
+    Some(FaceInfo {
+        family,
+        subfamily,
+        weight,
+        style
+    })
+}
+
+pub(crate) fn face_table<'a>(bytes: &'a [u8], face_index: usize, tag: &[u8]) -> Option<&'a [u8]> {
+    find_table(bytes, face_offset(bytes, face_index)?, tag)
+}
+
+// Embedded color bitmaps, either CBDT strikes indexed by a CBLC table or sbix
+// images. COLR glyphs are layered outlines instead, see `palette::ColorTables`.
+pub fn has_color_bitmaps(bytes: &[u8], face_index: usize) -> bool {
+    let has_table = |tag: &[u8]| face_table(bytes, face_index, tag).is_some();
+    (has_table(b"CBDT") && has_table(b"CBLC")) || has_table(b"sbix")
+}
+
+fn face_offset(bytes: &[u8], face_index: usize) -> Option<usize> {
+    if is_collection(bytes) {
+        if face_index >= face_count(bytes) {
+            return None;
+        }
+        Some(read_u32(bytes, 12 + face_index * 4)? as usize)
+    } else if face_index == 0 {
+        Some(0)
+    } else {
+        None
+    }
+}
+
+fn find_table<'a>(bytes: &'a [u8], offset: usize, tag: &[u8]) -> Option<&'a [u8]> {
+    let num_tables = read_u16(bytes, offset + 4)? as usize;
+    (0..num_tables)
+        .map(|i| offset + 12 + i * 16)
+        .find(|&record| bytes.get(record..record + 4) == Some(tag))
+        .and_then(|record| {
+            let start = read_u32(bytes, record + 8)? as usize;
+            let len = read_u32(bytes, record + 12)? as usize;
+            bytes.get(start..start.checked_add(len)?)
+        })
+}
+
+// Prefers US English Windows names, then any Windows or Unicode ones, then
+// Macintosh ones, which are read as Latin-1.
+fn read_name(table: &[u8], name_id: u16) -> Option<String> {
+    let count = read_u16(table, 2)? as usize;
+    let storage = read_u16(table, 4)? as usize;
+
+    let mut best: Option<(u8, u16, usize, usize)> = None;
+    for i in 0..count {
+        let record = 6 + i * 12;
+        if read_u16(table, record + 6)? != name_id {
+            continue;
+        }
+        let platform = read_u16(table, record)?;
+        let language = read_u16(table, record + 4)?;
+        let rank = match (platform, language) {
+            (3, 0x409) => 0,
+            (3, _) | (0, _) => 1,
+            (1, _) => 2,
+            _ => continue
+        };
+        if best.map_or(true, |(best_rank, _, _, _)| rank < best_rank) {
+            let len = read_u16(table, record + 8)? as usize;
+            let start = storage + read_u16(table, record + 10)? as usize;
+            best = Some((rank, platform, start, len));
+        }
+    }
+
+    let (_, platform, start, len) = best?;
+    let raw = table.get(start..start + len)?;
+    let name = if platform == 1 {
+        raw.iter().map(|&b| b as char).collect()
+    } else {
+        let units: Vec<u16> = raw.chunks(2)
+            .filter(|unit| unit.len() == 2)
+            .map(|unit| u16::from(unit[0]) << 8 | u16::from(unit[1]))
+            .collect();
+        String::from_utf16(&units).ok()?
+    };
+    let name = name.trim();
+    if name.is_empty() {
+        None
+    } else {
+        Some(name.to_string())
+    }
+}
+
+// Vertical origins of CFF glyphs from a VORG table, in design units with y
+// growing upwards. Glyphs without a record of their own use the default.
+pub(crate) fn vertical_origin_y(table: &[u8], glyph_index: u32) -> Option<i16> {
+    let default = read_u16(table, 4)? as i16;
+    let (mut low, mut high) = (0, usize::from(read_u16(table, 6)?));
+    while low < high {
+        let middle = (low + high) / 2;
+        let record = 8 + middle * 4;
+        let record_glyph = u32::from(read_u16(table, record)?);
+        if record_glyph == glyph_index {
+            return Some(read_u16(table, record + 2)? as i16);
+        } else if record_glyph < glyph_index {
+            low = middle + 1;
+        } else {
+            high = middle;
+        }
+    }
+    Some(default)
+}
+
+// Superscript and subscript shifts and scales from the OS/2 table, as used by
+// `BaselinePosition`, in thousandths of an em. Shifts are upwards, whereas the
+// table stores subscript offsets downwards. Faces leaving them blank have none.
+pub(crate) fn script_metrics(bytes: &[u8], face_index: usize) -> Option<((i16, u16), (i16, u16))> {
+    let units_per_em = i32::from(read_u16(face_table(bytes, face_index, b"head")?, 18)?);
+    let os2 = face_table(bytes, face_index, b"OS/2")?;
+    // Faces with few units per em scale values past 16 bits, so they're clamped.
+    let thousandths = |value: u16| i32::from(value as i16) * 1000 / units_per_em.max(1);
+    let shift = |value: i32| value.max(i32::from(i16::min_value())).min(i32::from(i16::max_value())) as i16;
+    let scale = |value: i32| value.max(0).min(i32::from(u16::max_value())) as u16;
+    let subscript = (shift(-thousandths(read_u16(os2, 16)?)), scale(thousandths(read_u16(os2, 12)?)));
+    let superscript = (shift(thousandths(read_u16(os2, 24)?)), scale(thousandths(read_u16(os2, 20)?)));
+    if superscript.1 == 0 || subscript.1 == 0 {
+        return None;
+    }
+    Some((superscript, subscript))
+}
+
+pub(crate) fn read_u16(bytes: &[u8], offset: usize) -> Option<u16> {
+    let b = bytes.get(offset..offset + 2)?;
+    Some(u16::from(b[0]) << 8 | u16::from(b[1]))
+}
+
+pub(crate) fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    let b = bytes.get(offset..offset + 4)?;
+    Some(u32::from(b[0]) << 24 | u32::from(b[1]) << 16 | u32::from(b[2]) << 8 | u32::from(b[3]))
+}
@@ -0,0 +1,303 @@
+/*
+Copyright 2016 Mozilla
+Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+this file except in compliance with the License. You may obtain a copy of the
+License at http://www.apache.org/licenses/LICENSE-2.0
+Unless required by applicable law or agreed to in writing, software distributed
+under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+CONDITIONS OF ANY KIND, either express or implied. See the License for the
+specific language governing permissions and limitations under the License.
+*/
+
+use std::borrow::Cow;
+use std::hash::{Hash, Hasher};
+
+use fnv::FnvHasher;
+use unicode_normalization::{is_nfc_quick, is_nfd_quick, is_nfkc_quick, is_nfkd_quick, IsNormalized, UnicodeNormalization};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum CaseMapping {
+    Preserve,
+    Uppercase,
+    Lowercase
+}
+
+impl Default for CaseMapping {
+    fn default() -> Self {
+        CaseMapping::Preserve
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum NormalizationForm {
+    Preserve,
+    NFC,
+    NFD,
+    NFKC,
+    NFKD
+}
+
+// Composed text is what fonts map most reliably, and it makes canonically
+// equivalent strings shape (and hit the shaped text caches) identically.
+impl Default for NormalizationForm {
+    fn default() -> Self {
+        NormalizationForm::NFC
+    }
+}
+
+impl NormalizationForm {
+    // Borrows the text when it's already normalized.
+    pub fn normalize<'a>(self, text: &'a str) -> Cow<'a, str> {
+        let quick_check = match self {
+            NormalizationForm::Preserve => return Cow::Borrowed(text),
+            NormalizationForm::NFC => is_nfc_quick(text.chars()),
+            NormalizationForm::NFD => is_nfd_quick(text.chars()),
+            NormalizationForm::NFKC => is_nfkc_quick(text.chars()),
+            NormalizationForm::NFKD => is_nfkd_quick(text.chars())
+        };
+        if quick_check == IsNormalized::Yes {
+            return Cow::Borrowed(text);
+        }
+        Cow::Owned(match self {
+            NormalizationForm::NFD => text.nfd().collect(),
+            NormalizationForm::NFKC => text.nfkc().collect(),
+            NormalizationForm::NFKD => text.nfkd().collect(),
+            _ => text.nfc().collect()
+        })
+    }
+
+    // Maps a cut made `len` bytes into the normalized text back to the text it
+    // came from. Returns the byte lengths of the longest prefix of `text` whose
+    // normalized form is a prefix of the normalized text no longer than `len`,
+    // and of that normalized form, so both sides of the cut show the same chars.
+    pub fn prefix_lens(self, text: &str, len: usize) -> (usize, usize) {
+        let normalized = self.normalize(text);
+        if let Cow::Borrowed(_) = normalized {
+            return (len, len);
+        }
+        let mut lens = (0, 0);
+        for (offset, c) in text.char_indices() {
+            let end = offset + c.len_utf8();
+            let prefix = self.normalize(&text[..end]);
+            if !normalized.starts_with(&*prefix) {
+                continue;
+            }
+            if prefix.len() > len {
+                break;
+            }
+            lens = (end, prefix.len());
+        }
+        lens
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum NumeralShaping {
+    Preserve,
+    Western,
+    ArabicIndic,
+    EasternArabicIndic,
+    Locale
+}
+
+impl Default for NumeralShaping {
+    fn default() -> Self {
+        NumeralShaping::Preserve
+    }
+}
+
+// Which shaper lays out an instance's runs. HarfBuzz is needed for complex
+// scripts and for kerning only found in GPOS tables, but costs a lot more
+// than summing advances, which is enough for most Latin text.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ShapingStrategy {
+    Auto,
+    Simple,
+    Complex
+}
+
+impl Default for ShapingStrategy {
+    fn default() -> Self {
+        ShapingStrategy::Auto
+    }
+}
+
+// Runs laid out by each shaper, not counting those served from caches.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ShapingCounters {
+    pub simple_runs: u64,
+    pub complex_runs: u64
+}
+
+// An OpenType feature requested for an instance's runs, e.g. `tnum` for
+// tabular numerals, or `liga` turned off for code. A value of 0 turns the
+// feature off, others pick among its alternates, 1 being the usual one.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct FontFeature {
+    pub tag: [u8; 4],
+    pub value: u32
+}
+
+impl FontFeature {
+    pub fn new(tag: &[u8; 4], value: u32) -> Self {
+        FontFeature { tag: *tag, value }
+    }
+
+    pub fn enabled(tag: &[u8; 4]) -> Self {
+        FontFeature::new(tag, 1)
+    }
+
+    pub fn disabled(tag: &[u8; 4]) -> Self {
+        FontFeature::new(tag, 0)
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.value != 0
+    }
+
+    pub fn tag_u32(&self) -> u32 {
+        u32::from(self.tag[0]) << 24 | u32::from(self.tag[1]) << 16 | u32::from(self.tag[2]) << 8 | u32::from(self.tag[3])
+    }
+}
+
+// Identifies a feature list in shaping keys, 0 standing for no features.
+pub(crate) fn features_hash(features: &[FontFeature]) -> u64 {
+    if features.is_empty() {
+        return 0;
+    }
+    let mut hasher = FnvHasher::default();
+    features.hash(&mut hasher);
+    hasher.finish()
+}
+
+// Latin and common punctuation have no marks to position, no contextual forms
+// and no right to left text, so they shape correctly by summing advances.
+pub fn is_simple_script(text: &str) -> bool {
+    text.chars().all(|c| match c {
+        '\u{0}'..='\u{2FF}' | '\u{1E00}'..='\u{1EFF}' => true,
+        '\u{2010}'..='\u{2027}' | '\u{2030}'..='\u{205E}' | '\u{20A0}'..='\u{20BF}' => true,
+        _ => false
+    })
+}
+
+// Where a span sits relative to the baseline of the text around it. Spans off
+// the baseline are shaped with a smaller instance, see `shape_span_h`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum BaselinePosition {
+    Baseline,
+    // Sized and raised as the face's OS/2 table says, if it says.
+    Superscript,
+    Subscript,
+    // Raised by `shift` and scaled by `scale`, both in thousandths of an em.
+    Shifted { shift: i16, scale: u16 }
+}
+
+impl Default for BaselinePosition {
+    fn default() -> Self {
+        BaselinePosition::Baseline
+    }
+}
+
+impl BaselinePosition {
+    // Roughly what most faces with OS/2 script metrics specify.
+    pub const SUPERSCRIPT_DEFAULTS: (i16, u16) = (350, 650);
+    pub const SUBSCRIPT_DEFAULTS: (i16, u16) = (-140, 650);
+
+    // Shift and scale in thousandths of an em, given the face's superscript
+    // and subscript metrics, when it has them.
+    pub fn resolve(&self, face_metrics: Option<((i16, u16), (i16, u16))>) -> (i16, u16) {
+        match *self {
+            BaselinePosition::Baseline => (0, 1000),
+            BaselinePosition::Superscript => face_metrics.map_or(Self::SUPERSCRIPT_DEFAULTS, |metrics| metrics.0),
+            BaselinePosition::Subscript => face_metrics.map_or(Self::SUBSCRIPT_DEFAULTS, |metrics| metrics.1),
+            BaselinePosition::Shifted { shift, scale } => (shift, scale)
+        }
+    }
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ShapingOptions {
+    pub locale: Option<String>,
+    pub case_mapping: CaseMapping,
+    pub numerals: NumeralShaping,
+    #[serde(default)]
+    pub baseline_position: BaselinePosition
+}
+
+impl ShapingOptions {
+    pub fn with_locale<T>(locale: T) -> Self
+    where
+        T: Into<String>
+    {
+        ShapingOptions {
+            locale: Some(locale.into()),
+            ..ShapingOptions::default()
+        }
+    }
+
+    pub fn is_identity(&self) -> bool {
+        self.case_mapping == CaseMapping::Preserve && self.resolved_numerals() == NumeralShaping::Preserve
+    }
+
+    // Applied to the text before it reaches the shaper.
+    pub fn transform<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        if self.is_identity() {
+            return Cow::Borrowed(text);
+        }
+
+        let turkic = self.language().map_or(false, |l| l == "tr" || l == "az");
+        let numerals = self.resolved_numerals();
+        let mut transformed = String::with_capacity(text.len());
+
+        for c in text.chars() {
+            let c = shape_numeral(c, numerals);
+            match self.case_mapping {
+                CaseMapping::Preserve => transformed.push(c),
+                CaseMapping::Uppercase => match c {
+                    'i' if turkic => transformed.push('\u{130}'),
+                    c => transformed.extend(c.to_uppercase())
+                },
+                CaseMapping::Lowercase => match c {
+                    'I' if turkic => transformed.push('\u{131}'),
+                    '\u{130}' if turkic => transformed.push('i'),
+                    c => transformed.extend(c.to_lowercase())
+                }
+            }
+        }
+
+        Cow::Owned(transformed)
+    }
+
+    fn language(&self) -> Option<String> {
+        let locale = self.locale.as_ref()?;
+        let language = locale.split(|c| c == '-' || c == '_').next()?;
+        Some(language.to_lowercase())
+    }
+
+    fn resolved_numerals(&self) -> NumeralShaping {
+        match self.numerals {
+            NumeralShaping::Locale => match self.language() {
+                Some(ref l) if l == "ar" => NumeralShaping::ArabicIndic,
+                Some(ref l) if l == "fa" || l == "ur" => NumeralShaping::EasternArabicIndic,
+                _ => NumeralShaping::Preserve
+            },
+            numerals => numerals
+        }
+    }
+}
+
+fn shape_numeral(c: char, numerals: NumeralShaping) -> char {
+    let digit = match c {
+        '0'..='9' => c as u32 - '0' as u32,
+        '\u{660}'..='\u{669}' => c as u32 - 0x660,
+        '\u{6F0}'..='\u{6F9}' => c as u32 - 0x6F0,
+        _ => return c
+    };
+    let zero = match numerals {
+        NumeralShaping::Preserve | NumeralShaping::Locale => return c,
+        NumeralShaping::Western => '0' as u32,
+        NumeralShaping::ArabicIndic => 0x660,
+        NumeralShaping::EasternArabicIndic => 0x6F0
+    };
+    ::std::char::from_u32(zero + digit).unwrap_or(c)
+}
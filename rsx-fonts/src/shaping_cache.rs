@@ -0,0 +1,163 @@
+/*
+Copyright 2016 Mozilla
+Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+this file except in compliance with the License. You may obtain a copy of the
+License at http://www.apache.org/licenses/LICENSE-2.0
+Unless required by applicable law or agreed to in writing, software distributed
+under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+CONDITIONS OF ANY KIND, either express or implied. See the License for the
+specific language governing permissions and limitations under the License.
+*/
+
+// Shaped text is cached per instance by default, or in one cache shared by all
+// instances of a font cache. Entries are keyed on the instance they were shaped
+// with as well as the text, so instances can't read each other's runs.
+
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+use fnv::FnvHashMap;
+use rsx_resource_updates::types::GlyphTransform;
+
+use palette::PaletteSelection;
+use types::{Dpi, FontId, FontInstance, FontSizeUnit, GlyphStore};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct ShapingKey {
+    pub font_id: FontId,
+    pub size: u32,
+    pub dpi: Dpi,
+    pub unit: FontSizeUnit,
+    pub transform: GlyphTransform,
+    pub palette: PaletteSelection,
+    pub features: u64,
+    pub vertical: bool,
+    pub text: u64
+}
+
+impl ShapingKey {
+    pub fn for_instance<FontKey, FontInstanceKey, GlyphInstance>(
+        instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>,
+        text: u64,
+        vertical: bool
+    ) -> Self {
+        ShapingKey {
+            font_id: instance.font_id(),
+            size: instance.size(),
+            dpi: instance.dpi(),
+            unit: instance.unit(),
+            transform: instance.transform(),
+            palette: instance.palette(),
+            features: instance.features_hash.get(),
+            vertical,
+            text
+        }
+    }
+
+    // Runs shaped with any of the instance's features, so that discarding its
+    // runs drops those shaped with previous features too.
+    pub fn is_for<FontKey, FontInstanceKey, GlyphInstance>(&self, instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>) -> bool {
+        self.font_id == instance.font_id() && self.size == instance.size() && self.dpi == instance.dpi() && self.unit == instance.unit()
+            && self.transform == instance.transform() && self.palette == instance.palette()
+    }
+}
+
+// A handle to shaped runs, cloned to share them.
+pub struct ShapingCache<FontKey, FontInstanceKey, GlyphInstance>(Rc<RefCell<FnvHashMap<ShapingKey, GlyphStore<FontKey, FontInstanceKey, GlyphInstance>>>>);
+
+impl<FontKey, FontInstanceKey, GlyphInstance> ShapingCache<FontKey, FontInstanceKey, GlyphInstance> {
+    pub fn new() -> Self {
+        ShapingCache(Rc::new(RefCell::new(FnvHashMap::default())))
+    }
+
+    pub fn is_shared_with(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn clear(&self) {
+        self.0.borrow_mut().clear();
+    }
+
+    pub fn contains(&self, key: &ShapingKey) -> bool {
+        self.0.borrow().contains_key(key)
+    }
+
+    pub fn get(&self, key: &ShapingKey) -> Option<GlyphStore<FontKey, FontInstanceKey, GlyphInstance>>
+    where
+        FontKey: Clone,
+        FontInstanceKey: Clone,
+        GlyphInstance: Clone
+    {
+        self.0.borrow().get(key).cloned()
+    }
+
+    pub fn insert(&self, key: ShapingKey, store: GlyphStore<FontKey, FontInstanceKey, GlyphInstance>) {
+        self.0.borrow_mut().insert(key, store);
+    }
+
+    // Runs the closure with the store cached for the key, if any, without cloning it.
+    pub fn with<F, R>(&self, key: &ShapingKey, f: F) -> Option<R>
+    where
+        F: FnOnce(&GlyphStore<FontKey, FontInstanceKey, GlyphInstance>) -> R
+    {
+        self.0.borrow().get(key).map(f)
+    }
+
+    pub fn entries_for<A, B, C>(&self, instance: &FontInstance<A, B, C>) -> usize {
+        self.0
+            .borrow()
+            .keys()
+            .filter(|key| key.is_for(instance))
+            .count()
+    }
+
+    // Runs of a removed face hold its keys, which aren't valid once it's added again.
+    pub fn discard_font(&self, font_id: FontId) {
+        self.0.borrow_mut().retain(|key, _| key.font_id != font_id);
+    }
+
+    // Only discards the instance's runs, leaving those of instances sharing the cache.
+    pub fn discard<A, B, C>(&self, instance: &FontInstance<A, B, C>, horizontal: bool, vertical: bool) {
+        self.0
+            .borrow_mut()
+            .retain(|key, _| !(key.is_for(instance) && if key.vertical { vertical } else { horizontal }));
+    }
+}
+
+impl<FontKey, FontInstanceKey, GlyphInstance> Clone for ShapingCache<FontKey, FontInstanceKey, GlyphInstance> {
+    fn clone(&self) -> Self {
+        ShapingCache(Rc::clone(&self.0))
+    }
+}
+
+// Shaped runs are derived data, so only compare which runs are cached.
+impl<FontKey, FontInstanceKey, GlyphInstance> PartialEq for ShapingCache<FontKey, FontInstanceKey, GlyphInstance> {
+    fn eq(&self, other: &Self) -> bool {
+        if self.is_shared_with(other) {
+            return true;
+        }
+        let (entries, other) = (self.0.borrow(), other.0.borrow());
+        entries.len() == other.len() && entries.keys().all(|key| other.contains_key(key))
+    }
+}
+
+impl<FontKey, FontInstanceKey, GlyphInstance> Default for ShapingCache<FontKey, FontInstanceKey, GlyphInstance> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<FontKey, FontInstanceKey, GlyphInstance> fmt::Debug for ShapingCache<FontKey, FontInstanceKey, GlyphInstance> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ShapingCache({} entries)", self.len())
+    }
+}
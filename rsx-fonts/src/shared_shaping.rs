@@ -0,0 +1,214 @@
+/*
+Copyright 2016 Mozilla
+Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+this file except in compliance with the License. You may obtain a copy of the
+License at http://www.apache.org/licenses/LICENSE-2.0
+Unless required by applicable law or agreed to in writing, software distributed
+under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+CONDITIONS OF ANY KIND, either express or implied. See the License for the
+specific language governing permissions and limitations under the License.
+*/
+
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
+
+use fnv::{FnvHashMap, FnvHasher};
+use rsx_resource_updates::types::GlyphTransform;
+
+use backend::ShapedRun;
+use glyph_load::GlyphLoadPolicy;
+use shaping::ShapingStrategy;
+use types::{Dpi, FontInstance, FontSizeUnit};
+
+pub const DEFAULT_SHARED_SHAPING_CAPACITY: usize = 4096;
+
+lazy_static! {
+    static ref GLOBAL: SharedShapingCache = SharedShapingCache::with_capacity(DEFAULT_SHARED_SHAPING_CAPACITY);
+}
+
+// Identifies a shaped run by the face bytes and rasterization parameters it was
+// shaped with, rather than by the keys of the cache that shaped it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct SharedShapingKey {
+    face: u64,
+    size: u32,
+    dpi: Dpi,
+    unit: FontSizeUnit,
+    transform: GlyphTransform,
+    load_policy: GlyphLoadPolicy,
+    ink_bounds: bool,
+    kerning: bool,
+    linear_advances: bool,
+    shaping_strategy: ShapingStrategy,
+    features: u64,
+    vertical: bool,
+    text: u64
+}
+
+impl SharedShapingKey {
+    // Instances created outside of a font cache have no face identity and aren't shared.
+    pub fn for_instance<FontKey, FontInstanceKey, GlyphInstance>(
+        instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>,
+        text: u64,
+        vertical: bool
+    ) -> Option<Self> {
+        let face = instance.face_hash.get();
+        if face == 0 {
+            return None;
+        }
+        Some(SharedShapingKey {
+            face,
+            size: instance.size(),
+            dpi: instance.dpi(),
+            unit: instance.unit(),
+            transform: instance.transform(),
+            load_policy: instance.load_policy(),
+            ink_bounds: instance.ink_bounds(),
+            kerning: instance.kerning(),
+            linear_advances: instance.linear_advances(),
+            shaping_strategy: instance.shaping_strategy(),
+            features: instance.features_hash.get(),
+            vertical,
+            text
+        })
+    }
+}
+
+pub fn face_hash(bytes: &[u8], face_index: usize) -> u64 {
+    let mut hasher = FnvHasher::default();
+    hasher.write(bytes);
+    face_index.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Copy, Clone)]
+pub struct SharedShapingStats {
+    pub entries: usize,
+    pub hits: u64,
+    pub misses: u64
+}
+
+#[derive(Debug, Default)]
+struct Entries {
+    capacity: usize,
+    clock: u64,
+    hits: u64,
+    misses: u64,
+    runs: FnvHashMap<SharedShapingKey, (u64, ShapedRun)>
+}
+
+impl Entries {
+    fn stats(&self) -> SharedShapingStats {
+        SharedShapingStats {
+            entries: self.runs.len(),
+            hits: self.hits,
+            misses: self.misses
+        }
+    }
+
+    fn evict_least_recently_used(&mut self) {
+        let oldest = self.runs
+            .iter()
+            .min_by_key(|&(_, &(last_used, _))| last_used)
+            .map(|(&key, _)| key);
+        if let Some(key) = oldest {
+            self.runs.remove(&key);
+        }
+    }
+}
+
+// Shaped runs shared by every font cache in the process, so that resource groups
+// showing the same text with the same face don't each shape it again. A poisoned
+// lock is treated as an empty cache.
+#[derive(Debug)]
+pub struct SharedShapingCache(Mutex<Entries>);
+
+impl SharedShapingCache {
+    pub fn with_capacity(capacity: usize) -> Self {
+        SharedShapingCache(Mutex::new(Entries {
+            capacity,
+            ..Entries::default()
+        }))
+    }
+
+    pub fn global() -> &'static SharedShapingCache {
+        &GLOBAL
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.0.lock().map(|entries| entries.capacity).unwrap_or(0)
+    }
+
+    pub fn set_capacity(&self, capacity: usize) {
+        if let Ok(mut entries) = self.0.lock() {
+            entries.capacity = capacity;
+            while entries.runs.len() > capacity {
+                entries.evict_least_recently_used();
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.lock().map(|entries| entries.runs.len()).unwrap_or(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn clear(&self) {
+        if let Ok(mut entries) = self.0.lock() {
+            entries.runs.clear();
+        }
+    }
+
+    pub fn stats(&self) -> SharedShapingStats {
+        self.0
+            .lock()
+            .map(|entries| entries.stats())
+            .unwrap_or_default()
+    }
+
+    pub fn reset_stats(&self) -> SharedShapingStats {
+        self.0
+            .lock()
+            .map(|mut entries| {
+                let stats = entries.stats();
+                entries.hits = 0;
+                entries.misses = 0;
+                stats
+            })
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn get(&self, key: &SharedShapingKey) -> Option<ShapedRun> {
+        let mut entries = self.0.lock().ok()?;
+        entries.clock += 1;
+        let clock = entries.clock;
+        let run = entries.runs.get_mut(key).map(|entry| {
+            entry.0 = clock;
+            ShapedRun::clone(&entry.1)
+        });
+        match run {
+            Some(_) => entries.hits += 1,
+            None => entries.misses += 1
+        }
+        run
+    }
+
+    pub(crate) fn insert(&self, key: SharedShapingKey, run: ShapedRun) {
+        let mut entries = match self.0.lock() {
+            Ok(entries) => entries,
+            Err(_) => return
+        };
+        if entries.capacity == 0 {
+            return;
+        }
+        if !entries.runs.contains_key(&key) && entries.runs.len() >= entries.capacity {
+            entries.evict_least_recently_used();
+        }
+        entries.clock += 1;
+        let clock = entries.clock;
+        entries.runs.insert(key, (clock, run));
+    }
+}
@@ -0,0 +1,51 @@
+/*
+Copyright 2016 Mozilla
+Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+this file except in compliance with the License. You may obtain a copy of the
+License at http://www.apache.org/licenses/LICENSE-2.0
+Unless required by applicable law or agreed to in writing, software distributed
+under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+CONDITIONS OF ANY KIND, either express or implied. See the License for the
+specific language governing permissions and limitations under the License.
+*/
+
+use rsx_resource_updates::traits::TGlyphInstanceExt;
+
+use types::GlyphStore;
+
+// Layout compatible with `SkPoint`.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SkiaPoint {
+    pub x: f32,
+    pub y: f32
+}
+
+// Glyph ids and positions as accepted by `SkTextBlobBuilder::allocRunPos`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SkiaGlyphRun {
+    pub glyphs: Vec<u16>,
+    pub positions: Vec<SkiaPoint>
+}
+
+impl<FontKey, FontInstanceKey, GlyphInstance> GlyphStore<FontKey, FontInstanceKey, GlyphInstance>
+where
+    GlyphInstance: TGlyphInstanceExt
+{
+    pub fn to_skia_glyph_run(&self, origin: (f32, f32)) -> SkiaGlyphRun {
+        let glyphs = &self.glyphs.0;
+        SkiaGlyphRun {
+            glyphs: glyphs
+                .iter()
+                .map(|glyph| glyph.glyph_index() as u16)
+                .collect(),
+            positions: glyphs
+                .iter()
+                .map(|glyph| SkiaPoint {
+                    x: origin.0 + glyph.x_64() as f32 / 64.0,
+                    y: origin.1 + glyph.y_64() as f32 / 64.0
+                })
+                .collect()
+        }
+    }
+}
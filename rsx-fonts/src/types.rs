@@ -9,24 +9,79 @@ CONDITIONS OF ANY KIND, either express or implied. See the License for the
 specific language governing permissions and limitations under the License.
 */
 
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::collections::hash_map::Entry;
+use std::fmt::{Debug, Write};
 use std::hash::Hash;
 use std::hash::Hasher;
-use std::ops::Deref;
+use std::ops::{Deref, Range};
+use std::path::Path;
+use std::ptr;
 use std::rc::Rc;
-
-use fnv::{FnvHashMap, FnvHasher};
-use rsx_shared::consts::{DEFAULT_FONT_DPI, DEFAULT_FONT_SIZE};
+use std::sync::Arc;
+
+use base64_util;
+use fnv::{FnvHashMap, FnvHashSet, FnvHasher};
+use rsx_files::types::SharedFiles;
+use rsx_resource_updates::cell::SharedCell;
+#[cfg(feature = "key-balance-watchdog")]
+use rsx_resource_updates::ledger::KeyLedger;
+use rsx_resource_updates::pixels;
+use rsx_resource_updates::quota::{self, ResourceExpiry};
+use rsx_resource_updates::stats::{ErrorStats, IdStats};
 use rsx_shared::traits::{TEncodedFont, TFontCache, TFontInstanceKey, TFontKey, TFontKeysAPI, TGlyphInstance, TGlyphStore};
 use uuid::Uuid;
 
 use error::{FontError, Result};
-use font_context::FontContext;
+use export;
+use placeholder::{self, PLACEHOLDER_FAMILY};
+use sfnt;
+use shaping;
+#[cfg(feature = "shared-shaping-cache")]
+use shared_shaping;
+pub use arena::{ShapingArena, TransientRun};
+pub use atlas::{AtlasPlacement, AtlasSlotId, AtlasUvRect, AtlasVariantStats, GlyphAtlas, SubpixelOffset};
+pub use backend::{MockCharClass, MockFontBackend, TFontBackend};
+pub use bidi::{DirectionRun, TextDirection};
+pub use blur::MAX_BLUR_RADIUS;
+pub use browser::{BrowserFontBackend, TFontFaceHost};
+pub use config::{FontConfig, FontDescriptor, FontFaceConfig, FontStyle};
+pub use coverage::{BlockCoverage, CoverageReport, Script, ScriptCoverage, UnicodeBlock};
+pub use font_context::FontContext;
+pub use font_thread::FontThread;
 
 pub use decoded::DecodedFont;
 pub use encoded::EncodedFont;
-pub use rsx_shared::types::{FontEncodedData, FontInstanceResourceData, FontResourceData};
+pub use fixed::Fixed26_6;
+pub use metrics::{FontMetricsTable, GlyphMetrics, MetricsFontBackend};
+pub use packing::AtlasRect;
+pub use palette::{ColorPalette, PaletteColor, PaletteSelection};
+pub use glyph_load::{GlyphLoadOptions, GlyphLoadPolicy, GlyphLoadPurpose, LcdFilter, LoadFlag, RenderTarget};
+pub use paragraph::{InlineAlignment, InlineObject, InlineObjectPlacement, LineLayout, ParagraphCache, ParagraphKey, ParagraphLayout};
+pub use placeholder::PlaceholderMetrics;
+pub use rsx_resource_updates::display::{DisplayProfile, SubpixelLayout};
+pub use rsx_resource_updates::pixels::AlphaMode;
+pub use rsx_resource_updates::quota::{ExpiryPolicy, ResourcePriority};
+pub use rsx_resource_updates::shmem::{SharedMemory, SharedMemoryDescriptor, SharedMemoryKind};
+pub use rsx_resource_updates::units::{Dpi, Pt, Px, Scale};
+pub use rsx_resource_updates::snapshot::{KeyDiff, KeySnapshot};
+pub use rsx_resource_updates::stats::{DeferredError, ErrorCategory, ErrorCounters, IdCounters};
+pub use shaping::{
+    BaselinePosition,
+    CaseMapping,
+    FontFeature,
+    NormalizationForm,
+    NumeralShaping,
+    ShapingCounters,
+    ShapingOptions,
+    ShapingStrategy
+};
+pub use shaping_cache::{ShapingCache, ShapingKey};
+#[cfg(feature = "shared-shaping-cache")]
+pub use shared_shaping::{SharedShapingCache, SharedShapingStats};
+pub use rsx_resource_updates::traits::{TFontKeysAPIExt, TGlyphInstanceExt, TGlyphStoreExt};
+pub use rsx_resource_updates::types::{GlyphRunSource, GlyphSourceMode, GlyphTransform};
+pub use rsx_shared::types::{FontEncodedData, FontInstanceResourceData, FontResourceData, ImagePixelFormat};
 
 pub type TFontInstance<A> =
     FontInstance<<A as TFontKeysAPI>::FontKey, <A as TFontKeysAPI>::FontInstanceKey, <A as TFontKeysAPI>::GlyphInstance>;
@@ -35,43 +90,167 @@ pub type RcFontInstance<A> = Rc<TFontInstance<A>>;
 pub type FontInstanceRef<'a, A> = &'a TFontInstance<A>;
 pub type FontInstanceRefMut<'a, A> = &'a mut TFontInstance<A>;
 
-#[derive(Default, Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
-pub struct FontId(u64);
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct FontId {
+    origin: Option<u64>,
+    font_name: u64
+}
 
 impl FontId {
     pub fn new<P>(font_name: P) -> Self
     where
         P: AsRef<str>
     {
-        let mut hasher = FnvHasher::default();
-        hasher.write(font_name.as_ref().as_bytes());
-        FontId(hasher.finish())
+        FontId {
+            origin: None,
+            font_name: hash_str(font_name)
+        }
+    }
+
+    pub fn with_origin<O, P>(origin: O, font_name: P) -> Self
+    where
+        O: AsRef<str>,
+        P: AsRef<str>
+    {
+        FontId {
+            origin: Some(hash_str(origin)),
+            font_name: hash_str(font_name)
+        }
     }
 
     pub fn uuid() -> Self {
         let mut hasher = FnvHasher::default();
         Uuid::new_v4().hash(&mut hasher);
-        FontId(hasher.finish())
+        FontId {
+            origin: None,
+            font_name: hasher.finish()
+        }
+    }
+
+    pub fn has_origin(&self) -> bool {
+        self.origin.is_some()
+    }
+
+    pub fn is_from_origin<O>(&self, origin: O) -> bool
+    where
+        O: AsRef<str>
+    {
+        self.origin == Some(hash_str(origin))
+    }
+}
+
+fn hash_str<T>(value: T) -> u64
+where
+    T: AsRef<str>
+{
+    let mut hasher = FnvHasher::default();
+    hasher.write(value.as_ref().as_bytes());
+    hasher.finish()
+}
+
+fn saturate_i32(value: i64) -> i32 {
+    if value > i64::from(i32::max_value()) {
+        i32::max_value()
+    } else if value < i64::from(i32::min_value()) {
+        i32::min_value()
+    } else {
+        value as i32
+    }
+}
+
+// Faces without a name table are named after the file.
+fn face_family(bytes: &[u8], path: &Path, face_index: usize) -> Option<String> {
+    match sfnt::face_info(bytes, face_index).and_then(|info| info.family) {
+        Some(family) => Some(family),
+        None => path.file_stem().map(|stem| stem.to_string_lossy().into_owned())
+    }
+}
+
+// Faces are named after their family, unless other faces of the file share
+// it, e.g. the styles in a collection of one family. Those are named after
+// their subfamily too, or their index where that's shared as well.
+fn face_font_name(bytes: &[u8], path: &Path, face_index: usize, family: &str) -> String {
+    let siblings: Vec<_> = (0..sfnt::face_count(bytes))
+        .filter(|&index| index != face_index && face_family(bytes, path, index).map_or(false, |other| other == family))
+        .collect();
+    if siblings.is_empty() {
+        return family.to_string();
+    }
+    let subfamily_of = |index: usize| sfnt::face_info(bytes, index).and_then(|info| info.subfamily);
+    match subfamily_of(face_index) {
+        Some(ref subfamily) if siblings.iter().all(|&index| subfamily_of(index).as_ref() != Some(subfamily)) => {
+            format!("{} {}", family, subfamily)
+        }
+        _ => format!("{} #{}", family, face_index)
     }
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+// Reuses the file if the cache already holds it.
+fn load_font_file(files: &SharedFiles, path: &Path) -> Result<Rc<Vec<u8>>> {
+    if let Ok(bytes) = files.borrow().get_file(path) {
+        return Ok(bytes);
+    }
+    files.borrow_mut().add_file(path)?;
+    Ok(files.borrow().get_file(path)?)
+}
+
+// Resolution at which a size in points equals the same size in pixels.
+pub const PIXEL_SIZE_DPI: Dpi = Dpi::PIXEL;
+
+// Kerning pairs memoized per instance. Custom layouts query the same few pairs
+// over and over, so the cache simply starts over once it fills up.
+pub const KERNING_PAIRS_CACHE_CAPACITY: usize = 1024;
+
+// Sizes of runs measured without being shaped, memoized per instance by the
+// hash of their text, and started over once full like kerning pairs.
+pub const MEASURED_TEXT_CACHE_CAPACITY: usize = 1024;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum FontSizeUnit {
+    Points,
+    Pixels
+}
+
+impl Default for FontSizeUnit {
+    fn default() -> Self {
+        FontSizeUnit::Points
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub struct FontInstanceId {
+    // Same as the origin of the font ids registering the family.
+    #[serde(default)]
+    origin: Option<u64>,
     family_name: u64,
+    // Set for the faces of a family added after its first one, e.g. its bold,
+    // to the name of their font id.
+    #[serde(default)]
+    face: Option<u64>,
+    // In points or pixels, depending on the unit.
     size: u32,
-    dpi: u32
+    dpi: Dpi,
+    transform: GlyphTransform,
+    unit: FontSizeUnit,
+    #[serde(default)]
+    palette: PaletteSelection
 }
 
 impl FontInstanceId {
-    pub fn from_family_hash(family_name: u64, size: u32, dpi: u32) -> Self {
+    pub fn from_family_hash(family_name: u64, size: Pt, dpi: Dpi) -> Self {
         FontInstanceId {
+            origin: None,
             family_name,
-            size,
-            dpi
+            face: None,
+            size: size.0,
+            dpi,
+            transform: GlyphTransform::IDENTITY,
+            unit: FontSizeUnit::Points,
+            palette: PaletteSelection::default()
         }
     }
 
-    pub fn from_family_str<T>(family_name: T, size: u32, dpi: u32) -> Self
+    pub fn from_family_str<T>(family_name: T, size: Pt, dpi: Dpi) -> Self
     where
         T: AsRef<str>
     {
@@ -80,12 +259,158 @@ impl FontInstanceId {
         Self::from_family_hash(hasher.finish(), size, dpi)
     }
 
-    pub fn resize(&self, size: u32) -> Self {
-        Self::from_family_hash(self.family_name, size, self.dpi)
+    pub fn from_family_px<T>(family_name: T, px: Px) -> Self
+    where
+        T: AsRef<str>
+    {
+        Self::from_family_str(family_name, Pt::DEFAULT, PIXEL_SIZE_DPI).resize_px(px)
+    }
+
+    pub fn resize(&self, size: Pt) -> Self {
+        self.resize_dpi(size, self.dpi)
+    }
+
+    // Ids resized in points are point sized, whichever unit they had before.
+    pub fn resize_dpi(&self, size: Pt, dpi: Dpi) -> Self {
+        FontInstanceId {
+            size: size.0,
+            dpi,
+            unit: FontSizeUnit::Points,
+            ..*self
+        }
+    }
+
+    pub fn resize_px(&self, size: Px) -> Self {
+        FontInstanceId {
+            size: size.0,
+            dpi: PIXEL_SIZE_DPI,
+            unit: FontSizeUnit::Pixels,
+            ..*self
+        }
+    }
+
+    pub fn with_transform(&self, transform: GlyphTransform) -> Self {
+        FontInstanceId { transform, ..*self }
+    }
+
+    // Picks the family registered by fonts with the same origin, see
+    // `FontId::with_origin`.
+    pub fn with_origin<O>(&self, origin: O) -> Self
+    where
+        O: AsRef<str>
+    {
+        FontInstanceId {
+            origin: Some(hash_str(origin)),
+            ..*self
+        }
+    }
+
+    fn with_origin_of(&self, font_id: FontId) -> Self {
+        FontInstanceId {
+            origin: font_id.origin,
+            ..*self
+        }
+    }
+
+    fn with_face_of(&self, font_id: FontId) -> Self {
+        FontInstanceId {
+            face: Some(font_id.font_name),
+            ..*self
+        }
+    }
+
+    // Ids without an origin also find the face of any origin, as with
+    // `resolve_origin`, whatever their size.
+    fn finds_face_of(&self, other: &FontInstanceId) -> bool {
+        self.family_name == other.family_name && self.face == other.face && (self.origin.is_none() || self.origin == other.origin)
+    }
+
+    pub fn has_origin(&self) -> bool {
+        self.origin.is_some()
+    }
+
+    pub fn is_from_origin<O>(&self, origin: O) -> bool
+    where
+        O: AsRef<str>
+    {
+        self.origin == Some(hash_str(origin))
+    }
+
+    // Only color glyphs are affected, so instances of faces without color
+    // glyphs rasterize the same regardless of the palette.
+    pub fn with_palette(&self, palette: PaletteSelection) -> Self {
+        FontInstanceId { palette, ..*self }
+    }
+
+    // Pixel sizes are stored at `PIXEL_SIZE_DPI`, where one point is one pixel, so every
+    // backend scaling by `size * dpi / 72` rasterizes them at exactly `size` pixels.
+    pub fn with_unit(&self, unit: FontSizeUnit) -> Self {
+        let dpi = match unit {
+            FontSizeUnit::Points => self.dpi,
+            FontSizeUnit::Pixels => PIXEL_SIZE_DPI
+        };
+        FontInstanceId { dpi, unit, ..*self }
+    }
+
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    pub fn dpi(&self) -> Dpi {
+        self.dpi
+    }
+
+    pub fn transform(&self) -> GlyphTransform {
+        self.transform
+    }
+
+    pub fn unit(&self) -> FontSizeUnit {
+        self.unit
+    }
+
+    pub fn palette(&self) -> PaletteSelection {
+        self.palette
     }
+}
+
+// Keys of the fonts and font instances the renderer holds, see `KeySnapshot`.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct FontKeySnapshot<FontKey, FontInstanceKey> {
+    pub fonts: KeySnapshot<FontId, FontKey>,
+    pub instances: KeySnapshot<FontInstanceId, FontInstanceKey>
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct FontInstanceInfo<FontKey, FontInstanceKey> {
+    pub instance_id: FontInstanceId,
+    pub font_id: FontId,
+    pub font_key: FontKey,
+    pub font_instance_key: FontInstanceKey,
+    pub shaped_text_entries: usize
+}
+
+// The faces and instances of a cache at some point, sorted by id, which can be
+// sent to another thread. Faces added or removed afterwards don't show up.
+#[derive(Debug, PartialEq, Clone)]
+pub struct FontCacheSnapshot<FontKey, FontInstanceKey> {
+    pub faces: Vec<FaceSnapshot>,
+    pub instances: Vec<FontInstanceInfo<FontKey, FontInstanceKey>>
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct FaceSnapshot {
+    pub font_id: FontId,
+    pub family_name: Option<String>,
+    pub face_index: usize,
+    // Backends which don't hold on to font files have no bytes to share.
+    pub bytes: Option<Arc<Vec<u8>>>
+}
 
-    pub fn resize_dpi(&self, size: u32, dpi: u32) -> Self {
-        Self::from_family_hash(self.family_name, size, dpi)
+impl FaceSnapshot {
+    pub fn to_data_uri(&self) -> Option<String> {
+        self.bytes
+            .as_ref()
+            .map(|bytes| base64_util::to_font_data_uri(bytes))
     }
 }
 
@@ -99,13 +424,206 @@ pub struct FontSizeMetrics {
     pub max_advance_64: i32
 }
 
+// Grid cell of a face, for monospace layouts: the advances of '0' and 'M' and
+// the line height, all in 26.6 pixels.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct CellMetrics {
+    pub zero_advance_64: i32,
+    pub em_advance_64: i32,
+    pub ascender_64: i32,
+    pub descender_64: i32,
+    pub line_height_64: i32
+}
+
 #[derive(Debug, PartialEq, Copy, Clone)]
 pub struct GlyphDimensions {
     pub glyph_index: u32,
     pub width_64: i32,
     pub height_64: i32,
     pub hori_advance_64: i32,
-    pub vert_advance_64: i32
+    pub vert_advance_64: i32,
+    // Components of the advances across their axis, with y growing downwards,
+    // which only transforms rotating or shearing glyphs give them.
+    pub hori_advance_y_64: i32,
+    pub vert_advance_x_64: i32,
+    // Drawn in color, from COLR layers or color bitmaps, rather than tinted coverage.
+    pub color: bool
+}
+
+// From a glyph's vertical origin, at the top center of its column, to the top
+// left of its ink, in 26.6 pixels with y growing downwards.
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Default, Serialize, Deserialize)]
+pub struct GlyphVerticalBearings {
+    pub bearing_x_64: i32,
+    pub bearing_y_64: i32
+}
+
+// Ink extents of a glyph or run in 26.6 pixels, y growing downwards. Bounds in
+// a glyph store are relative to the run origin, like the glyph positions.
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Default, Serialize, Deserialize)]
+pub struct GlyphInkBounds {
+    pub left_64: i32,
+    pub top_64: i32,
+    pub right_64: i32,
+    pub bottom_64: i32
+}
+
+impl GlyphInkBounds {
+    // Transforms a box given in y-up font units around the glyph origin.
+    pub(crate) fn from_outline_box(transform: GlyphTransform, x_min_64: i64, y_min_64: i64, x_max_64: i64, y_max_64: i64) -> Self {
+        if x_min_64 >= x_max_64 || y_min_64 >= y_max_64 {
+            return GlyphInkBounds::default();
+        }
+        let corners = [
+            transform.apply_64(x_min_64, y_min_64),
+            transform.apply_64(x_max_64, y_min_64),
+            transform.apply_64(x_min_64, y_max_64),
+            transform.apply_64(x_max_64, y_max_64)
+        ];
+        GlyphInkBounds {
+            left_64: saturate_i32(corners.iter().map(|c| c.0).min().unwrap_or_default()),
+            top_64: saturate_i32(-corners.iter().map(|c| c.1).max().unwrap_or_default()),
+            right_64: saturate_i32(corners.iter().map(|c| c.0).max().unwrap_or_default()),
+            bottom_64: saturate_i32(-corners.iter().map(|c| c.1).min().unwrap_or_default())
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.left_64 >= self.right_64 || self.top_64 >= self.bottom_64
+    }
+
+    pub fn width_64(&self) -> i32 {
+        self.right_64.saturating_sub(self.left_64).max(0)
+    }
+
+    pub fn height_64(&self) -> i32 {
+        self.bottom_64.saturating_sub(self.top_64).max(0)
+    }
+
+    pub fn translate(&self, x_64: i32, y_64: i32) -> Self {
+        GlyphInkBounds {
+            left_64: self.left_64.saturating_add(x_64),
+            top_64: self.top_64.saturating_add(y_64),
+            right_64: self.right_64.saturating_add(x_64),
+            bottom_64: self.bottom_64.saturating_add(y_64)
+        }
+    }
+
+    // Empty bounds don't contribute, so blank glyphs don't stretch damage rects.
+    pub fn union(&self, other: &GlyphInkBounds) -> Self {
+        if self.is_empty() {
+            return *other;
+        }
+        if other.is_empty() {
+            return *self;
+        }
+        GlyphInkBounds {
+            left_64: self.left_64.min(other.left_64),
+            top_64: self.top_64.min(other.top_64),
+            right_64: self.right_64.max(other.right_64),
+            bottom_64: self.bottom_64.max(other.bottom_64)
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct GlyphLinearAdvance {
+    pub glyph_index: u32,
+    pub hori_advance_65536: i64,
+    pub vert_advance_65536: i64
+}
+
+// Advances read from the face's tables in design units, before any hinting,
+// rounding or transform. Scaled in float math, text animated through sizes
+// less than a pixel apart grows smoothly instead of in steps.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct LinearGlyphMetrics {
+    pub glyph_index: u32,
+    pub units_per_em: u32,
+    pub hori_advance: i64,
+    pub vert_advance: i64
+}
+
+impl LinearGlyphMetrics {
+    pub fn hori_advance_px(&self, pixel_size: f32) -> f32 {
+        (self.hori_advance as f64 * f64::from(pixel_size) / f64::from(self.units_per_em.max(1))) as f32
+    }
+
+    pub fn vert_advance_px(&self, pixel_size: f32) -> f32 {
+        (self.vert_advance as f64 * f64::from(pixel_size) / f64::from(self.units_per_em.max(1))) as f32
+    }
+
+    // Unrounded 26.6 advances at the instance's size, through its transform.
+    pub(crate) fn scaled_64<FontKey, FontInstanceKey, GlyphInstance>(
+        &self,
+        instance: &FontInstance<FontKey, FontInstanceKey, GlyphInstance>
+    ) -> (f64, f64) {
+        let scale = instance.pixel_size_64() as f64 / f64::from(self.units_per_em.max(1));
+        let transform = instance.transform();
+        (
+            self.hori_advance as f64 * scale * f64::from(transform.xx) / 65536.0,
+            self.vert_advance as f64 * scale * f64::from(transform.yy) / 65536.0
+        )
+    }
+}
+
+// Glyph box, bearings and advances in the face's design units, loaded without
+// scaling or hinting, so they hold for every size of the face. Consumers scale
+// them by `pixel_size / units_per_em` themselves.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct DesignGlyphMetrics {
+    pub glyph_index: u32,
+    pub units_per_em: u32,
+    pub width: i64,
+    pub height: i64,
+    pub hori_bearing_x: i64,
+    pub hori_bearing_y: i64,
+    pub hori_advance: i64,
+    pub vert_advance: i64
+}
+
+impl DesignGlyphMetrics {
+    // Rounded to 26.6 at a 26.6 pixel size, like unhinted outlines would be.
+    pub fn scaled_64(&self, units: i64, pixel_size_64: i64) -> i64 {
+        let units_per_em = i64::from(self.units_per_em.max(1));
+        let scaled = units * pixel_size_64;
+        (scaled + scaled.signum() * units_per_em / 2) / units_per_em
+    }
+
+    pub fn dimensions_at(&self, pixel_size_64: i64) -> GlyphDimensions {
+        GlyphDimensions {
+            glyph_index: self.glyph_index,
+            width_64: self.scaled_64(self.width, pixel_size_64) as i32,
+            height_64: self.scaled_64(self.height, pixel_size_64) as i32,
+            hori_advance_64: self.scaled_64(self.hori_advance, pixel_size_64) as i32,
+            vert_advance_64: self.scaled_64(self.vert_advance, pixel_size_64) as i32,
+            hori_advance_y_64: 0,
+            vert_advance_x_64: 0,
+            color: false
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct GlyphBitmap {
+    pub glyph_index: u32,
+    pub format: ImagePixelFormat,
+    pub alpha_mode: AlphaMode,
+    pub size: (u32, u32),
+    pub bearing: (i32, i32),
+    pub pixels: Vec<u8>
+}
+
+impl GlyphBitmap {
+    // Color glyphs are canonical color bitmaps, others are coverage.
+    pub fn is_color(&self) -> bool {
+        self.format == pixels::canonical_color_format()
+    }
+
+    // Rows are tightly packed.
+    pub fn pitch(&self) -> usize {
+        self.size.0 as usize * pixels::bytes_per_pixel(self.format)
+    }
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -118,7 +636,20 @@ pub struct GlyphStore<FontKey, FontInstanceKey, GlyphInstance> {
     pub(crate) width_64: i32,
     pub(crate) height_64: i32,
     pub(crate) glyphs: GlyphsArray<GlyphInstance>,
-    pub(crate) generation_id: u64
+    pub(crate) generation_id: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) source: Option<GlyphRunSource>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) ink_bounds: Option<Rc<Vec<GlyphInkBounds>>>,
+    // Byte offset of each glyph's cluster in the shaped text, in glyph order.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) clusters: Option<Rc<Vec<u32>>>,
+    // Byte length of the shaped text, where clusters end. Zero if unknown.
+    #[serde(default)]
+    pub(crate) text_len: u32,
+    // Only present for text the bidi algorithm reordered.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) direction_runs: Option<Rc<Vec<DirectionRun>>>
 }
 
 // Testing equality between glyph stores can be slow in the worst case scenario,
@@ -129,6 +660,143 @@ impl<FontKey, FontInstanceKey, GlyphInstance> PartialEq for GlyphStore<FontKey,
     }
 }
 
+impl<FontKey, FontInstanceKey, GlyphInstance> GlyphStore<FontKey, FontInstanceKey, GlyphInstance> {
+    pub fn source(&self) -> Option<&GlyphRunSource> {
+        self.source.as_ref()
+    }
+
+    // Per-glyph ink bounds, present when the instance was shaped with them enabled.
+    pub fn ink_bounds(&self) -> Option<&[GlyphInkBounds]> {
+        self.ink_bounds.as_ref().map(|bounds| bounds.as_slice())
+    }
+
+    // Runs of glyphs in a single direction, in visual order, present when the
+    // text mixes directions or is right to left.
+    pub fn direction_runs(&self) -> Option<&[DirectionRun]> {
+        self.direction_runs.as_ref().map(|runs| runs.as_slice())
+    }
+
+    // Byte length of the text the store was shaped from, or zero if unknown,
+    // e.g. for stores deserialized from older versions.
+    pub fn text_len(&self) -> u32 {
+        self.text_len
+    }
+
+    pub fn ink_extent(&self) -> Option<GlyphInkBounds> {
+        self.ink_bounds
+            .as_ref()
+            .map(|bounds| bounds.iter().fold(GlyphInkBounds::default(), |acc, b| acc.union(b)))
+    }
+
+    // Union of the ink bounds of the glyphs in `range`, for damaging a partial text change.
+    pub fn ink_bounds_of(&self, range: Range<usize>) -> Option<GlyphInkBounds> {
+        self.ink_bounds.as_ref().map(|bounds| {
+            let end = range.end.min(bounds.len());
+            let start = range.start.min(end);
+            bounds[start..end]
+                .iter()
+                .fold(GlyphInkBounds::default(), |acc, b| acc.union(b))
+        })
+    }
+
+    // Moves every glyph, keeping the clusters and source. Shifted stores don't
+    // compare equal to the store they were moved from.
+    pub(crate) fn shifted(self, x_64: i32, y_64: i32) -> Self
+    where
+        GlyphInstance: TGlyphInstanceExt
+    {
+        let glyphs: Vec<GlyphInstance> = self.glyphs
+            .0
+            .iter()
+            .map(|glyph| GlyphInstance::new(glyph.glyph_index(), glyph.x_64().saturating_add(x_64), glyph.y_64().saturating_add(y_64)))
+            .collect();
+        let mut hasher = FnvHasher::default();
+        hasher.write_u64(self.generation_id);
+        hasher.write_i32(x_64);
+        hasher.write_i32(y_64);
+        GlyphStore {
+            glyphs: GlyphsArray(Rc::from(glyphs.into_boxed_slice())),
+            generation_id: hasher.finish(),
+            ink_bounds: self.ink_bounds
+                .map(|bounds| Rc::new(bounds.iter().map(|b| b.translate(x_64, y_64)).collect())),
+            ..self
+        }
+    }
+
+    // Places the glyphs of a store shaped from other text, e.g. an ellipsis,
+    // after this store's own. They all map to the end of this store's text.
+    pub(crate) fn appended(self, other: &Self) -> Self
+    where
+        GlyphInstance: TGlyphInstanceExt
+    {
+        let x_64 = self.width_64;
+        let mut glyphs = self.glyphs.0.to_vec();
+        glyphs.extend(other.glyphs.0.iter().map(|glyph| {
+            GlyphInstance::new(glyph.glyph_index(), glyph.x_64().saturating_add(x_64), glyph.y_64())
+        }));
+        let ink_bounds = match (self.ink_bounds, other.ink_bounds()) {
+            (Some(bounds), Some(other_bounds)) => {
+                let mut joined = Vec::clone(&bounds);
+                joined.extend(other_bounds.iter().map(|b| b.translate(x_64, 0)));
+                Some(Rc::new(joined))
+            }
+            _ => None
+        };
+        let text_len = self.text_len;
+        let clusters = self.clusters.map(|clusters| {
+            let mut joined = Vec::clone(&clusters);
+            joined.extend(other.glyphs.0.iter().map(|_| text_len));
+            Rc::new(joined)
+        });
+        let mut hasher = FnvHasher::default();
+        hasher.write_u64(self.generation_id);
+        hasher.write_u64(other.generation_id);
+        GlyphStore {
+            width_64: x_64.saturating_add(other.width_64),
+            height_64: self.height_64.max(other.height_64),
+            glyphs: GlyphsArray(Rc::from(glyphs.into_boxed_slice())),
+            generation_id: hasher.finish(),
+            ink_bounds,
+            clusters,
+            ..self
+        }
+    }
+
+    // Stores not produced by shaping, e.g. deserialized from older versions,
+    // get clusters at char boundaries, the per-char shaper's one glyph per char.
+    pub(crate) fn with_source(mut self, text: &str, mode: GlyphSourceMode) -> Self {
+        self.source = match mode {
+            GlyphSourceMode::Omitted => None,
+            GlyphSourceMode::Hashed | GlyphSourceMode::Text => {
+                let mut hasher = FnvHasher::default();
+                hasher.write(text.as_bytes());
+                let offsets = match self.clusters {
+                    Some(ref clusters) => Vec::clone(clusters),
+                    None => text.char_indices().map(|(offset, _)| offset as u32).collect()
+                };
+                let text = if mode == GlyphSourceMode::Text {
+                    Some(Rc::new(text.to_string()))
+                } else {
+                    None
+                };
+                Some(GlyphRunSource::new(text, hasher.finish(), offsets))
+            }
+        };
+        self
+    }
+}
+
+impl<FontKey, FontInstanceKey, GlyphInstance> TGlyphStoreExt for GlyphStore<FontKey, FontInstanceKey, GlyphInstance>
+where
+    FontKey: TFontKey,
+    FontInstanceKey: TFontInstanceKey,
+    GlyphInstance: TGlyphInstance
+{
+    fn clusters(&self) -> Option<&[u32]> {
+        self.clusters.as_ref().map(|clusters| clusters.as_slice())
+    }
+}
+
 impl<FontKey, FontInstanceKey, GlyphInstance> TGlyphStore for GlyphStore<FontKey, FontInstanceKey, GlyphInstance>
 where
     FontKey: TFontKey,
@@ -148,11 +816,11 @@ where
     }
 
     fn width_f(&self) -> f32 {
-        self.width_64 as f32 / 64.0
+        Fixed26_6::from_raw(self.width_64).to_f32()
     }
 
     fn height_f(&self) -> f32 {
-        self.height_64 as f32 / 64.0
+        Fixed26_6::from_raw(self.height_64).to_f32()
     }
 
     fn width_64(&self) -> i32 {
@@ -168,78 +836,444 @@ where
     }
 }
 
-#[derive(Debug)]
-pub struct FontInstance<FontKey, FontInstanceKey, GlyphInstance> {
-    font_id: FontId,
-    size: u32,
-    dpi: u32,
-    external_key: FontKey,
-    external_instance_key: FontInstanceKey,
-    pub(crate) shaped_text_h_cache: RefCell<FnvHashMap<u64, GlyphStore<FontKey, FontInstanceKey, GlyphInstance>>>,
-    pub(crate) shaped_text_v_cache: RefCell<FnvHashMap<u64, GlyphStore<FontKey, FontInstanceKey, GlyphInstance>>>
+// A glyph store owning its contents instead of sharing them through `Rc`, so
+// that it can be sent to other threads, e.g. out of a `FontThread` job. It's
+// usable as is, or turned back into a store once it arrives.
+#[derive(Debug, Clone)]
+pub struct SendGlyphStore<FontKey, FontInstanceKey, GlyphInstance> {
+    font_key: FontKey,
+    font_instance_key: FontInstanceKey,
+    width_64: i32,
+    height_64: i32,
+    glyphs: Vec<GlyphInstance>,
+    generation_id: u64,
+    // The text, its hash and the cluster offsets of the run's source.
+    source: Option<(Option<String>, u64, Vec<u32>)>,
+    ink_bounds: Option<Vec<GlyphInkBounds>>,
+    clusters: Option<Vec<u32>>,
+    text_len: u32,
+    direction_runs: Option<Vec<DirectionRun>>
 }
 
-// Testing equality between font instances can be slow in the worst case scenario,
-// depending on the state of the cache. Use the font id for a faster path.
-impl<FontKey, FontInstanceKey, GlyphInstance> PartialEq for FontInstance<FontKey, FontInstanceKey, GlyphInstance> {
-    fn eq(&self, other: &Self) -> bool {
-        self.font_id == other.font_id && self.size == other.size && self.dpi == other.dpi
+impl<FontKey, FontInstanceKey, GlyphInstance> GlyphStore<FontKey, FontInstanceKey, GlyphInstance>
+where
+    FontKey: Copy,
+    FontInstanceKey: Copy,
+    GlyphInstance: Clone
+{
+    pub fn to_send(&self) -> SendGlyphStore<FontKey, FontInstanceKey, GlyphInstance> {
+        SendGlyphStore {
+            font_key: self.font_key,
+            font_instance_key: self.font_instance_key,
+            width_64: self.width_64,
+            height_64: self.height_64,
+            glyphs: self.glyphs.0.to_vec(),
+            generation_id: self.generation_id,
+            source: self.source.as_ref().map(|source| {
+                let text = source.text().map(|text| text.to_string());
+                (text, source.text_hash, Vec::clone(&source.offsets))
+            }),
+            ink_bounds: self.ink_bounds.as_ref().map(|bounds| Vec::clone(bounds)),
+            clusters: self.clusters.as_ref().map(|clusters| Vec::clone(clusters)),
+            text_len: self.text_len,
+            direction_runs: self.direction_runs.as_ref().map(|runs| Vec::clone(runs))
+        }
     }
 }
 
-impl<FontKey, FontInstanceKey, GlyphInstance> FontInstance<FontKey, FontInstanceKey, GlyphInstance> {
-    pub fn new(font_id: FontId, size: u32, dpi: u32, external_key: FontKey, external_instance_key: FontInstanceKey) -> Rc<Self> {
-        Rc::new(FontInstance {
-            font_id,
-            size,
-            dpi,
-            external_key,
-            external_instance_key,
-            shaped_text_h_cache: RefCell::default(),
-            shaped_text_v_cache: RefCell::default()
-        })
+impl<FontKey, FontInstanceKey, GlyphInstance> SendGlyphStore<FontKey, FontInstanceKey, GlyphInstance> {
+    // The store this was made from, comparing equal to it.
+    pub fn into_store(self) -> GlyphStore<FontKey, FontInstanceKey, GlyphInstance> {
+        GlyphStore {
+            font_key: self.font_key,
+            font_instance_key: self.font_instance_key,
+            width_64: self.width_64,
+            height_64: self.height_64,
+            glyphs: GlyphsArray(Rc::from(self.glyphs.into_boxed_slice())),
+            generation_id: self.generation_id,
+            source: self.source
+                .map(|(text, text_hash, offsets)| GlyphRunSource::new(text.map(Rc::new), text_hash, offsets)),
+            ink_bounds: self.ink_bounds.map(Rc::new),
+            clusters: self.clusters.map(Rc::new),
+            text_len: self.text_len,
+            direction_runs: self.direction_runs.map(Rc::new)
+        }
     }
 
-    pub fn font_id(&self) -> FontId {
-        self.font_id
+    pub fn ink_bounds(&self) -> Option<&[GlyphInkBounds]> {
+        self.ink_bounds.as_ref().map(|bounds| bounds.as_slice())
     }
 
-    pub fn size(&self) -> u32 {
-        self.size
+    pub fn direction_runs(&self) -> Option<&[DirectionRun]> {
+        self.direction_runs.as_ref().map(|runs| runs.as_slice())
     }
 
-    pub fn dpi(&self) -> u32 {
-        self.dpi
+    pub fn text_len(&self) -> u32 {
+        self.text_len
     }
+}
 
-    pub fn external_key(&self) -> FontKey
-    where
-        FontKey: Copy
-    {
-        self.external_key
+impl<FontKey, FontInstanceKey, GlyphInstance> TGlyphStoreExt for SendGlyphStore<FontKey, FontInstanceKey, GlyphInstance>
+where
+    FontKey: TFontKey,
+    FontInstanceKey: TFontInstanceKey,
+    GlyphInstance: TGlyphInstance
+{
+    fn clusters(&self) -> Option<&[u32]> {
+        self.clusters.as_ref().map(|clusters| clusters.as_slice())
     }
+}
 
-    pub fn external_instance_key(&self) -> FontInstanceKey
-    where
+impl<FontKey, FontInstanceKey, GlyphInstance> TGlyphStore for SendGlyphStore<FontKey, FontInstanceKey, GlyphInstance>
+where
+    FontKey: TFontKey,
+    FontInstanceKey: TFontInstanceKey,
+    GlyphInstance: TGlyphInstance
+{
+    type FontKey = FontKey;
+    type FontInstanceKey = FontInstanceKey;
+    type Glyph = GlyphInstance;
+
+    fn font_key(&self) -> Self::FontKey {
+        self.font_key
+    }
+
+    fn font_instance_key(&self) -> Self::FontInstanceKey {
+        self.font_instance_key
+    }
+
+    fn width_f(&self) -> f32 {
+        Fixed26_6::from_raw(self.width_64).to_f32()
+    }
+
+    fn height_f(&self) -> f32 {
+        Fixed26_6::from_raw(self.height_64).to_f32()
+    }
+
+    fn width_64(&self) -> i32 {
+        self.width_64
+    }
+
+    fn height_64(&self) -> i32 {
+        self.height_64
+    }
+
+    fn glyphs(&self) -> &[Self::Glyph] {
+        &self.glyphs
+    }
+}
+
+#[derive(Debug)]
+pub struct FontInstance<FontKey, FontInstanceKey, GlyphInstance> {
+    font_id: FontId,
+    size: u32,
+    dpi: Dpi,
+    transform: GlyphTransform,
+    unit: FontSizeUnit,
+    // Part of the instance's identity, only chosen by the cache creating it.
+    pub(crate) palette: Cell<PaletteSelection>,
+    external_key: FontKey,
+    external_instance_key: FontInstanceKey,
+    load_policy: Cell<GlyphLoadPolicy>,
+    ink_bounds: Cell<bool>,
+    kerning: Cell<bool>,
+    linear_advances: Cell<bool>,
+    shaping_strategy: Cell<ShapingStrategy>,
+    features: RefCell<Vec<FontFeature>>,
+    pub(crate) features_hash: Cell<u64>,
+    shaping_counters: Cell<ShapingCounters>,
+    // Whether the face has a kern table, which the simple shaper kerns with.
+    pub(crate) legacy_kerning: Cell<Option<bool>>,
+    // Glyph substitutions the simple shaper applies for the features.
+    pub(crate) feature_substitutions: RefCell<Option<Rc<FnvHashMap<u32, u32>>>>,
+    // Glyphs shaped so far, which font subsets keep. See `FontCache::set_font_subsetting`.
+    pub(crate) used_glyphs: RefCell<FnvHashSet<u32>>,
+    // Identifies the face across font caches. Only computed for shared shaping.
+    pub(crate) face_hash: Cell<u64>,
+    // Its own unless the cache creating it shares one between its instances.
+    pub(crate) shaping_cache: RefCell<ShapingCache<FontKey, FontInstanceKey, GlyphInstance>>,
+    pub(crate) measured_chars_cache: RefCell<FnvHashMap<char, GlyphDimensions>>,
+    pub(crate) kerning_pairs_cache: RefCell<FnvHashMap<(char, char), i32>>,
+    pub(crate) measured_text_cache: RefCell<FnvHashMap<u64, (i32, i32)>>,
+    pub(crate) cell_metrics_cache: Cell<Option<CellMetrics>>
+}
+
+// Testing equality between font instances can be slow in the worst case scenario,
+// depending on the state of the cache. Use the font id for a faster path.
+impl<FontKey, FontInstanceKey, GlyphInstance> PartialEq for FontInstance<FontKey, FontInstanceKey, GlyphInstance> {
+    fn eq(&self, other: &Self) -> bool {
+        self.font_id == other.font_id
+            && self.size == other.size
+            && self.dpi == other.dpi
+            && self.transform == other.transform
+            && self.unit == other.unit
+            && self.palette.get() == other.palette.get()
+    }
+}
+
+impl<FontKey, FontInstanceKey, GlyphInstance> FontInstance<FontKey, FontInstanceKey, GlyphInstance> {
+    pub fn new(font_id: FontId, size: Pt, dpi: Dpi, external_key: FontKey, external_instance_key: FontInstanceKey) -> Rc<Self> {
+        Self::new_with_transform(
+            font_id,
+            size,
+            dpi,
+            GlyphTransform::IDENTITY,
+            external_key,
+            external_instance_key
+        )
+    }
+
+    pub fn new_with_transform(
+        font_id: FontId,
+        size: Pt,
+        dpi: Dpi,
+        transform: GlyphTransform,
+        external_key: FontKey,
+        external_instance_key: FontInstanceKey
+    ) -> Rc<Self> {
+        Self::new_with_unit(
+            font_id,
+            size.0,
+            dpi,
+            transform,
+            FontSizeUnit::Points,
+            external_key,
+            external_instance_key
+        )
+    }
+
+    // The size is in points or pixels, as given by the unit.
+    pub fn new_with_unit(
+        font_id: FontId,
+        size: u32,
+        dpi: Dpi,
+        transform: GlyphTransform,
+        unit: FontSizeUnit,
+        external_key: FontKey,
+        external_instance_key: FontInstanceKey
+    ) -> Rc<Self> {
+        let dpi = match unit {
+            FontSizeUnit::Points => dpi,
+            FontSizeUnit::Pixels => PIXEL_SIZE_DPI
+        };
+        Rc::new(FontInstance {
+            font_id,
+            size,
+            dpi,
+            transform,
+            unit,
+            palette: Cell::default(),
+            external_key,
+            external_instance_key,
+            load_policy: Cell::default(),
+            ink_bounds: Cell::new(false),
+            kerning: Cell::new(true),
+            linear_advances: Cell::new(false),
+            shaping_strategy: Cell::default(),
+            features: RefCell::default(),
+            features_hash: Cell::default(),
+            shaping_counters: Cell::default(),
+            legacy_kerning: Cell::default(),
+            feature_substitutions: RefCell::default(),
+            used_glyphs: RefCell::default(),
+            face_hash: Cell::default(),
+            shaping_cache: RefCell::default(),
+            measured_chars_cache: RefCell::default(),
+            kerning_pairs_cache: RefCell::default(),
+            measured_text_cache: RefCell::default(),
+            cell_metrics_cache: Cell::default()
+        })
+    }
+
+    pub fn font_id(&self) -> FontId {
+        self.font_id
+    }
+
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    pub fn dpi(&self) -> Dpi {
+        self.dpi
+    }
+
+    pub fn transform(&self) -> GlyphTransform {
+        self.transform
+    }
+
+    pub fn unit(&self) -> FontSizeUnit {
+        self.unit
+    }
+
+    pub fn palette(&self) -> PaletteSelection {
+        self.palette.get()
+    }
+
+    // Nominal em size in 26.6 pixels.
+    pub fn pixel_size_64(&self) -> i64 {
+        match self.unit {
+            FontSizeUnit::Points => Pt(self.size).to_px_64(self.dpi),
+            FontSizeUnit::Pixels => Px(self.size).to_px_64()
+        }
+    }
+
+    pub fn external_key(&self) -> FontKey
+    where
+        FontKey: Copy
+    {
+        self.external_key
+    }
+
+    pub fn external_instance_key(&self) -> FontInstanceKey
+    where
         FontInstanceKey: Copy
     {
         self.external_instance_key
     }
+
+    pub fn load_policy(&self) -> GlyphLoadPolicy {
+        self.load_policy.get()
+    }
+
+    // Measurement flags affect advances, so previously shaped text is discarded.
+    pub fn set_load_policy(&self, policy: GlyphLoadPolicy) {
+        if self.load_policy.replace(policy) != policy {
+            self.discard_shaped_text(true, true);
+            self.measured_chars_cache.borrow_mut().clear();
+            self.cell_metrics_cache.set(None);
+        }
+    }
+
+    pub fn ink_bounds(&self) -> bool {
+        self.ink_bounds.get()
+    }
+
+    // Stores shaped without ink bounds lack them, so previously shaped text is discarded.
+    pub fn set_ink_bounds(&self, enabled: bool) {
+        if self.ink_bounds.replace(enabled) != enabled {
+            self.discard_shaped_text(true, true);
+        }
+    }
+
+    pub fn kerning(&self) -> bool {
+        self.kerning.get()
+    }
+
+    // Pair adjustments move every following glyph, so previously shaped text is discarded.
+    pub fn set_kerning(&self, enabled: bool) {
+        if self.kerning.replace(enabled) != enabled {
+            self.discard_shaped_text(true, false);
+        }
+    }
+
+    pub fn linear_advances(&self) -> bool {
+        self.linear_advances.get()
+    }
+
+    // Pens advance by design units scaled in float math rather than by hinted
+    // pixel advances, so previously shaped text is discarded.
+    pub fn set_linear_advances(&self, enabled: bool) {
+        if self.linear_advances.replace(enabled) != enabled {
+            self.discard_shaped_text(true, true);
+        }
+    }
+
+    pub fn shaping_strategy(&self) -> ShapingStrategy {
+        self.shaping_strategy.get()
+    }
+
+    // Shapers disagree on ligatures and GPOS adjustments, so previously shaped text is discarded.
+    pub fn set_shaping_strategy(&self, strategy: ShapingStrategy) {
+        if self.shaping_strategy.replace(strategy) != strategy {
+            self.discard_shaped_text(true, true);
+        }
+    }
+
+    pub fn features(&self) -> Vec<FontFeature> {
+        self.features.borrow().clone()
+    }
+
+    pub(crate) fn has_enabled_features(&self) -> bool {
+        self.features.borrow().iter().any(FontFeature::is_enabled)
+    }
+
+    // Features are part of the shaping key, so runs shaped with other features
+    // stay cached, but sizes memoized apart from shaped runs are dropped.
+    pub fn set_features(&self, features: Vec<FontFeature>) {
+        let hash = shaping::features_hash(&features);
+        if self.features_hash.replace(hash) != hash {
+            *self.features.borrow_mut() = features;
+            *self.feature_substitutions.borrow_mut() = None;
+            self.measured_text_cache.borrow_mut().clear();
+        }
+    }
+
+    pub fn shaping_counters(&self) -> ShapingCounters {
+        self.shaping_counters.get()
+    }
+
+    pub fn reset_shaping_counters(&self) -> ShapingCounters {
+        self.shaping_counters.replace(ShapingCounters::default())
+    }
+
+    pub(crate) fn count_shaped_run(&self, complex: bool) {
+        let mut counters = self.shaping_counters.get();
+        if complex {
+            counters.complex_runs += 1;
+        } else {
+            counters.simple_runs += 1;
+        }
+        self.shaping_counters.set(counters);
+    }
+
+    pub fn shaped_text_entries(&self) -> usize {
+        self.shaping_cache.borrow().entries_for(self)
+    }
+
+    pub fn shaping_cache(&self) -> ShapingCache<FontKey, FontInstanceKey, GlyphInstance> {
+        ShapingCache::clone(&self.shaping_cache.borrow())
+    }
+
+    // Runs shaped so far stay in the previous cache.
+    pub(crate) fn set_shaping_cache(&self, cache: ShapingCache<FontKey, FontInstanceKey, GlyphInstance>) {
+        *self.shaping_cache.borrow_mut() = cache;
+    }
+
+    fn discard_shaped_text(&self, horizontal: bool, vertical: bool) {
+        self.shaping_cache
+            .borrow()
+            .discard(self, horizontal, vertical);
+        if horizontal {
+            self.measured_text_cache.borrow_mut().clear();
+        }
+    }
+
+    pub fn measured_chars_entries(&self) -> usize {
+        self.measured_chars_cache.borrow().len()
+    }
+
+    pub fn kerning_pairs_entries(&self) -> usize {
+        self.kerning_pairs_cache.borrow().len()
+    }
+
+    pub fn measured_text_entries(&self) -> usize {
+        self.measured_text_cache.borrow().len()
+    }
 }
 
 #[derive(Debug, PartialEq)]
-pub struct SharedFonts<A: TFontKeysAPI>(Rc<RefCell<FontCache<A>>>);
+pub struct SharedFonts<A: TFontKeysAPI, B = FontContext>(Rc<SharedCell<FontCache<A, B>>>);
 
-impl<A> From<FontCache<A>> for SharedFonts<A>
+impl<A, B> From<FontCache<A, B>> for SharedFonts<A, B>
 where
     A: TFontKeysAPI
 {
-    fn from(value: FontCache<A>) -> Self {
-        SharedFonts(Rc::new(RefCell::new(value)))
+    fn from(value: FontCache<A, B>) -> Self {
+        SharedFonts(Rc::new(SharedCell::new(value)))
     }
 }
 
-impl<A> Clone for SharedFonts<A>
+impl<A, B> Clone for SharedFonts<A, B>
 where
     A: TFontKeysAPI
 {
@@ -248,20 +1282,74 @@ where
     }
 }
 
-impl<A> Deref for SharedFonts<A>
+impl<A, B> Deref for SharedFonts<A, B>
 where
     A: TFontKeysAPI
 {
-    type Target = RefCell<FontCache<A>>;
+    type Target = SharedCell<FontCache<A, B>>;
 
     fn deref(&self) -> &Self::Target {
         &self.0
     }
 }
 
-impl<A> TFontCache for SharedFonts<A>
+impl<A, B> SharedFonts<A, B>
+where
+    A: TFontKeysAPI + 'static,
+    B: TFontBackend + 'static
+{
+    // Instances missing while the cache is borrowed (e.g. when requested from
+    // within a shaping call) are created once the outer borrow is released,
+    // and are `None` until then. Those which can't be created then are kept as
+    // deferred errors, see `take_deferred_errors`.
+    fn get_or_insert_font(&self, font_instance_id: FontInstanceId) -> Option<RcFontInstance<A>> {
+        if let Some(instance) = self.borrow().get_font_instance(font_instance_id) {
+            return Some(instance);
+        }
+        match self.try_borrow_mut() {
+            Some(mut cache) => cache.get_or_insert_font(font_instance_id),
+            None => {
+                self.mutate(move |cache| {
+                    let inserted = cache.get_or_insert_font(font_instance_id);
+                    cache.record_deferred(inserted.ok_or(FontError::FaceNotFound));
+                });
+                None
+            }
+        }
+    }
+
+    // Skips collision checks while the cache is being mutated, e.g. when adding
+    // fonts from within a mutation queued by the cache itself.
+    fn font_id<P>(&self, font_name: P) -> FontId
+    where
+        P: AsRef<str>
+    {
+        match self.try_borrow() {
+            Some(cache) => cache.font_id(font_name),
+            None => FontId::new(font_name)
+        }
+    }
+
+    fn font_instance_id<T>(&self, family_name: T, size: Pt, dpi: Dpi) -> FontInstanceId
+    where
+        T: AsRef<str>
+    {
+        FontInstanceId::from_family_hash(self.font_id(family_name).font_name, size, dpi)
+    }
+
+    pub fn get_font_with_px_size<T>(&self, family_name: T, px: Px) -> Option<RcFontInstance<A>>
+    where
+        T: AsRef<str>
+    {
+        let font_instance_id = self.font_instance_id(family_name, Pt::DEFAULT, PIXEL_SIZE_DPI).resize_px(px);
+        self.get_or_insert_font(font_instance_id)
+    }
+}
+
+impl<A, B> TFontCache for SharedFonts<A, B>
 where
-    A: TFontKeysAPI + 'static
+    A: TFontKeysAPI + 'static,
+    B: TFontBackend + 'static
 {
     type FontInstance = RcFontInstance<A>;
     type FontId = FontId;
@@ -273,8 +1361,9 @@ where
         P: AsRef<str>,
         T: Into<Rc<Vec<u8>>>
     {
-        let id = FontId::new(font_name);
-        self.borrow_mut().add_raw(id, bytes, face_index).ok()
+        let id = self.font_id(font_name);
+        let encoded = EncodedFont::from_bytes(bytes).ok()?;
+        self.add_font_with_id(id, &encoded, face_index)
     }
 
     fn add_font<P, E>(&mut self, font_name: P, encoded: &E, face_index: usize) -> Option<()>
@@ -282,22 +1371,33 @@ where
         P: AsRef<str>,
         E: TEncodedFont
     {
-        let id = FontId::new(font_name);
-        self.borrow_mut().add_font(id, encoded, face_index).ok()
+        let id = self.font_id(font_name);
+        self.add_font_with_id(id, encoded, face_index)
     }
 
+    // Fonts added while the cache is borrowed are queued, and `Some` only means
+    // that they were accepted. Failures to add them once the borrow is released
+    // are kept as deferred errors, see `take_deferred_errors`.
     fn add_font_with_id<E>(&mut self, id: Self::FontId, encoded: &E, face_index: usize) -> Option<()>
     where
         E: TEncodedFont
     {
-        self.borrow_mut().add_font(id, encoded, face_index).ok()
+        if let Some(mut cache) = self.try_borrow_mut() {
+            return cache.add_font(id, encoded, face_index).ok();
+        }
+        let encoded = EncodedFont::from_encoded(encoded)?;
+        self.mutate(move |cache| {
+            let added = cache.add_font(id, &encoded, face_index);
+            cache.record_deferred(added);
+        });
+        Some(())
     }
 
     fn get_family_name<P>(&self, font_name: P) -> Option<String>
     where
         P: AsRef<str>
     {
-        let id = FontId::new(font_name);
+        let id = self.font_id(font_name);
         self.borrow().get_family_name_for_id(id).ok()
     }
 
@@ -309,7 +1409,8 @@ where
     where
         T: AsRef<str>
     {
-        self.borrow_mut().set_default_font(family_name, size, dpi);
+        let font_instance_id = FontInstanceId::from_family_str(family_name, Pt(size), Dpi(dpi));
+        self.mutate(move |cache| cache.default_font = Some(font_instance_id));
     }
 
     fn get_default_font(&self) -> Option<Self::FontInstance> {
@@ -317,52 +1418,55 @@ where
     }
 
     fn get_default_font_with_size(&self, s: u32) -> Option<Self::FontInstance> {
-        self.borrow_mut().get_default_font_with_size(s)
+        let font_instance_id = self.borrow().default_font?.resize(Pt(s));
+        self.get_or_insert_font(font_instance_id)
     }
 
     fn get_default_font_with_size_and_dpi(&self, s: u32, d: u32) -> Option<Self::FontInstance> {
-        self.borrow_mut().get_default_font_with_size_and_dpi(s, d)
+        let font_instance_id = self.borrow().default_font?.resize_dpi(Pt(s), Dpi(d));
+        self.get_or_insert_font(font_instance_id)
     }
 
     fn get_font<T>(&self, family_name: T) -> Option<Self::FontInstance>
     where
         T: AsRef<str>
     {
-        let mut borrow = self.borrow_mut();
-        let font_instance_id = FontInstanceId::from_family_str(family_name, DEFAULT_FONT_SIZE, DEFAULT_FONT_DPI);
-        borrow.get_or_insert_font(font_instance_id)
+        let font_instance_id = self.font_instance_id(family_name, Pt::DEFAULT, Dpi::DEFAULT);
+        self.get_or_insert_font(font_instance_id)
     }
 
     fn get_font_with_size<T>(&self, family_name: T, size: u32) -> Option<Self::FontInstance>
     where
         T: AsRef<str>
     {
-        let mut borrow = self.borrow_mut();
-        let font_instance_id = FontInstanceId::from_family_str(family_name, size, DEFAULT_FONT_DPI);
-        borrow.get_or_insert_font(font_instance_id)
+        let font_instance_id = self.font_instance_id(family_name, Pt(size), Dpi::DEFAULT);
+        self.get_or_insert_font(font_instance_id)
     }
 
     fn get_font_with_size_and_dpi<T>(&self, family_name: T, size: u32, dpi: u32) -> Option<Self::FontInstance>
     where
         T: AsRef<str>
     {
-        let mut borrow = self.borrow_mut();
-        let font_instance_id = FontInstanceId::from_family_str(family_name, size, dpi);
-        borrow.get_or_insert_font(font_instance_id)
+        let font_instance_id = self.font_instance_id(family_name, Pt(size), Dpi(dpi));
+        self.get_or_insert_font(font_instance_id)
     }
 
     fn shape_text_h<T>(&self, instance: &Self::FontInstance, text: T) -> Option<Self::Glyphs>
     where
         T: AsRef<str>
     {
-        self.borrow().shape_text_h(instance, text).ok()
+        let glyphs = self.borrow().shape_text_h(instance, text).ok();
+        self.flush();
+        glyphs
     }
 
     fn shape_text_v<T>(&self, instance: &Self::FontInstance, text: T) -> Option<Self::Glyphs>
     where
         T: AsRef<str>
     {
-        self.borrow().shape_text_v(instance, text).ok()
+        let glyphs = self.borrow().shape_text_v(instance, text).ok();
+        self.flush();
+        glyphs
     }
 
     fn take_resource_updates(&mut self) -> Self::ResourceUpdates {
@@ -371,11 +1475,41 @@ where
 }
 
 #[derive(Debug, PartialEq)]
-pub struct FontCache<A: TFontKeysAPI> {
+pub struct FontCache<A: TFontKeysAPI, B = FontContext> {
     api: A,
-    context: FontContext,
+    context: B,
     instances: FnvHashMap<FontInstanceId, RcFontInstance<A>>,
-    default_font: Option<FontInstanceId>
+    default_font: Option<FontInstanceId>,
+    priorities: FnvHashMap<FontId, ResourcePriority>,
+    expiry: ResourceExpiry<FontId>,
+    descriptors: FnvHashMap<FontId, FontDescriptor>,
+    aliases: FnvHashMap<String, String>,
+    fallbacks: FnvHashMap<String, Vec<String>>,
+    shared_memory: FnvHashMap<FontId, Rc<SharedMemory>>,
+    display_profile: DisplayProfile,
+    glyph_source: GlyphSourceMode,
+    glyph_ink_bounds: bool,
+    normalization: NormalizationForm,
+    placeholder_metrics: Option<PlaceholderMetrics>,
+    placeholders: FnvHashMap<FontInstanceId, RcFontInstance<A>>,
+    // Instances created for spans off the baseline, see `shape_span_h`, with
+    // the time of the last sweep which saw them in use, if not since used.
+    derived_instances: FnvHashMap<FontInstanceId, Option<u64>>,
+    paragraphs: RefCell<ParagraphCache<A::FontKey, A::FontInstanceKey, A::GlyphInstance>>,
+    // Set when instances share a single shaping cache rather than each having their own.
+    shaping_cache: Option<ShapingCache<A::FontKey, A::FontInstanceKey, A::GlyphInstance>>,
+    // Font bytes copied for snapshots, kept until the face is removed.
+    snapshot_bytes: RefCell<FnvHashMap<FontId, Arc<Vec<u8>>>>,
+    subsetting: bool,
+    retained_chars: FnvHashMap<FontId, FnvHashSet<char>>,
+    // Glyphs of the subsets last sent, see `send_font_subsets`.
+    sent_subsets: FnvHashMap<FontId, FnvHashSet<u32>>,
+    errors: ErrorStats,
+    ids: IdStats<FontId>,
+    #[cfg(feature = "key-balance-watchdog")]
+    font_keys: KeyLedger<FontId, A::FontKey>,
+    #[cfg(feature = "key-balance-watchdog")]
+    font_instance_keys: KeyLedger<FontInstanceId, A::FontInstanceKey>
 }
 
 impl<A> FontCache<A>
@@ -383,19 +1517,56 @@ where
     A: TFontKeysAPI
 {
     pub fn new(api: A) -> Result<Self> {
-        Ok(FontCache {
+        Ok(FontCache::with_backend(api, FontContext::new()?))
+    }
+}
+
+impl<A, B> FontCache<A, B>
+where
+    A: TFontKeysAPI,
+    B: TFontBackend
+{
+    // Allows swapping FreeType for another backend, e.g. `MockFontBackend` to
+    // get platform independent metrics in layout tests.
+    pub fn with_backend(api: A, backend: B) -> Self {
+        FontCache {
             api,
-            context: FontContext::new()?,
+            context: backend,
             instances: FnvHashMap::default(),
-            default_font: None
-        })
+            default_font: None,
+            priorities: FnvHashMap::default(),
+            expiry: ResourceExpiry::new(),
+            descriptors: FnvHashMap::default(),
+            aliases: FnvHashMap::default(),
+            fallbacks: FnvHashMap::default(),
+            shared_memory: FnvHashMap::default(),
+            display_profile: DisplayProfile::default(),
+            glyph_source: GlyphSourceMode::default(),
+            glyph_ink_bounds: false,
+            normalization: NormalizationForm::default(),
+            placeholder_metrics: None,
+            placeholders: FnvHashMap::default(),
+            derived_instances: FnvHashMap::default(),
+            paragraphs: RefCell::default(),
+            shaping_cache: None,
+            snapshot_bytes: RefCell::default(),
+            subsetting: false,
+            retained_chars: FnvHashMap::default(),
+            sent_subsets: FnvHashMap::default(),
+            errors: ErrorStats::new(),
+            ids: IdStats::new(),
+            #[cfg(feature = "key-balance-watchdog")]
+            font_keys: KeyLedger::new(),
+            #[cfg(feature = "key-balance-watchdog")]
+            font_instance_keys: KeyLedger::new()
+        }
     }
 
     pub fn add_raw<T>(&mut self, font_id: FontId, bytes: T, face_index: usize) -> Result<()>
     where
         T: Into<Rc<Vec<u8>>>
     {
-        let encoded = EncodedFont::from_bytes(bytes)?;
+        let encoded = self.errors.track(EncodedFont::from_bytes(bytes), FontError::category)?;
         self.add_font(font_id, &encoded, face_index)
     }
 
@@ -403,30 +1574,93 @@ where
     where
         E: TEncodedFont
     {
-        let decoded = DecodedFont::from_encoded_font(encoded, face_index);
-        self.context.add_face(font_id, &decoded.bytes, face_index)?;
+        self.insert_font(font_id, encoded, face_index, |api, encoded, decoded| api.add_font(encoded, decoded))
+    }
 
-        let family_name = self.context.get_family_name(font_id)?;
-        let size = DEFAULT_FONT_SIZE;
-        let dpi = DEFAULT_FONT_DPI;
-        let font_instance_id = FontInstanceId::from_family_str(family_name, size, dpi);
+    // Fonts backed by shared memory are announced with its descriptor, so that
+    // the renderer process can map the font file rather than get a copy. The
+    // memory is kept alive until the font is removed.
+    pub fn add_shared_font(&mut self, font_id: FontId, encoded: &EncodedFont, face_index: usize) -> Result<()>
+    where
+        A: TFontKeysAPIExt
+    {
+        let memory = match encoded.shared_memory() {
+            Some(memory) => Rc::clone(memory),
+            None => return self.add_font(font_id, encoded, face_index)
+        };
+        let descriptor = memory.descriptor();
+        self.insert_font(font_id, encoded, face_index, |api, encoded, decoded| {
+            api.add_shared_font(descriptor, encoded, decoded)
+        })?;
+        self.shared_memory.insert(font_id, memory);
+        Ok(())
+    }
+
+    fn insert_font<E, F>(&mut self, font_id: FontId, encoded: &E, face_index: usize, add_font_key: F) -> Result<()>
+    where
+        E: TEncodedFont,
+        F: FnOnce(&mut A, FontEncodedData, FontResourceData) -> A::FontKey
+    {
+        let decoded = DecodedFont::from_encoded_font(encoded, face_index);
+        let added = self.context.add_face(font_id, &decoded.bytes, face_index);
+        self.errors.track(added, FontError::category)?;
+
+        let size = Pt::DEFAULT;
+        let dpi = Dpi::DEFAULT;
+        // Further faces of a family, e.g. its bold, are told apart by their id,
+        // since looking the family up finds its first face.
+        let font_instance_id = self.context
+            .get_family_name(font_id)
+            .map(|family_name| FontInstanceId::from_family_str(family_name, size, dpi).with_origin_of(font_id))
+            .map(|id| if self.instances.contains_key(&id) { id.with_face_of(font_id) } else { id });
+        let font_instance_id = match font_instance_id {
+            Ok(font_instance_id) if !self.instances.contains_key(&font_instance_id) => font_instance_id,
+            result => {
+                // The face would otherwise linger without any instance to
+                // reach it, and block adding it again.
+                self.context.remove_face(font_id)?;
+                self.errors.track(result, FontError::category)?;
+                Err(FontError::FontInstanceAlreadyAdded)?
+            }
+        };
         self.default_font.get_or_insert(font_instance_id);
+        let subset = if self.subsetting {
+            Some(self.subset_bytes(font_id, &decoded.bytes))
+        } else {
+            None
+        };
 
         match self.instances.entry(font_instance_id) {
             Entry::Occupied(_) => {
                 Err(FontError::FontInstanceAlreadyAdded)?;
             }
             Entry::Vacant(e) => {
-                let instance_data = FontInstanceResourceData::new(size, dpi);
-                let external_key = self.api.add_font(encoded.info(), decoded.info());
+                let instance_data = FontInstanceResourceData::new(size.0, dpi.0);
+                let external_key = match subset {
+                    Some((bytes, glyphs)) => {
+                        self.sent_subsets.insert(font_id, glyphs);
+                        let resource = FontResourceData { bytes: &bytes, face_index };
+                        add_font_key(&mut self.api, FontEncodedData::Bytes { bytes: &bytes }, resource)
+                    }
+                    None => add_font_key(&mut self.api, encoded.info(), decoded.info())
+                };
                 let external_instance_key = self.api.add_font_instance(external_key, instance_data);
-                e.insert(FontInstance::new(
-                    font_id,
-                    size,
-                    dpi,
-                    external_key,
-                    external_instance_key
-                ));
+                #[cfg(feature = "key-balance-watchdog")]
+                self.font_keys.allocated(font_id, external_key);
+                #[cfg(feature = "key-balance-watchdog")]
+                self.font_instance_keys
+                    .allocated(font_instance_id, external_instance_key);
+                let instance = FontInstance::new(font_id, size, dpi, external_key, external_instance_key);
+                instance.set_load_policy(GlyphLoadPolicy::for_display(&self.display_profile));
+                instance.set_ink_bounds(self.glyph_ink_bounds);
+                if let Some(ref cache) = self.shaping_cache {
+                    instance.set_shaping_cache(ShapingCache::clone(cache));
+                }
+                #[cfg(feature = "shared-shaping-cache")]
+                instance
+                    .face_hash
+                    .set(shared_shaping::face_hash(&decoded.bytes, face_index));
+                e.insert(instance);
             }
         }
 
@@ -434,10 +1668,10 @@ where
     }
 
     pub fn get_family_name_for_id(&self, id: FontId) -> Result<String> {
-        self.context.get_family_name(id).map(String::from)
+        self.get_family_name(id).map(String::from)
     }
 
-    pub fn set_default_font<T>(&mut self, family_name: T, size: u32, dpi: u32)
+    pub fn set_default_font<T>(&mut self, family_name: T, size: Pt, dpi: Dpi)
     where
         T: AsRef<str>
     {
@@ -446,88 +1680,1609 @@ where
     }
 
     pub fn get_default_font(&self) -> Option<RcFontInstance<A>> {
-        let font_instance_id = self.default_font?;
+        let font_instance_id = self.resolve_origin(self.default_font?);
         self.instances.get(&font_instance_id).map(Rc::clone)
     }
 
-    pub fn get_default_font_with_size(&mut self, size: u32) -> Option<RcFontInstance<A>> {
+    pub fn get_default_font_with_size(&mut self, size: Pt) -> Option<RcFontInstance<A>> {
         let font_instance_id = self.default_font?.resize(size);
         self.get_or_insert_font(font_instance_id)
     }
 
-    pub fn get_default_font_with_size_and_dpi(&mut self, size: u32, dpi: u32) -> Option<RcFontInstance<A>> {
+    pub fn get_default_font_with_size_and_dpi(&mut self, size: Pt, dpi: Dpi) -> Option<RcFontInstance<A>> {
         let font_instance_id = self.default_font?.resize_dpi(size, dpi);
         self.get_or_insert_font(font_instance_id)
     }
 
-    pub fn get_or_insert_font(&mut self, font_instance_id: FontInstanceId) -> Option<RcFontInstance<A>> {
-        let (font_id, external_key) = {
-            let font_instance_id = font_instance_id.resize_dpi(DEFAULT_FONT_SIZE, DEFAULT_FONT_DPI);
-            let instance = self.instances.get(&font_instance_id)?;
-            (instance.font_id(), instance.external_key())
-        };
-
-        Some(match self.instances.entry(font_instance_id) {
-            Entry::Occupied(e) => Rc::clone(e.get()),
-            Entry::Vacant(e) => {
-                let size = font_instance_id.size;
-                let dpi = font_instance_id.dpi;
-                let instance_data = FontInstanceResourceData::new(size, dpi);
-                let external_instance_key = self.api.add_font_instance(external_key, instance_data);
-                Rc::clone(e.insert(FontInstance::new(
-                    font_id,
-                    size,
-                    dpi,
-                    external_key,
-                    external_instance_key
-                )))
-            }
-        })
+    pub fn backend(&self) -> &B {
+        &self.context
     }
 
-    pub fn get_bytes(&self, font_id: FontId) -> Result<Rc<Vec<u8>>> {
-        self.context.get_bytes(font_id)
+    pub fn backend_mut(&mut self) -> &mut B {
+        &mut self.context
     }
 
-    pub fn get_face_index(&self, font_id: FontId) -> Result<usize> {
-        self.context.get_face_index(font_id)
+    pub fn display_profile(&self) -> DisplayProfile {
+        self.display_profile
     }
 
-    pub fn get_family_name(&self, font_id: FontId) -> Result<&str> {
-        self.context.get_family_name(font_id)
+    pub fn set_display_profile(&mut self, profile: DisplayProfile) {
+        self.display_profile = profile;
+        let policy = GlyphLoadPolicy::for_display(&profile);
+        for instance in self.instances.values() {
+            instance.set_load_policy(policy);
+        }
     }
 
-    pub fn get_glyph_index(&self, instance: FontInstanceRef<A>, c: char) -> Result<u32> {
-        self.context.get_glyph_index(instance, c)
+    pub fn get_default_font_for_display(&mut self, size: Pt) -> Option<RcFontInstance<A>> {
+        let dpi = self.display_profile.font_dpi();
+        self.get_default_font_with_size_and_dpi(size, dpi)
     }
 
-    pub fn get_glyph_dimensions(&self, instance: FontInstanceRef<A>, c: char) -> Result<GlyphDimensions> {
-        self.context.get_glyph_dimensions(instance, c)
+    pub fn get_default_font_with_px_size(&mut self, px: Px) -> Option<RcFontInstance<A>> {
+        let font_instance_id = self.default_font?.resize_px(px);
+        self.get_or_insert_font(font_instance_id)
     }
 
-    pub fn get_global_size_metrics(&self, instance: FontInstanceRef<A>) -> Result<FontSizeMetrics> {
-        self.context.get_global_size_metrics(instance)
+    // CSS pixels are scaled to device pixels before picking the instance.
+    pub fn get_default_font_for_display_px(&mut self, px: Px) -> Option<RcFontInstance<A>> {
+        let px = px.scale(self.display_profile.device_pixel_ratio);
+        self.get_default_font_with_px_size(px)
     }
 
-    pub fn shape_text_h<T>(
-        &self,
-        instance: FontInstanceRef<A>,
-        text: T
-    ) -> Result<GlyphStore<A::FontKey, A::FontInstanceKey, A::GlyphInstance>>
+    pub fn get_font_with_px_size<T>(&mut self, family_name: T, px: Px) -> Option<RcFontInstance<A>>
     where
         T: AsRef<str>
     {
-        self.context.shape_text_h(instance, text)
+        self.get_or_insert_font(FontInstanceId::from_family_px(family_name, px))
     }
 
-    pub fn shape_text_v<T>(
-        &self,
-        instance: FontInstanceRef<A>,
-        text: T
-    ) -> Result<GlyphStore<A::FontKey, A::FontInstanceKey, A::GlyphInstance>>
+    pub fn get_font_instance(&self, font_instance_id: FontInstanceId) -> Option<RcFontInstance<A>> {
+        self.instances
+            .get(&font_instance_id)
+            .or_else(|| self.placeholders.get(&font_instance_id))
+            .map(Rc::clone)
+    }
+
+    // Transformed instances can only be created through `get_or_insert_transformed_font`,
+    // since the transform needs to be forwarded to the renderer.
+    pub fn get_or_insert_font(&mut self, font_instance_id: FontInstanceId) -> Option<RcFontInstance<A>> {
+        if !font_instance_id.transform.is_identity() {
+            return self.get_font_instance(font_instance_id);
+        }
+        self.insert_font_instance(font_instance_id, |api, external_key, instance_data| {
+            api.add_font_instance(external_key, instance_data)
+        })
+    }
+
+    pub fn get_or_insert_transformed_font(&mut self, font_instance_id: FontInstanceId) -> Option<RcFontInstance<A>>
     where
-        T: AsRef<str>
+        A: TFontKeysAPIExt
+    {
+        let transform = font_instance_id.transform;
+        self.insert_font_instance(font_instance_id, |api, external_key, instance_data| {
+            api.add_font_instance_with_transform(external_key, instance_data, transform)
+        })
+    }
+
+    // Whether instances with this id are drawn with a face of their family,
+    // rather than the placeholder face. Nothing is inserted either way.
+    pub fn has_font_family(&self, font_instance_id: FontInstanceId) -> bool {
+        self.instances
+            .contains_key(&self.base_instance_id(font_instance_id))
+    }
+
+    // The instance added along with the face instances with this id are drawn with.
+    fn base_instance_id(&self, font_instance_id: FontInstanceId) -> FontInstanceId {
+        self.resolve_origin(
+            font_instance_id
+                .resize_dpi(Pt::DEFAULT, Dpi::DEFAULT)
+                .with_transform(GlyphTransform::IDENTITY)
+                .with_palette(PaletteSelection::default())
+        )
+    }
+
+    fn insert_font_instance<F>(&mut self, font_instance_id: FontInstanceId, add_font_instance: F) -> Option<RcFontInstance<A>>
+    where
+        F: FnOnce(&mut A, A::FontKey, FontInstanceResourceData) -> A::FontInstanceKey
     {
-        self.context.shape_text_v(instance, text)
+        let base_instance_id = self.base_instance_id(font_instance_id);
+        let font_instance_id = FontInstanceId {
+            origin: base_instance_id.origin,
+            ..font_instance_id
+        };
+        let is_placeholder = !self.instances.contains_key(&base_instance_id);
+        let base = if is_placeholder {
+            self.placeholder_instance()?
+        } else {
+            Rc::clone(&self.instances[&base_instance_id])
+        };
+        let (font_id, external_key, face_hash) = (base.font_id(), base.external_key(), base.face_hash.get());
+
+        let instances = if is_placeholder {
+            &mut self.placeholders
+        } else {
+            &mut self.instances
+        };
+        Some(match instances.entry(font_instance_id) {
+            Entry::Occupied(e) => Rc::clone(e.get()),
+            Entry::Vacant(e) => {
+                let size = font_instance_id.size;
+                let dpi = font_instance_id.dpi;
+                let transform = font_instance_id.transform;
+                let unit = font_instance_id.unit;
+                // Pixel sizes are already at `PIXEL_SIZE_DPI`, so renderers need no unit to
+                // interpret the resource data the same way as the rasterizer.
+                let instance_data = FontInstanceResourceData::new(size, dpi.0);
+                let external_instance_key = add_font_instance(&mut self.api, external_key, instance_data);
+                #[cfg(feature = "key-balance-watchdog")]
+                self.font_instance_keys
+                    .allocated(font_instance_id, external_instance_key);
+                let instance = FontInstance::new_with_unit(font_id, size, dpi, transform, unit, external_key, external_instance_key);
+                instance.set_load_policy(GlyphLoadPolicy::for_display(&self.display_profile));
+                instance.set_ink_bounds(self.glyph_ink_bounds);
+                instance.palette.set(font_instance_id.palette);
+                instance.face_hash.set(face_hash);
+                if let Some(ref cache) = self.shaping_cache {
+                    instance.set_shaping_cache(ShapingCache::clone(cache));
+                }
+                Rc::clone(e.insert(instance))
+            }
+        })
+    }
+
+    // Ids naming no origin fall back to the family registered by an origin,
+    // the same one every time, unless a font without origin registered it.
+    fn resolve_origin(&self, font_instance_id: FontInstanceId) -> FontInstanceId {
+        if font_instance_id.origin.is_some() || self.instances.contains_key(&font_instance_id) {
+            return font_instance_id;
+        }
+        self.instances
+            .keys()
+            .filter(|id| id.origin.is_some() && FontInstanceId { origin: None, ..**id } == font_instance_id)
+            .min()
+            .cloned()
+            .unwrap_or(font_instance_id)
+    }
+
+    pub fn get_origin_font_with_size<O, T>(&mut self, origin: O, family_name: T, size: Pt) -> Option<RcFontInstance<A>>
+    where
+        O: AsRef<str>,
+        T: AsRef<str>
+    {
+        self.get_origin_font_with_size_and_dpi(origin, family_name, size, Dpi::DEFAULT)
+    }
+
+    pub fn get_origin_font_with_size_and_dpi<O, T>(&mut self, origin: O, family_name: T, size: Pt, dpi: Dpi) -> Option<RcFontInstance<A>>
+    where
+        O: AsRef<str>,
+        T: AsRef<str>
+    {
+        let font_instance_id = FontInstanceId::from_family_str(family_name, size, dpi).with_origin(origin);
+        self.get_or_insert_font(font_instance_id)
+    }
+
+    // Missing families are drawn with the synthesized placeholder face when a
+    // metric profile is set, instead of not being found at all. Changing the
+    // profile discards the face previously synthesized and its instances.
+    pub fn set_placeholder_metrics(&mut self, metrics: Option<PlaceholderMetrics>) -> Result<()>
+    where
+        A: TFontKeysAPIExt
+    {
+        if self.placeholder_metrics == metrics {
+            return Ok(());
+        }
+        self.placeholder_metrics = metrics;
+        let font_id = FontId::new(PLACEHOLDER_FAMILY);
+        if self.context.font_ids().contains(&font_id) {
+            self.remove_font(font_id)?;
+            self.paragraphs.borrow_mut().clear();
+        }
+        Ok(())
+    }
+
+    pub fn placeholder_metrics(&self) -> Option<PlaceholderMetrics> {
+        self.placeholder_metrics
+    }
+
+    pub fn is_placeholder(&self, instance: FontInstanceRef<A>) -> bool {
+        instance.font_id() == FontId::new(PLACEHOLDER_FAMILY)
+    }
+
+    // The placeholder face is synthesized on first use, and pinned since it
+    // can be needed again at any time.
+    fn placeholder_instance(&mut self) -> Option<RcFontInstance<A>> {
+        let metrics = self.placeholder_metrics?;
+        let font_instance_id = FontInstanceId::from_family_str(PLACEHOLDER_FAMILY, Pt::DEFAULT, Dpi::DEFAULT);
+        if !self.instances.contains_key(&font_instance_id) {
+            let font_id = FontId::new(PLACEHOLDER_FAMILY);
+            self.add_raw(font_id, placeholder::synthesize(&metrics), 0).ok()?;
+            self.set_font_priority(font_id, ResourcePriority::Pinned);
+        }
+        self.instances.get(&font_instance_id).map(Rc::clone)
+    }
+
+    pub fn pre_register_instances<T>(&mut self, family_name: T, sizes: &[Pt], dpi: Dpi) -> Option<Vec<RcFontInstance<A>>>
+    where
+        T: AsRef<str>
+    {
+        let font_instance_id = FontInstanceId::from_family_str(family_name, Pt::DEFAULT, Dpi::DEFAULT);
+        sizes
+            .iter()
+            .map(|&size| self.get_or_insert_font(font_instance_id.resize_dpi(size, dpi)))
+            .collect()
+    }
+
+    pub fn load_config<P>(&mut self, path: P) -> Result<()>
+    where
+        P: AsRef<Path>
+    {
+        let config = FontConfig::from_path(path)?;
+        self.apply_config(&config)
+    }
+
+    pub fn apply_config(&mut self, config: &FontConfig) -> Result<()> {
+        for face in &config.fonts {
+            let font_id = face.font_id();
+            self.add_raw(font_id, config.load_bytes(face)?, face.face_index)?;
+            self.descriptors.insert(font_id, face.descriptor());
+        }
+        for (alias, family) in &config.aliases {
+            self.set_font_alias(alias.as_str(), family.as_str());
+        }
+        for (family, fallbacks) in &config.fallbacks {
+            self.set_font_fallbacks(family.as_str(), fallbacks.clone());
+        }
+        Ok(())
+    }
+
+    // Loads the file through the file cache and adds all of its faces, named
+    // after their family in the name table, see `face_font_name`. Faces added
+    // before one which fails are removed again.
+    pub fn add_font_file<P>(&mut self, files: &SharedFiles, path: P) -> Result<Vec<FontId>>
+    where
+        P: AsRef<Path>,
+        A: TFontKeysAPIExt
+    {
+        let bytes = load_font_file(files, path.as_ref())?;
+        let mut ids = Vec::new();
+        for face_index in 0..sfnt::face_count(&bytes) {
+            match self.add_font_file_bytes(&bytes, path.as_ref(), face_index) {
+                Ok(font_id) => ids.push(font_id),
+                Err(err) => {
+                    for &font_id in &ids {
+                        let _ = self.remove_font(font_id);
+                    }
+                    Err(err)?;
+                }
+            }
+        }
+        Ok(ids)
+    }
+
+    pub fn add_font_file_face<P>(&mut self, files: &SharedFiles, path: P, face_index: usize) -> Result<FontId>
+    where
+        P: AsRef<Path>
+    {
+        let bytes = load_font_file(files, path.as_ref())?;
+        self.add_font_file_bytes(&bytes, path.as_ref(), face_index)
+    }
+
+    fn add_font_file_bytes(&mut self, bytes: &Rc<Vec<u8>>, path: &Path, face_index: usize) -> Result<FontId> {
+        if face_index >= sfnt::face_count(bytes) {
+            Err(FontError::FaceNotFound)?;
+        }
+        let family = face_family(bytes, path, face_index).ok_or(FontError::FaceFamilyNameMissing)?;
+        let font_id = self.font_id(face_font_name(bytes, path, face_index, &family));
+        self.add_raw(font_id, Rc::clone(bytes), face_index)?;
+        if let Some(info) = sfnt::face_info(bytes, face_index) {
+            self.descriptors
+                .insert(font_id, FontDescriptor::new(family, info.weight, info.style));
+        }
+        Ok(font_id)
+    }
+
+    pub fn set_font_descriptor(&mut self, font_id: FontId, descriptor: FontDescriptor) {
+        self.descriptors.insert(font_id, descriptor);
+    }
+
+    pub fn font_descriptor(&self, font_id: FontId) -> Option<&FontDescriptor> {
+        self.descriptors.get(&font_id)
+    }
+
+    pub fn set_font_alias<S, T>(&mut self, alias: S, family: T)
+    where
+        S: Into<String>,
+        T: Into<String>
+    {
+        self.aliases.insert(alias.into(), family.into());
+    }
+
+    // Follows aliases of aliases, giving up on cycles.
+    pub fn resolve_font_family<'a>(&'a self, family: &'a str) -> &'a str {
+        let mut resolved = family;
+        for _ in 0..self.aliases.len() {
+            match self.aliases.get(resolved) {
+                Some(target) => resolved = target,
+                None => break
+            }
+        }
+        resolved
+    }
+
+    pub fn set_font_fallbacks<T>(&mut self, family: T, fallbacks: Vec<String>)
+    where
+        T: Into<String>
+    {
+        self.fallbacks.insert(family.into(), fallbacks);
+    }
+
+    pub fn font_fallbacks(&self, family: &str) -> &[String] {
+        self.fallbacks
+            .get(self.resolve_font_family(family))
+            .map_or(&[], |fallbacks| fallbacks.as_slice())
+    }
+
+    // Picks the closest face of the first family in the (alias resolved)
+    // fallback chain which has any faces described.
+    pub fn match_font(&self, family: &str, weight: u16, style: FontStyle) -> Option<FontId> {
+        let family = self.resolve_font_family(family);
+        let chain = Some(family)
+            .into_iter()
+            .chain(self.font_fallbacks(family).iter().map(|f| f.as_str()));
+
+        for candidate in chain {
+            let candidate = self.resolve_font_family(candidate);
+            let closest = self.descriptors
+                .iter()
+                .filter(|&(_, descriptor)| descriptor.family == candidate)
+                .min_by_key(|&(_, descriptor)| descriptor.distance(weight, style))
+                .map(|(&font_id, _)| font_id);
+            if closest.is_some() {
+                return closest;
+            }
+        }
+        None
+    }
+
+    pub fn get_or_insert_matching_font(
+        &mut self,
+        family: &str,
+        weight: u16,
+        style: FontStyle,
+        size: Pt,
+        dpi: Dpi
+    ) -> Option<RcFontInstance<A>> {
+        let font_id = self.match_font(family, weight, style)?;
+        let font_instance_id = self.face_instance_id(font_id)?.resize_dpi(size, dpi);
+        self.get_or_insert_font(font_instance_id)
+    }
+
+    // The id of the instance added along with the face, see `insert_font`.
+    fn face_instance_id(&self, font_id: FontId) -> Option<FontInstanceId> {
+        let family_name = self.context.get_family_name(font_id).ok()?;
+        let font_instance_id = FontInstanceId::from_family_str(family_name, Pt::DEFAULT, Dpi::DEFAULT).with_origin_of(font_id);
+        match self.instances.get(&font_instance_id) {
+            Some(instance) if instance.font_id() == font_id => Some(font_instance_id),
+            _ => Some(font_instance_id.with_face_of(font_id))
+        }
+    }
+
+    pub fn font_ids<'a>(&'a self) -> impl Iterator<Item = FontId> + 'a {
+        self.context.font_ids().into_iter()
+    }
+
+    pub fn ids_for_origin<O>(&self, origin: O) -> Vec<FontId>
+    where
+        O: AsRef<str>
+    {
+        let origin = origin.as_ref();
+        self.context
+            .font_ids()
+            .into_iter()
+            .filter(|id| id.is_from_origin(origin))
+            .collect()
+    }
+
+    pub fn evict_origin<O>(&mut self, origin: O) -> Result<usize>
+    where
+        O: AsRef<str>,
+        A: TFontKeysAPIExt
+    {
+        let origin = origin.as_ref();
+        let ids = self.ids_for_origin(origin);
+        for id in &ids {
+            self.remove_font(*id)?;
+        }
+        if !ids.is_empty() {
+            self.paragraphs.borrow_mut().clear();
+        }
+        if self.default_font.map_or(false, |id| id.is_from_origin(origin)) {
+            self.default_font = None;
+        }
+        Ok(ids.len())
+    }
+
+    pub fn set_font_priority(&mut self, font_id: FontId, priority: ResourcePriority) {
+        self.priorities.insert(font_id, priority);
+    }
+
+    pub fn font_priority(&self, font_id: FontId) -> ResourcePriority {
+        self.priorities
+            .get(&font_id)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub fn used_bytes(&self) -> usize {
+        self.context
+            .font_ids()
+            .into_iter()
+            .filter_map(|id| self.context.get_bytes(id).ok())
+            .map(|bytes| bytes.len())
+            .sum()
+    }
+
+    // Evicts fonts of the given priority, largest first, until at least
+    // `bytes` have been freed. Pinned fonts are never evicted.
+    pub fn evict_bytes(&mut self, priority: ResourcePriority, bytes: usize) -> Result<Vec<(FontId, usize)>>
+    where
+        A: TFontKeysAPIExt
+    {
+        if !priority.is_evictable() {
+            return Ok(Vec::new());
+        }
+        let candidates = self.context
+            .font_ids()
+            .into_iter()
+            .filter(|&id| self.font_priority(id) == priority)
+            .filter_map(|id| Some((id, self.context.get_bytes(id).ok()?.len())))
+            .collect();
+        let evicted = quota::select_for_eviction(candidates, bytes);
+        for &(id, _) in &evicted {
+            self.remove_font(id)?;
+        }
+        if !evicted.is_empty() {
+            self.paragraphs.borrow_mut().clear();
+        }
+        Ok(evicted)
+    }
+
+    pub fn expiry_policy(&self) -> &ExpiryPolicy {
+        self.expiry.policy()
+    }
+
+    pub fn set_expiry_policy(&mut self, policy: ExpiryPolicy) {
+        self.expiry.set_policy(policy);
+    }
+
+    // Evicts fonts whose TTL has elapsed at `now_ms`, e.g. once per frame.
+    // Pinned fonts never expire.
+    pub fn sweep_expired(&mut self, now_ms: u64) -> Result<Vec<(FontId, usize)>>
+    where
+        A: TFontKeysAPIExt
+    {
+        if self.expiry.policy().never_expires() {
+            return Ok(Vec::new());
+        }
+        self.sweep_derived_instances(now_ms);
+        let loaded: Vec<_> = self.context
+            .font_ids()
+            .into_iter()
+            .filter(|&id| self.font_priority(id).is_evictable())
+            .collect();
+        let expired = self.expiry
+            .sweep(loaded, now_ms, |id, origin| id.is_from_origin(origin));
+        let mut evicted = Vec::with_capacity(expired.len());
+        for id in expired {
+            let bytes = self.context.get_bytes(id)?.len();
+            self.remove_font(id)?;
+            evicted.push((id, bytes));
+        }
+        if !evicted.is_empty() {
+            self.paragraphs.borrow_mut().clear();
+        }
+        Ok(evicted)
+    }
+
+    // Removes the face along with its instances, deleting their keys. The
+    // default font moves to another font when its face is removed.
+    pub fn remove_font(&mut self, font_id: FontId) -> Result<()>
+    where
+        A: TFontKeysAPIExt
+    {
+        let removes_default = self.default_font.map_or(false, |default_font| {
+            self.instances
+                .iter()
+                .any(|(instance_id, instance)| instance.font_id() == font_id && default_font.finds_face_of(instance_id))
+        });
+        let evicted: Vec<_> = self.instances
+            .iter()
+            .filter(|&(_, instance)| instance.font_id() == font_id)
+            .map(|(&instance_id, _)| instance_id)
+            .collect();
+        let placeholders: Vec<_> = self.placeholders
+            .iter()
+            .filter(|&(_, instance)| instance.font_id() == font_id)
+            .map(|(&instance_id, _)| instance_id)
+            .collect();
+        let mut font_key = None;
+        for instance_id in placeholders {
+            if let Some(instance) = self.placeholders.remove(&instance_id) {
+                self.api.delete_font_instance(instance.external_instance_key);
+                #[cfg(feature = "key-balance-watchdog")]
+                self.font_instance_keys
+                    .deleted(instance.external_instance_key);
+            }
+        }
+        for instance_id in evicted {
+            if let Some(instance) = self.instances.remove(&instance_id) {
+                self.api.delete_font_instance(instance.external_instance_key);
+                #[cfg(feature = "key-balance-watchdog")]
+                self.font_instance_keys
+                    .deleted(instance.external_instance_key);
+                font_key = Some(instance.external_key);
+            }
+        }
+        if let Some(font_key) = font_key {
+            self.api.delete_font(font_key);
+            #[cfg(feature = "key-balance-watchdog")]
+            self.font_keys.deleted(font_key);
+        }
+        let instances = &self.instances;
+        let placeholders = &self.placeholders;
+        self.derived_instances
+            .retain(|instance_id, _| instances.contains_key(instance_id) || placeholders.contains_key(instance_id));
+        self.priorities.remove(&font_id);
+        self.descriptors.remove(&font_id);
+        self.shared_memory.remove(&font_id);
+        self.snapshot_bytes.borrow_mut().remove(&font_id);
+        self.retained_chars.remove(&font_id);
+        self.sent_subsets.remove(&font_id);
+        if let Some(ref cache) = self.shaping_cache {
+            cache.discard_font(font_id);
+        }
+        if removes_default {
+            self.default_font = self.instances.keys().min().cloned();
+        }
+        self.context.remove_face(font_id)
+    }
+
+    #[cfg(feature = "key-balance-watchdog")]
+    pub fn leaked_font_keys(&self) -> Vec<(FontId, A::FontKey)>
+    where
+        A::FontKey: PartialEq
+    {
+        self.font_keys
+            .leaked(|key| self.instances.values().any(|instance| instance.external_key == key))
+    }
+
+    #[cfg(feature = "key-balance-watchdog")]
+    pub fn leaked_font_instance_keys(&self) -> Vec<(FontInstanceId, A::FontInstanceKey)>
+    where
+        A::FontInstanceKey: PartialEq
+    {
+        self.font_instance_keys.leaked(|key| {
+            self.instances
+                .values()
+                .chain(self.placeholders.values())
+                .any(|instance| instance.external_instance_key == key)
+        })
+    }
+
+    #[cfg(feature = "key-balance-watchdog")]
+    pub fn unknown_deleted_keys(&self) -> (&[A::FontKey], &[A::FontInstanceKey]) {
+        (
+            self.font_keys.unknown_deletes(),
+            self.font_instance_keys.unknown_deletes()
+        )
+    }
+
+    // Placeholder instances are included, under the family they stand for.
+    pub fn key_snapshot(&self) -> FontKeySnapshot<A::FontKey, A::FontInstanceKey>
+    where
+        A::FontKey: Copy + PartialEq,
+        A::FontInstanceKey: Copy + PartialEq
+    {
+        let all_instances = || self.instances.iter().chain(self.placeholders.iter());
+        FontKeySnapshot {
+            fonts: self.instances
+                .values()
+                .map(|instance| (instance.font_id, instance.external_key))
+                .collect(),
+            instances: all_instances()
+                .map(|(&instance_id, instance)| (instance_id, instance.external_instance_key))
+                .collect()
+        }
+    }
+
+    // Like `TFontCache::take_resource_updates`, for caches used without `SharedFonts`.
+    pub fn take_resource_updates(&mut self) -> A::ResourceUpdates {
+        self.api.take_resource_updates()
+    }
+
+    // See `TFontKeysAPIExt::take_raw_resource_updates`.
+    pub fn take_raw_resource_updates(&mut self) -> A::ResourceUpdates
+    where
+        A: TFontKeysAPIExt
+    {
+        self.api.take_raw_resource_updates()
+    }
+
+    // Like `ImageCache::reserve_keys`.
+    pub fn reserve_keys(&mut self, snapshot: &FontKeySnapshot<A::FontKey, A::FontInstanceKey>)
+    where
+        A: TFontKeysAPIExt,
+        A::FontKey: Copy + PartialEq,
+        A::FontInstanceKey: Copy + PartialEq
+    {
+        let keys: Vec<_> = snapshot.fonts.keys().collect();
+        let instance_keys: Vec<_> = snapshot.instances.keys().collect();
+        self.api.reserve_font_keys(&keys, &instance_keys);
+    }
+
+    // Sends again, under their current keys, the fonts and instances missing
+    // from what the renderer still knows, and deletes the ones it holds for
+    // nothing. Instances of replayed fonts are always replayed, since the
+    // renderer can't have kept them without their font. Returns what was replayed.
+    pub fn replay_keys(&mut self, known: &FontKeySnapshot<A::FontKey, A::FontInstanceKey>) -> Result<FontKeySnapshot<A::FontKey, A::FontInstanceKey>>
+    where
+        A: TFontKeysAPIExt,
+        A::FontKey: Copy + PartialEq,
+        A::FontInstanceKey: Copy + PartialEq
+    {
+        let current = self.key_snapshot();
+        let font_diff = current.fonts.diff(&known.fonts);
+        let instance_diff = current.instances.diff(&known.instances);
+
+        for &(font_id, key) in &font_diff.replay {
+            let face_index = self.get_face_index(font_id)?;
+            let replayed = if self.subsetting {
+                let (bytes, glyphs) = self.subset_bytes(font_id, &self.sfnt_bytes(font_id)?);
+                self.sent_subsets.insert(font_id, glyphs);
+                let resource = FontResourceData { bytes: &bytes, face_index };
+                self.api.replay_font(key, FontEncodedData::Bytes { bytes: &bytes }, resource)
+            } else {
+                let encoded = EncodedFont::from_bytes(self.get_bytes(font_id)?)?;
+                let decoded = DecodedFont::from_encoded_font(&encoded, face_index);
+                self.api.replay_font(key, encoded.info(), decoded.info())
+            };
+            if !replayed {
+                Err(FontError::ReplayUnsupported)?;
+            }
+        }
+
+        let replayed_fonts: KeySnapshot<_, _> = font_diff.replay.into_iter().collect();
+        let mut replayed_instances: KeySnapshot<_, _> = instance_diff.replay.into_iter().collect();
+        for &(instance_id, instance_key) in current.instances.entries() {
+            let font_id = self.get_font_instance(instance_id).map(|instance| instance.font_id);
+            if font_id.and_then(|font_id| replayed_fonts.get(font_id)).is_some() {
+                replayed_instances.insert(instance_id, instance_key);
+            }
+        }
+        for &(instance_id, instance_key) in replayed_instances.entries() {
+            let instance = match self.get_font_instance(instance_id) {
+                Some(instance) => instance,
+                None => continue
+            };
+            let instance_data = FontInstanceResourceData::new(instance.size, instance.dpi.0);
+            if !self.api
+                .replay_font_instance(instance_key, instance.external_key, instance_data, instance.transform)
+            {
+                Err(FontError::ReplayUnsupported)?;
+            }
+        }
+
+        for &(_, instance_key) in &instance_diff.stale {
+            if !current.instances.keys().any(|key| key == instance_key) {
+                self.api.delete_font_instance(instance_key);
+            }
+        }
+        for &(_, key) in &font_diff.stale {
+            if !current.fonts.keys().any(|current_key| current_key == key) {
+                self.api.delete_font(key);
+            }
+        }
+
+        Ok(FontKeySnapshot {
+            fonts: replayed_fonts,
+            instances: replayed_instances
+        })
+    }
+
+    pub fn iter_instances<'a>(&'a self) -> impl Iterator<Item = FontInstanceInfo<A::FontKey, A::FontInstanceKey>> + 'a {
+        self.instances
+            .iter()
+            .map(|(&instance_id, instance)| FontInstanceInfo {
+                instance_id,
+                font_id: instance.font_id(),
+                font_key: instance.external_key(),
+                font_instance_key: instance.external_instance_key(),
+                shaped_text_entries: instance.shaped_text_entries()
+            })
+    }
+
+    // Faces are only copied by the first snapshot taken after they're added.
+    pub fn snapshot(&self) -> FontCacheSnapshot<A::FontKey, A::FontInstanceKey> {
+        let mut font_ids = self.context.font_ids();
+        font_ids.sort();
+
+        let mut snapshot_bytes = self.snapshot_bytes.borrow_mut();
+        let mut faces = Vec::with_capacity(font_ids.len());
+        for font_id in font_ids {
+            let bytes = match snapshot_bytes.entry(font_id) {
+                Entry::Occupied(e) => Some(Arc::clone(e.get())),
+                Entry::Vacant(e) => self.context
+                    .get_bytes(font_id)
+                    .ok()
+                    .map(|bytes| Arc::clone(e.insert(Arc::new(Vec::clone(&bytes)))))
+            };
+            faces.push(FaceSnapshot {
+                font_id,
+                family_name: self.context.get_family_name(font_id).ok().map(String::from),
+                face_index: self.context.get_face_index(font_id).unwrap_or_default(),
+                bytes
+            });
+        }
+
+        let mut instances = self.iter_instances().collect::<Vec<_>>();
+        instances.sort_by_key(|info| (info.font_id, info.instance_id.size(), info.instance_id.dpi()));
+        FontCacheSnapshot { faces, instances }
+    }
+
+    pub fn dump(&self) -> String
+    where
+        A::FontKey: Debug,
+        A::FontInstanceKey: Debug
+    {
+        let mut infos = self.iter_instances().collect::<Vec<_>>();
+        infos.sort_by_key(|info| {
+            (
+                info.font_id,
+                info.instance_id.size(),
+                info.instance_id.dpi()
+            )
+        });
+
+        let mut dump = String::new();
+        for info in infos {
+            let family_name = self
+                .context
+                .get_family_name(info.font_id)
+                .unwrap_or("<unknown>");
+            let _ = writeln!(
+                dump,
+                "{} size={} dpi={} font_key={:?} font_instance_key={:?} shaped_text_entries={}",
+                family_name,
+                info.instance_id.size(),
+                info.instance_id.dpi().0,
+                info.font_key,
+                info.font_instance_key,
+                info.shaped_text_entries
+            );
+        }
+        dump
+    }
+
+    pub fn get_bytes(&self, font_id: FontId) -> Result<Rc<Vec<u8>>> {
+        self.errors.track(self.context.get_bytes(font_id), FontError::category)
+    }
+
+    pub fn get_data_uri(&self, font_id: FontId) -> Result<String> {
+        let bytes = self.get_bytes(font_id)?;
+        Ok(base64_util::to_font_data_uri(&bytes))
+    }
+
+    pub fn get_face_index(&self, font_id: FontId) -> Result<usize> {
+        self.errors.track(self.context.get_face_index(font_id), FontError::category)
+    }
+
+    pub fn get_family_name(&self, font_id: FontId) -> Result<&str> {
+        self.errors.track(self.context.get_family_name(font_id), FontError::category)
+    }
+
+    // Palettes a color font's glyphs can be drawn with, see `FontInstanceId::with_palette`.
+    pub fn get_palettes(&self, font_id: FontId) -> Result<Vec<ColorPalette>> {
+        self.errors.track(self.context.get_palettes(font_id), FontError::category)
+    }
+
+    // Which scripts and blocks the face's character map covers, e.g. to pick
+    // fallbacks or to tell why some text renders as tofu.
+    // Exports the measurements `MetricsFontBackend` needs to lay out text in
+    // the given chars without this face's file.
+    pub fn export_metrics<I>(&self, font_id: FontId, chars: I) -> Result<FontMetricsTable>
+    where
+        I: IntoIterator<Item = char>
+    {
+        FontMetricsTable::export(&self.context, font_id, chars)
+    }
+
+    pub fn script_coverage(&self, font_id: FontId) -> Result<CoverageReport> {
+        self.errors.track(self.context.get_coverage(font_id), FontError::category)
+    }
+
+    pub fn get_glyph_index(&self, instance: FontInstanceRef<A>, c: char) -> Result<u32> {
+        self.errors.track(self.context.get_glyph_index(instance, c), FontError::category)
+    }
+
+    pub fn get_glyph_dimensions(&self, instance: FontInstanceRef<A>, c: char) -> Result<GlyphDimensions> {
+        self.errors.track(self.context.get_glyph_dimensions(instance, c), FontError::category)
+    }
+
+    // Measures a single char without shaping it, memoized per instance. Meant
+    // for grid layouts, which measure the same few chars over and over.
+    pub fn measure_char(&self, instance: FontInstanceRef<A>, c: char) -> Result<GlyphDimensions> {
+        if let Some(dimensions) = instance.measured_chars_cache.borrow().get(&c) {
+            return Ok(*dimensions);
+        }
+        let dimensions = self.get_glyph_dimensions(instance, c)?;
+        instance.measured_chars_cache.borrow_mut().insert(c, dimensions);
+        Ok(dimensions)
+    }
+
+    // Horizontal adjustment between two chars drawn next to each other, in
+    // 26.6 pixels, for layouts placing chars themselves, e.g. terminals.
+    // Memoized per instance. Instances with kerning disabled don't adjust anything.
+    pub fn get_kerning(&self, instance: FontInstanceRef<A>, left: char, right: char) -> Result<i32> {
+        if !instance.kerning() {
+            return Ok(0);
+        }
+        if let Some(kerning) = instance.kerning_pairs_cache.borrow().get(&(left, right)) {
+            return Ok(*kerning);
+        }
+        let left_glyph = self.get_glyph_index(instance, left)?;
+        let right_glyph = self.get_glyph_index(instance, right)?;
+        let (kerning, _) = self.errors.track(
+            self.context.get_kerning(instance, left_glyph, right_glyph),
+            FontError::category
+        )?;
+        let mut cache = instance.kerning_pairs_cache.borrow_mut();
+        if cache.len() >= KERNING_PAIRS_CACHE_CAPACITY {
+            cache.clear();
+        }
+        cache.insert((left, right), kerning);
+        Ok(kerning)
+    }
+
+    pub fn cell_metrics(&self, instance: FontInstanceRef<A>) -> Result<CellMetrics> {
+        if let Some(metrics) = instance.cell_metrics_cache.get() {
+            return Ok(metrics);
+        }
+        let size_metrics = self.get_global_size_metrics(instance)?;
+        let metrics = CellMetrics {
+            zero_advance_64: self.measure_char(instance, '0')?.hori_advance_64,
+            em_advance_64: self.measure_char(instance, 'M')?.hori_advance_64,
+            ascender_64: size_metrics.ascender_64,
+            descender_64: size_metrics.descender_64,
+            line_height_64: size_metrics.height_64
+        };
+        instance.cell_metrics_cache.set(Some(metrics));
+        Ok(metrics)
+    }
+
+    pub fn get_glyph_vertical_bearings(&self, instance: FontInstanceRef<A>, c: char) -> Result<GlyphVerticalBearings> {
+        self.errors.track(self.context.get_glyph_vertical_bearings(instance, c), FontError::category)
+    }
+
+    pub fn get_glyph_linear_advance(&self, instance: FontInstanceRef<A>, c: char) -> Result<GlyphLinearAdvance> {
+        self.errors.track(self.context.get_glyph_linear_advance(instance, c), FontError::category)
+    }
+
+    pub fn get_linear_glyph_metrics(&self, instance: FontInstanceRef<A>, c: char) -> Result<LinearGlyphMetrics> {
+        self.errors.track(self.context.get_linear_glyph_metrics(instance, c), FontError::category)
+    }
+
+    // Metrics of many chars at once, shared by all instances of the face.
+    pub fn get_design_glyph_metrics(&self, font_id: FontId, chars: &[char]) -> Result<Vec<DesignGlyphMetrics>> {
+        self.errors.track(self.context.get_design_glyph_metrics(font_id, chars), FontError::category)
+    }
+
+    pub fn get_glyph_bitmap(&self, instance: FontInstanceRef<A>, c: char) -> Result<GlyphBitmap> {
+        self.errors.track(self.context.get_glyph_bitmap(instance, c), FontError::category)
+    }
+
+    pub fn rasterize_glyph(&self, instance: FontInstanceRef<A>, glyph_index: u32) -> Result<GlyphBitmap> {
+        self.errors.track(self.context.rasterize_glyph(instance, glyph_index), FontError::category)
+    }
+
+    pub fn get_glyph_subpixel_bitmap(&self, instance: FontInstanceRef<A>, c: char) -> Result<GlyphBitmap> {
+        let bitmap = self.context
+            .get_glyph_subpixel_bitmap(instance, c, self.display_profile.subpixel_layout);
+        self.errors.track(bitmap, FontError::category)
+    }
+
+    // Rasterizes the glyph for the current display profile, unless the atlas
+    // already holds it for this instance.
+    pub fn get_atlas_glyph(&self, atlas: &mut GlyphAtlas<A::FontInstanceKey>, instance: FontInstanceRef<A>, c: char) -> Result<AtlasSlotId> {
+        let instance_key = instance.external_instance_key();
+        let glyph_index = self.get_glyph_index(instance, c)?;
+        match atlas.lookup(instance_key, glyph_index) {
+            Some(slot_id) => Ok(slot_id),
+            None => Ok(atlas.insert(instance_key, self.get_glyph_subpixel_bitmap(instance, c)?))
+        }
+    }
+
+    // For glyphs positioned at fractional pixels, see `SubpixelOffset::split_x_64`.
+    pub fn get_atlas_glyph_at_offset(
+        &self,
+        atlas: &mut GlyphAtlas<A::FontInstanceKey>,
+        instance: FontInstanceRef<A>,
+        c: char,
+        offset: SubpixelOffset
+    ) -> Result<AtlasSlotId> {
+        let instance_key = instance.external_instance_key();
+        let glyph_index = self.get_glyph_index(instance, c)?;
+        if let Some(slot_id) = atlas.lookup_at_offset(instance_key, glyph_index, offset) {
+            return Ok(slot_id);
+        }
+        let bitmap = self.context
+            .get_glyph_subpixel_bitmap_at_offset(instance, c, self.display_profile.subpixel_layout, offset);
+        let bitmap = self.errors.track(bitmap, FontError::category)?;
+        Ok(atlas.insert_at_offset(instance_key, bitmap, offset))
+    }
+
+    // Shadows only need coverage, so they're blurred from the grayscale
+    // rasterization regardless of the display profile.
+    pub fn get_atlas_blurred_glyph(
+        &self,
+        atlas: &mut GlyphAtlas<A::FontInstanceKey>,
+        instance: FontInstanceRef<A>,
+        c: char,
+        radius: u32
+    ) -> Result<AtlasSlotId> {
+        if radius == 0 {
+            return self.get_atlas_glyph(atlas, instance, c);
+        }
+        let instance_key = instance.external_instance_key();
+        let glyph_index = self.get_glyph_index(instance, c)?;
+        match atlas.lookup_blurred(instance_key, glyph_index, radius) {
+            Some(slot_id) => Ok(slot_id),
+            None => Ok(atlas.insert_blurred(instance_key, &self.get_glyph_bitmap(instance, c)?, radius))
+        }
+    }
+
+    // Queues the atlas pages and glyph regions added since the last flush
+    // with the rest of the resource updates.
+    pub fn flush_atlas_updates(&mut self, atlas: &mut GlyphAtlas<A::FontInstanceKey>) -> usize
+    where
+        A: TFontKeysAPIExt
+    {
+        atlas.flush_updates(&mut self.api)
+    }
+
+    pub fn error_counters(&self) -> ErrorCounters {
+        self.errors.counters()
+    }
+
+    // Returns the counts since the last reset, e.g. to report them per frame.
+    pub fn reset_error_counters(&self) -> ErrorCounters {
+        self.errors.reset()
+    }
+
+    // Errors of mutations queued through `SharedFonts` while it was borrowed,
+    // oldest first, since the last call.
+    pub fn take_deferred_errors(&self) -> Vec<DeferredError> {
+        self.errors.take_deferred()
+    }
+
+    // Errors are tracked by the mutations themselves, so only recorded here.
+    fn record_deferred<T>(&self, result: Result<T>) {
+        if let Err(err) = result {
+            self.errors.record_deferred(err.code(), err.message());
+        }
+    }
+
+    // Hashes a font name into an id, checking it for collisions if enabled.
+    pub fn font_id<P>(&self, font_name: P) -> FontId
+    where
+        P: AsRef<str>
+    {
+        self.ids.check(FontId::new(&font_name), font_name)
+    }
+
+    pub fn font_id_with_origin<O, P>(&self, origin: O, font_name: P) -> FontId
+    where
+        O: AsRef<str>,
+        P: AsRef<str>
+    {
+        let id = FontId::with_origin(&origin, &font_name);
+        self.ids
+            .check(id, format!("{}\0{}", origin.as_ref(), font_name.as_ref()))
+    }
+
+    pub fn id_collision_checks(&self) -> bool {
+        self.ids.is_enabled()
+    }
+
+    pub fn set_id_collision_checks(&mut self, enabled: bool) {
+        self.ids.set_enabled(enabled);
+    }
+
+    pub fn id_counters(&self) -> IdCounters {
+        self.ids.counters()
+    }
+
+    pub fn reset_id_counters(&self) -> IdCounters {
+        self.ids.reset()
+    }
+
+    pub fn lcd_filter(&self) -> LcdFilter {
+        self.context.lcd_filter()
+    }
+
+    pub fn set_lcd_filter(&mut self, filter: LcdFilter) -> Result<()> {
+        self.context.set_lcd_filter(filter)
+    }
+
+    pub fn get_global_size_metrics(&self, instance: FontInstanceRef<A>) -> Result<FontSizeMetrics> {
+        self.errors.track(self.context.get_global_size_metrics(instance), FontError::category)
+    }
+
+    pub fn shape_text_h<T>(
+        &self,
+        instance: FontInstanceRef<A>,
+        text: T
+    ) -> Result<GlyphStore<A::FontKey, A::FontInstanceKey, A::GlyphInstance>>
+    where
+        T: AsRef<str>
+    {
+        let text = self.normalization.normalize(text.as_ref());
+        let glyphs = self.errors.track(self.context.shape_text_h(instance, &*text), FontError::category)?;
+        Ok(glyphs.with_source(&text, self.glyph_source))
+    }
+
+    // Width and height of the text as `shape_text_h` would lay it out, kerning
+    // included, without allocating glyphs or caching a store. Runs already
+    // shaped through the instance are measured from their store.
+    pub fn measure_text_h<T>(&self, instance: FontInstanceRef<A>, text: T) -> Result<(i32, i32)>
+    where
+        T: AsRef<str>
+    {
+        let text = self.normalization.normalize(text.as_ref());
+        self.errors.track(self.context.measure_text_h(instance, &*text), FontError::category)
+    }
+
+    // Shapes text fitting within `max_width_64`, cutting it at the last cluster
+    // that leaves room for the ellipsis after it. Widths are measured without
+    // building stores, so only the kept text is shaped again. Returns the
+    // glyphs along with how many chars of the given text they show, which
+    // includes whitespace trimmed before the ellipsis, and counts chars before
+    // normalization. Text fitting as is is returned without an ellipsis, and
+    // the ellipsis alone if nothing else fits.
+    pub fn shape_text_h_truncated<T, E>(
+        &self,
+        instance: FontInstanceRef<A>,
+        text: T,
+        max_width_64: i32,
+        ellipsis: E
+    ) -> Result<(GlyphStore<A::FontKey, A::FontInstanceKey, A::GlyphInstance>, usize)>
+    where
+        T: AsRef<str>,
+        E: AsRef<str>,
+        A::GlyphInstance: TGlyphInstanceExt
+    {
+        let original = text.as_ref();
+        let text = self.normalization.normalize(original);
+        let shape = |text: &str| self.errors.track(self.context.shape_text_h(instance, text), FontError::category);
+        let glyphs = shape(&*text)?;
+        if glyphs.width_64 <= max_width_64 {
+            return Ok((glyphs.with_source(&text, self.glyph_source), original.chars().count()));
+        }
+
+        let ellipsis = shape(&*self.normalization.normalize(ellipsis.as_ref()))?;
+        let budget_64 = max_width_64.saturating_sub(ellipsis.width_64);
+        let mut cuts: Vec<usize> = match glyphs.clusters {
+            Some(ref clusters) => clusters.iter().map(|&cluster| cluster as usize).collect(),
+            None => text.char_indices().map(|(offset, _)| offset).collect()
+        };
+        cuts.push(0);
+        cuts.sort();
+        cuts.dedup();
+
+        // Kept text only gets wider the longer it is, and the whole text is
+        // already known not to fit, so search for the last cut that does.
+        let (mut low, mut high) = (0, cuts.len() - 1);
+        while low < high {
+            let middle = (low + high + 1) / 2;
+            let kept = text[..cuts[middle]].trim_right();
+            let (width_64, _) = self.errors
+                .track(self.context.measure_text_h(instance, kept), FontError::category)?;
+            if width_64 <= budget_64 {
+                low = middle;
+            } else {
+                high = middle - 1;
+            }
+        }
+
+        // Cuts inside text changed by normalization move back to where the
+        // given text can be cut, which only leaves less text to fit.
+        let (original_cut, cut) = self.normalization.prefix_lens(original, cuts[low]);
+        let kept = text[..cut].trim_right();
+        let glyphs = shape(kept)?.appended(&ellipsis);
+        Ok((glyphs.with_source(kept, self.glyph_source), original[..original_cut].chars().count()))
+    }
+
+    pub fn shape_text_h_with_options<T>(
+        &self,
+        instance: FontInstanceRef<A>,
+        text: T,
+        options: &ShapingOptions
+    ) -> Result<GlyphStore<A::FontKey, A::FontInstanceKey, A::GlyphInstance>>
+    where
+        T: AsRef<str>
+    {
+        self.shape_text_h(instance, options.transform(text.as_ref()))
+    }
+
+    pub fn shape_text_v_with_options<T>(
+        &self,
+        instance: FontInstanceRef<A>,
+        text: T,
+        options: &ShapingOptions
+    ) -> Result<GlyphStore<A::FontKey, A::FontInstanceKey, A::GlyphInstance>>
+    where
+        T: AsRef<str>
+    {
+        self.shape_text_v(instance, options.transform(text.as_ref()))
+    }
+
+    // Shift and scale of the position in thousandths of an em, taken from the
+    // face where it has superscript and subscript metrics.
+    pub fn baseline_position_metrics(&self, font_id: FontId, position: BaselinePosition) -> (i16, u16) {
+        let face_metrics = match (self.context.get_bytes(font_id), self.context.get_face_index(font_id)) {
+            (Ok(bytes), Ok(face_index)) => sfnt::script_metrics(&bytes, face_index),
+            _ => None
+        };
+        position.resolve(face_metrics)
+    }
+
+    // Shapes a span positioned off the baseline, e.g. a superscript, with an
+    // instance derived from the given one at the span's scale. Glyphs are
+    // placed relative to the given instance's baseline, so the span lines up
+    // with runs shaped through it. Derived instances are created on first use,
+    // and released along with their face, by `release_derived_instances`, or
+    // by `sweep_expired` once unused by spans for their face's TTL.
+    pub fn shape_span_h<T>(
+        &mut self,
+        instance: FontInstanceRef<A>,
+        text: T,
+        options: &ShapingOptions
+    ) -> Result<GlyphStore<A::FontKey, A::FontInstanceKey, A::GlyphInstance>>
+    where
+        T: AsRef<str>,
+        A::GlyphInstance: TGlyphInstanceExt
+    {
+        let (shift, scale) = self.baseline_position_metrics(instance.font_id(), options.baseline_position);
+        if (shift, scale) == (0, 1000) {
+            return self.shape_text_h_with_options(instance, text, options);
+        }
+        let derived = self.get_derived_instance(instance, scale);
+        let derived = self.errors
+            .track(derived.ok_or(FontError::FaceNotFound), FontError::category)?;
+
+        let metrics = self.get_global_size_metrics(instance)?;
+        let derived_metrics = self.get_global_size_metrics(&derived)?;
+        let shift_64 = (instance.pixel_size_64() * i64::from(shift) / 1000) as i32;
+        let glyphs = self.shape_text_h_with_options(&derived, text, options)?;
+        let height_64 = metrics.height_64;
+        Ok(GlyphStore {
+            height_64,
+            ..glyphs.shifted(0, metrics.ascender_64 - derived_metrics.ascender_64 - shift_64)
+        })
+    }
+
+    // Instances of the same face, size unit and dpi, scaled by thousandths.
+    fn get_derived_instance(&mut self, instance: FontInstanceRef<A>, scale: u16) -> Option<RcFontInstance<A>> {
+        let base_id = self.instances
+            .iter()
+            .chain(self.placeholders.iter())
+            .find(|&(_, candidate)| ptr::eq(&**candidate, instance))
+            .map(|(&instance_id, _)| instance_id)?;
+        let size = ((u64::from(base_id.size) * u64::from(scale) + 500) / 1000).max(1) as u32;
+        let derived_id = match base_id.unit {
+            FontSizeUnit::Points => base_id.resize(Pt(size)),
+            FontSizeUnit::Pixels => base_id.resize_px(Px(size))
+        };
+        // Instances registered otherwise, e.g. by `add_font`, aren't the span's to release.
+        let exists = self.instances.contains_key(&derived_id) || self.placeholders.contains_key(&derived_id);
+        let derived = self.get_or_insert_font(derived_id)?;
+        if !exists || self.derived_instances.contains_key(&derived_id) {
+            self.derived_instances.insert(derived_id, None);
+        }
+        Some(derived)
+    }
+
+    // Drops derived instances nothing outside the cache holds on to, deleting
+    // their keys. Call it once the glyphs `shape_span_h` returned are no longer
+    // rendered, e.g. after a relayout. Returns how many were released.
+    pub fn release_derived_instances(&mut self) -> usize
+    where
+        A: TFontKeysAPIExt
+    {
+        let unused: Vec<_> = self.derived_instances
+            .keys()
+            .cloned()
+            .filter(|instance_id| self.is_derived_instance_unused(instance_id))
+            .collect();
+        unused
+            .into_iter()
+            .filter(|&instance_id| self.release_derived_instance(instance_id))
+            .count()
+    }
+
+    // Releases derived instances no span used for their face's TTL. Their
+    // TTL restarts whenever `shape_span_h` uses them again.
+    fn sweep_derived_instances(&mut self, now_ms: u64)
+    where
+        A: TFontKeysAPIExt
+    {
+        let mut expired = Vec::new();
+        for (instance_id, used_at_ms) in &mut self.derived_instances {
+            let since_ms = *used_at_ms.get_or_insert(now_ms);
+            let ttl_ms = self.expiry
+                .policy()
+                .ttl_ms(|origin| instance_id.is_from_origin(origin));
+            match ttl_ms {
+                Some(ttl_ms) if now_ms.saturating_sub(since_ms) >= ttl_ms => expired.push(*instance_id),
+                _ => {}
+            }
+        }
+        for instance_id in expired {
+            if self.is_derived_instance_unused(&instance_id) {
+                self.release_derived_instance(instance_id);
+            }
+        }
+    }
+
+    fn is_derived_instance_unused(&self, instance_id: &FontInstanceId) -> bool {
+        self.instances
+            .get(instance_id)
+            .map_or(true, |instance| Rc::strong_count(instance) == 1)
+    }
+
+    fn release_derived_instance(&mut self, instance_id: FontInstanceId) -> bool
+    where
+        A: TFontKeysAPIExt
+    {
+        self.derived_instances.remove(&instance_id);
+        let instance = match self.instances.remove(&instance_id).or_else(|| self.placeholders.remove(&instance_id)) {
+            Some(instance) => instance,
+            None => return false
+        };
+        self.api.delete_font_instance(instance.external_instance_key);
+        #[cfg(feature = "key-balance-watchdog")]
+        self.font_instance_keys
+            .deleted(instance.external_instance_key);
+        true
+    }
+
+    pub fn derived_instance_count(&self) -> usize {
+        self.derived_instances.len()
+    }
+
+    pub fn shape_text_v<T>(
+        &self,
+        instance: FontInstanceRef<A>,
+        text: T
+    ) -> Result<GlyphStore<A::FontKey, A::FontInstanceKey, A::GlyphInstance>>
+    where
+        T: AsRef<str>
+    {
+        let text = self.normalization.normalize(text.as_ref());
+        let glyphs = self.errors.track(self.context.shape_text_v(instance, &*text), FontError::category)?;
+        Ok(glyphs.with_source(&text, self.glyph_source))
+    }
+
+    // Glyphs of transient runs live in the arena until it's reset, and
+    // aren't cached, so shaping them never grows the instance's caches.
+    pub fn shape_text_h_transient<T>(
+        &self,
+        instance: FontInstanceRef<A>,
+        text: T,
+        arena: &mut ShapingArena<A::GlyphInstance>
+    ) -> Result<TransientRun>
+    where
+        T: AsRef<str>,
+        A::GlyphInstance: Clone
+    {
+        let text = self.normalization.normalize(text.as_ref());
+        self.errors.track(self.context.shape_text_transient(instance, &*text, false, arena), FontError::category)
+    }
+
+    pub fn shape_text_v_transient<T>(
+        &self,
+        instance: FontInstanceRef<A>,
+        text: T,
+        arena: &mut ShapingArena<A::GlyphInstance>
+    ) -> Result<TransientRun>
+    where
+        T: AsRef<str>,
+        A::GlyphInstance: Clone
+    {
+        let text = self.normalization.normalize(text.as_ref());
+        self.errors.track(self.context.shape_text_transient(instance, &*text, true, arena), FontError::category)
+    }
+
+    pub fn glyph_source_mode(&self) -> GlyphSourceMode {
+        self.glyph_source
+    }
+
+    // Laid out paragraphs hold glyph stores shaped with the previous mode.
+    pub fn set_glyph_source_mode(&mut self, mode: GlyphSourceMode) {
+        if self.glyph_source != mode {
+            self.glyph_source = mode;
+            self.paragraphs.borrow_mut().clear();
+        }
+    }
+
+    pub fn glyph_ink_bounds(&self) -> bool {
+        self.glyph_ink_bounds
+    }
+
+    // Records per-glyph ink bounds while shaping, so text changes can damage
+    // exact rects instead of whole line boxes.
+    pub fn set_glyph_ink_bounds(&mut self, enabled: bool) {
+        if self.glyph_ink_bounds != enabled {
+            self.glyph_ink_bounds = enabled;
+            for instance in self.instances.values() {
+                instance.set_ink_bounds(enabled);
+            }
+            self.paragraphs.borrow_mut().clear();
+        }
+    }
+
+    pub fn font_subsetting(&self) -> bool {
+        self.subsetting
+    }
+
+    // Sends fonts added from now on with only the glyphs their instances
+    // shaped, e.g. a few hundred out of a CJK font's tens of thousands. Glyphs
+    // keep their indices, so runs shaped before stay valid. Fonts which can't
+    // be subset, e.g. CFF ones or collections, are sent whole.
+    //
+    // Subsets grow as more text is shaped, by adding the font again under the
+    // key it already has, see `send_font_subsets`. Consumers must replace the
+    // font they hold then, so subsetting can't be enabled for APIs which don't
+    // support font replacement.
+    pub fn set_font_subsetting(&mut self, enabled: bool) -> Result<()>
+    where
+        A: TFontKeysAPIExt
+    {
+        if enabled && !self.api.supports_font_replacement() {
+            Err(FontError::ReplayUnsupported)?;
+        }
+        self.subsetting = enabled;
+        Ok(())
+    }
+
+    // Keeps the glyphs of these chars in the font's subsets even if they were
+    // never shaped, e.g. for text the renderer lays out on its own.
+    pub fn retain_chars<I>(&mut self, font_id: FontId, chars: I)
+    where
+        I: IntoIterator<Item = char>
+    {
+        self.retained_chars
+            .entry(font_id)
+            .or_insert_with(FnvHashSet::default)
+            .extend(chars);
+    }
+
+    // Glyphs shaped through the font's instances, plus the ones of its retained
+    // chars. Glyphs already sent are kept, since runs shaped through instances
+    // released since may still refer to them.
+    pub fn used_glyphs(&self, font_id: FontId) -> FnvHashSet<u32> {
+        let mut glyphs = self.sent_subsets
+            .get(&font_id)
+            .cloned()
+            .unwrap_or_default();
+        for instance in self.instances.values().filter(|instance| instance.font_id == font_id) {
+            glyphs.extend(instance.used_glyphs.borrow().iter().cloned());
+        }
+        if let Some(chars) = self.retained_chars.get(&font_id) {
+            // Char lookups only need the face, not keys.
+            let probe = FontInstance::<(), (), ()>::new(font_id, Pt::DEFAULT, Dpi::DEFAULT, (), ());
+            glyphs.extend(chars.iter().filter_map(|&c| self.context.get_glyph_index(&*probe, c).ok()));
+        }
+        glyphs
+    }
+
+    // Backends may keep fonts the way they were added, so packed ones are
+    // unpacked again, to subset the same sfnt bytes as `insert_font` does.
+    fn sfnt_bytes(&self, font_id: FontId) -> Result<Rc<Vec<u8>>> {
+        let encoded = EncodedFont::from_bytes(self.get_bytes(font_id)?)?;
+        Ok(DecodedFont::from_encoded_font(&encoded, self.get_face_index(font_id)?).bytes)
+    }
+
+    // The font's bytes subset to its used glyphs, or whole when they can't be.
+    fn subset_bytes(&self, font_id: FontId, bytes: &Rc<Vec<u8>>) -> (Rc<Vec<u8>>, FnvHashSet<u32>) {
+        let glyphs = self.used_glyphs(font_id);
+        let subset = export::subset_glyphs(bytes, &glyphs).map_or_else(|| Rc::clone(bytes), Rc::new);
+        (subset, glyphs)
+    }
+
+    // Sends again, under the same keys, the subsets of fonts which shaped
+    // glyphs missing from what was sent, replacing the previous ones. Call it
+    // after shaping and before rendering, e.g. once per frame.
+    pub fn send_font_subsets(&mut self) -> Result<()>
+    where
+        A: TFontKeysAPIExt,
+        A::FontKey: Copy + PartialEq,
+        A::FontInstanceKey: Copy + PartialEq
+    {
+        if !self.subsetting {
+            return Ok(());
+        }
+        let fonts = self.key_snapshot().fonts;
+        for &(font_id, key) in fonts.entries() {
+            let grown = match self.sent_subsets.get(&font_id) {
+                Some(sent) => !self.used_glyphs(font_id).is_subset(sent),
+                None => false
+            };
+            if !grown {
+                continue;
+            }
+            let bytes = self.sfnt_bytes(font_id)?;
+            let face_index = self.get_face_index(font_id)?;
+            let (bytes, glyphs) = self.subset_bytes(font_id, &bytes);
+            self.sent_subsets.insert(font_id, glyphs);
+            let resource = FontResourceData { bytes: &bytes, face_index };
+            if !self.api.replay_font(key, FontEncodedData::Bytes { bytes: &bytes }, resource) {
+                Err(FontError::ReplayUnsupported)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn shaping_cache(&self) -> Option<&ShapingCache<A::FontKey, A::FontInstanceKey, A::GlyphInstance>> {
+        self.shaping_cache.as_ref()
+    }
+
+    // Instances sharing a cache hit runs shaped through any of them, e.g. the
+    // same text measured by instances which are dropped and recreated. Runs
+    // cached until now are discarded either way.
+    pub fn set_shares_shaping_cache(&mut self, enabled: bool) {
+        if self.shaping_cache.is_some() == enabled {
+            return;
+        }
+        self.shaping_cache = if enabled { Some(ShapingCache::new()) } else { None };
+        for instance in self.instances.values().chain(self.placeholders.values()) {
+            instance.set_shaping_cache(self.shaping_cache.clone().unwrap_or_default());
+        }
+    }
+
+    pub fn normalization(&self) -> NormalizationForm {
+        self.normalization
+    }
+
+    // Text is normalized after the shaping options are applied. Instances cache
+    // shaped runs by the normalized text, so they stay valid across forms.
+    pub fn set_normalization(&mut self, form: NormalizationForm) {
+        self.normalization = form;
+    }
+
+    // Sends the glyphs along with the text they were shaped from. Returns false
+    // when the store was shaped without keeping any source information.
+    pub fn add_glyph_run(&mut self, glyphs: &GlyphStore<A::FontKey, A::FontInstanceKey, A::GlyphInstance>) -> bool
+    where
+        A: TFontKeysAPIExt,
+        A::GlyphInstance: TGlyphInstanceExt
+    {
+        let source = match glyphs.source() {
+            Some(source) => source.clone(),
+            None => return false
+        };
+        let indices = glyphs
+            .glyphs()
+            .iter()
+            .map(|glyph| glyph.glyph_index())
+            .collect();
+        self.api
+            .add_glyph_run(glyphs.font_instance_key(), indices, source);
+        true
+    }
+
+    pub fn layout_paragraph<T>(
+        &self,
+        instance: FontInstanceRef<A>,
+        text: T,
+        options: &ShapingOptions,
+        max_width_64: Option<i32>
+    ) -> Result<Rc<ParagraphLayout<A::FontKey, A::FontInstanceKey, A::GlyphInstance>>>
+    where
+        T: AsRef<str>
+    {
+        let transformed = options.transform(text.as_ref());
+        let text = self.normalization.normalize(&transformed);
+        let key = ParagraphKey::new(&self.paragraph_style(instance, options), &text, max_width_64);
+
+        if let Some(layout) = self.paragraphs.borrow_mut().get(&key) {
+            return Ok(layout);
+        }
+
+        let layout = Rc::new(ParagraphLayout::layout(&text, key.max_width_64(), |line| {
+            self.shape_text_h(instance, line)
+        })?);
+        self.paragraphs
+            .borrow_mut()
+            .insert(key, Rc::clone(&layout));
+
+        Ok(layout)
+    }
+
+    // Like `layout_paragraph`, wrapping at exactly the given width and without
+    // caching the layout, for measuring text against arbitrary constraints.
+    pub fn shape_text_wrapped<T>(
+        &self,
+        instance: FontInstanceRef<A>,
+        text: T,
+        max_width_64: i32
+    ) -> Result<ParagraphLayout<A::FontKey, A::FontInstanceKey, A::GlyphInstance>>
+    where
+        T: AsRef<str>
+    {
+        let text = self.normalization.normalize(text.as_ref());
+        ParagraphLayout::layout(&text, Some(max_width_64), |line| self.shape_text_h(instance, line))
+    }
+
+    // Lays out the text with each object replacement char (U+FFFC) standing in
+    // for the next of the given objects, e.g. images sized with
+    // `InlineObject::from_dimensions`. Lines hold where the objects were placed.
+    pub fn layout_paragraph_with_objects<T>(
+        &self,
+        instance: FontInstanceRef<A>,
+        text: T,
+        objects: &[InlineObject],
+        options: &ShapingOptions,
+        max_width_64: Option<i32>
+    ) -> Result<Rc<ParagraphLayout<A::FontKey, A::FontInstanceKey, A::GlyphInstance>>>
+    where
+        T: AsRef<str>,
+        A::GlyphInstance: TGlyphInstanceExt
+    {
+        let transformed = options.transform(text.as_ref());
+        let text = self.normalization.normalize(&transformed);
+        let style = (self.paragraph_style(instance, options), objects);
+        let key = ParagraphKey::new(&style, &text, max_width_64);
+
+        if let Some(layout) = self.paragraphs.borrow_mut().get(&key) {
+            return Ok(layout);
+        }
+
+        let metrics = self.get_global_size_metrics(instance)?;
+        let font_extents_64 = (metrics.ascender_64, metrics.descender_64);
+        let layout = Rc::new(ParagraphLayout::layout_with_objects(&text, objects, font_extents_64, key.max_width_64(), |line| {
+            self.shape_text_h(instance, line)
+        })?);
+        self.paragraphs
+            .borrow_mut()
+            .insert(key, Rc::clone(&layout));
+
+        Ok(layout)
+    }
+
+    // Paragraphs are keyed on everything runs are, e.g. the size unit and the
+    // palette, along with the options which change how lines are shaped.
+    fn paragraph_style<'a>(
+        &self,
+        instance: FontInstanceRef<A>,
+        options: &'a ShapingOptions
+    ) -> (ShapingKey, GlyphLoadPolicy, bool, bool, ShapingStrategy, &'a ShapingOptions, NormalizationForm) {
+        (
+            ShapingKey::for_instance(instance, 0, false),
+            instance.load_policy(),
+            instance.kerning(),
+            instance.linear_advances(),
+            instance.shaping_strategy(),
+            options,
+            self.normalization
+        )
+    }
+
+    pub fn paragraph_cache_len(&self) -> usize {
+        self.paragraphs.borrow().len()
+    }
+
+    pub fn set_paragraph_cache_capacity(&mut self, capacity: usize) {
+        self.paragraphs.borrow_mut().set_capacity(capacity);
     }
 }
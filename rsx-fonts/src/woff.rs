@@ -0,0 +1,227 @@
+/*
+Copyright 2016 Mozilla
+Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+this file except in compliance with the License. You may obtain a copy of the
+License at http://www.apache.org/licenses/LICENSE-2.0
+Unless required by applicable law or agreed to in writing, software distributed
+under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+CONDITIONS OF ANY KIND, either express or implied. See the License for the
+specific language governing permissions and limitations under the License.
+*/
+
+// Unpacks WOFF and WOFF2 files, as fonts sourced from the web usually arrive,
+// into the sfnt files backends load. WOFF compresses each table on its own
+// with zlib. WOFF2 compresses all of them together with Brotli, and is only
+// read with the `woff2` feature.
+
+use std::io::Read;
+
+use flate2::read::ZlibDecoder;
+
+use error::{FontError, Result};
+use sfnt::{read_u16, read_u32};
+#[cfg(feature = "woff2")]
+use woff2;
+
+const WOFF_SIGNATURE: &[u8] = b"wOFF";
+const WOFF2_SIGNATURE: &[u8] = b"wOF2";
+const WOFF_HEADER_SIZE: usize = 44;
+const WOFF_ENTRY_SIZE: usize = 20;
+
+// Headers can claim any length, so buffers only reserve what this ratio allows
+// for the compressed data upfront, and grow from there while inflating.
+const INFLATE_RESERVE_RATIO: usize = 8;
+
+// Directory search fields count bytes, so fonts can't have more tables.
+pub(crate) const MAX_SFNT_TABLES: usize = 4095;
+
+// Unpacked fonts are never larger than this, whatever size the header claims.
+pub(crate) const MAX_SFNT_SIZE: usize = 256 << 20;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FontFormat {
+    Sfnt,
+    Woff,
+    Woff2
+}
+
+impl FontFormat {
+    // Anything which isn't WOFF is left to the backend to recognize.
+    pub fn sniff(bytes: &[u8]) -> Self {
+        if bytes.starts_with(WOFF_SIGNATURE) {
+            FontFormat::Woff
+        } else if bytes.starts_with(WOFF2_SIGNATURE) {
+            FontFormat::Woff2
+        } else {
+            FontFormat::Sfnt
+        }
+    }
+}
+
+// Returns nothing for bytes which can be loaded as they are.
+pub fn to_sfnt(bytes: &[u8]) -> Result<Option<Vec<u8>>> {
+    match FontFormat::sniff(bytes) {
+        FontFormat::Sfnt => Ok(None),
+        FontFormat::Woff => decode_woff(bytes).map(Some),
+        FontFormat::Woff2 => decode_woff2(bytes).map(Some)
+    }
+}
+
+fn decode_woff(bytes: &[u8]) -> Result<Vec<u8>> {
+    let flavor = u32_at(bytes, 4)?;
+    let num_tables = table_count(bytes)?;
+
+    // Tables are only inflated while they fit in the size the header claims.
+    let mut remaining = sfnt_size_limit(bytes)?
+        .checked_sub(12 + num_tables * 16)
+        .ok_or(FontError::WoffDecodeError)?;
+    let mut tables = Vec::with_capacity(num_tables);
+    for i in 0..num_tables {
+        let entry = WOFF_HEADER_SIZE + i * WOFF_ENTRY_SIZE;
+        let offset = u32_at(bytes, entry + 4)? as usize;
+        let comp_length = u32_at(bytes, entry + 8)? as usize;
+        let orig_length = u32_at(bytes, entry + 12)? as usize;
+        if orig_length > remaining {
+            Err(FontError::WoffDecodeError)?
+        }
+        remaining = remaining.saturating_sub(padded(orig_length));
+        let compressed = slice_at(bytes, offset, comp_length)?;
+
+        // Tables which wouldn't shrink are stored as they are.
+        let data = if comp_length == orig_length {
+            compressed.to_vec()
+        } else if comp_length < orig_length {
+            inflate(compressed, orig_length)?
+        } else {
+            Err(FontError::WoffDecodeError)?
+        };
+
+        tables.push(SfntTable {
+            tag: u32_at(bytes, entry)?,
+            checksum: u32_at(bytes, entry + 16)?,
+            data
+        });
+    }
+
+    write_sfnt(flavor, tables)
+}
+
+#[cfg(feature = "woff2")]
+fn decode_woff2(bytes: &[u8]) -> Result<Vec<u8>> {
+    woff2::decode(bytes)
+}
+
+#[cfg(not(feature = "woff2"))]
+fn decode_woff2(_: &[u8]) -> Result<Vec<u8>> {
+    Err(FontError::WoffUnsupported)
+}
+
+// Both WOFF and WOFF2 headers hold the table count and the unpacked size at
+// the same offsets.
+pub(crate) fn table_count(bytes: &[u8]) -> Result<usize> {
+    let num_tables = u16_at(bytes, 12)? as usize;
+    if num_tables == 0 || num_tables > MAX_SFNT_TABLES {
+        Err(FontError::WoffDecodeError)?
+    }
+    Ok(num_tables)
+}
+
+pub(crate) fn sfnt_size_limit(bytes: &[u8]) -> Result<usize> {
+    Ok((u32_at(bytes, 16)? as usize).min(MAX_SFNT_SIZE))
+}
+
+pub(crate) fn inflate_capacity(compressed: &[u8], length: usize) -> usize {
+    length.min(compressed.len().saturating_mul(INFLATE_RESERVE_RATIO))
+}
+
+fn inflate(compressed: &[u8], orig_length: usize) -> Result<Vec<u8>> {
+    let mut data = Vec::with_capacity(inflate_capacity(compressed, orig_length));
+    ZlibDecoder::new(compressed)
+        .take(orig_length as u64 + 1)
+        .read_to_end(&mut data)
+        .map_err(|_| FontError::WoffDecodeError)?;
+    if data.len() != orig_length {
+        Err(FontError::WoffDecodeError)?
+    }
+    Ok(data)
+}
+
+#[derive(Debug)]
+pub(crate) struct SfntTable {
+    pub tag: u32,
+    pub checksum: u32,
+    pub data: Vec<u8>
+}
+
+// Tables are listed sorted by tag, as backends may binary search for them,
+// and each starts on a four byte boundary.
+pub(crate) fn write_sfnt(flavor: u32, mut tables: Vec<SfntTable>) -> Result<Vec<u8>> {
+    if tables.is_empty() || tables.len() > MAX_SFNT_TABLES {
+        Err(FontError::WoffDecodeError)?
+    }
+    tables.sort_by_key(|table| table.tag);
+
+    let num_tables = tables.len() as u16;
+    let entry_selector = 15 - num_tables.leading_zeros() as u16;
+    let search_range = (1 << entry_selector) * 16;
+    let size = tables.iter().fold(12 + tables.len() * 16, |size, table| size + padded(table.data.len()));
+    if size > MAX_SFNT_SIZE {
+        Err(FontError::WoffDecodeError)?
+    }
+
+    let mut sfnt = Vec::with_capacity(size);
+    push_u32(&mut sfnt, flavor);
+    push_u16(&mut sfnt, num_tables);
+    push_u16(&mut sfnt, search_range);
+    push_u16(&mut sfnt, entry_selector);
+    push_u16(&mut sfnt, num_tables * 16 - search_range);
+
+    let mut offset = 12 + tables.len() * 16;
+    for table in &tables {
+        push_u32(&mut sfnt, table.tag);
+        push_u32(&mut sfnt, table.checksum);
+        push_u32(&mut sfnt, offset as u32);
+        push_u32(&mut sfnt, table.data.len() as u32);
+        offset += padded(table.data.len());
+    }
+    for table in &tables {
+        sfnt.extend_from_slice(&table.data);
+        let padding = padded(table.data.len()) - table.data.len();
+        sfnt.extend_from_slice(&[0; 3][..padding]);
+    }
+
+    Ok(sfnt)
+}
+
+// Sum of the table's big endian words, the last one padded with zeroes.
+pub(crate) fn checksum(data: &[u8]) -> u32 {
+    data.chunks(4).fold(0u32, |sum, word| {
+        let value = word.iter().enumerate().fold(0, |value, (i, &b)| value | u32::from(b) << (24 - i * 8));
+        sum.wrapping_add(value)
+    })
+}
+
+pub(crate) fn padded(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+pub(crate) fn push_u16(bytes: &mut Vec<u8>, value: u16) {
+    bytes.extend_from_slice(&[(value >> 8) as u8, value as u8]);
+}
+
+pub(crate) fn push_u32(bytes: &mut Vec<u8>, value: u32) {
+    bytes.extend_from_slice(&[(value >> 24) as u8, (value >> 16) as u8, (value >> 8) as u8, value as u8]);
+}
+
+pub(crate) fn u16_at(bytes: &[u8], offset: usize) -> Result<u16> {
+    read_u16(bytes, offset).ok_or(FontError::WoffDecodeError)
+}
+
+pub(crate) fn u32_at(bytes: &[u8], offset: usize) -> Result<u32> {
+    read_u32(bytes, offset).ok_or(FontError::WoffDecodeError)
+}
+
+pub(crate) fn slice_at(bytes: &[u8], offset: usize, len: usize) -> Result<&[u8]> {
+    let end = offset.checked_add(len).ok_or(FontError::WoffDecodeError)?;
+    bytes.get(offset..end).ok_or(FontError::WoffDecodeError)
+}
@@ -0,0 +1,515 @@
+/*
+Copyright 2016 Mozilla
+Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+this file except in compliance with the License. You may obtain a copy of the
+License at http://www.apache.org/licenses/LICENSE-2.0
+Unless required by applicable law or agreed to in writing, software distributed
+under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+CONDITIONS OF ANY KIND, either express or implied. See the License for the
+specific language governing permissions and limitations under the License.
+*/
+
+// WOFF2 tables are decompressed as a single Brotli stream. Encoders usually
+// store `glyf` in a transformed form, split into streams of contour counts,
+// point flags, coordinates and so on, with `loca` left out altogether, and
+// may leave out the side bearings in `hmtx` which match the glyph bounds.
+// Those are all rebuilt here. Font collections aren't supported.
+
+use std::io::Read;
+
+use brotli_decompressor::Decompressor;
+
+use error::{FontError, Result};
+use woff::{self, SfntTable};
+
+const WOFF2_HEADER_SIZE: usize = 48;
+const COLLECTION_FLAVOR: u32 = 0x7474_6366;
+
+const HEAD: u32 = 0x6865_6164;
+const GLYF: u32 = 0x676c_7966;
+const LOCA: u32 = 0x6c6f_6361;
+const HMTX: u32 = 0x686d_7478;
+const HHEA: u32 = 0x6868_6561;
+const MAXP: u32 = 0x6d61_7870;
+
+// Tags encoded in the directory by their index in this list.
+const KNOWN_TAGS: [&[u8; 4]; 63] = [
+    b"cmap", b"head", b"hhea", b"hmtx", b"maxp", b"name", b"OS/2", b"post", b"cvt ", b"fpgm", b"glyf", b"loca", b"prep", b"CFF ",
+    b"VORG", b"EBDT", b"EBLC", b"gasp", b"hdmx", b"kern", b"LTSH", b"PCLT", b"VDMX", b"vhea", b"vmtx", b"BASE", b"GDEF", b"GPOS",
+    b"GSUB", b"EBSC", b"JSTF", b"MATH", b"CBDT", b"CBLC", b"COLR", b"CPAL", b"SVG ", b"sbix", b"acnt", b"avar", b"bdat", b"bloc",
+    b"bsln", b"cvar", b"fdsc", b"feat", b"fmtx", b"fvar", b"gvar", b"hsty", b"just", b"lcar", b"mort", b"morx", b"opbd", b"prop",
+    b"trak", b"Zapf", b"Silf", b"Glat", b"Gloc", b"Feat", b"Sill"
+];
+
+const ON_CURVE: u8 = 1;
+const X_SHORT: u8 = 1 << 1;
+const Y_SHORT: u8 = 1 << 2;
+const X_SAME_OR_POSITIVE: u8 = 1 << 4;
+const Y_SAME_OR_POSITIVE: u8 = 1 << 5;
+const OVERLAP_SIMPLE: u8 = 1 << 6;
+
+const ARG_1_AND_2_ARE_WORDS: u16 = 1;
+const WE_HAVE_A_SCALE: u16 = 1 << 3;
+const MORE_COMPONENTS: u16 = 1 << 5;
+const WE_HAVE_AN_X_AND_Y_SCALE: u16 = 1 << 6;
+const WE_HAVE_A_TWO_BY_TWO: u16 = 1 << 7;
+const WE_HAVE_INSTRUCTIONS: u16 = 1 << 8;
+
+struct TableEntry {
+    tag: u32,
+    transformed: bool,
+    stored_length: usize
+}
+
+pub(crate) fn decode(bytes: &[u8]) -> Result<Vec<u8>> {
+    let flavor = woff::u32_at(bytes, 4)?;
+    if flavor == COLLECTION_FLAVOR {
+        Err(FontError::WoffUnsupported)?
+    }
+    let num_tables = woff::table_count(bytes)?;
+    let compressed_length = woff::u32_at(bytes, 20)? as usize;
+
+    let mut directory = Cursor::new(bytes);
+    directory.skip(WOFF2_HEADER_SIZE)?;
+    let mut entries = Vec::with_capacity(num_tables);
+    for _ in 0..num_tables {
+        let flags = directory.u8()?;
+        let tag = match KNOWN_TAGS.get(usize::from(flags & 0x3f)) {
+            Some(tag) => u32_from_tag(tag),
+            None => directory.u32()?
+        };
+        // The null transform is version 3 for outlines, and version 0 otherwise.
+        let version = flags >> 6;
+        let transformed = if tag == GLYF || tag == LOCA { version == 0 } else { version != 0 };
+        // Rebuilt tables needn't match their original length, so only stored lengths matter.
+        let orig_length = directory.base128()?;
+        let stored_length = if transformed { directory.base128()? } else { orig_length };
+        entries.push(TableEntry {
+            tag,
+            transformed,
+            stored_length
+        });
+    }
+
+    let stream_length = entries
+        .iter()
+        .fold(Some(0usize), |total, entry| total?.checked_add(entry.stored_length))
+        .ok_or(FontError::WoffDecodeError)?;
+    if stream_length > woff::sfnt_size_limit(bytes)? {
+        Err(FontError::WoffDecodeError)?
+    }
+    let compressed = woff::slice_at(bytes, directory.offset, compressed_length)?;
+    let mut stream = Vec::with_capacity(woff::inflate_capacity(compressed, stream_length));
+    Decompressor::new(compressed, 4096)
+        .take(stream_length as u64 + 1)
+        .read_to_end(&mut stream)
+        .map_err(|_| FontError::WoffDecodeError)?;
+    if stream.len() != stream_length {
+        Err(FontError::WoffDecodeError)?
+    }
+
+    let mut offset = 0;
+    let mut stored = Vec::with_capacity(entries.len());
+    for entry in &entries {
+        stored.push(&stream[offset..offset + entry.stored_length]);
+        offset += entry.stored_length;
+    }
+    let find = |tag: u32| entries.iter().position(|entry| entry.tag == tag);
+
+    // Side bearings left out of `hmtx` are the left edges of the glyph bounds.
+    let mut rebuilt_outlines = None;
+    if let Some(glyf) = find(GLYF) {
+        if entries[glyf].transformed {
+            if find(LOCA).is_none() {
+                Err(FontError::WoffDecodeError)?
+            }
+            rebuilt_outlines = Some(reconstruct_glyf(stored[glyf])?);
+        }
+    }
+
+    let mut tables = Vec::with_capacity(entries.len());
+    for (i, entry) in entries.iter().enumerate() {
+        let data = match (entry.tag, entry.transformed, rebuilt_outlines.as_ref()) {
+            (_, false, _) => stored[i].to_vec(),
+            (GLYF, true, Some(outlines)) => outlines.glyf.clone(),
+            (LOCA, true, Some(outlines)) => outlines.loca.clone(),
+            (HMTX, true, outlines) => {
+                let x_mins = outlines.map_or(&[][..], |outlines| &outlines.x_mins[..]);
+                let hhea = find(HHEA).map(|hhea| stored[hhea]).ok_or(FontError::WoffDecodeError)?;
+                let maxp = find(MAXP).map(|maxp| stored[maxp]).ok_or(FontError::WoffDecodeError)?;
+                let num_h_metrics = woff::u16_at(hhea, 34)? as usize;
+                let num_glyphs = woff::u16_at(maxp, 4)? as usize;
+                reconstruct_hmtx(stored[i], num_glyphs, num_h_metrics, x_mins)?
+            }
+            _ => Err(FontError::WoffDecodeError)?
+        };
+        tables.push(SfntTable {
+            tag: entry.tag,
+            checksum: table_checksum(entry.tag, &data),
+            data
+        });
+    }
+
+    woff::write_sfnt(flavor, tables)
+}
+
+// The checksum of `head` leaves out its adjustment for the checksum of the whole font.
+fn table_checksum(tag: u32, data: &[u8]) -> u32 {
+    let checksum = woff::checksum(data);
+    match tag {
+        HEAD => checksum.wrapping_sub(woff::u32_at(data, 8).unwrap_or(0)),
+        _ => checksum
+    }
+}
+
+struct Outlines {
+    glyf: Vec<u8>,
+    loca: Vec<u8>,
+    x_mins: Vec<i16>
+}
+
+fn reconstruct_glyf(data: &[u8]) -> Result<Outlines> {
+    let mut header = Cursor::new(data);
+    header.skip(2)?;
+    let option_flags = header.u16()?;
+    let num_glyphs = header.u16()? as usize;
+    let index_format = header.u16()?;
+
+    let mut offset = 36;
+    let mut n_contours = Cursor::new(split(data, &mut offset, header.u32()? as usize)?);
+    let mut n_points = Cursor::new(split(data, &mut offset, header.u32()? as usize)?);
+    let mut flags = Cursor::new(split(data, &mut offset, header.u32()? as usize)?);
+    let mut glyphs = Cursor::new(split(data, &mut offset, header.u32()? as usize)?);
+    let mut composites = Cursor::new(split(data, &mut offset, header.u32()? as usize)?);
+    let mut bboxes = Cursor::new(split(data, &mut offset, header.u32()? as usize)?);
+    let mut instructions = Cursor::new(split(data, &mut offset, header.u32()? as usize)?);
+    let bbox_bitmap = bboxes.take(((num_glyphs + 31) >> 5) << 2)?;
+    let overlap_bitmap = if option_flags & 1 != 0 {
+        Some(split(data, &mut offset, (num_glyphs + 7) >> 3)?)
+    } else {
+        None
+    };
+
+    let mut glyf = Vec::with_capacity(data.len());
+    let mut loca_offsets = Vec::with_capacity(num_glyphs + 1);
+    let mut x_mins = Vec::with_capacity(num_glyphs);
+    for glyph_id in 0..num_glyphs {
+        loca_offsets.push(glyf.len());
+        let has_bbox = bit_at(bbox_bitmap, glyph_id);
+        let contour_count = n_contours.i16()?;
+
+        let x_min = match contour_count {
+            0 if has_bbox => Err(FontError::WoffDecodeError)?,
+            0 => 0,
+            -1 if !has_bbox => Err(FontError::WoffDecodeError)?,
+            -1 => {
+                let bbox = read_bbox(&mut bboxes)?;
+                let start = composites.offset;
+                let mut has_instructions = false;
+                loop {
+                    let component_flags = composites.u16()?;
+                    has_instructions |= component_flags & WE_HAVE_INSTRUCTIONS != 0;
+                    let args_length = if component_flags & ARG_1_AND_2_ARE_WORDS != 0 { 4 } else { 2 };
+                    let scale_length = if component_flags & WE_HAVE_A_SCALE != 0 {
+                        2
+                    } else if component_flags & WE_HAVE_AN_X_AND_Y_SCALE != 0 {
+                        4
+                    } else if component_flags & WE_HAVE_A_TWO_BY_TWO != 0 {
+                        8
+                    } else {
+                        0
+                    };
+                    composites.skip(2 + args_length + scale_length)?;
+                    if component_flags & MORE_COMPONENTS == 0 {
+                        break;
+                    }
+                }
+
+                push_i16(&mut glyf, -1);
+                push_bbox(&mut glyf, bbox);
+                glyf.extend_from_slice(&composites.bytes[start..composites.offset]);
+                if has_instructions {
+                    let instruction_length = glyphs.u255()?;
+                    woff::push_u16(&mut glyf, instruction_length);
+                    glyf.extend_from_slice(instructions.take(usize::from(instruction_length))?);
+                }
+                bbox.0
+            }
+            contour_count if contour_count > 0 => {
+                let mut end_points = Vec::with_capacity(contour_count as usize);
+                let mut point_count = 0usize;
+                for _ in 0..contour_count {
+                    point_count += usize::from(n_points.u255()?);
+                    let end_point = point_count.checked_sub(1).ok_or(FontError::WoffDecodeError)?;
+                    if end_point > usize::from(u16::max_value()) {
+                        Err(FontError::WoffDecodeError)?
+                    }
+                    end_points.push(end_point as u16);
+                }
+
+                let mut points = Vec::with_capacity(point_count);
+                let (mut x, mut y) = (0i32, 0i32);
+                for &flag in flags.take(point_count)? {
+                    let (dx, dy) = decode_triplet(flag & 0x7f, &mut glyphs)?;
+                    x += dx;
+                    y += dy;
+                    points.push((x, y, flag & 0x80 == 0));
+                }
+                let instruction_length = glyphs.u255()?;
+                let bbox = if has_bbox {
+                    read_bbox(&mut bboxes)?
+                } else {
+                    bounds(&points)?
+                };
+
+                push_i16(&mut glyf, contour_count);
+                push_bbox(&mut glyf, bbox);
+                for end_point in end_points {
+                    woff::push_u16(&mut glyf, end_point);
+                }
+                woff::push_u16(&mut glyf, instruction_length);
+                glyf.extend_from_slice(instructions.take(usize::from(instruction_length))?);
+                let overlap = overlap_bitmap.map_or(false, |bitmap| bit_at(bitmap, glyph_id));
+                encode_points(&mut glyf, &points, overlap)?;
+                bbox.0
+            }
+            _ => Err(FontError::WoffDecodeError)?
+        };
+
+        x_mins.push(x_min);
+        let padding = woff::padded(glyf.len()) - glyf.len();
+        glyf.extend_from_slice(&[0; 3][..padding]);
+    }
+    loca_offsets.push(glyf.len());
+
+    // Short offsets are halved, which glyphs padded to four bytes allow.
+    let mut loca = Vec::with_capacity(loca_offsets.len() * 4);
+    for loca_offset in loca_offsets {
+        match index_format {
+            0 if loca_offset >> 1 <= usize::from(u16::max_value()) => woff::push_u16(&mut loca, (loca_offset >> 1) as u16),
+            1 => woff::push_u32(&mut loca, loca_offset as u32),
+            _ => Err(FontError::WoffDecodeError)?
+        }
+    }
+
+    Ok(Outlines { glyf, loca, x_mins })
+}
+
+fn reconstruct_hmtx(data: &[u8], num_glyphs: usize, num_h_metrics: usize, x_mins: &[i16]) -> Result<Vec<u8>> {
+    if num_h_metrics == 0 || num_h_metrics > num_glyphs || x_mins.len() < num_glyphs {
+        Err(FontError::WoffDecodeError)?
+    }
+    let mut cursor = Cursor::new(data);
+    let flags = cursor.u8()?;
+
+    let mut advances = Vec::with_capacity(num_h_metrics);
+    for _ in 0..num_h_metrics {
+        advances.push(cursor.u16()?);
+    }
+    let mut side_bearings = Vec::with_capacity(num_glyphs);
+    for glyph_id in 0..num_glyphs {
+        let omitted = if glyph_id < num_h_metrics { flags & 1 != 0 } else { flags & 2 != 0 };
+        side_bearings.push(if omitted { x_mins[glyph_id] } else { cursor.i16()? });
+    }
+
+    let mut hmtx = Vec::with_capacity(num_h_metrics * 2 + num_glyphs * 2);
+    for (glyph_id, side_bearing) in side_bearings.into_iter().enumerate() {
+        if let Some(&advance) = advances.get(glyph_id) {
+            woff::push_u16(&mut hmtx, advance);
+        }
+        push_i16(&mut hmtx, side_bearing);
+    }
+    Ok(hmtx)
+}
+
+// Each point's flag picks how many bytes its deltas take, and how those bytes
+// are split between them.
+fn decode_triplet(flag: u8, glyphs: &mut Cursor) -> Result<(i32, i32)> {
+    let with_sign = |flag: u8, value: i32| if flag & 1 != 0 { value } else { -value };
+    let flag_value = i32::from(flag);
+
+    Ok(match flag {
+        0..=9 => {
+            let b0 = i32::from(glyphs.u8()?);
+            (0, with_sign(flag, ((flag_value & 14) << 7) + b0))
+        }
+        10..=19 => {
+            let b0 = i32::from(glyphs.u8()?);
+            (with_sign(flag, (((flag_value - 10) & 14) << 7) + b0), 0)
+        }
+        20..=83 => {
+            let b0 = flag_value - 20;
+            let b1 = i32::from(glyphs.u8()?);
+            (
+                with_sign(flag, 1 + (b0 & 0x30) + (b1 >> 4)),
+                with_sign(flag >> 1, 1 + ((b0 & 0x0c) << 2) + (b1 & 0x0f))
+            )
+        }
+        84..=119 => {
+            let b0 = flag_value - 84;
+            let b1 = i32::from(glyphs.u8()?);
+            let b2 = i32::from(glyphs.u8()?);
+            (
+                with_sign(flag, 1 + ((b0 / 12) << 8) + b1),
+                with_sign(flag >> 1, 1 + (((b0 % 12) >> 2) << 8) + b2)
+            )
+        }
+        120..=123 => {
+            let b1 = i32::from(glyphs.u8()?);
+            let b2 = i32::from(glyphs.u8()?);
+            let b3 = i32::from(glyphs.u8()?);
+            (with_sign(flag, (b1 << 4) + (b2 >> 4)), with_sign(flag >> 1, ((b2 & 0x0f) << 8) + b3))
+        }
+        _ => {
+            let dx = i32::from(glyphs.u16()?);
+            let dy = i32::from(glyphs.u16()?);
+            (with_sign(flag, dx), with_sign(flag >> 1, dy))
+        }
+    })
+}
+
+// Deltas are written as single bytes where they fit, without repeat flags.
+fn encode_points(glyf: &mut Vec<u8>, points: &[(i32, i32, bool)], overlap: bool) -> Result<()> {
+    let mut flags = Vec::with_capacity(points.len());
+    let mut xs = Vec::with_capacity(points.len() * 2);
+    let mut ys = Vec::with_capacity(points.len() * 2);
+    let (mut last_x, mut last_y) = (0, 0);
+
+    for (i, &(x, y, on_curve)) in points.iter().enumerate() {
+        let mut flag = if on_curve { ON_CURVE } else { 0 };
+        if i == 0 && overlap {
+            flag |= OVERLAP_SIMPLE;
+        }
+        flag |= encode_delta(&mut xs, x - last_x, X_SHORT, X_SAME_OR_POSITIVE)?;
+        flag |= encode_delta(&mut ys, y - last_y, Y_SHORT, Y_SAME_OR_POSITIVE)?;
+        flags.push(flag);
+        last_x = x;
+        last_y = y;
+    }
+
+    glyf.extend_from_slice(&flags);
+    glyf.extend_from_slice(&xs);
+    glyf.extend_from_slice(&ys);
+    Ok(())
+}
+
+fn encode_delta(coordinates: &mut Vec<u8>, delta: i32, short: u8, same_or_positive: u8) -> Result<u8> {
+    if delta == 0 {
+        Ok(same_or_positive)
+    } else if delta > -256 && delta < 256 {
+        coordinates.push(delta.abs() as u8);
+        Ok(if delta > 0 { short | same_or_positive } else { short })
+    } else if delta >= i32::from(i16::min_value()) && delta <= i32::from(i16::max_value()) {
+        push_i16(coordinates, delta as i16);
+        Ok(0)
+    } else {
+        Err(FontError::WoffDecodeError)
+    }
+}
+
+fn bounds(points: &[(i32, i32, bool)]) -> Result<(i16, i16, i16, i16)> {
+    let (x_min, y_min, x_max, y_max) = points.iter().fold(
+        (i32::max_value(), i32::max_value(), i32::min_value(), i32::min_value()),
+        |(x_min, y_min, x_max, y_max), &(x, y, _)| (x_min.min(x), y_min.min(y), x_max.max(x), y_max.max(y))
+    );
+    let fits = |value: i32| value >= i32::from(i16::min_value()) && value <= i32::from(i16::max_value());
+    if points.is_empty() || ![x_min, y_min, x_max, y_max].iter().all(|&value| fits(value)) {
+        Err(FontError::WoffDecodeError)?
+    }
+    Ok((x_min as i16, y_min as i16, x_max as i16, y_max as i16))
+}
+
+fn read_bbox(bboxes: &mut Cursor) -> Result<(i16, i16, i16, i16)> {
+    Ok((bboxes.i16()?, bboxes.i16()?, bboxes.i16()?, bboxes.i16()?))
+}
+
+fn push_bbox(glyf: &mut Vec<u8>, (x_min, y_min, x_max, y_max): (i16, i16, i16, i16)) {
+    push_i16(glyf, x_min);
+    push_i16(glyf, y_min);
+    push_i16(glyf, x_max);
+    push_i16(glyf, y_max);
+}
+
+fn push_i16(bytes: &mut Vec<u8>, value: i16) {
+    woff::push_u16(bytes, value as u16);
+}
+
+fn bit_at(bitmap: &[u8], index: usize) -> bool {
+    bitmap[index >> 3] & (0x80 >> (index & 7)) != 0
+}
+
+fn split<'a>(data: &'a [u8], offset: &mut usize, len: usize) -> Result<&'a [u8]> {
+    let slice = woff::slice_at(data, *offset, len)?;
+    *offset += len;
+    Ok(slice)
+}
+
+fn u32_from_tag(tag: &[u8; 4]) -> u32 {
+    u32::from(tag[0]) << 24 | u32::from(tag[1]) << 16 | u32::from(tag[2]) << 8 | u32::from(tag[3])
+}
+
+struct Cursor<'a> {
+    bytes: &'a [u8],
+    offset: usize
+}
+
+impl<'a> Cursor<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Cursor { bytes, offset: 0 }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let slice = woff::slice_at(self.bytes, self.offset, len)?;
+        self.offset += len;
+        Ok(slice)
+    }
+
+    fn skip(&mut self, len: usize) -> Result<()> {
+        self.take(len).map(|_| ())
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        let b = self.take(2)?;
+        Ok(u16::from(b[0]) << 8 | u16::from(b[1]))
+    }
+
+    fn i16(&mut self) -> Result<i16> {
+        self.u16().map(|value| value as i16)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let high = self.u16()?;
+        let low = self.u16()?;
+        Ok(u32::from(high) << 16 | u32::from(low))
+    }
+
+    // Values below 253 take a single byte, the rest one or two more.
+    fn u255(&mut self) -> Result<u16> {
+        match self.u8()? {
+            253 => self.u16(),
+            254 => Ok(u16::from(self.u8()?) + 253 * 2),
+            255 => Ok(u16::from(self.u8()?) + 253),
+            code => Ok(u16::from(code))
+        }
+    }
+
+    // Big endian groups of seven bits, without leading zeroes, fitting 32 bits.
+    fn base128(&mut self) -> Result<usize> {
+        let mut value = 0u32;
+        for i in 0..5 {
+            let byte = self.u8()?;
+            if (i == 0 && byte == 0x80) || value & 0xfe00_0000 != 0 {
+                Err(FontError::WoffDecodeError)?
+            }
+            value = value << 7 | u32::from(byte & 0x7f);
+            if byte & 0x80 == 0 {
+                return Ok(value as usize);
+            }
+        }
+        Err(FontError::WoffDecodeError)
+    }
+}
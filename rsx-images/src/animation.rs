@@ -0,0 +1,260 @@
+/*
+Copyright 2016 Mozilla
+Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+this file except in compliance with the License. You may obtain a copy of the
+License at http://www.apache.org/licenses/LICENSE-2.0
+Unless required by applicable law or agreed to in writing, software distributed
+under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+CONDITIONS OF ANY KIND, either express or implied. See the License for the
+specific language governing permissions and limitations under the License.
+*/
+
+use std::sync::Arc;
+
+use rsx_resource_updates::pixels;
+
+use decoded::DecodedImage;
+use error::{ImageError, Result};
+use types::ImagePixelFormat;
+
+// Browsers clamp very short frame delays, since many GIFs in the wild rely on it.
+pub const MIN_FRAME_DELAY_MS: u32 = 20;
+pub const CLAMPED_FRAME_DELAY_MS: u32 = 100;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum FrameDisposal {
+    Keep,
+    Background,
+    Previous
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum LoopCount {
+    Infinite,
+    // Total number of times the animation is played.
+    Finite(u32)
+}
+
+#[derive(Debug, PartialEq)]
+pub struct AnimationFrame {
+    pub offset: (u32, u32),
+    pub image: DecodedImage,
+    pub delay_ms: u32,
+    pub disposal: FrameDisposal
+}
+
+impl AnimationFrame {
+    pub fn effective_delay_ms(&self) -> u32 {
+        if self.delay_ms < MIN_FRAME_DELAY_MS {
+            CLAMPED_FRAME_DELAY_MS
+        } else {
+            self.delay_ms
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct AnimatedImage {
+    size: (u32, u32),
+    frames: Vec<AnimationFrame>,
+    loop_count: LoopCount
+}
+
+impl AnimatedImage {
+    pub fn new(size: (u32, u32), frames: Vec<AnimationFrame>, loop_count: LoopCount) -> Result<Self> {
+        let format = frames
+            .first()
+            .ok_or(ImageError::InvalidAnimation)?
+            .image
+            .format;
+        let valid = frames.iter().all(|frame| {
+            frame.image.format == format
+                && pixels::bytes_per_pixel(format) == 4
+                && frame.offset.0 + frame.image.size.0 <= size.0
+                && frame.offset.1 + frame.image.size.1 <= size.1
+        });
+        if !valid || loop_count == LoopCount::Finite(0) {
+            Err(ImageError::InvalidAnimation)?;
+        }
+        Ok(AnimatedImage {
+            size,
+            frames,
+            loop_count
+        })
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        self.size
+    }
+
+    pub fn format(&self) -> ImagePixelFormat {
+        self.frames[0].image.format
+    }
+
+    pub fn frames(&self) -> &[AnimationFrame] {
+        &self.frames
+    }
+
+    pub fn loop_count(&self) -> LoopCount {
+        self.loop_count
+    }
+
+    pub fn loop_duration_ms(&self) -> u64 {
+        self.frames
+            .iter()
+            .map(|frame| u64::from(frame.effective_delay_ms()))
+            .sum()
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct AnimationTick {
+    pub frame_index: usize,
+    pub needs_update: bool,
+    pub finished: bool,
+    pub next_update_in_ms: Option<u64>
+}
+
+// Tracks playback of an animated image and composites its frames, honoring
+// each frame's disposal, so the current frame can be handed to the renderer.
+#[derive(Debug, PartialEq)]
+pub struct AnimationClock {
+    start_ms: Option<u64>,
+    frame_index: Option<usize>,
+    canvas: Arc<Vec<u8>>,
+    saved: Option<Vec<u8>>
+}
+
+impl Default for AnimationClock {
+    fn default() -> Self {
+        AnimationClock::new()
+    }
+}
+
+impl AnimationClock {
+    pub fn new() -> Self {
+        AnimationClock {
+            start_ms: None,
+            frame_index: None,
+            canvas: Arc::default(),
+            saved: None
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = AnimationClock::new();
+    }
+
+    pub fn frame_index(&self) -> Option<usize> {
+        self.frame_index
+    }
+
+    pub fn tick(&mut self, animation: &AnimatedImage, timestamp_ms: u64) -> AnimationTick {
+        let start_ms = *self.start_ms.get_or_insert(timestamp_ms);
+        let elapsed_ms = timestamp_ms.saturating_sub(start_ms);
+        let loop_duration_ms = animation.loop_duration_ms();
+
+        let completed_loops = elapsed_ms / loop_duration_ms;
+        let finished = match animation.loop_count {
+            LoopCount::Infinite => false,
+            LoopCount::Finite(plays) => completed_loops >= u64::from(plays)
+        };
+
+        let (frame_index, next_update_in_ms) = if finished {
+            (animation.frames.len() - 1, None)
+        } else {
+            let mut position_ms = elapsed_ms % loop_duration_ms;
+            let mut frame_index = 0;
+            for frame in &animation.frames {
+                let delay_ms = u64::from(frame.effective_delay_ms());
+                if position_ms < delay_ms {
+                    position_ms = delay_ms - position_ms;
+                    break;
+                }
+                position_ms -= delay_ms;
+                frame_index += 1;
+            }
+            (frame_index, Some(position_ms))
+        };
+
+        let needs_update = self.frame_index != Some(frame_index);
+        if needs_update {
+            self.composite(animation, frame_index);
+        }
+
+        AnimationTick {
+            frame_index,
+            needs_update,
+            finished,
+            next_update_in_ms
+        }
+    }
+
+    pub fn current_image(&self, animation: &AnimatedImage) -> Option<DecodedImage> {
+        self.frame_index?;
+        DecodedImage::from_raw_parts(animation.format(), animation.size, Arc::clone(&self.canvas)).ok()
+    }
+
+    fn composite(&mut self, animation: &AnimatedImage, frame_index: usize) {
+        let first = match self.frame_index {
+            Some(current) if current < frame_index => current + 1,
+            _ => {
+                let (width, height) = animation.size;
+                self.canvas = Arc::new(vec![0; width as usize * height as usize * 4]);
+                self.saved = None;
+                0
+            }
+        };
+
+        let canvas_stride = animation.size.0 as usize * 4;
+        for index in first..=frame_index {
+            let canvas = Arc::make_mut(&mut self.canvas);
+            if index > 0 {
+                let previous = &animation.frames[index - 1];
+                match previous.disposal {
+                    FrameDisposal::Keep => {}
+                    FrameDisposal::Background => clear_rect(canvas, canvas_stride, previous),
+                    FrameDisposal::Previous => {
+                        if let Some(saved) = self.saved.take() {
+                            *canvas = saved;
+                        }
+                    }
+                }
+            }
+            let frame = &animation.frames[index];
+            if frame.disposal == FrameDisposal::Previous {
+                self.saved = Some(canvas.clone());
+            }
+            draw_frame(canvas, canvas_stride, frame);
+        }
+
+        self.frame_index = Some(frame_index);
+    }
+}
+
+fn clear_rect(canvas: &mut [u8], canvas_stride: usize, frame: &AnimationFrame) {
+    let (x, y) = (frame.offset.0 as usize, frame.offset.1 as usize);
+    let (width, height) = (frame.image.size.0 as usize, frame.image.size.1 as usize);
+    for row in y..y + height {
+        let start = row * canvas_stride + x * 4;
+        for byte in &mut canvas[start..start + width * 4] {
+            *byte = 0;
+        }
+    }
+}
+
+// GIF transparency is binary, so fully transparent pixels leave the canvas intact.
+fn draw_frame(canvas: &mut [u8], canvas_stride: usize, frame: &AnimationFrame) {
+    let (x, y) = (frame.offset.0 as usize, frame.offset.1 as usize);
+    let (width, height) = (frame.image.size.0 as usize, frame.image.size.1 as usize);
+    for row in 0..height {
+        let source = &frame.image.pixels[row * frame.image.row_stride..row * frame.image.row_stride + width * 4];
+        let start = (y + row) * canvas_stride + x * 4;
+        let destination = &mut canvas[start..start + width * 4];
+        for (to, from) in destination.chunks_mut(4).zip(source.chunks(4)) {
+            if from[3] != 0 {
+                to.copy_from_slice(from);
+            }
+        }
+    }
+}
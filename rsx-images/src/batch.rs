@@ -0,0 +1,105 @@
+/*
+Copyright 2016 Mozilla
+Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+this file except in compliance with the License. You may obtain a copy of the
+License at http://www.apache.org/licenses/LICENSE-2.0
+Unless required by applicable law or agreed to in writing, software distributed
+under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+CONDITIONS OF ANY KIND, either express or implied. See the License for the
+specific language governing permissions and limitations under the License.
+*/
+
+// Decodes batches of images on worker threads. Workers finish in any order,
+// and each decoded image is handed back to the calling thread, which is the
+// only one touching the keys API, either as soon as it's done or in the order
+// the batch was submitted in.
+
+use std::collections::BTreeMap;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use decoded::DecodedImage;
+use error::Result;
+use types::ImageEncodingFormat;
+
+pub const DECODE_THREADS: usize = 4;
+
+// When resource updates for a batch are pushed to the keys API. Submission
+// order makes the updates reproducible across runs, at the cost of holding
+// back images decoded ahead of earlier, slower ones.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DecodeOrdering {
+    AsCompleted,
+    SubmissionOrder
+}
+
+impl Default for DecodeOrdering {
+    fn default() -> Self {
+        DecodeOrdering::AsCompleted
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct DecodeJob {
+    format: ImageEncodingFormat,
+    bytes: Vec<u8>
+}
+
+impl DecodeJob {
+    pub fn new(format: ImageEncodingFormat, bytes: Vec<u8>) -> Self {
+        DecodeJob { format, bytes }
+    }
+}
+
+// Calls `done` once per job, with its position in `jobs`, on this thread.
+pub(crate) fn decode_all<F>(jobs: Vec<DecodeJob>, row_alignment: usize, ordering: DecodeOrdering, mut done: F)
+where
+    F: FnMut(usize, Result<DecodedImage>)
+{
+    let count = jobs.len();
+    let queue = Arc::new(Mutex::new(jobs.into_iter().enumerate()));
+    let (sender, receiver) = mpsc::channel();
+
+    let workers: Vec<_> = (0..DECODE_THREADS.min(count))
+        .map(|_| {
+            let queue = Arc::clone(&queue);
+            let sender = sender.clone();
+            thread::spawn(move || loop {
+                let (position, job) = match queue.lock().ok().and_then(|mut jobs| jobs.next()) {
+                    Some(next) => next,
+                    None => break
+                };
+                let decoded = DecodedImage::from_bytes(job.format, &job.bytes).map(|decoded| decoded.with_row_alignment(row_alignment));
+                if sender.send((position, decoded)).is_err() {
+                    break;
+                }
+            })
+        })
+        .collect();
+    drop(sender);
+
+    let mut next = 0;
+    let mut held = BTreeMap::new();
+    for (position, decoded) in receiver {
+        match ordering {
+            DecodeOrdering::AsCompleted => done(position, decoded),
+            DecodeOrdering::SubmissionOrder => {
+                held.insert(position, decoded);
+                while let Some(decoded) = held.remove(&next) {
+                    done(next, decoded);
+                    next += 1;
+                }
+            }
+        }
+    }
+
+    // Only reached with gaps if a worker died, which decoder panics don't
+    // cause. Later images are still handed back, in order.
+    for (position, decoded) in held {
+        done(position, decoded);
+    }
+    for worker in workers {
+        let _ = worker.join();
+    }
+}
@@ -0,0 +1,82 @@
+/*
+Copyright 2016 Mozilla
+Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+this file except in compliance with the License. You may obtain a copy of the
+License at http://www.apache.org/licenses/LICENSE-2.0
+Unless required by applicable law or agreed to in writing, software distributed
+under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+CONDITIONS OF ANY KIND, either express or implied. See the License for the
+specific language governing permissions and limitations under the License.
+*/
+
+use rsx_resource_updates::pixels;
+
+use decoded::DecodedImage;
+use types::ImagePixelFormat;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum CairoFormat {
+    ARgb32,
+    Rgb24
+}
+
+impl CairoFormat {
+    // Equivalent of `cairo_format_stride_for_width`.
+    pub fn stride_for_width(&self, width: u32) -> usize {
+        (width as usize * 4 + 3) & !3
+    }
+}
+
+// Arguments for `cairo_image_surface_create_for_data`. Pixels are native-endian
+// 32-bit words, premultiplied when the format has an alpha channel.
+#[derive(Debug, PartialEq)]
+pub struct CairoSurfaceData {
+    pub format: CairoFormat,
+    pub width: i32,
+    pub height: i32,
+    pub stride: i32,
+    pub data: Vec<u8>
+}
+
+impl DecodedImage {
+    pub fn to_cairo_surface_data(&self) -> CairoSurfaceData {
+        let (width, height) = self.size;
+        let (format, channels, (red, green, blue)) = match self.format {
+            ImagePixelFormat::Gray(_) => (CairoFormat::Rgb24, 1, (0, 0, 0)),
+            ImagePixelFormat::RGB(_) => (CairoFormat::Rgb24, 3, (0, 1, 2)),
+            ImagePixelFormat::BGR(_) => (CairoFormat::Rgb24, 3, (2, 1, 0)),
+            ImagePixelFormat::RGBA(_) => (CairoFormat::ARgb32, 4, (0, 1, 2)),
+            ImagePixelFormat::BGRA(_) => (CairoFormat::ARgb32, 4, (2, 1, 0))
+        };
+
+        let stride = format.stride_for_width(width);
+        let row_bytes = self.row_bytes();
+        let mut data = Vec::with_capacity(stride * height as usize);
+
+        if row_bytes != 0 {
+            for row in self.pixels.chunks(self.row_stride).take(height as usize) {
+                for pixel in row[..row_bytes].chunks(channels) {
+                    let mut rgba = [pixel[red], pixel[green], pixel[blue], 255];
+                    if channels == 4 {
+                        rgba[3] = pixel[3];
+                        pixels::premultiply(&mut rgba);
+                    }
+                    let mut argb = [rgba[3], rgba[0], rgba[1], rgba[2]];
+                    if cfg!(target_endian = "little") {
+                        argb.reverse();
+                    }
+                    data.extend_from_slice(&argb);
+                }
+                data.resize(data.len() + stride - width as usize * 4, 0);
+            }
+        }
+
+        CairoSurfaceData {
+            format,
+            width: width as i32,
+            height: height as i32,
+            stride: stride as i32,
+            data
+        }
+    }
+}
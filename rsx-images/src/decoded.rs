@@ -12,18 +12,30 @@ specific language governing permissions and limitations under the License.
 use std::sync::Arc;
 
 #[cfg(not(feature = "image-dummy-decode"))]
-use image::{load_from_memory_with_format, DynamicImage, Rgba};
+use image;
+#[cfg(not(feature = "image-dummy-decode"))]
+use image::png::PNGEncoder;
+#[cfg(not(feature = "image-dummy-decode"))]
+use image::{load_from_memory_with_format, ColorType, DynamicImage, Rgba};
 #[cfg(not(feature = "image-dummy-decode"))]
 use imageproc::map::map_colors;
+#[cfg(not(feature = "image-dummy-decode"))]
+use jpeg_decoder::{self, PixelFormat};
+use rsx_resource_updates::pixels::{self, MaskChannel};
+use rsx_resource_updates::staging::StagingPool;
 use rsx_shared::traits::TEncodedImage;
 
-use error::Result;
-use types::{ImageEncodingFormat, ImagePixelFormat, ImageResourceData};
+use error::{ImageError, Result};
+use types::{ImageEncodingFormat, ImageOrientation, ImagePixelFormat, ImageResourceData};
+use util;
 
+// Rows may be padded for texture uploads. The resource data handed to the
+// keys API has no stride field, so renderers recover it from the buffer size.
 #[derive(Debug, PartialEq)]
 pub struct DecodedImage {
     pub format: ImagePixelFormat,
     pub size: (u32, u32),
+    pub row_stride: usize,
     pub pixels: Arc<Vec<u8>>
 }
 
@@ -32,10 +44,96 @@ impl DecodedImage {
         Ok(DecodedImage {
             format,
             size,
+            row_stride: size.0 as usize * pixels::bytes_per_pixel(format),
             pixels
         })
     }
 
+    pub fn from_encoded_image_with_row_alignment<E>(encoded: &E, alignment: usize) -> Result<DecodedImage>
+    where
+        E: TEncodedImage
+    {
+        Ok(Self::from_encoded_image(encoded)?.with_row_alignment(alignment))
+    }
+
+    pub fn row_bytes(&self) -> usize {
+        self.size.0 as usize * pixels::bytes_per_pixel(self.format)
+    }
+
+    pub fn with_row_alignment(self, alignment: usize) -> DecodedImage {
+        let row_bytes = self.row_bytes();
+        let row_stride = pixels::aligned_row_stride(row_bytes, alignment);
+        if row_stride == self.row_stride || self.pixels.is_empty() {
+            return self;
+        }
+        let pixels = if self.row_stride == row_bytes {
+            pixels::pad_rows(&self.pixels, row_bytes, row_stride)
+        } else {
+            pixels::pad_rows(&self.packed_pixels(), row_bytes, row_stride)
+        };
+        DecodedImage {
+            row_stride,
+            pixels: Arc::new(pixels),
+            ..self
+        }
+    }
+
+    // Keeps a single channel, taking 1 byte per pixel. The rows are packed.
+    pub fn into_mask(self) -> (DecodedImage, MaskChannel) {
+        let pixels = self.packed_pixels();
+        let channel = pixels::mask_channel(self.format, &pixels);
+        let mask = if pixels.is_empty() {
+            pixels
+        } else {
+            pixels::to_mask(self.format, channel, &pixels)
+        };
+        let decoded = DecodedImage {
+            format: ImagePixelFormat::Gray(8),
+            size: self.size,
+            row_stride: self.size.0 as usize,
+            pixels: Arc::new(mask)
+        };
+        (decoded, channel)
+    }
+
+    pub fn packed_pixels(&self) -> Vec<u8> {
+        pixels::unpad_rows(&self.pixels, self.row_bytes(), self.row_stride)
+    }
+
+    // Materializes the orientation, see `Image::bake`. The rows are packed.
+    pub fn oriented(self, orientation: ImageOrientation) -> DecodedImage {
+        if orientation == ImageOrientation::Identity {
+            return self;
+        }
+        let pixels = util::orient_pixels(&self.packed_pixels(), self.size, orientation);
+        let size = orientation.display_size(self.size);
+        DecodedImage {
+            row_stride: size.0 as usize * pixels::bytes_per_pixel(self.format),
+            size,
+            pixels: Arc::new(pixels),
+            ..self
+        }
+    }
+
+    // Copies out the rectangle at `origin`, which must lie within the image.
+    pub fn crop(&self, origin: (u32, u32), size: (u32, u32)) -> DecodedImage {
+        let bytes_per_pixel = pixels::bytes_per_pixel(self.format);
+        let row_bytes = size.0 as usize * bytes_per_pixel;
+        let mut cropped = Vec::with_capacity(row_bytes * size.1 as usize);
+        if !self.pixels.is_empty() {
+            for y in origin.1..origin.1 + size.1 {
+                let start = y as usize * self.row_stride + origin.0 as usize * bytes_per_pixel;
+                cropped.extend_from_slice(&self.pixels[start..start + row_bytes]);
+            }
+        }
+        DecodedImage {
+            format: self.format,
+            size,
+            row_stride: row_bytes,
+            pixels: Arc::new(cropped)
+        }
+    }
+
     #[cfg(feature = "image-dummy-decode")]
     pub fn from_encoded_image<E>(encoded: &E) -> Result<DecodedImage>
     where
@@ -52,7 +150,132 @@ impl DecodedImage {
     where
         E: TEncodedImage
     {
-        Self::load_from_memory(encoded.format().unwrap(), encoded.bytes().unwrap())
+        util::catch_decoder_panic(|| Self::load_from_memory(encoded.format().unwrap(), encoded.bytes().unwrap()))
+    }
+
+    // Encoded images share their bytes through `Rc`, so decoding off the
+    // main thread goes through a copy of them instead.
+    #[cfg(feature = "image-dummy-decode")]
+    pub fn from_bytes(_: ImageEncodingFormat, _: &[u8]) -> Result<DecodedImage> {
+        Self::from_raw_parts(ImagePixelFormat::RGBA(0), (0, 0), Arc::default())
+    }
+
+    #[cfg(not(feature = "image-dummy-decode"))]
+    pub fn from_bytes(format: ImageEncodingFormat, bytes: &[u8]) -> Result<DecodedImage> {
+        util::catch_decoder_panic(|| Self::load_from_memory(format, bytes))
+    }
+
+    // Reads only the header, leaving decoding to the consumer, e.g. a browser
+    // using `createImageBitmap`. The pixels are empty.
+    pub fn from_encoded_header<E>(encoded: &E) -> Result<DecodedImage>
+    where
+        E: TEncodedImage
+    {
+        let size = match (encoded.size_info(), encoded.format(), encoded.bytes()) {
+            (Some(size), _, _) => size,
+            (None, Some(format), Some(bytes)) => util::get_dimensions(format, bytes)?,
+            _ => Err(ImageError::ImageNotFound)?
+        };
+        Self::from_raw_parts(ImagePixelFormat::RGBA(8), size, Arc::default())
+    }
+
+    // Moves the pixels into a buffer from the pool, returning the decoder's own
+    // buffer to it. The staged buffer is in flight until the pool reclaims it.
+    pub fn staged(self, pool: &mut StagingPool) -> DecodedImage {
+        let mut buffer = pool.acquire(self.pixels.len());
+        buffer.extend_from_slice(&self.pixels);
+        if let Ok(pixels) = Arc::try_unwrap(self.pixels) {
+            pool.recycle(pixels);
+        }
+        let pixels = Arc::new(buffer);
+        pool.stage(Arc::clone(&pixels));
+        DecodedImage { pixels, ..self }
+    }
+
+    // Fits within `max_size`, preserving the aspect ratio. Never upscales.
+    pub fn downsample_to_fit(self, max_size: (u32, u32)) -> DecodedImage {
+        let size = util::fit_size(self.size, max_size);
+        self.resample(size)
+    }
+
+    fn resample(self, size: (u32, u32)) -> DecodedImage {
+        if size == self.size || self.pixels.is_empty() {
+            return DecodedImage { size, ..self };
+        }
+        let bytes_per_pixel = pixels::bytes_per_pixel(self.format);
+        let pixels = util::downsample_pixels(self.packed_pixels(), bytes_per_pixel, self.size, size);
+        DecodedImage {
+            format: self.format,
+            size,
+            row_stride: size.0 as usize * bytes_per_pixel,
+            pixels: Arc::new(pixels)
+        }
+    }
+
+    #[cfg(feature = "image-dummy-decode")]
+    pub fn from_encoded_image_at_size<E>(encoded: &E, max_size: (u32, u32)) -> Result<DecodedImage>
+    where
+        E: TEncodedImage
+    {
+        Ok(Self::from_encoded_image(encoded)?.downsample_to_fit(max_size))
+    }
+
+    // JPEGs are scaled down while decoding, which is much cheaper than a full
+    // decode. Other formats are decoded at full size and downsampled.
+    #[cfg(not(feature = "image-dummy-decode"))]
+    pub fn from_encoded_image_at_size<E>(encoded: &E, max_size: (u32, u32)) -> Result<DecodedImage>
+    where
+        E: TEncodedImage
+    {
+        let format = encoded.format().unwrap();
+        let bytes = encoded.bytes().unwrap();
+        let decoded = match format {
+            ImageEncodingFormat::JPEG => util::catch_decoder_panic(|| Self::load_scaled_jpeg(bytes, max_size))?,
+            _ => None
+        };
+        match decoded {
+            Some(decoded) => Ok(decoded),
+            None => Ok(util::catch_decoder_panic(|| Self::load_from_memory(format, bytes))?.downsample_to_fit(max_size))
+        }
+    }
+
+    // The DCT scaled output is at least as large as requested, and is then
+    // resampled to the size fitted from the original dimensions. Returns None
+    // for pixel formats which need the full decoder's conversions.
+    #[cfg(not(feature = "image-dummy-decode"))]
+    pub(crate) fn load_scaled_jpeg(bytes: &[u8], max_size: (u32, u32)) -> Result<Option<DecodedImage>> {
+        let mut decoder = jpeg_decoder::Decoder::new(bytes);
+        decoder.read_info().map_err(image::ImageError::from)?;
+        let info = match decoder.info() {
+            Some(info) => info,
+            None => return Ok(None)
+        };
+        let target = util::fit_size((u32::from(info.width), u32::from(info.height)), max_size);
+
+        let (width, height) = decoder
+            .scale(target.0 as u16, target.1 as u16)
+            .map_err(image::ImageError::from)?;
+        let data = decoder.decode().map_err(image::ImageError::from)?;
+        let size = (u32::from(width), u32::from(height));
+
+        let decoded = match info.pixel_format {
+            PixelFormat::L8 => DecodedImage::from_raw_parts(ImagePixelFormat::Gray(8), size, Arc::new(data))?,
+            PixelFormat::RGB24 => {
+                let mut pixels = Vec::with_capacity(data.len() / 3 * 4);
+                for pixel in data.chunks(3) {
+                    pixels.extend_from_slice(pixel);
+                    pixels.push(255);
+                }
+                let format = pixels::to_canonical(
+                    ImagePixelFormat::RGBA(8),
+                    pixels::AlphaMode::Straight,
+                    &mut pixels
+                );
+                DecodedImage::from_raw_parts(format, size, Arc::new(pixels))?
+            }
+            PixelFormat::CMYK32 => return Ok(None)
+        };
+        Ok(Some(decoded.resample(target)))
     }
 
     #[cfg(not(feature = "image-dummy-decode"))]
@@ -61,6 +284,7 @@ impl DecodedImage {
             DynamicImage::ImageLuma8(data) => DecodedImage {
                 format: ImagePixelFormat::Gray(8),
                 size: data.dimensions(),
+                row_stride: data.width() as usize,
                 pixels: Arc::new(data.into_raw())
             },
             DynamicImage::ImageRgba8(data) => {
@@ -73,6 +297,7 @@ impl DecodedImage {
                 DecodedImage {
                     format,
                     size: data.dimensions(),
+                    row_stride: data.width() as usize * 4,
                     pixels: Arc::new(data.into_raw())
                 }
             }
@@ -87,12 +312,41 @@ impl DecodedImage {
                 DecodedImage {
                     format,
                     size: data.dimensions(),
+                    row_stride: data.width() as usize * 4,
                     pixels: Arc::new(data.into_raw())
                 }
             }
         })
     }
 
+    #[cfg(feature = "image-dummy-decode")]
+    pub fn encode_png(&self) -> Result<Vec<u8>> {
+        Ok(Vec::new())
+    }
+
+    #[cfg(not(feature = "image-dummy-decode"))]
+    pub fn encode_png(&self) -> Result<Vec<u8>> {
+        let (width, height) = self.size;
+        let mut pixels = self.packed_pixels();
+        let color = match self.format {
+            ImagePixelFormat::Gray(depth) => ColorType::Gray(depth),
+            ImagePixelFormat::RGB(depth) => ColorType::RGB(depth),
+            ImagePixelFormat::RGBA(depth) => ColorType::RGBA(depth),
+            ImagePixelFormat::BGR(depth) => {
+                pixels::swap_red_blue(&mut pixels, 3);
+                ColorType::RGB(depth)
+            }
+            ImagePixelFormat::BGRA(depth) => {
+                pixels::swap_red_blue(&mut pixels, 4);
+                ColorType::RGBA(depth)
+            }
+        };
+
+        let mut bytes = Vec::new();
+        PNGEncoder::new(&mut bytes).encode(&pixels, width, height, color)?;
+        Ok(bytes)
+    }
+
     pub fn info(&self) -> ImageResourceData {
         ImageResourceData {
             format: self.format,
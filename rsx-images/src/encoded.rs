@@ -14,6 +14,7 @@ use std::rc::Rc;
 use image;
 
 use base64_util;
+use rsx_resource_updates::shmem::SharedMemory;
 use rsx_shared::traits::TEncodedImage;
 
 use error::{ImageError, Result};
@@ -32,6 +33,12 @@ pub enum EncodedImage {
         bytes: Rc<Vec<u8>>,
         data_uri: Rc<String>,
         size_info: Option<(u32, u32)>
+    },
+    Shared {
+        format: ImageEncodingFormat,
+        bytes: Rc<Vec<u8>>,
+        memory: Rc<SharedMemory>,
+        size_info: Option<(u32, u32)>
     }
 }
 
@@ -43,6 +50,47 @@ impl EncodedImage {
     pub fn get_dimensions(format: ImageEncodingFormat, bytes: &[u8]) -> Result<(u32, u32)> {
         util::get_dimensions(format, bytes)
     }
+
+    pub fn from_shared_memory(memory: SharedMemory) -> Result<Self> {
+        let bytes = Rc::new(memory.read_bytes()?);
+        let format = EncodedImage::guess_format(&bytes)?;
+        let size_info = None;
+        Ok(EncodedImage::Shared {
+            format,
+            bytes,
+            memory: Rc::new(memory),
+            size_info
+        })
+    }
+
+    pub fn shared_memory(&self) -> Option<&Rc<SharedMemory>> {
+        match self {
+            &EncodedImage::Shared { ref memory, .. } => Some(memory),
+            _ => None
+        }
+    }
+
+    pub fn from_encoded<E>(encoded: &E) -> Option<Self>
+    where
+        E: TEncodedImage
+    {
+        let format = encoded.format()?;
+        let bytes = Rc::clone(encoded.bytes()?);
+        let size_info = encoded.size_info();
+        Some(match encoded.data_uri() {
+            Some(data_uri) => EncodedImage::BytesAndDataUri {
+                format,
+                bytes,
+                data_uri: Rc::clone(data_uri),
+                size_info
+            },
+            None => EncodedImage::Bytes {
+                format,
+                bytes,
+                size_info
+            }
+        })
+    }
 }
 
 impl TEncodedImage for EncodedImage {
@@ -80,26 +128,32 @@ impl TEncodedImage for EncodedImage {
 
     fn format(&self) -> Option<ImageEncodingFormat> {
         match self {
-            &EncodedImage::Bytes { format, .. } | &EncodedImage::BytesAndDataUri { format, .. } => Some(format)
+            &EncodedImage::Bytes { format, .. } | &EncodedImage::BytesAndDataUri { format, .. } | &EncodedImage::Shared { format, .. } => {
+                Some(format)
+            }
         }
     }
 
     fn bytes(&self) -> Option<&Rc<Vec<u8>>> {
         match self {
-            &EncodedImage::Bytes { ref bytes, .. } | &EncodedImage::BytesAndDataUri { ref bytes, .. } => Some(bytes)
+            &EncodedImage::Bytes { ref bytes, .. }
+            | &EncodedImage::BytesAndDataUri { ref bytes, .. }
+            | &EncodedImage::Shared { ref bytes, .. } => Some(bytes)
         }
     }
 
     fn data_uri(&self) -> Option<&Rc<String>> {
         match self {
-            &EncodedImage::Bytes { .. } => None,
+            &EncodedImage::Bytes { .. } | &EncodedImage::Shared { .. } => None,
             &EncodedImage::BytesAndDataUri { ref data_uri, .. } => Some(data_uri)
         }
     }
 
     fn size_info(&self) -> Option<(u32, u32)> {
         match self {
-            &EncodedImage::Bytes { size_info, .. } | &EncodedImage::BytesAndDataUri { size_info, .. } => size_info
+            &EncodedImage::Bytes { size_info, .. }
+            | &EncodedImage::BytesAndDataUri { size_info, .. }
+            | &EncodedImage::Shared { size_info, .. } => size_info
         }
     }
 
@@ -107,6 +161,7 @@ impl TEncodedImage for EncodedImage {
     fn info(&self) -> ImageEncodedData {
         match self {
             &EncodedImage::Bytes { ref bytes, format, .. } => ImageEncodedData::Bytes { bytes, format },
+            &EncodedImage::Shared { ref bytes, format, .. } => ImageEncodedData::Bytes { bytes, format },
             &EncodedImage::BytesAndDataUri { ref data_uri, .. } => ImageEncodedData::DataUri { data_uri }
         }
     }
@@ -13,6 +13,8 @@ use std::io;
 use std::result;
 
 use image;
+use rsx_resource_updates::codes::{self, ErrorCode};
+use rsx_resource_updates::stats::ErrorCategory;
 
 pub type Result<T> = result::Result<T, ImageError>;
 
@@ -21,7 +23,62 @@ pub enum ImageError {
     IOError(io::Error),
     LibError(image::ImageError),
     DataUriDecodeError,
-    ImageAlreadyAdded
+    ImageAlreadyAdded,
+    ImageNotFound,
+    InvalidImageSet,
+    InvalidImageArray,
+    InvalidMaskedImage,
+    InvalidAnimation,
+    InvalidSpriteGrid,
+    ReplayUnsupported,
+    DecoderPanic(String)
+}
+
+impl ImageError {
+    pub fn category(&self) -> Option<ErrorCategory> {
+        match self {
+            &ImageError::IOError(ref err) if err.kind() == io::ErrorKind::NotFound => Some(ErrorCategory::MissingResource),
+            &ImageError::LibError(_) | &ImageError::DataUriDecodeError | &ImageError::InvalidAnimation | &ImageError::DecoderPanic(_) => {
+                Some(ErrorCategory::DecodeFailure)
+            }
+            &ImageError::ImageNotFound => Some(ErrorCategory::MissingResource),
+            _ => None
+        }
+    }
+
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            &ImageError::IOError(_) => codes::IMAGE_IO,
+            &ImageError::LibError(_) => codes::IMAGE_DECODER,
+            &ImageError::DataUriDecodeError => codes::IMAGE_DATA_URI_DECODE,
+            &ImageError::ImageAlreadyAdded => codes::IMAGE_ALREADY_ADDED,
+            &ImageError::ImageNotFound => codes::IMAGE_NOT_FOUND,
+            &ImageError::InvalidImageSet => codes::IMAGE_INVALID_SET,
+            &ImageError::InvalidImageArray => codes::IMAGE_INVALID_ARRAY,
+            &ImageError::InvalidMaskedImage => codes::IMAGE_INVALID_MASKED,
+            &ImageError::InvalidAnimation => codes::IMAGE_INVALID_ANIMATION,
+            &ImageError::InvalidSpriteGrid => codes::IMAGE_INVALID_SPRITE_GRID,
+            &ImageError::ReplayUnsupported => codes::IMAGE_REPLAY_UNSUPPORTED,
+            &ImageError::DecoderPanic(_) => codes::IMAGE_DECODER_PANIC
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            &ImageError::IOError(ref err) => format!("I/O error: {}", err),
+            &ImageError::LibError(ref err) => format!("Decoder error: {}", err),
+            &ImageError::DataUriDecodeError => "Invalid data URI".to_string(),
+            &ImageError::ImageAlreadyAdded => "Image already added".to_string(),
+            &ImageError::ImageNotFound => "Image not found".to_string(),
+            &ImageError::InvalidImageSet => "Invalid image set".to_string(),
+            &ImageError::InvalidImageArray => "Invalid image array".to_string(),
+            &ImageError::InvalidMaskedImage => "Invalid masked image".to_string(),
+            &ImageError::InvalidAnimation => "Invalid animation".to_string(),
+            &ImageError::InvalidSpriteGrid => "Invalid sprite grid".to_string(),
+            &ImageError::ReplayUnsupported => "Replay unsupported".to_string(),
+            &ImageError::DecoderPanic(ref reason) => format!("Decoder panicked: {}", reason)
+        }
+    }
 }
 
 impl From<io::Error> for ImageError {
@@ -0,0 +1,137 @@
+/*
+Copyright 2016 Mozilla
+Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+this file except in compliance with the License. You may obtain a copy of the
+License at http://www.apache.org/licenses/LICENSE-2.0
+Unless required by applicable law or agreed to in writing, software distributed
+under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+CONDITIONS OF ANY KIND, either express or implied. See the License for the
+specific language governing permissions and limitations under the License.
+*/
+
+// Drawing at less than this scale wastes enough memory and filtering quality
+// that renderers are better off asking for a downsampled variant instead.
+pub const RESIZED_VARIANT_THRESHOLD: f32 = 0.5;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ObjectFit {
+    Fill,
+    Contain,
+    Cover,
+    None,
+    ScaleDown
+}
+
+impl Default for ObjectFit {
+    fn default() -> Self {
+        ObjectFit::Fill
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct FitRect {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32
+}
+
+impl FitRect {
+    pub fn new(x: f32, y: f32, width: f32, height: f32) -> Self {
+        FitRect { x, y, width, height }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.width <= 0.0 || self.height <= 0.0
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct FitLayout {
+    // Part of the image to sample from, in image pixels.
+    pub source: FitRect,
+    // Where to draw it, relative to the box's origin.
+    pub destination: FitRect,
+    pub scale: (f32, f32),
+    // Size to request via `get_image_at_size` when the image is drawn small
+    // enough for a downsampled variant to be worth generating.
+    pub resized_size: Option<(u32, u32)>
+}
+
+impl FitLayout {
+    pub fn needs_resized_variant(&self) -> bool {
+        self.resized_size.is_some()
+    }
+}
+
+// Computes the object-fit layout of an image within a box, with the image
+// centered as per the default `object-position`. The box is expected to be in
+// the same units as the image, i.e. device pixels for a decoded image.
+pub fn object_fit(image_size: (u32, u32), box_size: (f32, f32), fit: ObjectFit) -> FitLayout {
+    let (image_w, image_h) = (image_size.0 as f32, image_size.1 as f32);
+    let (box_w, box_h) = box_size;
+
+    if image_w <= 0.0 || image_h <= 0.0 || box_w <= 0.0 || box_h <= 0.0 {
+        return FitLayout {
+            source: FitRect::new(0.0, 0.0, image_w, image_h),
+            destination: FitRect::default(),
+            scale: (0.0, 0.0),
+            resized_size: None
+        };
+    }
+
+    let contain = (box_w / image_w).min(box_h / image_h);
+    let scale = match fit {
+        ObjectFit::Fill => (box_w / image_w, box_h / image_h),
+        ObjectFit::Contain => (contain, contain),
+        ObjectFit::Cover => {
+            let cover = (box_w / image_w).max(box_h / image_h);
+            (cover, cover)
+        }
+        ObjectFit::None => (1.0, 1.0),
+        ObjectFit::ScaleDown => {
+            let scale_down = contain.min(1.0);
+            (scale_down, scale_down)
+        }
+    };
+
+    // The scaled image is centered and clipped to the box, with the source
+    // rect shrunk accordingly so that nothing outside the box is sampled.
+    let (scaled_w, scaled_h) = (image_w * scale.0, image_h * scale.1);
+    let (dest_x, dest_w) = clip_axis(scaled_w, box_w);
+    let (dest_y, dest_h) = clip_axis(scaled_h, box_h);
+    let source = FitRect::new(
+        ((scaled_w - box_w) / 2.0).max(0.0) / scale.0,
+        ((scaled_h - box_h) / 2.0).max(0.0) / scale.1,
+        dest_w / scale.0,
+        dest_h / scale.1
+    );
+
+    // Variants keep the aspect ratio, so stretched images are resized to the
+    // larger of the two scales and never end up undersampled on either axis.
+    let max_scale = scale.0.max(scale.1);
+    let resized_size = if max_scale < RESIZED_VARIANT_THRESHOLD {
+        Some((
+            ((image_w * max_scale).ceil() as u32).max(1),
+            ((image_h * max_scale).ceil() as u32).max(1)
+        ))
+    } else {
+        None
+    };
+
+    FitLayout {
+        source,
+        destination: FitRect::new(dest_x, dest_y, dest_w, dest_h),
+        scale,
+        resized_size
+    }
+}
+
+fn clip_axis(scaled: f32, available: f32) -> (f32, f32) {
+    if scaled > available {
+        (0.0, available)
+    } else {
+        ((available - scaled) / 2.0, scaled)
+    }
+}
@@ -0,0 +1,35 @@
+/*
+Copyright 2016 Mozilla
+Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+this file except in compliance with the License. You may obtain a copy of the
+License at http://www.apache.org/licenses/LICENSE-2.0
+Unless required by applicable law or agreed to in writing, software distributed
+under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+CONDITIONS OF ANY KIND, either express or implied. See the License for the
+specific language governing permissions and limitations under the License.
+*/
+
+// Entry points for fuzzers, taking plain slices rather than shared buffers.
+// Every input is expected to give a result, decoder panics included.
+
+use rsx_shared::traits::TEncodedImage;
+
+use decoded::DecodedImage;
+use encoded::EncodedImage;
+use error::Result;
+
+pub fn encoded_image(bytes: &[u8]) -> Result<EncodedImage> {
+    EncodedImage::from_bytes(bytes.to_vec())
+}
+
+pub fn encoded_image_from_data_uri(data_uri: &str) -> Result<EncodedImage> {
+    EncodedImage::from_data_uri(data_uri.to_string())
+}
+
+pub fn dimensions(bytes: &[u8]) -> Result<(u32, u32)> {
+    EncodedImage::get_dimensions(EncodedImage::guess_format(bytes)?, bytes)
+}
+
+pub fn decoded_image(bytes: &[u8]) -> Result<DecodedImage> {
+    DecodedImage::from_encoded_image(&encoded_image(bytes)?)
+}
@@ -12,9 +12,13 @@ specific language governing permissions and limitations under the License.
 #![cfg_attr(feature = "cargo-clippy", allow(match_ref_pats, type_complexity))]
 
 extern crate base64_util;
+#[cfg(not(feature = "image-dummy-decode"))]
+extern crate flate2;
 extern crate fnv;
 extern crate image;
 extern crate imageproc;
+#[cfg(not(feature = "image-dummy-decode"))]
+extern crate jpeg_decoder;
 extern crate rsx_resource_updates;
 extern crate rsx_shared;
 extern crate serde;
@@ -22,9 +26,18 @@ extern crate serde;
 extern crate serde_derive;
 extern crate uuid;
 
+pub mod animation;
+pub mod batch;
 pub mod error;
 pub mod types;
 pub mod encoded;
 pub mod decoded;
+pub mod fit;
+pub mod fuzz;
+pub mod progressive;
+#[cfg(feature = "cairo-interop")]
+pub mod cairo;
+#[cfg(feature = "skia-interop")]
+pub mod skia;
 
 mod util;
@@ -0,0 +1,283 @@
+/*
+Copyright 2016 Mozilla
+Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+this file except in compliance with the License. You may obtain a copy of the
+License at http://www.apache.org/licenses/LICENSE-2.0
+Unless required by applicable law or agreed to in writing, software distributed
+under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+CONDITIONS OF ANY KIND, either express or implied. See the License for the
+specific language governing permissions and limitations under the License.
+*/
+
+// Progressive JPEGs and interlaced PNGs can be shown before they're fully
+// decoded, the way browsers show slow loading photos. The coarse pass is much
+// cheaper than a full decode: JPEGs are decoded at an eighth of their size,
+// and only the first of the seven Adam7 passes of PNGs, which holds every
+// eighth pixel of every eighth row, is inflated. Previews are sent at their
+// own size with a scale, so they measure and lay out like the final image.
+
+#[cfg(not(feature = "image-dummy-decode"))]
+use std::io::Read;
+#[cfg(not(feature = "image-dummy-decode"))]
+use std::sync::Arc;
+
+#[cfg(not(feature = "image-dummy-decode"))]
+use flate2::read::ZlibDecoder;
+#[cfg(not(feature = "image-dummy-decode"))]
+use rsx_resource_updates::pixels;
+#[cfg(not(feature = "image-dummy-decode"))]
+use rsx_resource_updates::pixels::AlphaMode;
+
+use decoded::DecodedImage;
+use types::ImageEncodingFormat;
+#[cfg(not(feature = "image-dummy-decode"))]
+use types::ImagePixelFormat;
+#[cfg(not(feature = "image-dummy-decode"))]
+use util;
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+// Headers may claim any size, so larger images aren't previewed, rather than
+// allocating for their passes. They're only shown once fully decoded.
+#[cfg(not(feature = "image-dummy-decode"))]
+const MAX_PREVIEW_DIMENSION: u32 = 1 << 16;
+#[cfg(not(feature = "image-dummy-decode"))]
+const MAX_PREVIEW_PIXELS: u64 = 1 << 28;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ProgressiveKind {
+    // JPEGs whose scans each refine the whole image.
+    ProgressiveJpeg,
+    // PNGs whose rows are stored in seven passes of increasing density.
+    InterlacedPng
+}
+
+impl ProgressiveKind {
+    pub fn sniff(format: ImageEncodingFormat, bytes: &[u8]) -> Option<Self> {
+        match format {
+            ImageEncodingFormat::JPEG if is_progressive_jpeg(bytes) => Some(ProgressiveKind::ProgressiveJpeg),
+            ImageEncodingFormat::PNG if is_interlaced_png(bytes) => Some(ProgressiveKind::InterlacedPng),
+            _ => None
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct PreviewPass {
+    pub kind: ProgressiveKind,
+    // Size of the final image, of which the pass is a scaled down version.
+    pub size: (u32, u32),
+    pub decoded: DecodedImage
+}
+
+impl PreviewPass {
+    // Where sizes aren't multiples of the pass's, its height lays out within
+    // a pass pixel of the final image's.
+    pub fn scale(&self) -> f32 {
+        self.decoded.size.0 as f32 / self.size.0 as f32
+    }
+}
+
+// Returns nothing for images which aren't progressive, or whose coarse pass
+// can't be read, in which case they're only shown once fully decoded.
+pub fn preview_pass(format: ImageEncodingFormat, bytes: &[u8]) -> Option<PreviewPass> {
+    let kind = ProgressiveKind::sniff(format, bytes)?;
+    let (decoded, size) = match kind {
+        ProgressiveKind::ProgressiveJpeg => coarse_jpeg_pass(bytes)?,
+        ProgressiveKind::InterlacedPng => coarse_png_pass(bytes)?
+    };
+    Some(PreviewPass { kind, size, decoded })
+}
+
+#[cfg(not(feature = "image-dummy-decode"))]
+fn within_preview_limits(size: (u32, u32)) -> bool {
+    size.0 > 0 && size.1 > 0 && size.0 <= MAX_PREVIEW_DIMENSION && size.1 <= MAX_PREVIEW_DIMENSION
+        && u64::from(size.0) * u64::from(size.1) <= MAX_PREVIEW_PIXELS
+}
+
+// Frame markers from SOF2 on, other than the ones reserved for other uses,
+// describe progressive frames. The frame header always precedes the scans.
+fn is_progressive_jpeg(bytes: &[u8]) -> bool {
+    let mut offset = 2;
+    while offset + 4 <= bytes.len() && bytes[offset] == 0xFF {
+        match bytes[offset + 1] {
+            0xC2 | 0xC6 | 0xCA | 0xCE => return true,
+            0xC0 | 0xC1 | 0xC3 | 0xC5 | 0xC7 | 0xC9 | 0xCB | 0xCD | 0xCF | 0xDA => return false,
+            _ => {}
+        }
+        let len = (usize::from(bytes[offset + 2]) << 8) | usize::from(bytes[offset + 3]);
+        offset += 2 + len;
+    }
+    false
+}
+
+fn is_interlaced_png(bytes: &[u8]) -> bool {
+    bytes.starts_with(PNG_SIGNATURE) && bytes.get(12..16) == Some(&b"IHDR"[..]) && bytes.get(28) == Some(&1)
+}
+
+#[cfg(feature = "image-dummy-decode")]
+fn coarse_jpeg_pass(_: &[u8]) -> Option<(DecodedImage, (u32, u32))> {
+    None
+}
+
+#[cfg(not(feature = "image-dummy-decode"))]
+fn coarse_jpeg_pass(bytes: &[u8]) -> Option<(DecodedImage, (u32, u32))> {
+    let size = util::get_dimensions(ImageEncodingFormat::JPEG, bytes).ok()?;
+    if !within_preview_limits(size) {
+        return None;
+    }
+    let coarse_size = ((size.0 + 7) / 8, (size.1 + 7) / 8);
+    let coarse = DecodedImage::load_scaled_jpeg(bytes, coarse_size).ok()??;
+    Some((coarse, size))
+}
+
+#[cfg(not(feature = "image-dummy-decode"))]
+struct PngHeader {
+    size: (u32, u32),
+    depth: u8,
+    color_type: u8
+}
+
+#[cfg(feature = "image-dummy-decode")]
+fn coarse_png_pass(_: &[u8]) -> Option<(DecodedImage, (u32, u32))> {
+    None
+}
+
+#[cfg(not(feature = "image-dummy-decode"))]
+fn coarse_png_pass(bytes: &[u8]) -> Option<(DecodedImage, (u32, u32))> {
+    let mut header = None;
+    let mut palette: &[u8] = &[];
+    let mut transparency: &[u8] = &[];
+    let mut data = Vec::new();
+
+    let mut offset = PNG_SIGNATURE.len();
+    while offset + 8 <= bytes.len() {
+        let len = read_u32(bytes, offset)? as usize;
+        let chunk = bytes.get(offset + 8..offset.checked_add(8 + len)?)?;
+        match &bytes[offset + 4..offset + 8] {
+            b"IHDR" if chunk.len() >= 13 => {
+                header = Some(PngHeader {
+                    size: (read_u32(chunk, 0)?, read_u32(chunk, 4)?),
+                    depth: chunk[8],
+                    color_type: chunk[9]
+                })
+            }
+            b"PLTE" => palette = chunk,
+            b"tRNS" => transparency = chunk,
+            b"IDAT" => data.extend_from_slice(chunk),
+            b"IEND" => break,
+            _ => {}
+        }
+        // Skips the chunk's CRC as well.
+        offset += 12 + len;
+    }
+
+    let header = header?;
+    let channels = match header.color_type {
+        0 | 3 => 1,
+        2 => 3,
+        4 => 2,
+        6 => 4,
+        _ => return None
+    };
+    if !within_preview_limits(header.size) {
+        return None;
+    }
+
+    // The first pass starts every scanline with a filter type, like the others.
+    let pass_size = ((header.size.0 + 7) / 8, (header.size.1 + 7) / 8);
+    let bits_per_pixel = usize::from(header.depth) * channels;
+    let row_bytes = (pass_size.0 as usize * bits_per_pixel + 7) / 8;
+    let filter_bpp = (bits_per_pixel / 8).max(1);
+    let mut pass = vec![0; pass_size.1 as usize * (row_bytes + 1)];
+    ZlibDecoder::new(&data[..]).read_exact(&mut pass).ok()?;
+
+    let mut previous = vec![0; row_bytes];
+    let mut pixels = Vec::with_capacity(pass_size.0 as usize * pass_size.1 as usize * 4);
+    for line in pass.chunks_mut(row_bytes + 1) {
+        let (filter, row) = line.split_at_mut(1);
+        unfilter(filter[0], filter_bpp, &previous, row)?;
+        for x in 0..pass_size.0 as usize {
+            let sample = |channel: usize| read_sample(row, header.depth, x * channels + channel);
+            let rgba = match header.color_type {
+                0 => [sample(0), sample(0), sample(0), 255],
+                2 => [sample(0), sample(1), sample(2), 255],
+                3 => {
+                    let index = usize::from(read_index(row, header.depth, x));
+                    let color = palette.get(index * 3..index * 3 + 3)?;
+                    [color[0], color[1], color[2], *transparency.get(index).unwrap_or(&255)]
+                }
+                4 => [sample(0), sample(0), sample(0), sample(1)],
+                _ => [sample(0), sample(1), sample(2), sample(3)]
+            };
+            pixels.extend_from_slice(&rgba);
+        }
+        previous.copy_from_slice(row);
+    }
+
+    let format = pixels::to_canonical(ImagePixelFormat::RGBA(8), AlphaMode::Straight, &mut pixels);
+    let coarse = DecodedImage::from_raw_parts(format, pass_size, Arc::new(pixels)).ok()?;
+    Some((coarse, header.size))
+}
+
+#[cfg(not(feature = "image-dummy-decode"))]
+fn unfilter(filter: u8, bpp: usize, previous: &[u8], row: &mut [u8]) -> Option<()> {
+    for i in 0..row.len() {
+        let left = if i >= bpp { row[i - bpp] } else { 0 };
+        let up = previous[i];
+        let up_left = if i >= bpp { previous[i - bpp] } else { 0 };
+        let predicted = match filter {
+            0 => 0,
+            1 => left,
+            2 => up,
+            3 => ((u16::from(left) + u16::from(up)) / 2) as u8,
+            4 => paeth(left, up, up_left),
+            _ => return None
+        };
+        row[i] = row[i].wrapping_add(predicted);
+    }
+    Some(())
+}
+
+#[cfg(not(feature = "image-dummy-decode"))]
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = i16::from(a) + i16::from(b) - i16::from(c);
+    let (pa, pb, pc) = ((p - i16::from(a)).abs(), (p - i16::from(b)).abs(), (p - i16::from(c)).abs());
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+// Samples are scaled to 8 bits. Samples of 16 bits keep their high byte.
+#[cfg(not(feature = "image-dummy-decode"))]
+fn read_sample(row: &[u8], depth: u8, index: usize) -> u8 {
+    match depth {
+        16 => row[index * 2],
+        8 => row[index],
+        _ => {
+            let max = (1u16 << depth) - 1;
+            (u16::from(read_index(row, depth, index)) * 255 / max) as u8
+        }
+    }
+}
+
+// Samples of less than 8 bits are packed from the high bits of each byte.
+#[cfg(not(feature = "image-dummy-decode"))]
+fn read_index(row: &[u8], depth: u8, index: usize) -> u8 {
+    if depth >= 8 {
+        return row[index * usize::from(depth / 8)];
+    }
+    let bit = index * usize::from(depth);
+    let shift = 8 - usize::from(depth) - bit % 8;
+    (row[bit / 8] >> shift) & ((1u16 << depth) - 1) as u8
+}
+
+#[cfg(not(feature = "image-dummy-decode"))]
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    let bytes = bytes.get(offset..offset + 4)?;
+    Some((u32::from(bytes[0]) << 24) | (u32::from(bytes[1]) << 16) | (u32::from(bytes[2]) << 8) | u32::from(bytes[3]))
+}
@@ -0,0 +1,82 @@
+/*
+Copyright 2016 Mozilla
+Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+this file except in compliance with the License. You may obtain a copy of the
+License at http://www.apache.org/licenses/LICENSE-2.0
+Unless required by applicable law or agreed to in writing, software distributed
+under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+CONDITIONS OF ANY KIND, either express or implied. See the License for the
+specific language governing permissions and limitations under the License.
+*/
+
+use std::sync::Arc;
+
+use decoded::DecodedImage;
+use types::ImagePixelFormat;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum SkiaColorType {
+    RGBA8888,
+    BGRA8888,
+    Gray8
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum SkiaAlphaType {
+    Opaque,
+    Premul,
+    Unpremul
+}
+
+// Mirrors `SkImageInfo` plus the row bytes expected by `SkPixmap`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct SkiaImageInfo {
+    pub width: i32,
+    pub height: i32,
+    pub color_type: SkiaColorType,
+    pub alpha_type: SkiaAlphaType,
+    pub row_bytes: usize
+}
+
+#[derive(Debug, PartialEq)]
+pub struct SkiaPixmap {
+    pub info: SkiaImageInfo,
+    pub pixels: Arc<Vec<u8>>
+}
+
+impl DecodedImage {
+    // Formats Skia supports natively share the decoded buffer, while three
+    // channel pixels are expanded to opaque four channel ones.
+    pub fn to_skia_pixmap(&self) -> SkiaPixmap {
+        let (width, height) = self.size;
+        let (color_type, alpha_type) = match self.format {
+            ImagePixelFormat::Gray(_) => (SkiaColorType::Gray8, SkiaAlphaType::Opaque),
+            ImagePixelFormat::RGB(_) => (SkiaColorType::RGBA8888, SkiaAlphaType::Opaque),
+            ImagePixelFormat::BGR(_) => (SkiaColorType::BGRA8888, SkiaAlphaType::Opaque),
+            ImagePixelFormat::RGBA(_) => (SkiaColorType::RGBA8888, SkiaAlphaType::Unpremul),
+            ImagePixelFormat::BGRA(_) => (SkiaColorType::BGRA8888, SkiaAlphaType::Unpremul)
+        };
+
+        let (row_bytes, pixels) = match self.format {
+            ImagePixelFormat::RGB(_) | ImagePixelFormat::BGR(_) => {
+                let mut expanded = Vec::with_capacity(width as usize * height as usize * 4);
+                for pixel in self.packed_pixels().chunks(3) {
+                    expanded.extend_from_slice(&[pixel[0], pixel[1], pixel[2], 255]);
+                }
+                (width as usize * 4, Arc::new(expanded))
+            }
+            _ => (self.row_stride, Arc::clone(&self.pixels))
+        };
+
+        SkiaPixmap {
+            info: SkiaImageInfo {
+                width: width as i32,
+                height: height as i32,
+                color_type,
+                alpha_type,
+                row_bytes
+            },
+            pixels
+        }
+    }
+}
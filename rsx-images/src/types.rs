@@ -17,40 +17,175 @@ use std::ops::Deref;
 use std::rc::Rc;
 use std::sync::Arc;
 
-use fnv::{FnvHashMap, FnvHasher};
+use base64_util;
+use fnv::{FnvHashMap, FnvHashSet, FnvHasher};
+use rsx_resource_updates::cell::SharedCell;
+#[cfg(feature = "key-balance-watchdog")]
+use rsx_resource_updates::ledger::KeyLedger;
+use rsx_resource_updates::pixels::{self, CANONICAL_ALPHA_MODE};
+use rsx_resource_updates::quota::{self, ResourceExpiry};
+use rsx_resource_updates::staging::StagingPool;
+use rsx_resource_updates::stats::{ErrorStats, IdStats};
 use rsx_shared::traits::{TDimensionsInfo, TEncodedImage, TImageCache, TImageKeysAPI, TMediaKey};
 use uuid::Uuid;
 
+use batch::{self, DecodeJob};
 use error::{ImageError, Result};
+use fit;
+use progressive;
 
+pub use animation::{AnimatedImage, AnimationClock, AnimationFrame, AnimationTick, FrameDisposal, LoopCount};
+pub use batch::DecodeOrdering;
 pub use decoded::DecodedImage;
 pub use encoded::EncodedImage;
+pub use fit::{object_fit, FitLayout, FitRect, ObjectFit};
+pub use rsx_resource_updates::display::{DisplayProfile, SubpixelLayout};
+pub use rsx_resource_updates::pixels::{AlphaMode, MaskChannel};
+pub use rsx_resource_updates::quota::{ExpiryPolicy, ResourcePriority};
+pub use rsx_resource_updates::shmem::{SharedMemory, SharedMemoryDescriptor, SharedMemoryKind};
+pub use rsx_resource_updates::snapshot::{KeyDiff, KeySnapshot};
+pub use rsx_resource_updates::units::Scale;
+pub use rsx_resource_updates::staging::StagingStats;
+pub use rsx_resource_updates::stats::{DeferredError, ErrorCategory, ErrorCounters, IdCounters};
+pub use rsx_resource_updates::traits::TImageKeysAPIExt;
+pub use rsx_resource_updates::types::ImageOrientation;
 pub use rsx_shared::types::{ImageEncodedData, ImageEncodingFormat, ImagePixelFormat, ImageResourceData};
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
-pub struct ImageId(u64);
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct ImageId {
+    origin: Option<u64>,
+    src: u64
+}
 
 impl ImageId {
     pub fn new<P>(src: P) -> Self
     where
         P: AsRef<str>
     {
-        let mut hasher = FnvHasher::default();
-        hasher.write(src.as_ref().as_bytes());
-        ImageId(hasher.finish())
+        ImageId {
+            origin: None,
+            src: hash_str(src)
+        }
+    }
+
+    pub fn with_origin<O, P>(origin: O, src: P) -> Self
+    where
+        O: AsRef<str>,
+        P: AsRef<str>
+    {
+        ImageId {
+            origin: Some(hash_str(origin)),
+            src: hash_str(src)
+        }
     }
 
     pub fn uuid() -> Self {
         let mut hasher = FnvHasher::default();
         Uuid::new_v4().hash(&mut hasher);
-        ImageId(hasher.finish())
+        ImageId {
+            origin: None,
+            src: hasher.finish()
+        }
+    }
+
+    pub fn with_scale(&self, scale: Scale) -> Self {
+        let mut hasher = FnvHasher::default();
+        hasher.write_u64(self.src);
+        hasher.write_u32(scale.0.to_bits());
+        ImageId {
+            origin: self.origin,
+            src: hasher.finish()
+        }
+    }
+
+    pub fn with_size(&self, size: (u32, u32)) -> Self {
+        let mut hasher = FnvHasher::default();
+        hasher.write_u64(self.src);
+        hasher.write_u32(size.0);
+        hasher.write_u32(size.1);
+        ImageId {
+            origin: self.origin,
+            src: hasher.finish()
+        }
+    }
+
+    // Identifies the mask paired with an image by `add_masked_image`.
+    pub fn with_mask(&self) -> Self {
+        let mut hasher = FnvHasher::default();
+        hasher.write_u64(self.src);
+        hasher.write(b"mask");
+        ImageId {
+            origin: self.origin,
+            src: hasher.finish()
+        }
+    }
+
+    // Identifies the recolored variant made by `get_tinted_image`.
+    pub fn with_tint(&self, color: TintColor) -> Self {
+        let mut hasher = FnvHasher::default();
+        hasher.write_u64(self.src);
+        hasher.write(b"tint");
+        hasher.write(&[color.r, color.g, color.b, color.a]);
+        ImageId {
+            origin: self.origin,
+            src: hasher.finish()
+        }
+    }
+
+    // Identifies the coverage mask which tinted variants are drawn through.
+    pub fn with_tint_coverage(&self) -> Self {
+        let mut hasher = FnvHasher::default();
+        hasher.write_u64(self.src);
+        hasher.write(b"tint-coverage");
+        ImageId {
+            origin: self.origin,
+            src: hasher.finish()
+        }
+    }
+
+    // Identifies a cell sliced by `add_sprite_grid`. Equals the id of the
+    // sheet's src with `#index` appended, e.g. `sheet.png#3`.
+    pub fn with_cell(&self, index: usize) -> Self {
+        let mut hasher = FnvHasher::with_key(self.src);
+        hasher.write(format!("#{}", index).as_bytes());
+        ImageId {
+            origin: self.origin,
+            src: hasher.finish()
+        }
+    }
+
+    pub fn has_origin(&self) -> bool {
+        self.origin.is_some()
+    }
+
+    pub fn is_from_origin<O>(&self, origin: O) -> bool
+    where
+        O: AsRef<str>
+    {
+        self.origin == Some(hash_str(origin))
     }
 }
 
+fn hash_str<T>(value: T) -> u64
+where
+    T: AsRef<str>
+{
+    let mut hasher = FnvHasher::default();
+    hasher.write(value.as_ref().as_bytes());
+    hasher.finish()
+}
+
 #[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize)]
 pub struct ImageDimensionsInfo<ImageKey> {
     image_key: ImageKey,
-    size: (u32, u32)
+    size: (u32, u32),
+    scale: f32
+}
+
+impl<ImageKey> ImageDimensionsInfo<ImageKey> {
+    pub fn scale(&self) -> f32 {
+        self.scale
+    }
 }
 
 impl<ImageKey> TDimensionsInfo for ImageDimensionsInfo<ImageKey>
@@ -72,20 +207,44 @@ where
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct Image<ImageKey> {
     format: ImagePixelFormat,
     size: (u32, u32),
+    row_stride: usize,
     pixels: Arc<Vec<u8>>,
+    orientation: ImageOrientation,
+    scale: f32,
+    layer: Option<u32>,
     external_key: ImageKey
 }
 
 impl<ImageKey> Image<ImageKey> {
     pub fn new(format: ImagePixelFormat, size: (u32, u32), pixels: Arc<Vec<u8>>, external_key: ImageKey) -> Rc<Self> {
+        Self::new_with_orientation(
+            format,
+            size,
+            pixels,
+            ImageOrientation::Identity,
+            external_key
+        )
+    }
+
+    pub fn new_with_orientation(
+        format: ImagePixelFormat,
+        size: (u32, u32),
+        pixels: Arc<Vec<u8>>,
+        orientation: ImageOrientation,
+        external_key: ImageKey
+    ) -> Rc<Self> {
         Rc::new(Image {
             format,
             size,
+            row_stride: size.0 as usize * pixels::bytes_per_pixel(format),
             pixels,
+            orientation,
+            scale: 1.0,
+            layer: None,
             external_key
         })
     }
@@ -102,10 +261,73 @@ impl<ImageKey> Image<ImageKey> {
         self.size.1
     }
 
+    pub fn row_stride(&self) -> usize {
+        self.row_stride
+    }
+
     pub fn pixels(&self) -> Arc<Vec<u8>> {
         Arc::clone(&self.pixels)
     }
 
+    pub fn alpha_mode(&self) -> AlphaMode {
+        CANONICAL_ALPHA_MODE
+    }
+
+    pub fn orientation(&self) -> ImageOrientation {
+        self.orientation
+    }
+
+    pub fn display_width(&self) -> u32 {
+        self.orientation.display_size(self.size).0
+    }
+
+    pub fn display_height(&self) -> u32 {
+        self.orientation.display_size(self.size).1
+    }
+
+    pub fn scale(&self) -> f32 {
+        self.scale
+    }
+
+    // Index within the texture array the image was added to, if any. Layers
+    // share the external key of their array.
+    pub fn layer(&self) -> Option<u32> {
+        self.layer
+    }
+
+    pub fn css_width(&self) -> f32 {
+        self.display_width() as f32 / self.scale
+    }
+
+    pub fn css_height(&self) -> f32 {
+        self.display_height() as f32 / self.scale
+    }
+
+    // Lays out the image as displayed, i.e. after applying its orientation.
+    pub fn object_fit(&self, box_size: (f32, f32), fit: ObjectFit) -> FitLayout {
+        fit::object_fit(self.orientation.display_size(self.size), box_size, fit)
+    }
+
+    // Pixels are stored unrotated and renderers are expected to apply the
+    // orientation via UV transforms. Baking materializes them instead.
+    pub fn bake(&self) -> DecodedImage {
+        let decoded = DecodedImage {
+            format: self.format,
+            size: self.size,
+            row_stride: self.row_stride,
+            pixels: Arc::clone(&self.pixels)
+        };
+        decoded.oriented(self.orientation)
+    }
+
+    pub fn to_data_uri(&self) -> Result<String> {
+        let bytes = self.bake().encode_png()?;
+        Ok(base64_util::to_image_data_uri(
+            ImageEncodingFormat::PNG.as_ref(),
+            &bytes
+        ))
+    }
+
     pub fn external_key(&self) -> ImageKey
     where
         ImageKey: Copy
@@ -119,20 +341,34 @@ impl<ImageKey> Image<ImageKey> {
     {
         ImageDimensionsInfo {
             image_key: self.external_key,
-            size: self.size
+            size: (
+                self.css_width().round() as u32,
+                self.css_height().round() as u32
+            ),
+            scale: self.scale
         }
     }
 }
 
+// The images of a cache at some point, sorted by id, which can be sent to
+// another thread. Images added, replaced or removed afterwards don't show up.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ImageCacheSnapshot<ImageKey> {
+    pub images: Vec<(ImageId, Image<ImageKey>)>
+}
+
+// Ids of images whose addition was queued while the cache was borrowed.
+type QueuedImages = Rc<RefCell<FnvHashSet<ImageId>>>;
+
 #[derive(Debug, PartialEq)]
-pub struct SharedImages<A: TImageKeysAPI>(Rc<RefCell<ImageCache<A>>>);
+pub struct SharedImages<A: TImageKeysAPI>(Rc<SharedCell<ImageCache<A>>>, QueuedImages);
 
 impl<A> From<ImageCache<A>> for SharedImages<A>
 where
     A: TImageKeysAPI
 {
     fn from(value: ImageCache<A>) -> Self {
-        SharedImages(Rc::new(RefCell::new(value)))
+        SharedImages(Rc::new(SharedCell::new(value)), QueuedImages::default())
     }
 }
 
@@ -141,7 +377,7 @@ where
     A: TImageKeysAPI
 {
     fn clone(&self) -> Self {
-        SharedImages(Rc::clone(&self.0))
+        SharedImages(Rc::clone(&self.0), Rc::clone(&self.1))
     }
 }
 
@@ -149,13 +385,159 @@ impl<A> Deref for SharedImages<A>
 where
     A: TImageKeysAPI
 {
-    type Target = RefCell<ImageCache<A>>;
+    type Target = SharedCell<ImageCache<A>>;
 
     fn deref(&self) -> &Self::Target {
         &self.0
     }
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImageFailure {
+    pub category: ErrorCategory,
+    pub message: String
+}
+
+// Lifecycle of an image, for rendering placeholders without inferring the
+// state from lookups. Images added with `add_deferred_image` stay pending,
+// since they're only ever decoded at the sizes requested from them.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ResourceStatus {
+    Unknown,
+    Pending,
+    Decoding,
+    Ready,
+    Failed(ImageFailure),
+    Evicted
+}
+
+impl ResourceStatus {
+    pub fn is_ready(&self) -> bool {
+        *self == ResourceStatus::Ready
+    }
+}
+
+// Where images are decoded. `External` only reads dimensions from the image
+// headers and leaves decoding to the consumer of the resource updates, which
+// gets the encoded data with every image key, e.g. browsers via `createImageBitmap`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ImageDecoding {
+    InProcess,
+    External
+}
+
+impl Default for ImageDecoding {
+    fn default() -> Self {
+        ImageDecoding::InProcess
+    }
+}
+
+// Options for images added through `add_image_with_options`. Masks suit
+// monochrome icons, and take a quarter of the memory of color images.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct ImageOptions {
+    pub orientation: ImageOrientation,
+    pub mask: bool
+}
+
+impl ImageOptions {
+    pub fn mask() -> Self {
+        ImageOptions {
+            mask: true,
+            ..ImageOptions::default()
+        }
+    }
+}
+
+// Layout of the cells of a sprite sheet, see `add_sprite_grid`. Cells are
+// laid out in rows, `margin` pixels away from the sheet's edges and `spacing`
+// pixels apart. Without a count, every cell which fits is sliced.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct SpriteGrid {
+    pub cell_size: (u32, u32),
+    pub margin: u32,
+    pub spacing: u32,
+    pub count: Option<usize>
+}
+
+impl SpriteGrid {
+    pub fn new(cell_size: (u32, u32)) -> Self {
+        SpriteGrid {
+            cell_size,
+            ..SpriteGrid::default()
+        }
+    }
+
+    // Number of columns and rows of cells fitting within `size`. Margins and
+    // spacings too large for any sheet fit no more cells rather than overflow.
+    pub fn cells_within(&self, size: (u32, u32)) -> (u32, u32) {
+        let fit = |length: u32, cell: u32| {
+            let inner = length.saturating_sub(self.margin.saturating_mul(2));
+            if cell == 0 || inner < cell {
+                0
+            } else {
+                (inner - cell) / cell.saturating_add(self.spacing) + 1
+            }
+        };
+        (fit(size.0, self.cell_size.0), fit(size.1, self.cell_size.1))
+    }
+}
+
+// A pixel of the color, drawn through the coverage of a tinted variant.
+fn tint_fill(color: TintColor) -> Result<DecodedImage> {
+    let (format, fill) = pixels::tint_mask(&[255], (color.r, color.g, color.b, color.a));
+    DecodedImage::from_raw_parts(format, (1, 1), Arc::new(fill))
+}
+
+// Straight alpha color filling template icons, see `get_tinted_image`.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct TintColor {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8
+}
+
+impl TintColor {
+    pub fn new(r: u8, g: u8, b: u8, a: u8) -> Self {
+        TintColor { r, g, b, a }
+    }
+}
+
+impl<A> SharedImages<A>
+where
+    A: TImageKeysAPI
+{
+    pub fn ack_resource_updates(&self) -> usize {
+        self.borrow_mut().ack_resource_updates()
+    }
+
+    // Images queued while the cache was borrowed are decoding until the queue is applied.
+    pub fn status<P>(&self, src: P) -> ResourceStatus
+    where
+        P: AsRef<str>
+    {
+        self.flush();
+        let id = self.image_id(src);
+        if self.1.borrow().contains(&id) {
+            return ResourceStatus::Decoding;
+        }
+        self.borrow().status_for_id(id)
+    }
+
+    // Skips collision checks while the cache is being mutated, e.g. when adding
+    // images from within a mutation queued by the cache itself.
+    fn image_id<P>(&self, src: P) -> ImageId
+    where
+        P: AsRef<str>
+    {
+        match self.try_borrow() {
+            Some(cache) => cache.image_id(src),
+            None => ImageId::new(src)
+        }
+    }
+}
+
 impl<A> TImageCache for SharedImages<A>
 where
     A: TImageKeysAPI + 'static
@@ -170,8 +552,9 @@ where
         P: AsRef<str>,
         T: Into<Rc<Vec<u8>>>
     {
-        let id = ImageId::new(src);
-        self.borrow_mut().add_raw(id, bytes).ok()
+        let id = self.image_id(src);
+        let encoded = EncodedImage::from_bytes(bytes).ok()?;
+        self.add_image_with_id(id, &encoded)
     }
 
     fn add_image<P, E>(&mut self, src: P, encoded: &E) -> Option<()>
@@ -179,15 +562,29 @@ where
         P: AsRef<str>,
         E: TEncodedImage
     {
-        let id = ImageId::new(src);
-        self.borrow_mut().add_image(id, encoded).ok()
+        let id = self.image_id(src);
+        self.add_image_with_id(id, encoded)
     }
 
+    // Images added while the cache is borrowed are queued, and `Some` only
+    // means that they were accepted. Failures to add them once the borrow is
+    // released are kept as deferred errors, see `take_deferred_errors`.
     fn add_image_with_id<E>(&mut self, id: Self::ImageId, encoded: &E) -> Option<()>
     where
         E: TEncodedImage
     {
-        self.borrow_mut().add_image(id, encoded).ok()
+        if let Some(mut cache) = self.try_borrow_mut() {
+            return cache.add_image(id, encoded).ok();
+        }
+        let encoded = EncodedImage::from_encoded(encoded)?;
+        let queued = Rc::clone(&self.1);
+        queued.borrow_mut().insert(id);
+        self.mutate(move |cache| {
+            queued.borrow_mut().remove(&id);
+            let added = cache.add_image(id, &encoded);
+            cache.record_deferred(added);
+        });
+        Some(())
     }
 
     fn get_image<P>(&self, src: P) -> Option<Self::Image>
@@ -212,7 +609,29 @@ where
 #[derive(Debug, PartialEq)]
 pub struct ImageCache<A: TImageKeysAPI> {
     api: A,
-    images: FnvHashMap<ImageId, Rc<Image<A::ImageKey>>>
+    images: FnvHashMap<ImageId, Rc<Image<A::ImageKey>>>,
+    image_sets: FnvHashMap<ImageId, Vec<(Scale, ImageId)>>,
+    image_arrays: FnvHashMap<ImageId, Vec<ImageId>>,
+    masks: FnvHashMap<ImageId, ImageId>,
+    mask_channels: FnvHashMap<ImageId, MaskChannel>,
+    tints: FnvHashMap<ImageId, Vec<ImageId>>,
+    tint_fills: FnvHashMap<ImageId, (TintColor, ImageId)>,
+    deferred: FnvHashMap<ImageId, EncodedImage>,
+    external_sources: FnvHashMap<ImageId, EncodedImage>,
+    progressive: FnvHashMap<ImageId, EncodedImage>,
+    decoding: ImageDecoding,
+    staging: Option<StagingPool>,
+    failures: FnvHashMap<ImageId, ImageFailure>,
+    evicted: FnvHashSet<ImageId>,
+    shared_memory: FnvHashMap<ImageId, Rc<SharedMemory>>,
+    priorities: FnvHashMap<ImageId, ResourcePriority>,
+    expiry: ResourceExpiry<ImageId>,
+    device_pixel_ratio: Scale,
+    row_alignment: usize,
+    errors: ErrorStats,
+    ids: IdStats<ImageId>,
+    #[cfg(feature = "key-balance-watchdog")]
+    image_keys: KeyLedger<ImageId, A::ImageKey>
 }
 
 impl<A> ImageCache<A>
@@ -222,7 +641,29 @@ where
     pub fn new(api: A) -> Result<Self> {
         Ok(ImageCache {
             api,
-            images: FnvHashMap::default()
+            images: FnvHashMap::default(),
+            image_sets: FnvHashMap::default(),
+            image_arrays: FnvHashMap::default(),
+            masks: FnvHashMap::default(),
+            mask_channels: FnvHashMap::default(),
+            tints: FnvHashMap::default(),
+            tint_fills: FnvHashMap::default(),
+            deferred: FnvHashMap::default(),
+            external_sources: FnvHashMap::default(),
+            progressive: FnvHashMap::default(),
+            decoding: ImageDecoding::default(),
+            staging: None,
+            failures: FnvHashMap::default(),
+            evicted: FnvHashSet::default(),
+            shared_memory: FnvHashMap::default(),
+            priorities: FnvHashMap::default(),
+            expiry: ResourceExpiry::new(),
+            device_pixel_ratio: Scale::ONE,
+            row_alignment: 1,
+            errors: ErrorStats::new(),
+            ids: IdStats::new(),
+            #[cfg(feature = "key-balance-watchdog")]
+            image_keys: KeyLedger::new()
         })
     }
 
@@ -230,11 +671,387 @@ where
     where
         T: Into<Rc<Vec<u8>>>
     {
-        let encoded = EncodedImage::from_bytes(bytes)?;
+        let encoded = self.errors.track(EncodedImage::from_bytes(bytes), ImageError::category);
+        let encoded = self.track_failure(image_id, encoded)?;
         self.add_image(image_id, &encoded)
     }
 
     pub fn add_image<E>(&mut self, image_id: ImageId, encoded: &E) -> Result<()>
+    where
+        E: TEncodedImage
+    {
+        if self.image_sets.contains_key(&image_id) {
+            Err(ImageError::ImageAlreadyAdded)?;
+        }
+        self.add_scaled_image(image_id, encoded, 1.0)
+    }
+
+    // Decodes the images on worker threads. Results are always in submission
+    // order, it's the resource updates that follow `ordering`. Decoding only
+    // headers is cheap enough that external decoding doesn't use threads.
+    pub fn add_raw_batch<I, T>(&mut self, images: I, ordering: DecodeOrdering) -> Vec<Result<()>>
+    where
+        I: IntoIterator<Item = (ImageId, T)>,
+        T: Into<Rc<Vec<u8>>>
+    {
+        let mut results = Vec::new();
+        let mut pending = Vec::new();
+        let mut jobs = Vec::new();
+        let mut submitted = FnvHashSet::default();
+
+        // Ids repeated within the batch are rejected upfront, so that it's
+        // always the later one failing, whichever finishes decoding first.
+        for (image_id, bytes) in images {
+            let encoded = self.errors.track(EncodedImage::from_bytes(bytes), ImageError::category);
+            let result = match self.track_failure(image_id, encoded) {
+                Ok(_) if self.images.contains_key(&image_id) || self.image_sets.contains_key(&image_id) || submitted.contains(&image_id) => {
+                    Err(ImageError::ImageAlreadyAdded)
+                }
+                Ok(encoded) => match (self.decoding, encoded.format(), encoded.bytes().map(|bytes| bytes.to_vec())) {
+                    (ImageDecoding::InProcess, Some(format), Some(bytes)) => {
+                        submitted.insert(image_id);
+                        jobs.push(DecodeJob::new(format, bytes));
+                        pending.push((results.len(), image_id, encoded));
+                        Ok(())
+                    }
+                    _ => self.add_image(image_id, &encoded)
+                },
+                Err(err) => Err(err)
+            };
+            results.push(result);
+        }
+
+        let row_alignment = self.row_alignment;
+        batch::decode_all(jobs, row_alignment, ordering, |position, decoded| {
+            let (slot, image_id, ref encoded) = pending[position];
+            let decoded = self.errors.track(decoded, ImageError::category);
+            results[slot] = self.track_failure(image_id, decoded).and_then(|decoded| self.insert_decoded(image_id, encoded, decoded, 1.0));
+        });
+
+        results
+    }
+
+    pub fn add_image_set<I, T>(&mut self, image_id: ImageId, variants: I) -> Result<()>
+    where
+        I: IntoIterator<Item = (Scale, T)>,
+        T: Into<Rc<Vec<u8>>>
+    {
+        if self.images.contains_key(&image_id) || self.image_sets.contains_key(&image_id) {
+            Err(ImageError::ImageAlreadyAdded)?;
+        }
+
+        let mut decoded_variants = Vec::new();
+        for (scale, bytes) in variants {
+            if !scale.0.is_finite() || scale.0 <= 0.0 || decoded_variants.iter().any(|&(s, _, _)| s == scale) {
+                Err(ImageError::InvalidImageSet)?;
+            }
+            let encoded = self.errors.track(EncodedImage::from_bytes(bytes), ImageError::category);
+            let encoded = self.track_failure(image_id, encoded)?;
+            let decoded = self.decode(&encoded);
+            let decoded = self.track_failure(image_id, decoded)?;
+            decoded_variants.push((scale, encoded, decoded));
+        }
+        if decoded_variants.is_empty() {
+            Err(ImageError::InvalidImageSet)?;
+        }
+
+        let mut set = Vec::with_capacity(decoded_variants.len());
+        for (scale, encoded, decoded) in decoded_variants {
+            let variant_id = image_id.with_scale(scale);
+            self.insert_decoded(variant_id, &encoded, decoded, scale.0)?;
+            set.push((scale, variant_id));
+        }
+        set.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        self.image_sets.insert(image_id, set);
+        self.mark_added(image_id);
+
+        Ok(())
+    }
+
+    // Groups equally sized images of the same format into one texture array,
+    // in order, so that they take a single external key. Layers are looked up
+    // by their own ids. Nothing is added if any of them fails to decode or
+    // doesn't match the first one.
+    pub fn add_image_array<I, T>(&mut self, array_id: ImageId, layers: I) -> Result<()>
+    where
+        I: IntoIterator<Item = (ImageId, T)>,
+        T: Into<Rc<Vec<u8>>>,
+        A: TImageKeysAPIExt
+    {
+        if self.image_arrays.contains_key(&array_id) {
+            Err(ImageError::ImageAlreadyAdded)?;
+        }
+
+        let mut decoded_layers: Vec<(ImageId, EncodedImage, DecodedImage)> = Vec::new();
+        for (layer_id, bytes) in layers {
+            if self.images.contains_key(&layer_id) || decoded_layers.iter().any(|&(id, _, _)| id == layer_id) {
+                Err(ImageError::ImageAlreadyAdded)?;
+            }
+            let encoded = self.errors.track(EncodedImage::from_bytes(bytes), ImageError::category);
+            let encoded = self.track_failure(array_id, encoded)?;
+            let decoded = self.decode(&encoded);
+            let decoded = self.track_failure(array_id, decoded)?;
+            if let Some(&(_, _, ref first)) = decoded_layers.first() {
+                if first.size != decoded.size || first.format != decoded.format {
+                    Err(ImageError::InvalidImageArray)?;
+                }
+            }
+            decoded_layers.push((layer_id, encoded, decoded));
+        }
+        let size = match decoded_layers.first() {
+            Some(&(_, _, ref decoded)) => decoded.size,
+            None => Err(ImageError::InvalidImageArray)?
+        };
+
+        let array_key = {
+            let data = decoded_layers
+                .iter()
+                .map(|&(_, ref encoded, ref decoded)| (encoded.info(), decoded.info()))
+                .collect();
+            self.api.add_image_array(size, data)
+        };
+        let external_key = match array_key {
+            Some(external_key) => external_key,
+            None => {
+                for (layer_id, encoded, decoded) in decoded_layers {
+                    self.insert_decoded(layer_id, &encoded, decoded, 1.0)?;
+                }
+                self.mark_added(array_id);
+                return Ok(());
+            }
+        };
+        #[cfg(feature = "key-balance-watchdog")]
+        self.image_keys.allocated(array_id, external_key);
+
+        let mut layer_ids = Vec::with_capacity(decoded_layers.len());
+        for (layer, (layer_id, encoded, decoded)) in decoded_layers.into_iter().enumerate() {
+            self.keep_external_source(layer_id, &encoded);
+            self.images.insert(
+                layer_id,
+                Rc::new(Image {
+                    format: decoded.format,
+                    size: decoded.size,
+                    row_stride: decoded.row_stride,
+                    pixels: decoded.pixels,
+                    orientation: ImageOrientation::Identity,
+                    scale: 1.0,
+                    layer: Some(layer as u32),
+                    external_key
+                })
+            );
+            layer_ids.push(layer_id);
+        }
+        self.image_arrays.insert(array_id, layer_ids);
+        self.mark_added(array_id);
+
+        Ok(())
+    }
+
+    pub fn image_array_layers(&self, array_id: ImageId) -> Option<&[ImageId]> {
+        self.image_arrays.get(&array_id).map(Vec::as_slice)
+    }
+
+    // Slices an already added sheet into separate images, one per cell, with
+    // ids made by `ImageId::with_cell` and returned in reading order. Cells
+    // are cropped from the sheet as displayed, i.e. after its orientation.
+    // Nothing is added if the grid holds fewer cells than asked for.
+    pub fn add_sprite_grid(&mut self, sheet_id: ImageId, grid: SpriteGrid) -> Result<Vec<ImageId>> {
+        let image = self.errors.track(
+            self.images.get(&sheet_id).map(Rc::clone).ok_or(ImageError::ImageNotFound),
+            ImageError::category
+        )?;
+
+        // Images without pixels of their own are decoded again from their
+        // encoded data, as in `get_image_at_size`.
+        let sheet = if !image.pixels.is_empty() || !self.external_sources.contains_key(&sheet_id) {
+            image.bake()
+        } else {
+            let decoded = DecodedImage::from_encoded_image(&self.external_sources[&sheet_id]);
+            self.errors.track(decoded, ImageError::category)?.oriented(image.orientation)
+        };
+
+        let (columns, rows) = grid.cells_within(sheet.size);
+        let available = columns as usize * rows as usize;
+        let count = grid.count.unwrap_or(available);
+        if count == 0 || count > available {
+            Err(ImageError::InvalidSpriteGrid)?;
+        }
+        let cell_ids: Vec<ImageId> = (0..count).map(|index| sheet_id.with_cell(index)).collect();
+        if cell_ids.iter().any(|cell_id| self.images.contains_key(cell_id)) {
+            Err(ImageError::ImageAlreadyAdded)?;
+        }
+
+        for (index, &cell_id) in cell_ids.iter().enumerate() {
+            let (column, row) = (index as u32 % columns, index as u32 / columns);
+            let origin = (
+                grid.margin + column * grid.cell_size.0.saturating_add(grid.spacing),
+                grid.margin + row * grid.cell_size.1.saturating_add(grid.spacing)
+            );
+            let cell = sheet.crop(origin, grid.cell_size);
+            let encoded = EncodedImage::Bytes {
+                format: ImageEncodingFormat::PNG,
+                bytes: Rc::new(cell.encode_png()?),
+                size_info: Some(cell.size)
+            };
+            let scale = image.scale;
+            self.insert_decoded(cell_id, &encoded, cell.with_row_alignment(self.row_alignment), scale)?;
+        }
+
+        Ok(cell_ids)
+    }
+
+    // Pairs a color image with an equally sized mask, whose alpha or luminance
+    // is taken as coverage like in `ImageOptions::mask`. The mask is sent as a
+    // separate image linked to the color one, or composited into its alpha
+    // when the renderer can't draw through masks. Nothing is added if either
+    // fails to decode or the sizes differ.
+    pub fn add_masked_image<E, M>(&mut self, image_id: ImageId, color: &E, mask: &M) -> Result<()>
+    where
+        E: TEncodedImage,
+        M: TEncodedImage,
+        A: TImageKeysAPIExt
+    {
+        let mask_id = image_id.with_mask();
+        if self.images.contains_key(&image_id) || self.images.contains_key(&mask_id) {
+            Err(ImageError::ImageAlreadyAdded)?;
+        }
+
+        let color_decoded = self.decode(color);
+        let color_decoded = self.track_failure(image_id, color_decoded)?;
+        let mask_decoded = self.decode_mask(mask);
+        let (mask_decoded, channel) = self.track_failure(image_id, mask_decoded)?;
+        if color_decoded.size != mask_decoded.size {
+            return self.track_failure(image_id, Err(ImageError::InvalidMaskedImage));
+        }
+
+        if !self.api.supports_image_masks() {
+            let composite = self.composite_mask(color, mask);
+            let (encoded, composite) = self.track_failure(image_id, composite)?;
+            return self.insert_decoded(image_id, &encoded, composite, 1.0);
+        }
+
+        self.insert_decoded(image_id, color, color_decoded, 1.0)?;
+        let mask_decoded = mask_decoded.with_row_alignment(self.row_alignment);
+        let (mask_decoded, staged) = match self.staging {
+            Some(ref mut pool) => (mask_decoded.staged(pool), true),
+            None => (mask_decoded, false)
+        };
+        let mask_key = self.api
+            .add_image_mask(mask.info(), mask_decoded.info(), ImageOrientation::Identity, channel);
+        self.mask_channels.insert(mask_id, channel);
+        #[cfg(feature = "key-balance-watchdog")]
+        self.image_keys.allocated(mask_id, mask_key);
+        self.images.insert(
+            mask_id,
+            Rc::new(Image {
+                format: mask_decoded.format,
+                size: mask_decoded.size,
+                row_stride: mask_decoded.row_stride,
+                pixels: if staged { Arc::default() } else { mask_decoded.pixels },
+                orientation: ImageOrientation::Identity,
+                scale: 1.0,
+                layer: None,
+                external_key: mask_key
+            })
+        );
+        self.keep_external_source(mask_id, mask);
+
+        let color_key = self.images[&image_id].external_key;
+        self.api.link_image_mask(color_key, mask_key);
+        self.masks.insert(image_id, mask_id);
+        Ok(())
+    }
+
+    // Always decodes in process, since the composite is sent re-encoded.
+    fn composite_mask<E, M>(&self, color: &E, mask: &M) -> Result<(EncodedImage, DecodedImage)>
+    where
+        E: TEncodedImage,
+        M: TEncodedImage
+    {
+        let color = self.errors
+            .track(DecodedImage::from_encoded_image(color), ImageError::category)?;
+        let (mask, _) = self.errors
+            .track(DecodedImage::from_encoded_image(mask), ImageError::category)?
+            .into_mask();
+        let (format, masked) = pixels::apply_mask(color.format, &color.packed_pixels(), &mask.pixels);
+        let composite = DecodedImage::from_raw_parts(format, color.size, Arc::new(masked))?;
+        let encoded = EncodedImage::from_bytes(composite.encode_png()?)?;
+        Ok((encoded, composite.with_row_alignment(self.row_alignment)))
+    }
+
+    pub fn get_image_mask<P>(&self, src: P) -> Option<Rc<Image<A::ImageKey>>>
+    where
+        P: AsRef<str>
+    {
+        self.get_image_mask_for_id(self.image_id(src))
+    }
+
+    pub fn get_image_mask_for_id(&self, image_id: ImageId) -> Option<Rc<Image<A::ImageKey>>> {
+        self.masks
+            .get(&image_id)
+            .and_then(|mask_id| self.images.get(mask_id))
+            .map(Rc::clone)
+    }
+
+    fn add_scaled_image<E>(&mut self, image_id: ImageId, encoded: &E, scale: f32) -> Result<()>
+    where
+        E: TEncodedImage
+    {
+        if self.images.contains_key(&image_id) {
+            Err(ImageError::ImageAlreadyAdded)?;
+        }
+        let decoded = self.decode(encoded);
+        let decoded = self.track_failure(image_id, decoded)?;
+        self.insert_decoded(image_id, encoded, decoded, scale)
+    }
+
+    fn decode<E>(&self, encoded: &E) -> Result<DecodedImage>
+    where
+        E: TEncodedImage
+    {
+        let decoded = match self.decoding {
+            ImageDecoding::InProcess => DecodedImage::from_encoded_image_with_row_alignment(encoded, self.row_alignment),
+            ImageDecoding::External => DecodedImage::from_encoded_header(encoded)
+        };
+        self.errors.track(decoded, ImageError::category)
+    }
+
+    // Masks are decoded in process whatever the decoding, since their A8
+    // coverage is sent rather than the encoded bytes.
+    fn decode_mask<E>(&self, encoded: &E) -> Result<(DecodedImage, MaskChannel)>
+    where
+        E: TEncodedImage
+    {
+        let decoded = self.errors
+            .track(DecodedImage::from_encoded_image(encoded), ImageError::category)?;
+        Ok(decoded.into_mask())
+    }
+
+    fn track_failure<T>(&mut self, image_id: ImageId, result: Result<T>) -> Result<T> {
+        if let Err(ref err) = result {
+            record_failure(&mut self.failures, image_id, err);
+        }
+        result
+    }
+
+    fn mark_added(&mut self, image_id: ImageId) {
+        self.failures.remove(&image_id);
+        self.evicted.remove(&image_id);
+    }
+
+    // Images without pixels keep their encoded data, for resending it at other sizes.
+    fn keep_external_source<E>(&mut self, image_id: ImageId, encoded: &E)
+    where
+        E: TEncodedImage
+    {
+        if self.decoding == ImageDecoding::External || self.staging.is_some() {
+            if let Some(encoded) = EncodedImage::from_encoded(encoded) {
+                self.external_sources.insert(image_id, encoded);
+            }
+        }
+    }
+
+    fn insert_decoded<E>(&mut self, image_id: ImageId, encoded: &E, decoded: DecodedImage, scale: f32) -> Result<()>
     where
         E: TEncodedImage
     {
@@ -243,31 +1060,1027 @@ where
                 Err(ImageError::ImageAlreadyAdded)?;
             }
             Entry::Vacant(e) => {
-                let decoded = DecodedImage::from_encoded_image(encoded)?;
+                let (decoded, staged) = match self.staging {
+                    Some(ref mut pool) => (decoded.staged(pool), true),
+                    None => (decoded, false)
+                };
                 let external_key = self.api.add_image(encoded.info(), decoded.info());
-                e.insert(Image::new(
-                    decoded.format,
-                    decoded.size,
-                    decoded.pixels,
+                #[cfg(feature = "key-balance-watchdog")]
+                self.image_keys.allocated(image_id, external_key);
+                e.insert(Rc::new(Image {
+                    format: decoded.format,
+                    size: decoded.size,
+                    row_stride: decoded.row_stride,
+                    pixels: if staged { Arc::default() } else { decoded.pixels },
+                    orientation: ImageOrientation::Identity,
+                    scale,
+                    layer: None,
                     external_key
-                ));
+                }));
             }
         }
+        self.keep_external_source(image_id, encoded);
+        self.mark_added(image_id);
 
         Ok(())
     }
 
-    pub fn get_image<P>(&self, src: P) -> Option<Rc<Image<A::ImageKey>>>
-    where
-        P: AsRef<str>
-    {
-        self.images.get(&ImageId::new(src)).map(Rc::clone)
+    pub fn decoding(&self) -> ImageDecoding {
+        self.decoding
     }
 
-    pub fn measure_image<P>(&self, src: P) -> Option<ImageDimensionsInfo<A::ImageKey>>
+    // Applies to images added afterwards.
+    pub fn set_decoding(&mut self, decoding: ImageDecoding) {
+        self.decoding = decoding;
+    }
+
+    // Stages pixels in reusable buffers for APIs which copy them while the
+    // updates are consumed. Images added afterwards keep no pixels of their
+    // own and only track their dimensions. Has no effect for other APIs.
+    pub fn set_staging_buffers(&mut self, enabled: bool)
     where
-        P: AsRef<str>
+        A: TImageKeysAPIExt
     {
-        Some(self.images.get(&ImageId::new(src))?.to_dimensions_info())
+        self.staging = if enabled && self.api.copies_pixels_synchronously() {
+            Some(self.staging.take().unwrap_or_default())
+        } else {
+            None
+        };
+    }
+
+    pub fn staging_buffers(&self) -> bool {
+        self.staging.is_some()
+    }
+
+    pub fn staging_stats(&self) -> Option<StagingStats> {
+        self.staging.as_ref().map(StagingPool::stats)
+    }
+
+    // Called by the consumer once it applied the updates taken so far,
+    // making their staged buffers reusable. Returns how many were reclaimed.
+    pub fn ack_resource_updates(&mut self) -> usize {
+        self.staging
+            .as_mut()
+            .map_or(0, StagingPool::reclaim)
+    }
+
+    pub fn set_device_pixel_ratio(&mut self, device_pixel_ratio: Scale) {
+        self.device_pixel_ratio = device_pixel_ratio;
+    }
+
+    pub fn device_pixel_ratio(&self) -> Scale {
+        self.device_pixel_ratio
+    }
+
+    pub fn set_display_profile(&mut self, profile: DisplayProfile) {
+        self.set_device_pixel_ratio(profile.device_pixel_ratio);
+    }
+
+    // Pads decoded rows so that renderers can upload them without repacking.
+    // Only affects images added afterwards.
+    pub fn set_row_alignment(&mut self, alignment: usize) {
+        self.row_alignment = alignment.max(1);
+    }
+
+    pub fn row_alignment(&self) -> usize {
+        self.row_alignment
+    }
+
+    pub fn error_counters(&self) -> ErrorCounters {
+        self.errors.counters()
+    }
+
+    // Returns the counts since the last reset, e.g. to report them per frame.
+    pub fn reset_error_counters(&self) -> ErrorCounters {
+        self.errors.reset()
+    }
+
+    // Errors of mutations queued through `SharedImages` while it was borrowed,
+    // oldest first, since the last call.
+    pub fn take_deferred_errors(&self) -> Vec<DeferredError> {
+        self.errors.take_deferred()
+    }
+
+    // Errors are tracked by the mutations themselves, so only recorded here.
+    fn record_deferred<T>(&self, result: Result<T>) {
+        if let Err(err) = result {
+            self.errors.record_deferred(err.code(), err.message());
+        }
+    }
+
+    // Hashes a source into an id, checking it for collisions if enabled.
+    pub fn image_id<P>(&self, src: P) -> ImageId
+    where
+        P: AsRef<str>
+    {
+        self.ids.check(ImageId::new(&src), src)
+    }
+
+    pub fn image_id_with_origin<O, P>(&self, origin: O, src: P) -> ImageId
+    where
+        O: AsRef<str>,
+        P: AsRef<str>
+    {
+        let id = ImageId::with_origin(&origin, &src);
+        self.ids
+            .check(id, format!("{}\0{}", origin.as_ref(), src.as_ref()))
+    }
+
+    pub fn id_collision_checks(&self) -> bool {
+        self.ids.is_enabled()
+    }
+
+    pub fn set_id_collision_checks(&mut self, enabled: bool) {
+        self.ids.set_enabled(enabled);
+    }
+
+    pub fn id_counters(&self) -> IdCounters {
+        self.ids.counters()
+    }
+
+    pub fn reset_id_counters(&self) -> IdCounters {
+        self.ids.reset()
+    }
+
+    pub fn add_image_with_orientation<E>(&mut self, image_id: ImageId, encoded: &E, orientation: ImageOrientation) -> Result<()>
+    where
+        E: TEncodedImage,
+        A: TImageKeysAPIExt
+    {
+        let options = ImageOptions {
+            orientation,
+            ..ImageOptions::default()
+        };
+        self.insert_oriented(image_id, encoded, options, None)
+    }
+
+    pub fn add_image_with_options<E>(&mut self, image_id: ImageId, encoded: &E, options: ImageOptions) -> Result<()>
+    where
+        E: TEncodedImage,
+        A: TImageKeysAPIExt
+    {
+        self.insert_oriented(image_id, encoded, options, None)
+    }
+
+    // Images backed by shared memory are announced with its descriptor, so that
+    // the renderer process can map the encoded bytes rather than get a copy.
+    // The memory is kept alive until the image is removed.
+    pub fn add_shared_image(&mut self, image_id: ImageId, encoded: &EncodedImage, orientation: ImageOrientation) -> Result<()>
+    where
+        A: TImageKeysAPIExt
+    {
+        let options = ImageOptions {
+            orientation,
+            ..ImageOptions::default()
+        };
+        self.add_shared_image_with_options(image_id, encoded, options)
+    }
+
+    // Masks are announced with the descriptor too, along with the channel the
+    // renderer takes their coverage from.
+    pub fn add_shared_image_with_options(&mut self, image_id: ImageId, encoded: &EncodedImage, options: ImageOptions) -> Result<()>
+    where
+        A: TImageKeysAPIExt
+    {
+        let memory = encoded.shared_memory().map(Rc::clone);
+        self.insert_oriented(image_id, encoded, options, memory)
+    }
+
+    fn insert_oriented<E>(&mut self, image_id: ImageId, encoded: &E, options: ImageOptions, memory: Option<Rc<SharedMemory>>) -> Result<()>
+    where
+        E: TEncodedImage,
+        A: TImageKeysAPIExt
+    {
+        let orientation = options.orientation;
+        match self.images.entry(image_id) {
+            Entry::Occupied(_) => {
+                Err(ImageError::ImageAlreadyAdded)?;
+            }
+            Entry::Vacant(e) => {
+                // Masks are decoded in process whatever the decoding, see `decode_mask`.
+                let decoded = match (self.decoding, options.mask) {
+                    (ImageDecoding::InProcess, _) | (_, true) => DecodedImage::from_encoded_image(encoded),
+                    (ImageDecoding::External, false) => DecodedImage::from_encoded_header(encoded)
+                };
+                let decoded = match self.errors.track(decoded, ImageError::category) {
+                    Ok(decoded) => decoded,
+                    Err(err) => {
+                        record_failure(&mut self.failures, image_id, &err);
+                        return Err(err);
+                    }
+                };
+                let (decoded, mask_channel) = if options.mask {
+                    let (decoded, channel) = decoded.into_mask();
+                    (decoded, Some(channel))
+                } else {
+                    (decoded, None)
+                };
+                let decoded = decoded.with_row_alignment(self.row_alignment);
+                let (decoded, staged) = match self.staging {
+                    Some(ref mut pool) => (decoded.staged(pool), true),
+                    None => (decoded, false)
+                };
+                let external_key = match (memory, mask_channel) {
+                    (Some(memory), Some(channel)) => {
+                        self.mask_channels.insert(image_id, channel);
+                        let external_key = self.api
+                            .add_shared_image_mask(memory.descriptor(), encoded.info(), decoded.info(), orientation, channel);
+                        self.shared_memory.insert(image_id, memory);
+                        external_key
+                    }
+                    (None, Some(channel)) => {
+                        self.mask_channels.insert(image_id, channel);
+                        self.api
+                            .add_image_mask(encoded.info(), decoded.info(), orientation, channel)
+                    }
+                    (Some(memory), None) => {
+                        let external_key = self.api
+                            .add_shared_image(memory.descriptor(), encoded.info(), decoded.info(), orientation);
+                        self.shared_memory.insert(image_id, memory);
+                        external_key
+                    }
+                    (None, None) => self.api
+                        .add_image_with_orientation(encoded.info(), decoded.info(), orientation)
+                };
+                #[cfg(feature = "key-balance-watchdog")]
+                self.image_keys.allocated(image_id, external_key);
+                e.insert(Rc::new(Image {
+                    format: decoded.format,
+                    size: decoded.size,
+                    row_stride: decoded.row_stride,
+                    pixels: if staged { Arc::default() } else { decoded.pixels },
+                    orientation,
+                    scale: 1.0,
+                    layer: None,
+                    external_key
+                }));
+            }
+        }
+        self.keep_external_source(image_id, encoded);
+        self.mark_added(image_id);
+
+        Ok(())
+    }
+
+    pub fn get_image<P>(&self, src: P) -> Option<Rc<Image<A::ImageKey>>>
+    where
+        P: AsRef<str>
+    {
+        self.get_image_for_id(self.image_id(src))
+    }
+
+    pub fn get_image_for_id(&self, image_id: ImageId) -> Option<Rc<Image<A::ImageKey>>> {
+        let image_id = self.resolve_variant(image_id);
+        self.images.get(&image_id).map(Rc::clone)
+    }
+
+    // Keeps the encoded bytes around without decoding them, for images which
+    // are only ever displayed through `get_image_at_size`.
+    pub fn add_deferred_image<E>(&mut self, image_id: ImageId, encoded: &E) -> Result<()>
+    where
+        E: TEncodedImage
+    {
+        if self.images.contains_key(&image_id) || self.image_sets.contains_key(&image_id) {
+            Err(ImageError::ImageAlreadyAdded)?;
+        }
+        match self.deferred.entry(image_id) {
+            Entry::Occupied(_) => {
+                Err(ImageError::ImageAlreadyAdded)?;
+            }
+            Entry::Vacant(e) => {
+                e.insert(EncodedImage::from_encoded(encoded).ok_or(ImageError::ImageNotFound)?);
+            }
+        }
+        self.mark_added(image_id);
+
+        Ok(())
+    }
+
+    // Progressive JPEGs and interlaced PNGs are added with their coarse first
+    // pass, at the scale laying it out at the full size, until
+    // `complete_progressive_image` decodes them fully. Other images, and
+    // externally decoded ones, are added as usual.
+    pub fn add_progressive_image<E>(&mut self, image_id: ImageId, encoded: &E) -> Result<()>
+    where
+        E: TEncodedImage
+    {
+        if self.images.contains_key(&image_id) || self.image_sets.contains_key(&image_id) {
+            Err(ImageError::ImageAlreadyAdded)?;
+        }
+        let preview = match (self.decoding, encoded.format(), encoded.bytes()) {
+            (ImageDecoding::InProcess, Some(format), Some(bytes)) => progressive::preview_pass(format, bytes),
+            _ => None
+        };
+        let (scale, preview, source) = match (preview, EncodedImage::from_encoded(encoded)) {
+            (Some(preview), Some(source)) => (preview.scale(), preview.decoded, source),
+            _ => return self.add_image(image_id, encoded)
+        };
+
+        let preview = preview.with_row_alignment(self.row_alignment);
+        let preview_encoded = EncodedImage::Bytes {
+            format: ImageEncodingFormat::PNG,
+            bytes: Rc::new(preview.encode_png()?),
+            size_info: Some(preview.size)
+        };
+        self.insert_decoded(image_id, &preview_encoded, preview, scale)?;
+        self.progressive.insert(image_id, source);
+        Ok(())
+    }
+
+    pub fn is_progressive_preview(&self, image_id: ImageId) -> bool {
+        self.progressive.contains_key(&image_id)
+    }
+
+    // Replaces the preview with the fully decoded image, through an update of
+    // the image under its key, or by deleting it and adding it again under a
+    // new one for keys APIs which can't update images in place.
+    pub fn complete_progressive_image(&mut self, image_id: ImageId) -> Result<()>
+    where
+        A: TImageKeysAPIExt
+    {
+        let encoded = self.errors.track(
+            self.progressive.remove(&image_id).ok_or(ImageError::ImageNotFound),
+            ImageError::category
+        )?;
+        let preview = match self.images.get(&image_id) {
+            Some(image) => Rc::clone(image),
+            None => Err(ImageError::ImageNotFound)?
+        };
+        let decoded = self.decode(&encoded);
+        let decoded = self.track_failure(image_id, decoded)?;
+        let (decoded, staged) = match self.staging {
+            Some(ref mut pool) => (decoded.staged(pool), true),
+            None => (decoded, false)
+        };
+
+        let external_key = if self.api
+            .update_image(preview.external_key, encoded.info(), decoded.info(), preview.orientation)
+        {
+            preview.external_key
+        } else {
+            self.api.delete_image(preview.external_key);
+            #[cfg(feature = "key-balance-watchdog")]
+            self.image_keys.deleted(preview.external_key);
+            let external_key = self.api
+                .add_image_with_orientation(encoded.info(), decoded.info(), preview.orientation);
+            #[cfg(feature = "key-balance-watchdog")]
+            self.image_keys.allocated(image_id, external_key);
+            external_key
+        };
+        self.images.insert(
+            image_id,
+            Rc::new(Image {
+                format: decoded.format,
+                size: decoded.size,
+                row_stride: decoded.row_stride,
+                pixels: if staged { Arc::default() } else { decoded.pixels },
+                orientation: preview.orientation,
+                scale: 1.0,
+                layer: None,
+                external_key
+            })
+        );
+        self.keep_external_source(image_id, &encoded);
+
+        Ok(())
+    }
+
+    pub fn complete_progressive_images(&mut self) -> Vec<(ImageId, Result<()>)>
+    where
+        A: TImageKeysAPIExt
+    {
+        let image_ids: Vec<ImageId> = self.progressive.keys().cloned().collect();
+        image_ids
+            .into_iter()
+            .map(|image_id| (image_id, self.complete_progressive_image(image_id)))
+            .collect()
+    }
+
+    pub fn get_image_at_size<P>(&mut self, src: P, max_size: (u32, u32)) -> Result<Rc<Image<A::ImageKey>>>
+    where
+        P: AsRef<str>
+    {
+        self.get_image_at_size_for_id(self.image_id(src), max_size)
+    }
+
+    // Decodes deferred images straight to the requested size, or downsamples
+    // already decoded ones. Each size is cached as a separate image with its
+    // own external key, fitting within `max_size` with the aspect ratio kept.
+    pub fn get_image_at_size_for_id(&mut self, image_id: ImageId, max_size: (u32, u32)) -> Result<Rc<Image<A::ImageKey>>> {
+        let source_id = self.resolve_variant(image_id);
+        let sized_id = source_id.with_size(max_size);
+        if let Some(image) = self.images.get(&sized_id) {
+            return Ok(Rc::clone(image));
+        }
+
+        // Images without pixels of their own, i.e. staged or externally decoded
+        // ones, are decoded again from their encoded data.
+        let image = self.images.get(&source_id).map(Rc::clone);
+        let (decoded, orientation) = match image {
+            Some(ref image) if !image.pixels.is_empty() || !self.external_sources.contains_key(&source_id) => {
+                let max_size = image.orientation.display_size(max_size);
+                let decoded = DecodedImage {
+                    format: image.format,
+                    size: image.size,
+                    row_stride: image.row_stride,
+                    pixels: Arc::clone(&image.pixels)
+                };
+                (decoded.downsample_to_fit(max_size), image.orientation)
+            }
+            _ => {
+                let orientation = image.map_or(ImageOrientation::Identity, |image| image.orientation);
+                let max_size = orientation.display_size(max_size);
+                let encoded = self.errors.track(
+                    self.external_sources
+                        .get(&source_id)
+                        .or_else(|| self.deferred.get(&source_id))
+                        .ok_or(ImageError::ImageNotFound),
+                    ImageError::category
+                )?;
+                let decoded = match self.decoding {
+                    ImageDecoding::InProcess => DecodedImage::from_encoded_image_at_size(encoded, max_size),
+                    ImageDecoding::External => DecodedImage::from_encoded_header(encoded).map(|decoded| decoded.downsample_to_fit(max_size))
+                };
+                let decoded = self.errors.track(decoded, ImageError::category);
+                let decoded = self.track_failure(source_id, decoded)?;
+                (decoded, orientation)
+            }
+        };
+        let decoded = decoded.with_row_alignment(self.row_alignment);
+
+        // Externally decoded images have no pixels to encode, so the consumer
+        // gets the source data along with the size to decode it at.
+        let source = match self.decoding {
+            ImageDecoding::External => self.external_sources
+                .get(&source_id)
+                .or_else(|| self.deferred.get(&source_id))
+                .and_then(EncodedImage::from_encoded),
+            ImageDecoding::InProcess => None
+        };
+        let encoded = match source {
+            Some(encoded) => encoded,
+            None => EncodedImage::Bytes {
+                format: ImageEncodingFormat::PNG,
+                bytes: Rc::new(decoded.encode_png()?),
+                size_info: Some(decoded.size)
+            }
+        };
+        let external_key = self.api.add_image(encoded.info(), decoded.info());
+        #[cfg(feature = "key-balance-watchdog")]
+        self.image_keys.allocated(sized_id, external_key);
+        if let Some(&priority) = self.priorities.get(&source_id) {
+            self.priorities.insert(sized_id, priority);
+        }
+
+        let image = Rc::new(Image {
+            format: decoded.format,
+            size: decoded.size,
+            row_stride: decoded.row_stride,
+            pixels: decoded.pixels,
+            orientation,
+            scale: 1.0,
+            layer: None,
+            external_key
+        });
+        self.images.insert(sized_id, Rc::clone(&image));
+        Ok(image)
+    }
+
+    pub fn get_tinted_image<P>(&mut self, src: P, color: TintColor) -> Result<Rc<Image<A::ImageKey>>>
+    where
+        P: AsRef<str>,
+        A: TImageKeysAPIExt
+    {
+        self.get_tinted_image_for_id(self.image_id(src), color)
+    }
+
+    // Recolors a template icon, keeping only its coverage: the mask it was
+    // added with, its own pixels if it is a mask, or else its alpha, or its
+    // luminance when opaque. Each color is cached as a separate image with its
+    // own external key, and removed along with the icon. Renderers drawing
+    // through masks get the coverage once, and a pixel of each color linked to
+    // it. Others get each variant re-encoded. Always decodes in process.
+    pub fn get_tinted_image_for_id(&mut self, image_id: ImageId, color: TintColor) -> Result<Rc<Image<A::ImageKey>>>
+    where
+        A: TImageKeysAPIExt
+    {
+        let source_id = self.resolve_variant(image_id);
+        let tinted_id = source_id.with_tint(color);
+        if let Some(image) = self.images.get(&tinted_id) {
+            return Ok(Rc::clone(image));
+        }
+
+        let coverage_id = self.masks.get(&source_id).cloned().unwrap_or(source_id);
+        let image = self.images.get(&coverage_id).map(Rc::clone);
+        let decoded = match image {
+            Some(ref image) if !image.pixels.is_empty() => DecodedImage {
+                format: image.format,
+                size: image.size,
+                row_stride: image.row_stride,
+                pixels: Arc::clone(&image.pixels)
+            },
+            _ => {
+                let encoded = self.errors.track(
+                    self.external_sources
+                        .get(&coverage_id)
+                        .or_else(|| self.deferred.get(&coverage_id))
+                        .ok_or(ImageError::ImageNotFound),
+                    ImageError::category
+                )?;
+                let decoded = self.errors
+                    .track(DecodedImage::from_encoded_image(encoded), ImageError::category);
+                self.track_failure(source_id, decoded)?
+            }
+        };
+        let orientation = self.images
+            .get(&source_id)
+            .map_or(ImageOrientation::Identity, |image| image.orientation);
+
+        let (mask, _) = decoded.into_mask();
+        let (format, tinted) = pixels::tint_mask(&mask.pixels, (color.r, color.g, color.b, color.a));
+        let tinted = DecodedImage::from_raw_parts(format, mask.size, Arc::new(tinted))?.with_row_alignment(self.row_alignment);
+        let external_key = if self.api.supports_image_masks() {
+            let (coverage_id, coverage_key) = self.tint_coverage(source_id, mask, orientation)?;
+            let fill = tint_fill(color)?;
+            let encoded = EncodedImage::from_bytes(fill.encode_png()?)?;
+            let external_key = self.api
+                .add_image_with_orientation(encoded.info(), fill.info(), orientation);
+            self.api.link_image_mask(external_key, coverage_key);
+            self.tint_fills.insert(tinted_id, (color, coverage_id));
+            external_key
+        } else {
+            let encoded = EncodedImage::Bytes {
+                format: ImageEncodingFormat::PNG,
+                bytes: Rc::new(tinted.encode_png()?),
+                size_info: Some(tinted.size)
+            };
+            self.api.add_image(encoded.info(), tinted.info())
+        };
+        self.tints
+            .entry(source_id)
+            .or_insert_with(Vec::new)
+            .push(tinted_id);
+        #[cfg(feature = "key-balance-watchdog")]
+        self.image_keys.allocated(tinted_id, external_key);
+        if let Some(&priority) = self.priorities.get(&source_id) {
+            self.priorities.insert(tinted_id, priority);
+        }
+
+        let image = Rc::new(Image {
+            format: tinted.format,
+            size: tinted.size,
+            row_stride: tinted.row_stride,
+            pixels: tinted.pixels,
+            orientation,
+            scale: 1.0,
+            layer: None,
+            external_key
+        });
+        self.images.insert(tinted_id, Rc::clone(&image));
+        Ok(image)
+    }
+
+    // The mask the icon was added with, or else its coverage, sent once.
+    fn tint_coverage(&mut self, source_id: ImageId, mask: DecodedImage, orientation: ImageOrientation) -> Result<(ImageId, A::ImageKey)>
+    where
+        A: TImageKeysAPIExt
+    {
+        let mask_id = self.masks
+            .get(&source_id)
+            .cloned()
+            .unwrap_or_else(|| source_id.with_tint_coverage());
+        if let Some(image) = self.images.get(&mask_id) {
+            return Ok((mask_id, image.external_key));
+        }
+
+        let encoded = EncodedImage::from_bytes(mask.encode_png()?)?;
+        let mask = mask.with_row_alignment(self.row_alignment);
+        let channel = MaskChannel::Luminance;
+        let mask_key = self.api
+            .add_image_mask(encoded.info(), mask.info(), orientation, channel);
+        #[cfg(feature = "key-balance-watchdog")]
+        self.image_keys.allocated(mask_id, mask_key);
+        self.mask_channels.insert(mask_id, channel);
+        self.images.insert(
+            mask_id,
+            Rc::new(Image {
+                format: mask.format,
+                size: mask.size,
+                row_stride: mask.row_stride,
+                pixels: mask.pixels,
+                orientation,
+                scale: 1.0,
+                layer: None,
+                external_key: mask_key
+            })
+        );
+        self.tints
+            .entry(source_id)
+            .or_insert_with(Vec::new)
+            .push(mask_id);
+        Ok((mask_id, mask_key))
+    }
+
+    pub fn measure_image<P>(&self, src: P) -> Option<ImageDimensionsInfo<A::ImageKey>>
+    where
+        P: AsRef<str>
+    {
+        self.measure_image_for_id(self.image_id(src))
+    }
+
+    pub fn measure_image_for_id(&self, image_id: ImageId) -> Option<ImageDimensionsInfo<A::ImageKey>> {
+        let image_id = self.resolve_variant(image_id);
+        Some(self.images.get(&image_id)?.to_dimensions_info())
+    }
+
+    // Picks the smallest variant at least as dense as the device, falling
+    // back to the densest one available.
+    fn resolve_variant(&self, image_id: ImageId) -> ImageId {
+        match self.image_sets.get(&image_id) {
+            None => image_id,
+            Some(set) => set.iter()
+                .find(|&&(scale, _)| scale >= self.device_pixel_ratio)
+                .or_else(|| set.last())
+                .map_or(image_id, |&(_, variant_id)| variant_id)
+        }
+    }
+
+    pub fn status<P>(&self, src: P) -> ResourceStatus
+    where
+        P: AsRef<str>
+    {
+        self.status_for_id(self.image_id(src))
+    }
+
+    pub fn status_for_id(&self, image_id: ImageId) -> ResourceStatus {
+        if self.images.contains_key(&image_id) || self.image_sets.contains_key(&image_id) || self.image_arrays.contains_key(&image_id) {
+            return ResourceStatus::Ready;
+        }
+        if let Some(failure) = self.failures.get(&image_id) {
+            return ResourceStatus::Failed(failure.clone());
+        }
+        if self.deferred.contains_key(&image_id) {
+            ResourceStatus::Pending
+        } else if self.evicted.contains(&image_id) {
+            ResourceStatus::Evicted
+        } else {
+            ResourceStatus::Unknown
+        }
+    }
+
+    pub fn iter_images<'a>(&'a self) -> impl Iterator<Item = (ImageId, Rc<Image<A::ImageKey>>)> + 'a {
+        self.images
+            .iter()
+            .map(|(&image_id, image)| (image_id, Rc::clone(image)))
+    }
+
+    // Images share their pixels with the cache, so snapshots are cheap.
+    pub fn snapshot(&self) -> ImageCacheSnapshot<A::ImageKey>
+    where
+        A::ImageKey: Clone
+    {
+        let mut images = self.images
+            .iter()
+            .map(|(&image_id, image)| (image_id, Image::clone(image)))
+            .collect::<Vec<_>>();
+        images.sort_by_key(|&(image_id, _)| image_id);
+        ImageCacheSnapshot { images }
+    }
+
+    pub fn ids_for_origin<O>(&self, origin: O) -> Vec<ImageId>
+    where
+        O: AsRef<str>
+    {
+        let origin = origin.as_ref();
+        self.images
+            .keys()
+            .filter(|id| id.is_from_origin(origin))
+            .cloned()
+            .collect()
+    }
+
+    pub fn evict_origin<O>(&mut self, origin: O) -> Result<usize>
+    where
+        O: AsRef<str>,
+        A: TImageKeysAPIExt
+    {
+        let origin = origin.as_ref();
+        let evicted = &mut self.evicted;
+        let mut evict = |id: &ImageId| {
+            let from_origin = id.is_from_origin(origin);
+            if from_origin {
+                evicted.insert(*id);
+            }
+            !from_origin
+        };
+        self.image_sets.retain(|id, _| evict(id));
+        self.deferred.retain(|id, _| evict(id));
+        self.external_sources.retain(|id, _| !id.is_from_origin(origin));
+
+        let ids = self.ids_for_origin(origin);
+        for id in &ids {
+            self.remove_image(*id);
+        }
+        Ok(ids.len())
+    }
+
+    pub fn set_image_priority<P>(&mut self, src: P, priority: ResourcePriority)
+    where
+        P: AsRef<str>
+    {
+        self.set_image_priority_for_id(self.image_id(src), priority)
+    }
+
+    // Priorities set on an image set apply to all of its variants.
+    pub fn set_image_priority_for_id(&mut self, image_id: ImageId, priority: ResourcePriority) {
+        let ids = match self.image_sets.get(&image_id) {
+            Some(set) => set.iter().map(|&(_, variant_id)| variant_id).collect(),
+            None => vec![image_id]
+        };
+        for id in ids {
+            self.priorities.insert(id, priority);
+        }
+    }
+
+    pub fn image_priority<P>(&self, src: P) -> ResourcePriority
+    where
+        P: AsRef<str>
+    {
+        self.image_priority_for_id(self.image_id(src))
+    }
+
+    pub fn image_priority_for_id(&self, image_id: ImageId) -> ResourcePriority {
+        let image_id = self.resolve_variant(image_id);
+        self.priorities
+            .get(&image_id)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub fn used_bytes(&self) -> usize {
+        self.images
+            .values()
+            .map(|image| image.pixels.len())
+            .sum()
+    }
+
+    // Evicts images of the given priority, largest first, until at least
+    // `bytes` have been freed. Pinned images are never evicted.
+    pub fn evict_bytes(&mut self, priority: ResourcePriority, bytes: usize) -> Result<Vec<(ImageId, usize)>>
+    where
+        A: TImageKeysAPIExt
+    {
+        if !priority.is_evictable() {
+            return Ok(Vec::new());
+        }
+        let candidates = self.images
+            .iter()
+            .filter(|&(id, _)| self.priorities.get(id).cloned().unwrap_or_default() == priority)
+            .map(|(&id, image)| (id, image.pixels.len()))
+            .collect();
+        let evicted = quota::select_for_eviction(candidates, bytes);
+        for &(id, _) in &evicted {
+            self.remove_image(id);
+        }
+        Ok(evicted)
+    }
+
+    pub fn expiry_policy(&self) -> &ExpiryPolicy {
+        self.expiry.policy()
+    }
+
+    pub fn set_expiry_policy(&mut self, policy: ExpiryPolicy) {
+        self.expiry.set_policy(policy);
+    }
+
+    // Evicts images whose TTL has elapsed at `now_ms`, e.g. once per frame.
+    // Pinned images never expire.
+    pub fn sweep_expired(&mut self, now_ms: u64) -> Result<Vec<(ImageId, usize)>>
+    where
+        A: TImageKeysAPIExt
+    {
+        if self.expiry.policy().never_expires() {
+            return Ok(Vec::new());
+        }
+        let loaded: Vec<_> = self.images
+            .keys()
+            .filter(|&id| self.priorities.get(id).cloned().unwrap_or_default().is_evictable())
+            .cloned()
+            .collect();
+        let expired = self.expiry
+            .sweep(loaded, now_ms, |id, origin| id.is_from_origin(origin));
+        Ok(expired
+            .into_iter()
+            .filter_map(|id| {
+                let bytes = self.images.get(&id)?.pixels.len();
+                self.remove_image(id);
+                Some((id, bytes))
+            })
+            .collect())
+    }
+
+    // Keys of the images the renderer holds, with texture arrays under their
+    // own id rather than their layers'.
+    pub fn key_snapshot(&self) -> KeySnapshot<ImageId, A::ImageKey>
+    where
+        A::ImageKey: Copy + PartialEq
+    {
+        let images = self.images
+            .iter()
+            .filter(|&(_, image)| image.layer.is_none())
+            .map(|(&image_id, image)| (image_id, image.external_key));
+        let arrays = self.image_arrays.iter().filter_map(|(&array_id, layers)| {
+            layers
+                .first()
+                .and_then(|layer_id| self.images.get(layer_id))
+                .map(|image| (array_id, image.external_key))
+        });
+        images.chain(arrays).collect()
+    }
+
+    // For caches outliving their keys API, e.g. in a relaunched process whose
+    // renderer kept the resources added before.
+    pub fn reserve_keys(&mut self, snapshot: &KeySnapshot<ImageId, A::ImageKey>)
+    where
+        A: TImageKeysAPIExt,
+        A::ImageKey: Copy + PartialEq
+    {
+        let keys: Vec<_> = snapshot.keys().collect();
+        self.api.reserve_image_keys(&keys);
+    }
+
+    // Sends again, under their current keys, the images missing from what the
+    // renderer still knows, and deletes the ones it holds for nothing. Masks
+    // are linked again to their images. Returns what was replayed.
+    pub fn replay_keys(&mut self, known: &KeySnapshot<ImageId, A::ImageKey>) -> Result<KeySnapshot<ImageId, A::ImageKey>>
+    where
+        A: TImageKeysAPIExt,
+        A::ImageKey: Copy + PartialEq
+    {
+        let current = self.key_snapshot();
+        let diff = current.diff(known);
+
+        for &(image_id, key) in &diff.replay {
+            let replayed = match self.image_arrays.get(&image_id) {
+                Some(layer_ids) => {
+                    let mut layers = Vec::with_capacity(layer_ids.len());
+                    for &layer_id in layer_ids {
+                        layers.push(self.replay_source(layer_id)?);
+                    }
+                    let size = layers[0].1.size;
+                    let data = layers
+                        .iter()
+                        .map(|&(ref encoded, ref decoded, _)| (encoded.info(), decoded.info()))
+                        .collect();
+                    self.api.replay_image_array(key, size, data)
+                }
+                None => {
+                    let (encoded, decoded, channel) = self.replay_source(image_id)?;
+                    let orientation = self.images[&image_id].orientation;
+                    self.api
+                        .replay_image(key, encoded.info(), decoded.info(), orientation, channel)
+                }
+            };
+            if !replayed {
+                Err(ImageError::ReplayUnsupported)?;
+            }
+        }
+
+        for &(_, key) in &diff.stale {
+            if !current.keys().any(|current_key| current_key == key) {
+                self.api.delete_image(key);
+            }
+        }
+
+        let replayed: KeySnapshot<_, _> = diff.replay.into_iter().collect();
+        let fills = self.tint_fills
+            .iter()
+            .map(|(image_id, &(_, ref mask_id))| (image_id, mask_id));
+        for (&image_id, &mask_id) in self.masks.iter().chain(fills) {
+            let (image_key, mask_key) = match (current.get(image_id), current.get(mask_id)) {
+                (Some(image_key), Some(mask_key)) => (image_key, mask_key),
+                _ => continue
+            };
+            if replayed.get(image_id).is_some() || replayed.get(mask_id).is_some() {
+                self.api.link_image_mask(image_key, mask_key);
+            }
+        }
+        Ok(replayed)
+    }
+
+    // Pixels are kept unrotated, and sent along with the orientation like when
+    // first added. Images whose pixels aren't kept are decoded again from their
+    // encoded data, and the others re-encoded from their pixels.
+    fn replay_source(&self, image_id: ImageId) -> Result<(EncodedImage, DecodedImage, Option<MaskChannel>)> {
+        let image = self.images.get(&image_id).ok_or(ImageError::ImageNotFound)?;
+        if let Some(&(color, _)) = self.tint_fills.get(&image_id) {
+            let fill = tint_fill(color)?;
+            return Ok((EncodedImage::from_bytes(fill.encode_png()?)?, fill, None));
+        }
+        let channel = self.mask_channels.get(&image_id).cloned();
+        if let Some(encoded) = self.external_sources.get(&image_id) {
+            let decoded = match channel {
+                Some(_) => self.decode_mask(encoded)?.0.with_row_alignment(self.row_alignment),
+                None => self.decode(encoded)?
+            };
+            let encoded = EncodedImage::from_encoded(encoded).ok_or(ImageError::ImageNotFound)?;
+            return Ok((encoded, decoded, channel));
+        }
+        let decoded = DecodedImage {
+            format: image.format,
+            size: image.size,
+            row_stride: image.row_stride,
+            pixels: Arc::clone(&image.pixels)
+        };
+        let encoded = EncodedImage::from_bytes(decoded.encode_png()?)?;
+        Ok((encoded, decoded, channel.map(|_| MaskChannel::Luminance)))
+    }
+
+    fn remove_image(&mut self, image_id: ImageId)
+    where
+        A: TImageKeysAPIExt
+    {
+        if let Some(mask_id) = self.masks.remove(&image_id) {
+            self.remove_image(mask_id);
+        }
+        self.masks.retain(|_, &mut mask_id| mask_id != image_id);
+        self.mask_channels.remove(&image_id);
+        if let Some(variants) = self.tints.remove(&image_id) {
+            for variant_id in variants {
+                self.remove_image(variant_id);
+            }
+        }
+        for variants in self.tints.values_mut() {
+            variants.retain(|&variant_id| variant_id != image_id);
+        }
+        self.tint_fills.remove(&image_id);
+        self.priorities.remove(&image_id);
+        self.shared_memory.remove(&image_id);
+        self.external_sources.remove(&image_id);
+        self.progressive.remove(&image_id);
+        self.evicted.insert(image_id);
+        for set in self.image_sets.values_mut() {
+            set.retain(|&(_, variant_id)| variant_id != image_id);
+        }
+        let evicted = &mut self.evicted;
+        self.image_sets.retain(|&id, set| {
+            if set.is_empty() {
+                evicted.insert(id);
+            }
+            !set.is_empty()
+        });
+
+        if let Some(image) = self.images.remove(&image_id) {
+            // Layers share the key of their array, which goes away with its last layer.
+            if image.layer.is_some() && !self.remove_array_layer(image_id) {
+                return;
+            }
+            self.api.delete_image(image.external_key);
+            #[cfg(feature = "key-balance-watchdog")]
+            self.image_keys.deleted(image.external_key);
+        }
+    }
+
+    // Returns whether the array holding the layer is now empty.
+    fn remove_array_layer(&mut self, image_id: ImageId) -> bool {
+        let arrays = self.image_arrays.len();
+        for layers in self.image_arrays.values_mut() {
+            layers.retain(|&layer_id| layer_id != image_id);
+        }
+        let evicted = &mut self.evicted;
+        self.image_arrays.retain(|&id, layers| {
+            if layers.is_empty() {
+                evicted.insert(id);
+            }
+            !layers.is_empty()
+        });
+        self.image_arrays.len() < arrays
+    }
+
+    #[cfg(feature = "key-balance-watchdog")]
+    pub fn leaked_keys(&self) -> Vec<(ImageId, A::ImageKey)>
+    where
+        A::ImageKey: Copy + PartialEq
+    {
+        self.image_keys
+            .leaked(|key| self.images.values().any(|image| image.external_key == key))
+    }
+
+    #[cfg(feature = "key-balance-watchdog")]
+    pub fn unknown_deleted_keys(&self) -> &[A::ImageKey] {
+        self.image_keys.unknown_deletes()
+    }
+}
+
+// Only resource failures are recorded, not misuses such as adding an image twice.
+fn record_failure(failures: &mut FnvHashMap<ImageId, ImageFailure>, image_id: ImageId, err: &ImageError) {
+    if let Some(category) = err.category() {
+        failures.insert(
+            image_id,
+            ImageFailure {
+                category,
+                message: format!("{:?}", err)
+            }
+        );
     }
 }
@@ -9,13 +9,16 @@ CONDITIONS OF ANY KIND, either express or implied. See the License for the
 specific language governing permissions and limitations under the License.
 */
 
+use std::any::Any;
 use std::io::Cursor;
+use std::panic::{self, AssertUnwindSafe};
 
-use image::ImageDecoder;
+use image::{ImageBuffer, ImageDecoder, Luma, Pixel, Rgb, Rgba};
 use image::bmp::BMPDecoder;
 use image::gif::Decoder as GIFDecoder;
 use image::hdr::HDRDecoder;
 use image::ico::ICODecoder;
+use image::imageops::{resize, FilterType};
 use image::jpeg::JPEGDecoder;
 use image::png::PNGDecoder;
 use image::pnm::PNMDecoder;
@@ -23,10 +26,32 @@ use image::tga::TGADecoder;
 use image::tiff::TIFFDecoder;
 use image::webp::WebpDecoder;
 
-use error::Result;
-use types::ImageEncodingFormat;
+use error::{ImageError, Result};
+use types::{ImageEncodingFormat, ImageOrientation};
+
+// Some malformed inputs make decoder crates panic, e.g. on arithmetic overflows
+// in debug builds. Decoders only write to their own buffers, so the caches
+// are left as they were, as if decoding had failed.
+pub fn catch_decoder_panic<T, F>(decode: F) -> Result<T>
+where
+    F: FnOnce() -> Result<T>
+{
+    panic::catch_unwind(AssertUnwindSafe(decode)).unwrap_or_else(|payload| Err(ImageError::DecoderPanic(panic_message(&*payload))))
+}
+
+fn panic_message(payload: &(Any + Send)) -> String {
+    match (payload.downcast_ref::<&str>(), payload.downcast_ref::<String>()) {
+        (Some(message), _) => message.to_string(),
+        (_, Some(message)) => message.clone(),
+        _ => String::new()
+    }
+}
 
 pub fn get_dimensions(format: ImageEncodingFormat, bytes: &[u8]) -> Result<(u32, u32)> {
+    catch_decoder_panic(|| read_dimensions(format, bytes))
+}
+
+fn read_dimensions(format: ImageEncodingFormat, bytes: &[u8]) -> Result<(u32, u32)> {
     Ok(match format {
         ImageEncodingFormat::PNG => {
             let mut decoder = PNGDecoder::new(bytes);
@@ -70,3 +95,58 @@ pub fn get_dimensions(format: ImageEncodingFormat, bytes: &[u8]) -> Result<(u32,
         }
     })
 }
+
+pub fn orient_pixels(pixels: &[u8], size: (u32, u32), orientation: ImageOrientation) -> Vec<u8> {
+    let (w, h) = size;
+    let count = w as usize * h as usize;
+    if count == 0 || orientation == ImageOrientation::Identity {
+        return pixels.to_vec();
+    }
+
+    let bytes_per_pixel = pixels.len() / count;
+    let (display_w, display_h) = orientation.display_size(size);
+    let mut oriented = Vec::with_capacity(pixels.len());
+
+    for y in 0..display_h {
+        for x in 0..display_w {
+            let (source_x, source_y) = orientation.source_position((x, y), size);
+            let offset = (source_y as usize * w as usize + source_x as usize) * bytes_per_pixel;
+            oriented.extend_from_slice(&pixels[offset..offset + bytes_per_pixel]);
+        }
+    }
+
+    oriented
+}
+
+// Fits a size within a bounding box, preserving the aspect ratio. Never upscales.
+pub fn fit_size(size: (u32, u32), max_size: (u32, u32)) -> (u32, u32) {
+    let (w, h) = size;
+    let (max_w, max_h) = max_size;
+    if w <= max_w && h <= max_h {
+        return size;
+    }
+    let ratio = (f64::from(max_w) / f64::from(w)).min(f64::from(max_h) / f64::from(h));
+    (
+        ((f64::from(w) * ratio).round() as u32).max(1),
+        ((f64::from(h) * ratio).round() as u32).max(1)
+    )
+}
+
+pub fn downsample_pixels(pixels: Vec<u8>, bytes_per_pixel: usize, size: (u32, u32), target: (u32, u32)) -> Vec<u8> {
+    match bytes_per_pixel {
+        1 => downsample::<Luma<u8>>(pixels, size, target),
+        3 => downsample::<Rgb<u8>>(pixels, size, target),
+        4 => downsample::<Rgba<u8>>(pixels, size, target),
+        _ => pixels
+    }
+}
+
+fn downsample<P>(pixels: Vec<u8>, size: (u32, u32), target: (u32, u32)) -> Vec<u8>
+where
+    P: Pixel<Subpixel = u8> + 'static
+{
+    match ImageBuffer::<P, _>::from_raw(size.0, size.1, pixels) {
+        Some(buffer) => resize(&buffer, target.0, target.1, FilterType::Triangle).into_raw(),
+        None => Vec::new()
+    }
+}
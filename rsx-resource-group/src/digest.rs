@@ -0,0 +1,103 @@
+/*
+Copyright 2016 Mozilla
+Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+this file except in compliance with the License. You may obtain a copy of the
+License at http://www.apache.org/licenses/LICENSE-2.0
+Unless required by applicable law or agreed to in writing, software distributed
+under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+CONDITIONS OF ANY KIND, either express or implied. See the License for the
+specific language governing permissions and limitations under the License.
+*/
+
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
+use fnv::FnvHasher;
+use rsx_shared::traits::{TFontKeysAPI, TImageKeysAPI};
+
+use fonts::types::TFontBackend;
+use types::ResourceGroup;
+
+// Identifies the content and configuration of a resource group. Keys handed
+// out by the keys APIs aren't part of it, so groups holding the same resources
+// in different processes have the same digest, on the same platform.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct ResourceDigest(pub u64);
+
+impl fmt::Display for ResourceDigest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+impl<ImageKeysAPI, FontKeysAPI, FontBackend> ResourceGroup<ImageKeysAPI, FontKeysAPI, FontBackend>
+where
+    ImageKeysAPI: TImageKeysAPI,
+    FontKeysAPI: TFontKeysAPI,
+    FontBackend: TFontBackend
+{
+    // Resources are hashed sorted by id, so the order they were added in
+    // doesn't matter. Hashes every byte held, so best not called per frame.
+    pub fn digest(&self) -> ResourceDigest {
+        let files = self.files.borrow();
+        let images = self.images.borrow();
+        let fonts = self.fonts.borrow();
+        let mut hasher = FnvHasher::default();
+
+        format!("{:?}", self.display_profile).hash(&mut hasher);
+        self.byte_quota.map(|quota| quota as u64).hash(&mut hasher);
+        format!("{:?}", fonts.normalization()).hash(&mut hasher);
+        format!("{:?}", fonts.glyph_source_mode()).hash(&mut hasher);
+
+        let mut file_entries = files.iter_files().collect::<Vec<_>>();
+        file_entries.sort_by_key(|&(file_id, _)| file_id);
+        hasher.write_u64(file_entries.len() as u64);
+        for (file_id, bytes) in file_entries {
+            file_id.hash(&mut hasher);
+            write_bytes(&mut hasher, &bytes);
+        }
+
+        let mut image_entries = images.iter_images().collect::<Vec<_>>();
+        image_entries.sort_by_key(|&(image_id, _)| image_id);
+        hasher.write_u64(image_entries.len() as u64);
+        for (image_id, image) in image_entries {
+            image_id.hash(&mut hasher);
+            format!("{:?} {:?}", image.format(), image.orientation()).hash(&mut hasher);
+            (image.width(), image.height(), image.row_stride() as u64).hash(&mut hasher);
+            (image.scale().to_bits(), image.layer()).hash(&mut hasher);
+            write_bytes(&mut hasher, &image.pixels());
+        }
+
+        let mut font_ids = fonts.font_ids().collect::<Vec<_>>();
+        font_ids.sort();
+        hasher.write_u64(font_ids.len() as u64);
+        // Queried from the backend, so faces without bytes aren't counted as errors.
+        let backend = fonts.backend();
+        for font_id in font_ids {
+            font_id.hash(&mut hasher);
+            backend.get_face_index(font_id).ok().map(|index| index as u64).hash(&mut hasher);
+            match backend.get_bytes(font_id) {
+                Ok(bytes) => write_bytes(&mut hasher, &bytes),
+                Err(_) => hasher.write_u64(0)
+            }
+        }
+
+        let mut instances = fonts
+            .iter_instances()
+            .map(|info| (info.font_id, info.instance_id))
+            .collect::<Vec<_>>();
+        instances.sort();
+        hasher.write_u64(instances.len() as u64);
+        for instance in instances {
+            instance.hash(&mut hasher);
+        }
+
+        ResourceDigest(hasher.finish())
+    }
+}
+
+// Lengths go first, so that adjacent resources can't be confused.
+fn write_bytes(hasher: &mut FnvHasher, bytes: &[u8]) {
+    hasher.write_u64(bytes.len() as u64);
+    hasher.write(bytes);
+}
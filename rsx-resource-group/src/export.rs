@@ -0,0 +1,153 @@
+/*
+Copyright 2016 Mozilla
+Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+this file except in compliance with the License. You may obtain a copy of the
+License at http://www.apache.org/licenses/LICENSE-2.0
+Unless required by applicable law or agreed to in writing, software distributed
+under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+CONDITIONS OF ANY KIND, either express or implied. See the License for the
+specific language governing permissions and limitations under the License.
+*/
+
+use std::fmt::{Debug, Write};
+
+use rsx_shared::traits::{TFontKeysAPI, TImageKeysAPI};
+
+use fonts::types::TFontBackend;
+use snapshot::ResourceSnapshot;
+use types::ResourceGroup;
+
+const SAMPLE_TEXT: &str = "The quick brown fox jumps over the lazy dog 0123456789";
+
+impl<ImageKeysAPI, FontKeysAPI, FontBackend> ResourceGroup<ImageKeysAPI, FontKeysAPI, FontBackend>
+where
+    ImageKeysAPI: TImageKeysAPI,
+    FontKeysAPI: TFontKeysAPI,
+    FontBackend: TFontBackend
+{
+    // Produces a single self-contained HTML page embedding every registered
+    // image and font as data URIs. Meant for debugging only.
+    pub fn export_html(&self) -> String
+    where
+        ImageKeysAPI::ImageKey: Copy + Debug,
+        FontKeysAPI::FontKey: Debug,
+        FontKeysAPI::FontInstanceKey: Debug
+    {
+        self.snapshot().export_html()
+    }
+}
+
+// Encoding previews and fonts is the slow part of exporting, so snapshots can
+// be exported on another thread.
+impl<ImageKey, FontKey, FontInstanceKey> ResourceSnapshot<ImageKey, FontKey, FontInstanceKey>
+where
+    ImageKey: Copy + Debug,
+    FontKey: Debug,
+    FontInstanceKey: Debug
+{
+    pub fn export_html(&self) -> String {
+        let image_entries = &self.images.images;
+        let faces = &self.fonts.faces;
+        let mut instances = self.fonts.instances.iter().collect::<Vec<_>>();
+        instances.sort_by_key(|info| (info.instance_id.size(), info.instance_id.dpi()));
+
+        let mut html = String::new();
+        let _ = writeln!(html, "<!DOCTYPE html>");
+        let _ = writeln!(html, "<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Resource group</title>");
+        let _ = writeln!(html, "<style>");
+        let _ = writeln!(html, "body {{ font-family: sans-serif; }}");
+        let _ = writeln!(html, "table {{ border-collapse: collapse; }}");
+        let _ = writeln!(html, "td, th {{ border: 1px solid #ccc; padding: 4px; vertical-align: top; }}");
+        let _ = writeln!(html, "img {{ max-width: 256px; max-height: 256px; }}");
+        for (index, face) in faces.iter().enumerate() {
+            if let Some(data_uri) = face.to_data_uri() {
+                let _ = writeln!(
+                    html,
+                    "@font-face {{ font-family: \"rsx-font-{}\"; src: url(\"{}\"); }}",
+                    index, data_uri
+                );
+            }
+        }
+        let _ = writeln!(html, "</style>\n</head>\n<body>");
+
+        let _ = writeln!(html, "<h1>Images ({})</h1>", image_entries.len());
+        let _ = writeln!(
+            html,
+            "<table>\n<tr><th>Id</th><th>Key</th><th>Size</th><th>Display size</th><th>Format</th><th>Orientation</th><th>Preview</th></tr>"
+        );
+        for &(image_id, ref image) in image_entries {
+            let preview = match image.to_data_uri() {
+                Ok(data_uri) => format!("<img src=\"{}\">", data_uri),
+                Err(_) => "<em>unavailable</em>".to_string()
+            };
+            let _ = writeln!(
+                html,
+                "<tr><td>{}</td><td>{}</td><td>{}x{}</td><td>{}x{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                escape(format!("{:?}", image_id)),
+                escape(format!("{:?}", image.external_key())),
+                image.width(),
+                image.height(),
+                image.display_width(),
+                image.display_height(),
+                escape(format!("{:?}", image.format())),
+                escape(format!("{:?}", image.orientation())),
+                preview
+            );
+        }
+        let _ = writeln!(html, "</table>");
+
+        let _ = writeln!(html, "<h1>Fonts ({})</h1>", faces.len());
+        for (index, face) in faces.iter().enumerate() {
+            let family_name = face.family_name.as_ref().map_or("<unknown>", String::as_str);
+            let _ = writeln!(html, "<h2>{}</h2>", escape(family_name));
+            let _ = writeln!(
+                html,
+                "<p>Id: {}<br>Face index: {}</p>",
+                escape(format!("{:?}", face.font_id)),
+                face.face_index
+            );
+            let _ = writeln!(
+                html,
+                "<p style=\"font-family: 'rsx-font-{}'; font-size: 24px;\">{}</p>",
+                index, SAMPLE_TEXT
+            );
+            let _ = writeln!(
+                html,
+                "<table>\n<tr><th>Size</th><th>DPI</th><th>Font key</th><th>Instance key</th><th>Shaped text entries</th></tr>"
+            );
+            for info in instances.iter().filter(|info| info.font_id == face.font_id) {
+                let _ = writeln!(
+                    html,
+                    "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                    info.instance_id.size(),
+                    info.instance_id.dpi().0,
+                    escape(format!("{:?}", info.font_key)),
+                    escape(format!("{:?}", info.font_instance_key)),
+                    info.shaped_text_entries
+                );
+            }
+            let _ = writeln!(html, "</table>");
+        }
+
+        let _ = writeln!(html, "</body>\n</html>");
+        html
+    }
+}
+
+fn escape<T>(text: T) -> String
+where
+    T: AsRef<str>
+{
+    text.as_ref()
+        .chars()
+        .fold(String::new(), |mut escaped, c| {
+            match c {
+                '&' => escaped.push_str("&amp;"),
+                '<' => escaped.push_str("&lt;"),
+                '>' => escaped.push_str("&gt;"),
+                '"' => escaped.push_str("&quot;"),
+                c => escaped.push(c)
+            }
+            escaped
+        })
+}
@@ -0,0 +1,99 @@
+/*
+Copyright 2016 Mozilla
+Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+this file except in compliance with the License. You may obtain a copy of the
+License at http://www.apache.org/licenses/LICENSE-2.0
+Unless required by applicable law or agreed to in writing, software distributed
+under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+CONDITIONS OF ANY KIND, either express or implied. See the License for the
+specific language governing permissions and limitations under the License.
+*/
+
+// Proptest strategies for small images and fonts, to run random resources
+// through the encoded, cached, updates and serialized layers. Embedders can
+// reuse them for their own property tests.
+
+use std::sync::Arc;
+
+use proptest::collection::vec;
+use proptest::prelude::*;
+
+use fonts::placeholder;
+use fonts::types::PlaceholderMetrics;
+use images::types::{DecodedImage, ImagePixelFormat};
+use updates::pixels;
+use updates::units::{Dpi, Pt};
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct GeneratedImage {
+    pub format: ImagePixelFormat,
+    pub size: (u32, u32),
+    pub pixels: Vec<u8>
+}
+
+impl GeneratedImage {
+    pub fn decoded(&self) -> DecodedImage {
+        DecodedImage::from_raw_parts(self.format, self.size, Arc::new(self.pixels.clone())).unwrap()
+    }
+
+    pub fn encode_png(&self) -> Vec<u8> {
+        self.decoded().encode_png().unwrap()
+    }
+}
+
+// A placeholder face with random metrics, and text to shape with it.
+#[derive(Debug, PartialEq, Clone)]
+pub struct GeneratedFont {
+    pub metrics: PlaceholderMetrics,
+    pub size: Pt,
+    pub dpi: Dpi,
+    pub text: String
+}
+
+impl GeneratedFont {
+    pub fn bytes(&self) -> Vec<u8> {
+        placeholder::synthesize(&self.metrics)
+    }
+}
+
+// Formats PNGs can hold without conversion.
+pub fn pixel_formats() -> impl Strategy<Value = ImagePixelFormat> {
+    prop_oneof![
+        Just(ImagePixelFormat::Gray(8)),
+        Just(ImagePixelFormat::RGB(8)),
+        Just(ImagePixelFormat::RGBA(8))
+    ]
+}
+
+pub fn images(max_side: u32) -> impl Strategy<Value = GeneratedImage> {
+    (pixel_formats(), 1..=max_side, 1..=max_side).prop_flat_map(|(format, width, height)| {
+        let len = (width * height) as usize * pixels::bytes_per_pixel(format);
+        vec(any::<u8>(), len).prop_map(move |pixels| GeneratedImage {
+            format,
+            size: (width, height),
+            pixels
+        })
+    })
+}
+
+pub fn placeholder_metrics() -> impl Strategy<Value = PlaceholderMetrics> {
+    (1i16..2000, -1000i16..=0, 0i16..500, 1u16..2000, 0u16..200).prop_map(
+        |(ascender, descender, line_gap, advance, stroke)| PlaceholderMetrics {
+            ascender,
+            descender,
+            line_gap,
+            advance,
+            stroke
+        }
+    )
+}
+
+pub fn fonts() -> impl Strategy<Value = GeneratedFont> {
+    let dpis = prop_oneof![Just(Dpi(72)), Just(Dpi(96)), Just(Dpi(144))];
+    (placeholder_metrics(), 1u32..=96, dpis, "[ a-zA-Z0-9]{1,16}").prop_map(|(metrics, size, dpi, text)| GeneratedFont {
+        metrics,
+        size: Pt(size),
+        dpi,
+        text
+    })
+}
@@ -14,6 +14,28 @@ pub extern crate rsx_fonts as fonts;
 pub extern crate rsx_images as images;
 pub extern crate rsx_resource_updates as updates;
 
+extern crate fnv;
+#[cfg(feature = "lz4")]
+extern crate lz4;
+#[cfg(feature = "test-support")]
+#[macro_use]
+extern crate proptest;
 extern crate rsx_shared;
+#[cfg(feature = "zstd")]
+extern crate zstd;
 
+pub mod digest;
+pub mod export;
+#[cfg(feature = "test-support")]
+pub mod generators;
+pub mod lookup;
+pub mod persistence;
+pub mod preload;
+pub mod prelude;
+pub mod quota;
+pub mod snapshot;
+pub mod stats;
+pub mod test_harness;
 pub mod types;
+#[cfg(feature = "key-balance-watchdog")]
+pub mod watchdog;
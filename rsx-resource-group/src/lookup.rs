@@ -0,0 +1,118 @@
+/*
+Copyright 2016 Mozilla
+Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+this file except in compliance with the License. You may obtain a copy of the
+License at http://www.apache.org/licenses/LICENSE-2.0
+Unless required by applicable law or agreed to in writing, software distributed
+under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+CONDITIONS OF ANY KIND, either express or implied. See the License for the
+specific language governing permissions and limitations under the License.
+*/
+
+use std::rc::Rc;
+
+use rsx_shared::traits::{TFontKeysAPI, TImageKeysAPI};
+
+use fonts::types::{Dpi, FontInstanceId, Pt, RcFontInstance, TFontBackend};
+use images::types::Image;
+use types::ResourceGroup;
+use updates::codes::{self, ErrorCode};
+
+// How lookups through the group treat ids nothing was registered under.
+// Lenient lookups fall back to placeholders where the caches have them, e.g.
+// the placeholder face for fonts, and to nothing otherwise. Strict lookups
+// fail, so that typos in asset names surface where they're made instead of
+// as blank spots on screen.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Strictness {
+    Lenient,
+    Strict
+}
+
+impl Default for Strictness {
+    fn default() -> Self {
+        Strictness::Lenient
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum LookupError {
+    ImageNotRegistered(String),
+    FamilyNotRegistered(String)
+}
+
+impl LookupError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            &LookupError::ImageNotRegistered(_) => codes::LOOKUP_IMAGE_NOT_REGISTERED,
+            &LookupError::FamilyNotRegistered(_) => codes::LOOKUP_FAMILY_NOT_REGISTERED
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            &LookupError::ImageNotRegistered(ref src) => format!("No image registered for \"{}\"", src),
+            &LookupError::FamilyNotRegistered(ref family) => format!("No font family registered as \"{}\"", family)
+        }
+    }
+}
+
+impl<ImageKeysAPI, FontKeysAPI, FontBackend> ResourceGroup<ImageKeysAPI, FontKeysAPI, FontBackend>
+where
+    ImageKeysAPI: TImageKeysAPI,
+    FontKeysAPI: TFontKeysAPI,
+    FontBackend: TFontBackend
+{
+    pub fn strictness(&self) -> Strictness {
+        self.strictness
+    }
+
+    pub fn set_strictness(&mut self, strictness: Strictness) {
+        self.strictness = strictness;
+    }
+
+    // Strict lookups of missing ids panic instead of failing, in debug builds
+    // only, for catching them in tests without checking every result.
+    pub fn set_panic_on_missing(&mut self, enabled: bool) {
+        self.panic_on_missing = enabled;
+    }
+
+    pub fn lookup_image<P>(&self, src: P) -> Result<Option<Rc<Image<ImageKeysAPI::ImageKey>>>, LookupError>
+    where
+        P: AsRef<str>
+    {
+        match self.images.borrow().get_image(src.as_ref()) {
+            Some(image) => Ok(Some(image)),
+            None => self.missing(LookupError::ImageNotRegistered(src.as_ref().to_string()))
+        }
+    }
+
+    // Families only drawn with the placeholder face count as missing. Strict
+    // lookups of them fail before anything is inserted.
+    pub fn lookup_font<T>(&self, family_name: T, size: Pt, dpi: Dpi) -> Result<Option<RcFontInstance<FontKeysAPI>>, LookupError>
+    where
+        T: AsRef<str>
+    {
+        let font_instance_id = FontInstanceId::from_family_str(family_name.as_ref(), size, dpi);
+        let mut fonts = self.fonts.borrow_mut();
+        if self.strictness == Strictness::Strict && !fonts.has_font_family(font_instance_id) {
+            return self.missing(LookupError::FamilyNotRegistered(family_name.as_ref().to_string()));
+        }
+        let instance = fonts.get_or_insert_font(font_instance_id);
+        match instance {
+            Some(ref instance) if !fonts.is_placeholder(instance) => Ok(Some(Rc::clone(instance))),
+            placeholder => match self.strictness {
+                Strictness::Lenient => Ok(placeholder),
+                Strictness::Strict => self.missing(LookupError::FamilyNotRegistered(family_name.as_ref().to_string()))
+            }
+        }
+    }
+
+    fn missing<T>(&self, error: LookupError) -> Result<Option<T>, LookupError> {
+        match self.strictness {
+            Strictness::Lenient => Ok(None),
+            Strictness::Strict if self.panic_on_missing && cfg!(debug_assertions) => panic!("{}", error.message()),
+            Strictness::Strict => Err(error)
+        }
+    }
+}
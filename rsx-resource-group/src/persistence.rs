@@ -0,0 +1,325 @@
+/*
+Copyright 2016 Mozilla
+Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+this file except in compliance with the License. You may obtain a copy of the
+License at http://www.apache.org/licenses/LICENSE-2.0
+Unless required by applicable law or agreed to in writing, software distributed
+under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+CONDITIONS OF ANY KIND, either express or implied. See the License for the
+specific language governing permissions and limitations under the License.
+*/
+
+// Compression of the payloads written when persisting resources, e.g. to
+// bundles or disk caches. Codecs are picked per payload type, since fonts
+// compress well while most encoded images already are compressed. Each
+// payload starts with a header naming its codec and dictionary, so reading it
+// back only needs the dictionaries it was written with.
+
+use std::hash::Hasher;
+use std::rc::Rc;
+
+use fnv::{FnvHashMap, FnvHasher};
+#[cfg(feature = "lz4")]
+use lz4;
+#[cfg(feature = "zstd")]
+use zstd;
+
+use images::types::ImageEncodingFormat;
+use updates::codes::{self, ErrorCode};
+
+const HEADER_LEN: usize = 9;
+
+const TAG_IDENTITY: u8 = 0;
+const TAG_ZSTD: u8 = 1;
+const TAG_LZ4: u8 = 2;
+
+pub const DEFAULT_ZSTD_LEVEL: i32 = 3;
+
+// Payloads read back are allocated upfront at the length their header claims,
+// so corrupt or hostile ones claiming more than this are refused.
+pub const DEFAULT_MAX_PAYLOAD_LEN: usize = 256 * 1024 * 1024;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Codec {
+    Identity,
+    // Supports dictionaries.
+    Zstd { level: i32 },
+    Lz4
+}
+
+impl Codec {
+    // The best codec compiled in, see the `zstd` and `lz4` features.
+    pub fn preferred() -> Self {
+        if cfg!(feature = "zstd") {
+            Codec::Zstd {
+                level: DEFAULT_ZSTD_LEVEL
+            }
+        } else if cfg!(feature = "lz4") {
+            Codec::Lz4
+        } else {
+            Codec::Identity
+        }
+    }
+
+    pub fn is_available(&self) -> bool {
+        match self {
+            &Codec::Identity => true,
+            &Codec::Zstd { .. } => cfg!(feature = "zstd"),
+            &Codec::Lz4 => cfg!(feature = "lz4")
+        }
+    }
+
+    fn tag(&self) -> u8 {
+        match self {
+            &Codec::Identity => TAG_IDENTITY,
+            &Codec::Zstd { .. } => TAG_ZSTD,
+            &Codec::Lz4 => TAG_LZ4
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum PayloadType {
+    Font,
+    // Images in formats without compression of their own, e.g. BMP or TGA.
+    RawImage,
+    // PNG, JPEG, GIF and WebP images, which hardly shrink any further.
+    CompressedImage,
+    // Ids, keys and other bookkeeping.
+    Metadata
+}
+
+impl PayloadType {
+    pub fn for_image(format: ImageEncodingFormat) -> Self {
+        match format {
+            ImageEncodingFormat::PNG | ImageEncodingFormat::JPEG | ImageEncodingFormat::GIF | ImageEncodingFormat::WEBP => {
+                PayloadType::CompressedImage
+            }
+            _ => PayloadType::RawImage
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum PersistenceError {
+    CodecUnavailable(Codec),
+    UnknownCodec(u8),
+    DictionaryMissing(u32),
+    Truncated,
+    PayloadTooLarge,
+    CodecFailed(String)
+}
+
+impl PersistenceError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            &PersistenceError::CodecUnavailable(_) => codes::PERSISTENCE_CODEC_UNAVAILABLE,
+            &PersistenceError::UnknownCodec(_) => codes::PERSISTENCE_UNKNOWN_CODEC,
+            &PersistenceError::DictionaryMissing(_) => codes::PERSISTENCE_DICTIONARY_MISSING,
+            &PersistenceError::Truncated => codes::PERSISTENCE_TRUNCATED,
+            &PersistenceError::PayloadTooLarge => codes::PERSISTENCE_PAYLOAD_TOO_LARGE,
+            &PersistenceError::CodecFailed(_) => codes::PERSISTENCE_CODEC_FAILED
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            &PersistenceError::CodecUnavailable(codec) => format!("Codec {:?} isn't compiled in", codec),
+            &PersistenceError::UnknownCodec(tag) => format!("Unknown codec tag {}", tag),
+            &PersistenceError::DictionaryMissing(id) => format!("No dictionary with id {:08x}", id),
+            &PersistenceError::Truncated => "Persisted payload is truncated".to_string(),
+            &PersistenceError::PayloadTooLarge => "Payload exceeds the maximum length".to_string(),
+            &PersistenceError::CodecFailed(ref message) => format!("Codec failed: {}", message)
+        }
+    }
+}
+
+// Codecs and dictionaries per payload type. Dictionaries help with many small
+// similar assets, e.g. icons, and are only used by zstd.
+#[derive(Debug, PartialEq, Clone)]
+pub struct PersistenceOptions {
+    pub fonts: Codec,
+    pub raw_images: Codec,
+    pub compressed_images: Codec,
+    pub metadata: Codec,
+    pub dictionaries: FnvHashMap<PayloadType, Rc<Vec<u8>>>,
+    // Longest payload written or read back, before compression.
+    pub max_payload_len: usize
+}
+
+impl Default for PersistenceOptions {
+    fn default() -> Self {
+        PersistenceOptions {
+            fonts: Codec::preferred(),
+            raw_images: Codec::preferred(),
+            compressed_images: Codec::Identity,
+            metadata: Codec::preferred(),
+            dictionaries: FnvHashMap::default(),
+            max_payload_len: DEFAULT_MAX_PAYLOAD_LEN
+        }
+    }
+}
+
+impl PersistenceOptions {
+    pub fn uncompressed() -> Self {
+        PersistenceOptions {
+            fonts: Codec::Identity,
+            raw_images: Codec::Identity,
+            compressed_images: Codec::Identity,
+            metadata: Codec::Identity,
+            dictionaries: FnvHashMap::default(),
+            max_payload_len: DEFAULT_MAX_PAYLOAD_LEN
+        }
+    }
+
+    pub fn codec(&self, payload_type: PayloadType) -> Codec {
+        match payload_type {
+            PayloadType::Font => self.fonts,
+            PayloadType::RawImage => self.raw_images,
+            PayloadType::CompressedImage => self.compressed_images,
+            PayloadType::Metadata => self.metadata
+        }
+    }
+
+    pub fn set_dictionary(&mut self, payload_type: PayloadType, dictionary: Vec<u8>) {
+        self.dictionaries.insert(payload_type, Rc::new(dictionary));
+    }
+
+    // Trains a dictionary of at most `max_size` bytes on typical payloads.
+    #[cfg(feature = "zstd")]
+    pub fn train_dictionary<T>(&mut self, payload_type: PayloadType, samples: &[T], max_size: usize) -> Result<(), PersistenceError>
+    where
+        T: AsRef<[u8]>
+    {
+        let dictionary = zstd::dict::from_samples(samples, max_size).map_err(|e| PersistenceError::CodecFailed(e.to_string()))?;
+        self.set_dictionary(payload_type, dictionary);
+        Ok(())
+    }
+
+    // The header holds the codec's tag, the dictionary's id, 0 for none, and
+    // the payload's length before compression.
+    pub fn compress(&self, payload_type: PayloadType, bytes: &[u8]) -> Result<Vec<u8>, PersistenceError> {
+        let codec = self.codec(payload_type);
+        if bytes.len() > self.max_payload_len || bytes.len() > u32::max_value() as usize {
+            Err(PersistenceError::PayloadTooLarge)?;
+        }
+        let dictionary = match codec {
+            Codec::Zstd { .. } => self.dictionaries.get(&payload_type),
+            _ => None
+        };
+
+        let mut compressed = Vec::with_capacity(HEADER_LEN + bytes.len());
+        compressed.push(codec.tag());
+        push_u32(&mut compressed, dictionary.map_or(0, |dictionary| dictionary_id(dictionary)));
+        push_u32(&mut compressed, bytes.len() as u32);
+        match codec {
+            Codec::Identity => compressed.extend_from_slice(bytes),
+            Codec::Zstd { level } => compressed.extend(zstd_compress(bytes, level, dictionary)?),
+            Codec::Lz4 => compressed.extend(lz4_compress(bytes)?)
+        }
+        Ok(compressed)
+    }
+
+    pub fn decompress(&self, bytes: &[u8]) -> Result<Vec<u8>, PersistenceError> {
+        if bytes.len() < HEADER_LEN {
+            Err(PersistenceError::Truncated)?;
+        }
+        let (tag, id, len) = (bytes[0], read_u32(&bytes[1..]), read_u32(&bytes[5..]) as usize);
+        if len > self.max_payload_len {
+            Err(PersistenceError::PayloadTooLarge)?;
+        }
+        let payload = &bytes[HEADER_LEN..];
+        let dictionary = match id {
+            0 => None,
+            id => Some(
+                self.dictionaries
+                    .values()
+                    .find(|dictionary| dictionary_id(dictionary) == id)
+                    .ok_or(PersistenceError::DictionaryMissing(id))?
+            )
+        };
+
+        let decompressed = match tag {
+            TAG_IDENTITY => payload.to_vec(),
+            TAG_ZSTD => zstd_decompress(payload, len, dictionary)?,
+            TAG_LZ4 => lz4_decompress(payload, len)?,
+            tag => Err(PersistenceError::UnknownCodec(tag))?
+        };
+        if decompressed.len() != len {
+            Err(PersistenceError::Truncated)?;
+        }
+        Ok(decompressed)
+    }
+}
+
+// Never 0, which stands for no dictionary.
+fn dictionary_id(dictionary: &[u8]) -> u32 {
+    let mut hasher = FnvHasher::default();
+    hasher.write(dictionary);
+    (hasher.finish() as u32).max(1)
+}
+
+fn push_u32(bytes: &mut Vec<u8>, value: u32) {
+    bytes.extend_from_slice(&[(value >> 24) as u8, (value >> 16) as u8, (value >> 8) as u8, value as u8]);
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from(bytes[0]) << 24 | u32::from(bytes[1]) << 16 | u32::from(bytes[2]) << 8 | u32::from(bytes[3])
+}
+
+#[cfg(feature = "zstd")]
+fn zstd_compress(bytes: &[u8], level: i32, dictionary: Option<&Rc<Vec<u8>>>) -> Result<Vec<u8>, PersistenceError> {
+    let mut compressor = match dictionary {
+        Some(dictionary) => zstd::block::Compressor::with_dict(dictionary.to_vec()),
+        None => zstd::block::Compressor::new()
+    };
+    compressor
+        .compress(bytes, level)
+        .map_err(|e| PersistenceError::CodecFailed(e.to_string()))
+}
+
+#[cfg(not(feature = "zstd"))]
+fn zstd_compress(_: &[u8], level: i32, _: Option<&Rc<Vec<u8>>>) -> Result<Vec<u8>, PersistenceError> {
+    Err(PersistenceError::CodecUnavailable(Codec::Zstd { level }))
+}
+
+#[cfg(feature = "zstd")]
+fn zstd_decompress(bytes: &[u8], len: usize, dictionary: Option<&Rc<Vec<u8>>>) -> Result<Vec<u8>, PersistenceError> {
+    let mut decompressor = match dictionary {
+        Some(dictionary) => zstd::block::Decompressor::with_dict(dictionary.to_vec()),
+        None => zstd::block::Decompressor::new()
+    };
+    decompressor
+        .decompress(bytes, len)
+        .map_err(|e| PersistenceError::CodecFailed(e.to_string()))
+}
+
+#[cfg(not(feature = "zstd"))]
+fn zstd_decompress(_: &[u8], _: usize, _: Option<&Rc<Vec<u8>>>) -> Result<Vec<u8>, PersistenceError> {
+    Err(PersistenceError::CodecUnavailable(Codec::Zstd {
+        level: DEFAULT_ZSTD_LEVEL
+    }))
+}
+
+#[cfg(feature = "lz4")]
+fn lz4_compress(bytes: &[u8]) -> Result<Vec<u8>, PersistenceError> {
+    lz4::block::compress(bytes, None, false).map_err(|e| PersistenceError::CodecFailed(e.to_string()))
+}
+
+#[cfg(not(feature = "lz4"))]
+fn lz4_compress(_: &[u8]) -> Result<Vec<u8>, PersistenceError> {
+    Err(PersistenceError::CodecUnavailable(Codec::Lz4))
+}
+
+#[cfg(feature = "lz4")]
+fn lz4_decompress(bytes: &[u8], len: usize) -> Result<Vec<u8>, PersistenceError> {
+    if len > i32::max_value() as usize {
+        Err(PersistenceError::PayloadTooLarge)?;
+    }
+    lz4::block::decompress(bytes, Some(len as i32)).map_err(|e| PersistenceError::CodecFailed(e.to_string()))
+}
+
+#[cfg(not(feature = "lz4"))]
+fn lz4_decompress(_: &[u8], _: usize) -> Result<Vec<u8>, PersistenceError> {
+    Err(PersistenceError::CodecUnavailable(Codec::Lz4))
+}
@@ -0,0 +1,194 @@
+/*
+Copyright 2016 Mozilla
+Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+this file except in compliance with the License. You may obtain a copy of the
+License at http://www.apache.org/licenses/LICENSE-2.0
+Unless required by applicable law or agreed to in writing, software distributed
+under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+CONDITIONS OF ANY KIND, either express or implied. See the License for the
+specific language governing permissions and limitations under the License.
+*/
+
+use rsx_shared::traits::{TFontKeysAPI, TImageKeysAPI};
+
+use files::error::FileError;
+use fonts::error::FontError;
+use fonts::types::{Dpi, Pt, TFontBackend, TFontKeysAPIExt};
+use images::error::ImageError;
+use types::ResourceGroup;
+use updates::codes::{self, ErrorCode};
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum AssetReference {
+    Image {
+        src: String
+    },
+    Font {
+        family: String,
+        src: Option<String>,
+        face_index: usize,
+        sizes: Vec<Pt>,
+        dpi: Dpi
+    }
+}
+
+impl AssetReference {
+    fn src(&self) -> Option<&str> {
+        match self {
+            &AssetReference::Image { ref src } => Some(src),
+            &AssetReference::Font { ref src, .. } => src.as_ref().map(String::as_str)
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum PreloadError {
+    FileNotLoaded,
+    ImageNotDecoded,
+    FontNotDecoded,
+    FamilyNotFound
+}
+
+impl PreloadError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            &PreloadError::FileNotLoaded => codes::PRELOAD_FILE_NOT_LOADED,
+            &PreloadError::ImageNotDecoded => codes::PRELOAD_IMAGE_NOT_DECODED,
+            &PreloadError::FontNotDecoded => codes::PRELOAD_FONT_NOT_DECODED,
+            &PreloadError::FamilyNotFound => codes::PRELOAD_FAMILY_NOT_FOUND
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            &PreloadError::FileNotLoaded => "Referenced file couldn't be loaded".to_string(),
+            &PreloadError::ImageNotDecoded => "Referenced image couldn't be decoded".to_string(),
+            &PreloadError::FontNotDecoded => "Referenced font couldn't be decoded".to_string(),
+            &PreloadError::FamilyNotFound => "Referenced font family not found".to_string()
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct UnresolvedReference {
+    pub reference: AssetReference,
+    pub error: PreloadError
+}
+
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct PreloadReport {
+    pub resolved: usize,
+    pub unresolved: Vec<UnresolvedReference>
+}
+
+impl PreloadReport {
+    pub fn is_complete(&self) -> bool {
+        self.unresolved.is_empty()
+    }
+}
+
+impl<ImageKeysAPI, FontKeysAPI, FontBackend> ResourceGroup<ImageKeysAPI, FontKeysAPI, FontBackend>
+where
+    ImageKeysAPI: TImageKeysAPI,
+    FontKeysAPI: TFontKeysAPI,
+    FontBackend: TFontBackend
+{
+    // Loads all referenced assets in dependency order: files first, then font
+    // faces, font instances and finally images.
+    pub fn preload(&self, references: &[AssetReference]) -> PreloadReport
+    where
+        FontKeysAPI: TFontKeysAPIExt
+    {
+        let mut report = PreloadReport::default();
+        let mut pending = Vec::with_capacity(references.len());
+
+        for reference in references {
+            match reference.src().map(|src| self.preload_file(src)) {
+                Some(Err(error)) => report.unresolved.push(UnresolvedReference {
+                    reference: reference.clone(),
+                    error
+                }),
+                _ => pending.push(reference)
+            }
+        }
+
+        let (fonts, images): (Vec<_>, Vec<_>) = pending.into_iter().partition(|reference| match reference {
+            &&AssetReference::Font { .. } => true,
+            &&AssetReference::Image { .. } => false
+        });
+
+        for reference in fonts.into_iter().chain(images) {
+            match self.preload_reference(reference) {
+                Ok(()) => report.resolved += 1,
+                Err(error) => report.unresolved.push(UnresolvedReference {
+                    reference: reference.clone(),
+                    error
+                })
+            }
+        }
+
+        report
+    }
+
+    fn preload_file(&self, src: &str) -> Result<(), PreloadError> {
+        match self.files.borrow_mut().add_file(src) {
+            Ok(()) | Err(FileError::FileAlreadyAdded) => Ok(()),
+            Err(_) => Err(PreloadError::FileNotLoaded)
+        }
+    }
+
+    fn preload_reference(&self, reference: &AssetReference) -> Result<(), PreloadError>
+    where
+        FontKeysAPI: TFontKeysAPIExt
+    {
+        match reference {
+            &AssetReference::Image { ref src } => {
+                let bytes = self.files
+                    .borrow()
+                    .get_file(src)
+                    .map_err(|_| PreloadError::FileNotLoaded)?;
+                let mut images = self.images.borrow_mut();
+                let id = images.image_id(src);
+                match images.add_raw(id, bytes) {
+                    Ok(()) | Err(ImageError::ImageAlreadyAdded) => Ok(()),
+                    Err(_) => Err(PreloadError::ImageNotDecoded)
+                }
+            }
+            &AssetReference::Font {
+                ref family,
+                ref src,
+                face_index,
+                ref sizes,
+                dpi
+            } => {
+                let mut fonts = self.fonts.borrow_mut();
+                let (family, added) = match src {
+                    &Some(ref src) => {
+                        let bytes = self.files
+                            .borrow()
+                            .get_file(src)
+                            .map_err(|_| PreloadError::FileNotLoaded)?;
+                        let id = fonts.font_id(family);
+                        let added = match fonts.add_raw(id, bytes, face_index) {
+                            Ok(()) => Some(id),
+                            Err(FontError::FaceAlreadyAdded) | Err(FontError::FontInstanceAlreadyAdded) => None,
+                            Err(_) => Err(PreloadError::FontNotDecoded)?
+                        };
+                        // Instances are keyed by the family in the face's name
+                        // table, which manifests may spell differently.
+                        (fonts.get_family_name_for_id(id).unwrap_or_else(|_| family.clone()), added)
+                    }
+                    &None => (family.clone(), None)
+                };
+                if fonts.pre_register_instances(&family, sizes, dpi).is_some() {
+                    return Ok(());
+                }
+                // Faces only added for this reference don't outlive it.
+                if let Some(id) = added {
+                    let _ = fonts.remove_font(id);
+                }
+                Err(PreloadError::FamilyNotFound)
+            }
+        }
+    }
+}
@@ -0,0 +1,69 @@
+/*
+Copyright 2016 Mozilla
+Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+this file except in compliance with the License. You may obtain a copy of the
+License at http://www.apache.org/licenses/LICENSE-2.0
+Unless required by applicable law or agreed to in writing, software distributed
+under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+CONDITIONS OF ANY KIND, either express or implied. See the License for the
+specific language governing permissions and limitations under the License.
+*/
+
+// Everything the common path needs behind a single glob import: the traits
+// whose methods the caches are used through, the caches and ids of the three
+// sub-crates, the default keys APIs and the group itself. Consumers going
+// through it don't depend on rsx-shared directly, so there's no version of it
+// to keep in sync with the one used here.
+
+pub use rsx_shared::traits::{
+    TDimensionsInfo,
+    TEncodedFont,
+    TEncodedImage,
+    TFileCache,
+    TFontCache,
+    TFontInstanceKey,
+    TFontKey,
+    TFontKeysAPI,
+    TGlyphInstance,
+    TGlyphStore,
+    TImageCache,
+    TImageKeysAPI,
+    TMediaKey,
+    TResourceGroup
+};
+pub use rsx_shared::types::{FontEncodedData, FontInstanceResourceData, FontResourceData, ImageEncodedData, ImageResourceData};
+
+pub use files::error::FileError;
+pub use files::types::{FileCache, FileId, SharedFiles};
+pub use fonts::error::FontError;
+pub use fonts::types::{
+    Dpi,
+    EncodedFont,
+    FontCache,
+    FontContext,
+    FontId,
+    FontInstanceId,
+    GlyphStore,
+    Pt,
+    Px,
+    RcFontInstance,
+    ShapingOptions,
+    SharedFonts,
+    TFontBackend
+};
+pub use images::error::ImageError;
+pub use images::types::{EncodedImage, Image, ImageCache, ImageId, SharedImages};
+pub use updates::traits::{TFontKeysAPIExt, TGlyphInstanceExt, TGlyphStoreExt, TImageKeysAPIExt};
+pub use updates::types::{
+    DefaultFontInstanceKey,
+    DefaultFontKey,
+    DefaultFontKeysAPI,
+    DefaultGlyphInstance,
+    DefaultImageKey,
+    DefaultImageKeysAPI,
+    ResourceUpdates,
+    Update
+};
+
+pub use lookup::{LookupError, Strictness};
+pub use types::{DefaultResourceGroup, ResourceGroup};
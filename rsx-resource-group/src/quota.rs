@@ -0,0 +1,119 @@
+/*
+Copyright 2016 Mozilla
+Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+this file except in compliance with the License. You may obtain a copy of the
+License at http://www.apache.org/licenses/LICENSE-2.0
+Unless required by applicable law or agreed to in writing, software distributed
+under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+CONDITIONS OF ANY KIND, either express or implied. See the License for the
+specific language governing permissions and limitations under the License.
+*/
+
+use rsx_shared::traits::{TFontKeysAPI, TImageKeysAPI};
+
+use fonts::types::{FontId, TFontBackend, TFontKeysAPIExt};
+use images::types::{ImageId, TImageKeysAPIExt};
+use types::ResourceGroup;
+use updates::quota::ResourcePriority;
+
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct QuotaReport {
+    pub used_bytes: usize,
+    pub freed_bytes: usize,
+    pub evicted_images: Vec<ImageId>,
+    pub evicted_fonts: Vec<FontId>
+}
+
+impl<ImageKeysAPI, FontKeysAPI, FontBackend> ResourceGroup<ImageKeysAPI, FontKeysAPI, FontBackend>
+where
+    ImageKeysAPI: TImageKeysAPI,
+    FontKeysAPI: TFontKeysAPI,
+    FontBackend: TFontBackend
+{
+    pub fn byte_quota(&self) -> Option<usize> {
+        self.byte_quota
+    }
+
+    // The quota isn't enforced as resources are added, since caches can be
+    // mutated directly. Call `enforce_byte_quota` once loading settles.
+    pub fn set_byte_quota(&mut self, quota: Option<usize>) {
+        self.byte_quota = quota;
+    }
+
+    pub fn used_bytes(&self) -> usize {
+        self.images.borrow().used_bytes() + self.fonts.borrow().used_bytes()
+    }
+
+    // Evicts discardable resources first, then normal ones, until the decoded
+    // images and font faces fit in the quota. Pinned resources are kept even
+    // if that leaves the group over quota.
+    pub fn enforce_byte_quota(&mut self) -> QuotaReport
+    where
+        ImageKeysAPI: TImageKeysAPIExt,
+        FontKeysAPI: TFontKeysAPIExt
+    {
+        let mut report = QuotaReport {
+            used_bytes: self.used_bytes(),
+            ..QuotaReport::default()
+        };
+        let quota = match self.byte_quota {
+            Some(quota) => quota,
+            None => return report
+        };
+
+        for &priority in &ResourcePriority::EVICTION_ORDER {
+            if report.used_bytes <= quota {
+                break;
+            }
+            let images = self.images
+                .borrow_mut()
+                .evict_bytes(priority, report.used_bytes - quota)
+                .unwrap_or_default();
+            for (id, bytes) in images {
+                report.used_bytes -= bytes;
+                report.freed_bytes += bytes;
+                report.evicted_images.push(id);
+            }
+
+            if report.used_bytes <= quota {
+                break;
+            }
+            // Candidates come from the loaded faces, so removing them can't fail.
+            let fonts = self.fonts
+                .borrow_mut()
+                .evict_bytes(priority, report.used_bytes - quota)
+                .unwrap_or_default();
+            for (id, bytes) in fonts {
+                report.used_bytes -= bytes;
+                report.freed_bytes += bytes;
+                report.evicted_fonts.push(id);
+            }
+        }
+
+        report
+    }
+
+    // Evicts images and fonts whose TTL, as set by each cache's expiry policy,
+    // has elapsed at `now_ms`. Meant to be driven by the frame scheduler.
+    pub fn sweep_expired(&mut self, now_ms: u64) -> QuotaReport
+    where
+        ImageKeysAPI: TImageKeysAPIExt,
+        FontKeysAPI: TFontKeysAPIExt
+    {
+        let images = self.images
+            .borrow_mut()
+            .sweep_expired(now_ms)
+            .unwrap_or_default();
+        let fonts = self.fonts
+            .borrow_mut()
+            .sweep_expired(now_ms)
+            .unwrap_or_default();
+
+        QuotaReport {
+            used_bytes: self.used_bytes(),
+            freed_bytes: images.iter().map(|&(_, bytes)| bytes).sum::<usize>() + fonts.iter().map(|&(_, bytes)| bytes).sum::<usize>(),
+            evicted_images: images.into_iter().map(|(id, _)| id).collect(),
+            evicted_fonts: fonts.into_iter().map(|(id, _)| id).collect()
+        }
+    }
+}
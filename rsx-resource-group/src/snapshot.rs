@@ -0,0 +1,44 @@
+/*
+Copyright 2016 Mozilla
+Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+this file except in compliance with the License. You may obtain a copy of the
+License at http://www.apache.org/licenses/LICENSE-2.0
+Unless required by applicable law or agreed to in writing, software distributed
+under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+CONDITIONS OF ANY KIND, either express or implied. See the License for the
+specific language governing permissions and limitations under the License.
+*/
+
+use rsx_shared::traits::{TFontKeysAPI, TImageKeysAPI};
+
+use fonts::types::{FontCacheSnapshot, TFontBackend};
+use images::types::ImageCacheSnapshot;
+use types::ResourceGroup;
+
+// A consistent view of the images and fonts of a group, for serializing them
+// on a background thread while the caches keep changing on the main one.
+// Pixels are shared with the caches rather than copied. Backends keep font
+// bytes in `Rc`s, which can't cross threads, so each face is copied once, by
+// the first snapshot taken after it's added, and later snapshots share that.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ResourceSnapshot<ImageKey, FontKey, FontInstanceKey> {
+    pub images: ImageCacheSnapshot<ImageKey>,
+    pub fonts: FontCacheSnapshot<FontKey, FontInstanceKey>
+}
+
+impl<ImageKeysAPI, FontKeysAPI, FontBackend> ResourceGroup<ImageKeysAPI, FontKeysAPI, FontBackend>
+where
+    ImageKeysAPI: TImageKeysAPI,
+    FontKeysAPI: TFontKeysAPI,
+    FontBackend: TFontBackend
+{
+    pub fn snapshot(&self) -> ResourceSnapshot<ImageKeysAPI::ImageKey, FontKeysAPI::FontKey, FontKeysAPI::FontInstanceKey>
+    where
+        ImageKeysAPI::ImageKey: Clone
+    {
+        ResourceSnapshot {
+            images: self.images.borrow().snapshot(),
+            fonts: self.fonts.borrow().snapshot()
+        }
+    }
+}
@@ -0,0 +1,84 @@
+/*
+Copyright 2016 Mozilla
+Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+this file except in compliance with the License. You may obtain a copy of the
+License at http://www.apache.org/licenses/LICENSE-2.0
+Unless required by applicable law or agreed to in writing, software distributed
+under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+CONDITIONS OF ANY KIND, either express or implied. See the License for the
+specific language governing permissions and limitations under the License.
+*/
+
+use rsx_shared::traits::{TFontKeysAPI, TImageKeysAPI};
+
+use fonts::types::TFontBackend;
+use types::ResourceGroup;
+use updates::stats::{ErrorCounters, IdCounters};
+
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct ErrorReport {
+    pub images: ErrorCounters,
+    pub fonts: ErrorCounters
+}
+
+impl ErrorReport {
+    pub fn total(&self) -> ErrorCounters {
+        self.images.merged(self.fonts)
+    }
+}
+
+// Collisions are expected to stay at zero. Any other value means two sources
+// resolve to the same resource, and that 64 bit ids aren't enough.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct IdReport {
+    pub images: IdCounters,
+    pub fonts: IdCounters
+}
+
+impl IdReport {
+    pub fn total(&self) -> IdCounters {
+        self.images.merged(self.fonts)
+    }
+}
+
+impl<ImageKeysAPI, FontKeysAPI, FontBackend> ResourceGroup<ImageKeysAPI, FontKeysAPI, FontBackend>
+where
+    ImageKeysAPI: TImageKeysAPI,
+    FontKeysAPI: TFontKeysAPI,
+    FontBackend: TFontBackend
+{
+    pub fn error_report(&self) -> ErrorReport {
+        ErrorReport {
+            images: self.images.borrow().error_counters(),
+            fonts: self.fonts.borrow().error_counters()
+        }
+    }
+
+    // Returns the counts since the last reset, so that calling this once per
+    // frame yields per frame error rates.
+    pub fn reset_error_report(&self) -> ErrorReport {
+        ErrorReport {
+            images: self.images.borrow().reset_error_counters(),
+            fonts: self.fonts.borrow().reset_error_counters()
+        }
+    }
+
+    pub fn id_report(&self) -> IdReport {
+        IdReport {
+            images: self.images.borrow().id_counters(),
+            fonts: self.fonts.borrow().id_counters()
+        }
+    }
+
+    pub fn reset_id_report(&self) -> IdReport {
+        IdReport {
+            images: self.images.borrow().reset_id_counters(),
+            fonts: self.fonts.borrow().reset_id_counters()
+        }
+    }
+
+    pub fn set_id_collision_checks(&self, enabled: bool) {
+        self.images.borrow_mut().set_id_collision_checks(enabled);
+        self.fonts.borrow_mut().set_id_collision_checks(enabled);
+    }
+}
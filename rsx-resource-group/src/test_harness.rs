@@ -0,0 +1,358 @@
+/*
+Copyright 2016 Mozilla
+Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+this file except in compliance with the License. You may obtain a copy of the
+License at http://www.apache.org/licenses/LICENSE-2.0
+Unless required by applicable law or agreed to in writing, software distributed
+under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+CONDITIONS OF ANY KIND, either express or implied. See the License for the
+specific language governing permissions and limitations under the License.
+*/
+
+// Drives a resource group the way an embedder's frame loop would: resources
+// are loaded and measured during a frame, then the updates it caused are taken
+// and applied to a mock renderer. The mock checks that every update only
+// refers to keys added before it, and that keys are added and deleted exactly
+// once, so a frame fails as soon as a consumer applying the updates would.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::hash::Hash;
+
+use rsx_shared::traits::{TFontCache, TImageCache};
+
+use fonts::types::TFontBackend;
+use stats::ErrorReport;
+use types::ResourceGroup;
+use updates::order::UpdateId;
+use updates::types::{DefaultFontKey, DefaultFontInstanceKey, DefaultFontKeysAPI, DefaultImageKey, DefaultImageKeysAPI, ResourceUpdates, Update};
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum SinkError<ImageKey, FontKey, FontInstanceKey> {
+    DuplicateImage(ImageKey),
+    MissingImage(ImageKey),
+    DuplicateFont(FontKey),
+    MissingFont(FontKey),
+    DuplicateFontInstance(FontInstanceKey),
+    MissingFontInstance(FontInstanceKey),
+    DuplicateAtlasPage(u32),
+    MissingAtlasPage(u32)
+}
+
+impl<ImageKey, FontKey, FontInstanceKey> fmt::Display for SinkError<ImageKey, FontKey, FontInstanceKey>
+where
+    ImageKey: fmt::Debug,
+    FontKey: fmt::Debug,
+    FontInstanceKey: fmt::Debug
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &SinkError::DuplicateImage(ref key) => write!(f, "Image {:?} added twice", key),
+            &SinkError::MissingImage(ref key) => write!(f, "Image {:?} not added", key),
+            &SinkError::DuplicateFont(ref key) => write!(f, "Font {:?} added twice", key),
+            &SinkError::MissingFont(ref key) => write!(f, "Font {:?} not added", key),
+            &SinkError::DuplicateFontInstance(ref key) => write!(f, "Font instance {:?} added twice", key),
+            &SinkError::MissingFontInstance(ref key) => write!(f, "Font instance {:?} not added", key),
+            &SinkError::DuplicateAtlasPage(page) => write!(f, "Glyph atlas page {} added twice", page),
+            &SinkError::MissingAtlasPage(page) => write!(f, "Glyph atlas page {} not added", page)
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Default)]
+pub struct KeyCounts {
+    pub images: usize,
+    pub fonts: usize,
+    pub font_instances: usize
+}
+
+// Stands in for a renderer, keeping track of the keys it was told about.
+#[derive(Debug, PartialEq, Clone)]
+pub struct MockSink<ImageKey: Eq + Hash, FontKey: Eq + Hash, FontInstanceKey: Eq + Hash> {
+    images: HashSet<ImageKey>,
+    fonts: HashSet<FontKey>,
+    font_instances: HashSet<FontInstanceKey>,
+    atlas_pages: HashSet<u32>,
+    replaces_fonts: bool,
+    applied: usize
+}
+
+impl<ImageKey, FontKey, FontInstanceKey> MockSink<ImageKey, FontKey, FontInstanceKey>
+where
+    ImageKey: Copy + Eq + Hash,
+    FontKey: Copy + Eq + Hash,
+    FontInstanceKey: Copy + Eq + Hash
+{
+    pub fn new() -> Self {
+        MockSink {
+            images: HashSet::new(),
+            fonts: HashSet::new(),
+            font_instances: HashSet::new(),
+            atlas_pages: HashSet::new(),
+            replaces_fonts: false,
+            applied: 0
+        }
+    }
+
+    pub fn key_counts(&self) -> KeyCounts {
+        KeyCounts {
+            images: self.images.len(),
+            fonts: self.fonts.len(),
+            font_instances: self.font_instances.len()
+        }
+    }
+
+    // Accepts fonts added again under a key already held, replacing them like
+    // renderers supporting `TFontKeysAPIExt::supports_font_replacement` do.
+    pub fn set_replaces_fonts(&mut self, enabled: bool) {
+        self.replaces_fonts = enabled;
+    }
+
+    // Total updates applied so far, across all batches.
+    pub fn applied(&self) -> usize {
+        self.applied
+    }
+
+    pub fn has_image(&self, key: ImageKey) -> bool {
+        self.images.contains(&key)
+    }
+
+    pub fn has_font(&self, key: FontKey) -> bool {
+        self.fonts.contains(&key)
+    }
+
+    pub fn has_font_instance(&self, key: FontInstanceKey) -> bool {
+        self.font_instances.contains(&key)
+    }
+
+    // Applies updates one by one, stopping at the first one a renderer would
+    // reject. Updates before it stay applied.
+    pub fn apply(
+        &mut self,
+        updates: &ResourceUpdates<ImageKey, FontKey, FontInstanceKey>
+    ) -> Result<(), (UpdateId, SinkError<ImageKey, FontKey, FontInstanceKey>)> {
+        for (id, update) in updates.updates.iter().enumerate() {
+            self.apply_update(update).map_err(|error| (id, error))?;
+            self.applied += 1;
+        }
+        Ok(())
+    }
+
+    fn apply_update(&mut self, update: &Update<ImageKey, FontKey, FontInstanceKey>) -> Result<(), SinkError<ImageKey, FontKey, FontInstanceKey>> {
+        match update {
+            &Update::AddImage { key, .. }
+            | &Update::AddImageMask { key, .. }
+            | &Update::AddImageArray { key, .. }
+            | &Update::AddSharedImage { key, .. }
+            | &Update::AddSharedImageMask { key, .. } => {
+                if !self.images.insert(key) {
+                    Err(SinkError::DuplicateImage(key))?
+                }
+            }
+            &Update::AddImageArrayLayer { key, .. } | &Update::UpdateImage { key, .. } => self.expect_image(key)?,
+            &Update::LinkImageMask { key, mask_key } => {
+                self.expect_image(key)?;
+                self.expect_image(mask_key)?;
+            }
+            &Update::AddFont { key, .. }
+            | &Update::AddFontBytes { key, .. }
+            | &Update::AddSharedFont { key, .. } => {
+                if !self.fonts.insert(key) && !self.replaces_fonts {
+                    Err(SinkError::DuplicateFont(key))?
+                }
+            }
+            &Update::AddFontInstance { key, instance_key, .. } => {
+                if !self.fonts.contains(&key) {
+                    Err(SinkError::MissingFont(key))?
+                }
+                if !self.font_instances.insert(instance_key) {
+                    Err(SinkError::DuplicateFontInstance(instance_key))?
+                }
+            }
+            &Update::AddGlyphRun { instance_key, .. } => {
+                if !self.font_instances.contains(&instance_key) {
+                    Err(SinkError::MissingFontInstance(instance_key))?
+                }
+            }
+            &Update::AddGlyphAtlasPage { page, .. } => {
+                if !self.atlas_pages.insert(page) {
+                    Err(SinkError::DuplicateAtlasPage(page))?
+                }
+            }
+            &Update::UpdateGlyphAtlasRegion { page, .. } => {
+                if !self.atlas_pages.contains(&page) {
+                    Err(SinkError::MissingAtlasPage(page))?
+                }
+            }
+            &Update::DeleteGlyphAtlasPage { page } => {
+                if !self.atlas_pages.remove(&page) {
+                    Err(SinkError::MissingAtlasPage(page))?
+                }
+            }
+            &Update::DeleteImage { key } => {
+                if !self.images.remove(&key) {
+                    Err(SinkError::MissingImage(key))?
+                }
+            }
+            &Update::DeleteFont { key } => {
+                if !self.fonts.remove(&key) {
+                    Err(SinkError::MissingFont(key))?
+                }
+            }
+            &Update::DeleteFontInstance { instance_key } => {
+                if !self.font_instances.remove(&instance_key) {
+                    Err(SinkError::MissingFontInstance(instance_key))?
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn expect_image(&self, key: ImageKey) -> Result<(), SinkError<ImageKey, FontKey, FontInstanceKey>> {
+        if self.images.contains(&key) {
+            Ok(())
+        } else {
+            Err(SinkError::MissingImage(key))
+        }
+    }
+}
+
+impl<ImageKey, FontKey, FontInstanceKey> Default for MockSink<ImageKey, FontKey, FontInstanceKey>
+where
+    ImageKey: Copy + Eq + Hash,
+    FontKey: Copy + Eq + Hash,
+    FontInstanceKey: Copy + Eq + Hash
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub type DefaultMockSink = MockSink<DefaultImageKey, DefaultFontKey, DefaultFontInstanceKey>;
+pub type DefaultSinkError = SinkError<DefaultImageKey, DefaultFontKey, DefaultFontInstanceKey>;
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct FrameError {
+    pub frame: usize,
+    pub update: UpdateId,
+    pub image_updates: bool,
+    pub error: DefaultSinkError
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let batch = if self.image_updates { "image" } else { "font" };
+        write!(f, "Frame {}, {} update {}: {}", self.frame, batch, self.update, self.error)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct FrameReport {
+    pub frame: usize,
+    pub image_updates: usize,
+    pub font_updates: usize,
+    // Keys the mock renderer holds, and resources the caches hold.
+    pub sink_keys: KeyCounts,
+    pub cache_keys: KeyCounts,
+    pub shaped_text_entries: usize,
+    pub used_bytes: usize,
+    pub errors: ErrorReport
+}
+
+impl FrameReport {
+    pub fn updates(&self) -> usize {
+        self.image_updates + self.font_updates
+    }
+
+    // Every cached resource was added to the renderer, and everything the
+    // renderer still holds is cached.
+    pub fn is_balanced(&self) -> bool {
+        self.sink_keys == self.cache_keys
+    }
+}
+
+pub struct FrameLoop<FontBackend: TFontBackend> {
+    group: ResourceGroup<DefaultImageKeysAPI, DefaultFontKeysAPI, FontBackend>,
+    sink: DefaultMockSink,
+    frame: usize
+}
+
+impl<FontBackend> FrameLoop<FontBackend>
+where
+    FontBackend: TFontBackend + 'static
+{
+    pub fn new(group: ResourceGroup<DefaultImageKeysAPI, DefaultFontKeysAPI, FontBackend>) -> Self {
+        FrameLoop {
+            group,
+            sink: MockSink::new(),
+            frame: 0
+        }
+    }
+
+    pub fn group(&self) -> &ResourceGroup<DefaultImageKeysAPI, DefaultFontKeysAPI, FontBackend> {
+        &self.group
+    }
+
+    pub fn sink(&self) -> &DefaultMockSink {
+        &self.sink
+    }
+
+    // Frames completed so far.
+    pub fn frames(&self) -> usize {
+        self.frame
+    }
+
+    // Runs one frame: whatever `f` loads, measures or evicts is applied to the
+    // mock renderer once it returns. Images go first, though neither batch
+    // refers to keys in the other. Error counters are reset every frame.
+    pub fn frame<F, R>(&mut self, f: F) -> Result<(R, FrameReport), FrameError>
+    where
+        F: FnOnce(&mut ResourceGroup<DefaultImageKeysAPI, DefaultFontKeysAPI, FontBackend>) -> R
+    {
+        let result = f(&mut self.group);
+        let frame = self.frame;
+        self.frame += 1;
+
+        let image_updates = self.group.images.take_resource_updates();
+        let font_updates = self.group.fonts.take_resource_updates();
+        self.sink.apply(&image_updates).map_err(|(update, error)| FrameError {
+            frame,
+            update,
+            image_updates: true,
+            error
+        })?;
+        self.sink.apply(&font_updates).map_err(|(update, error)| FrameError {
+            frame,
+            update,
+            image_updates: false,
+            error
+        })?;
+
+        let report = FrameReport {
+            frame,
+            image_updates: image_updates.len(),
+            font_updates: font_updates.len(),
+            sink_keys: self.sink.key_counts(),
+            cache_keys: self.cache_keys(),
+            shaped_text_entries: self.group
+                .fonts
+                .borrow()
+                .iter_instances()
+                .map(|info| info.shaped_text_entries)
+                .sum(),
+            used_bytes: self.group.used_bytes(),
+            errors: self.group.reset_error_report()
+        };
+        Ok((result, report))
+    }
+
+    fn cache_keys(&self) -> KeyCounts {
+        let images = self.group.images.borrow();
+        let fonts = self.group.fonts.borrow();
+        KeyCounts {
+            images: images.iter_images().count(),
+            fonts: fonts.font_ids().count(),
+            font_instances: fonts.iter_instances().count()
+        }
+    }
+}
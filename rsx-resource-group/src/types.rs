@@ -11,25 +11,41 @@ specific language governing permissions and limitations under the License.
 
 use rsx_shared::traits::{TFontKeysAPI, TImageKeysAPI, TResourceGroup};
 
-use files::types::SharedFiles;
-use fonts::types::SharedFonts;
-use images::types::SharedImages;
+use files::types::{FileCache, SharedFiles};
+use fonts::error::Result as FontResult;
+use fonts::types::{BrowserFontBackend, FontCache, FontContext, FontId, FontMetricsTable, SharedFonts, TFontBackend, TFontFaceHost};
+use images::types::{ImageCache, ImageDecoding, SharedImages};
+use lookup::Strictness;
+use updates::display::DisplayProfile;
+use updates::types::{DefaultFontKeysAPI, DefaultImageKeysAPI};
+
+// Groups running in a browser, where the consumer decodes images and the host
+// registers font faces. The API is the same as for native groups.
+pub type BrowserResourceGroup<ImageKeysAPI, FontKeysAPI, Host> = ResourceGroup<ImageKeysAPI, FontKeysAPI, BrowserFontBackend<Host>>;
+
+// Groups handing out updates through the default keys APIs, e.g. as JSON.
+pub type DefaultResourceGroup = ResourceGroup<DefaultImageKeysAPI, DefaultFontKeysAPI>;
 
 #[derive(Debug, PartialEq)]
-pub struct ResourceGroup<ImageKeysAPI: TImageKeysAPI, FontKeysAPI: TFontKeysAPI> {
-    files: SharedFiles,
-    images: SharedImages<ImageKeysAPI>,
-    fonts: SharedFonts<FontKeysAPI>
+pub struct ResourceGroup<ImageKeysAPI: TImageKeysAPI, FontKeysAPI: TFontKeysAPI, FontBackend = FontContext> {
+    pub(crate) files: SharedFiles,
+    pub(crate) images: SharedImages<ImageKeysAPI>,
+    pub(crate) fonts: SharedFonts<FontKeysAPI, FontBackend>,
+    pub(crate) display_profile: DisplayProfile,
+    pub(crate) byte_quota: Option<usize>,
+    pub(crate) strictness: Strictness,
+    pub(crate) panic_on_missing: bool
 }
 
-impl<ImageKeysAPI, FontKeysAPI> TResourceGroup for ResourceGroup<ImageKeysAPI, FontKeysAPI>
+impl<ImageKeysAPI, FontKeysAPI, FontBackend> TResourceGroup for ResourceGroup<ImageKeysAPI, FontKeysAPI, FontBackend>
 where
     ImageKeysAPI: TImageKeysAPI + 'static,
-    FontKeysAPI: TFontKeysAPI + 'static
+    FontKeysAPI: TFontKeysAPI + 'static,
+    FontBackend: TFontBackend + 'static
 {
     type Files = SharedFiles;
     type Images = SharedImages<ImageKeysAPI>;
-    type Fonts = SharedFonts<FontKeysAPI>;
+    type Fonts = SharedFonts<FontKeysAPI, FontBackend>;
 
     fn files(&self) -> Self::Files {
         Self::Files::clone(&self.files)
@@ -44,21 +60,74 @@ where
     }
 }
 
-impl<ImageKeysAPI, FontKeysAPI> ResourceGroup<ImageKeysAPI, FontKeysAPI>
+impl<ImageKeysAPI, FontKeysAPI, FontBackend> ResourceGroup<ImageKeysAPI, FontKeysAPI, FontBackend>
 where
     ImageKeysAPI: TImageKeysAPI,
-    FontKeysAPI: TFontKeysAPI
+    FontKeysAPI: TFontKeysAPI,
+    FontBackend: TFontBackend
 {
     pub fn new<FileCache, ImagesCache, FontCache>(files: FileCache, images: ImagesCache, fonts: FontCache) -> Self
     where
         FileCache: Into<SharedFiles>,
         ImagesCache: Into<SharedImages<ImageKeysAPI>>,
-        FontCache: Into<SharedFonts<FontKeysAPI>>
+        FontCache: Into<SharedFonts<FontKeysAPI, FontBackend>>
     {
         ResourceGroup {
             files: files.into(),
             images: images.into(),
-            fonts: fonts.into()
+            fonts: fonts.into(),
+            display_profile: DisplayProfile::default(),
+            byte_quota: None,
+            strictness: Strictness::default(),
+            panic_on_missing: false
         }
     }
+
+    pub fn display_profile(&self) -> DisplayProfile {
+        self.display_profile
+    }
+
+    // Applies the profile to both caches, so that image density selection and
+    // font rasterization agree on the target display.
+    pub fn set_display_profile(&mut self, profile: DisplayProfile) {
+        self.display_profile = profile;
+        self.images
+            .borrow_mut()
+            .set_display_profile(profile);
+        self.fonts.borrow_mut().set_display_profile(profile);
+    }
+}
+
+impl DefaultResourceGroup {
+    // Creates the caches along with their keys APIs. Returns None if any of
+    // them couldn't be created.
+    pub fn with_default_apis() -> Option<Self> {
+        let files = FileCache::new().ok()?;
+        let images = ImageCache::new(DefaultImageKeysAPI::new(())).ok()?;
+        let fonts = FontCache::new(DefaultFontKeysAPI::new(())).ok()?;
+        Some(ResourceGroup::new(files, images, fonts))
+    }
+}
+
+impl<ImageKeysAPI, FontKeysAPI, Host> ResourceGroup<ImageKeysAPI, FontKeysAPI, BrowserFontBackend<Host>>
+where
+    ImageKeysAPI: TImageKeysAPI,
+    FontKeysAPI: TFontKeysAPI,
+    Host: TFontFaceHost
+{
+    pub fn new_browser<FileCache>(files: FileCache, mut images: ImageCache<ImageKeysAPI>, fonts: FontCache<FontKeysAPI, BrowserFontBackend<Host>>) -> Self
+    where
+        FileCache: Into<SharedFiles>
+    {
+        images.set_decoding(ImageDecoding::External);
+        ResourceGroup::new(files, images, fonts)
+    }
+
+    // Faces become measurable once the consumer supplies their metrics.
+    pub fn set_measured_metrics(&self, font_id: FontId, table: FontMetricsTable) -> FontResult<()> {
+        self.fonts
+            .borrow_mut()
+            .backend_mut()
+            .set_measured_metrics(font_id, table)
+    }
 }
@@ -0,0 +1,64 @@
+/*
+Copyright 2016 Mozilla
+Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+this file except in compliance with the License. You may obtain a copy of the
+License at http://www.apache.org/licenses/LICENSE-2.0
+Unless required by applicable law or agreed to in writing, software distributed
+under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+CONDITIONS OF ANY KIND, either express or implied. See the License for the
+specific language governing permissions and limitations under the License.
+*/
+
+use rsx_shared::traits::{TFontKeysAPI, TImageKeysAPI};
+
+use fonts::types::{FontId, FontInstanceId, TFontBackend};
+use images::types::ImageId;
+use types::ResourceGroup;
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct KeyBalanceReport<ImageKey, FontKey, FontInstanceKey> {
+    pub leaked_images: Vec<(ImageId, ImageKey)>,
+    pub leaked_fonts: Vec<(FontId, FontKey)>,
+    pub leaked_font_instances: Vec<(FontInstanceId, FontInstanceKey)>,
+    pub unknown_image_deletes: Vec<ImageKey>,
+    pub unknown_font_deletes: Vec<FontKey>,
+    pub unknown_font_instance_deletes: Vec<FontInstanceKey>
+}
+
+impl<ImageKey, FontKey, FontInstanceKey> KeyBalanceReport<ImageKey, FontKey, FontInstanceKey> {
+    pub fn is_balanced(&self) -> bool {
+        self.leaked_images.is_empty() && self.leaked_fonts.is_empty() && self.leaked_font_instances.is_empty()
+            && self.unknown_image_deletes.is_empty() && self.unknown_font_deletes.is_empty()
+            && self.unknown_font_instance_deletes.is_empty()
+    }
+}
+
+impl<ImageKeysAPI, FontKeysAPI, FontBackend> ResourceGroup<ImageKeysAPI, FontKeysAPI, FontBackend>
+where
+    ImageKeysAPI: TImageKeysAPI,
+    FontKeysAPI: TFontKeysAPI,
+    FontBackend: TFontBackend
+{
+    // Compares the keys allocated through the keys APIs against the delete
+    // updates emitted, reporting keys which are no longer referenced by any
+    // cache entry but were never deleted, and deletes of unknown keys.
+    pub fn verify_key_balance(&self) -> KeyBalanceReport<ImageKeysAPI::ImageKey, FontKeysAPI::FontKey, FontKeysAPI::FontInstanceKey>
+    where
+        ImageKeysAPI::ImageKey: Copy + PartialEq,
+        FontKeysAPI::FontKey: PartialEq,
+        FontKeysAPI::FontInstanceKey: PartialEq
+    {
+        let images = self.images.borrow();
+        let fonts = self.fonts.borrow();
+        let (unknown_font_deletes, unknown_font_instance_deletes) = fonts.unknown_deleted_keys();
+
+        KeyBalanceReport {
+            leaked_images: images.leaked_keys(),
+            leaked_fonts: fonts.leaked_font_keys(),
+            leaked_font_instances: fonts.leaked_font_instance_keys(),
+            unknown_image_deletes: images.unknown_deleted_keys().to_vec(),
+            unknown_font_deletes: unknown_font_deletes.to_vec(),
+            unknown_font_instance_deletes: unknown_font_instance_deletes.to_vec()
+        }
+    }
+}
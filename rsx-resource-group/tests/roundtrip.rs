@@ -0,0 +1,84 @@
+/*
+Copyright 2016 Mozilla
+Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+this file except in compliance with the License. You may obtain a copy of the
+License at http://www.apache.org/licenses/LICENSE-2.0
+Unless required by applicable law or agreed to in writing, software distributed
+under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+CONDITIONS OF ANY KIND, either express or implied. See the License for the
+specific language governing permissions and limitations under the License.
+*/
+
+#[macro_use]
+extern crate proptest;
+extern crate rsx_resource_updates;
+extern crate rsx_resources;
+extern crate rsx_shared;
+extern crate serde_json;
+
+use proptest::collection::vec;
+use proptest::test_runner::TestCaseError;
+
+use rsx_resource_updates::types::*;
+use rsx_resources::fonts::placeholder::PLACEHOLDER_FAMILY;
+use rsx_resources::fonts::types::*;
+use rsx_resources::generators::*;
+use rsx_resources::images::types::*;
+use rsx_resources::test_harness::*;
+use rsx_shared::traits::*;
+
+type DefaultUpdates = ResourceUpdates<DefaultImageKey, DefaultFontKey, DefaultFontInstanceKey>;
+
+// Updates only refer to keys added before them, and read back the same from JSON.
+fn check_updates(updates: &DefaultUpdates, sink: &mut DefaultMockSink) -> Result<(), TestCaseError> {
+    prop_assert!(sink.apply(updates).is_ok());
+    let mut json = Vec::new();
+    prop_assert!(updates.write_json_to(&mut json).is_ok());
+    let parsed: Vec<Update<DefaultImageKey, DefaultFontKey, DefaultFontInstanceKey>> = serde_json::from_slice(&json).unwrap();
+    prop_assert_eq!(&parsed, &updates.updates);
+    Ok(())
+}
+
+proptest! {
+    #[test]
+    fn test_roundtrip_images(generated in vec(images(16), 1..4)) {
+        let mut images = SharedImages::from(ImageCache::new(DefaultImageKeysAPI::new(())).unwrap());
+        let mut sink = DefaultMockSink::new();
+        for (index, image) in generated.iter().enumerate() {
+            let src = format!("Generated {}", index);
+            let encoded = EncodedImage::from_bytes(image.encode_png()).unwrap();
+            prop_assert_eq!(EncodedImage::get_dimensions(ImageEncodingFormat::PNG, &image.encode_png()).unwrap(), image.size);
+            prop_assert!(images.borrow_mut().add_image(ImageId::new(&src), &encoded).is_ok());
+
+            let cached = images.borrow().get_image(&src).unwrap();
+            prop_assert_eq!((cached.width(), cached.height()), image.size);
+            check_updates(&images.take_resource_updates(), &mut sink)?;
+            prop_assert!(sink.has_image(cached.external_key()));
+        }
+    }
+
+    #[test]
+    fn test_roundtrip_fonts(generated in fonts()) {
+        let mut fonts = SharedFonts::from(FontCache::new(DefaultFontKeysAPI::new(())).unwrap());
+        let mut sink = DefaultMockSink::new();
+        let font_id = FontId::new("Generated");
+        prop_assert!(fonts.borrow_mut().add_raw(font_id, generated.bytes(), 0).is_ok());
+
+        let font_instance_id = FontInstanceId::from_family_str(PLACEHOLDER_FAMILY, generated.size, generated.dpi);
+        let instance = fonts.borrow_mut().get_or_insert_font(font_instance_id).unwrap();
+        prop_assert_eq!(instance.font_id(), font_id);
+        let glyphs = fonts.borrow().shape_text_h(&instance, &generated.text).unwrap();
+        prop_assert_eq!(glyphs.glyphs().len(), generated.text.chars().count());
+        prop_assert!(glyphs.width_64() >= 0);
+
+        check_updates(&fonts.take_resource_updates(), &mut sink)?;
+        prop_assert!(sink.has_font(instance.external_key()));
+        prop_assert!(sink.has_font_instance(instance.external_instance_key()));
+
+        // Evicting the font deletes every key it added.
+        fonts.borrow_mut().set_font_priority(font_id, ResourcePriority::Discardable);
+        prop_assert_eq!(fonts.borrow_mut().evict_bytes(ResourcePriority::Discardable, 1).unwrap().len(), 1);
+        check_updates(&fonts.take_resource_updates(), &mut sink)?;
+        prop_assert_eq!(sink.key_counts(), KeyCounts::default());
+    }
+}
@@ -13,13 +13,30 @@ extern crate base64_util;
 extern crate rsx_resource_updates;
 extern crate rsx_resources;
 extern crate rsx_shared;
+extern crate serde_json;
 
 use std::rc::Rc;
+use std::sync::Arc;
 
+use rsx_resource_updates::codes;
+use rsx_resource_updates::namespace::*;
+use rsx_resource_updates::pixels;
+use rsx_resource_updates::stats::IdStats;
 use rsx_resource_updates::types::*;
 use rsx_resources::files::types::*;
+use rsx_resources::files::error::FileError;
+use rsx_resources::fonts::error::{FontError, Result as FontResult, ShapingError};
 use rsx_resources::fonts::types::*;
+use rsx_resources::images::error::ImageError;
 use rsx_resources::images::types::*;
+use rsx_resources::digest::*;
+use rsx_resources::lookup::*;
+use rsx_resources::persistence::*;
+use rsx_resources::preload::*;
+use rsx_resources::quota::*;
+use rsx_resources::stats::*;
+use rsx_resources::test_harness::*;
+use rsx_resources::types::*;
 use rsx_shared::traits::*;
 
 type ImageKeysAPI = DefaultImageKeysAPI;
@@ -38,6 +55,25 @@ fn test_encode_decode() {
     assert_eq!(&decoded[..], &bytes[..]);
 }
 
+#[test]
+fn test_fuzz_entry_points() {
+    let png = include_bytes!("fixtures/Quantum.png");
+    let font = include_bytes!("fixtures/FreeSans.ttf");
+    assert!(rsx_resources::images::fuzz::decoded_image(png).is_ok());
+    assert!(rsx_resources::fonts::fuzz::family_name(font, 0).is_ok());
+
+    // Truncated and empty inputs fail without panicking.
+    for len in &[0, 1, 7, 33, png.len() / 2] {
+        assert!(rsx_resources::images::fuzz::decoded_image(&png[..*len]).is_err());
+    }
+    for len in &[0, 4, 12, 100] {
+        assert!(rsx_resources::fonts::fuzz::face_info(&font[..*len], 0).is_none());
+        assert!(rsx_resources::fonts::fuzz::family_name(&font[..*len], 0).is_err());
+    }
+    assert_eq!(base64_util::from_data_uri("data:").unwrap(), Vec::<u8>::new());
+    assert!(rsx_resources::images::fuzz::encoded_image_from_data_uri("data:").is_err());
+}
+
 #[test]
 fn test_image_cache_1() {
     let mut files_cache = FileCache::new().unwrap();
@@ -90,7 +126,7 @@ fn test_fonts_cache_1() {
 
     assert!(
         fonts_cache
-            .get_or_insert_font(FontInstanceId::from_family_str("FreeSans", 16, 72))
+            .get_or_insert_font(FontInstanceId::from_family_str("FreeSans", Pt(16), Dpi(72)))
             .is_none()
     );
 
@@ -112,11 +148,269 @@ fn test_fonts_cache_1() {
 
     assert!(
         fonts_cache
-            .get_or_insert_font(FontInstanceId::from_family_str("FreeSans", 16, 72))
+            .get_or_insert_font(FontInstanceId::from_family_str("FreeSans", Pt(16), Dpi(72)))
             .is_some()
     );
 }
 
+// Packs faces into a collection, relocating their table offsets.
+fn font_collection(faces: &[&[u8]]) -> Vec<u8> {
+    let mut bytes = b"ttcf\0\x01\0\0".to_vec();
+    bytes.extend_from_slice(&(faces.len() as u32).to_be_bytes());
+    let mut offset = 12 + faces.len() * 4;
+    let mut offsets = vec![];
+    for face in faces {
+        offsets.push(offset);
+        offset += (face.len() + 3) & !3;
+    }
+    for &offset in &offsets {
+        bytes.extend_from_slice(&(offset as u32).to_be_bytes());
+    }
+    for (face, &offset) in faces.iter().zip(&offsets) {
+        let mut face = face.to_vec();
+        let num_tables = u16::from_be_bytes([face[4], face[5]]) as usize;
+        for record in (0..num_tables).map(|i| 12 + i * 16 + 8) {
+            let table_offset = u32::from_be_bytes([face[record], face[record + 1], face[record + 2], face[record + 3]]);
+            face[record..record + 4].copy_from_slice(&(table_offset + offset as u32).to_be_bytes());
+        }
+        face.resize((face.len() + 3) & !3, 0);
+        bytes.extend_from_slice(&face);
+    }
+    bytes
+}
+
+#[test]
+fn test_fonts_cache_add_font_file() {
+    use std::fs;
+
+    let files = SharedFiles::from(FileCache::new().unwrap());
+    let mut fonts = FontCache::new(FontKeysAPI::new(())).unwrap();
+
+    let collection = font_collection(&[include_bytes!("fixtures/FiraMono-Regular.ttf"), include_bytes!("fixtures/FreeSans.ttf")]);
+    let path = std::env::temp_dir().join(format!("rsx-font-collection-{}.ttc", std::process::id()));
+    fs::write(&path, collection).unwrap();
+    let ids = fonts.add_font_file(&files, &path).unwrap();
+    assert!(files.borrow().get_file(&path).is_ok());
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(ids, vec![FontId::new("Fira Mono"), FontId::new("FreeSans")]);
+    assert_eq!(fonts.get_face_index(ids[1]).unwrap(), 1);
+    assert_eq!(fonts.get_family_name(ids[1]).unwrap(), "FreeSans");
+    assert_eq!(fonts.font_descriptor(ids[0]), Some(&FontDescriptor::new("Fira Mono", 400, FontStyle::Normal)));
+    assert_eq!(fonts.match_font("FreeSans", 400, FontStyle::Normal), Some(ids[1]));
+    assert!(fonts.get_or_insert_font(FontInstanceId::from_family_str("Fira Mono", Pt(16), Dpi(72))).is_some());
+
+    let sans_path = "tests/fixtures/FreeSans.ttf";
+    assert!(fonts.add_font_file(&files, sans_path).is_err());
+    assert!(fonts.add_font_file_face(&files, sans_path, 1).is_err());
+    assert!(fonts.add_font_file(&files, "tests/fixtures/Missing.ttf").is_err());
+
+    let mut fonts = FontCache::new(FontKeysAPI::new(())).unwrap();
+    assert_eq!(fonts.add_font_file_face(&files, sans_path, 0).unwrap(), FontId::new("FreeSans"));
+}
+
+#[test]
+fn test_fonts_cache_add_font_file_same_family() {
+    use std::fs;
+
+    let files = SharedFiles::from(FileCache::new().unwrap());
+    let mut fonts = FontCache::new(FontKeysAPI::new(())).unwrap();
+
+    let sans = include_bytes!("fixtures/FreeSans.ttf");
+    let collection = font_collection(&[sans, sans]);
+    let path = std::env::temp_dir().join(format!("rsx-font-family-{}.ttc", std::process::id()));
+    fs::write(&path, collection).unwrap();
+    let ids = fonts.add_font_file(&files, &path).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(ids, vec![FontId::new("FreeSans #0"), FontId::new("FreeSans #1")]);
+    assert_eq!(fonts.get_face_index(ids[0]).unwrap(), 0);
+    assert_eq!(fonts.get_face_index(ids[1]).unwrap(), 1);
+    assert_eq!(fonts.get_family_name(ids[1]).unwrap(), "FreeSans");
+    assert_eq!(fonts.font_ids().count(), 2);
+
+    let mut fonts = FontCache::new(FontKeysAPI::new(())).unwrap();
+    assert_eq!(fonts.add_font_file_face(&files, "tests/fixtures/FreeSans.ttf", 0).unwrap(), FontId::new("FreeSans"));
+
+    let collection = font_collection(&[include_bytes!("fixtures/FiraMono-Regular.ttf"), sans]);
+    let path = std::env::temp_dir().join(format!("rsx-font-rollback-{}.ttc", std::process::id()));
+    fs::write(&path, collection).unwrap();
+    assert!(fonts.add_font_file(&files, &path).is_err());
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(fonts.font_ids().collect::<Vec<_>>(), vec![FontId::new("FreeSans")]);
+    assert!(fonts.get_family_name(FontId::new("Fira Mono")).is_err());
+}
+
+#[test]
+fn test_fonts_cache_remove_default_font() {
+    let mut fonts = FontCache::new(FontKeysAPI::new(())).unwrap();
+    let sans = FontId::new("FreeSans");
+    let mono = FontId::new("Fira Mono");
+    assert!(fonts.add_raw(sans, include_bytes!("fixtures/FreeSans.ttf").to_vec(), 0).is_ok());
+    assert!(fonts.add_raw(mono, include_bytes!("fixtures/FiraMono-Regular.ttf").to_vec(), 0).is_ok());
+    assert_eq!(fonts.get_default_font().unwrap().font_id(), sans);
+
+    // Removing the default font's face moves the default to a font still there.
+    fonts.set_font_priority(sans, ResourcePriority::Discardable);
+    assert_eq!(fonts.evict_bytes(ResourcePriority::Discardable, 1).unwrap(), vec![(sans, include_bytes!("fixtures/FreeSans.ttf").len())]);
+    assert_eq!(fonts.get_default_font().unwrap().font_id(), mono);
+    assert_eq!(fonts.get_default_font_with_size(Pt(24)).unwrap().font_id(), mono);
+
+    fonts.set_font_priority(mono, ResourcePriority::Discardable);
+    assert_eq!(fonts.evict_bytes(ResourcePriority::Discardable, 1).unwrap().len(), 1);
+    assert!(fonts.get_default_font().is_none());
+    assert!(fonts.get_default_font_with_size(Pt(24)).is_none());
+}
+
+#[test]
+fn test_fonts_woff() {
+    use rsx_resources::fonts::sfnt;
+    use rsx_resources::fonts::woff::{self, FontFormat};
+
+    let ttf = &include_bytes!("fixtures/FiraMono-Regular.ttf")[..];
+    let packed = &include_bytes!("fixtures/FiraMono-Regular.woff")[..];
+    assert_eq!(FontFormat::sniff(packed), FontFormat::Woff);
+    assert_eq!(FontFormat::sniff(b"wOF2\0\0"), FontFormat::Woff2);
+    assert_eq!(FontFormat::sniff(ttf), FontFormat::Sfnt);
+    assert_eq!(woff::to_sfnt(ttf).unwrap(), None);
+
+    // Tables are laid out again in tag order, so only the contents match.
+    let unpacked = woff::to_sfnt(packed).unwrap().unwrap();
+    assert_eq!(unpacked.len(), ttf.len());
+    assert_eq!(sfnt::face_info(&unpacked, 0), sfnt::face_info(ttf, 0));
+    assert_eq!(woff::to_sfnt(&packed[..100]).unwrap_err().code(), codes::FONT_WOFF_DECODE);
+
+    let mut fonts = FontCache::new(FontKeysAPI::new(())).unwrap();
+    let font_id = FontId::new("Fira Mono");
+    assert!(fonts.add_raw(font_id, packed.to_vec(), 0).is_ok());
+    assert_eq!(*fonts.get_bytes(font_id).unwrap(), unpacked);
+    let instance = fonts.get_font_with_px_size("Fira Mono", Px(16)).unwrap();
+    assert_eq!(
+        fonts.shape_text_h(&instance, "Hello").unwrap().width_64(),
+        fonts.shape_text_h(&instance, "World").unwrap().width_64()
+    );
+
+    // Data URIs of packed fonts are dropped along with the packed bytes.
+    let encoded = EncodedFont::from_data_uri(base64_util::to_font_data_uri(packed)).unwrap();
+    assert_eq!(**encoded.bytes().unwrap(), unpacked);
+    assert!(encoded.data_uri().is_none());
+}
+
+#[test]
+fn test_fonts_thread() {
+    use std::thread;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<FontThread<FontKeysAPI>>();
+
+    let fonts = FontThread::spawn(|| FontCache::new(FontKeysAPI::new(()))).unwrap();
+    let bytes = include_bytes!("fixtures/FreeSans.ttf").to_vec();
+    assert!(fonts.post(move |cache| cache.add_raw(FontId::new("FreeSans"), bytes, 0).unwrap()).is_ok());
+
+    let handles: Vec<_> = (0..2)
+        .map(|_| {
+            let fonts = fonts.clone();
+            thread::spawn(move || {
+                fonts
+                    .run(|cache| {
+                        let instance = cache.get_font_with_px_size("FreeSans", Px(16)).unwrap();
+                        cache.shape_text_h(&instance, "Hello").unwrap().width_64()
+                    })
+                    .unwrap()
+            })
+        })
+        .collect();
+    let widths: Vec<_> = handles.into_iter().map(|handle| handle.join().unwrap()).collect();
+    assert_eq!(widths[0], widths[1]);
+    assert_eq!(fonts.run(|cache| cache.get_font_with_px_size("FreeSans", Px(16)).is_some()).unwrap(), true);
+
+    // Shaped glyphs are sent back as owned stores, usable on the receiving
+    // thread as is and turned back into the store they were made from.
+    let shape = |fonts: &FontThread<FontKeysAPI>| {
+        fonts
+            .run(|cache| {
+                let instance = cache.get_font_with_px_size("FreeSans", Px(16)).unwrap();
+                let glyphs = cache.shape_text_h(&instance, "Hello").unwrap();
+                (glyphs.to_send(), glyphs.glyphs().to_vec(), glyphs.clusters().map(|clusters| clusters.to_vec()))
+            })
+            .unwrap()
+    };
+    let handle = {
+        let fonts = fonts.clone();
+        thread::spawn(move || {
+            let (sent, glyphs, clusters) = shape(&fonts);
+            assert_eq!(sent.glyphs(), &glyphs[..]);
+            assert_eq!(sent.clusters().map(|clusters| clusters.to_vec()), clusters);
+            let store = sent.clone().into_store();
+            assert_eq!(store.glyphs(), sent.glyphs());
+            assert_eq!(store.source().map(|source| source.glyph_count()), Some(5));
+            sent
+        })
+    };
+    let sent = handle.join().unwrap();
+    assert_eq!(sent.width_64(), widths[0]);
+    assert_eq!(sent.text_len(), 5);
+    assert_eq!(shape(&fonts).0.into_store(), sent.into_store());
+
+    let failed = FontThread::<FontKeysAPI>::spawn(|| Err(FontError::ThreadUnavailable));
+    assert_eq!(failed.err().unwrap().code(), codes::FONT_THREAD_UNAVAILABLE);
+}
+
+#[test]
+#[cfg(feature = "woff2")]
+fn test_fonts_woff2() {
+    use rsx_resources::fonts::woff;
+
+    let unpacked = woff::to_sfnt(include_bytes!("fixtures/FiraMono-Regular.woff")).unwrap();
+    assert_eq!(woff::to_sfnt(include_bytes!("fixtures/FiraMono-Regular.woff2")).unwrap(), unpacked);
+}
+
+#[test]
+fn test_fonts_woff_forged_lengths() {
+    use rsx_resources::fonts::woff;
+
+    // The compressed `GDEF` table claims to inflate to almost 4GB, which isn't
+    // reserved upfront.
+    let mut bytes = include_bytes!("fixtures/FiraMono-Regular.woff").to_vec();
+    bytes[76..80].copy_from_slice(&[0xff, 0xff, 0xff, 0x00]);
+    let err = woff::to_sfnt(&bytes).unwrap_err();
+    assert_eq!(err.code(), codes::FONT_WOFF_DECODE);
+
+    // Tables are no larger in total than the header's unpacked size.
+    let mut bytes = include_bytes!("fixtures/FiraMono-Regular.woff").to_vec();
+    bytes[16..20].copy_from_slice(&[0, 0, 0x10, 0]);
+    assert_eq!(woff::to_sfnt(&bytes).unwrap_err().code(), codes::FONT_WOFF_DECODE);
+
+    // Fonts can't have more than 4095 tables.
+    let mut bytes = include_bytes!("fixtures/FiraMono-Regular.woff").to_vec();
+    bytes[12..14].copy_from_slice(&[0xff, 0xff]);
+    assert_eq!(woff::to_sfnt(&bytes).unwrap_err().code(), codes::FONT_WOFF_DECODE);
+}
+
+#[test]
+#[cfg(feature = "woff2")]
+fn test_fonts_woff2_forged_lengths() {
+    use rsx_resources::fonts::woff;
+
+    let mut bytes = include_bytes!("fixtures/FiraMono-Regular.woff2").to_vec();
+    bytes[16..20].copy_from_slice(&[0, 0, 0x10, 0]);
+    assert_eq!(woff::to_sfnt(&bytes).unwrap_err().code(), codes::FONT_WOFF_DECODE);
+
+    let mut bytes = include_bytes!("fixtures/FiraMono-Regular.woff2").to_vec();
+    bytes[12..14].copy_from_slice(&[0x10, 0]);
+    assert_eq!(woff::to_sfnt(&bytes).unwrap_err().code(), codes::FONT_WOFF_DECODE);
+}
+
+#[test]
+#[cfg(not(feature = "woff2"))]
+fn test_fonts_woff2_unsupported() {
+    use rsx_resources::fonts::woff;
+
+    let err = woff::to_sfnt(include_bytes!("fixtures/FiraMono-Regular.woff2")).unwrap_err();
+    assert_eq!(err.code(), codes::FONT_WOFF_UNSUPPORTED);
+}
+
 #[test]
 fn test_fonts_cache_2() {
     let mut files_cache = FileCache::new().unwrap();
@@ -137,12 +431,12 @@ fn test_fonts_cache_2() {
 
     assert!(
         fonts_cache
-            .get_or_insert_font(FontInstanceId::from_family_str("FreeSans", 16, 72))
+            .get_or_insert_font(FontInstanceId::from_family_str("FreeSans", Pt(16), Dpi(72)))
             .is_none()
     );
     assert!(
         fonts_cache
-            .get_or_insert_font(FontInstanceId::from_family_str("Fira Mono", 16, 72))
+            .get_or_insert_font(FontInstanceId::from_family_str("Fira Mono", Pt(16), Dpi(72)))
             .is_none()
     );
 
@@ -180,12 +474,4174 @@ fn test_fonts_cache_2() {
 
     assert!(
         fonts_cache
-            .get_or_insert_font(FontInstanceId::from_family_str("FreeSans", 16, 72))
+            .get_or_insert_font(FontInstanceId::from_family_str("FreeSans", Pt(16), Dpi(72)))
             .is_some()
     );
     assert!(
         fonts_cache
-            .get_or_insert_font(FontInstanceId::from_family_str("Fira Mono", 16, 72))
+            .get_or_insert_font(FontInstanceId::from_family_str("Fira Mono", Pt(16), Dpi(72)))
+            .is_some()
+    );
+}
+
+#[test]
+fn test_fonts_cache_pre_register_instances() {
+    let mut files_cache = FileCache::new().unwrap();
+
+    let font_path = "tests/fixtures/FreeSans.ttf";
+    assert!(files_cache.add_file(font_path).is_ok());
+
+    let font_keys = FontKeysAPI::new(());
+    let mut fonts = SharedFonts::from(FontCache::new(font_keys).unwrap());
+
+    let font_id = FontId::new("FreeSans");
+    let font_bytes = files_cache.get_file(font_path).unwrap();
+
+    assert!(
+        fonts
+            .borrow_mut()
+            .pre_register_instances("FreeSans", &[Pt(14), Pt(20), Pt(32)], Dpi(96))
+            .is_none()
+    );
+
+    assert!(
+        fonts
+            .borrow_mut()
+            .add_raw(font_id, Rc::clone(&font_bytes), 0)
+            .is_ok()
+    );
+    assert_eq!(fonts.take_resource_updates().len(), 2);
+
+    let instances = fonts
+        .borrow_mut()
+        .pre_register_instances("FreeSans", &[Pt(14), Pt(20), Pt(32)], Dpi(96))
+        .unwrap();
+    assert_eq!(instances.len(), 3);
+    assert_eq!(instances[0].size(), 14);
+    assert_eq!(instances[1].size(), 20);
+    assert_eq!(instances[2].size(), 32);
+    assert_eq!(fonts.take_resource_updates().len(), 3);
+
+    assert!(
+        fonts
+            .borrow_mut()
+            .pre_register_instances("FreeSans", &[Pt(14), Pt(20), Pt(32)], Dpi(96))
             .is_some()
     );
+    assert!(fonts.take_resource_updates().is_empty());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_shared_memory_resources() {
+    use std::fs::{self, File};
+    use std::io::Write;
+
+    // Stands in for a memfd, with the font placed after the image to check offsets.
+    let image_bytes = include_bytes!("fixtures/Quantum.png");
+    let font_bytes = include_bytes!("fixtures/FreeSans.ttf");
+    let path = std::env::temp_dir().join(format!("rsx-shared-memory-{}", std::process::id()));
+    let mut file = File::create(&path).unwrap();
+    file.write_all(image_bytes).unwrap();
+    file.write_all(font_bytes).unwrap();
+    let image_len = image_bytes.len() as u64;
+    let font_len = font_bytes.len() as u64;
+
+    let image_memory = SharedMemory::from_file(SharedMemoryKind::Memfd, File::open(&path).unwrap(), 0, image_len);
+    let font_memory = SharedMemory::from_file(SharedMemoryKind::Memfd, File::open(&path).unwrap(), image_len, font_len);
+    let mask_memory = SharedMemory::from_file(SharedMemoryKind::Memfd, File::open(&path).unwrap(), 0, image_len);
+    fs::remove_file(&path).unwrap();
+    let image_descriptor = image_memory.descriptor();
+    let mask_descriptor = mask_memory.descriptor();
+    let font_descriptor = font_memory.descriptor();
+
+    let (image_keys, image_receiver) = ImageKeysAPI::new(()).into_stream();
+    let mut images = ImageCache::new(image_keys).unwrap();
+    let encoded = EncodedImage::from_shared_memory(image_memory).unwrap();
+    assert_eq!(encoded.bytes().unwrap().len(), image_bytes.len());
+    assert!(
+        images
+            .add_shared_image(ImageId::new("Quantum"), &encoded, ImageOrientation::Identity)
+            .is_ok()
+    );
+    assert_eq!(images.get_image("Quantum").unwrap().width(), 512);
+    match image_receiver.try_recv() {
+        Ok(Update::AddSharedImage { memory, ref format, .. }) => {
+            assert_eq!(memory, image_descriptor);
+            assert_eq!(format, "png");
+        }
+        _ => panic!("Expected a shared image update")
+    }
+
+    // So are masks, the renderer taking their coverage from the mapped bytes.
+    let encoded = EncodedImage::from_shared_memory(mask_memory).unwrap();
+    assert!(
+        images
+            .add_shared_image_with_options(ImageId::new("Mask"), &encoded, ImageOptions::mask())
+            .is_ok()
+    );
+    assert_eq!(images.get_image("Mask").unwrap().format(), ImagePixelFormat::Gray(8));
+    match image_receiver.try_recv() {
+        Ok(Update::AddSharedImageMask { memory, ref format, .. }) => {
+            assert_eq!(memory, mask_descriptor);
+            assert_eq!(format, "png");
+        }
+        _ => panic!("Expected a shared image mask update")
+    }
+
+    // Images not backed by shared memory are sent as usual.
+    let encoded = EncodedImage::from_bytes(Rc::new(image_bytes.to_vec())).unwrap();
+    assert!(
+        images
+            .add_shared_image(ImageId::new("Copy"), &encoded, ImageOrientation::Identity)
+            .is_ok()
+    );
+    match image_receiver.try_recv() {
+        Ok(Update::AddImage { .. }) => {}
+        _ => panic!("Expected an image update")
+    }
+
+    let (font_keys, font_receiver) = FontKeysAPI::new(()).into_stream();
+    let mut fonts = FontCache::new(font_keys).unwrap();
+    let encoded = EncodedFont::from_shared_memory(font_memory).unwrap();
+    assert!(fonts.add_shared_font(FontId::new("FreeSans"), &encoded, 0).is_ok());
+    assert_eq!(fonts.get_family_name(FontId::new("FreeSans")).unwrap(), "FreeSans");
+    match font_receiver.try_recv() {
+        Ok(Update::AddSharedFont { memory, .. }) => assert_eq!(memory, font_descriptor),
+        _ => panic!("Expected a shared font update")
+    }
+}
+
+#[test]
+fn test_image_cache_orientation() {
+    let image_keys = ImageKeysAPI::new(());
+    let mut images = SharedImages::from(ImageCache::new(image_keys).unwrap());
+
+    let image_bytes = Rc::new(include_bytes!("fixtures/Quantum.png").to_vec());
+    let encoded = EncodedImage::from_bytes(image_bytes).unwrap();
+
+    assert!(
+        images
+            .borrow_mut()
+            .add_image_with_orientation(
+                ImageId::new("Quantum"),
+                &encoded,
+                ImageOrientation::Rotate90
+            )
+            .is_ok()
+    );
+
+    let image = images.get_image("Quantum").unwrap();
+    assert_eq!(image.width(), 512);
+    assert_eq!(image.height(), 529);
+    assert_eq!(image.display_width(), 529);
+    assert_eq!(image.display_height(), 512);
+    assert_eq!(image.orientation(), ImageOrientation::Rotate90);
+
+    let dimensions = images.measure_image("Quantum").unwrap();
+    assert_eq!(dimensions.width(), 529);
+    assert_eq!(dimensions.height(), 512);
+
+    let baked = image.bake();
+    let pixels = image.pixels();
+    assert_eq!(baked.size, (529, 512));
+    assert_eq!(baked.pixels.len(), pixels.len());
+    assert_eq!(
+        &baked.pixels[0..4],
+        &pixels[(528 * 512) * 4..(528 * 512) * 4 + 4]
+    );
+    assert_eq!(&baked.pixels[528 * 4..528 * 4 + 4], &pixels[0..4]);
+
+    match images.take_resource_updates().updates[0] {
+        Update::AddImage { orientation, .. } => assert_eq!(orientation, ImageOrientation::Rotate90),
+        _ => panic!("Expected an image update")
+    }
+}
+
+#[test]
+fn test_resource_group_preload() {
+    let files = FileCache::new().unwrap();
+    let images = ImageCache::new(ImageKeysAPI::new(())).unwrap();
+    let fonts = FontCache::new(FontKeysAPI::new(())).unwrap();
+    let group = ResourceGroup::new(files, images, fonts);
+
+    let references = vec![
+        AssetReference::Image {
+            src: "tests/fixtures/Quantum.png".to_string()
+        },
+        AssetReference::Image {
+            src: "tests/fixtures/Missing.png".to_string()
+        },
+        AssetReference::Font {
+            family: "FreeSans".to_string(),
+            src: Some("tests/fixtures/FreeSans.ttf".to_string()),
+            face_index: 0,
+            sizes: vec![Pt(14), Pt(20)],
+            dpi: Dpi(96)
+        },
+        AssetReference::Font {
+            family: "Fira Mono".to_string(),
+            src: None,
+            face_index: 0,
+            sizes: vec![Pt(14)],
+            dpi: Dpi(96)
+        }
+    ];
+
+    let report = group.preload(&references);
+    assert!(!report.is_complete());
+    assert_eq!(report.resolved, 2);
+    assert_eq!(
+        report.unresolved,
+        vec![
+            UnresolvedReference {
+                reference: references[1].clone(),
+                error: PreloadError::FileNotLoaded
+            },
+            UnresolvedReference {
+                reference: references[3].clone(),
+                error: PreloadError::FamilyNotFound
+            }
+        ]
+    );
+
+    assert!(group.images().get_image("tests/fixtures/Quantum.png").is_some());
+    assert_eq!(group.fonts().take_resource_updates().len(), 4);
+
+    let report = group.preload(&references[..1]);
+    assert!(report.is_complete());
+    assert_eq!(report.resolved, 1);
+}
+
+#[test]
+fn test_resource_group_preload_family_name() {
+    let files = FileCache::new().unwrap();
+    let images = ImageCache::new(ImageKeysAPI::new(())).unwrap();
+    let fonts = FontCache::new(FontKeysAPI::new(())).unwrap();
+    let group = ResourceGroup::new(files, images, fonts);
+
+    // Instances are registered under the family the face itself names.
+    let references = vec![
+        AssetReference::Font {
+            family: "Free Sans".to_string(),
+            src: Some("tests/fixtures/FreeSans.ttf".to_string()),
+            face_index: 0,
+            sizes: vec![Pt(14)],
+            dpi: Dpi(96)
+        }
+    ];
+    assert!(group.preload(&references).is_complete());
+    assert!(group.fonts().borrow().font_ids().any(|id| id == FontId::new("Free Sans")));
+    let instance_id = FontInstanceId::from_family_str("FreeSans", Pt(14), Dpi(96));
+    assert!(group.fonts().borrow().get_font_instance(instance_id).is_some());
+    assert_eq!(group.fonts().take_resource_updates().len(), 3);
+}
+
+#[test]
+fn test_cache_origins() {
+    let image_keys = ImageKeysAPI::new(());
+    let mut images = SharedImages::from(ImageCache::new(image_keys).unwrap());
+
+    let image_bytes = Rc::new(include_bytes!("fixtures/Quantum.png").to_vec());
+    let image_id_1 = ImageId::with_origin("https://a.example", "Quantum.png");
+    let image_id_2 = ImageId::with_origin("https://b.example", "Quantum.png");
+    assert_ne!(image_id_1, image_id_2);
+    assert_ne!(image_id_1, ImageId::new("Quantum.png"));
+
+    assert!(
+        images
+            .borrow_mut()
+            .add_raw(image_id_1, Rc::clone(&image_bytes))
+            .is_ok()
+    );
+    assert!(
+        images
+            .borrow_mut()
+            .add_raw(image_id_2, Rc::clone(&image_bytes))
+            .is_ok()
+    );
+    assert!(images.get_image("Quantum.png").is_none());
+    assert_eq!(images.take_resource_updates().len(), 2);
+
+    assert_eq!(
+        images.borrow().ids_for_origin("https://a.example"),
+        vec![image_id_1]
+    );
+    assert_eq!(
+        images
+            .borrow_mut()
+            .evict_origin("https://a.example")
+            .unwrap(),
+        1
+    );
+    assert!(images.borrow().get_image_for_id(image_id_1).is_none());
+    assert!(images.borrow().get_image_for_id(image_id_2).is_some());
+
+    match images.take_resource_updates().updates[..] {
+        [Update::DeleteImage { .. }] => {}
+        _ => panic!("Expected an image deletion")
+    }
+
+    let font_keys = FontKeysAPI::new(());
+    let mut fonts = SharedFonts::from(FontCache::new(font_keys).unwrap());
+
+    let font_bytes = Rc::new(include_bytes!("fixtures/FreeSans.ttf").to_vec());
+    let font_id = FontId::with_origin("https://a.example", "FreeSans");
+
+    assert!(
+        fonts
+            .borrow_mut()
+            .add_raw(font_id, Rc::clone(&font_bytes), 0)
+            .is_ok()
+    );
+    assert!(fonts.get_font_with_size("FreeSans", 20).is_some());
+    assert_eq!(fonts.take_resource_updates().len(), 3);
+
+    assert!(
+        fonts
+            .borrow()
+            .ids_for_origin("https://b.example")
+            .is_empty()
+    );
+    assert_eq!(
+        fonts
+            .borrow_mut()
+            .evict_origin("https://a.example")
+            .unwrap(),
+        1
+    );
+    assert!(fonts.get_font("FreeSans").is_none());
+    assert_eq!(fonts.take_resource_updates().len(), 3);
+}
+
+#[test]
+fn test_fonts_origins_same_family() {
+    let mut fonts = FontCache::new(FontKeysAPI::new(())).unwrap();
+    let font_bytes = Rc::new(include_bytes!("fixtures/FreeSans.ttf").to_vec());
+    let font_id_a = FontId::with_origin("https://a.example", "FreeSans");
+    let font_id_b = FontId::with_origin("https://b.example", "FreeSans");
+    assert!(fonts.add_raw(font_id_a, Rc::clone(&font_bytes), 0).is_ok());
+    assert!(fonts.add_raw(font_id_b, Rc::clone(&font_bytes), 0).is_ok());
+    assert_eq!(fonts.get_default_font().unwrap().font_id(), font_id_a);
+
+    let instance_a = fonts
+        .get_origin_font_with_size("https://a.example", "FreeSans", Pt(20))
+        .unwrap();
+    let instance_b = fonts
+        .get_origin_font_with_size("https://b.example", "FreeSans", Pt(20))
+        .unwrap();
+    assert_eq!(instance_a.font_id(), font_id_a);
+    assert_eq!(instance_b.font_id(), font_id_b);
+    assert!(
+        fonts
+            .get_origin_font_with_size("https://c.example", "FreeSans", Pt(20))
+            .is_none()
+    );
+
+    // Lookups naming no origin settle on one of them.
+    let instance = fonts
+        .get_font_with_px_size("FreeSans", Px(20))
+        .unwrap();
+    assert_eq!(
+        instance.font_id(),
+        fonts
+            .get_font_with_px_size("FreeSans", Px(30))
+            .unwrap()
+            .font_id()
+    );
+
+    // Evicting an origin forgets the default font it registered.
+    assert_eq!(fonts.evict_origin("https://a.example").unwrap(), 1);
+    assert!(fonts.get_default_font().is_none());
+    assert_eq!(
+        fonts
+            .get_font_with_px_size("FreeSans", Px(20))
+            .unwrap()
+            .font_id(),
+        font_id_b
+    );
+
+    // Faces whose family is already taken aren't left behind.
+    let font_id_c = FontId::new("FreeSans copy");
+    assert!(fonts.add_raw(FontId::new("FreeSans"), Rc::clone(&font_bytes), 0).is_ok());
+    match fonts.add_raw(font_id_c, font_bytes, 0) {
+        Err(FontError::FontInstanceAlreadyAdded) => {}
+        _ => panic!("Expected the family to be taken")
+    }
+    assert!(!fonts.font_ids().any(|id| id == font_id_c));
+}
+
+#[test]
+fn test_resource_group_export_html() {
+    let files = FileCache::new().unwrap();
+    let images = ImageCache::new(ImageKeysAPI::new(())).unwrap();
+    let fonts = FontCache::new(FontKeysAPI::new(())).unwrap();
+    let group = ResourceGroup::new(files, images, fonts);
+
+    let references = vec![
+        AssetReference::Image {
+            src: "tests/fixtures/Quantum.png".to_string()
+        },
+        AssetReference::Font {
+            family: "FreeSans".to_string(),
+            src: Some("tests/fixtures/FreeSans.ttf".to_string()),
+            face_index: 0,
+            sizes: vec![Pt(14)],
+            dpi: Dpi(96)
+        }
+    ];
+    assert!(group.preload(&references).is_complete());
+
+    let html = group.export_html();
+    assert!(html.starts_with("<!DOCTYPE html>"));
+    assert!(html.contains("<h1>Images (1)</h1>"));
+    assert!(html.contains("<img src=\"data:image/png;base64,"));
+    assert!(html.contains("<td>512x529</td>"));
+    assert!(html.contains("<h1>Fonts (1)</h1>"));
+    assert!(html.contains("<h2>FreeSans</h2>"));
+    assert!(html.contains("src: url(\"data:application/x-font-woff;base64,"));
+    assert!(html.contains("<tr><td>14</td><td>96</td>"));
+}
+
+#[test]
+fn test_resource_group_prelude() {
+    use rsx_resources::prelude::*;
+
+    let group = DefaultResourceGroup::with_default_apis().unwrap();
+    let font_id = group.fonts().borrow().font_id("FreeSans");
+    let font_bytes = include_bytes!("fixtures/FreeSans.ttf").to_vec();
+    assert!(group.fonts().borrow_mut().add_raw(font_id, font_bytes, 0).is_ok());
+
+    let instance = group.lookup_font("FreeSans", Pt(12), Dpi(72)).unwrap().unwrap();
+    let glyphs = group.fonts().shape_text_h(&instance, "Hello").unwrap();
+    assert_eq!(glyphs.glyphs().len(), 5);
+
+    let updates: ResourceUpdates<DefaultImageKey, DefaultFontKey, DefaultFontInstanceKey> = group.fonts().take_resource_updates();
+    assert!(updates.updates.iter().any(|update| match update {
+        &Update::AddFontInstance { instance_key, .. } => instance_key == instance.external_instance_key(),
+        _ => false
+    }));
+}
+
+#[test]
+fn test_resource_group_strictness() {
+    let files = FileCache::new().unwrap();
+    let images = ImageCache::new(ImageKeysAPI::new(())).unwrap();
+    let fonts = FontCache::new(FontKeysAPI::new(())).unwrap();
+    let mut group = ResourceGroup::new(files, images, fonts);
+
+    let references = vec![
+        AssetReference::Image {
+            src: "tests/fixtures/Quantum.png".to_string()
+        },
+        AssetReference::Font {
+            family: "FreeSans".to_string(),
+            src: Some("tests/fixtures/FreeSans.ttf".to_string()),
+            face_index: 0,
+            sizes: vec![Pt(14)],
+            dpi: Dpi(96)
+        }
+    ];
+    assert!(group.preload(&references).is_complete());
+
+    // Lenient lookups of misspelled names find nothing, like the caches do.
+    assert_eq!(group.strictness(), Strictness::Lenient);
+    assert!(group.lookup_image("tests/fixtures/Quantum.png").unwrap().is_some());
+    assert!(group.lookup_image("tests/fixtures/Qantum.png").unwrap().is_none());
+    assert!(group.lookup_font("FreeSans", Pt(14), Dpi(96)).unwrap().is_some());
+    assert!(group.lookup_font("FreeSnas", Pt(14), Dpi(96)).unwrap().is_none());
+
+    // Or the placeholder face, when there's one.
+    assert!(group.fonts().borrow_mut().set_placeholder_metrics(Some(PlaceholderMetrics::default())).is_ok());
+    let placeholder = group.lookup_font("FreeSnas", Pt(14), Dpi(96)).unwrap().unwrap();
+    assert!(group.fonts().borrow().is_placeholder(&placeholder));
+
+    group.set_strictness(Strictness::Strict);
+    assert!(group.lookup_image("tests/fixtures/Quantum.png").unwrap().is_some());
+    let error = group.lookup_image("tests/fixtures/Qantum.png").unwrap_err();
+    assert_eq!(error.code(), codes::LOOKUP_IMAGE_NOT_REGISTERED);
+    assert!(error.message().contains("Qantum.png"));
+    assert!(group.lookup_font("FreeSans", Pt(14), Dpi(96)).unwrap().is_some());
+    assert_eq!(
+        group.lookup_font("FreeSnas", Pt(14), Dpi(96)).unwrap_err(),
+        LookupError::FamilyNotRegistered("FreeSnas".to_string())
+    );
+
+    // Without inserting placeholder instances or queueing their keys.
+    group.fonts().borrow_mut().take_resource_updates();
+    assert!(group.lookup_font("FreeSnas", Pt(20), Dpi(96)).is_err());
+    assert!(group.fonts().borrow_mut().take_resource_updates().is_empty());
+    assert!(codes::is_consistent());
+}
+
+#[test]
+fn test_resource_group_snapshot() {
+    use std::thread;
+
+    let files = FileCache::new().unwrap();
+    let images = ImageCache::new(ImageKeysAPI::new(())).unwrap();
+    let fonts = FontCache::new(FontKeysAPI::new(())).unwrap();
+    let group = ResourceGroup::new(files, images, fonts);
+
+    let references = vec![
+        AssetReference::Image {
+            src: "tests/fixtures/Quantum.png".to_string()
+        },
+        AssetReference::Font {
+            family: "FreeSans".to_string(),
+            src: Some("tests/fixtures/FreeSans.ttf".to_string()),
+            face_index: 0,
+            sizes: vec![Pt(14)],
+            dpi: Dpi(96)
+        }
+    ];
+    assert!(group.preload(&references).is_complete());
+
+    // Images added after a snapshot was taken don't show up in it.
+    let snapshot = group.snapshot();
+    let image_bytes = include_bytes!("fixtures/Quantum.png");
+    assert!(group.images().add_raw("Quantum", Rc::new(image_bytes.to_vec())).is_some());
+    assert_eq!(snapshot.images.images.len(), 1);
+    assert_eq!(snapshot.fonts.faces.len(), 1);
+    assert_eq!(snapshot.fonts.faces[0].family_name, Some("FreeSans".to_string()));
+
+    // Pixels and font bytes are shared by snapshots.
+    let later = group.snapshot();
+    assert_eq!(later.images.images.len(), 2);
+    let (image_id, ref image) = snapshot.images.images[0];
+    let same_image = later.images.images.iter().find(|&&(id, _)| id == image_id).unwrap();
+    assert!(Arc::ptr_eq(&image.pixels(), &same_image.1.pixels()));
+    assert!(Arc::ptr_eq(
+        snapshot.fonts.faces[0].bytes.as_ref().unwrap(),
+        later.fonts.faces[0].bytes.as_ref().unwrap()
+    ));
+
+    let html = thread::spawn(move || snapshot.export_html()).join().unwrap();
+    assert!(html.contains("<h1>Images (1)</h1>"));
+    assert!(html.contains("<h2>FreeSans</h2>"));
+    assert!(group.export_html().contains("<h1>Images (2)</h1>"));
+}
+
+#[test]
+fn test_resource_group_frame_loop() {
+    let files = FileCache::new().unwrap();
+    let images = ImageCache::new(ImageKeysAPI::new(())).unwrap();
+    let fonts = FontCache::new(FontKeysAPI::new(())).unwrap();
+    let mut frames = FrameLoop::new(ResourceGroup::new(files, images, fonts));
+
+    let manifest = vec![
+        AssetReference::Image {
+            src: "tests/fixtures/Quantum.png".to_string()
+        },
+        AssetReference::Font {
+            family: "FreeSans".to_string(),
+            src: Some("tests/fixtures/FreeSans.ttf".to_string()),
+            face_index: 0,
+            sizes: vec![Pt(12)],
+            dpi: Dpi(96)
+        },
+    ];
+
+    let (preload, report) = frames.frame(|group| group.preload(&manifest)).unwrap();
+    assert!(preload.is_complete());
+    assert_eq!(report.frame, 0);
+    assert_eq!(report.image_updates, 1);
+    assert_eq!(report.font_updates, 2);
+    assert!(report.is_balanced());
+    assert_eq!(
+        report.sink_keys,
+        KeyCounts {
+            images: 1,
+            fonts: 1,
+            font_instances: 1
+        }
+    );
+    assert!(report.used_bytes > 0);
+
+    let (width, report) = frames
+        .frame(|group| {
+            assert!(group.images().measure_image("tests/fixtures/Quantum.png").is_some());
+            let fonts = group.fonts();
+            let instance = fonts.get_font_with_px_size("FreeSans", Px(16)).unwrap();
+            fonts.borrow().shape_text_h(&instance, "Hello").unwrap().width_64()
+        })
+        .unwrap();
+    assert!(width > 0);
+    assert_eq!(report.image_updates, 0);
+    assert!(report.is_balanced());
+    assert!(report.shaped_text_entries > 0);
+    assert_eq!(report.errors, ErrorReport::default());
+
+    // Nothing changes between frames which only measure what's loaded.
+    let (_, report) = frames
+        .frame(|group| {
+            let fonts = group.fonts();
+            let instance = fonts.get_font_with_px_size("FreeSans", Px(16)).unwrap();
+            fonts.borrow().shape_text_h(&instance, "Hello").unwrap().width_64()
+        })
+        .unwrap();
+    assert_eq!(report.updates(), 0);
+    assert!(report.is_balanced());
+
+    let (quota, report) = frames
+        .frame(|group| {
+            group.set_byte_quota(Some(0));
+            group.enforce_byte_quota()
+        })
+        .unwrap();
+    assert_eq!(quota.evicted_images.len(), 1);
+    assert_eq!(quota.evicted_fonts, vec![FontId::new("FreeSans")]);
+    assert!(report.is_balanced());
+    assert_eq!(report.sink_keys, KeyCounts::default());
+    assert_eq!(report.used_bytes, 0);
+
+    // Reloading adds fresh keys, which the sink hasn't seen yet.
+    let (preload, report) = frames.frame(|group| group.preload(&manifest)).unwrap();
+    assert!(preload.is_complete());
+    assert!(report.is_balanced());
+    assert_eq!(frames.frames(), 5);
+    assert_eq!(frames.sink().key_counts(), report.cache_keys);
+}
+
+#[test]
+fn test_resource_group_mock_sink() {
+    let mut updates = ResourceUpdates::with_capacity(3);
+    updates.add_font_instance(DefaultFontInstanceKey(1), DefaultFontKey(1), 12);
+    updates.add_font(DefaultFontKey(1), Rc::new("data:".to_string()));
+
+    let mut sink = DefaultMockSink::new();
+    assert_eq!(sink.apply(&updates), Err((0, SinkError::MissingFont(DefaultFontKey(1)))));
+    assert_eq!(sink.applied(), 0);
+
+    updates.sort_dependencies();
+    assert!(sink.apply(&updates).is_ok());
+    assert!(sink.has_font_instance(DefaultFontInstanceKey(1)));
+
+    let mut deletes = ResourceUpdates::with_capacity(2);
+    deletes.delete_image(DefaultImageKey(1));
+    assert_eq!(sink.apply(&deletes), Err((0, SinkError::MissingImage(DefaultImageKey(1)))));
+}
+
+#[test]
+fn test_resource_group_digest() {
+    let new_group = || {
+        let files = FileCache::new().unwrap();
+        let images = ImageCache::new(ImageKeysAPI::new(())).unwrap();
+        let fonts = FontCache::new(FontKeysAPI::new(())).unwrap();
+        ResourceGroup::new(files, images, fonts)
+    };
+
+    let mut references = vec![
+        AssetReference::Image {
+            src: "tests/fixtures/Quantum.png".to_string()
+        },
+        AssetReference::Font {
+            family: "FreeSans".to_string(),
+            src: Some("tests/fixtures/FreeSans.ttf".to_string()),
+            face_index: 0,
+            sizes: vec![Pt(14)],
+            dpi: Dpi(96)
+        }
+    ];
+
+    let empty = new_group().digest();
+    assert_eq!(empty, new_group().digest());
+
+    let first = new_group();
+    assert!(first.preload(&references).is_complete());
+    references.reverse();
+    let mut second = new_group();
+    assert!(second.preload(&references).is_complete());
+
+    assert_ne!(first.digest(), empty);
+    assert_eq!(first.digest(), second.digest());
+    assert_eq!(first.digest().to_string().len(), 16);
+
+    second.set_display_profile(DisplayProfile::HIDPI);
+    assert_ne!(first.digest(), second.digest());
+}
+
+#[test]
+fn test_resource_group_error_report() {
+    let files = FileCache::new().unwrap();
+    let images = ImageCache::new(ImageKeysAPI::new(())).unwrap();
+    let fonts = FontCache::new(FontKeysAPI::new(())).unwrap();
+    let group = ResourceGroup::new(files, images, fonts);
+    assert_eq!(group.error_report(), ErrorReport::default());
+
+    let image_bytes = include_bytes!("fixtures/Quantum.png");
+    let truncated = Rc::new(image_bytes[..64].to_vec());
+    assert!(group.images().add_raw("Truncated", truncated).is_none());
+    assert!(group.images().add_raw("Quantum", Rc::new(image_bytes.to_vec())).is_some());
+    assert!(group.images().add_raw("Quantum", Rc::new(image_bytes.to_vec())).is_none());
+    assert!(group.images().borrow_mut().get_image_at_size("Missing", (64, 64)).is_err());
+
+    assert!(group.fonts().add_raw("Broken", b"Not a font".to_vec(), 0).is_none());
+    assert!(group.fonts().get_family_name("Missing").is_none());
+
+    // Adding the same resource twice is a misuse, not a resource failure.
+    let report = group.error_report();
+    assert_eq!(report.images.get(ErrorCategory::DecodeFailure), 1);
+    assert_eq!(report.images.get(ErrorCategory::MissingResource), 1);
+    assert_eq!(report.fonts.get(ErrorCategory::FontBackendFailure), 1);
+    assert_eq!(report.fonts.get(ErrorCategory::MissingResource), 1);
+    assert_eq!(report.total().total(), 4);
+    assert_eq!(report.total().limit_rejections, 0);
+
+    assert_eq!(group.reset_error_report(), report);
+    assert_eq!(group.error_report(), ErrorReport::default());
+}
+
+#[test]
+fn test_resource_group_id_report() {
+    let files = FileCache::new().unwrap();
+    let images = ImageCache::new(ImageKeysAPI::new(())).unwrap();
+    let fonts = FontCache::new(FontKeysAPI::new(())).unwrap();
+    let group = ResourceGroup::new(files, images, fonts);
+    group.set_id_collision_checks(true);
+    assert_eq!(group.id_report(), IdReport::default());
+
+    let image_bytes = include_bytes!("fixtures/Quantum.png");
+    assert!(group.images().add_raw("Quantum", Rc::new(image_bytes.to_vec())).is_some());
+    assert!(group.images().get_image("Quantum").is_some());
+    assert!(group.images().get_image("Missing").is_none());
+    assert!(group.fonts().get_family_name("Missing").is_none());
+
+    let id = group.images().borrow().image_id_with_origin("https://a.com", "Quantum");
+    assert_ne!(id, group.images().borrow().image_id("Quantum"));
+
+    let report = group.id_report();
+    assert_eq!(report.images.checked, 5);
+    assert_eq!(report.images.distinct, 3);
+    assert_eq!(report.fonts.distinct, 1);
+    assert_eq!(report.total().collisions, 0);
+
+    assert_eq!(group.reset_id_report(), report);
+    assert_eq!(group.id_report().images.checked, 0);
+    assert_eq!(group.id_report().images.distinct, 3);
+
+    // Disabling the checks forgets the sources seen so far.
+    group.set_id_collision_checks(false);
+    assert!(group.images().get_image("Quantum").is_some());
+    assert_eq!(group.id_report().images.checked, 0);
+
+    // Sources sharing an id are recorded rather than treated as fatal.
+    let mut ids = IdStats::new();
+    ids.set_enabled(true);
+    assert_eq!(ids.check(1, "a"), 1);
+    assert_eq!(ids.check(1, "a"), 1);
+    assert_eq!(ids.check(1, "b"), 1);
+    assert_eq!(ids.check(2, "b"), 2);
+    let counters = ids.counters();
+    assert_eq!((counters.checked, counters.distinct, counters.collisions), (4, 2, 1));
+}
+
+#[test]
+#[cfg(feature = "key-balance-watchdog")]
+fn test_resource_group_key_balance() {
+    let files = FileCache::new().unwrap();
+    let images = ImageCache::new(ImageKeysAPI::new(())).unwrap();
+    let fonts = FontCache::new(FontKeysAPI::new(())).unwrap();
+    let group = ResourceGroup::new(files, images, fonts);
+
+    let image_bytes = Rc::new(include_bytes!("fixtures/Quantum.png").to_vec());
+    let image_id = ImageId::with_origin("https://a.example", "Quantum.png");
+    assert!(
+        group
+            .images()
+            .borrow_mut()
+            .add_raw(image_id, image_bytes)
+            .is_ok()
+    );
+
+    let font_bytes = Rc::new(include_bytes!("fixtures/FreeSans.ttf").to_vec());
+    let font_id = FontId::with_origin("https://a.example", "FreeSans");
+    assert!(
+        group
+            .fonts()
+            .borrow_mut()
+            .add_raw(font_id, font_bytes, 0)
+            .is_ok()
+    );
+    assert!(group.fonts().get_font_with_size("FreeSans", 20).is_some());
+    assert!(group.verify_key_balance().is_balanced());
+
+    assert!(
+        group
+            .images()
+            .borrow_mut()
+            .evict_origin("https://a.example")
+            .is_ok()
+    );
+    assert!(
+        group
+            .fonts()
+            .borrow_mut()
+            .evict_origin("https://a.example")
+            .is_ok()
+    );
+
+    let report = group.verify_key_balance();
+    assert!(report.is_balanced());
+    assert!(report.leaked_images.is_empty());
+    assert!(report.leaked_font_instances.is_empty());
+}
+
+#[test]
+fn test_fonts_shaping_options() {
+    let mut options = ShapingOptions::with_locale("tr-TR");
+    assert_eq!(options.transform("istanbul"), "istanbul");
+
+    options.case_mapping = CaseMapping::Uppercase;
+    assert_eq!(options.transform("istanbul"), "\u{130}STANBUL");
+
+    options.case_mapping = CaseMapping::Lowercase;
+    assert_eq!(options.transform("ISPARTA \u{130}ZM\u{130}R"), "\u{131}sparta izmir");
+
+    let mut options = ShapingOptions::with_locale("en-US");
+    options.case_mapping = CaseMapping::Uppercase;
+    assert_eq!(options.transform("istanbul"), "ISTANBUL");
+
+    let mut options = ShapingOptions::with_locale("ar-EG");
+    assert_eq!(options.transform("2018"), "2018");
+
+    options.numerals = NumeralShaping::Locale;
+    assert_eq!(options.transform("2018"), "\u{662}\u{660}\u{661}\u{668}");
+
+    options.numerals = NumeralShaping::Western;
+    assert_eq!(options.transform("\u{6F2}\u{6F0}\u{661}\u{668}"), "2018");
+
+    let font_keys = FontKeysAPI::new(());
+    let fonts = SharedFonts::from(FontCache::new(font_keys).unwrap());
+
+    let font_bytes = Rc::new(include_bytes!("fixtures/FreeSans.ttf").to_vec());
+    assert!(
+        fonts
+            .borrow_mut()
+            .add_raw(FontId::new("FreeSans"), font_bytes, 0)
+            .is_ok()
+    );
+
+    let instance = fonts.get_font("FreeSans").unwrap();
+    let mut options = ShapingOptions::default();
+    options.case_mapping = CaseMapping::Uppercase;
+
+    let shaped = fonts
+        .borrow()
+        .shape_text_h_with_options(&instance, "hello", &options)
+        .unwrap();
+    let expected = fonts.shape_text_h(&instance, "HELLO").unwrap();
+    assert_eq!(shaped, expected);
+}
+
+#[test]
+fn test_fonts_normalization() {
+    let composed = "Caf\u{e9}";
+    let decomposed = "Cafe\u{301}";
+    assert_eq!(NormalizationForm::NFC.normalize(decomposed), composed);
+    assert_eq!(NormalizationForm::NFD.normalize(composed), decomposed);
+    assert_eq!(NormalizationForm::NFKC.normalize("\u{fb01}"), "fi");
+    assert_eq!(NormalizationForm::Preserve.normalize(decomposed), decomposed);
+
+    let mut fonts = FontCache::new(FontKeysAPI::new(())).unwrap();
+    let font_bytes = Rc::new(include_bytes!("fixtures/FreeSans.ttf").to_vec());
+    assert!(fonts.add_raw(FontId::new("FreeSans"), font_bytes, 0).is_ok());
+    let instance = fonts.get_default_font().unwrap();
+    assert_eq!(fonts.normalization(), NormalizationForm::NFC);
+
+    // Canonically equivalent strings share a single shaped run.
+    let glyphs = fonts.shape_text_h(&instance, composed).unwrap();
+    assert_eq!(fonts.shape_text_h(&instance, decomposed).unwrap(), glyphs);
+    assert_eq!(glyphs.glyphs().len(), 4);
+    assert_eq!(instance.shaped_text_entries(), 1);
+
+    let options = ShapingOptions::default();
+    fonts.layout_paragraph(&instance, composed, &options, None).unwrap();
+    fonts.layout_paragraph(&instance, decomposed, &options, None).unwrap();
+    assert_eq!(fonts.paragraph_cache_len(), 1);
+
+    fonts.set_normalization(NormalizationForm::Preserve);
+    assert_eq!(fonts.shape_text_h(&instance, decomposed).unwrap().glyphs().len(), 5);
+    fonts.layout_paragraph(&instance, composed, &options, None).unwrap();
+    assert_eq!(fonts.paragraph_cache_len(), 2);
+}
+
+#[test]
+fn test_fonts_glyph_ink_bounds() {
+    let mut fonts = FontCache::new(FontKeysAPI::new(())).unwrap();
+    let font_bytes = Rc::new(include_bytes!("fixtures/FreeSans.ttf").to_vec());
+    assert!(fonts.add_raw(FontId::new("FreeSans"), font_bytes, 0).is_ok());
+    let instance = fonts.get_default_font().unwrap();
+
+    let glyphs = fonts.shape_text_h(&instance, "a b").unwrap();
+    assert!(glyphs.ink_bounds().is_none());
+    assert!(glyphs.ink_extent().is_none());
+
+    fonts.set_glyph_ink_bounds(true);
+    assert!(instance.ink_bounds());
+    assert_eq!(instance.shaped_text_entries(), 0);
+
+    let glyphs = fonts.shape_text_h(&instance, "a b").unwrap();
+    let bounds = glyphs.ink_bounds().unwrap();
+    assert_eq!(bounds.len(), 3);
+    assert!(!bounds[0].is_empty());
+    assert!(bounds[1].is_empty());
+    assert!(bounds[2].left_64 > bounds[0].right_64);
+
+    // Ink sits within the line box, and glyphs without ink don't widen the extent.
+    let extent = glyphs.ink_extent().unwrap();
+    assert!(extent.top_64 >= 0 && extent.bottom_64 <= glyphs.height_64());
+    assert_eq!(extent.left_64, bounds[0].left_64);
+    assert_eq!(extent.right_64, bounds[2].right_64);
+    assert!(glyphs.ink_bounds_of(1..2).unwrap().is_empty());
+    assert_eq!(glyphs.ink_bounds_of(2..10), Some(bounds[2]));
+
+    assert!(serde_json::to_string(&glyphs).unwrap().contains("ink_bounds"));
+
+    let mut mock = FontCache::with_backend(FontKeysAPI::new(()), MockFontBackend::new());
+    mock.set_glyph_ink_bounds(true);
+    assert!(mock.add_raw(FontId::new("Mock"), b"Mock".to_vec(), 0).is_ok());
+    let instance = mock.get_default_font().unwrap();
+    let glyphs = mock.shape_text_v(&instance, "ab").unwrap();
+    let bounds = glyphs.ink_bounds().unwrap();
+    assert_eq!(bounds[0].width_64(), bounds[1].width_64());
+    assert_eq!(bounds[1].top_64 - bounds[0].top_64, glyphs.glyphs()[1].y_64() - glyphs.glyphs()[0].y_64());
+
+    // Glyphs hang from their vertical origin, centered in the column.
+    assert!(bounds[0].top_64 >= 0 && bounds[0].top_64 < glyphs.glyphs()[0].y_64());
+    assert!((bounds[0].left_64 + bounds[0].right_64 - glyphs.width_64()).abs() <= 1);
+}
+
+#[test]
+fn test_fonts_shares_shaping_cache() {
+    let mut fonts = FontCache::new(FontKeysAPI::new(())).unwrap();
+    let font_bytes = Rc::new(include_bytes!("fixtures/FreeSans.ttf").to_vec());
+    assert!(fonts.add_raw(FontId::new("FreeSans"), font_bytes, 0).is_ok());
+    let small = fonts.get_font_with_px_size("FreeSans", Px(12)).unwrap();
+    let large = fonts.get_font_with_px_size("FreeSans", Px(16)).unwrap();
+
+    // Runs are keyed on the instance along with the text.
+    let text_hash = 42;
+    assert_ne!(ShapingKey::for_instance(&*small, text_hash, false), ShapingKey::for_instance(&*large, text_hash, false));
+    assert_ne!(ShapingKey::for_instance(&*small, text_hash, false), ShapingKey::for_instance(&*small, text_hash, true));
+
+    fonts.shape_text_h(&small, "Hello").unwrap();
+    fonts.shape_text_h(&large, "Hello").unwrap();
+    assert_eq!(small.shaped_text_entries(), 1);
+    assert_eq!(large.shaped_text_entries(), 1);
+    assert!(fonts.shaping_cache().is_none());
+    assert!(!small.shaping_cache().is_shared_with(&large.shaping_cache()));
+
+    fonts.set_shares_shaping_cache(true);
+    assert!(small.shaping_cache().is_shared_with(&large.shaping_cache()));
+    assert_eq!(small.shaped_text_entries(), 0);
+
+    let small_width = fonts.shape_text_h(&small, "Hello").unwrap().width_64();
+    let large_width = fonts.shape_text_h(&large, "Hello").unwrap().width_64();
+    assert!(small_width < large_width);
+    assert_eq!(fonts.shape_text_h(&small, "Hello").unwrap().width_64(), small_width);
+    assert_eq!(fonts.shaping_cache().unwrap().len(), 2);
+    assert_eq!(small.shaped_text_entries(), 1);
+
+    let larger = fonts.get_font_with_px_size("FreeSans", Px(20)).unwrap();
+    assert!(larger.shaping_cache().is_shared_with(fonts.shaping_cache().unwrap()));
+
+    // Changing an instance's options only discards its own runs.
+    small.set_kerning(false);
+    assert_eq!(small.shaped_text_entries(), 0);
+    assert_eq!(large.shaped_text_entries(), 1);
+
+    fonts.set_shares_shaping_cache(false);
+    assert!(fonts.shaping_cache().is_none());
+    assert!(!small.shaping_cache().is_shared_with(&large.shaping_cache()));
+    assert_eq!(large.shaped_text_entries(), 0);
+}
+
+#[test]
+fn test_fonts_shared_shaping_cache_remove_font() {
+    let mut fonts = FontCache::new(FontKeysAPI::new(())).unwrap();
+    let font_id = FontId::new("FreeSans");
+    let font_bytes = Rc::new(include_bytes!("fixtures/FreeSans.ttf").to_vec());
+    fonts.set_shares_shaping_cache(true);
+    assert!(fonts.add_raw(font_id, Rc::clone(&font_bytes), 0).is_ok());
+
+    let instance = fonts.get_font_with_px_size("FreeSans", Px(12)).unwrap();
+    let old_key = fonts.shape_text_h(&instance, "Hello").unwrap().font_key();
+    assert_eq!(fonts.shaping_cache().unwrap().len(), 1);
+    drop(instance);
+
+    // Runs of the evicted face go with it, so they can't hand out its stale keys.
+    fonts.set_font_priority(font_id, ResourcePriority::Discardable);
+    assert_eq!(fonts.evict_bytes(ResourcePriority::Discardable, 1).unwrap().len(), 1);
+    assert!(fonts.shaping_cache().unwrap().is_empty());
+
+    assert!(fonts.add_raw(font_id, font_bytes, 0).is_ok());
+    let instance = fonts.get_font_with_px_size("FreeSans", Px(12)).unwrap();
+    let new_key = fonts.shape_text_h(&instance, "Hello").unwrap().font_key();
+    assert_ne!(new_key, old_key);
+    assert_eq!(new_key, instance.external_key());
+}
+
+#[test]
+fn test_pixels_to_canonical() {
+    let mut pixels = vec![0, 64, 128, 128, 10, 20, 30, 255, 0, 0, 0, 0];
+    let format = pixels::to_canonical(
+        ImagePixelFormat::BGRA(8),
+        AlphaMode::Premultiplied,
+        &mut pixels
+    );
+    assert_eq!(format, ImagePixelFormat::RGBA(8));
+    assert_eq!(pixels, vec![255, 128, 0, 128, 30, 20, 10, 255, 0, 0, 0, 0]);
+
+    let mut gray = vec![0, 128, 255];
+    let format = pixels::to_canonical(ImagePixelFormat::Gray(8), AlphaMode::Straight, &mut gray);
+    assert_eq!(format, ImagePixelFormat::Gray(8));
+    assert_eq!(gray, vec![0, 128, 255]);
+
+    let mut premultiplied = vec![255, 128, 0, 128];
+    pixels::premultiply(&mut premultiplied);
+    assert_eq!(premultiplied, vec![128, 64, 0, 128]);
+}
+
+#[test]
+fn test_shared_caches_reentrancy() {
+    let font_keys = FontKeysAPI::new(());
+    let mut fonts = SharedFonts::from(FontCache::new(font_keys).unwrap());
+
+    let font_bytes = Rc::new(include_bytes!("fixtures/FreeSans.ttf").to_vec());
+    assert!(fonts.add_raw("FreeSans", font_bytes, 0).is_some());
+    assert_eq!(fonts.take_resource_updates().len(), 2);
+
+    {
+        let _guard = fonts.borrow();
+        let mut fonts = fonts.clone();
+        assert!(fonts.get_font("FreeSans").is_some());
+        assert!(fonts.get_font_with_size("FreeSans", 40).is_none());
+        fonts.set_default_font("FreeSans", 24, 72);
+        assert_eq!(fonts.pending_mutations(), 2);
+    }
+
+    let instance = fonts.get_font_with_size("FreeSans", 40).unwrap();
+    assert!(fonts.shape_text_h(&instance, "Hello").is_some());
+    assert_eq!(fonts.pending_mutations(), 0);
+    assert!(fonts.get_default_font().is_none());
+    assert_eq!(
+        fonts
+            .get_default_font_with_size(24)
+            .unwrap()
+            .dpi(),
+        Dpi(72)
+    );
+    assert_eq!(fonts.take_resource_updates().len(), 2);
+
+    // Queued mutations failing once applied are kept as deferred errors.
+    let font_bytes = Rc::new(include_bytes!("fixtures/FreeSans.ttf").to_vec());
+    {
+        let _guard = fonts.borrow();
+        let mut fonts = fonts.clone();
+        assert!(fonts.add_raw("FreeSans", font_bytes, 0).is_some());
+        assert!(fonts.get_font_with_size("Missing", 40).is_none());
+    }
+    fonts.flush();
+    let deferred: Vec<_> = fonts.borrow().take_deferred_errors().into_iter().map(|err| err.code).collect();
+    assert_eq!(deferred, vec![codes::FONT_FACE_ALREADY_ADDED, codes::FONT_FACE_NOT_FOUND]);
+    assert_eq!(fonts.borrow().error_counters().deferred_failures, 2);
+    assert!(fonts.borrow().take_deferred_errors().is_empty());
+
+    let image_keys = ImageKeysAPI::new(());
+    let images = SharedImages::from(ImageCache::new(image_keys).unwrap());
+
+    let image_bytes = Rc::new(include_bytes!("fixtures/Quantum.png").to_vec());
+    {
+        let _guard = images.borrow();
+        let mut images = images.clone();
+        assert!(images.add_raw("Quantum", image_bytes).is_some());
+        assert!(images.get_image("Quantum").is_none());
+    }
+
+    // Reads apply mutations queued while the cache was borrowed.
+    assert!(images.get_image("Quantum").is_some());
+    assert_eq!(images.pending_mutations(), 0);
+    assert_eq!(images.clone().take_resource_updates().len(), 1);
+
+    let image_bytes = Rc::new(include_bytes!("fixtures/Quantum.png").to_vec());
+    {
+        let _guard = images.borrow();
+        assert!(images.clone().add_raw("Quantum", image_bytes).is_some());
+    }
+    images.flush();
+    let deferred = images.borrow().take_deferred_errors();
+    assert_eq!(deferred.len(), 1);
+    assert_eq!(deferred[0].code, codes::IMAGE_ALREADY_ADDED);
+    assert_eq!(images.borrow().error_counters().deferred_failures, 1);
+}
+
+#[test]
+fn test_image_cache_image_set() {
+    let image_keys = ImageKeysAPI::new(());
+    let mut images_cache = ImageCache::new(image_keys).unwrap();
+
+    let image_bytes = Rc::new(include_bytes!("fixtures/Quantum.png").to_vec());
+    let image_id = ImageId::new("icon");
+
+    assert!(
+        images_cache
+            .add_image_set(image_id, vec![(Scale(1.0), Vec::new())])
+            .is_err()
+    );
+    assert!(
+        images_cache
+            .add_image_set(image_id, Vec::<(Scale, Vec<u8>)>::new())
+            .is_err()
+    );
+    assert!(
+        images_cache
+            .add_image_set(
+                image_id,
+                vec![
+                    (Scale(2.0), Rc::clone(&image_bytes)),
+                    (Scale(1.0), Rc::clone(&image_bytes))
+                ]
+            )
+            .is_ok()
+    );
+    assert!(
+        images_cache
+            .add_raw(image_id, Rc::clone(&image_bytes))
+            .is_err()
+    );
+
+    let image = images_cache.get_image("icon").unwrap();
+    assert_eq!(image.scale(), 1.0);
+    assert_eq!(image.css_width(), 512.0);
+
+    images_cache.set_device_pixel_ratio(Scale(1.5));
+    let image = images_cache.get_image("icon").unwrap();
+    assert_eq!(image.scale(), 2.0);
+    assert_eq!(image.css_width(), 256.0);
+    assert_eq!(image.css_height(), 264.5);
+
+    let dimensions = images_cache.measure_image("icon").unwrap();
+    assert_eq!(dimensions.width(), 256);
+    assert_eq!(dimensions.height(), 265);
+    assert_eq!(dimensions.scale(), 2.0);
+
+    images_cache.set_device_pixel_ratio(Scale(3.0));
+    assert_eq!(images_cache.get_image("icon").unwrap().scale(), 2.0);
+}
+
+#[test]
+fn test_resource_updates_stream() {
+    let image_bytes = Rc::new(include_bytes!("fixtures/Quantum.png").to_vec());
+    let encoded = EncodedImage::from_bytes(image_bytes).unwrap();
+
+    let mut image_keys = ImageKeysAPI::new(());
+    let receiver = image_keys.subscribe();
+    let mut images = SharedImages::from(ImageCache::new(image_keys).unwrap());
+
+    assert!(receiver.try_recv().is_err());
+    assert!(images.add_image("Quantum", &encoded).is_some());
+
+    match receiver.try_recv() {
+        Ok(Update::AddImage { .. }) => {}
+        _ => panic!("Expected a streamed image update")
+    }
+    assert!(receiver.try_recv().is_err());
+    assert_eq!(images.take_resource_updates().len(), 1);
+
+    let (image_keys, receiver) = ImageKeysAPI::new(()).into_stream();
+    let mut images = SharedImages::from(ImageCache::new(image_keys).unwrap());
+
+    assert!(images.add_image("Quantum", &encoded).is_some());
+    assert!(receiver.try_recv().is_ok());
+    assert_eq!(images.take_resource_updates().len(), 0);
+}
+
+#[test]
+fn test_resource_updates_stream_threads() {
+    use std::thread;
+
+    fn assert_send<T: Send>() {}
+    assert_send::<UpdateReceiver<DefaultImageKey, DefaultFontKey, DefaultFontInstanceKey>>();
+
+    let image_bytes = Rc::new(include_bytes!("fixtures/Quantum.png").to_vec());
+    let encoded = EncodedImage::from_bytes(image_bytes).unwrap();
+
+    let (image_keys, receiver) = ImageKeysAPI::new(()).into_stream();
+    let mut images = SharedImages::from(ImageCache::new(image_keys).unwrap());
+    let renderer = thread::spawn(move || match receiver.recv() {
+        Ok(Update::AddImage { ref data_uri, .. }) => data_uri.starts_with("data:image/png;base64,"),
+        _ => false
+    });
+    assert!(images.add_image("Quantum", &encoded).is_some());
+    assert!(renderer.join().unwrap());
+    assert_eq!(images.take_resource_updates().len(), 0);
+
+    // Once the receiver is gone, updates are batched again instead of lost.
+    assert!(images.add_image("Quantum 2", &encoded).is_some());
+    assert_eq!(images.take_resource_updates().len(), 1);
+    assert!(images.add_image("Quantum 3", &encoded).is_some());
+    assert_eq!(images.take_resource_updates().len(), 1);
+}
+
+#[test]
+fn test_resource_updates_raw_font_bytes() {
+    let font_bytes = include_bytes!("fixtures/FreeSans.ttf").to_vec();
+    let take_font_updates = |font_keys: FontKeysAPI| {
+        let mut fonts = FontCache::new(font_keys).unwrap();
+        assert!(fonts.add_raw(FontId::new("FreeSans"), font_bytes.clone(), 0).is_ok());
+        assert!(fonts.get_or_insert_font(FontInstanceId::from_family_str("FreeSans", Pt(12), Dpi(72))).is_some());
+        SharedFonts::from(fonts).take_resource_updates()
+    };
+
+    // Fonts are encoded into data uris by default, for JSON consumers.
+    let font_keys = FontKeysAPI::new(());
+    assert!(!font_keys.supports_raw_font_bytes());
+    let updates = take_font_updates(font_keys);
+    assert!(updates.updates.iter().any(|update| match update {
+        &Update::AddFont { ref data_uri, .. } => data_uri.starts_with("data:application/x-font-woff;base64,"),
+        _ => false
+    }));
+    assert!(!updates.updates.iter().any(|update| match update {
+        &Update::AddFontBytes { .. } => true,
+        _ => false
+    }));
+
+    let mut font_keys = FontKeysAPI::new(());
+    font_keys.set_supports_raw_font_bytes(true);
+    assert!(font_keys.supports_raw_font_bytes());
+    let mut updates = take_font_updates(font_keys);
+    assert!(updates.updates.iter().any(|update| match update {
+        &Update::AddFontBytes { ref bytes, .. } => **bytes == font_bytes,
+        _ => false
+    }));
+    assert!(updates.is_ordered());
+
+    // Binary consumers take raw bytes whatever was declared.
+    let mut fonts = FontCache::new(FontKeysAPI::new(())).unwrap();
+    assert!(fonts.add_raw(FontId::new("FreeSans"), font_bytes.clone(), 0).is_ok());
+    let raw = fonts.take_raw_resource_updates();
+    assert!(raw.updates.iter().any(|update| match update {
+        &Update::AddFontBytes { ref bytes, .. } => **bytes == font_bytes,
+        _ => false
+    }));
+    assert!(raw.is_ordered());
+
+    // Raw bytes can still be encoded later on.
+    updates.encode_font_bytes(0);
+    assert!(updates.updates.iter().any(|update| match update {
+        &Update::AddFont { ref data_uri, .. } => data_uri.starts_with("data:application/x-font-woff;base64,"),
+        _ => false
+    }));
+
+    // Subscribers get fonts the same way batches do.
+    let (font_keys, receiver) = FontKeysAPI::new(()).into_stream();
+    let mut fonts = FontCache::new(font_keys).unwrap();
+    assert!(fonts.add_raw(FontId::new("FreeSans"), font_bytes.clone(), 0).is_ok());
+    assert!(fonts.get_or_insert_font(FontInstanceId::from_family_str("FreeSans", Pt(12), Dpi(72))).is_some());
+    match receiver.try_recv() {
+        Ok(Update::AddFont { .. }) => {}
+        _ => panic!("Expected a streamed font update")
+    }
+}
+
+#[test]
+fn test_resource_updates_json() {
+    let mut updates: ResourceUpdates<DefaultImageKey, DefaultFontKey, DefaultFontInstanceKey> = ResourceUpdates::with_capacity(3);
+    updates.add_image(DefaultImageKey(0), Rc::new("data:image/png;base64,AAAA".to_string()));
+    updates.add_font_instance(DefaultFontInstanceKey(1), DefaultFontKey(2), 16);
+    updates.delete_image(DefaultImageKey(0));
+    assert!(updates.json_size_hint() > 26);
+
+    // Appends to whatever the buffer already holds, reusing its allocation.
+    let mut buffer = b"updates=".to_vec();
+    assert!(updates.write_json_to(&mut buffer).is_ok());
+    let capacity = buffer.capacity();
+    let json = String::from_utf8(buffer[8..].to_vec()).unwrap();
+    buffer.clear();
+    assert!(updates.write_json_to(&mut buffer).is_ok());
+    assert_eq!(buffer.capacity(), capacity);
+
+    let parsed: Vec<Update<DefaultImageKey, DefaultFontKey, DefaultFontInstanceKey>> = serde_json::from_str(&json).unwrap();
+    assert_eq!(parsed, updates.updates);
+    assert_eq!(String::from_utf8(buffer).unwrap(), json);
+
+    let string: String = updates.into();
+    assert_eq!(string, json);
+}
+
+#[test]
+fn test_resource_updates_key_namespaces() {
+    type DefaultUpdates = ResourceUpdates<DefaultImageKey, DefaultFontKey, DefaultFontInstanceKey>;
+
+    let mut updates = DefaultUpdates::with_capacity(2);
+    updates.add_image(DefaultImageKey(0), Rc::new("data:image/png;base64,AAAA".to_string()));
+    updates.add_font_instance(DefaultFontInstanceKey(1), DefaultFontKey(2), 16);
+
+    let mut buffer = Vec::new();
+    assert!(updates.write_namespaced_json_to(&mut buffer).is_ok());
+    assert_eq!(DefaultUpdates::from_namespaced_json(&buffer).unwrap(), updates);
+
+    let mut value: serde_json::Value = serde_json::from_slice(&buffer).unwrap();
+    assert_eq!(value["namespaces"]["font_instance"], DefaultFontInstanceKey::NAMESPACE);
+    value["namespaces"]["font"] = serde_json::Value::from("other.font");
+    let foreign = serde_json::to_vec(&value).unwrap();
+    match DefaultUpdates::from_namespaced_json(&foreign) {
+        Err(NamespaceError::Mismatch { expected, found }) => {
+            assert_eq!(expected, KeyNamespaces::of::<DefaultImageKey, DefaultFontKey, DefaultFontInstanceKey>());
+            assert_eq!(found.font, "other.font");
+        }
+        _ => panic!("Expected a namespace mismatch")
+    }
+
+    // Untagged updates aren't accepted.
+    let mut untagged = Vec::new();
+    assert!(updates.write_json_to(&mut untagged).is_ok());
+    assert!(DefaultUpdates::from_namespaced_json(&untagged).is_err());
+}
+
+#[test]
+fn test_resource_updates_order() {
+    let uri = Rc::new("data:font/ttf;base64,AAAA".to_string());
+    let mut updates: ResourceUpdates<DefaultImageKey, DefaultFontKey, DefaultFontInstanceKey> = ResourceUpdates::with_capacity(6);
+    updates.add_glyph_run(DefaultFontInstanceKey(1), vec![3], GlyphRunSource::new(None, 0, vec![0]));
+    updates.add_font_instance(DefaultFontInstanceKey(1), DefaultFontKey(0), 16);
+    updates.add_image(DefaultImageKey(0), Rc::clone(&uri));
+    updates.add_font(DefaultFontKey(0), Rc::clone(&uri));
+    updates.delete_font(DefaultFontKey(0));
+    updates.delete_font_instance(DefaultFontInstanceKey(1));
+
+    assert!(!updates.is_ordered());
+    assert_eq!(updates.dependencies(0), vec![1]);
+    assert_eq!(updates.dependencies(4), vec![1, 3, 5]);
+    assert_eq!(updates.dependencies_of(&[DefaultImageKey(0)], &[DefaultFontInstanceKey(1)]), vec![1, 2, 3]);
+
+    updates.sort_dependencies();
+    assert!(updates.is_ordered());
+    let kinds: Vec<_> = updates
+        .updates
+        .iter()
+        .map(|update| match update {
+            &Update::AddFont { .. } => "font",
+            &Update::AddFontInstance { .. } => "instance",
+            &Update::AddGlyphRun { .. } => "run",
+            &Update::AddImage { .. } => "image",
+            &Update::DeleteFontInstance { .. } => "delete instance",
+            &Update::DeleteFont { .. } => "delete font",
+            _ => "other"
+        })
+        .collect();
+    assert_eq!(kinds, vec!["image", "font", "instance", "run", "delete instance", "delete font"]);
+
+    // Batches taken from the caches are always ordered.
+    let mut fonts = FontCache::new(FontKeysAPI::new(())).unwrap();
+    let font_bytes = Rc::new(include_bytes!("fixtures/FreeSans.ttf").to_vec());
+    assert!(fonts.add_raw(FontId::new("FreeSans"), font_bytes, 0).is_ok());
+    let instance = fonts.get_or_insert_font(FontInstanceId::from_family_str("FreeSans", Pt(12), Dpi(72))).unwrap();
+    let mut fonts = SharedFonts::from(fonts);
+    assert!(fonts.shape_text_h(&instance, "Hello").is_some());
+    let updates = fonts.take_resource_updates();
+    assert!(updates.len() >= 2);
+    assert!(updates.is_ordered());
+}
+
+#[test]
+fn test_fonts_paragraph_cache() {
+    let font_keys = FontKeysAPI::new(());
+    let mut fonts_cache = FontCache::new(font_keys).unwrap();
+
+    let font_bytes = Rc::new(include_bytes!("fixtures/FreeSans.ttf").to_vec());
+    assert!(
+        fonts_cache
+            .add_raw(FontId::new("FreeSans"), font_bytes, 0)
+            .is_ok()
+    );
+
+    let instance = fonts_cache.get_default_font().unwrap();
+    let options = ShapingOptions::default();
+    let text = "The quick brown fox\njumps over the lazy dog";
+
+    let unbounded = fonts_cache
+        .layout_paragraph(&instance, text, &options, None)
+        .unwrap();
+    assert_eq!(unbounded.lines.len(), 2);
+    assert_eq!(unbounded.lines[0].text_range, (0, 19));
+    assert_eq!(unbounded.lines[1].offset_64, unbounded.lines[0].glyphs.height_64());
+    assert_eq!(fonts_cache.paragraph_cache_len(), 1);
+
+    let max_width_64 = 100 * 64;
+    let wrapped = fonts_cache
+        .layout_paragraph(&instance, text, &options, Some(max_width_64))
+        .unwrap();
+    assert!(wrapped.lines.len() > 2);
+    assert!(wrapped.width_64 <= max_width_64);
+    assert!(wrapped.lines.iter().all(|line| line.glyphs.width_64() <= max_width_64));
+    assert_eq!(fonts_cache.paragraph_cache_len(), 2);
+
+    let resized = fonts_cache
+        .layout_paragraph(&instance, text, &options, Some(max_width_64 + 64))
+        .unwrap();
+    assert!(Rc::ptr_eq(&wrapped, &resized));
+    assert_eq!(fonts_cache.paragraph_cache_len(), 2);
+
+    fonts_cache.set_paragraph_cache_capacity(1);
+    assert_eq!(fonts_cache.paragraph_cache_len(), 1);
+
+    let again = fonts_cache
+        .layout_paragraph(&instance, text, &options, Some(max_width_64))
+        .unwrap();
+    assert!(Rc::ptr_eq(&wrapped, &again));
+
+    let again = fonts_cache
+        .layout_paragraph(&instance, text, &options, None)
+        .unwrap();
+    assert!(!Rc::ptr_eq(&unbounded, &again));
+    assert_eq!(*unbounded, *again);
+
+    // Instances differing only by their size unit or palette lay out their own paragraphs.
+    fonts_cache.set_paragraph_cache_capacity(8);
+    let points = fonts_cache
+        .get_or_insert_font(FontInstanceId::from_family_str("FreeSans", Pt(20), Dpi(72)))
+        .unwrap();
+    let pixels = fonts_cache.get_font_with_px_size("FreeSans", Px(20)).unwrap();
+    assert_eq!((points.size(), points.dpi()), (pixels.size(), pixels.dpi()));
+    let in_points = fonts_cache.layout_paragraph(&points, text, &options, None).unwrap();
+    let in_pixels = fonts_cache.layout_paragraph(&pixels, text, &options, None).unwrap();
+    assert!(!Rc::ptr_eq(&in_points, &in_pixels));
+
+    let selection = PaletteSelection::new(1, PaletteColor::new(255, 0, 0, 255));
+    let tinted = fonts_cache
+        .get_or_insert_font(FontInstanceId::from_family_px("FreeSans", Px(20)).with_palette(selection))
+        .unwrap();
+    let in_palette = fonts_cache.layout_paragraph(&tinted, text, &options, None).unwrap();
+    assert!(!Rc::ptr_eq(&in_pixels, &in_palette));
+    assert_eq!(in_palette.lines[0].glyphs.font_instance_key(), tinted.external_instance_key());
+}
+
+#[test]
+fn test_fonts_shape_text_wrapped() {
+    let mut fonts = FontCache::new(FontKeysAPI::new(())).unwrap();
+    let font_bytes = Rc::new(include_bytes!("fixtures/FreeSans.ttf").to_vec());
+    assert!(fonts.add_raw(FontId::new("FreeSans"), font_bytes, 0).is_ok());
+    let instance = fonts.get_default_font().unwrap();
+
+    let text = "well-known words";
+    let hyphenated_64 = fonts.shape_text_h(&instance, "well-").unwrap().width_64();
+    let wrapped = fonts
+        .shape_text_wrapped(&instance, text, hyphenated_64 + 64)
+        .unwrap();
+    assert_eq!(wrapped.lines[0].text_range, (0, 5));
+    assert_eq!(wrapped.line_widths_64()[0], hyphenated_64);
+    assert_eq!(
+        wrapped.height_64,
+        wrapped
+            .lines
+            .iter()
+            .map(|line| line.glyphs.height_64())
+            .sum::<i32>()
+    );
+    assert_eq!(fonts.paragraph_cache_len(), 0);
+
+    // Hyphens leading a word and no-break spaces don't break lines.
+    let text = "-a\u{00A0}b c";
+    let joined_64 = fonts.shape_text_h(&instance, "-a\u{00A0}b").unwrap().width_64();
+    let wrapped = fonts.shape_text_wrapped(&instance, text, joined_64).unwrap();
+    assert_eq!(wrapped.lines.len(), 2);
+    assert_eq!(wrapped.lines[0].text_range, (0, 5));
+    assert_eq!(wrapped.lines[1].text_range, (6, 7));
+}
+
+#[test]
+fn test_fonts_paragraph_inline_objects() {
+    let mut fonts = FontCache::with_backend(FontKeysAPI::new(()), MockFontBackend::new());
+    assert!(fonts.add_raw(FontId::new("Mock"), b"Mock Sans".to_vec(), 0).is_ok());
+    let instance = fonts.get_font_with_px_size("Mock Sans", Px(10)).unwrap();
+    let options = ShapingOptions::default();
+
+    let mut images = ImageCache::new(ImageKeysAPI::new(())).unwrap();
+    let png = include_bytes!("fixtures/Quantum.png").to_vec();
+    assert!(images.add_raw(ImageId::new("quantum"), png).is_ok());
+    let quantum = images.measure_image("quantum").unwrap();
+    let icon = InlineObject::from_dimensions(&quantum, InlineAlignment::Baseline);
+    assert_eq!(icon.width_64, quantum.width() as i32 * 64);
+
+    let small = InlineObject::new(8 * 64, 8 * 64, InlineAlignment::Baseline);
+    let text = "ab \u{FFFC}cd \u{FFFC}";
+    let layout = fonts
+        .layout_paragraph_with_objects(&instance, text, &[small, small], &options, None)
+        .unwrap();
+    assert_eq!(layout.lines.len(), 1);
+    let line = &layout.lines[0];
+    assert_eq!(line.objects.len(), 2);
+    assert_eq!(line.objects[0].text_offset, 3);
+    assert_eq!(line.objects[0].x_64, fonts.shape_text_h(&instance, "ab ").unwrap().width_64());
+    // Objects fitting under the ascender sit on the baseline without moving the text.
+    assert_eq!(line.objects[0].y_64, 0);
+    assert_eq!(line.glyphs.glyphs()[0].y_64(), 8 * 64);
+    assert_eq!(line.glyphs.glyphs().len(), 6);
+    assert!(line.glyphs.width_64() > line.objects[1].x_64);
+
+    // Taller objects push the baseline, and the glyphs, down.
+    let tall = InlineObject::new(10 * 64, 30 * 64, InlineAlignment::Baseline);
+    let layout = fonts
+        .layout_paragraph_with_objects(&instance, "a\u{FFFC}", &[tall], &options, None)
+        .unwrap();
+    let line = &layout.lines[0];
+    assert_eq!(line.objects[0].y_64, 0);
+    assert_eq!(line.glyphs.glyphs()[0].y_64(), 30 * 64);
+    assert!(line.glyphs.height_64() >= 30 * 64);
+
+    // Objects take part in line breaking.
+    let wide = InlineObject::new(40 * 64, 8 * 64, InlineAlignment::Middle);
+    let layout = fonts
+        .layout_paragraph_with_objects(&instance, "ab\u{FFFC}", &[wide], &options, Some(44 * 64))
+        .unwrap();
+    assert_eq!(layout.lines.len(), 2);
+    assert_eq!(layout.lines[1].objects[0].x_64, 0);
+    assert_eq!(layout.lines[1].offset_64, layout.lines[0].glyphs.height_64());
+
+    // Lines of objects wider than fits in 26.6 break instead of overflowing.
+    let huge = InlineObject::new(i32::max_value() / 2 + 64, 8 * 64, InlineAlignment::Baseline);
+    let layout = fonts
+        .layout_paragraph_with_objects(&instance, "\u{FFFC}\u{FFFC}", &[huge, huge], &options, Some(i32::max_value() - 1))
+        .unwrap();
+    assert_eq!(layout.lines.len(), 2);
+    assert_eq!(layout.lines[0].objects.len(), 1);
+    assert_eq!(layout.lines[1].objects[0].x_64, 0);
+}
+
+#[test]
+fn test_image_masks() {
+    let icon = vec![0, 0, 0, 255, 0, 0, 0, 128, 0, 0, 0, 0, 10, 20, 30, 64];
+    let icon = DecodedImage::from_raw_parts(ImagePixelFormat::RGBA(8), (2, 2), Arc::new(icon)).unwrap();
+    let opaque = vec![255, 255, 255, 0, 0, 0, 255, 0, 0];
+    let opaque = DecodedImage::from_raw_parts(ImagePixelFormat::RGB(8), (3, 1), Arc::new(opaque)).unwrap();
+
+    let (image_keys, receiver) = ImageKeysAPI::new(()).into_stream();
+    let mut images = ImageCache::new(image_keys).unwrap();
+    images.set_row_alignment(4);
+    let encoded = EncodedImage::from_bytes(icon.encode_png().unwrap()).unwrap();
+    assert!(images.add_image_with_options(ImageId::new("icon"), &encoded, ImageOptions::mask()).is_ok());
+
+    // Transparent icons are masked by their alpha, one byte per pixel.
+    let image = images.get_image("icon").unwrap();
+    assert_eq!(image.format(), ImagePixelFormat::Gray(8));
+    assert_eq!(image.row_stride(), 4);
+    assert_eq!(*image.pixels(), vec![255, 128, 0, 0, 0, 64, 0, 0]);
+    // The update carries the coverage with its rows packed.
+    match receiver.try_recv() {
+        Ok(Update::AddImageMask {
+            width,
+            height,
+            ref bytes,
+            ..
+        }) => {
+            assert_eq!((width, height), (2, 2));
+            assert_eq!(**bytes, vec![255, 128, 0, 64]);
+        }
+        _ => panic!("Expected an image mask update")
+    }
+
+    let encoded = EncodedImage::from_bytes(opaque.encode_png().unwrap()).unwrap();
+    let options = ImageOptions {
+        orientation: ImageOrientation::Rotate90,
+        mask: true
+    };
+    assert!(images.add_image_with_options(ImageId::new("opaque"), &encoded, options).is_ok());
+    let image = images.get_image("opaque").unwrap();
+    assert_eq!(*image.pixels(), vec![255, 0, 54, 0]);
+    assert_eq!(image.display_width(), 1);
+    match receiver.try_recv() {
+        Ok(Update::AddImageMask {
+            width,
+            ref bytes,
+            orientation,
+            ..
+        }) => {
+            assert_eq!(width, 3);
+            assert_eq!(**bytes, vec![255, 0, 54]);
+            assert_eq!(orientation, ImageOrientation::Rotate90);
+        }
+        _ => panic!("Expected an image mask update")
+    }
+
+    // Without the flag, images keep their color.
+    assert!(
+        images
+            .add_image_with_options(ImageId::new("color"), &encoded, ImageOptions::default())
+            .is_ok()
+    );
+    assert_eq!(images.get_image("color").unwrap().format(), ImagePixelFormat::RGBA(8));
+    match receiver.try_recv() {
+        Ok(Update::AddImage { .. }) => {}
+        _ => panic!("Expected an image update")
+    }
+}
+
+#[test]
+fn test_images_status() {
+    let image_bytes = include_bytes!("fixtures/Quantum.png");
+    let mut cache = ImageCache::new(ImageKeysAPI::new(())).unwrap();
+    assert_eq!(cache.status("Quantum"), ResourceStatus::Unknown);
+
+    assert!(cache.add_raw(ImageId::new("Quantum"), image_bytes.to_vec()).is_ok());
+    assert!(cache.status("Quantum").is_ready());
+
+    assert!(cache.add_raw(ImageId::new("Truncated"), image_bytes[..64].to_vec()).is_err());
+    match cache.status("Truncated") {
+        ResourceStatus::Failed(failure) => assert_eq!(failure.category, ErrorCategory::DecodeFailure),
+        status => panic!("Expected a failure, got {:?}", status)
+    }
+    // Adding an image twice isn't a failure of the image.
+    assert!(cache.add_raw(ImageId::new("Quantum"), image_bytes.to_vec()).is_err());
+    assert!(cache.status("Quantum").is_ready());
+
+    let deferred = EncodedImage::from_bytes(image_bytes.to_vec()).unwrap();
+    let deferred_id = ImageId::with_origin("https://a.example", "Deferred");
+    assert!(cache.add_deferred_image(deferred_id, &deferred).is_ok());
+    assert_eq!(cache.status_for_id(deferred_id), ResourceStatus::Pending);
+    assert_eq!(cache.evict_origin("https://a.example").unwrap(), 0);
+    assert_eq!(cache.status_for_id(deferred_id), ResourceStatus::Evicted);
+
+    cache.set_image_priority("Quantum", ResourcePriority::Discardable);
+    assert_eq!(cache.evict_bytes(ResourcePriority::Discardable, 1).unwrap().len(), 1);
+    assert_eq!(cache.status("Quantum"), ResourceStatus::Evicted);
+    assert!(cache.add_raw(ImageId::new("Quantum"), image_bytes.to_vec()).is_ok());
+    assert!(cache.status("Quantum").is_ready());
+
+    // Additions queued while the cache is borrowed are decoding until applied.
+    let mut images = SharedImages::from(cache);
+    {
+        let _guard = images.borrow();
+        let mut images = images.clone();
+        assert!(images.add_raw("Queued", image_bytes.to_vec()).is_some());
+        assert_eq!(images.status("Queued"), ResourceStatus::Decoding);
+    }
+    assert!(images.status("Queued").is_ready());
+    assert!(images.add_raw("Added", image_bytes.to_vec()).is_some());
+    assert!(images.status("Added").is_ready());
+}
+
+#[test]
+fn test_images_decoder_panic() {
+    // The PNM decoder overflows computing the buffer size, which panics in debug builds.
+    let pnm_bytes = b"P5 4294967295 4294967295 255\n\0".to_vec();
+    let image_bytes = include_bytes!("fixtures/Quantum.png");
+    let mut cache = ImageCache::new(ImageKeysAPI::new(())).unwrap();
+
+    match cache.add_raw(ImageId::new("Malformed"), pnm_bytes) {
+        Err(ImageError::DecoderPanic(_)) => assert!(cfg!(debug_assertions)),
+        result => assert!(result.is_err())
+    }
+    match cache.status("Malformed") {
+        ResourceStatus::Failed(failure) => assert_eq!(failure.category, ErrorCategory::DecodeFailure),
+        status => panic!("Expected a failure, got {:?}", status)
+    }
+    assert!(cache.get_image("Malformed").is_none());
+    assert_eq!(cache.error_counters().decode_failures, 1);
+
+    assert!(cache.add_raw(ImageId::new("Quantum"), image_bytes.to_vec()).is_ok());
+    assert_eq!(cache.iter_images().count(), 1);
+}
+
+#[test]
+fn test_images_batch_decode_ordering() {
+    let png_bytes = include_bytes!("fixtures/Quantum.png");
+    let jpg_bytes = include_bytes!("fixtures/Quantum.jpg");
+    let ids: Vec<ImageId> = (0..8).map(|i| ImageId::new(format!("Quantum{}", i))).collect();
+    let batch = || {
+        ids.iter()
+            .enumerate()
+            .map(|(i, &id)| (id, if i % 2 == 0 { png_bytes.to_vec() } else { jpg_bytes.to_vec() }))
+            .collect::<Vec<_>>()
+    };
+
+    let mut cache = ImageCache::new(ImageKeysAPI::new(())).unwrap();
+    let results = cache.add_raw_batch(batch(), DecodeOrdering::SubmissionOrder);
+    assert!(results.iter().all(Result::is_ok));
+
+    let keys: Vec<_> = ids.iter().map(|&id| cache.get_image_for_id(id).unwrap().external_key()).collect();
+    let updates: Vec<_> = cache
+        .take_resource_updates()
+        .updates
+        .into_iter()
+        .map(|update| match update {
+            Update::AddImage { key, .. } => key,
+            _ => panic!("Expected an image update")
+        })
+        .collect();
+    assert_eq!(updates, keys);
+
+    // Results stay in submission order, whatever order images finish in.
+    let mut cache = ImageCache::new(ImageKeysAPI::new(())).unwrap();
+    let mut images = batch();
+    images.insert(1, (ImageId::new("Truncated"), png_bytes[..64].to_vec()));
+    images.push((ids[0], png_bytes.to_vec()));
+    let results = cache.add_raw_batch(images, DecodeOrdering::AsCompleted);
+    assert_eq!(results.len(), 10);
+    assert!(results[1].is_err());
+    assert!(results[9].is_err());
+    assert_eq!(results.iter().filter(|result| result.is_ok()).count(), 8);
+    assert_eq!(cache.take_resource_updates().len(), 8);
+    assert!(cache.status("Truncated") != ResourceStatus::Ready);
+}
+
+#[test]
+fn test_images_tinted() {
+    let icon = vec![0, 0, 0, 255, 0, 0, 0, 0];
+    let icon = DecodedImage::from_raw_parts(ImagePixelFormat::RGBA(8), (2, 1), Arc::new(icon)).unwrap();
+    let opaque = vec![255, 255, 255, 0, 0, 0];
+    let opaque = DecodedImage::from_raw_parts(ImagePixelFormat::RGB(8), (2, 1), Arc::new(opaque)).unwrap();
+
+    let (image_keys, receiver) = ImageKeysAPI::new(()).into_stream();
+    let mut images = ImageCache::new(image_keys).unwrap();
+    assert!(images.add_raw(ImageId::new("icon"), icon.encode_png().unwrap()).is_ok());
+    assert!(images.add_raw(ImageId::new("opaque"), opaque.encode_png().unwrap()).is_ok());
+    assert_eq!(receiver.try_iter().count(), 2);
+
+    let purple = TintColor::new(200, 100, 200, 255);
+    let tinted = images.get_tinted_image("icon", purple).unwrap();
+    assert_eq!(tinted.format(), pixels::canonical_color_format());
+    assert_eq!(*tinted.pixels(), vec![200, 100, 200, 255, 200, 100, 200, 0]);
+    assert_ne!(tinted.external_key(), images.get_image("icon").unwrap().external_key());
+
+    // The coverage is sent once, and each color as a pixel drawn through it.
+    let updates: Vec<_> = receiver.try_iter().collect();
+    assert_eq!(updates.len(), 3);
+    assert!(updates.iter().any(|update| match update {
+        &Update::AddImageMask { .. } => true,
+        _ => false
+    }));
+    assert!(updates.iter().any(|update| match update {
+        &Update::LinkImageMask { key, .. } => key == tinted.external_key(),
+        _ => false
+    }));
+
+    // Variants are cached per color.
+    let again = images.get_tinted_image("icon", purple).unwrap();
+    assert!(Rc::ptr_eq(&tinted, &again));
+    assert_eq!(receiver.try_iter().count(), 0);
+    let faded = images
+        .get_tinted_image("icon", TintColor::new(200, 100, 200, 128))
+        .unwrap();
+    assert_eq!(*faded.pixels(), vec![200, 100, 200, 128, 200, 100, 200, 0]);
+    assert_eq!(receiver.try_iter().count(), 2);
+
+    // Opaque icons are tinted through their luminance.
+    let tinted = images.get_tinted_image("opaque", purple).unwrap();
+    assert_eq!(*tinted.pixels(), vec![200, 100, 200, 255, 200, 100, 200, 0]);
+
+    assert!(images.get_tinted_image("missing", purple).is_err());
+    assert_eq!(receiver.try_iter().count(), 3);
+
+    // Variants and their coverage go along with the icon.
+    images.set_image_priority("icon", ResourcePriority::Discardable);
+    assert_eq!(images.evict_bytes(ResourcePriority::Discardable, 1).unwrap().len(), 1);
+    let deleted = receiver
+        .try_iter()
+        .filter(|update| match update {
+            &Update::DeleteImage { .. } => true,
+            _ => false
+        })
+        .count();
+    assert_eq!(deleted, 4);
+    assert!(images.get_image_for_id(ImageId::new("icon").with_tint(purple)).is_none());
+    assert!(images.get_tinted_image("opaque", purple).is_ok());
+}
+
+#[test]
+fn test_images_masked() {
+    let png = |format, size: (u32, u32), pixels: Vec<u8>| {
+        let decoded = DecodedImage::from_raw_parts(format, size, Arc::new(pixels)).unwrap();
+        EncodedImage::from_bytes(decoded.encode_png().unwrap()).unwrap()
+    };
+    let color = png(ImagePixelFormat::RGB(8), (2, 2), vec![255, 0, 0, 0, 255, 0, 0, 0, 255, 255, 255, 255]);
+    let mask = png(ImagePixelFormat::Gray(8), (2, 2), vec![255, 0, 128, 255]);
+    let wide_mask = png(ImagePixelFormat::Gray(8), (4, 2), vec![255; 8]);
+
+    let mut cache = ImageCache::new(ImageKeysAPI::new(())).unwrap();
+    match cache.add_masked_image(ImageId::new("Wide"), &color, &wide_mask) {
+        Err(ImageError::InvalidMaskedImage) => {}
+        result => panic!("Expected mismatched sizes to fail, got {:?}", result)
+    }
+    assert!(cache.get_image("Wide").is_none());
+    assert!(cache.iter_images().next().is_none());
+
+    // Renderers drawing through masks get both images, linked.
+    assert!(cache.add_masked_image(ImageId::new("Masked"), &color, &mask).is_ok());
+    let image = cache.get_image("Masked").unwrap();
+    let mask_image = cache.get_image_mask("Masked").unwrap();
+    assert_eq!(mask_image.format(), ImagePixelFormat::Gray(8));
+    assert_eq!(*mask_image.pixels(), vec![255, 0, 128, 255]);
+    let mut images = SharedImages::from(cache);
+    let updates = images.take_resource_updates();
+    match updates.updates.last() {
+        Some(&Update::LinkImageMask { key, mask_key }) => {
+            assert_eq!(key, image.external_key());
+            assert_eq!(mask_key, mask_image.external_key());
+        }
+        update => panic!("Expected a mask link, got {:?}", update)
+    }
+    assert!(updates.is_ordered());
+
+    images
+        .borrow_mut()
+        .set_image_priority("Masked", ResourcePriority::Discardable);
+    assert_eq!(images.borrow_mut().evict_bytes(ResourcePriority::Discardable, 1).unwrap().len(), 1);
+    assert!(images.borrow().get_image_mask("Masked").is_none());
+    assert_eq!(images.take_resource_updates().len(), 2);
+
+    // Others get the mask composited into the alpha of the color image.
+    let mut cache = ImageCache::new(CopyingImageKeysAPI::new(())).unwrap();
+    assert!(cache.add_masked_image(ImageId::new("Masked"), &color, &mask).is_ok());
+    assert!(cache.get_image_mask("Masked").is_none());
+    let image = cache.get_image("Masked").unwrap();
+    let alphas: Vec<u8> = image.pixels().chunks(4).map(|pixel| pixel[3]).collect();
+    assert_eq!(alphas, vec![255, 0, 128, 255]);
+}
+
+#[test]
+fn test_keys_replay() {
+    let bytes = include_bytes!("fixtures/Quantum.png");
+    let encoded = EncodedImage::from_bytes(bytes.to_vec()).unwrap();
+    let mask = EncodedImage::from_bytes(
+        DecodedImage::from_raw_parts(ImagePixelFormat::Gray(8), (1, 1), Arc::new(vec![128]))
+            .unwrap()
+            .encode_png()
+            .unwrap()
+    ).unwrap();
+
+    let mut images = SharedImages::from(ImageCache::new(ImageKeysAPI::new(())).unwrap());
+    assert!(images.borrow_mut().add_image(ImageId::new("A"), &encoded).is_ok());
+    assert!(images.borrow_mut().add_image(ImageId::new("B"), &encoded).is_ok());
+    assert!(images.borrow_mut().add_masked_image(ImageId::new("M"), &mask, &mask).is_ok());
+    images.take_resource_updates();
+
+    // Snapshots survive a round trip through whatever persists them.
+    let snapshot = images.borrow().key_snapshot();
+    assert_eq!(snapshot.len(), 4);
+    let json = serde_json::to_string(&snapshot).unwrap();
+    assert_eq!(serde_json::from_str::<KeySnapshot<ImageId, DefaultImageKey>>(&json).unwrap(), snapshot);
+
+    // Only what the restarted renderer lacks is sent again, under the same keys.
+    let a_key = images.get_image("A").unwrap().external_key();
+    let mut known = KeySnapshot::new();
+    known.insert(ImageId::new("A"), a_key);
+    known.insert(ImageId::new("Gone"), DefaultImageKey(99));
+    let replayed = images.borrow_mut().replay_keys(&known).unwrap();
+    assert_eq!(replayed.len(), 3);
+    assert!(replayed.get(ImageId::new("A")).is_none());
+    let updates = images.take_resource_updates();
+    let keys: Vec<_> = updates
+        .updates
+        .iter()
+        .map(|update| match update {
+            &Update::AddImage { key, .. } | &Update::AddImageMask { key, .. } | &Update::DeleteImage { key } => key,
+            &Update::LinkImageMask { key, .. } => key,
+            update => panic!("Unexpected {:?}", update)
+        })
+        .collect();
+    assert_eq!(keys.len(), 5);
+    assert!(!keys.contains(&a_key));
+    assert!(keys.contains(&DefaultImageKey(99)));
+    assert!(updates.is_ordered());
+    assert_eq!(images.borrow().key_snapshot(), snapshot);
+
+    // Nothing is sent to renderers which kept everything.
+    assert!(images.borrow_mut().replay_keys(&snapshot).unwrap().is_empty());
+    assert_eq!(images.take_resource_updates().len(), 0);
+
+    // Relaunched caches don't hand out keys the renderer still holds.
+    let mut relaunched = ImageCache::new(ImageKeysAPI::new(())).unwrap();
+    relaunched.reserve_keys(&snapshot);
+    assert!(relaunched.add_image(ImageId::new("C"), &encoded).is_ok());
+    let key = relaunched.get_image("C").unwrap().external_key();
+    assert!(snapshot.keys().all(|reserved| reserved < key));
+
+    let font_keys = FontKeysAPI::new(());
+    let mut fonts = SharedFonts::from(FontCache::new(font_keys).unwrap());
+    let font_id = FontId::new("FreeSans");
+    assert!(fonts.borrow_mut().add_raw(font_id, include_bytes!("fixtures/FreeSans.ttf").to_vec(), 0).is_ok());
+    let instance = fonts
+        .borrow_mut()
+        .get_or_insert_font(FontInstanceId::from_family_str("FreeSans", Pt(16), Dpi(72)))
+        .unwrap();
+    fonts.take_resource_updates();
+
+    let snapshot = fonts.borrow().key_snapshot();
+    assert_eq!(snapshot.fonts.len(), 1);
+    assert_eq!(snapshot.instances.len(), 2);
+    let json = serde_json::to_string(&snapshot).unwrap();
+    assert_eq!(serde_json::from_str::<FontKeySnapshot<DefaultFontKey, DefaultFontInstanceKey>>(&json).unwrap(), snapshot);
+
+    // Instances are replayed along with their font, even when known.
+    let known = FontKeySnapshot {
+        fonts: KeySnapshot::new(),
+        instances: snapshot.instances.clone()
+    };
+    let replayed = fonts.borrow_mut().replay_keys(&known).unwrap();
+    assert_eq!(replayed, snapshot);
+    let updates = fonts.take_resource_updates();
+    assert_eq!(updates.len(), 3);
+    match updates.updates[0] {
+        Update::AddFont { key, .. } => assert_eq!(key, instance.external_key()),
+        ref update => panic!("Expected the font first, got {:?}", update)
+    }
+    assert!(updates.updates.iter().any(|update| match update {
+        &Update::AddFontInstance { instance_key, size, .. } => instance_key == instance.external_instance_key() && size == 16,
+        _ => false
+    }));
+}
+
+// Drops its references to the pixels once the updates are taken, like a
+// consumer copying them while applying the updates.
+#[derive(Debug, PartialEq)]
+struct CopyingImageKeysAPI {
+    inner: DefaultImageKeysAPI,
+    pending: Vec<Arc<Vec<u8>>>
+}
+
+impl TImageKeysAPI for CopyingImageKeysAPI {
+    type RootRendererAPI = ();
+    type ResourceUpdates = <DefaultImageKeysAPI as TImageKeysAPI>::ResourceUpdates;
+    type ImageKey = DefaultImageKey;
+
+    fn new(api: Self::RootRendererAPI) -> Self {
+        CopyingImageKeysAPI {
+            inner: DefaultImageKeysAPI::new(api),
+            pending: Vec::new()
+        }
+    }
+
+    fn add_image(&mut self, encoded: ImageEncodedData, decoded: ImageResourceData) -> Self::ImageKey {
+        self.pending.push(Arc::clone(decoded.pixels));
+        self.inner.add_image(encoded, decoded)
+    }
+
+    fn take_resource_updates(&mut self) -> Self::ResourceUpdates {
+        self.pending.clear();
+        self.inner.take_resource_updates()
+    }
+}
+
+impl TImageKeysAPIExt for CopyingImageKeysAPI {
+    fn add_image_with_orientation(&mut self, encoded: ImageEncodedData, decoded: ImageResourceData, orientation: ImageOrientation) -> Self::ImageKey {
+        self.pending.push(Arc::clone(decoded.pixels));
+        self.inner
+            .add_image_with_orientation(encoded, decoded, orientation)
+    }
+
+    fn copies_pixels_synchronously(&self) -> bool {
+        true
+    }
+
+    fn delete_image(&mut self, key: Self::ImageKey) {
+        self.inner.delete_image(key)
+    }
+}
+
+#[test]
+fn test_images_staging_buffers() {
+    let image_bytes = Rc::new(include_bytes!("fixtures/Quantum.png").to_vec());
+
+    let mut images = ImageCache::new(ImageKeysAPI::new(())).unwrap();
+    images.set_staging_buffers(true);
+    assert!(!images.staging_buffers());
+
+    let mut cache = ImageCache::new(CopyingImageKeysAPI::new(())).unwrap();
+    cache.set_staging_buffers(true);
+    assert!(cache.staging_buffers());
+    let mut images = SharedImages::from(cache);
+
+    assert!(images.add_raw("First", Rc::clone(&image_bytes)).is_some());
+    let image = images.get_image("First").unwrap();
+    assert_eq!((image.width(), image.height()), (512, 529));
+    assert!(image.pixels().is_empty());
+    assert_eq!(images.borrow().used_bytes(), 0);
+    let stats = images.borrow().staging_stats().unwrap();
+    assert_eq!((stats.allocated, stats.reused, stats.in_flight), (1, 0, 1));
+
+    // Buffers are reclaimed once the consumer applied the updates referencing them.
+    assert_eq!(images.take_resource_updates().len(), 1);
+    assert_eq!(images.ack_resource_updates(), 1);
+    let stats = images.borrow().staging_stats().unwrap();
+    assert_eq!((stats.in_flight, stats.free), (0, 2));
+
+    assert!(images.add_raw("Second", Rc::clone(&image_bytes)).is_some());
+    let stats = images.borrow().staging_stats().unwrap();
+    assert_eq!((stats.allocated, stats.reused, stats.in_flight), (1, 1, 1));
+
+    // Images without pixels of their own are resized from their encoded data.
+    let sized = images
+        .borrow_mut()
+        .get_image_at_size("Second", (256, 256))
+        .unwrap();
+    assert_eq!((sized.width(), sized.height()), (248, 256));
+    assert!(!sized.pixels().is_empty());
+}
+
+#[test]
+fn test_image_arrays() {
+    let tile = |value: u8| {
+        let pixels = Arc::new(vec![value; 2 * 2 * 4]);
+        let decoded = DecodedImage::from_raw_parts(ImagePixelFormat::RGBA(8), (2, 2), pixels).unwrap();
+        decoded.encode_png().unwrap()
+    };
+    let (image_keys, receiver) = ImageKeysAPI::new(()).into_stream();
+    let mut images = ImageCache::new(image_keys).unwrap();
+
+    let origin = "https://tiles.example";
+    let layers = (0..3).map(|i| (ImageId::with_origin(origin, format!("tile{}", i)), tile(i * 100)));
+    assert!(images.add_image_array(ImageId::new("tiles"), layers).is_ok());
+
+    match receiver.try_recv() {
+        Ok(Update::AddImageArray { width, height, layers, .. }) => assert_eq!((width, height, layers), (2, 2, 3)),
+        _ => panic!("Expected an image array update")
+    }
+    let updates: Vec<_> = receiver.try_iter().collect();
+    assert_eq!(updates.len(), 3);
+    match updates[2] {
+        Update::AddImageArrayLayer { layer, .. } => assert_eq!(layer, 2),
+        _ => panic!("Expected an image array layer update")
+    }
+
+    let layer_ids = images
+        .image_array_layers(ImageId::new("tiles"))
+        .unwrap()
+        .to_vec();
+    let first = images.get_image_for_id(layer_ids[0]).unwrap();
+    let last = images.get_image_for_id(layer_ids[2]).unwrap();
+    assert_eq!(first.layer(), Some(0));
+    assert_eq!(last.layer(), Some(2));
+    assert_eq!(first.external_key(), last.external_key());
+    assert_eq!(last.pixels()[0], 200);
+
+    // Layers must match the first one, otherwise nothing is added.
+    let odd = DecodedImage::from_raw_parts(ImagePixelFormat::RGBA(8), (3, 1), Arc::new(vec![0; 12])).unwrap();
+    let mismatched = vec![(ImageId::new("a"), tile(0)), (ImageId::new("b"), odd.encode_png().unwrap())];
+    assert!(images.add_image_array(ImageId::new("mismatched"), mismatched).is_err());
+    assert!(images.get_image("a").is_none());
+    assert!(receiver.try_recv().is_err());
+
+    // The shared key is only deleted along with the last layer.
+    assert_eq!(images.evict_origin(origin).unwrap(), 3);
+    assert!(images.image_array_layers(ImageId::new("tiles")).is_none());
+    let deleted: Vec<_> = receiver.try_iter().collect();
+    assert_eq!(deleted, vec![Update::DeleteImage { key: first.external_key() }]);
+}
+
+#[test]
+fn test_image_row_alignment() {
+    let pixels = Arc::new(vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12]);
+    let decoded = DecodedImage::from_raw_parts(ImagePixelFormat::RGB(8), (2, 2), pixels).unwrap();
+    assert_eq!(decoded.row_stride, 6);
+
+    let padded = decoded.with_row_alignment(8);
+    assert_eq!(padded.row_stride, 8);
+    assert_eq!(
+        *padded.pixels,
+        vec![1, 2, 3, 4, 5, 6, 0, 0, 7, 8, 9, 10, 11, 12, 0, 0]
+    );
+    assert_eq!(
+        padded.packed_pixels(),
+        vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12]
+    );
+    assert_eq!(padded.with_row_alignment(1).row_stride, 6);
+
+    let image_bytes = Rc::new(include_bytes!("fixtures/Quantum.png").to_vec());
+
+    let mut packed_cache = ImageCache::new(ImageKeysAPI::new(())).unwrap();
+    assert!(
+        packed_cache
+            .add_raw(ImageId::new("Quantum"), Rc::clone(&image_bytes))
+            .is_ok()
+    );
+
+    let mut padded_cache = ImageCache::new(ImageKeysAPI::new(())).unwrap();
+    padded_cache.set_row_alignment(768);
+    assert!(
+        padded_cache
+            .add_raw(ImageId::new("Quantum"), Rc::clone(&image_bytes))
+            .is_ok()
+    );
+
+    let packed = packed_cache.get_image("Quantum").unwrap();
+    let padded = padded_cache.get_image("Quantum").unwrap();
+    assert_eq!(packed.row_stride(), 512 * 4);
+    assert_eq!(padded.row_stride(), 2304);
+    assert_eq!(padded.pixels().len(), 2304 * 529);
+    assert_eq!(padded.to_data_uri().unwrap(), packed.to_data_uri().unwrap());
+}
+
+#[test]
+fn test_image_at_size() {
+    let (image_keys, receiver) = ImageKeysAPI::new(()).into_stream();
+    let mut images = ImageCache::new(image_keys).unwrap();
+
+    let jpeg_bytes = Rc::new(include_bytes!("fixtures/Quantum.jpg").to_vec());
+    let encoded = EncodedImage::from_bytes(jpeg_bytes).unwrap();
+    assert!(images.add_deferred_image(ImageId::new("Photo"), &encoded).is_ok());
+    assert!(images.get_image("Photo").is_none());
+    assert!(receiver.try_recv().is_err());
+
+    let thumbnail = images.get_image_at_size("Photo", (64, 64)).unwrap();
+    assert_eq!((thumbnail.width(), thumbnail.height()), (62, 64));
+    assert_eq!(thumbnail.format(), pixels::canonical_color_format());
+    assert_eq!(thumbnail.pixels().len(), 62 * 64 * 4);
+    assert_eq!(receiver.try_iter().count(), 1);
+
+    let cached = images.get_image_at_size("Photo", (64, 64)).unwrap();
+    assert!(Rc::ptr_eq(&thumbnail, &cached));
+    assert!(receiver.try_recv().is_err());
+
+    let image_bytes = Rc::new(include_bytes!("fixtures/Quantum.png").to_vec());
+    assert!(images.add_raw(ImageId::new("Quantum"), image_bytes).is_ok());
+    let downsampled = images.get_image_at_size("Quantum", (100, 100)).unwrap();
+    assert_eq!((downsampled.width(), downsampled.height()), (97, 100));
+    assert_ne!(
+        downsampled.external_key(),
+        images.get_image("Quantum").unwrap().external_key()
+    );
+
+    let full = images.get_image_at_size("Quantum", (1024, 1024)).unwrap();
+    assert_eq!((full.width(), full.height()), (512, 529));
+
+    assert!(images.get_image_at_size("Missing", (64, 64)).is_err());
+}
+
+#[test]
+fn test_image_object_fit() {
+    let contain = object_fit((400, 200), (100.0, 100.0), ObjectFit::Contain);
+    assert_eq!(contain.source, FitRect::new(0.0, 0.0, 400.0, 200.0));
+    assert_eq!(contain.destination, FitRect::new(0.0, 25.0, 100.0, 50.0));
+    assert_eq!(contain.resized_size, Some((100, 50)));
+
+    let cover = object_fit((400, 200), (100.0, 100.0), ObjectFit::Cover);
+    assert_eq!(cover.source, FitRect::new(100.0, 0.0, 200.0, 200.0));
+    assert_eq!(cover.destination, FitRect::new(0.0, 0.0, 100.0, 100.0));
+    assert!(!cover.needs_resized_variant());
+
+    let fill = object_fit((400, 200), (100.0, 40.0), ObjectFit::Fill);
+    assert_eq!(fill.scale, (0.25, 0.2));
+    assert_eq!(fill.destination, FitRect::new(0.0, 0.0, 100.0, 40.0));
+    assert_eq!(fill.resized_size, Some((100, 50)));
+
+    let scale_down = object_fit((40, 20), (100.0, 100.0), ObjectFit::ScaleDown);
+    assert_eq!(scale_down.destination, FitRect::new(30.0, 40.0, 40.0, 20.0));
+    assert!(!scale_down.needs_resized_variant());
+
+    let none = object_fit((400, 200), (100.0, 100.0), ObjectFit::None);
+    assert_eq!(none.source, FitRect::new(150.0, 50.0, 100.0, 100.0));
+    assert_eq!(none.destination, FitRect::new(0.0, 0.0, 100.0, 100.0));
+
+    let empty = object_fit((400, 200), (0.0, 100.0), ObjectFit::Contain);
+    assert!(empty.destination.is_empty());
+    assert!(!empty.needs_resized_variant());
+
+    let (image_keys, _) = ImageKeysAPI::new(()).into_stream();
+    let mut images = ImageCache::new(image_keys).unwrap();
+    let image_bytes = Rc::new(include_bytes!("fixtures/Quantum.png").to_vec());
+    assert!(images.add_raw(ImageId::new("Quantum"), image_bytes).is_ok());
+
+    let image = images.get_image("Quantum").unwrap();
+    let layout = image.object_fit((100.0, 100.0), ObjectFit::Contain);
+    let resized_size = layout.resized_size.unwrap();
+    let resized = images.get_image_at_size("Quantum", resized_size).unwrap();
+    assert_eq!((resized.width(), resized.height()), (97, 100));
+}
+
+#[test]
+fn test_image_animation_clock() {
+    let frame = |offset, color: u8, delay_ms, disposal| AnimationFrame {
+        offset,
+        image: DecodedImage::from_raw_parts(ImagePixelFormat::RGBA(8), (1, 1), Arc::new(vec![color, color, color, 255])).unwrap(),
+        delay_ms,
+        disposal
+    };
+
+    assert!(AnimatedImage::new((2, 1), vec![], LoopCount::Infinite).is_err());
+    assert!(
+        AnimatedImage::new(
+            (2, 1),
+            vec![frame((2, 0), 1, 100, FrameDisposal::Keep)],
+            LoopCount::Infinite
+        ).is_err()
+    );
+
+    let animation = AnimatedImage::new(
+        (2, 1),
+        vec![
+            frame((0, 0), 1, 100, FrameDisposal::Previous),
+            frame((1, 0), 2, 0, FrameDisposal::Background),
+            frame((0, 0), 3, 50, FrameDisposal::Keep),
+        ],
+        LoopCount::Finite(2)
+    ).unwrap();
+    assert_eq!(animation.loop_duration_ms(), 250);
+
+    let mut clock = AnimationClock::new();
+    assert!(clock.current_image(&animation).is_none());
+
+    let tick = clock.tick(&animation, 1000);
+    assert_eq!(
+        tick,
+        AnimationTick {
+            frame_index: 0,
+            needs_update: true,
+            finished: false,
+            next_update_in_ms: Some(100)
+        }
+    );
+    assert_eq!(
+        *clock.current_image(&animation).unwrap().pixels,
+        vec![1, 1, 1, 255, 0, 0, 0, 0]
+    );
+
+    let tick = clock.tick(&animation, 1050);
+    assert_eq!(tick.frame_index, 0);
+    assert!(!tick.needs_update);
+    assert_eq!(tick.next_update_in_ms, Some(50));
+
+    let tick = clock.tick(&animation, 1150);
+    assert_eq!(tick.frame_index, 1);
+    assert!(tick.needs_update);
+    assert_eq!(
+        *clock.current_image(&animation).unwrap().pixels,
+        vec![0, 0, 0, 0, 2, 2, 2, 255]
+    );
+
+    let tick = clock.tick(&animation, 1200);
+    assert_eq!(tick.frame_index, 2);
+    assert_eq!(
+        *clock.current_image(&animation).unwrap().pixels,
+        vec![3, 3, 3, 255, 0, 0, 0, 0]
+    );
+
+    let tick = clock.tick(&animation, 1300);
+    assert_eq!(tick.frame_index, 0);
+    assert!(tick.needs_update);
+    assert_eq!(
+        *clock.current_image(&animation).unwrap().pixels,
+        vec![1, 1, 1, 255, 0, 0, 0, 0]
+    );
+
+    let tick = clock.tick(&animation, 1600);
+    assert_eq!(
+        tick,
+        AnimationTick {
+            frame_index: 2,
+            needs_update: true,
+            finished: true,
+            next_update_in_ms: None
+        }
+    );
+}
+
+#[test]
+fn test_resource_group_display_profile() {
+    assert_eq!(DisplayProfile::STANDARD.font_dpi(), Dpi(72));
+    assert_eq!(DisplayProfile::HIDPI.font_dpi(), Dpi(144));
+    assert_eq!(DisplayProfile::with_device_pixel_ratio(Scale(1.5)).font_dpi(), Dpi(108));
+    assert_eq!(Pt(12).to_px_64(Dpi(96)), Px(16).to_px_64());
+    assert_eq!(Px(16).scale(Scale(1.5)), Px(24));
+    assert_eq!(Dpi::PIXEL, PIXEL_SIZE_DPI);
+    assert!(DisplayProfile::STANDARD.prefers_subpixel());
+    assert!(!DisplayProfile::MOBILE_HIDPI.prefers_subpixel());
+
+    let files = FileCache::new().unwrap();
+    let images = ImageCache::new(ImageKeysAPI::new(())).unwrap();
+    let mut fonts = FontCache::new(FontKeysAPI::new(())).unwrap();
+
+    let font_bytes = Rc::new(include_bytes!("fixtures/FreeSans.ttf").to_vec());
+    assert!(fonts.add_raw(FontId::new("FreeSans"), font_bytes, 0).is_ok());
+
+    let mut group = ResourceGroup::new(files, images, fonts);
+    assert_eq!(group.display_profile(), DisplayProfile::STANDARD);
+
+    let profile = DisplayProfile {
+        subpixel_layout: SubpixelLayout::Vertical,
+        ..DisplayProfile::HIDPI
+    };
+    group.set_display_profile(profile);
+    assert_eq!(group.display_profile(), profile);
+    assert_eq!(group.images().borrow().device_pixel_ratio(), Scale(2.0));
+
+    let fonts = group.fonts();
+    let default_font = fonts.get_default_font().unwrap();
+    assert_eq!(
+        default_font.load_policy().raster_lcd.target,
+        RenderTarget::LCDVertical
+    );
+
+    let instance = fonts
+        .borrow_mut()
+        .get_default_font_for_display(Pt(16))
+        .unwrap();
+    assert_eq!(instance.size(), 16);
+    assert_eq!(instance.dpi(), Dpi(144));
+    assert_eq!(instance.load_policy(), GlyphLoadPolicy::for_display(&profile));
+}
+
+#[test]
+fn test_resource_group_byte_quota() {
+    let files = FileCache::new().unwrap();
+    let mut images = ImageCache::new(ImageKeysAPI::new(())).unwrap();
+    let mut fonts = FontCache::new(FontKeysAPI::new(())).unwrap();
+
+    for src in &["Logo", "Photo", "Thumbnail 1", "Thumbnail 2"] {
+        let image_bytes = Rc::new(include_bytes!("fixtures/Quantum.png").to_vec());
+        assert!(images.add_raw(ImageId::new(src), image_bytes).is_ok());
+    }
+    images.set_image_priority("Logo", ResourcePriority::Pinned);
+    images.set_image_priority("Thumbnail 1", ResourcePriority::Discardable);
+    images.set_image_priority("Thumbnail 2", ResourcePriority::Discardable);
+    assert_eq!(images.image_priority("Photo"), ResourcePriority::Normal);
+    let image_bytes = images.get_image("Logo").unwrap().pixels().len();
+
+    let sans_bytes = Rc::new(include_bytes!("fixtures/FreeSans.ttf").to_vec());
+    let mono_bytes = Rc::new(include_bytes!("fixtures/FiraMono-Regular.ttf").to_vec());
+    let (sans_len, mono_len) = (sans_bytes.len(), mono_bytes.len());
+    assert!(fonts.add_raw(FontId::new("FreeSans"), sans_bytes, 0).is_ok());
+    assert!(fonts.add_raw(FontId::new("FiraMono"), mono_bytes, 0).is_ok());
+    fonts.set_font_priority(FontId::new("FreeSans"), ResourcePriority::Pinned);
+
+    let mut group = ResourceGroup::new(files, images, fonts);
+    let total = image_bytes * 4 + sans_len + mono_len;
+    assert_eq!(group.used_bytes(), total);
+    assert_eq!(group.enforce_byte_quota().freed_bytes, 0);
+
+    group.set_byte_quota(Some(total - image_bytes - 1));
+    let report = group.enforce_byte_quota();
+    assert_eq!(report.freed_bytes, image_bytes * 2);
+    assert_eq!(report.used_bytes, group.used_bytes());
+    assert!(report.evicted_fonts.is_empty());
+    assert!(group.images().borrow().get_image("Thumbnail 1").is_none());
+    assert!(group.images().borrow().get_image("Photo").is_some());
+
+    group.set_byte_quota(Some(0));
+    let report = group.enforce_byte_quota();
+    assert_eq!(report.evicted_images, vec![ImageId::new("Photo")]);
+    assert_eq!(report.evicted_fonts, vec![FontId::new("FiraMono")]);
+    assert_eq!(report.used_bytes, image_bytes + sans_len);
+    assert!(group.images().borrow().get_image("Logo").is_some());
+    assert!(group.fonts().get_default_font().is_some());
+
+    let updates = group.images().take_resource_updates();
+    assert_eq!(
+        updates
+            .updates
+            .iter()
+            .filter(|update| match update {
+                &&Update::DeleteImage { .. } => true,
+                _ => false
+            })
+            .count(),
+        3
+    );
+}
+
+#[test]
+fn test_resource_group_expiry() {
+    let files = FileCache::new().unwrap();
+    let mut images = ImageCache::new(ImageKeysAPI::new(())).unwrap();
+    let mut fonts = FontCache::new(FontKeysAPI::new(())).unwrap();
+
+    let avatar_id = ImageId::with_origin("https://avatars.example", "Avatar");
+    let pinned_id = ImageId::with_origin("https://avatars.example", "Pinned");
+    for &id in &[ImageId::new("Logo"), avatar_id, pinned_id] {
+        let image_bytes = Rc::new(include_bytes!("fixtures/Quantum.png").to_vec());
+        assert!(images.add_raw(id, image_bytes).is_ok());
+    }
+    images.set_image_priority_for_id(pinned_id, ResourcePriority::Pinned);
+    let mut policy = ExpiryPolicy::new();
+    policy.set_origin_ttl_ms("https://avatars.example", Some(600_000));
+    images.set_expiry_policy(policy);
+
+    let font_bytes = Rc::new(include_bytes!("fixtures/FreeSans.ttf").to_vec());
+    assert!(fonts.add_raw(FontId::new("FreeSans"), font_bytes, 0).is_ok());
+    let mut policy = ExpiryPolicy::new();
+    policy.set_default_ttl_ms(Some(1_000));
+    fonts.set_expiry_policy(policy);
+    assert_eq!(fonts.expiry_policy().default_ttl_ms(), Some(1_000));
+
+    // TTLs start at the first sweep which sees the resources.
+    let mut group = ResourceGroup::new(files, images, fonts);
+    assert_eq!(group.sweep_expired(5_000), QuotaReport { used_bytes: group.used_bytes(), ..QuotaReport::default() });
+
+    let report = group.sweep_expired(6_000);
+    assert_eq!(report.evicted_fonts, vec![FontId::new("FreeSans")]);
+    assert!(report.evicted_images.is_empty());
+    assert!(group.fonts().get_default_font().is_none());
+
+    let report = group.sweep_expired(605_000);
+    assert_eq!(report.evicted_images, vec![avatar_id]);
+    assert_eq!(report.freed_bytes, group.images().borrow().get_image("Logo").unwrap().pixels().len());
+    assert!(group.images().borrow().get_image_for_id(pinned_id).is_some());
+    assert!(group.sweep_expired(10_000_000).evicted_images.is_empty());
+
+    let updates = group.images().take_resource_updates();
+    assert_eq!(
+        updates
+            .updates
+            .iter()
+            .filter(|update| match update {
+                &&Update::DeleteImage { .. } => true,
+                _ => false
+            })
+            .count(),
+        1
+    );
+}
+
+#[test]
+fn test_fonts_glyph_transform() {
+    let (font_keys, receiver) = FontKeysAPI::new(()).into_stream();
+    let mut fonts = FontCache::new(font_keys).unwrap();
+
+    let font_bytes = Rc::new(include_bytes!("fixtures/FreeSans.ttf").to_vec());
+    assert!(fonts.add_raw(FontId::new("FreeSans"), font_bytes, 0).is_ok());
+    assert_eq!(receiver.try_iter().count(), 2);
+
+    let plain_id = FontInstanceId::from_family_str("FreeSans", Pt(16), Dpi(72));
+    let condensed_id = plain_id.with_transform(GlyphTransform::scale(0.5, 1.0));
+    assert_ne!(plain_id, condensed_id);
+    assert_eq!(condensed_id.resize(Pt(24)).transform(), condensed_id.transform());
+
+    assert!(fonts.get_or_insert_font(condensed_id).is_none());
+    let condensed = fonts
+        .get_or_insert_transformed_font(condensed_id)
+        .unwrap();
+    let plain = fonts.get_or_insert_font(plain_id).unwrap();
+    assert_ne!(plain, condensed);
+    assert_eq!(condensed.transform(), GlyphTransform::scale(0.5, 1.0));
+
+    match receiver.try_recv() {
+        Ok(Update::AddFontInstance { transform, .. }) => assert_eq!(transform, GlyphTransform::scale(0.5, 1.0)),
+        _ => panic!("Expected a font instance update")
+    }
+
+    let plain_dimensions = fonts.get_glyph_dimensions(&plain, 'W').unwrap();
+    let condensed_dimensions = fonts.get_glyph_dimensions(&condensed, 'W').unwrap();
+    assert_eq!(
+        condensed_dimensions.hori_advance_64,
+        plain_dimensions.hori_advance_64 / 2
+    );
+    assert_eq!(
+        condensed_dimensions.vert_advance_64,
+        plain_dimensions.vert_advance_64
+    );
+
+    let plain_bitmap = fonts.get_glyph_bitmap(&plain, 'W').unwrap();
+    let condensed_bitmap = fonts.get_glyph_bitmap(&condensed, 'W').unwrap();
+    assert!(condensed_bitmap.size.0 < plain_bitmap.size.0);
+    assert_eq!(condensed_bitmap.size.1, plain_bitmap.size.1);
+
+    let italic_id = plain_id.with_transform(GlyphTransform::shear(0.25));
+    let italic = fonts.get_or_insert_transformed_font(italic_id).unwrap();
+    let italic_bitmap = fonts.get_glyph_bitmap(&italic, 'l').unwrap();
+    assert!(italic_bitmap.size.0 > fonts.get_glyph_bitmap(&plain, 'l').unwrap().size.0);
+    assert_eq!(
+        fonts.get_glyph_dimensions(&italic, 'l').unwrap().hori_advance_64,
+        fonts.get_glyph_dimensions(&plain, 'l').unwrap().hori_advance_64
+    );
+    // Shears move vertical runs sideways, to the left going down.
+    let italic_dimensions = fonts.get_glyph_dimensions(&italic, 'l').unwrap();
+    assert_eq!(italic_dimensions.hori_advance_y_64, 0);
+    assert_eq!(italic_dimensions.vert_advance_x_64, -italic_dimensions.vert_advance_64 / 4);
+
+    // Rotated runs advance upwards rather than to the right.
+    let rotated_id = plain_id.with_transform(GlyphTransform::rotation(::std::f32::consts::FRAC_PI_2));
+    let rotated = fonts.get_or_insert_transformed_font(rotated_id).unwrap();
+    let rotated_dimensions = fonts.get_glyph_dimensions(&rotated, 'W').unwrap();
+    assert_eq!(rotated_dimensions.hori_advance_64, 0);
+    assert_eq!(rotated_dimensions.hori_advance_y_64, -plain_dimensions.hori_advance_64);
+    let glyphs = fonts.shape_text_h(&rotated, "WW").unwrap();
+    assert_eq!(glyphs.glyphs()[1].x_64(), glyphs.glyphs()[0].x_64());
+    assert_eq!(
+        glyphs.glyphs()[1].y_64() - glyphs.glyphs()[0].y_64(),
+        -plain_dimensions.hori_advance_64
+    );
+}
+
+#[test]
+fn test_fonts_pixel_sizes() {
+    let (font_keys, receiver) = FontKeysAPI::new(()).into_stream();
+    let mut fonts = FontCache::new(font_keys).unwrap();
+
+    let font_bytes = Rc::new(include_bytes!("fixtures/FreeSans.ttf").to_vec());
+    assert!(fonts.add_raw(FontId::new("FreeSans"), font_bytes, 0).is_ok());
+    assert_eq!(receiver.try_iter().count(), 2);
+
+    let px_id = FontInstanceId::from_family_px("FreeSans", Px(16));
+    assert_eq!(px_id.unit(), FontSizeUnit::Pixels);
+    assert_eq!(px_id.dpi(), PIXEL_SIZE_DPI);
+    assert_eq!(px_id.resize_px(Px(24)).dpi(), PIXEL_SIZE_DPI);
+    assert_eq!(px_id.resize_dpi(Pt(24), Dpi(144)).unit(), FontSizeUnit::Points);
+    assert_ne!(px_id, FontInstanceId::from_family_str("FreeSans", Pt(16), Dpi(72)));
+
+    let px = fonts.get_font_with_px_size("FreeSans", Px(16)).unwrap();
+    assert_eq!(px.unit(), FontSizeUnit::Pixels);
+    assert_eq!(px.pixel_size_64(), 16 * 64);
+    match receiver.try_recv() {
+        Ok(Update::AddFontInstance { size, .. }) => assert_eq!(size, 16),
+        _ => panic!("Expected a font instance update")
+    }
+
+    // 12pt at 96 dpi and 16px describe the same em size.
+    let pt = fonts
+        .get_or_insert_font(FontInstanceId::from_family_str("FreeSans", Pt(12), Dpi(96)))
+        .unwrap();
+    assert_eq!(pt.unit(), FontSizeUnit::Points);
+    assert_eq!(pt.pixel_size_64(), px.pixel_size_64());
+    let px_metrics = fonts.get_global_size_metrics(&px).unwrap();
+    assert_eq!(px_metrics.nominal_height, 16);
+    assert_eq!(px_metrics, fonts.get_global_size_metrics(&pt).unwrap());
+    assert_eq!(
+        fonts.get_glyph_bitmap(&px, 'W').unwrap().size,
+        fonts.get_glyph_bitmap(&pt, 'W').unwrap().size
+    );
+
+    fonts.set_display_profile(DisplayProfile::HIDPI);
+    let display = fonts.get_default_font_for_display_px(Px(16)).unwrap();
+    assert_eq!(display.size(), 32);
+    assert_eq!(display.unit(), FontSizeUnit::Pixels);
+}
+
+#[cfg(feature = "shared-shaping-cache")]
+#[test]
+fn test_fonts_shared_shaping_cache() {
+    let font_bytes = Rc::new(include_bytes!("fixtures/FreeSans.ttf").to_vec());
+    let mut main = FontCache::new(FontKeysAPI::new(())).unwrap();
+    let mut popup = FontCache::new(FontKeysAPI::new(())).unwrap();
+    assert!(main.add_raw(FontId::new("FreeSans"), Rc::clone(&font_bytes), 0).is_ok());
+    assert!(popup.add_raw(FontId::new("Popup Sans"), font_bytes, 0).is_ok());
+
+    let main_instance = main.get_default_font_with_size(Pt(17)).unwrap();
+    let popup_instance = popup.get_default_font_with_size(Pt(17)).unwrap();
+    let cache = SharedShapingCache::global();
+
+    let text = "Shared across resource groups";
+    let main_glyphs = main.shape_text_h(&main_instance, text).unwrap();
+    let hits = cache.stats().hits;
+    let popup_glyphs = popup.shape_text_h(&popup_instance, text).unwrap();
+    assert!(cache.stats().hits > hits);
+    assert_eq!(popup_glyphs.width_64(), main_glyphs.width_64());
+    assert_eq!(popup_glyphs.glyphs().len(), main_glyphs.glyphs().len());
+    assert!(cache.len() > 0);
+
+    // A different size is a different shaping identity.
+    let larger = popup.get_default_font_with_size(Pt(18)).unwrap();
+    assert!(popup.shape_text_h(&larger, text).unwrap().width_64() > main_glyphs.width_64());
+}
+
+#[cfg(feature = "harfbuzz")]
+#[test]
+fn test_fonts_harfbuzz_shaping() {
+    let mut fonts = FontCache::new(FontKeysAPI::new(())).unwrap();
+    assert!(fonts.add_raw(FontId::new("FreeSans"), include_bytes!("fixtures/FreeSans.ttf").to_vec(), 0).is_ok());
+    fonts.set_glyph_source_mode(GlyphSourceMode::Text);
+    let instance = fonts.get_font_with_px_size("FreeSans", Px(32)).unwrap();
+
+    // Same output type, glyphs from the same cmap.
+    let glyphs = fonts.shape_text_h(&instance, "Hello").unwrap();
+    assert_eq!(glyphs.glyphs().len(), 5);
+    assert_eq!(glyphs.glyphs()[0].glyph_index(), 43);
+    assert!(glyphs.width_64() > 0);
+    assert!(glyphs.glyphs().windows(2).all(|pair| pair[0].x_64() < pair[1].x_64()));
+
+    // Combining marks share their base's cluster.
+    let composed = fonts.shape_text_h(&instance, "e\u{301}\u{302}").unwrap();
+    let source = composed.source().unwrap();
+    assert!(source.offsets.iter().all(|&offset| (offset as usize) < "e\u{301}\u{302}".len()));
+    assert_eq!(source.glyph_count(), composed.glyphs().len());
+
+    let vertical = fonts.shape_text_v(&instance, "Hello").unwrap();
+    assert!(vertical.height_64() > 0);
+    assert!(vertical.glyphs().windows(2).all(|pair| pair[0].y_64() < pair[1].y_64()));
+}
+
+#[cfg(feature = "harfbuzz")]
+#[test]
+fn test_fonts_shaping_strategy() {
+    let mut fonts = FontCache::new(FontKeysAPI::new(())).unwrap();
+    assert!(fonts.add_raw(FontId::new("FreeSans"), include_bytes!("fixtures/FreeSans.ttf").to_vec(), 0).is_ok());
+    assert!(fonts.add_raw(FontId::new("Fira Mono"), include_bytes!("fixtures/FiraMono-Regular.ttf").to_vec(), 0).is_ok());
+    let instance = fonts.get_font_with_px_size("FreeSans", Px(32)).unwrap();
+    assert_eq!(instance.shaping_strategy(), ShapingStrategy::Auto);
+
+    // Latin text kerned through the kern table takes the simple path.
+    let simple = fonts.shape_text_h(&instance, "AVA").unwrap();
+    assert!(fonts.shape_text_h(&instance, "\u{915}\u{94D}\u{937}").is_ok());
+    assert!(fonts.shape_text_h(&instance, "\u{5E9}\u{5DC}\u{5D5}\u{5DD}").is_ok());
+    assert_eq!(
+        instance.reset_shaping_counters(),
+        ShapingCounters {
+            simple_runs: 1,
+            complex_runs: 2
+        }
+    );
+
+    // Cached runs aren't counted again.
+    assert!(fonts.shape_text_h(&instance, "AVA").is_ok());
+    assert_eq!(instance.shaping_counters(), ShapingCounters::default());
+
+    // Overriding the strategy discards runs shaped by the other shaper.
+    instance.set_shaping_strategy(ShapingStrategy::Complex);
+    assert_eq!(instance.shaped_text_entries(), 0);
+    let complex = fonts.shape_text_h(&instance, "AVA").unwrap();
+    assert_eq!(complex.glyphs().len(), simple.glyphs().len());
+    assert_eq!(instance.shaping_counters().complex_runs, 1);
+
+    // Faces without a kern table only kern through HarfBuzz.
+    let mono = fonts.get_font_with_px_size("Fira Mono", Px(32)).unwrap();
+    assert!(fonts.shape_text_h(&mono, "AVA").is_ok());
+    assert_eq!(mono.shaping_counters().complex_runs, 1);
+    mono.set_kerning(false);
+    assert!(fonts.shape_text_h(&mono, "AVA").is_ok());
+    assert_eq!(mono.shaping_counters().simple_runs, 1);
+}
+
+#[test]
+fn test_fonts_features() {
+    let mut fonts = FontCache::new(FontKeysAPI::new(())).unwrap();
+    assert!(fonts.add_raw(FontId::new("Fira Mono"), include_bytes!("fixtures/FiraMono-Regular.ttf").to_vec(), 0).is_ok());
+    let instance = fonts.get_font_with_px_size("Fira Mono", Px(32)).unwrap();
+    instance.set_shaping_strategy(ShapingStrategy::Simple);
+    assert_eq!(instance.features(), vec![]);
+
+    let plain = fonts.shape_text_h(&instance, "10").unwrap();
+    assert_eq!(fonts.measure_text_h(&instance, "01").unwrap(), (plain.width_64(), plain.height_64()));
+    assert_eq!(instance.measured_text_entries(), 1);
+    let plain_key = ShapingKey::for_instance(&*instance, 0, false);
+
+    // The simple shaper applies single substitutions, e.g. slashed zeros.
+    instance.set_features(vec![FontFeature::enabled(b"zero")]);
+    assert_eq!(instance.measured_text_entries(), 0);
+    assert_ne!(ShapingKey::for_instance(&*instance, 0, false), plain_key);
+    let slashed = fonts.shape_text_h(&instance, "10").unwrap();
+    assert_eq!(slashed.glyphs()[0].glyph_index(), plain.glyphs()[0].glyph_index());
+    assert_ne!(slashed.glyphs()[1].glyph_index(), plain.glyphs()[1].glyph_index());
+    assert_eq!(slashed.width_64(), plain.width_64());
+
+    // Runs shaped with either feature list stay cached.
+    assert_eq!(instance.shaped_text_entries(), 2);
+    instance.set_features(vec![]);
+    assert_eq!(ShapingKey::for_instance(&*instance, 0, false), plain_key);
+    assert_eq!(fonts.shape_text_h(&instance, "10").unwrap().glyphs()[1].glyph_index(), plain.glyphs()[1].glyph_index());
+    assert_eq!(instance.shaped_text_entries(), 2);
+
+    // Disabled features don't substitute anything.
+    instance.set_features(vec![FontFeature::disabled(b"zero")]);
+    assert_eq!(fonts.shape_text_h(&instance, "10").unwrap().glyphs()[1].glyph_index(), plain.glyphs()[1].glyph_index());
+}
+
+#[cfg(feature = "harfbuzz")]
+#[test]
+fn test_fonts_features_complex() {
+    let mut fonts = FontCache::new(FontKeysAPI::new(())).unwrap();
+    assert!(fonts.add_raw(FontId::new("FreeSans"), include_bytes!("fixtures/FreeSans.ttf").to_vec(), 0).is_ok());
+    let instance = fonts.get_font_with_px_size("FreeSans", Px(32)).unwrap();
+
+    // Enabled features need HarfBuzz, which applies all their lookups.
+    instance.set_features(vec![FontFeature::enabled(b"dlig")]);
+    assert!(fonts.shape_text_h(&instance, "fi").is_ok());
+    assert_eq!(instance.shaping_counters().complex_runs, 1);
+
+    // Code rendering keeps every char its own glyph.
+    instance.set_shaping_strategy(ShapingStrategy::Complex);
+    instance.set_features(vec![FontFeature::disabled(b"liga")]);
+    assert_eq!(fonts.shape_text_h(&instance, "fi").unwrap().glyphs().len(), 2);
+}
+
+#[test]
+fn test_fonts_subsetting() {
+    let font_bytes = include_bytes!("fixtures/FreeSans.ttf").to_vec();
+
+    // Subsets are sent again under the same key, which consumers must support.
+    let mut fonts = FontCache::new(FontKeysAPI::new(())).unwrap();
+    match fonts.set_font_subsetting(true) {
+        Err(FontError::ReplayUnsupported) => {}
+        _ => panic!("Expected subsetting to be refused")
+    }
+    assert!(!fonts.font_subsetting());
+
+    let mut font_keys = FontKeysAPI::new(());
+    font_keys.set_supports_raw_font_bytes(true);
+    font_keys.set_supports_font_replacement(true);
+    let mut fonts = SharedFonts::from(FontCache::new(font_keys).unwrap());
+    let font_id = FontId::new("FreeSans");
+    assert!(fonts.borrow_mut().set_font_subsetting(true).is_ok());
+    fonts.borrow_mut().retain_chars(font_id, "0123456789".chars());
+    assert!(fonts.borrow_mut().add_raw(font_id, font_bytes.clone(), 0).is_ok());
+    let instance = fonts.get_font_with_px_size("FreeSans", Px(32)).unwrap();
+
+    // Only `.notdef` and the retained digits are sent at first.
+    let added_bytes = |updates: &ResourceUpdates<DefaultImageKey, DefaultFontKey, DefaultFontInstanceKey>| {
+        updates
+            .updates
+            .iter()
+            .filter_map(|update| match update {
+                &Update::AddFontBytes { key, ref bytes } => Some((key, bytes.len())),
+                _ => None
+            })
+            .collect::<Vec<_>>()
+    };
+    let updates = fonts.take_resource_updates();
+    let mut strict_sink = DefaultMockSink::new();
+    let mut sink = DefaultMockSink::new();
+    sink.set_replaces_fonts(true);
+    assert!(strict_sink.apply(&updates).is_ok());
+    assert!(sink.apply(&updates).is_ok());
+    let added = added_bytes(&updates);
+    assert_eq!(added, vec![(instance.external_key(), added[0].1)]);
+    assert!(added[0].1 < font_bytes.len() / 2);
+    assert_eq!(fonts.borrow().used_glyphs(font_id).len(), 11);
+
+    // Shaping new glyphs grows the subset, sent again under the same key.
+    assert!(fonts.borrow().shape_text_h(&instance, "42").is_ok());
+    assert!(fonts.borrow_mut().send_font_subsets().is_ok());
+    assert!(added_bytes(&fonts.take_resource_updates()).is_empty());
+    assert!(fonts.borrow().shape_text_h(&instance, "Hello").is_ok());
+    assert!(fonts.borrow_mut().send_font_subsets().is_ok());
+    let updates = fonts.take_resource_updates();
+    let grown = added_bytes(&updates);
+    assert_eq!(grown.len(), 1);
+    assert_eq!(grown[0].0, instance.external_key());
+    assert!(grown[0].1 > added[0].1);
+    assert_eq!(fonts.borrow().used_glyphs(font_id).len(), 15);
+
+    // Renderers rejecting fonts added twice can't take the grown subset.
+    match strict_sink.apply(&updates) {
+        Err((_, SinkError::DuplicateFont(key))) => assert_eq!(key, instance.external_key()),
+        _ => panic!("Expected the font to be added twice")
+    }
+    assert!(sink.apply(&updates).is_ok());
+    assert!(sink.has_font(instance.external_key()));
+
+    // Glyphs sent for runs shaped through released instances stay in later subsets.
+    let halved = ShapingOptions {
+        baseline_position: BaselinePosition::Shifted { shift: 0, scale: 500 },
+        ..ShapingOptions::default()
+    };
+    assert!(fonts.borrow_mut().shape_span_h(&instance, "Quartz", &halved).is_ok());
+    assert!(fonts.borrow_mut().send_font_subsets().is_ok());
+    let used = fonts.borrow().used_glyphs(font_id);
+    assert!(used.len() > 15);
+    fonts.take_resource_updates();
+    assert_eq!(fonts.borrow_mut().release_derived_instances(), 1);
+    assert_eq!(fonts.borrow().used_glyphs(font_id), used);
+    assert!(fonts.borrow().shape_text_h(&instance, "!").is_ok());
+    assert!(fonts.borrow_mut().send_font_subsets().is_ok());
+    assert_eq!(added_bytes(&fonts.take_resource_updates()).len(), 1);
+    assert!(used.is_subset(&fonts.borrow().used_glyphs(font_id)));
+
+    // Whole fonts are sent while subsetting is off.
+    let mono_bytes = include_bytes!("fixtures/FiraMono-Regular.ttf").to_vec();
+    assert!(fonts.borrow_mut().set_font_subsetting(false).is_ok());
+    assert!(fonts.borrow_mut().add_raw(FontId::new("Fira Mono"), mono_bytes.clone(), 0).is_ok());
+    assert_eq!(added_bytes(&fonts.take_resource_updates())[0].1, mono_bytes.len());
+}
+
+#[test]
+fn test_fonts_mock_backend() {
+    let mut backend = MockFontBackend::new();
+    backend.set_advance(MockCharClass::Wide, 1000);
+
+    let mut fonts = FontCache::with_backend(FontKeysAPI::new(()), backend);
+    assert!(fonts.add_raw(FontId::new("Mock"), b"Mock Sans".to_vec(), 0).is_ok());
+    assert_eq!(fonts.get_family_name(FontId::new("Mock")).unwrap(), "Mock Sans");
+
+    let instance_id = FontInstanceId::from_family_str("Mock Sans", Pt(16), Dpi(72));
+    let instance = fonts.get_or_insert_font(instance_id).unwrap();
+
+    let metrics = fonts.get_global_size_metrics(&instance).unwrap();
+    assert_eq!(metrics.ascender_64, 819);
+    assert_eq!(metrics.height_64, 1228);
+
+    // Regular, narrow and space advances at 16px, plus the overridden wide one.
+    let glyphs = fonts.shape_text_h(&instance, "Hello world").unwrap();
+    assert_eq!(glyphs.width_64(), 512 * 6 + 307 * 3 + 256 + 1024);
+    assert_eq!(fonts.shape_text_h(&instance, "日本").unwrap().width_64(), 1024 * 2);
+
+    let options = ShapingOptions::default();
+    let paragraph = fonts
+        .layout_paragraph(&instance, "Hello world", &options, Some(64 * 48))
+        .unwrap();
+    assert_eq!(paragraph.lines.len(), 2);
+    assert_eq!(paragraph.height_64, 1228 * 2);
+
+    let bitmap = fonts.get_glyph_bitmap(&instance, 'H').unwrap();
+    assert_eq!(bitmap.size, (8, 13));
+    assert!(bitmap.pixels.iter().all(|&p| p == 255));
+    assert_eq!(fonts.get_glyph_bitmap(&instance, ' ').unwrap().size, (0, 0));
+}
+
+#[test]
+fn test_fonts_kerning() {
+    let mut fonts = FontCache::new(FontKeysAPI::new(())).unwrap();
+    assert!(fonts.add_raw(FontId::new("FreeSans"), include_bytes!("fixtures/FreeSans.ttf").to_vec(), 0).is_ok());
+    let instance = fonts.get_font_with_px_size("FreeSans", Px(40)).unwrap();
+
+    let separate = fonts.shape_text_h(&instance, "A").unwrap().width_64() + fonts.shape_text_h(&instance, "V").unwrap().width_64();
+    let kerned = fonts.shape_text_h(&instance, "AV").unwrap();
+    assert!(kerned.width_64() < separate);
+    assert!(kerned.glyphs()[1].x_64() < fonts.shape_text_h(&instance, "A").unwrap().width_64());
+
+    // Pairs can be queried on their own, and are memoized.
+    let pair = fonts.get_kerning(&instance, 'A', 'V').unwrap();
+    assert!(pair < 0);
+    assert_eq!(fonts.get_kerning(&instance, 'A', 'V').unwrap(), pair);
+    assert_eq!(fonts.get_kerning(&instance, 'H', 'H').unwrap(), 0);
+    assert_eq!(instance.kerning_pairs_entries(), 2);
+
+    // Disabling it discards runs shaped with it.
+    instance.set_kerning(false);
+    assert_eq!(instance.shaped_text_entries(), 0);
+    assert_eq!(fonts.shape_text_h(&instance, "AV").unwrap().width_64(), separate);
+    assert_eq!(fonts.get_kerning(&instance, 'A', 'V').unwrap(), 0);
+}
+
+#[test]
+fn test_fonts_linear_glyph_metrics() {
+    let mut fonts = FontCache::new(FontKeysAPI::new(())).unwrap();
+    assert!(fonts.add_raw(FontId::new("FreeSans"), include_bytes!("fixtures/FreeSans.ttf").to_vec(), 0).is_ok());
+    let instance = fonts.get_font_with_px_size("FreeSans", Px(40)).unwrap();
+
+    // Design units agree with the scaled linear advance.
+    let metrics = fonts.get_linear_glyph_metrics(&instance, 'A').unwrap();
+    let linear = fonts.get_glyph_linear_advance(&instance, 'A').unwrap();
+    assert_eq!(metrics.units_per_em, 1000);
+    assert_eq!(metrics.glyph_index, linear.glyph_index);
+    assert!((metrics.hori_advance_px(40.0) - linear.hori_advance_65536 as f32 / 65536.0).abs() < 0.01);
+
+    let mut mock = FontCache::with_backend(FontKeysAPI::new(()), MockFontBackend::new());
+    assert!(mock.add_raw(FontId::new("Mock"), b"Mock Sans".to_vec(), 0).is_ok());
+    let instance = mock.get_font_with_px_size("Mock Sans", Px(16)).unwrap();
+    let metrics = mock.get_linear_glyph_metrics(&instance, 'l').unwrap();
+    assert_eq!((metrics.units_per_em, metrics.hori_advance), (1000, 300));
+    assert_eq!(metrics.hori_advance_px(16.0), 4.8);
+
+    // Narrow glyphs are 4.8px apart, rounded down to 307 each when hinted.
+    let hinted = mock.shape_text_h(&instance, "lll").unwrap();
+    assert_eq!(hinted.width_64(), 921);
+
+    // Float advances only round glyph positions, so the error doesn't add up.
+    instance.set_linear_advances(true);
+    assert_eq!(instance.shaped_text_entries(), 0);
+    let linear = mock.shape_text_h(&instance, "lll").unwrap();
+    assert_eq!(linear.width_64(), 922);
+    let positions = linear.glyphs().iter().map(|glyph| glyph.x_64()).collect::<Vec<_>>();
+    assert_eq!(positions, vec![0, 307, 614]);
+}
+
+#[test]
+fn test_fonts_design_glyph_metrics() {
+    let mut fonts = FontCache::new(FontKeysAPI::new(())).unwrap();
+    let font_id = FontId::new("FreeSans");
+    assert!(fonts.add_raw(font_id, include_bytes!("fixtures/FreeSans.ttf").to_vec(), 0).is_ok());
+    let small = fonts.get_font_with_px_size("FreeSans", Px(12)).unwrap();
+    let large = fonts.get_font_with_px_size("FreeSans", Px(40)).unwrap();
+
+    // The same whichever instance of the face was used last.
+    assert!(fonts.get_glyph_dimensions(&small, 'A').is_ok());
+    let metrics = fonts.get_design_glyph_metrics(font_id, &['A', 'V', ' ']).unwrap();
+    assert!(fonts.get_glyph_dimensions(&large, 'A').is_ok());
+    assert_eq!(fonts.get_design_glyph_metrics(font_id, &['A', 'V', ' ']).unwrap(), metrics);
+
+    assert_eq!(metrics.len(), 3);
+    assert!(metrics.iter().all(|glyph| glyph.units_per_em == 1000));
+    assert_eq!(metrics[0].glyph_index, fonts.get_glyph_index(&large, 'A').unwrap());
+    assert_eq!(metrics[0].hori_advance, fonts.get_linear_glyph_metrics(&large, 'A').unwrap().hori_advance);
+    assert!(metrics[0].width > 0 && metrics[0].hori_bearing_y > 0);
+    assert_eq!((metrics[2].width, metrics[2].height), (0, 0));
+
+    // Scaled by consumers, they're within a pixel of the hinted dimensions.
+    for &(instance, px) in &[(&small, 12), (&large, 40)] {
+        let scaled = metrics[1].dimensions_at(px * 64);
+        let hinted = fonts.get_glyph_dimensions(instance, 'V').unwrap();
+        assert_eq!(scaled.glyph_index, hinted.glyph_index);
+        assert!((scaled.hori_advance_64 - hinted.hori_advance_64).abs() <= 64);
+    }
+
+    let mut mock = FontCache::with_backend(FontKeysAPI::new(()), MockFontBackend::new());
+    assert!(mock.add_raw(FontId::new("Mock"), b"Mock Sans".to_vec(), 0).is_ok());
+    let metrics = mock.get_design_glyph_metrics(FontId::new("Mock"), &['l']).unwrap();
+    assert_eq!((metrics[0].units_per_em, metrics[0].hori_advance), (1000, 300));
+    assert_eq!(metrics[0].dimensions_at(16 * 64).hori_advance_64, 307);
+}
+
+#[test]
+fn test_fonts_palettes() {
+    use rsx_resources::fonts::palette::{self, ColorLayer, FOREGROUND_ENTRY};
+
+    let mut cpal = vec![0, 0, 0, 2, 0, 2, 0, 4, 0, 0, 0, 16, 0, 0, 0, 2];
+    cpal.extend_from_slice(&[0, 0, 255, 255, 255, 0, 0, 255, 0, 255, 0, 255, 0, 0, 0, 128]);
+    let palettes = palette::read_palettes(&cpal);
+    assert_eq!(palettes.len(), 2);
+    assert_eq!(palettes[0].colors, vec![PaletteColor::new(255, 0, 0, 255), PaletteColor::new(0, 0, 255, 255)]);
+    assert_eq!(palettes[1].colors[1], PaletteColor::new(0, 0, 0, 128));
+    assert_eq!(palette::read_palettes(&cpal[..20]).len(), 0);
+
+    let red = PaletteColor::new(255, 0, 0, 255);
+    let selection = PaletteSelection::new(1, red);
+    assert_eq!(selection.resolve(&palettes, 0), PaletteColor::new(0, 255, 0, 255));
+    assert_eq!(selection.resolve(&palettes, FOREGROUND_ENTRY), red);
+    assert_eq!(PaletteSelection::new(7, red).resolve(&palettes, 1), palettes[0].colors[1]);
+
+    let mut colr = vec![0, 0, 0, 1, 0, 0, 0, 14, 0, 0, 0, 20, 0, 2];
+    colr.extend_from_slice(&[0, 5, 0, 0, 0, 2, 0, 6, 0, 1, 0, 7, 255, 255]);
+    assert_eq!(
+        palette::read_layers(&colr, 5).unwrap(),
+        vec![
+            ColorLayer {
+                glyph_index: 6,
+                palette_entry: 1
+            },
+            ColorLayer {
+                glyph_index: 7,
+                palette_entry: FOREGROUND_ENTRY
+            },
+        ]
+    );
+    assert_eq!(palette::read_layers(&colr, 6).unwrap(), vec![]);
+
+    // Palettes are part of the instance's identity, but only color glyphs use them.
+    let mut fonts = FontCache::new(FontKeysAPI::new(())).unwrap();
+    assert!(fonts.add_raw(FontId::new("FreeSans"), include_bytes!("fixtures/FreeSans.ttf").to_vec(), 0).is_ok());
+    assert_eq!(fonts.get_palettes(FontId::new("FreeSans")).unwrap(), vec![]);
+    let plain = fonts.get_font_with_px_size("FreeSans", Px(20)).unwrap();
+    let tinted = fonts
+        .get_or_insert_font(FontInstanceId::from_family_px("FreeSans", Px(20)).with_palette(selection))
+        .unwrap();
+    assert_eq!(tinted.palette(), selection);
+    assert!(plain != tinted);
+    assert!(plain.external_instance_key() != tinted.external_instance_key());
+    assert_eq!(fonts.get_glyph_bitmap(&plain, 'A').unwrap(), fonts.get_glyph_bitmap(&tinted, 'A').unwrap());
+}
+
+#[test]
+fn test_fonts_rasterize_glyph() {
+    let mut fonts = FontCache::new(FontKeysAPI::new(())).unwrap();
+    assert!(fonts.add_raw(FontId::new("FreeSans"), include_bytes!("fixtures/FreeSans.ttf").to_vec(), 0).is_ok());
+    let instance = fonts.get_font_with_px_size("FreeSans", Px(24)).unwrap();
+
+    let glyphs = fonts.shape_text_h(&instance, "Ag").unwrap();
+    let bitmap = fonts.rasterize_glyph(&instance, glyphs.glyphs()[0].glyph_index()).unwrap();
+    assert_eq!(bitmap.format, ImagePixelFormat::Gray(8));
+    assert_eq!(bitmap.pitch(), bitmap.size.0 as usize);
+    assert_eq!(bitmap.pixels.len(), bitmap.pitch() * bitmap.size.1 as usize);
+    assert_eq!(bitmap, fonts.get_glyph_bitmap(&instance, 'A').unwrap());
+
+    // Descenders reach below the baseline.
+    let descender = fonts.rasterize_glyph(&instance, glyphs.glyphs()[1].glyph_index()).unwrap();
+    assert!(descender.bearing.1 < descender.size.1 as i32);
+
+    let mut mock = FontCache::with_backend(FontKeysAPI::new(()), MockFontBackend::new());
+    assert!(mock.add_raw(FontId::new("Mock"), b"Mock Sans".to_vec(), 0).is_ok());
+    let instance = mock.get_font_with_px_size("Mock Sans", Px(10)).unwrap();
+    assert_eq!(mock.rasterize_glyph(&instance, 'A' as u32).unwrap(), mock.get_glyph_bitmap(&instance, 'A').unwrap());
+}
+
+#[test]
+fn test_fonts_bidi_shaping() {
+    let mut fonts = FontCache::new(FontKeysAPI::new(())).unwrap();
+    let font_bytes = Rc::new(include_bytes!("fixtures/FreeSans.ttf").to_vec());
+    assert!(fonts.add_raw(FontId::new("FreeSans"), font_bytes, 0).is_ok());
+    let instance = fonts.get_default_font().unwrap();
+    let glyph_index = |c: &str| fonts.shape_text_h(&instance, c).unwrap().glyphs()[0].glyph_index();
+    let (alef, bet, gimel, open) = (glyph_index("\u{5D0}"), glyph_index("\u{5D1}"), glyph_index("\u{5D2}"), glyph_index("("));
+
+    assert!(fonts.shape_text_h(&instance, "abc").unwrap().direction_runs().is_none());
+
+    let glyphs = fonts.shape_text_h(&instance, "abc \u{5D0}\u{5D1}\u{5D2}").unwrap();
+    let runs = glyphs.direction_runs().unwrap().to_vec();
+    assert_eq!(runs.len(), 2);
+    assert_eq!((runs[0].text_range, runs[0].direction), ((0, 4), TextDirection::LeftToRight));
+    assert_eq!((runs[1].text_range, runs[1].direction), ((4, 10), TextDirection::RightToLeft));
+    assert_eq!(runs[1].glyph_range, (4, 7));
+    let hebrew: Vec<u32> = glyphs.glyphs()[4..]
+        .iter()
+        .map(|glyph| glyph.glyph_index())
+        .collect();
+    assert_eq!(hebrew, vec![gimel, bet, alef]);
+    assert!(glyphs.glyphs()[4].x_64() < glyphs.glyphs()[6].x_64());
+
+    // Brackets in right to left runs are mirrored.
+    let glyphs = fonts.shape_text_h(&instance, "\u{5D0}(\u{5D1})").unwrap();
+    assert!(glyphs.direction_runs().unwrap()[0].is_rtl());
+    assert_eq!(glyphs.glyphs()[0].glyph_index(), open);
+    assert_eq!(glyphs.glyphs()[3].glyph_index(), alef);
+}
+
+#[test]
+fn test_fonts_glyph_clusters() {
+    let mut fonts = FontCache::new(FontKeysAPI::new(())).unwrap();
+    let font_bytes = Rc::new(include_bytes!("fixtures/FreeSans.ttf").to_vec());
+    assert!(fonts.add_raw(FontId::new("FreeSans"), font_bytes, 0).is_ok());
+    let instance = fonts.get_default_font().unwrap();
+
+    // Each glyph maps back to the byte offset of its char.
+    let glyphs = fonts.shape_text_h(&instance, "h\u{e9}llo").unwrap();
+    assert_eq!(glyphs.clusters(), Some(&[0, 1, 3, 4, 5][..]));
+    assert_eq!(glyphs.glyph_cluster(4), Some(5));
+    assert_eq!(glyphs.glyph_cluster(5), None);
+    assert_eq!(glyphs.glyphs_at_offset(2), Some(1..2));
+    assert_eq!(glyphs.glyphs_at_offset(100), Some(4..5));
+    assert!(serde_json::to_string(&glyphs).unwrap().contains("clusters"));
+
+    // Right to left glyphs come in visual order.
+    let glyphs = fonts.shape_text_h(&instance, "abc \u{5D0}\u{5D1}\u{5D2}").unwrap();
+    assert_eq!(&glyphs.clusters().unwrap()[4..], &[8, 6, 4]);
+    assert_eq!(glyphs.glyphs_at_offset(7), Some(5..6));
+}
+
+#[test]
+fn test_fonts_caret_positions() {
+    use rsx_resources::fonts::caret::{caret_position_for_offset, offset_for_position};
+
+    let mut fonts = FontCache::new(FontKeysAPI::new(())).unwrap();
+    assert!(fonts.add_raw(FontId::new("FreeSans"), include_bytes!("fixtures/FreeSans.ttf").to_vec(), 0).is_ok());
+    let instance = fonts.get_default_font().unwrap();
+
+    let glyphs = fonts.shape_text_h(&instance, "h\u{e9}llo").unwrap();
+    let x = |glyph: usize| glyphs.glyphs()[glyph].x_64();
+    assert_eq!(glyphs.text_len(), 6);
+    assert_eq!(caret_position_for_offset(&glyphs, 0), 0);
+    assert_eq!(caret_position_for_offset(&glyphs, 1), x(1));
+    assert_eq!(caret_position_for_offset(&glyphs, 3), x(2));
+    assert_eq!(caret_position_for_offset(&glyphs, 6), glyphs.width_64());
+    assert_eq!(caret_position_for_offset(&glyphs, 100), glyphs.width_64());
+
+    // Hits round to the nearest cluster edge, the trailing one past the middle.
+    assert_eq!(offset_for_position(&glyphs, -64), 0);
+    assert_eq!(offset_for_position(&glyphs, x(1) + 1), 1);
+    assert_eq!(offset_for_position(&glyphs, x(2) - 1), 3);
+    assert_eq!(offset_for_position(&glyphs, glyphs.width_64() - 1), 6);
+    assert_eq!(offset_for_position(&glyphs, glyphs.width_64() + 640), 6);
+    for offset in &[0, 1, 3, 4, 5, 6] {
+        assert_eq!(offset_for_position(&glyphs, caret_position_for_offset(&glyphs, *offset)), *offset);
+    }
+
+    // Right to left text starts at the right edge.
+    let glyphs = fonts.shape_text_h(&instance, "\u{5D0}\u{5D1}\u{5D2}").unwrap();
+    assert_eq!(caret_position_for_offset(&glyphs, 0), glyphs.width_64());
+    assert_eq!(caret_position_for_offset(&glyphs, 2), glyphs.glyphs()[2].x_64());
+    assert_eq!(caret_position_for_offset(&glyphs, 6), 0);
+    assert_eq!(offset_for_position(&glyphs, glyphs.width_64() - 1), 0);
+    assert_eq!(offset_for_position(&glyphs, 1), 6);
+}
+
+#[test]
+fn test_fonts_baseline_positions() {
+    let mut fonts = FontCache::new(FontKeysAPI::new(())).unwrap();
+    assert!(fonts.add_raw(FontId::new("FreeSans"), include_bytes!("fixtures/FreeSans.ttf").to_vec(), 0).is_ok());
+    let instance = fonts.get_font_with_px_size("FreeSans", Px(40)).unwrap();
+    let baseline = fonts.shape_text_h(&instance, "2").unwrap();
+
+    // Spans on the baseline are shaped as usual.
+    let plain = fonts.shape_span_h(&instance, "2", &ShapingOptions::default()).unwrap();
+    assert_eq!(plain.glyphs(), baseline.glyphs());
+    assert_eq!(fonts.derived_instance_count(), 0);
+
+    let superscript = ShapingOptions {
+        baseline_position: BaselinePosition::Superscript,
+        ..ShapingOptions::default()
+    };
+    let (shift, scale) = fonts.baseline_position_metrics(instance.font_id(), BaselinePosition::Superscript);
+    assert!(shift > 0 && scale < 1000);
+    let raised = fonts.shape_span_h(&instance, "2", &superscript).unwrap();
+    assert_ne!(raised.font_instance_key(), baseline.font_instance_key());
+    assert!(raised.width_64() < baseline.width_64());
+    assert!(raised.glyphs()[0].y_64() < baseline.glyphs()[0].y_64());
+    assert_eq!(raised.height_64(), baseline.height_64());
+
+    let subscript = ShapingOptions {
+        baseline_position: BaselinePosition::Subscript,
+        ..ShapingOptions::default()
+    };
+    let lowered = fonts.shape_span_h(&instance, "2", &subscript).unwrap();
+    assert!(lowered.glyphs()[0].y_64() > raised.glyphs()[0].y_64());
+
+    // Positions scaling to the same size share their derived instance.
+    let shifted = ShapingOptions {
+        baseline_position: BaselinePosition::Shifted { shift: 0, scale: 500 },
+        ..ShapingOptions::default()
+    };
+    let halved = fonts.shape_span_h(&instance, "2", &shifted).unwrap();
+    assert_eq!(fonts.get_font_with_px_size("FreeSans", Px(20)).unwrap().external_instance_key(), halved.font_instance_key());
+    let (_, subscript_scale) = fonts.baseline_position_metrics(instance.font_id(), BaselinePosition::Subscript);
+    let mut sizes: Vec<u32> = [scale, subscript_scale, 500].iter().map(|&scale| (40 * u32::from(scale) + 500) / 1000).collect();
+    sizes.sort();
+    sizes.dedup();
+    assert_eq!(fonts.derived_instance_count(), sizes.len());
+
+    // Derived instances are released once unused, deleting their keys.
+    fonts.take_resource_updates();
+    assert_eq!(fonts.release_derived_instances(), sizes.len());
+    assert_eq!(fonts.derived_instance_count(), 0);
+    assert_eq!(fonts.take_resource_updates().len(), sizes.len());
+}
+
+#[test]
+fn test_fonts_derived_instances_swept() {
+    let mut fonts = FontCache::new(FontKeysAPI::new(())).unwrap();
+    assert!(fonts.add_raw(FontId::new("FreeSans"), include_bytes!("fixtures/FreeSans.ttf").to_vec(), 0).is_ok());
+    let instance = fonts.get_font_with_px_size("FreeSans", Px(40)).unwrap();
+    let user_instance = fonts.get_font_with_px_size("FreeSans", Px(20)).unwrap();
+    let superscript = ShapingOptions {
+        baseline_position: BaselinePosition::Superscript,
+        ..ShapingOptions::default()
+    };
+    let halved = ShapingOptions {
+        baseline_position: BaselinePosition::Shifted { shift: 0, scale: 500 },
+        ..ShapingOptions::default()
+    };
+    let raised = fonts.shape_span_h(&instance, "2", &superscript).unwrap();
+    assert_eq!(fonts.derived_instance_count(), 1);
+    fonts.take_resource_updates();
+
+    // Sweeping keeps the keys spans were shaped with.
+    assert!(fonts.sweep_expired(0).is_ok());
+    assert!(fonts.take_resource_updates().is_empty());
+    assert_eq!(fonts.derived_instance_count(), 1);
+    assert!(fonts
+        .iter_instances()
+        .any(|info| info.font_instance_key == raised.font_instance_key()));
+
+    // Instances registered before aren't derived, so never released by spans.
+    let shifted = fonts.shape_span_h(&instance, "2", &halved).unwrap();
+    assert_eq!(shifted.font_instance_key(), user_instance.external_instance_key());
+    assert_eq!(fonts.derived_instance_count(), 1);
+    drop(user_instance);
+    assert_eq!(fonts.release_derived_instances(), 1);
+    assert!(fonts.get_font_with_px_size("FreeSans", Px(20)).is_some());
+    assert_eq!(fonts.take_resource_updates().len(), 1);
+}
+
+#[test]
+fn test_fonts_derived_instances_expire() {
+    let mut fonts = FontCache::new(FontKeysAPI::new(())).unwrap();
+    assert!(fonts.add_raw(FontId::new("FreeSans"), include_bytes!("fixtures/FreeSans.ttf").to_vec(), 0).is_ok());
+    fonts.set_font_priority(FontId::new("FreeSans"), ResourcePriority::Pinned);
+    let mut policy = ExpiryPolicy::new();
+    policy.set_default_ttl_ms(Some(1_000));
+    fonts.set_expiry_policy(policy);
+    let instance = fonts.get_font_with_px_size("FreeSans", Px(40)).unwrap();
+    let superscript = ShapingOptions {
+        baseline_position: BaselinePosition::Superscript,
+        ..ShapingOptions::default()
+    };
+    assert!(fonts.shape_span_h(&instance, "2", &superscript).is_ok());
+    fonts.take_resource_updates();
+
+    // Spans shaped again restart the TTL of their derived instance.
+    assert!(fonts.sweep_expired(0).is_ok());
+    assert!(fonts.shape_span_h(&instance, "2", &superscript).is_ok());
+    assert!(fonts.sweep_expired(800).is_ok());
+    assert!(fonts.sweep_expired(1_600).is_ok());
+    assert_eq!(fonts.derived_instance_count(), 1);
+    assert!(fonts.take_resource_updates().is_empty());
+
+    // Once unused for the TTL, its key is deleted but the face is kept.
+    assert!(fonts.sweep_expired(1_800).is_ok());
+    assert_eq!(fonts.derived_instance_count(), 0);
+    assert_eq!(fonts.take_resource_updates().len(), 1);
+    assert!(fonts.shape_text_h(&instance, "2").is_ok());
+}
+
+#[test]
+fn test_fonts_iter_instances() {
+    let mut fonts = FontCache::new(FontKeysAPI::new(())).unwrap();
+    let font_id = FontId::new("FreeSans");
+    assert!(fonts.add_raw(font_id, include_bytes!("fixtures/FreeSans.ttf").to_vec(), 0).is_ok());
+    let default = fonts.get_default_font().unwrap();
+    let small = fonts.get_font_with_px_size("FreeSans", Px(16)).unwrap();
+    let large = fonts.get_font_with_px_size("FreeSans", Px(32)).unwrap();
+    assert!(fonts.shape_text_h(&small, "Hello").is_ok());
+    assert!(fonts.shape_text_h(&large, "Hello").is_ok());
+    assert!(fonts.shape_text_h(&large, "world").is_ok());
+
+    // The instance added along with the face, and one per size since.
+    let infos: Vec<_> = fonts.iter_instances().collect();
+    assert_eq!(infos.len(), 3);
+    for &(instance, entries) in &[(&default, 0), (&small, 1), (&large, 2)] {
+        let info = infos
+            .iter()
+            .find(|info| info.font_instance_key == instance.external_instance_key())
+            .unwrap();
+        assert!(Rc::ptr_eq(&fonts.get_font_instance(info.instance_id).unwrap(), instance));
+        assert_eq!(
+            (info.font_id, info.font_key, info.shaped_text_entries),
+            (font_id, instance.external_key(), entries)
+        );
+    }
+
+    // Dumps list the same instances, one per line.
+    let dump = fonts.dump();
+    assert_eq!(dump.lines().count(), 3);
+    for &(instance, entries) in &[(&default, 0), (&small, 1), (&large, 2)] {
+        let line = format!(
+            "FreeSans size={} dpi={} font_key={:?} font_instance_key={:?} shaped_text_entries={}",
+            instance.size(),
+            instance.dpi().0,
+            instance.external_key(),
+            instance.external_instance_key(),
+            entries
+        );
+        assert!(dump.lines().any(|dumped| dumped == line));
+    }
+}
+
+#[test]
+fn test_fonts_measure_text() {
+    let mut fonts = FontCache::new(FontKeysAPI::new(())).unwrap();
+    assert!(fonts.add_raw(FontId::new("FreeSans"), include_bytes!("fixtures/FreeSans.ttf").to_vec(), 0).is_ok());
+    let instance = fonts.get_font_with_px_size("FreeSans", Px(24)).unwrap();
+
+    // Measuring allocates no glyphs and caches no store.
+    let measured = fonts.measure_text_h(&instance, "AVATAR").unwrap();
+    assert_eq!(instance.shaped_text_entries(), 0);
+    assert_eq!(instance.measured_text_entries(), 1);
+    assert_eq!(fonts.measure_text_h(&instance, "AVATAR").unwrap(), measured);
+    assert_eq!(instance.measured_text_entries(), 1);
+
+    let glyphs = fonts.shape_text_h(&instance, "AVATAR").unwrap();
+    assert_eq!(measured, (glyphs.width_64(), glyphs.height_64()));
+
+    // Shaped runs are measured from their store.
+    let glyphs = fonts.shape_text_h(&instance, "Hello").unwrap();
+    assert_eq!(fonts.measure_text_h(&instance, "Hello").unwrap(), (glyphs.width_64(), glyphs.height_64()));
+    assert_eq!(instance.measured_text_entries(), 1);
+
+    // Sizes measured with kerning are discarded along with shaped runs.
+    instance.set_kerning(false);
+    assert_eq!(instance.measured_text_entries(), 0);
+    let unkerned = fonts.measure_text_h(&instance, "AVATAR").unwrap();
+    assert_eq!(unkerned, (fonts.shape_text_h(&instance, "AVATAR").unwrap().width_64(), measured.1));
+}
+
+#[test]
+fn test_fonts_truncated_shaping() {
+    let mut fonts = FontCache::new(FontKeysAPI::new(())).unwrap();
+    assert!(fonts.add_raw(FontId::new("FreeSans"), include_bytes!("fixtures/FreeSans.ttf").to_vec(), 0).is_ok());
+    let instance = fonts.get_font_with_px_size("FreeSans", Px(24)).unwrap();
+    let full = fonts.shape_text_h(&instance, "Hello world").unwrap();
+    let ellipsis = fonts.shape_text_h(&instance, "…").unwrap();
+
+    // Text fitting as is gets no ellipsis.
+    let (glyphs, consumed) = fonts
+        .shape_text_h_truncated(&instance, "Hello world", full.width_64(), "…")
+        .unwrap();
+    assert_eq!(glyphs.glyphs().len(), 11);
+    assert_eq!(consumed, 11);
+
+    // Whitespace before the ellipsis is trimmed, but still consumed.
+    let hello = fonts.shape_text_h(&instance, "Hello").unwrap();
+    let max_width_64 = hello.width_64() + ellipsis.width_64() + 64;
+    let (glyphs, consumed) = fonts
+        .shape_text_h_truncated(&instance, "Hello world", max_width_64, "…")
+        .unwrap();
+    assert_eq!(consumed, 6);
+    assert_eq!(glyphs.glyphs().len(), 6);
+    assert_eq!(glyphs.width_64(), hello.width_64() + ellipsis.width_64());
+    assert!(glyphs.width_64() <= max_width_64);
+    assert_eq!(glyphs.glyphs()[..5], hello.glyphs()[..]);
+    assert_eq!(glyphs.glyphs()[5].glyph_index, ellipsis.glyphs()[0].glyph_index);
+    assert_eq!(glyphs.glyphs()[5].x_64, hello.width_64());
+    assert_eq!(glyphs.clusters().unwrap()[5], 5);
+
+    // Only the ellipsis is left when nothing else fits.
+    let (glyphs, consumed) = fonts
+        .shape_text_h_truncated(&instance, "Hello world", ellipsis.width_64(), "…")
+        .unwrap();
+    assert_eq!(consumed, 0);
+    assert_eq!(glyphs.glyphs().len(), 1);
+    assert_eq!(glyphs.width_64(), ellipsis.width_64());
+
+    // Consumed chars are counted in the given text, before it's composed.
+    let cafe = fonts.shape_text_h(&instance, "Caf\u{e9}").unwrap();
+    let max_width_64 = cafe.width_64() + ellipsis.width_64() + 64;
+    let (glyphs, consumed) = fonts
+        .shape_text_h_truncated(&instance, "Cafe\u{301} world", max_width_64, "…")
+        .unwrap();
+    assert_eq!(consumed, 6);
+    assert_eq!(glyphs.glyphs().len(), 5);
+    assert_eq!(glyphs.glyphs()[..4], cafe.glyphs()[..]);
+    assert_eq!(fonts.shape_text_h_truncated(&instance, "Cafe\u{301}", cafe.width_64(), "…").unwrap().1, 5);
+}
+
+#[test]
+fn test_fonts_transient_shaping() {
+    let mut fonts = FontCache::new(FontKeysAPI::new(())).unwrap();
+    assert!(fonts.add_raw(FontId::new("FreeSans"), include_bytes!("fixtures/FreeSans.ttf").to_vec(), 0).is_ok());
+    let instance = fonts.get_font_with_px_size("FreeSans", Px(24)).unwrap();
+
+    let mut arena = ShapingArena::with_capacity(64);
+    let hello = fonts.shape_text_h_transient(&instance, "Hello", &mut arena).unwrap();
+    let world = fonts.shape_text_v_transient(&instance, "world", &mut arena).unwrap();
+    assert_eq!(instance.shaped_text_entries(), 0);
+    assert_eq!(arena.runs(), 2);
+    assert_eq!(arena.len(), 10);
+    assert_eq!(arena.glyphs(&hello).len(), 5);
+    assert_eq!(arena.glyphs(&world).len(), 5);
+
+    let glyphs = fonts.shape_text_h(&instance, "Hello").unwrap();
+    assert_eq!(hello.width_64, glyphs.width_64());
+    assert_eq!(arena.glyphs(&hello), glyphs.glyphs());
+
+    // Resetting keeps the capacity, but stale runs lose their glyphs.
+    let capacity = arena.capacity();
+    arena.reset();
+    assert!(arena.is_empty());
+    assert_eq!(arena.capacity(), capacity);
+    assert!(arena.glyphs(&hello).is_empty());
+
+    let cached = fonts.shape_text_h_transient(&instance, "Hello", &mut arena).unwrap();
+    assert_eq!(arena.glyphs(&cached), glyphs.glyphs());
+    assert_eq!(instance.shaped_text_entries(), 1);
+}
+
+#[test]
+fn test_fonts_measure_char() {
+    let mut fonts = FontCache::with_backend(FontKeysAPI::new(()), MockFontBackend::new());
+    assert!(fonts.add_raw(FontId::new("Mock"), b"Mock Sans".to_vec(), 0).is_ok());
+
+    let instance_id = FontInstanceId::from_family_str("Mock Sans", Pt(16), Dpi(72));
+    let instance = fonts.get_or_insert_font(instance_id).unwrap();
+    assert_eq!(fonts.measure_char(&instance, 'a').unwrap().hori_advance_64, 512);
+    assert_eq!(fonts.measure_char(&instance, 'a').unwrap(), fonts.get_glyph_dimensions(&instance, 'a').unwrap());
+    assert_eq!(instance.measured_chars_entries(), 1);
+    assert_eq!(instance.shaped_text_entries(), 0);
+
+    let cell = fonts.cell_metrics(&instance).unwrap();
+    assert_eq!(cell.zero_advance_64, 512);
+    assert_eq!(cell.em_advance_64, 819);
+    assert_eq!(cell.line_height_64, 1228);
+    assert_eq!(instance.measured_chars_entries(), 3);
+    assert_eq!(fonts.cell_metrics(&instance).unwrap(), cell);
+
+    instance.set_load_policy(GlyphLoadPolicy::default().with(GlyphLoadPurpose::Measure, GlyphLoadOptions::new(LoadFlag::NO_BITMAP, RenderTarget::Light)));
+    assert_eq!(instance.measured_chars_entries(), 0);
+}
+
+#[test]
+fn test_fonts_placeholder() {
+    let font_keys = FontKeysAPI::new(());
+    let mut fonts = FontCache::new(font_keys).unwrap();
+    assert!(fonts.get_font_with_px_size("Missing Sans", Px(40)).is_none());
+
+    assert!(fonts.set_placeholder_metrics(Some(PlaceholderMetrics::default())).is_ok());
+    let instance = fonts.get_font_with_px_size("Missing Sans", Px(40)).unwrap();
+    assert!(fonts.is_placeholder(&instance));
+    assert_eq!(fonts.get_family_name(instance.font_id()).unwrap(), "RSX Placeholder");
+    assert_eq!(fonts.font_priority(instance.font_id()), ResourcePriority::Pinned);
+
+    // Advances and vertical metrics follow the profile, at 40px per em.
+    let metrics = fonts.get_global_size_metrics(&instance).unwrap();
+    assert_eq!(metrics.ascender_64, 32 * 64);
+    assert_eq!(metrics.descender_64, -8 * 64);
+    let cell = fonts.cell_metrics(&instance).unwrap();
+    assert_eq!(cell.zero_advance_64, 24 * 64);
+    assert_eq!(cell.em_advance_64, 24 * 64);
+    assert_eq!(fonts.shape_text_h(&instance, "Tofu 豆腐").unwrap().width_64(), 7 * 24 * 64);
+
+    // Glyphs are hollow boxes, and spaces are blank.
+    let bitmap = fonts.get_glyph_bitmap(&instance, '豆').unwrap();
+    let (width, height) = (bitmap.size.0 as usize, bitmap.size.1 as usize);
+    let stride = bitmap.pixels.len() / (width * height);
+    let pixel = |x: usize, y: usize| bitmap.pixels[(y * width + x) * stride + stride - 1];
+    assert!(width > 10 && height > 20);
+    assert!(pixel(1, height / 2) > 0);
+    assert_eq!(pixel(width / 2, height / 2), 0);
+    assert_eq!(fonts.get_glyph_bitmap(&instance, ' ').unwrap().size, (0, 0));
+
+    // Real families win once added, and profile changes drop the old face.
+    let font_bytes = Rc::new(include_bytes!("fixtures/FreeSans.ttf").to_vec());
+    assert!(fonts.add_raw(FontId::new("FreeSans"), font_bytes, 0).is_ok());
+    let free_sans = fonts.get_font_with_px_size("FreeSans", Px(40)).unwrap();
+    assert!(!fonts.is_placeholder(&free_sans));
+    let condensed = PlaceholderMetrics {
+        advance: 500,
+        ..PlaceholderMetrics::default()
+    };
+    assert!(fonts.set_placeholder_metrics(Some(condensed)).is_ok());
+    assert!(fonts.get_font_instance(FontInstanceId::from_family_px("Missing Sans", Px(40))).is_none());
+    let instance = fonts.get_font_with_px_size("Missing Sans", Px(40)).unwrap();
+    assert_eq!(fonts.measure_char(&instance, 'x').unwrap().hori_advance_64, 20 * 64);
+
+    assert!(fonts.set_placeholder_metrics(None).is_ok());
+    assert!(fonts.get_font_with_px_size("Missing Sans", Px(40)).is_none());
+}
+
+#[test]
+fn test_fonts_metrics_backend() {
+    let mut fonts = FontCache::new(FontKeysAPI::new(())).unwrap();
+    let font_bytes = Rc::new(include_bytes!("fixtures/FreeSans.ttf").to_vec());
+    assert!(fonts.add_raw(FontId::new("FreeSans"), font_bytes, 0).is_ok());
+
+    let table = fonts
+        .export_metrics(FontId::new("FreeSans"), (' '..'\u{7f}').chain(Some('\u{e000}')))
+        .unwrap();
+    assert_eq!(table.family_name, "FreeSans");
+    assert_eq!(table.glyphs.len(), 95);
+    assert_eq!(table.glyph('\u{e000}'), &table.notdef);
+    let json = table.to_json().unwrap();
+    assert_eq!(FontMetricsTable::from_json(&json).unwrap(), table);
+
+    let mut headless = FontCache::with_backend(FontKeysAPI::new(()), MetricsFontBackend::new());
+    assert!(headless.add_raw(FontId::new("FreeSans"), json.into_bytes(), 0).is_ok());
+    assert_eq!(headless.get_family_name(FontId::new("FreeSans")).unwrap(), "FreeSans");
+
+    let instance_id = FontInstanceId::from_family_str("FreeSans", Pt(16), Dpi(72));
+    let native = fonts.get_or_insert_font(instance_id).unwrap();
+    let instance = headless.get_or_insert_font(instance_id).unwrap();
+
+    // Rounding to font units costs at most a 64th of a pixel per glyph.
+    let text = "Hello, headless world!";
+    let native_glyphs = fonts.shape_text_h(&native, text).unwrap();
+    let glyphs = headless.shape_text_h(&instance, text).unwrap();
+    assert_eq!(glyphs.glyphs().len(), native_glyphs.glyphs().len());
+    assert!((glyphs.width_64() - native_glyphs.width_64()).abs() <= text.len() as i32);
+    assert_eq!(
+        headless.get_global_size_metrics(&instance).unwrap().nominal_height,
+        16
+    );
+
+    let options = ShapingOptions::default();
+    let paragraph = headless
+        .layout_paragraph(&instance, text, &options, Some(64 * 100))
+        .unwrap();
+    let native_paragraph = fonts
+        .layout_paragraph(&native, text, &options, Some(64 * 100))
+        .unwrap();
+    assert!(paragraph.lines.len() > 1);
+    assert_eq!(paragraph.lines.len(), native_paragraph.lines.len());
+
+    assert!(headless.get_glyph_bitmap(&instance, 'H').is_err());
+    assert!(headless.script_coverage(FontId::new("FreeSans")).is_ok());
+}
+
+#[derive(Debug, Default)]
+struct FontFaceRecorder {
+    registered: Vec<FontId>
+}
+
+impl TFontFaceHost for FontFaceRecorder {
+    fn register_font_face(&mut self, font_id: FontId, _: &Rc<Vec<u8>>, _: usize) -> FontResult<String> {
+        self.registered.push(font_id);
+        Ok("FreeSans".to_string())
+    }
+
+    fn unregister_font_face(&mut self, font_id: FontId) {
+        self.registered.retain(|&id| id != font_id);
+    }
+}
+
+#[test]
+fn test_resource_group_browser() {
+    let files = FileCache::new().unwrap();
+    let images = ImageCache::new(ImageKeysAPI::new(())).unwrap();
+    let fonts = FontCache::with_backend(FontKeysAPI::new(()), BrowserFontBackend::new(FontFaceRecorder::default()));
+    let group = BrowserResourceGroup::new_browser(files, images, fonts);
+
+    // Images keep their ids and dimensions, but the pixels are left to the consumer.
+    let image_bytes = include_bytes!("fixtures/Quantum.png");
+    assert!(group.images().add_raw("Quantum", Rc::new(image_bytes.to_vec())).is_some());
+    let image = group.images().get_image("Quantum").unwrap();
+    assert_eq!((image.width(), image.height()), (512, 529));
+    assert!(image.pixels().is_empty());
+    assert_eq!(group.images().borrow().used_bytes(), 0);
+    match group.images().take_resource_updates().updates[0] {
+        Update::AddImage { ref data_uri, .. } => assert!(data_uri.starts_with("data:image/png;base64,")),
+        _ => panic!("Expected an image update")
+    }
+    let sized = group
+        .images()
+        .borrow_mut()
+        .get_image_at_size("Quantum", (256, 256))
+        .unwrap();
+    assert_eq!((sized.width(), sized.height()), (248, 256));
+    assert_eq!(group.images().take_resource_updates().len(), 1);
+
+    let font_bytes = include_bytes!("fixtures/FreeSans.ttf").to_vec();
+    assert!(group.fonts().add_raw("FreeSans", font_bytes.clone(), 0).is_some());
+    let font_id = FontId::new("FreeSans");
+    assert_eq!(group.fonts().borrow().backend().host().registered, vec![font_id]);
+    assert_eq!(group.fonts().get_family_name("FreeSans").unwrap(), "FreeSans");
+
+    let instance_id = FontInstanceId::from_family_str("FreeSans", Pt(16), Dpi(72));
+    let instance = group.fonts().borrow_mut().get_or_insert_font(instance_id).unwrap();
+    assert!(group.fonts().borrow().shape_text_h(&instance, "Hello").is_err());
+
+    // Stands in for advances measured by the browser.
+    let mut native = FontCache::new(FontKeysAPI::new(())).unwrap();
+    assert!(native.add_raw(font_id, font_bytes, 0).is_ok());
+    let table = native.export_metrics(font_id, ' '..'\u{7f}').unwrap();
+    assert!(group.set_measured_metrics(font_id, table).is_ok());
+
+    let glyphs = group.fonts().borrow().shape_text_h(&instance, "Hello").unwrap();
+    let native_instance = native.get_or_insert_font(instance_id).unwrap();
+    let native_glyphs = native.shape_text_h(&native_instance, "Hello").unwrap();
+    assert!((glyphs.width_64() - native_glyphs.width_64()).abs() <= 5);
+    assert!(group.fonts().borrow().get_glyph_bitmap(&instance, 'H').is_err());
+}
+
+#[test]
+fn test_fonts_script_coverage() {
+    let mut fonts = FontCache::new(FontKeysAPI::new(())).unwrap();
+    let font_bytes = Rc::new(include_bytes!("fixtures/FreeSans.ttf").to_vec());
+    assert!(fonts.add_raw(FontId::new("FreeSans"), font_bytes, 0).is_ok());
+
+    let coverage = fonts.script_coverage(FontId::new("FreeSans")).unwrap();
+    assert_eq!(coverage.code_point_count(), 4371);
+    assert!(coverage.covers('A'));
+    assert!(!coverage.covers('\u{7F}'));
+    assert_eq!(coverage.blocks()[0].block.name, "Basic Latin");
+    assert_eq!(coverage.blocks()[0].covered, 95);
+
+    let latin = coverage.script(Script::Latin);
+    assert_eq!((latin.covered, latin.total), (835, 944));
+    assert_eq!(coverage.script(Script::Han).covered, 0);
+    assert!(coverage.scripts().iter().any(|script| script.script == Script::Hebrew));
+    assert!(!coverage.scripts().iter().any(|script| script.script == Script::Arabic));
+
+    // Explains which characters render as tofu, and why.
+    let missing = coverage.missing_chars("Hello, 世界 שלום\n");
+    assert_eq!(missing, vec!['世', '界']);
+    assert_eq!(Script::of(missing[0]), Some(Script::Han));
+    assert_eq!(UnicodeBlock::of('é').unwrap().name, "Latin-1 Supplement");
+    assert_eq!(Script::of('\u{E000}'), None);
+
+    assert!(fonts.script_coverage(FontId::new("Missing")).is_err());
+
+    let mut fonts = FontCache::with_backend(FontKeysAPI::new(()), MockFontBackend::new());
+    assert!(fonts.add_raw(FontId::new("Mock"), b"Mock".to_vec(), 0).is_ok());
+    let coverage = fonts.script_coverage(FontId::new("Mock")).unwrap();
+    assert!(coverage.missing_chars("Hello, 世界").is_empty());
+    assert_eq!(coverage.ranges(), &[(0, 0xD7FF), (0xE000, 0x10FFFF)]);
+}
+
+#[test]
+fn test_fonts_glyph_run_source() {
+    let (font_keys, receiver) = FontKeysAPI::new(()).into_stream();
+    let mut fonts = FontCache::with_backend(font_keys, MockFontBackend::new());
+    assert!(fonts.add_raw(FontId::new("Mock"), b"Mock".to_vec(), 0).is_ok());
+    let instance = fonts.get_default_font_with_size(Pt(16)).unwrap();
+    while receiver.try_recv().is_ok() {}
+
+    // Nothing is kept unless asked for.
+    let glyphs = fonts.shape_text_h(&instance, "héllo").unwrap();
+    assert_eq!(glyphs.source(), None);
+    assert!(!fonts.add_glyph_run(&glyphs));
+    assert!(receiver.try_recv().is_err());
+
+    fonts.set_glyph_source_mode(GlyphSourceMode::Hashed);
+    let hashed = fonts.shape_text_h(&instance, "héllo").unwrap();
+    let source = hashed.source().unwrap();
+    assert_eq!(source.text(), None);
+    assert_eq!(&source.offsets[..], &[0, 1, 3, 4, 5]);
+    assert_eq!(source.glyph_text(1), None);
+
+    fonts.set_glyph_source_mode(GlyphSourceMode::Text);
+    let text = fonts.shape_text_v(&instance, "héllo").unwrap();
+    let source = text.source().unwrap().clone();
+    assert_eq!(source.text(), Some("héllo"));
+    assert_eq!(source.text_hash, hashed.source().unwrap().text_hash);
+    assert_eq!(source.glyph_count(), text.glyphs().len());
+    assert_eq!(source.glyph_text(1), Some("é"));
+    assert_eq!(source.glyph_text(4), Some("o"));
+    assert_eq!(source.glyph_text(5), None);
+
+    let serialized = serde_json::to_string(&text).unwrap();
+    assert!(serialized.contains("\"text\":\"héllo\""));
+    assert!(!serde_json::to_string(&glyphs).unwrap().contains("source"));
+
+    assert!(fonts.add_glyph_run(&text));
+    match receiver.try_recv() {
+        Ok(Update::AddGlyphRun {
+            instance_key,
+            ref glyphs,
+            source: ref run_source
+        }) => {
+            assert_eq!(instance_key, instance.external_instance_key());
+            assert_eq!(glyphs.len(), 5);
+            assert_eq!(run_source, &source);
+        }
+        _ => panic!("Expected a glyph run update")
+    }
+
+    // Paragraphs laid out before the mode changed are shaped again.
+    let layout = fonts.layout_paragraph(&instance, "héllo", &ShapingOptions::default(), None).unwrap();
+    assert_eq!(layout.lines[0].glyphs.source().unwrap().text(), Some("héllo"));
+    fonts.set_glyph_source_mode(GlyphSourceMode::Omitted);
+    let layout = fonts.layout_paragraph(&instance, "héllo", &ShapingOptions::default(), None).unwrap();
+    assert_eq!(layout.lines[0].glyphs.source(), None);
+}
+
+#[test]
+fn test_fonts_atlas_dedupe() {
+    let mut fonts = FontCache::with_backend(FontKeysAPI::new(()), MockFontBackend::new());
+    assert!(fonts.add_raw(FontId::new("A"), b"Mock A".to_vec(), 0).is_ok());
+    assert!(fonts.add_raw(FontId::new("B"), b"Mock B".to_vec(), 0).is_ok());
+
+    let a = fonts.get_default_font_with_size(Pt(16)).unwrap();
+    let b = fonts
+        .get_or_insert_font(FontInstanceId::from_family_str("Mock B", Pt(16), Dpi(72)))
+        .unwrap();
+    assert_ne!(a.external_instance_key(), b.external_instance_key());
+
+    let mut atlas = GlyphAtlas::new();
+    for c in "Hello".chars() {
+        fonts.get_atlas_glyph(&mut atlas, &a, c).unwrap();
+    }
+    // Regular and narrow glyphs are solid boxes of the same size each.
+    assert_eq!(atlas.glyph_count(), 4);
+    assert_eq!(atlas.len(), 2);
+
+    for c in "Hello".chars() {
+        fonts.get_atlas_glyph(&mut atlas, &b, c).unwrap();
+    }
+    assert_eq!(atlas.glyph_count(), 8);
+    assert_eq!(atlas.len(), 2);
+    assert_eq!(
+        fonts.get_atlas_glyph(&mut atlas, &a, 'H').unwrap(),
+        fonts.get_atlas_glyph(&mut atlas, &b, 'o').unwrap()
+    );
+
+    let wide = fonts.get_atlas_glyph(&mut atlas, &b, 'W').unwrap();
+    assert_eq!(atlas.get(wide).unwrap().size, (13, 13));
+    assert_eq!(atlas.len(), 3);
+
+    assert_eq!(atlas.remove_instance(a.external_instance_key()), 0);
+    assert_eq!(atlas.remove_instance(b.external_instance_key()), 3);
+    assert!(atlas.is_empty());
+    assert!(atlas.get(wide).is_none());
+}
+
+#[test]
+fn test_fonts_atlas_subpixel_offsets() {
+    assert_eq!(SubpixelOffset::split_x_64(0), (0, SubpixelOffset::Zero));
+    assert_eq!(SubpixelOffset::split_x_64(64 + 30), (1, SubpixelOffset::Half));
+    assert_eq!(SubpixelOffset::split_x_64(64 + 60), (2, SubpixelOffset::Zero));
+    assert_eq!(SubpixelOffset::split_x_64(-20), (-1, SubpixelOffset::ThreeQuarters));
+
+    let mut fonts = FontCache::new(FontKeysAPI::new(())).unwrap();
+    let font_bytes = Rc::new(include_bytes!("fixtures/FreeSans.ttf").to_vec());
+    assert!(fonts.add_raw(FontId::new("FreeSans"), font_bytes, 0).is_ok());
+    let instance = fonts.get_default_font_with_size(Pt(16)).unwrap();
+
+    let mut atlas = GlyphAtlas::new();
+    let plain = fonts.get_atlas_glyph(&mut atlas, &instance, 'l').unwrap();
+    assert_eq!(fonts.get_atlas_glyph_at_offset(&mut atlas, &instance, 'l', SubpixelOffset::Zero).unwrap(), plain);
+    for &offset in &SubpixelOffset::ALL {
+        fonts.get_atlas_glyph_at_offset(&mut atlas, &instance, 'l', offset).unwrap();
+        fonts.get_atlas_glyph_at_offset(&mut atlas, &instance, 'o', offset).unwrap();
+    }
+    let half = fonts.get_atlas_glyph_at_offset(&mut atlas, &instance, 'l', SubpixelOffset::Half).unwrap();
+    assert_ne!(half, plain);
+    assert_ne!(atlas.get(half).unwrap().pixels, atlas.get(plain).unwrap().pixels);
+
+    let stats = atlas.variant_stats();
+    assert_eq!(stats.glyphs, 2);
+    assert_eq!(stats.variants, 8);
+    assert_eq!(stats.variants_by_offset, [2, 2, 2, 2]);
+    assert_eq!(stats.max_variants_per_glyph, 4);
+
+    // Backends without subpixel positioning share a slot for all offsets.
+    let mut fonts = FontCache::with_backend(FontKeysAPI::new(()), MockFontBackend::new());
+    assert!(fonts.add_raw(FontId::new("A"), b"Mock A".to_vec(), 0).is_ok());
+    let a = fonts.get_default_font_with_size(Pt(16)).unwrap();
+    let mut atlas = GlyphAtlas::new();
+    for &offset in &SubpixelOffset::ALL {
+        fonts.get_atlas_glyph_at_offset(&mut atlas, &a, 'W', offset).unwrap();
+    }
+    assert_eq!(atlas.len(), 1);
+    assert_eq!(atlas.variant_stats().variants, 4);
+    assert_eq!(atlas.remove_instance(a.external_instance_key()), 1);
+    assert_eq!(atlas.variant_stats(), AtlasVariantStats::default());
+}
+
+#[test]
+fn test_fonts_atlas_blur() {
+    let mut fonts = FontCache::with_backend(FontKeysAPI::new(()), MockFontBackend::new());
+    assert!(fonts.add_raw(FontId::new("A"), b"Mock A".to_vec(), 0).is_ok());
+    let a = fonts.get_default_font_with_size(Pt(16)).unwrap();
+
+    let mut atlas = GlyphAtlas::new();
+    let sharp = fonts.get_atlas_glyph(&mut atlas, &a, 'W').unwrap();
+    assert_eq!(fonts.get_atlas_blurred_glyph(&mut atlas, &a, 'W', 0).unwrap(), sharp);
+
+    let shadow = fonts.get_atlas_blurred_glyph(&mut atlas, &a, 'W', 4).unwrap();
+    assert_ne!(shadow, sharp);
+    assert_eq!(atlas.len(), 2);
+    assert_eq!(atlas.glyph_count(), 2);
+    assert_eq!(fonts.get_atlas_blurred_glyph(&mut atlas, &a, 'W', 4).unwrap(), shadow);
+
+    let (sharp, shadow) = (atlas.get(sharp).unwrap().clone(), atlas.get(shadow).unwrap().clone());
+    assert_eq!(shadow.format, ImagePixelFormat::Gray(8));
+    assert_eq!(shadow.size, (sharp.size.0 + 8, sharp.size.1 + 8));
+    assert_eq!(shadow.bearing, (sharp.bearing.0 - 4, sharp.bearing.1 + 4));
+    assert_eq!(shadow.pixels.len(), shadow.size.0 as usize * shadow.size.1 as usize);
+
+    // Coverage fades out towards the edges and is kept mostly intact inside.
+    let width = shadow.size.0 as usize;
+    let center = shadow.pixels[shadow.size.1 as usize / 2 * width + width / 2];
+    assert_eq!(shadow.pixels[0], 0);
+    assert!(shadow.pixels[4 * width + 4] < center);
+    assert!(center > 200);
+
+    let wider = fonts
+        .get_atlas_blurred_glyph(&mut atlas, &a, 'W', MAX_BLUR_RADIUS + 10)
+        .unwrap();
+    assert_eq!(atlas.get(wider).unwrap().size.0, sharp.size.0 + MAX_BLUR_RADIUS * 2);
+    assert_eq!(atlas.len(), 3);
+
+    assert_eq!(atlas.remove_instance(a.external_instance_key()), 3);
+    assert!(atlas.is_empty());
+}
+
+#[test]
+fn test_fonts_atlas_pages() {
+    let mut fonts = FontCache::with_backend(FontKeysAPI::new(()), MockFontBackend::new());
+    assert!(fonts.add_raw(FontId::new("A"), b"Mock A".to_vec(), 0).is_ok());
+    let a = fonts.get_default_font_with_size(Pt(16)).unwrap();
+    fonts.take_resource_updates();
+
+    let mut atlas = GlyphAtlas::with_page_size((32, 32));
+    let wide = fonts.get_atlas_glyph(&mut atlas, &a, 'W').unwrap();
+    let placement = atlas.placement(wide).unwrap();
+    assert_eq!(placement.page, 0);
+    assert_eq!(placement.rect, AtlasRect::new(0, 0, 13, 13));
+    assert_eq!(
+        atlas.uv_rect(wide).unwrap(),
+        AtlasUvRect {
+            u0: 0.0,
+            v0: 0.0,
+            u1: 13.0 / 32.0,
+            v1: 13.0 / 32.0
+        }
+    );
+
+    // The shadow doesn't fit beside or below the glyph, so it opens a page.
+    let shadow = fonts.get_atlas_blurred_glyph(&mut atlas, &a, 'W', 4).unwrap();
+    assert_eq!(atlas.placement(shadow).unwrap().page, 1);
+    assert_eq!(atlas.page_count(), 2);
+
+    assert_eq!(fonts.flush_atlas_updates(&mut atlas), 4);
+    assert_eq!(fonts.flush_atlas_updates(&mut atlas), 0);
+    let wide_pixels = Rc::new(base64_util::to_pixels_data_uri(&atlas.get(wide).unwrap().pixels));
+    let shadow_pixels = Rc::new(base64_util::to_pixels_data_uri(&atlas.get(shadow).unwrap().pixels));
+    assert_eq!(
+        fonts.take_resource_updates().updates,
+        vec![
+            Update::AddGlyphAtlasPage {
+                page: 0,
+                width: 32,
+                height: 32,
+                bytes_per_pixel: 1
+            },
+            Update::UpdateGlyphAtlasRegion {
+                page: 0,
+                x: 0,
+                y: 0,
+                width: 13,
+                height: 13,
+                data_uri: wide_pixels
+            },
+            Update::AddGlyphAtlasPage {
+                page: 1,
+                width: 32,
+                height: 32,
+                bytes_per_pixel: 1
+            },
+            Update::UpdateGlyphAtlasRegion {
+                page: 1,
+                x: 0,
+                y: 0,
+                width: 21,
+                height: 21,
+                data_uri: shadow_pixels
+            },
+        ]
+    );
+
+    // Freed space is packed again, but page ids are never reused.
+    assert_eq!(atlas.remove_instance(a.external_instance_key()), 2);
+    let wide = fonts.get_atlas_glyph(&mut atlas, &a, 'W').unwrap();
+    assert_eq!(atlas.placement(wide).unwrap().rect, placement.rect);
+    atlas.clear();
+    assert_eq!(atlas.page_count(), 0);
+    assert_eq!(fonts.flush_atlas_updates(&mut atlas), 2);
+    assert_eq!(
+        fonts.take_resource_updates().updates,
+        vec![Update::DeleteGlyphAtlasPage { page: 0 }, Update::DeleteGlyphAtlasPage { page: 1 }]
+    );
+    let wide = fonts.get_atlas_glyph(&mut atlas, &a, 'W').unwrap();
+    assert_eq!(atlas.placement(wide).unwrap().page, 2);
+}
+
+#[test]
+fn test_fonts_load_config() {
+    let mut fonts = FontCache::new(FontKeysAPI::new(())).unwrap();
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/fonts.json");
+    assert!(fonts.load_config(path).is_ok());
+    assert!(fonts.load_config("fixtures/missing.json").is_err());
+    assert!(FontConfig::from_json("{ \"fonts\": 0 }").is_err());
+
+    let free_sans = FontId::new("FreeSans");
+    let fira_mono = FontId::new("FiraMono-Regular.ttf");
+    assert_eq!(fonts.get_family_name(fira_mono).unwrap(), "Fira Mono");
+    assert_eq!(
+        fonts.font_descriptor(free_sans),
+        Some(&FontDescriptor::new("FreeSans", 400, FontStyle::Normal))
+    );
+
+    assert_eq!(fonts.resolve_font_family("monospace"), "Fira Mono");
+    assert_eq!(fonts.resolve_font_family("FreeSans"), "FreeSans");
+    assert_eq!(fonts.font_fallbacks("serif").len(), 2);
+
+    assert_eq!(fonts.match_font("sans-serif", 700, FontStyle::Italic), Some(free_sans));
+    assert_eq!(fonts.match_font("monospace", 400, FontStyle::Normal), Some(fira_mono));
+    assert_eq!(fonts.match_font("serif", 400, FontStyle::Normal), Some(free_sans));
+    assert_eq!(fonts.match_font("cursive", 400, FontStyle::Normal), None);
+
+    fonts.set_font_alias("a", "b");
+    fonts.set_font_alias("b", "a");
+    assert_eq!(fonts.match_font("a", 400, FontStyle::Normal), None);
+
+    let instance = fonts
+        .get_or_insert_matching_font("monospace", 400, FontStyle::Normal, Pt(24), Dpi(72))
+        .unwrap();
+    assert_eq!(instance.font_id(), fira_mono);
+    assert_eq!(instance.size(), 24);
+}
+
+#[test]
+fn test_fonts_config_weights() {
+    let mut fonts = FontCache::new(FontKeysAPI::new(())).unwrap();
+    let config = FontConfig::from_json(
+        r#"{
+            "fonts": [
+                { "name": "FreeSans", "path": "tests/fixtures/FreeSans.ttf", "family": "FreeSans" },
+                { "name": "FreeSans-Bold", "path": "tests/fixtures/FreeSans.ttf", "family": "FreeSans", "weight": 700 }
+            ]
+        }"#
+    ).unwrap();
+    assert!(fonts.apply_config(&config).is_ok());
+
+    // Both weights of the family keep their face and get instances of their own.
+    let regular = FontId::new("FreeSans");
+    let bold = FontId::new("FreeSans-Bold");
+    assert_eq!(fonts.font_ids().count(), 2);
+    let instance = fonts
+        .get_or_insert_matching_font("FreeSans", 400, FontStyle::Normal, Pt(24), Dpi(72))
+        .unwrap();
+    assert_eq!(instance.font_id(), regular);
+    let bold_instance = fonts
+        .get_or_insert_matching_font("FreeSans", 700, FontStyle::Normal, Pt(24), Dpi(72))
+        .unwrap();
+    assert_eq!(bold_instance.font_id(), bold);
+    assert_eq!(bold_instance.size(), 24);
+    assert_ne!(bold_instance.external_key(), instance.external_key());
+
+    // The family itself still resolves to its first face.
+    let family = fonts.get_or_insert_font(FontInstanceId::from_family_str("FreeSans", Pt(24), Dpi(72)));
+    assert!(Rc::ptr_eq(&family.unwrap(), &instance));
+    assert_eq!(fonts.take_resource_updates().len(), 6);
+}
+
+#[test]
+#[cfg(feature = "cairo-interop")]
+fn test_cairo_interop() {
+    use rsx_resources::images::cairo::*;
+
+    let pixels = Arc::new(vec![255, 0, 0, 128, 0, 0, 255, 255]);
+    let decoded = DecodedImage::from_raw_parts(ImagePixelFormat::RGBA(8), (2, 1), pixels).unwrap();
+    let surface = decoded.to_cairo_surface_data();
+    assert_eq!(surface.format, CairoFormat::ARgb32);
+    assert_eq!((surface.width, surface.height, surface.stride), (2, 1, 8));
+    if cfg!(target_endian = "little") {
+        assert_eq!(surface.data, vec![0, 0, 128, 128, 255, 0, 0, 255]);
+    }
+
+    let pixels = Arc::new(vec![10, 20, 30]);
+    let decoded = DecodedImage::from_raw_parts(ImagePixelFormat::RGB(8), (1, 1), pixels).unwrap();
+    let surface = decoded.to_cairo_surface_data();
+    assert_eq!(surface.format, CairoFormat::Rgb24);
+    if cfg!(target_endian = "little") {
+        assert_eq!(surface.data, vec![30, 20, 10, 255]);
+    }
+
+    let mut fonts = FontCache::new(FontKeysAPI::new(())).unwrap();
+    let font_bytes = Rc::new(include_bytes!("fixtures/FreeSans.ttf").to_vec());
+    assert!(fonts.add_raw(FontId::new("FreeSans"), font_bytes, 0).is_ok());
+
+    let instance = fonts.get_default_font().unwrap();
+    let glyphs = fonts.shape_text_h(&instance, "ab").unwrap();
+    let cairo_glyphs = glyphs.to_cairo_glyphs((10.0, 20.0));
+    assert_eq!(cairo_glyphs.len(), 2);
+    assert_eq!(cairo_glyphs[0].index, 68);
+    assert_eq!(cairo_glyphs[0].x, 10.0);
+    assert_eq!(
+        cairo_glyphs[1].x,
+        10.0 + f64::from(glyphs.glyphs()[1].x_64) / 64.0
+    );
+    assert_eq!(
+        cairo_glyphs[0].y,
+        20.0 + f64::from(glyphs.glyphs()[0].y_64) / 64.0
+    );
+}
+
+#[test]
+#[cfg(feature = "skia-interop")]
+fn test_skia_interop() {
+    use rsx_resources::images::skia::*;
+
+    let pixels = Arc::new(vec![1, 2, 3, 4, 5, 6, 7, 8]);
+    let decoded = DecodedImage::from_raw_parts(ImagePixelFormat::BGRA(8), (2, 1), Arc::clone(&pixels)).unwrap();
+    let pixmap = decoded.to_skia_pixmap();
+    assert_eq!(
+        pixmap.info,
+        SkiaImageInfo {
+            width: 2,
+            height: 1,
+            color_type: SkiaColorType::BGRA8888,
+            alpha_type: SkiaAlphaType::Unpremul,
+            row_bytes: 8
+        }
+    );
+    assert!(Arc::ptr_eq(&pixmap.pixels, &pixels));
+
+    let pixels = Arc::new(vec![1, 2, 3, 4, 5, 6]);
+    let decoded = DecodedImage::from_raw_parts(ImagePixelFormat::RGB(8), (2, 1), pixels)
+        .unwrap()
+        .with_row_alignment(8);
+    let pixmap = decoded.to_skia_pixmap();
+    assert_eq!(pixmap.info.color_type, SkiaColorType::RGBA8888);
+    assert_eq!(pixmap.info.alpha_type, SkiaAlphaType::Opaque);
+    assert_eq!(*pixmap.pixels, vec![1, 2, 3, 255, 4, 5, 6, 255]);
+
+    let mut fonts = FontCache::new(FontKeysAPI::new(())).unwrap();
+    let font_bytes = Rc::new(include_bytes!("fixtures/FreeSans.ttf").to_vec());
+    assert!(fonts.add_raw(FontId::new("FreeSans"), font_bytes, 0).is_ok());
+
+    let instance = fonts.get_default_font().unwrap();
+    let glyphs = fonts.shape_text_h(&instance, "ab").unwrap();
+    let run = glyphs.to_skia_glyph_run((0.0, 0.0));
+    assert_eq!(run.glyphs, vec![68, 69]);
+    assert_eq!(run.positions.len(), 2);
+    assert_eq!(run.positions[0].x, 0.0);
+}
+
+#[test]
+fn test_error_codes() {
+    assert!(codes::is_consistent());
+    assert_eq!(codes::name(codes::IMAGE_NOT_FOUND), Some("IMAGE_NOT_FOUND"));
+    assert_eq!(codes::name(0), None);
+
+    assert_eq!(FileError::FileNotFound.code(), codes::FILE_NOT_FOUND);
+    assert_eq!(ImageError::InvalidAnimation.code(), codes::IMAGE_INVALID_ANIMATION);
+    assert_eq!(FontError::FaceNotLoaded.code(), codes::FONT_FACE_NOT_LOADED);
+    assert_eq!(PreloadError::FamilyNotFound.code(), codes::PRELOAD_FAMILY_NOT_FOUND);
+
+    // Wrapped errors keep the code of the error they wrap.
+    assert_eq!(FontError::FileError(FileError::FileAlreadyAdded).code(), codes::FILE_ALREADY_ADDED);
+    assert_eq!(FontError::ShapingError(ShapingError::Overflow).code(), codes::SHAPING_OVERFLOW);
+    assert_eq!(FontError::FileError(FileError::FileNotFound).message(), FileError::FileNotFound.message());
+
+    let mut images = ImageCache::new(ImageKeysAPI::new(())).unwrap();
+    let err = images.add_raw(ImageId::new("garbage"), vec![1, 2, 3]).unwrap_err();
+    assert!(codes::name(err.code()).unwrap().starts_with("IMAGE_"));
+    assert!(!err.message().is_empty());
+}
+
+#[test]
+fn test_persistence_options() {
+    let font = include_bytes!("fixtures/FreeSans.ttf").to_vec();
+    let options = PersistenceOptions::default();
+    assert_eq!(options.codec(PayloadType::Font), Codec::preferred());
+    assert_eq!(options.codec(PayloadType::for_image(ImageEncodingFormat::JPEG)), Codec::Identity);
+    assert_eq!(PayloadType::for_image(ImageEncodingFormat::BMP), PayloadType::RawImage);
+
+    // Payloads name their codec, so readers don't need the writer's options.
+    let compressed = options.compress(PayloadType::Font, &font).unwrap();
+    assert_eq!(PersistenceOptions::uncompressed().decompress(&compressed).unwrap(), font);
+    let stored = PersistenceOptions::uncompressed().compress(PayloadType::Font, &font).unwrap();
+    assert_eq!(stored.len(), font.len() + 9);
+    assert_eq!(options.decompress(&stored).unwrap(), font);
+
+    assert_eq!(options.decompress(&stored[..4]).unwrap_err(), PersistenceError::Truncated);
+    assert_eq!(options.decompress(&stored[..stored.len() - 1]).unwrap_err(), PersistenceError::Truncated);
+    let mut unknown = stored.clone();
+    unknown[0] = 255;
+    assert_eq!(options.decompress(&unknown).unwrap_err(), PersistenceError::UnknownCodec(255));
+    assert_eq!(options.decompress(&unknown).unwrap_err().code(), codes::PERSISTENCE_UNKNOWN_CODEC);
+
+    // Lengths claimed by headers are checked before anything is allocated.
+    let mut forged = compressed.clone();
+    forged[5..9].copy_from_slice(&[0xff, 0xff, 0xff, 0xff]);
+    assert_eq!(options.decompress(&forged).unwrap_err(), PersistenceError::PayloadTooLarge);
+    let limited = PersistenceOptions {
+        max_payload_len: font.len() - 1,
+        ..PersistenceOptions::uncompressed()
+    };
+    assert_eq!(limited.decompress(&stored).unwrap_err().code(), codes::PERSISTENCE_PAYLOAD_TOO_LARGE);
+    assert_eq!(limited.compress(PayloadType::Font, &font).unwrap_err(), PersistenceError::PayloadTooLarge);
+
+    let codecs = [Codec::Zstd { level: DEFAULT_ZSTD_LEVEL }, Codec::Lz4];
+    for &codec in &codecs {
+        let options = PersistenceOptions {
+            fonts: codec,
+            ..PersistenceOptions::uncompressed()
+        };
+        match options.compress(PayloadType::Font, &font) {
+            Ok(compressed) => {
+                assert!(codec.is_available());
+                assert!(compressed.len() < font.len());
+                assert_eq!(PersistenceOptions::uncompressed().decompress(&compressed).unwrap(), font);
+            }
+            Err(err) => {
+                assert!(!codec.is_available());
+                assert_eq!(err, PersistenceError::CodecUnavailable(codec));
+            }
+        }
+    }
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn test_persistence_dictionaries() {
+    let samples = (0..64)
+        .map(|i| format!("{{\"id\":\"icon-{}\",\"size\":[{},{}],\"format\":\"PNG\"}}", i, i % 8, i % 16).into_bytes())
+        .collect::<Vec<_>>();
+    let mut options = PersistenceOptions::uncompressed();
+    options.metadata = Codec::preferred();
+    // Any bytes make a raw content dictionary, e.g. typical payloads.
+    options.set_dictionary(PayloadType::Metadata, samples[..8].concat());
+
+    let compressed = options.compress(PayloadType::Metadata, &samples[3]).unwrap();
+    assert_eq!(options.decompress(&compressed).unwrap(), samples[3]);
+
+    // Readers need the dictionary the payload was written with.
+    match PersistenceOptions::uncompressed().decompress(&compressed).unwrap_err() {
+        PersistenceError::DictionaryMissing(_) => {}
+        err => panic!("{:?}", err)
+    }
+}
+
+#[test]
+fn test_images_progressive_preview() {
+    let interlaced = &include_bytes!("fixtures/Interlaced.png")[..];
+    let preview = rsx_resources::images::progressive::preview_pass(ImageEncodingFormat::PNG, interlaced).unwrap();
+    assert_eq!(preview.kind, rsx_resources::images::progressive::ProgressiveKind::InterlacedPng);
+    assert_eq!(preview.size, (24, 16));
+    assert_eq!(preview.decoded.size, (3, 2));
+    assert_eq!(preview.scale(), 0.125);
+
+    // The coarse pass holds every eighth pixel, matching the full decode there.
+    let full = DecodedImage::from_bytes(ImageEncodingFormat::PNG, interlaced).unwrap();
+    let pixel = |decoded: &DecodedImage, x: usize, y: usize| decoded.pixels[y * decoded.row_stride + x * 4..][..4].to_vec();
+    assert_eq!(pixel(&preview.decoded, 0, 0), pixel(&full, 0, 0));
+    assert_eq!(pixel(&preview.decoded, 1, 1), pixel(&full, 8, 8));
+
+    let quantum = &include_bytes!("fixtures/Quantum.png")[..];
+    assert!(rsx_resources::images::progressive::preview_pass(ImageEncodingFormat::PNG, quantum).is_none());
+
+    // Headers claiming huge sizes aren't previewed, rather than allocated for.
+    let mut forged = interlaced.to_vec();
+    forged[16..20].copy_from_slice(&[0x7F, 0xFF, 0xFF, 0xFF]);
+    assert!(rsx_resources::images::progressive::preview_pass(ImageEncodingFormat::PNG, &forged).is_none());
+    forged[16..24].copy_from_slice(&[0, 0, 0x80, 0, 0, 0, 0x80, 0]);
+    assert!(rsx_resources::images::progressive::preview_pass(ImageEncodingFormat::PNG, &forged).is_none());
+
+    let mut images = ImageCache::new(ImageKeysAPI::new(())).unwrap();
+    let image_id = ImageId::new("Interlaced.png");
+    let encoded = EncodedImage::from_bytes(interlaced.to_vec()).unwrap();
+    assert!(images.add_progressive_image(image_id, &encoded).is_ok());
+    assert!(images.is_progressive_preview(image_id));
+    let preview = images.get_image_for_id(image_id).unwrap();
+    let key = preview.external_key();
+    assert_eq!((preview.width(), preview.height()), (3, 2));
+    assert_eq!((preview.css_width(), preview.css_height()), (24.0, 16.0));
+
+    // Images which aren't progressive are added fully decoded.
+    let quantum_id = ImageId::new("Quantum.png");
+    let encoded = EncodedImage::from_bytes(quantum.to_vec()).unwrap();
+    assert!(images.add_progressive_image(quantum_id, &encoded).is_ok());
+    assert!(!images.is_progressive_preview(quantum_id));
+
+    let results = images.complete_progressive_images();
+    assert_eq!(results.len(), 1);
+    assert!(results[0].1.is_ok());
+    assert!(!images.is_progressive_preview(image_id));
+    assert_eq!(images.get_image_for_id(image_id).unwrap().external_key(), key);
+    assert_eq!(images.get_image_for_id(image_id).unwrap().scale(), 1.0);
+    assert_eq!(&*images.get_image_for_id(image_id).unwrap().pixels(), &*full.pixels);
+    assert_eq!(
+        images.complete_progressive_image(image_id).unwrap_err().code(),
+        codes::IMAGE_NOT_FOUND
+    );
+
+    // The final pass is applied under the preview's key, after its addition.
+    let mut updates = images.take_resource_updates();
+    match updates.updates[..] {
+        [Update::AddImage { .. }, Update::AddImage { .. }, Update::UpdateImage { key: updated, .. }] => assert_eq!(updated, key),
+        _ => panic!("Expected the preview to be updated")
+    }
+    updates.updates.swap(0, 2);
+    assert!(!updates.is_ordered());
+    updates.sort_dependencies();
+    assert!(updates.is_ordered());
+}
+
+#[test]
+fn test_images_sprite_grid() {
+    let quantum = &include_bytes!("fixtures/Quantum.png")[..];
+    let mut images = ImageCache::new(ImageKeysAPI::new(())).unwrap();
+    let sheet_id = ImageId::new("Quantum.png");
+    let mut grid = SpriteGrid::new((128, 128));
+    grid.margin = 4;
+    grid.spacing = 2;
+    assert_eq!(grid.cells_within((529, 512)), (4, 3));
+    let oversized = SpriteGrid {
+        margin: u32::max_value(),
+        ..grid
+    };
+    assert_eq!(oversized.cells_within((529, 512)), (0, 0));
+    let spread = SpriteGrid {
+        spacing: u32::max_value(),
+        ..grid
+    };
+    assert_eq!(spread.cells_within((529, 512)), (1, 1));
+    assert_eq!(
+        images.add_sprite_grid(sheet_id, grid).unwrap_err().code(),
+        codes::IMAGE_NOT_FOUND
+    );
+
+    let encoded = EncodedImage::from_bytes(quantum.to_vec()).unwrap();
+    assert!(images.add_image(sheet_id, &encoded).is_ok());
+    let cell_ids = images.add_sprite_grid(sheet_id, grid).unwrap();
+    assert_eq!(cell_ids.len(), 12);
+    assert_eq!(cell_ids[0], ImageId::new("Quantum.png#0"));
+    assert_eq!(cell_ids[11], sheet_id.with_cell(11));
+    assert!(images.get_image("Quantum.png#11").is_some());
+
+    // The second cell of the second row starts past the margin and a spacing.
+    let sheet = images.get_image_for_id(sheet_id).unwrap();
+    let cell = images.get_image("Quantum.png#5").unwrap();
+    assert_eq!((cell.width(), cell.height()), (128, 128));
+    let bytes_per_pixel = pixels::bytes_per_pixel(cell.format());
+    let offset = 134 * sheet.row_stride() + 134 * bytes_per_pixel;
+    assert_eq!(&cell.pixels()[..bytes_per_pixel], &sheet.pixels()[offset..offset + bytes_per_pixel]);
+
+    assert_eq!(
+        images.add_sprite_grid(sheet_id, grid).unwrap_err().code(),
+        codes::IMAGE_ALREADY_ADDED
+    );
+    grid.count = Some(13);
+    assert_eq!(
+        images.add_sprite_grid(sheet_id, grid).unwrap_err().code(),
+        codes::IMAGE_INVALID_SPRITE_GRID
+    );
+    assert!(images.get_image_for_id(sheet_id.with_cell(12)).is_none());
 }
@@ -0,0 +1,79 @@
+/*
+Copyright 2016 Mozilla
+Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+this file except in compliance with the License. You may obtain a copy of the
+License at http://www.apache.org/licenses/LICENSE-2.0
+Unless required by applicable law or agreed to in writing, software distributed
+under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+CONDITIONS OF ANY KIND, either express or implied. See the License for the
+specific language governing permissions and limitations under the License.
+*/
+
+#![feature(test)]
+
+extern crate rsx_resource_updates;
+extern crate serde_json;
+extern crate test;
+
+use std::rc::Rc;
+
+use rsx_resource_updates::types::{DefaultFontInstanceKey, DefaultFontKey, DefaultImageKey, ResourceUpdates};
+use test::Bencher;
+
+type Updates = ResourceUpdates<DefaultImageKey, DefaultFontKey, DefaultFontInstanceKey>;
+
+// A frame adding a few big images, roughly the size of base64 encoded photos.
+fn big_frame() -> Updates {
+    let mut updates = Updates::with_capacity(8);
+    for i in 0..8 {
+        let data_uri = format!("data:image/png;base64,{}", "iVBORw0KGgo".repeat(64 * 1024));
+        updates.add_image(DefaultImageKey(i), Rc::new(data_uri));
+    }
+    updates
+}
+
+// Many small updates, as when a page registers lots of font instances.
+fn small_frame() -> Updates {
+    let mut updates = Updates::with_capacity(1024);
+    for i in 0..1024 {
+        updates.add_font_instance(DefaultFontInstanceKey(i), DefaultFontKey(i / 16), 12 + (i as u32 % 32));
+    }
+    updates
+}
+
+// Compares fresh allocations per frame, as with `Into<String>`, against
+// writing into a buffer kept around between frames.
+
+#[bench]
+fn bench_big_frame_to_string(b: &mut Bencher) {
+    let frame = big_frame();
+    b.iter(|| serde_json::to_string(&frame.updates).unwrap().len());
+}
+
+#[bench]
+fn bench_big_frame_write_json_to(b: &mut Bencher) {
+    let frame = big_frame();
+    let mut buffer = Vec::new();
+    b.iter(|| {
+        buffer.clear();
+        frame.write_json_to(&mut buffer).unwrap();
+        buffer.len()
+    });
+}
+
+#[bench]
+fn bench_small_frame_to_string(b: &mut Bencher) {
+    let frame = small_frame();
+    b.iter(|| serde_json::to_string(&frame.updates).unwrap().len());
+}
+
+#[bench]
+fn bench_small_frame_write_json_to(b: &mut Bencher) {
+    let frame = small_frame();
+    let mut buffer = Vec::new();
+    b.iter(|| {
+        buffer.clear();
+        frame.write_json_to(&mut buffer).unwrap();
+        buffer.len()
+    });
+}
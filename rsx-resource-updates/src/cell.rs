@@ -0,0 +1,120 @@
+/*
+Copyright 2016 Mozilla
+Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+this file except in compliance with the License. You may obtain a copy of the
+License at http://www.apache.org/licenses/LICENSE-2.0
+Unless required by applicable law or agreed to in writing, software distributed
+under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+CONDITIONS OF ANY KIND, either express or implied. See the License for the
+specific language governing permissions and limitations under the License.
+*/
+
+use std::cell::{Ref, RefCell, RefMut};
+use std::fmt;
+use std::mem;
+
+// A `RefCell` which allows mutations requested while the value is borrowed
+// (e.g. from within a read path) to be queued instead of panicking. Queued
+// mutations are applied in order as soon as nothing borrows the value, by the
+// next borrow of either kind, so reads see them too.
+
+pub struct SharedCell<T> {
+    value: RefCell<T>,
+    pending: RefCell<Vec<Box<FnMut(&mut T)>>>
+}
+
+impl<T> SharedCell<T> {
+    pub fn new(value: T) -> Self {
+        SharedCell {
+            value: RefCell::new(value),
+            pending: RefCell::new(Vec::new())
+        }
+    }
+
+    pub fn borrow(&self) -> Ref<T> {
+        self.flush();
+        self.value.borrow()
+    }
+
+    pub fn try_borrow(&self) -> Option<Ref<T>> {
+        self.flush();
+        self.value.try_borrow().ok()
+    }
+
+    pub fn borrow_mut(&self) -> RefMut<T> {
+        let mut value = self.value.borrow_mut();
+        self.apply_pending(&mut value);
+        value
+    }
+
+    pub fn try_borrow_mut(&self) -> Option<RefMut<T>> {
+        let mut value = self.value.try_borrow_mut().ok()?;
+        self.apply_pending(&mut value);
+        Some(value)
+    }
+
+    pub fn is_borrowed(&self) -> bool {
+        self.value.try_borrow_mut().is_err()
+    }
+
+    // Returns true if the mutation was applied right away, or false if it was
+    // queued because the value is currently borrowed.
+    pub fn mutate<F>(&self, mut f: F) -> bool
+    where
+        F: FnMut(&mut T) + 'static
+    {
+        match self.try_borrow_mut() {
+            Some(mut value) => {
+                f(&mut value);
+                true
+            }
+            None => {
+                self.pending.borrow_mut().push(Box::new(f));
+                false
+            }
+        }
+    }
+
+    pub fn flush(&self) {
+        if let Ok(mut value) = self.value.try_borrow_mut() {
+            self.apply_pending(&mut value);
+        }
+    }
+
+    pub fn pending_mutations(&self) -> usize {
+        self.pending.borrow().len()
+    }
+
+    fn apply_pending(&self, value: &mut T) {
+        loop {
+            let pending = mem::replace(&mut *self.pending.borrow_mut(), Vec::new());
+            if pending.is_empty() {
+                break;
+            }
+            for mut f in pending {
+                f(value);
+            }
+        }
+    }
+}
+
+impl<T> fmt::Debug for SharedCell<T>
+where
+    T: fmt::Debug
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SharedCell")
+            .field("value", &self.value)
+            .field("pending", &self.pending_mutations())
+            .finish()
+    }
+}
+
+impl<T> PartialEq for SharedCell<T>
+where
+    T: PartialEq
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.value == other.value
+    }
+}
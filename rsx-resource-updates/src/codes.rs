@@ -0,0 +1,142 @@
+/*
+Copyright 2016 Mozilla
+Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+this file except in compliance with the License. You may obtain a copy of the
+License at http://www.apache.org/licenses/LICENSE-2.0
+Unless required by applicable law or agreed to in writing, software distributed
+under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+CONDITIONS OF ANY KIND, either express or implied. See the License for the
+specific language governing permissions and limitations under the License.
+*/
+
+// Stable numeric codes for the errors of every cache, for hosts on the other
+// side of the C ABI or the JS bindings to branch on. Each error enum owns a
+// range of codes. Codes are only ever appended: the code of a removed variant
+// moves to `RETIRED`, so that no code ever changes meaning.
+
+pub type ErrorCode = u32;
+
+pub const FILE_IO: ErrorCode = 1000;
+pub const FILE_ALREADY_ADDED: ErrorCode = 1001;
+pub const FILE_NOT_FOUND: ErrorCode = 1002;
+
+pub const IMAGE_IO: ErrorCode = 2000;
+pub const IMAGE_DECODER: ErrorCode = 2001;
+pub const IMAGE_DATA_URI_DECODE: ErrorCode = 2002;
+pub const IMAGE_ALREADY_ADDED: ErrorCode = 2003;
+pub const IMAGE_NOT_FOUND: ErrorCode = 2004;
+pub const IMAGE_INVALID_SET: ErrorCode = 2005;
+pub const IMAGE_INVALID_ARRAY: ErrorCode = 2006;
+pub const IMAGE_INVALID_MASKED: ErrorCode = 2007;
+pub const IMAGE_INVALID_ANIMATION: ErrorCode = 2008;
+pub const IMAGE_REPLAY_UNSUPPORTED: ErrorCode = 2009;
+pub const IMAGE_DECODER_PANIC: ErrorCode = 2010;
+pub const IMAGE_INVALID_SPRITE_GRID: ErrorCode = 2011;
+
+pub const FONT_FREETYPE: ErrorCode = 3000;
+pub const FONT_IO: ErrorCode = 3001;
+pub const FONT_UTF8: ErrorCode = 3002;
+pub const FONT_JSON: ErrorCode = 3003;
+pub const FONT_DATA_URI_DECODE: ErrorCode = 3004;
+pub const FONT_FACE_ALREADY_ADDED: ErrorCode = 3005;
+pub const FONT_INSTANCE_ALREADY_ADDED: ErrorCode = 3006;
+pub const FONT_FACE_NOT_FOUND: ErrorCode = 3007;
+pub const FONT_FACE_NOT_LOADED: ErrorCode = 3008;
+pub const FONT_FACE_FAMILY_NAME_MISSING: ErrorCode = 3009;
+pub const FONT_FACE_SIZE_MISSING: ErrorCode = 3010;
+pub const FONT_FACE_GLYPH_MISSING: ErrorCode = 3011;
+pub const FONT_FACE_METRICS_MISSING: ErrorCode = 3012;
+pub const FONT_GLYPH_BITMAP_UNSUPPORTED: ErrorCode = 3013;
+pub const FONT_RASTERIZATION_UNSUPPORTED: ErrorCode = 3014;
+pub const FONT_REPLAY_UNSUPPORTED: ErrorCode = 3015;
+pub const FONT_WOFF_DECODE: ErrorCode = 3016;
+pub const FONT_WOFF_UNSUPPORTED: ErrorCode = 3017;
+pub const FONT_THREAD_UNAVAILABLE: ErrorCode = 3018;
+
+pub const SHAPING_OVERFLOW: ErrorCode = 3500;
+
+pub const PRELOAD_FILE_NOT_LOADED: ErrorCode = 4000;
+pub const PRELOAD_IMAGE_NOT_DECODED: ErrorCode = 4001;
+pub const PRELOAD_FONT_NOT_DECODED: ErrorCode = 4002;
+pub const PRELOAD_FAMILY_NOT_FOUND: ErrorCode = 4003;
+
+pub const LOOKUP_IMAGE_NOT_REGISTERED: ErrorCode = 5000;
+pub const LOOKUP_FAMILY_NOT_REGISTERED: ErrorCode = 5001;
+
+pub const PERSISTENCE_CODEC_UNAVAILABLE: ErrorCode = 6000;
+pub const PERSISTENCE_UNKNOWN_CODEC: ErrorCode = 6001;
+pub const PERSISTENCE_DICTIONARY_MISSING: ErrorCode = 6002;
+pub const PERSISTENCE_TRUNCATED: ErrorCode = 6003;
+pub const PERSISTENCE_PAYLOAD_TOO_LARGE: ErrorCode = 6004;
+pub const PERSISTENCE_CODEC_FAILED: ErrorCode = 6005;
+
+pub const CAPI_PANIC: ErrorCode = 7000;
+
+// Every code in use, with the name hosts see it under.
+pub const REGISTRY: &[(ErrorCode, &str)] = &[
+    (FILE_IO, "FILE_IO"),
+    (FILE_ALREADY_ADDED, "FILE_ALREADY_ADDED"),
+    (FILE_NOT_FOUND, "FILE_NOT_FOUND"),
+    (IMAGE_IO, "IMAGE_IO"),
+    (IMAGE_DECODER, "IMAGE_DECODER"),
+    (IMAGE_DATA_URI_DECODE, "IMAGE_DATA_URI_DECODE"),
+    (IMAGE_ALREADY_ADDED, "IMAGE_ALREADY_ADDED"),
+    (IMAGE_NOT_FOUND, "IMAGE_NOT_FOUND"),
+    (IMAGE_INVALID_SET, "IMAGE_INVALID_SET"),
+    (IMAGE_INVALID_ARRAY, "IMAGE_INVALID_ARRAY"),
+    (IMAGE_INVALID_MASKED, "IMAGE_INVALID_MASKED"),
+    (IMAGE_INVALID_ANIMATION, "IMAGE_INVALID_ANIMATION"),
+    (IMAGE_REPLAY_UNSUPPORTED, "IMAGE_REPLAY_UNSUPPORTED"),
+    (IMAGE_DECODER_PANIC, "IMAGE_DECODER_PANIC"),
+    (IMAGE_INVALID_SPRITE_GRID, "IMAGE_INVALID_SPRITE_GRID"),
+    (FONT_FREETYPE, "FONT_FREETYPE"),
+    (FONT_IO, "FONT_IO"),
+    (FONT_UTF8, "FONT_UTF8"),
+    (FONT_JSON, "FONT_JSON"),
+    (FONT_DATA_URI_DECODE, "FONT_DATA_URI_DECODE"),
+    (FONT_FACE_ALREADY_ADDED, "FONT_FACE_ALREADY_ADDED"),
+    (FONT_INSTANCE_ALREADY_ADDED, "FONT_INSTANCE_ALREADY_ADDED"),
+    (FONT_FACE_NOT_FOUND, "FONT_FACE_NOT_FOUND"),
+    (FONT_FACE_NOT_LOADED, "FONT_FACE_NOT_LOADED"),
+    (FONT_FACE_FAMILY_NAME_MISSING, "FONT_FACE_FAMILY_NAME_MISSING"),
+    (FONT_FACE_SIZE_MISSING, "FONT_FACE_SIZE_MISSING"),
+    (FONT_FACE_GLYPH_MISSING, "FONT_FACE_GLYPH_MISSING"),
+    (FONT_FACE_METRICS_MISSING, "FONT_FACE_METRICS_MISSING"),
+    (FONT_GLYPH_BITMAP_UNSUPPORTED, "FONT_GLYPH_BITMAP_UNSUPPORTED"),
+    (FONT_RASTERIZATION_UNSUPPORTED, "FONT_RASTERIZATION_UNSUPPORTED"),
+    (FONT_REPLAY_UNSUPPORTED, "FONT_REPLAY_UNSUPPORTED"),
+    (FONT_WOFF_DECODE, "FONT_WOFF_DECODE"),
+    (FONT_WOFF_UNSUPPORTED, "FONT_WOFF_UNSUPPORTED"),
+    (FONT_THREAD_UNAVAILABLE, "FONT_THREAD_UNAVAILABLE"),
+    (SHAPING_OVERFLOW, "SHAPING_OVERFLOW"),
+    (PRELOAD_FILE_NOT_LOADED, "PRELOAD_FILE_NOT_LOADED"),
+    (PRELOAD_IMAGE_NOT_DECODED, "PRELOAD_IMAGE_NOT_DECODED"),
+    (PRELOAD_FONT_NOT_DECODED, "PRELOAD_FONT_NOT_DECODED"),
+    (PRELOAD_FAMILY_NOT_FOUND, "PRELOAD_FAMILY_NOT_FOUND"),
+    (LOOKUP_IMAGE_NOT_REGISTERED, "LOOKUP_IMAGE_NOT_REGISTERED"),
+    (LOOKUP_FAMILY_NOT_REGISTERED, "LOOKUP_FAMILY_NOT_REGISTERED"),
+    (PERSISTENCE_CODEC_UNAVAILABLE, "PERSISTENCE_CODEC_UNAVAILABLE"),
+    (PERSISTENCE_UNKNOWN_CODEC, "PERSISTENCE_UNKNOWN_CODEC"),
+    (PERSISTENCE_DICTIONARY_MISSING, "PERSISTENCE_DICTIONARY_MISSING"),
+    (PERSISTENCE_TRUNCATED, "PERSISTENCE_TRUNCATED"),
+    (PERSISTENCE_PAYLOAD_TOO_LARGE, "PERSISTENCE_PAYLOAD_TOO_LARGE"),
+    (PERSISTENCE_CODEC_FAILED, "PERSISTENCE_CODEC_FAILED"),
+    (CAPI_PANIC, "CAPI_PANIC")
+];
+
+// Codes of removed variants, which mustn't be handed out again.
+pub const RETIRED: &[ErrorCode] = &[];
+
+pub fn name(code: ErrorCode) -> Option<&'static str> {
+    REGISTRY
+        .iter()
+        .find(|&&(registered, _)| registered == code)
+        .map(|&(_, name)| name)
+}
+
+// Whether no code is registered twice or registered after being retired.
+pub fn is_consistent() -> bool {
+    REGISTRY.iter().enumerate().all(|(i, &(code, _))| {
+        !RETIRED.contains(&code) && REGISTRY[i + 1..].iter().all(|&(other, _)| other != code)
+    })
+}
@@ -0,0 +1,69 @@
+/*
+Copyright 2016 Mozilla
+Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+this file except in compliance with the License. You may obtain a copy of the
+License at http://www.apache.org/licenses/LICENSE-2.0
+Unless required by applicable law or agreed to in writing, software distributed
+under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+CONDITIONS OF ANY KIND, either express or implied. See the License for the
+specific language governing permissions and limitations under the License.
+*/
+
+use units::{Dpi, Scale};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum SubpixelLayout {
+    None,
+    RGB,
+    BGR,
+    Vertical
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DisplayProfile {
+    pub dpi: Dpi,
+    pub device_pixel_ratio: Scale,
+    pub subpixel_layout: SubpixelLayout
+}
+
+impl DisplayProfile {
+    pub const STANDARD: DisplayProfile = DisplayProfile {
+        dpi: Dpi::DEFAULT,
+        device_pixel_ratio: Scale(1.0),
+        subpixel_layout: SubpixelLayout::RGB
+    };
+
+    pub const HIDPI: DisplayProfile = DisplayProfile {
+        dpi: Dpi::DEFAULT,
+        device_pixel_ratio: Scale(2.0),
+        subpixel_layout: SubpixelLayout::None
+    };
+
+    pub const MOBILE_HIDPI: DisplayProfile = DisplayProfile {
+        dpi: Dpi::DEFAULT,
+        device_pixel_ratio: Scale(3.0),
+        subpixel_layout: SubpixelLayout::None
+    };
+
+    pub fn with_device_pixel_ratio(device_pixel_ratio: Scale) -> Self {
+        DisplayProfile {
+            device_pixel_ratio,
+            ..DisplayProfile::STANDARD
+        }
+    }
+
+    // Resolution fonts should be rasterized at, accounting for the pixel ratio.
+    pub fn font_dpi(&self) -> Dpi {
+        self.dpi.scale(self.device_pixel_ratio)
+    }
+
+    pub fn prefers_subpixel(&self) -> bool {
+        self.subpixel_layout != SubpixelLayout::None
+    }
+}
+
+impl Default for DisplayProfile {
+    fn default() -> Self {
+        DisplayProfile::STANDARD
+    }
+}
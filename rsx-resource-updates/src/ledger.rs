@@ -0,0 +1,82 @@
+/*
+Copyright 2016 Mozilla
+Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+this file except in compliance with the License. You may obtain a copy of the
+License at http://www.apache.org/licenses/LICENSE-2.0
+Unless required by applicable law or agreed to in writing, software distributed
+under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+CONDITIONS OF ANY KIND, either express or implied. See the License for the
+specific language governing permissions and limitations under the License.
+*/
+
+// Bookkeeping for external keys allocated through a keys API, used to find
+// keys which were never deleted even though nothing refers to them anymore.
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct KeyLedgerEntry<Id, Key> {
+    pub id: Id,
+    pub key: Key,
+    pub deleted: bool
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct KeyLedger<Id, Key> {
+    entries: Vec<KeyLedgerEntry<Id, Key>>,
+    unknown_deletes: Vec<Key>
+}
+
+impl<Id, Key> KeyLedger<Id, Key>
+where
+    Id: Copy,
+    Key: Copy + PartialEq
+{
+    pub fn new() -> Self {
+        KeyLedger {
+            entries: Vec::new(),
+            unknown_deletes: Vec::new()
+        }
+    }
+
+    pub fn allocated(&mut self, id: Id, key: Key) {
+        self.entries.push(KeyLedgerEntry {
+            id,
+            key,
+            deleted: false
+        });
+    }
+
+    pub fn deleted(&mut self, key: Key) {
+        match self.entries
+            .iter_mut()
+            .find(|entry| !entry.deleted && entry.key == key)
+        {
+            Some(entry) => entry.deleted = true,
+            None => self.unknown_deletes.push(key)
+        }
+    }
+
+    pub fn leaked<F>(&self, is_referenced: F) -> Vec<(Id, Key)>
+    where
+        F: Fn(Key) -> bool
+    {
+        self.entries
+            .iter()
+            .filter(|entry| !entry.deleted && !is_referenced(entry.key))
+            .map(|entry| (entry.id, entry.key))
+            .collect()
+    }
+
+    pub fn unknown_deletes(&self) -> &[Key] {
+        &self.unknown_deletes
+    }
+}
+
+impl<Id, Key> Default for KeyLedger<Id, Key>
+where
+    Id: Copy,
+    Key: Copy + PartialEq
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -15,5 +15,21 @@ extern crate serde;
 #[macro_use]
 extern crate serde_derive;
 extern crate serde_json;
+#[cfg(feature = "simd-json")]
+extern crate simd_json;
 
+pub mod cell;
+pub mod codes;
+pub mod display;
+pub mod ledger;
+pub mod namespace;
+pub mod order;
+pub mod pixels;
+pub mod quota;
+pub mod shmem;
+pub mod snapshot;
+pub mod staging;
+pub mod stats;
+pub mod traits;
 pub mod types;
+pub mod units;
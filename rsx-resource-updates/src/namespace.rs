@@ -0,0 +1,142 @@
+/*
+Copyright 2016 Mozilla
+Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+this file except in compliance with the License. You may obtain a copy of the
+License at http://www.apache.org/licenses/LICENSE-2.0
+Unless required by applicable law or agreed to in writing, software distributed
+under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+CONDITIONS OF ANY KIND, either express or implied. See the License for the
+specific language governing permissions and limitations under the License.
+*/
+
+// Keys are plain numbers once serialized, so updates written with one keys API
+// would be read back just as well into another's key types. Key types name the
+// namespace they're allocated in, which is written ahead of the updates and
+// checked before they're read back.
+
+use std::fmt;
+use std::io;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json;
+
+use types::{self, DefaultFontInstanceKey, DefaultFontKey, DefaultImageKey, ResourceUpdates, Update};
+
+pub trait TKeyNamespace {
+    const NAMESPACE: &'static str;
+}
+
+impl TKeyNamespace for DefaultImageKey {
+    const NAMESPACE: &'static str = "rsx.image";
+}
+
+impl TKeyNamespace for DefaultFontKey {
+    const NAMESPACE: &'static str = "rsx.font";
+}
+
+impl TKeyNamespace for DefaultFontInstanceKey {
+    const NAMESPACE: &'static str = "rsx.font-instance";
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct KeyNamespaces {
+    pub image: String,
+    pub font: String,
+    pub font_instance: String
+}
+
+impl KeyNamespaces {
+    pub fn of<ImageKey, FontKey, FontInstanceKey>() -> Self
+    where
+        ImageKey: TKeyNamespace,
+        FontKey: TKeyNamespace,
+        FontInstanceKey: TKeyNamespace
+    {
+        KeyNamespaces {
+            image: ImageKey::NAMESPACE.to_string(),
+            font: FontKey::NAMESPACE.to_string(),
+            font_instance: FontInstanceKey::NAMESPACE.to_string()
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum NamespaceError {
+    Json(String),
+    Mismatch {
+        expected: KeyNamespaces,
+        found: KeyNamespaces
+    }
+}
+
+impl fmt::Display for NamespaceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &NamespaceError::Json(ref err) => write!(f, "Malformed namespaced updates: {}", err),
+            &NamespaceError::Mismatch {
+                ref expected,
+                ref found
+            } => write!(f, "Updates keyed in {:?}, expected {:?}", found, expected)
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct NamespacedUpdatesRef<'a, ImageKey: 'a, FontKey: 'a, FontInstanceKey: 'a> {
+    namespaces: KeyNamespaces,
+    updates: &'a [Update<ImageKey, FontKey, FontInstanceKey>]
+}
+
+#[derive(Deserialize)]
+struct NamespacesHeader {
+    namespaces: KeyNamespaces
+}
+
+#[derive(Deserialize)]
+struct NamespacedUpdates<ImageKey, FontKey, FontInstanceKey> {
+    updates: Vec<Update<ImageKey, FontKey, FontInstanceKey>>
+}
+
+impl<ImageKey, FontKey, FontInstanceKey> ResourceUpdates<ImageKey, FontKey, FontInstanceKey>
+where
+    ImageKey: Serialize + TKeyNamespace,
+    FontKey: Serialize + TKeyNamespace,
+    FontInstanceKey: Serialize + TKeyNamespace
+{
+    // Like `write_json_to`, wrapped in an object naming the key namespaces.
+    pub fn write_namespaced_json_to(&self, buffer: &mut Vec<u8>) -> io::Result<()> {
+        buffer.reserve(self.json_size_hint() + 128);
+        let namespaced = NamespacedUpdatesRef {
+            namespaces: KeyNamespaces::of::<ImageKey, FontKey, FontInstanceKey>(),
+            updates: &self.updates
+        };
+        types::write_json(buffer, &namespaced)
+    }
+}
+
+impl<ImageKey, FontKey, FontInstanceKey> ResourceUpdates<ImageKey, FontKey, FontInstanceKey>
+where
+    ImageKey: DeserializeOwned + TKeyNamespace,
+    FontKey: DeserializeOwned + TKeyNamespace,
+    FontInstanceKey: DeserializeOwned + TKeyNamespace
+{
+    // The namespaces are checked on their own first, since keys of another
+    // namespace may not even parse as these key types.
+    pub fn from_namespaced_json(bytes: &[u8]) -> Result<Self, NamespaceError> {
+        let header: NamespacesHeader = serde_json::from_slice(bytes).map_err(|err| NamespaceError::Json(err.to_string()))?;
+        let expected = KeyNamespaces::of::<ImageKey, FontKey, FontInstanceKey>();
+        if header.namespaces != expected {
+            Err(NamespaceError::Mismatch {
+                expected,
+                found: header.namespaces
+            })?
+        }
+
+        let namespaced: NamespacedUpdates<ImageKey, FontKey, FontInstanceKey> =
+            serde_json::from_slice(bytes).map_err(|err| NamespaceError::Json(err.to_string()))?;
+        Ok(ResourceUpdates {
+            updates: namespaced.updates
+        })
+    }
+}
@@ -0,0 +1,284 @@
+/*
+Copyright 2016 Mozilla
+Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+this file except in compliance with the License. You may obtain a copy of the
+License at http://www.apache.org/licenses/LICENSE-2.0
+Unless required by applicable law or agreed to in writing, software distributed
+under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+CONDITIONS OF ANY KIND, either express or implied. See the License for the
+specific language governing permissions and limitations under the License.
+*/
+
+// Updates in a batch may refer to resources added or deleted by other updates
+// in the same batch: instances to their font, glyph runs to their instance,
+// array layers to their array, image updates to their image, mask links to
+// both images, glyph atlas regions to their page, and deletes to everything
+// using what they delete. A batch is ordered when every update comes after
+// the ones it refers to, which is what consumers applying updates one by one
+// rely on.
+//
+// Updates are identified by their index in the batch. Keys are assumed not to
+// be reused within a batch, as the keys APIs hand out fresh ones.
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::hash::Hash;
+
+use types::{ResourceUpdates, Update};
+
+pub type UpdateId = usize;
+
+impl<ImageKey, FontKey, FontInstanceKey> ResourceUpdates<ImageKey, FontKey, FontInstanceKey>
+where
+    ImageKey: Eq + Hash,
+    FontKey: Eq + Hash,
+    FontInstanceKey: Eq + Hash
+{
+    // Ids of the updates in this batch which `id` refers to, sorted.
+    pub fn dependencies(&self, id: UpdateId) -> Vec<UpdateId> {
+        match self.updates.get(id) {
+            Some(update) => DependencyIndex::new(&self.updates).dependencies(update),
+            None => Vec::new()
+        }
+    }
+
+    // Ids of the updates a display list using these images and font instances
+    // needs applied first, including the fonts of the instances and the masks
+    // linked to the images, sorted.
+    pub fn dependencies_of(&self, images: &[ImageKey], instances: &[FontInstanceKey]) -> Vec<UpdateId> {
+        let index = DependencyIndex::new(&self.updates);
+        let mut ids: Vec<UpdateId> = images
+            .iter()
+            .flat_map(|key| index.image_adds(key))
+            .collect();
+        for key in images {
+            for &link in index.image_links.get(key).into_iter().flat_map(|links| links.iter()) {
+                ids.push(link);
+                ids.extend(index.dependencies(&self.updates[link]));
+            }
+        }
+        for key in instances {
+            if let Some(&(id, font_key)) = index.instance_adds.get(key) {
+                ids.push(id);
+                ids.extend(index.font_adds.get(font_key));
+            }
+        }
+        ids.sort();
+        ids.dedup();
+        ids
+    }
+
+    pub fn is_ordered(&self) -> bool {
+        let index = DependencyIndex::new(&self.updates);
+        self.updates
+            .iter()
+            .enumerate()
+            .all(|(id, update)| index.dependencies(update).iter().all(|&dependency| dependency < id))
+    }
+
+    // Reorders the batch so that every update comes after the ones it refers
+    // to, otherwise keeping the order updates were made in. Updates caught in
+    // a cycle, which only reused keys can cause, stay in their original order
+    // at the end.
+    pub fn sort_dependencies(&mut self) {
+        let order = {
+            let index = DependencyIndex::new(&self.updates);
+            let dependencies: Vec<Vec<UpdateId>> = self.updates
+                .iter()
+                .map(|update| index.dependencies(update))
+                .collect();
+            if dependencies
+                .iter()
+                .enumerate()
+                .all(|(id, deps)| deps.iter().all(|&dependency| dependency < id))
+            {
+                return;
+            }
+            topological_order(&dependencies)
+        };
+
+        let mut updates: Vec<Option<Update<ImageKey, FontKey, FontInstanceKey>>> = self.updates
+            .drain(..)
+            .map(Some)
+            .collect();
+        self.updates = order
+            .into_iter()
+            .filter_map(|id| updates[id].take())
+            .collect();
+    }
+}
+
+// Kahn's algorithm, always picking the earliest ready update.
+fn topological_order(dependencies: &[Vec<UpdateId>]) -> Vec<UpdateId> {
+    let mut pending: Vec<usize> = dependencies.iter().map(Vec::len).collect();
+    let mut dependents = vec![Vec::new(); dependencies.len()];
+    for (id, deps) in dependencies.iter().enumerate() {
+        for &dependency in deps {
+            dependents[dependency].push(id);
+        }
+    }
+
+    let mut ready: BinaryHeap<Reverse<UpdateId>> = pending
+        .iter()
+        .enumerate()
+        .filter(|&(_, &count)| count == 0)
+        .map(|(id, _)| Reverse(id))
+        .collect();
+    let mut order = Vec::with_capacity(dependencies.len());
+    let mut placed = vec![false; dependencies.len()];
+    while let Some(Reverse(id)) = ready.pop() {
+        order.push(id);
+        placed[id] = true;
+        for &dependent in &dependents[id] {
+            pending[dependent] -= 1;
+            if pending[dependent] == 0 {
+                ready.push(Reverse(dependent));
+            }
+        }
+    }
+
+    order.extend((0..dependencies.len()).filter(|&id| !placed[id]));
+    order
+}
+
+struct DependencyIndex<'a, ImageKey: 'a, FontKey: 'a, FontInstanceKey: 'a> {
+    images: HashMap<&'a ImageKey, Vec<UpdateId>>,
+    image_arrays: HashMap<&'a ImageKey, UpdateId>,
+    font_adds: HashMap<&'a FontKey, UpdateId>,
+    instance_adds: HashMap<&'a FontInstanceKey, (UpdateId, &'a FontKey)>,
+    instances_by_font: HashMap<&'a FontKey, Vec<(UpdateId, &'a FontInstanceKey)>>,
+    instance_uses: HashMap<&'a FontInstanceKey, Vec<UpdateId>>,
+    instance_deletes: HashMap<&'a FontInstanceKey, UpdateId>,
+    image_links: HashMap<&'a ImageKey, Vec<UpdateId>>,
+    image_updates: HashMap<&'a ImageKey, Vec<UpdateId>>,
+    atlas_pages: HashMap<u32, UpdateId>,
+    atlas_regions: HashMap<u32, Vec<UpdateId>>
+}
+
+impl<'a, ImageKey, FontKey, FontInstanceKey> DependencyIndex<'a, ImageKey, FontKey, FontInstanceKey>
+where
+    ImageKey: Eq + Hash,
+    FontKey: Eq + Hash,
+    FontInstanceKey: Eq + Hash
+{
+    fn new(updates: &'a [Update<ImageKey, FontKey, FontInstanceKey>]) -> Self {
+        let mut index = DependencyIndex {
+            images: HashMap::new(),
+            image_arrays: HashMap::new(),
+            font_adds: HashMap::new(),
+            instance_adds: HashMap::new(),
+            instances_by_font: HashMap::new(),
+            instance_uses: HashMap::new(),
+            instance_deletes: HashMap::new(),
+            image_links: HashMap::new(),
+            image_updates: HashMap::new(),
+            atlas_pages: HashMap::new(),
+            atlas_regions: HashMap::new()
+        };
+        for (id, update) in updates.iter().enumerate() {
+            match update {
+                &Update::AddImageArray { ref key, .. } => {
+                    index.image_arrays.insert(key, id);
+                    index.images.entry(key).or_default().push(id);
+                }
+                &Update::AddImage { ref key, .. }
+                | &Update::AddImageMask { ref key, .. }
+                | &Update::AddImageArrayLayer { ref key, .. }
+                | &Update::AddSharedImage { ref key, .. }
+                | &Update::AddSharedImageMask { ref key, .. } => {
+                    index.images.entry(key).or_default().push(id);
+                }
+                &Update::AddFont { ref key, .. }
+                | &Update::AddFontBytes { ref key, .. }
+                | &Update::AddSharedFont { ref key, .. } => {
+                    index.font_adds.insert(key, id);
+                }
+                &Update::AddFontInstance {
+                    ref key,
+                    ref instance_key,
+                    ..
+                } => {
+                    index.instance_adds.insert(instance_key, (id, key));
+                    index.instances_by_font
+                        .entry(key)
+                        .or_default()
+                        .push((id, instance_key));
+                }
+                &Update::AddGlyphRun { ref instance_key, .. } => {
+                    index.instance_uses.entry(instance_key).or_default().push(id);
+                }
+                &Update::DeleteFontInstance { ref instance_key } => {
+                    index.instance_deletes.insert(instance_key, id);
+                }
+                &Update::LinkImageMask { ref key, ref mask_key } => {
+                    index.image_links.entry(key).or_default().push(id);
+                    index.image_links.entry(mask_key).or_default().push(id);
+                }
+                &Update::UpdateImage { ref key, .. } => {
+                    index.image_updates.entry(key).or_default().push(id);
+                }
+                &Update::AddGlyphAtlasPage { page, .. } => {
+                    index.atlas_pages.insert(page, id);
+                }
+                &Update::UpdateGlyphAtlasRegion { page, .. } => {
+                    index.atlas_regions.entry(page).or_default().push(id);
+                }
+                &Update::DeleteImage { .. } | &Update::DeleteFont { .. } | &Update::DeleteGlyphAtlasPage { .. } => {}
+            }
+        }
+        index
+    }
+
+    fn image_adds(&self, key: &ImageKey) -> Vec<UpdateId> {
+        self.images.get(key).cloned().unwrap_or_default()
+    }
+
+    fn dependencies(&self, update: &Update<ImageKey, FontKey, FontInstanceKey>) -> Vec<UpdateId> {
+        let mut ids = Vec::new();
+        match update {
+            &Update::AddImageArrayLayer { ref key, .. } => {
+                ids.extend(self.image_arrays.get(key));
+            }
+            &Update::AddFontInstance { ref key, .. } => {
+                ids.extend(self.font_adds.get(key));
+            }
+            &Update::AddGlyphRun { ref instance_key, .. } => {
+                ids.extend(self.instance_adds.get(instance_key).map(|&(id, _)| id));
+            }
+            &Update::LinkImageMask { ref key, ref mask_key } => {
+                ids.extend(self.image_adds(key));
+                ids.extend(self.image_adds(mask_key));
+            }
+            &Update::UpdateImage { ref key, .. } => {
+                ids.extend(self.image_adds(key));
+            }
+            &Update::UpdateGlyphAtlasRegion { page, .. } => {
+                ids.extend(self.atlas_pages.get(&page));
+            }
+            &Update::DeleteGlyphAtlasPage { page } => {
+                ids.extend(self.atlas_pages.get(&page));
+                ids.extend(self.atlas_regions.get(&page).into_iter().flat_map(|regions| regions.iter()));
+            }
+            &Update::DeleteImage { ref key } => {
+                ids.extend(self.image_adds(key));
+                ids.extend(self.image_links.get(key).into_iter().flat_map(|links| links.iter()));
+                ids.extend(self.image_updates.get(key).into_iter().flat_map(|updates| updates.iter()));
+            }
+            &Update::DeleteFontInstance { ref instance_key } => {
+                ids.extend(self.instance_adds.get(instance_key).map(|&(id, _)| id));
+                ids.extend(self.instance_uses.get(instance_key).into_iter().flat_map(|uses| uses.iter()));
+            }
+            &Update::DeleteFont { ref key } => {
+                ids.extend(self.font_adds.get(key));
+                for &(id, instance_key) in self.instances_by_font.get(key).into_iter().flat_map(|instances| instances.iter()) {
+                    ids.push(id);
+                    ids.extend(self.instance_deletes.get(instance_key));
+                }
+            }
+            _ => {}
+        }
+        ids.sort();
+        ids.dedup();
+        ids
+    }
+}
@@ -0,0 +1,196 @@
+/*
+Copyright 2016 Mozilla
+Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+this file except in compliance with the License. You may obtain a copy of the
+License at http://www.apache.org/licenses/LICENSE-2.0
+Unless required by applicable law or agreed to in writing, software distributed
+under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+CONDITIONS OF ANY KIND, either express or implied. See the License for the
+specific language governing permissions and limitations under the License.
+*/
+
+use rsx_shared::types::ImagePixelFormat;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum AlphaMode {
+    Straight,
+    Premultiplied
+}
+
+// Color bitmaps handed out to renderers, be it decoded images or color glyphs,
+// always use straight alpha and the same channel order.
+pub const CANONICAL_ALPHA_MODE: AlphaMode = AlphaMode::Straight;
+
+pub fn canonical_color_format() -> ImagePixelFormat {
+    if cfg!(feature = "image-rgb-to-bgr") {
+        ImagePixelFormat::BGRA(8)
+    } else {
+        ImagePixelFormat::RGBA(8)
+    }
+}
+
+pub fn to_canonical(format: ImagePixelFormat, alpha_mode: AlphaMode, pixels: &mut [u8]) -> ImagePixelFormat {
+    match format {
+        ImagePixelFormat::RGBA(8) | ImagePixelFormat::BGRA(8) => {
+            if alpha_mode == AlphaMode::Premultiplied {
+                unpremultiply(pixels);
+            }
+            let canonical = canonical_color_format();
+            if format != canonical {
+                swap_red_blue(pixels, 4);
+            }
+            canonical
+        }
+        format => format
+    }
+}
+
+pub fn premultiply(pixels: &mut [u8]) {
+    for pixel in pixels.chunks_mut(4) {
+        let alpha = u32::from(pixel[3]);
+        for channel in &mut pixel[0..3] {
+            *channel = ((u32::from(*channel) * alpha + 127) / 255) as u8;
+        }
+    }
+}
+
+pub fn unpremultiply(pixels: &mut [u8]) {
+    for pixel in pixels.chunks_mut(4) {
+        let alpha = u32::from(pixel[3]);
+        if alpha == 0 || alpha == 255 {
+            continue;
+        }
+        for channel in &mut pixel[0..3] {
+            *channel = ((u32::from(*channel) * 255 + alpha / 2) / alpha).min(255) as u8;
+        }
+    }
+}
+
+pub fn swap_red_blue(pixels: &mut [u8], bytes_per_pixel: usize) {
+    for pixel in pixels.chunks_mut(bytes_per_pixel) {
+        pixel.swap(0, 2);
+    }
+}
+
+pub fn bytes_per_pixel(format: ImagePixelFormat) -> usize {
+    let (channels, depth) = match format {
+        ImagePixelFormat::Gray(depth) => (1, depth),
+        ImagePixelFormat::RGB(depth) | ImagePixelFormat::BGR(depth) => (3, depth),
+        ImagePixelFormat::RGBA(depth) | ImagePixelFormat::BGRA(depth) => (4, depth)
+    };
+    channels * ((depth as usize + 7) / 8)
+}
+
+// Which channel of a color image becomes the coverage of an A8 mask. As with
+// CSS `mask-mode`, luminance makes white opaque and black transparent.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum MaskChannel {
+    Alpha,
+    Luminance
+}
+
+// Icons with transparency are masked by their alpha, opaque ones by their
+// luminance. Expects packed 8 bit pixels.
+pub fn mask_channel(format: ImagePixelFormat, pixels: &[u8]) -> MaskChannel {
+    match format {
+        ImagePixelFormat::RGBA(8) | ImagePixelFormat::BGRA(8) if pixels.chunks(4).any(|pixel| pixel[3] != 255) => MaskChannel::Alpha,
+        _ => MaskChannel::Luminance
+    }
+}
+
+pub fn to_mask(format: ImagePixelFormat, channel: MaskChannel, pixels: &[u8]) -> Vec<u8> {
+    let (bytes_per_pixel, red) = match format {
+        ImagePixelFormat::Gray(_) => return pixels.to_vec(),
+        ImagePixelFormat::RGB(_) => (3, 0),
+        ImagePixelFormat::BGR(_) => (3, 2),
+        ImagePixelFormat::RGBA(_) => (4, 0),
+        ImagePixelFormat::BGRA(_) => (4, 2)
+    };
+    match channel {
+        MaskChannel::Alpha if bytes_per_pixel == 4 => pixels.chunks(4).map(|pixel| pixel[3]).collect(),
+        MaskChannel::Alpha => vec![255; pixels.len() / bytes_per_pixel],
+        MaskChannel::Luminance => pixels
+            .chunks(bytes_per_pixel)
+            .map(|pixel| {
+                let (r, g, b) = (u32::from(pixel[red]), u32::from(pixel[1]), u32::from(pixel[2 - red]));
+                ((r * 2126 + g * 7152 + b * 722 + 5000) / 10000) as u8
+            })
+            .collect()
+    }
+}
+
+// Multiplies the alpha of a color image by the coverage of an A8 mask of the
+// same size, into the canonical color format. Expects packed 8 bit pixels.
+pub fn apply_mask(format: ImagePixelFormat, pixels: &[u8], mask: &[u8]) -> (ImagePixelFormat, Vec<u8>) {
+    let (bytes_per_pixel, red) = match format {
+        ImagePixelFormat::Gray(_) => (1, 0),
+        ImagePixelFormat::RGB(_) => (3, 0),
+        ImagePixelFormat::BGR(_) => (3, 2),
+        ImagePixelFormat::RGBA(_) => (4, 0),
+        ImagePixelFormat::BGRA(_) => (4, 2)
+    };
+    let canonical = canonical_color_format();
+    let canonical_red = if canonical == ImagePixelFormat::BGRA(8) { 2 } else { 0 };
+
+    let mut masked = Vec::with_capacity(mask.len() * 4);
+    for (pixel, &coverage) in pixels.chunks(bytes_per_pixel).zip(mask) {
+        let (r, g, b) = if bytes_per_pixel == 1 {
+            (pixel[0], pixel[0], pixel[0])
+        } else {
+            (pixel[red], pixel[1], pixel[2 - red])
+        };
+        let alpha = if bytes_per_pixel == 4 { pixel[3] } else { 255 };
+        let alpha = ((u32::from(alpha) * u32::from(coverage) + 127) / 255) as u8;
+        if canonical_red == 0 {
+            masked.extend_from_slice(&[r, g, b, alpha]);
+        } else {
+            masked.extend_from_slice(&[b, g, r, alpha]);
+        }
+    }
+    (canonical, masked)
+}
+
+// Fills an A8 mask with a single color, into the canonical color format. The
+// color's alpha is scaled by the coverage of each pixel.
+pub fn tint_mask(mask: &[u8], (r, g, b, a): (u8, u8, u8, u8)) -> (ImagePixelFormat, Vec<u8>) {
+    let canonical = canonical_color_format();
+    let (first, last) = if canonical == ImagePixelFormat::BGRA(8) { (b, r) } else { (r, b) };
+
+    let mut tinted = Vec::with_capacity(mask.len() * 4);
+    for &coverage in mask {
+        let alpha = ((u32::from(a) * u32::from(coverage) + 127) / 255) as u8;
+        tinted.extend_from_slice(&[first, g, last, alpha]);
+    }
+    (canonical, tinted)
+}
+
+pub fn aligned_row_stride(row_bytes: usize, alignment: usize) -> usize {
+    if alignment <= 1 {
+        row_bytes
+    } else {
+        (row_bytes + alignment - 1) / alignment * alignment
+    }
+}
+
+pub fn pad_rows(pixels: &[u8], row_bytes: usize, row_stride: usize) -> Vec<u8> {
+    if row_bytes == 0 || row_bytes == row_stride {
+        return pixels.to_vec();
+    }
+    let mut padded = Vec::with_capacity(pixels.len() / row_bytes * row_stride);
+    for row in pixels.chunks(row_bytes) {
+        padded.extend_from_slice(row);
+        padded.resize(padded.len() + row_stride - row.len(), 0);
+    }
+    padded
+}
+
+pub fn unpad_rows(pixels: &[u8], row_bytes: usize, row_stride: usize) -> Vec<u8> {
+    if row_stride == 0 || row_bytes == row_stride {
+        return pixels.to_vec();
+    }
+    let mut packed = Vec::with_capacity(pixels.len() / row_stride * row_bytes);
+    for row in pixels.chunks(row_stride) {
+        packed.extend_from_slice(&row[..row_bytes.min(row.len())]);
+    }
+    packed
+}
@@ -0,0 +1,162 @@
+/*
+Copyright 2016 Mozilla
+Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+this file except in compliance with the License. You may obtain a copy of the
+License at http://www.apache.org/licenses/LICENSE-2.0
+Unless required by applicable law or agreed to in writing, software distributed
+under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+CONDITIONS OF ANY KIND, either express or implied. See the License for the
+specific language governing permissions and limitations under the License.
+*/
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::hash::Hash;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum ResourcePriority {
+    // Never evicted to satisfy a quota.
+    Pinned,
+    Normal,
+    // Reclaimed before anything else.
+    Discardable
+}
+
+impl Default for ResourcePriority {
+    fn default() -> Self {
+        ResourcePriority::Normal
+    }
+}
+
+impl ResourcePriority {
+    pub const EVICTION_ORDER: [ResourcePriority; 2] = [ResourcePriority::Discardable, ResourcePriority::Normal];
+
+    pub fn is_evictable(self) -> bool {
+        self != ResourcePriority::Pinned
+    }
+}
+
+// Picks the largest candidates first until enough bytes are freed, so that a
+// few big resources are reclaimed rather than many small ones.
+pub fn select_for_eviction<Id>(mut candidates: Vec<(Id, usize)>, bytes_to_free: usize) -> Vec<(Id, usize)>
+where
+    Id: Ord
+{
+    candidates.sort_by(|a, b| (Reverse(a.1), &a.0).cmp(&(Reverse(b.1), &b.0)));
+
+    let mut freed = 0;
+    candidates
+        .into_iter()
+        .take_while(|&(_, bytes)| {
+            let needed = freed < bytes_to_free;
+            freed += bytes;
+            needed
+        })
+        .collect()
+}
+
+// Time-to-live of resources in milliseconds, by origin. Resources without an
+// origin, e.g. local assets, and origins without a TTL of their own use the
+// default one. A TTL of `None` never expires, which is the default.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct ExpiryPolicy {
+    default_ttl_ms: Option<u64>,
+    origin_ttls_ms: Vec<(String, Option<u64>)>
+}
+
+impl ExpiryPolicy {
+    pub fn new() -> Self {
+        ExpiryPolicy::default()
+    }
+
+    pub fn default_ttl_ms(&self) -> Option<u64> {
+        self.default_ttl_ms
+    }
+
+    pub fn set_default_ttl_ms(&mut self, ttl_ms: Option<u64>) {
+        self.default_ttl_ms = ttl_ms;
+    }
+
+    pub fn set_origin_ttl_ms<O>(&mut self, origin: O, ttl_ms: Option<u64>)
+    where
+        O: Into<String>
+    {
+        let origin = origin.into();
+        self.origin_ttls_ms.retain(|entry| entry.0 != origin);
+        self.origin_ttls_ms.push((origin, ttl_ms));
+    }
+
+    pub fn never_expires(&self) -> bool {
+        self.default_ttl_ms.is_none() && self.origin_ttls_ms.iter().all(|&(_, ttl_ms)| ttl_ms.is_none())
+    }
+
+    // Ids only keep a hash of their origin, so callers test origins for them.
+    pub fn ttl_ms<F>(&self, is_from_origin: F) -> Option<u64>
+    where
+        F: Fn(&str) -> bool
+    {
+        self.origin_ttls_ms
+            .iter()
+            .find(|entry| is_from_origin(&entry.0))
+            .map(|&(_, ttl_ms)| ttl_ms)
+            .unwrap_or(self.default_ttl_ms)
+    }
+}
+
+// Caches aren't told the time as resources are added, so a resource's TTL
+// starts at the first sweep which sees it. When sweeping every frame, that's
+// at most a frame late.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ResourceExpiry<Id: Hash + Eq> {
+    policy: ExpiryPolicy,
+    loaded_at_ms: HashMap<Id, u64>
+}
+
+impl<Id: Hash + Eq> Default for ResourceExpiry<Id> {
+    fn default() -> Self {
+        ResourceExpiry {
+            policy: ExpiryPolicy::default(),
+            loaded_at_ms: HashMap::new()
+        }
+    }
+}
+
+impl<Id> ResourceExpiry<Id>
+where
+    Id: Copy + Ord + Hash
+{
+    pub fn new() -> Self {
+        ResourceExpiry::default()
+    }
+
+    pub fn policy(&self) -> &ExpiryPolicy {
+        &self.policy
+    }
+
+    pub fn set_policy(&mut self, policy: ExpiryPolicy) {
+        self.policy = policy;
+    }
+
+    // Returns the ids whose TTL has elapsed at `now_ms`, in order. Ids which
+    // aren't loaded anymore are forgotten, so reloading restarts their TTL.
+    pub fn sweep<I, F>(&mut self, loaded: I, now_ms: u64, is_from_origin: F) -> Vec<Id>
+    where
+        I: IntoIterator<Item = Id>,
+        F: Fn(Id, &str) -> bool
+    {
+        let mut loaded_at_ms = HashMap::new();
+        let mut expired = Vec::new();
+        for id in loaded {
+            let since_ms = self.loaded_at_ms.get(&id).cloned().unwrap_or(now_ms);
+            match self.policy.ttl_ms(|origin| is_from_origin(id, origin)) {
+                Some(ttl_ms) if now_ms.saturating_sub(since_ms) >= ttl_ms => expired.push(id),
+                _ => {
+                    loaded_at_ms.insert(id, since_ms);
+                }
+            }
+        }
+        self.loaded_at_ms = loaded_at_ms;
+        expired.sort();
+        expired
+    }
+}
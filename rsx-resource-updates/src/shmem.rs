@@ -0,0 +1,131 @@
+/*
+Copyright 2016 Mozilla
+Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+this file except in compliance with the License. You may obtain a copy of the
+License at http://www.apache.org/licenses/LICENSE-2.0
+Unless required by applicable law or agreed to in writing, software distributed
+under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+CONDITIONS OF ANY KIND, either express or implied. See the License for the
+specific language governing permissions and limitations under the License.
+*/
+
+use std::fmt;
+use std::fs::File;
+use std::io;
+#[cfg(not(unix))]
+use std::io::{Read, Seek, SeekFrom};
+#[cfg(unix)]
+use std::os::unix::fs::FileExt;
+#[cfg(unix)]
+use std::os::unix::io::AsRawFd;
+#[cfg(unix)]
+pub use std::os::unix::io::RawFd;
+
+// Elsewhere there are no file descriptors to share, so no kinds of segments
+// either, and descriptors are never created. Updates still carry the field.
+#[cfg(not(unix))]
+pub type RawFd = i32;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum SharedMemoryKind {
+    #[cfg(unix)]
+    Memfd,
+    #[cfg(unix)]
+    Ashmem
+}
+
+// Sent to the renderer process in place of the bytes. The file descriptor is
+// only meaningful once passed over an IPC channel capable of sharing them,
+// after which the renderer can map `len` bytes starting at `offset`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SharedMemoryDescriptor {
+    pub kind: SharedMemoryKind,
+    pub fd: RawFd,
+    pub offset: u64,
+    pub len: u64
+}
+
+// Owns a shared memory segment created by the embedder, e.g. through
+// `memfd_create` or `ASharedMemory_create` and wrapped with `File::from_raw_fd`,
+// closing it when dropped. Caches keep it alive for as long as the resource
+// backed by it exists.
+pub struct SharedMemory {
+    kind: SharedMemoryKind,
+    file: File,
+    offset: u64,
+    len: u64
+}
+
+impl SharedMemory {
+    pub fn from_file(kind: SharedMemoryKind, file: File, offset: u64, len: u64) -> Self {
+        SharedMemory {
+            kind,
+            file,
+            offset,
+            len
+        }
+    }
+
+    pub fn kind(&self) -> SharedMemoryKind {
+        self.kind
+    }
+
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn descriptor(&self) -> SharedMemoryDescriptor {
+        SharedMemoryDescriptor {
+            kind: self.kind,
+            fd: self.raw_fd(),
+            offset: self.offset,
+            len: self.len
+        }
+    }
+
+    #[cfg(unix)]
+    fn raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+
+    #[cfg(not(unix))]
+    fn raw_fd(&self) -> RawFd {
+        match self.kind {}
+    }
+
+    // Decoding still happens in this process, so the contents are read once
+    // here. Only the renderer process gets to skip the copy.
+    #[cfg(unix)]
+    pub fn read_bytes(&self) -> io::Result<Vec<u8>> {
+        let mut bytes = vec![0; self.len as usize];
+        self.file.read_exact_at(&mut bytes, self.offset)?;
+        Ok(bytes)
+    }
+
+    #[cfg(not(unix))]
+    pub fn read_bytes(&self) -> io::Result<Vec<u8>> {
+        let mut bytes = vec![0; self.len as usize];
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(self.offset))?;
+        file.read_exact(&mut bytes)?;
+        Ok(bytes)
+    }
+}
+
+impl fmt::Debug for SharedMemory {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SharedMemory")
+            .field("descriptor", &self.descriptor())
+            .finish()
+    }
+}
+
+impl PartialEq for SharedMemory {
+    fn eq(&self, other: &Self) -> bool {
+        self.descriptor() == other.descriptor()
+    }
+}
@@ -0,0 +1,115 @@
+/*
+Copyright 2016 Mozilla
+Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+this file except in compliance with the License. You may obtain a copy of the
+License at http://www.apache.org/licenses/LICENSE-2.0
+Unless required by applicable law or agreed to in writing, software distributed
+under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+CONDITIONS OF ANY KIND, either express or implied. See the License for the
+specific language governing permissions and limitations under the License.
+*/
+
+// Mappings from cache ids to the external keys renderers know resources by.
+// A renderer which survives a restart of the caches can keep its mappings,
+// while caches which survive a restart of the renderer diff their snapshot
+// against what the renderer still knows to replay only the missing resources.
+
+use std::iter::FromIterator;
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct KeySnapshot<Id, Key> {
+    entries: Vec<(Id, Key)>
+}
+
+// Entries of a snapshot the renderer needs sent again, because it doesn't
+// know them or knows them by another key, and entries the renderer holds
+// which nothing in the snapshot refers to anymore.
+#[derive(Debug, PartialEq, Clone)]
+pub struct KeyDiff<Id, Key> {
+    pub replay: Vec<(Id, Key)>,
+    pub stale: Vec<(Id, Key)>
+}
+
+impl<Id, Key> KeySnapshot<Id, Key>
+where
+    Id: Copy + Ord,
+    Key: Copy + PartialEq
+{
+    pub fn new() -> Self {
+        KeySnapshot { entries: Vec::new() }
+    }
+
+    // Later entries win over earlier ones with the same id.
+    pub fn insert(&mut self, id: Id, key: Key) {
+        match self.entries.binary_search_by(|&(entry_id, _)| entry_id.cmp(&id)) {
+            Ok(index) => self.entries[index].1 = key,
+            Err(index) => self.entries.insert(index, (id, key))
+        }
+    }
+
+    pub fn get(&self, id: Id) -> Option<Key> {
+        self.entries
+            .binary_search_by(|&(entry_id, _)| entry_id.cmp(&id))
+            .ok()
+            .map(|index| self.entries[index].1)
+    }
+
+    // Sorted by id.
+    pub fn entries(&self) -> &[(Id, Key)] {
+        &self.entries
+    }
+
+    pub fn keys<'a>(&'a self) -> impl Iterator<Item = Key> + 'a {
+        self.entries.iter().map(|&(_, key)| key)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn diff(&self, known: &Self) -> KeyDiff<Id, Key> {
+        let replay = self.entries
+            .iter()
+            .filter(|&&(id, key)| known.get(id) != Some(key))
+            .cloned()
+            .collect();
+        let stale = known
+            .entries
+            .iter()
+            .filter(|&&(id, key)| self.get(id) != Some(key))
+            .cloned()
+            .collect();
+        KeyDiff { replay, stale }
+    }
+}
+
+impl<Id, Key> Default for KeySnapshot<Id, Key>
+where
+    Id: Copy + Ord,
+    Key: Copy + PartialEq
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<Id, Key> FromIterator<(Id, Key)> for KeySnapshot<Id, Key>
+where
+    Id: Copy + Ord,
+    Key: Copy + PartialEq
+{
+    fn from_iter<I>(iter: I) -> Self
+    where
+        I: IntoIterator<Item = (Id, Key)>
+    {
+        let mut snapshot = Self::new();
+        for (id, key) in iter {
+            snapshot.insert(id, key);
+        }
+        snapshot
+    }
+}
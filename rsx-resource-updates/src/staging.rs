@@ -0,0 +1,121 @@
+/*
+Copyright 2016 Mozilla
+Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+this file except in compliance with the License. You may obtain a copy of the
+License at http://www.apache.org/licenses/LICENSE-2.0
+Unless required by applicable law or agreed to in writing, software distributed
+under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+CONDITIONS OF ANY KIND, either express or implied. See the License for the
+specific language governing permissions and limitations under the License.
+*/
+
+use std::mem;
+use std::sync::Arc;
+
+pub const DEFAULT_MAX_FREE_BUFFERS: usize = 8;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct StagingStats {
+    pub allocated: u64,
+    pub reused: u64,
+    pub in_flight: usize,
+    pub free: usize
+}
+
+// Reusable pixel buffers for keys APIs which copy the pixels out while the
+// resource updates are consumed. Buffers handed out are in flight until the
+// consumer acknowledges the updates, after which they can be reused.
+#[derive(Debug, PartialEq)]
+pub struct StagingPool {
+    free: Vec<Vec<u8>>,
+    in_flight: Vec<Arc<Vec<u8>>>,
+    max_free: usize,
+    allocated: u64,
+    reused: u64
+}
+
+impl Default for StagingPool {
+    fn default() -> Self {
+        StagingPool::with_max_free(DEFAULT_MAX_FREE_BUFFERS)
+    }
+}
+
+impl StagingPool {
+    pub fn with_max_free(max_free: usize) -> Self {
+        StagingPool {
+            free: Vec::new(),
+            in_flight: Vec::new(),
+            max_free,
+            allocated: 0,
+            reused: 0
+        }
+    }
+
+    // Returns an empty buffer able to hold `len` bytes, preferring the
+    // smallest free one that fits.
+    pub fn acquire(&mut self, len: usize) -> Vec<u8> {
+        let fitting = self.free
+            .iter()
+            .enumerate()
+            .filter(|&(_, buffer)| buffer.capacity() >= len)
+            .min_by_key(|&(_, buffer)| buffer.capacity())
+            .map(|(index, _)| index);
+        match fitting {
+            Some(index) => {
+                self.reused += 1;
+                let mut buffer = self.free.swap_remove(index);
+                buffer.clear();
+                buffer
+            }
+            None => {
+                self.allocated += 1;
+                Vec::with_capacity(len)
+            }
+        }
+    }
+
+    // Keeps the largest buffers when the pool is full.
+    pub fn recycle(&mut self, buffer: Vec<u8>) {
+        if self.free.len() < self.max_free {
+            self.free.push(buffer);
+            return;
+        }
+        let smallest = self.free
+            .iter()
+            .enumerate()
+            .min_by_key(|&(_, free)| free.capacity())
+            .map(|(index, free)| (index, free.capacity()));
+        if let Some((index, capacity)) = smallest {
+            if capacity < buffer.capacity() {
+                self.free[index] = buffer;
+            }
+        }
+    }
+
+    pub fn stage(&mut self, buffer: Arc<Vec<u8>>) {
+        self.in_flight.push(buffer);
+    }
+
+    // Called once the updates referencing the staged buffers were consumed.
+    // Buffers still referenced elsewhere are dropped rather than reused.
+    // Returns the number of buffers made available again.
+    pub fn reclaim(&mut self) -> usize {
+        let mut reclaimed = 0;
+        for buffer in mem::replace(&mut self.in_flight, Vec::new()) {
+            if let Ok(buffer) = Arc::try_unwrap(buffer) {
+                self.recycle(buffer);
+                reclaimed += 1;
+            }
+        }
+        reclaimed
+    }
+
+    pub fn stats(&self) -> StagingStats {
+        StagingStats {
+            allocated: self.allocated,
+            reused: self.reused,
+            in_flight: self.in_flight.len(),
+            free: self.free.len()
+        }
+    }
+}
@@ -0,0 +1,242 @@
+/*
+Copyright 2016 Mozilla
+Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+this file except in compliance with the License. You may obtain a copy of the
+License at http://www.apache.org/licenses/LICENSE-2.0
+Unless required by applicable law or agreed to in writing, software distributed
+under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+CONDITIONS OF ANY KIND, either express or implied. See the License for the
+specific language governing permissions and limitations under the License.
+*/
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::hash::Hash;
+
+use codes::ErrorCode;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ErrorCategory {
+    DecodeFailure,
+    FontBackendFailure,
+    MissingResource,
+    LimitRejection
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ErrorCounters {
+    pub decode_failures: u64,
+    pub font_backend_failures: u64,
+    pub missing_resources: u64,
+    pub limit_rejections: u64,
+    // Failures of mutations queued while a shared cache was borrowed, whether
+    // or not they have a category. Not part of `total`, since categorized ones
+    // are already counted there.
+    #[serde(default)]
+    pub deferred_failures: u64
+}
+
+impl ErrorCounters {
+    pub fn get(&self, category: ErrorCategory) -> u64 {
+        match category {
+            ErrorCategory::DecodeFailure => self.decode_failures,
+            ErrorCategory::FontBackendFailure => self.font_backend_failures,
+            ErrorCategory::MissingResource => self.missing_resources,
+            ErrorCategory::LimitRejection => self.limit_rejections
+        }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.decode_failures + self.font_backend_failures + self.missing_resources + self.limit_rejections
+    }
+
+    pub fn merged(self, other: ErrorCounters) -> Self {
+        ErrorCounters {
+            decode_failures: self.decode_failures + other.decode_failures,
+            font_backend_failures: self.font_backend_failures + other.font_backend_failures,
+            missing_resources: self.missing_resources + other.missing_resources,
+            limit_rejections: self.limit_rejections + other.limit_rejections,
+            deferred_failures: self.deferred_failures + other.deferred_failures
+        }
+    }
+
+    fn increment(&mut self, category: ErrorCategory) {
+        let counter = match category {
+            ErrorCategory::DecodeFailure => &mut self.decode_failures,
+            ErrorCategory::FontBackendFailure => &mut self.font_backend_failures,
+            ErrorCategory::MissingResource => &mut self.missing_resources,
+            ErrorCategory::LimitRejection => &mut self.limit_rejections
+        };
+        *counter = counter.saturating_add(1);
+    }
+}
+
+// The error of a mutation queued while a shared cache was borrowed. Its caller
+// had already returned by the time it was applied, so it's kept until taken.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct DeferredError {
+    pub code: ErrorCode,
+    pub message: String
+}
+
+// Only the most recent deferred errors are kept, for caches nobody takes them from.
+pub const MAX_DEFERRED_ERRORS: usize = 64;
+
+// Counts errors by category as they happen, including ones which the cache
+// traits swallow into `None`. Uses a cell so that lookups taking `&self` can
+// record errors too.
+#[derive(Debug, Default, PartialEq)]
+pub struct ErrorStats(Cell<ErrorCounters>, RefCell<Vec<DeferredError>>);
+
+impl ErrorStats {
+    pub fn new() -> Self {
+        ErrorStats::default()
+    }
+
+    pub fn record(&self, category: ErrorCategory) {
+        let mut counters = self.0.get();
+        counters.increment(category);
+        self.0.set(counters);
+    }
+
+    // Errors without a category, e.g. adding the same resource twice, are
+    // considered misuse rather than a sign of poor resource health.
+    pub fn track<T, E, F>(&self, result: Result<T, E>, categorize: F) -> Result<T, E>
+    where
+        F: FnOnce(&E) -> Option<ErrorCategory>
+    {
+        if let Err(ref err) = result {
+            if let Some(category) = categorize(err) {
+                self.record(category);
+            }
+        }
+        result
+    }
+
+    // Meant for errors already tracked when they happened, so only counts them
+    // as deferred failures.
+    pub fn record_deferred(&self, code: ErrorCode, message: String) {
+        let mut counters = self.0.get();
+        counters.deferred_failures = counters.deferred_failures.saturating_add(1);
+        self.0.set(counters);
+
+        let mut deferred = self.1.borrow_mut();
+        if deferred.len() == MAX_DEFERRED_ERRORS {
+            deferred.remove(0);
+        }
+        deferred.push(DeferredError { code, message });
+    }
+
+    // Oldest first.
+    pub fn take_deferred(&self) -> Vec<DeferredError> {
+        self.1.replace(Vec::new())
+    }
+
+    pub fn counters(&self) -> ErrorCounters {
+        self.0.get()
+    }
+
+    // Meant to be called once per frame, returning the counts since the last reset.
+    pub fn reset(&self) -> ErrorCounters {
+        self.0.replace(ErrorCounters::default())
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct IdCounters {
+    pub checked: u64,
+    pub distinct: u64,
+    pub collisions: u64
+}
+
+impl IdCounters {
+    pub fn merged(self, other: IdCounters) -> Self {
+        IdCounters {
+            checked: self.checked + other.checked,
+            distinct: self.distinct + other.distinct,
+            collisions: self.collisions + other.collisions
+        }
+    }
+}
+
+// Ids are 64 bit FNV hashes of their source strings, so distinct sources can
+// end up sharing an id. When enabled, remembers the source of every id hashed
+// through a cache and counts collisions instead of panicking, at the cost of
+// keeping all source strings alive. Enabled by default in debug builds only.
+#[derive(Debug, PartialEq)]
+pub struct IdStats<K: Hash + Eq> {
+    enabled: bool,
+    sources: RefCell<HashMap<K, Box<str>>>,
+    counters: Cell<IdCounters>
+}
+
+impl<K: Hash + Eq> Default for IdStats<K> {
+    fn default() -> Self {
+        IdStats {
+            enabled: cfg!(debug_assertions),
+            sources: RefCell::new(HashMap::new()),
+            counters: Cell::new(IdCounters::default())
+        }
+    }
+}
+
+impl<K: Hash + Eq> IdStats<K> {
+    pub fn new() -> Self {
+        IdStats::default()
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.sources.borrow_mut().clear();
+        }
+    }
+
+    // Returns the id unchanged. Sources are compared verbatim, so callers
+    // hashing several strings into one id should join them unambiguously.
+    pub fn check<S>(&self, id: K, source: S) -> K
+    where
+        K: Clone,
+        S: AsRef<str>
+    {
+        if !self.enabled {
+            return id;
+        }
+        let source = source.as_ref();
+        let mut counters = self.counters.get();
+        counters.checked += 1;
+        match self.sources.borrow_mut().entry(id.clone()) {
+            Entry::Occupied(e) => {
+                if &**e.get() != source {
+                    counters.collisions += 1;
+                }
+            }
+            Entry::Vacant(e) => {
+                e.insert(source.into());
+                counters.distinct += 1;
+            }
+        }
+        self.counters.set(counters);
+        id
+    }
+
+    pub fn counters(&self) -> IdCounters {
+        self.counters.get()
+    }
+
+    // Only resets the counts, the remembered sources are kept so collisions
+    // with ids seen before the reset are still detected.
+    pub fn reset(&self) -> IdCounters {
+        let counters = self.counters.get();
+        self.counters.set(IdCounters {
+            distinct: counters.distinct,
+            ..IdCounters::default()
+        });
+        counters
+    }
+}
@@ -0,0 +1,218 @@
+/*
+Copyright 2016 Mozilla
+Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+this file except in compliance with the License. You may obtain a copy of the
+License at http://www.apache.org/licenses/LICENSE-2.0
+Unless required by applicable law or agreed to in writing, software distributed
+under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+CONDITIONS OF ANY KIND, either express or implied. See the License for the
+specific language governing permissions and limitations under the License.
+*/
+
+use std::ops::Range;
+
+use rsx_shared::traits::{TFontKeysAPI, TGlyphInstance, TGlyphStore, TImageKeysAPI};
+use rsx_shared::types::{FontEncodedData, FontInstanceResourceData, FontResourceData, ImageEncodedData, ImageResourceData};
+
+use pixels::MaskChannel;
+use shmem::SharedMemoryDescriptor;
+use types::{GlyphRunSource, GlyphTransform, ImageOrientation};
+
+// Capabilities of a keys API beyond the ones required by `rsx_shared`.
+// Caches only require these bounds on the methods that rely on them.
+
+pub trait TImageKeysAPIExt: TImageKeysAPI {
+    fn add_image_with_orientation(
+        &mut self,
+        encoded: ImageEncodedData,
+        decoded: ImageResourceData,
+        orientation: ImageOrientation
+    ) -> Self::ImageKey;
+
+    // Lets the renderer map the encoded bytes from shared memory. APIs unable
+    // to forward the descriptor fall back to sending the bytes.
+    fn add_shared_image(
+        &mut self,
+        _: SharedMemoryDescriptor,
+        encoded: ImageEncodedData,
+        decoded: ImageResourceData,
+        orientation: ImageOrientation
+    ) -> Self::ImageKey {
+        self.add_image_with_orientation(encoded, decoded, orientation)
+    }
+
+    // The decoded pixels are already an A8 mask, taken from `channel` of the
+    // encoded image. APIs should send them rather than the encoded bytes.
+    fn add_image_mask(
+        &mut self,
+        encoded: ImageEncodedData,
+        decoded: ImageResourceData,
+        orientation: ImageOrientation,
+        _: MaskChannel
+    ) -> Self::ImageKey {
+        self.add_image_with_orientation(encoded, decoded, orientation)
+    }
+
+    // Lets the renderer map the encoded bytes of a mask from shared memory and
+    // extract `channel` itself. APIs unable to forward the descriptor fall back
+    // to sending the decoded A8 mask.
+    fn add_shared_image_mask(
+        &mut self,
+        _: SharedMemoryDescriptor,
+        encoded: ImageEncodedData,
+        decoded: ImageResourceData,
+        orientation: ImageOrientation,
+        channel: MaskChannel
+    ) -> Self::ImageKey {
+        self.add_image_mask(encoded, decoded, orientation, channel)
+    }
+
+    // Adds equally sized images as the layers of one texture array, returning
+    // its key. APIs without texture arrays return `None`, in which case the
+    // layers are added as standalone images instead.
+    fn add_image_array(&mut self, _: (u32, u32), _: Vec<(ImageEncodedData, ImageResourceData)>) -> Option<Self::ImageKey> {
+        None
+    }
+
+    // Whether the consumer copies the decoded pixels out while applying the
+    // resource updates, instead of keeping references to them. Caches may then
+    // stage pixels in reusable buffers, reclaimed once the updates are acknowledged.
+    fn copies_pixels_synchronously(&self) -> bool {
+        false
+    }
+
+    // Whether the renderer can draw a color image through a separate mask,
+    // linked with `link_image_mask`. Otherwise caches composite the mask into
+    // the color image when decoding.
+    fn supports_image_masks(&self) -> bool {
+        false
+    }
+
+    fn link_image_mask(&mut self, _: Self::ImageKey, _: Self::ImageKey) {}
+
+    // Replaces the pixels of an image under the key it was added with. APIs
+    // unable to update images in place return false, in which case caches
+    // delete the image and add it again under a new key.
+    fn update_image(&mut self, _: Self::ImageKey, _: ImageEncodedData, _: ImageResourceData, _: ImageOrientation) -> bool {
+        false
+    }
+
+    // Sends an image again under the key it was added with, for renderers
+    // which lost it, e.g. after a GPU reset. Masks come with their channel.
+    // APIs unable to reuse keys return false.
+    fn replay_image(
+        &mut self,
+        _: Self::ImageKey,
+        _: ImageEncodedData,
+        _: ImageResourceData,
+        _: ImageOrientation,
+        _: Option<MaskChannel>
+    ) -> bool {
+        false
+    }
+
+    fn replay_image_array(&mut self, _: Self::ImageKey, _: (u32, u32), _: Vec<(ImageEncodedData, ImageResourceData)>) -> bool {
+        false
+    }
+
+    // Keeps keys a renderer still knows from being handed out again, e.g.
+    // after relaunching the process owning the caches.
+    fn reserve_image_keys(&mut self, _: &[Self::ImageKey]) {}
+
+    fn delete_image(&mut self, key: Self::ImageKey);
+}
+
+pub trait TFontKeysAPIExt: TFontKeysAPI {
+    fn add_font_instance_with_transform(
+        &mut self,
+        font_key: Self::FontKey,
+        resource: FontInstanceResourceData,
+        transform: GlyphTransform
+    ) -> Self::FontInstanceKey;
+
+    fn add_shared_font(&mut self, _: SharedMemoryDescriptor, encoded: FontEncodedData, resource: FontResourceData) -> Self::FontKey {
+        self.add_font(encoded, resource)
+    }
+
+    // Whether the consumer takes fonts as raw bytes, e.g. through a binary
+    // encoding of the updates, instead of as base64 data uris.
+    fn supports_raw_font_bytes(&self) -> bool {
+        false
+    }
+
+    // Takes the updates with fonts as raw bytes whatever the consumer declared,
+    // for callers encoding them into a binary format themselves.
+    fn take_raw_resource_updates(&mut self) -> Self::ResourceUpdates {
+        self.take_resource_updates()
+    }
+
+    // Whether the consumer replaces a font when it's added again under a key it
+    // already holds, instead of rejecting it. Font subsets grow this way.
+    fn supports_font_replacement(&self) -> bool {
+        false
+    }
+
+    // Describes the text a shaped run represents. APIs with no use for it,
+    // e.g. ones not driving accessibility tooling, can ignore it.
+    fn add_glyph_run(&mut self, _: Self::FontInstanceKey, _: Vec<u32>, _: GlyphRunSource) {}
+
+    // Like `TImageKeysAPIExt::replay_image`.
+    fn replay_font(&mut self, _: Self::FontKey, _: FontEncodedData, _: FontResourceData) -> bool {
+        false
+    }
+
+    fn replay_font_instance(
+        &mut self,
+        _: Self::FontInstanceKey,
+        _: Self::FontKey,
+        _: FontInstanceResourceData,
+        _: GlyphTransform
+    ) -> bool {
+        false
+    }
+
+    fn reserve_font_keys(&mut self, _: &[Self::FontKey], _: &[Self::FontInstanceKey]) {}
+
+    fn delete_font(&mut self, key: Self::FontKey);
+
+    fn delete_font_instance(&mut self, instance_key: Self::FontInstanceKey);
+
+    // Glyph atlas pages are textures many rasterized glyphs are packed into,
+    // uploaded region by region as glyphs are added. APIs whose renderer
+    // rasterizes glyphs itself can ignore them.
+    fn add_glyph_atlas_page(&mut self, _: u32, _: (u32, u32), _: u32) {}
+
+    fn update_glyph_atlas_region(&mut self, _: u32, _: (u32, u32), _: (u32, u32), _: &[u8]) {}
+
+    fn delete_glyph_atlas_page(&mut self, _: u32) {}
+}
+
+pub trait TGlyphInstanceExt: TGlyphInstance {
+    fn glyph_index(&self) -> u32;
+
+    fn x_64(&self) -> i32;
+
+    fn y_64(&self) -> i32;
+}
+
+// Maps glyphs back to the text they were shaped from, for placing carets and
+// hit testing. Clusters are byte offsets into the text, one per glyph, and a
+// cluster spans all glyphs sharing its offset, e.g. a ligature's chars or a
+// char with its marks.
+pub trait TGlyphStoreExt: TGlyphStore {
+    fn clusters(&self) -> Option<&[u32]>;
+
+    fn glyph_cluster(&self, glyph: usize) -> Option<u32> {
+        self.clusters().and_then(|clusters| clusters.get(glyph).cloned())
+    }
+
+    // The glyphs of the cluster containing the byte offset. Glyphs are in
+    // visual order, so offsets decrease along right to left runs.
+    fn glyphs_at_offset(&self, offset: u32) -> Option<Range<usize>> {
+        let clusters = self.clusters()?;
+        let cluster = clusters.iter().cloned().filter(|&cluster| cluster <= offset).max()?;
+        let start = clusters.iter().position(|&c| c == cluster)?;
+        let end = clusters.iter().rposition(|&c| c == cluster)? + 1;
+        Some(start..end)
+    }
+}
@@ -10,16 +10,24 @@ specific language governing permissions and limitations under the License.
 */
 
 use std::ffi::CString;
+use std::fmt;
+use std::io;
 use std::mem;
 use std::os::raw::c_char;
 use std::rc::Rc;
+use std::sync::mpsc::{channel, Receiver, RecvError, Sender, TryRecvError};
 
 use base64_util;
 use rsx_shared::traits::{TFontInstanceKey, TFontKey, TFontKeysAPI, TGlyphInstance, TImageKeysAPI, TMediaKey};
 use rsx_shared::types::{FontEncodedData, FontInstanceResourceData, FontResourceData, ImageEncodedData, ImageResourceData};
 use serde::Serialize;
+#[cfg(not(feature = "simd-json"))]
 use serde_json;
 
+use pixels::{self, MaskChannel};
+use shmem::SharedMemoryDescriptor;
+use traits::{TFontKeysAPIExt, TGlyphInstanceExt, TImageKeysAPIExt};
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub struct DefaultImageKey(pub u64);
 
@@ -35,6 +43,136 @@ pub struct DefaultFontInstanceKey(pub u64);
 
 impl TFontInstanceKey for DefaultFontInstanceKey {}
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ImageOrientation {
+    Identity,
+    FlipHorizontal,
+    FlipVertical,
+    Rotate90,
+    Rotate180,
+    Rotate270,
+    Transpose,
+    Transverse
+}
+
+impl Default for ImageOrientation {
+    fn default() -> Self {
+        ImageOrientation::Identity
+    }
+}
+
+impl ImageOrientation {
+    pub fn swaps_axes(self) -> bool {
+        match self {
+            ImageOrientation::Rotate90 | ImageOrientation::Rotate270 | ImageOrientation::Transpose | ImageOrientation::Transverse => true,
+            _ => false
+        }
+    }
+
+    pub fn display_size(self, size: (u32, u32)) -> (u32, u32) {
+        if self.swaps_axes() {
+            (size.1, size.0)
+        } else {
+            size
+        }
+    }
+
+    // Maps a pixel coordinate in display orientation back to the stored pixel
+    // coordinate, given the stored (unoriented) image size.
+    pub fn source_position(self, position: (u32, u32), size: (u32, u32)) -> (u32, u32) {
+        let (x, y) = position;
+        let (w, h) = size;
+        match self {
+            ImageOrientation::Identity => (x, y),
+            ImageOrientation::FlipHorizontal => (w - 1 - x, y),
+            ImageOrientation::FlipVertical => (x, h - 1 - y),
+            ImageOrientation::Rotate90 => (y, h - 1 - x),
+            ImageOrientation::Rotate180 => (w - 1 - x, h - 1 - y),
+            ImageOrientation::Rotate270 => (w - 1 - y, x),
+            ImageOrientation::Transpose => (y, x),
+            ImageOrientation::Transverse => (w - 1 - y, h - 1 - x)
+        }
+    }
+}
+
+// A 2x2 matrix in 16.16 fixed point, applied to glyph outlines before they're
+// measured and rasterized. Stored as integers so it can be part of an identity.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct GlyphTransform {
+    pub xx: i32,
+    pub xy: i32,
+    pub yx: i32,
+    pub yy: i32
+}
+
+impl Default for GlyphTransform {
+    fn default() -> Self {
+        GlyphTransform::IDENTITY
+    }
+}
+
+impl GlyphTransform {
+    pub const IDENTITY: GlyphTransform = GlyphTransform {
+        xx: 0x10000,
+        xy: 0,
+        yx: 0,
+        yy: 0x10000
+    };
+
+    pub fn new(xx: f32, xy: f32, yx: f32, yy: f32) -> Self {
+        GlyphTransform {
+            xx: to_fixed_16_16(xx),
+            xy: to_fixed_16_16(xy),
+            yx: to_fixed_16_16(yx),
+            yy: to_fixed_16_16(yy)
+        }
+    }
+
+    // Slants glyphs to the right by `shear` horizontal units per vertical unit.
+    pub fn shear(shear: f32) -> Self {
+        GlyphTransform::new(1.0, shear, 0.0, 1.0)
+    }
+
+    pub fn scale(x: f32, y: f32) -> Self {
+        GlyphTransform::new(x, 0.0, 0.0, y)
+    }
+
+    // Counter-clockwise, in radians.
+    pub fn rotation(angle: f32) -> Self {
+        let (sin, cos) = angle.sin_cos();
+        GlyphTransform::new(cos, -sin, sin, cos)
+    }
+
+    pub fn is_identity(&self) -> bool {
+        *self == GlyphTransform::IDENTITY
+    }
+
+    pub fn apply_64(&self, x_64: i64, y_64: i64) -> (i64, i64) {
+        let x = i64::from(self.xx) * x_64 + i64::from(self.xy) * y_64;
+        let y = i64::from(self.yx) * x_64 + i64::from(self.yy) * y_64;
+        (x >> 16, y >> 16)
+    }
+
+    // Both components of an advance along one axis once transformed, with y
+    // growing downwards like glyph positions. Rotations and shears move the
+    // pen across the axis as well as along it.
+    pub fn advance_64(&self, advance_64: i64, vertical: bool) -> (i64, i64) {
+        let (x, y) = if vertical { self.apply_64(0, -advance_64) } else { self.apply_64(advance_64, 0) };
+        (x, -y)
+    }
+
+    // Size of the axis-aligned box containing a transformed `width` x `height` box.
+    pub fn bounds_64(&self, width_64: i64, height_64: i64) -> (i64, i64) {
+        let width = i64::from(self.xx).abs() * width_64 + i64::from(self.xy).abs() * height_64;
+        let height = i64::from(self.yx).abs() * width_64 + i64::from(self.yy).abs() * height_64;
+        (width >> 16, height >> 16)
+    }
+}
+
+fn to_fixed_16_16(value: f32) -> i32 {
+    (value * 65536.0).round() as i32
+}
+
 #[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize)]
 pub struct DefaultGlyphInstance {
     pub glyph_index: u32,
@@ -52,12 +190,91 @@ impl TGlyphInstance for DefaultGlyphInstance {
     }
 }
 
+impl TGlyphInstanceExt for DefaultGlyphInstance {
+    fn glyph_index(&self) -> u32 {
+        self.glyph_index
+    }
+
+    fn x_64(&self) -> i32 {
+        self.x_64
+    }
+
+    fn y_64(&self) -> i32 {
+        self.y_64
+    }
+}
+
+// How much of the source text is kept alongside shaped glyph runs, so that
+// a run can be mapped back to the text it was shaped from.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum GlyphSourceMode {
+    Omitted,
+    Hashed,
+    Text
+}
+
+impl Default for GlyphSourceMode {
+    fn default() -> Self {
+        GlyphSourceMode::Omitted
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct GlyphRunSource {
+    pub text: Option<Rc<String>>,
+    pub text_hash: u64,
+    // Byte offset in the source text of the cluster each glyph was shaped from.
+    pub offsets: Rc<Vec<u32>>
+}
+
+impl GlyphRunSource {
+    pub fn new(text: Option<Rc<String>>, text_hash: u64, offsets: Vec<u32>) -> Self {
+        GlyphRunSource {
+            text,
+            text_hash,
+            offsets: Rc::new(offsets)
+        }
+    }
+
+    pub fn text(&self) -> Option<&str> {
+        self.text.as_ref().map(|text| text.as_str())
+    }
+
+    pub fn glyph_count(&self) -> usize {
+        self.offsets.len()
+    }
+
+    // Glyphs sharing a cluster all map to the whole cluster.
+    pub fn glyph_text(&self, index: usize) -> Option<&str> {
+        let text = self.text()?;
+        let start = *self.offsets.get(index)?;
+        let end = self.offsets[index + 1..]
+            .iter()
+            .find(|&&offset| offset > start)
+            .map_or(text.len(), |&offset| offset as usize);
+        text.get(start as usize..end)
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub struct DefaultImageKeysAPI {
     up: <DefaultImageKeysAPI as TImageKeysAPI>::ResourceUpdates,
+    stream: UpdateStream<DefaultImageKey, DefaultFontKey, DefaultFontInstanceKey>,
     next_image_key: u64
 }
 
+impl DefaultImageKeysAPI {
+    pub fn subscribe(&mut self) -> UpdateReceiver<DefaultImageKey, DefaultFontKey, DefaultFontInstanceKey> {
+        self.stream.subscribe()
+    }
+
+    pub fn into_stream(mut self) -> (Self, UpdateReceiver<DefaultImageKey, DefaultFontKey, DefaultFontInstanceKey>) {
+        let receiver = self.stream.subscribe();
+        self.stream.batching = false;
+        (self, receiver)
+    }
+}
+
 impl TImageKeysAPI for DefaultImageKeysAPI {
     type RootRendererAPI = ();
     type ResourceUpdates = ResourceUpdates<Self::ImageKey, DefaultFontKey, DefaultFontInstanceKey>;
@@ -66,34 +283,250 @@ impl TImageKeysAPI for DefaultImageKeysAPI {
     fn new(_: Self::RootRendererAPI) -> Self {
         DefaultImageKeysAPI {
             up: Self::ResourceUpdates::with_capacity(0),
+            stream: UpdateStream::new(),
             next_image_key: 0
         }
     }
 
-    fn add_image(&mut self, encoded: ImageEncodedData, _: ImageResourceData) -> Self::ImageKey {
+    fn add_image(&mut self, encoded: ImageEncodedData, decoded: ImageResourceData) -> Self::ImageKey {
+        self.add_image_with_orientation(encoded, decoded, ImageOrientation::Identity)
+    }
+
+    // Batches are always handed out ordered, see `order`.
+    fn take_resource_updates(&mut self) -> Self::ResourceUpdates {
+        let mut updates = mem::replace(&mut self.up, Self::ResourceUpdates::with_capacity(0));
+        updates.sort_dependencies();
+        updates
+    }
+}
+
+impl TImageKeysAPIExt for DefaultImageKeysAPI {
+    fn add_image_with_orientation(
+        &mut self,
+        encoded: ImageEncodedData,
+        _: ImageResourceData,
+        orientation: ImageOrientation
+    ) -> Self::ImageKey {
         let image_key = DefaultImageKey(self.next_image_key);
         self.next_image_key += 1;
 
-        let uri = match encoded {
-            ImageEncodedData::Bytes { format, bytes } => Rc::new(base64_util::to_image_data_uri(format.as_ref(), bytes)),
-            ImageEncodedData::DataUri { data_uri } => Rc::clone(data_uri)
-        };
+        let from = self.up.len();
+        self.up
+            .add_oriented_image(image_key, image_data_uri(encoded), orientation);
+        self.stream.forward(&mut self.up, from);
+
+        image_key
+    }
 
-        self.up.add_image(image_key, uri);
+    fn add_shared_image(
+        &mut self,
+        memory: SharedMemoryDescriptor,
+        encoded: ImageEncodedData,
+        _: ImageResourceData,
+        orientation: ImageOrientation
+    ) -> Self::ImageKey {
+        let image_key = DefaultImageKey(self.next_image_key);
+        self.next_image_key += 1;
+
+        let from = self.up.len();
+        self.up
+            .add_shared_image(image_key, memory, encoded_format(encoded), orientation);
+        self.stream.forward(&mut self.up, from);
 
         image_key
     }
 
-    fn take_resource_updates(&mut self) -> Self::ResourceUpdates {
-        mem::replace(&mut self.up, Self::ResourceUpdates::with_capacity(0))
+    fn add_image_mask(
+        &mut self,
+        _: ImageEncodedData,
+        decoded: ImageResourceData,
+        orientation: ImageOrientation,
+        _: MaskChannel
+    ) -> Self::ImageKey {
+        let image_key = DefaultImageKey(self.next_image_key);
+        self.next_image_key += 1;
+
+        let from = self.up.len();
+        self.up
+            .add_image_mask(image_key, decoded.size, mask_bytes(decoded), orientation);
+        self.stream.forward(&mut self.up, from);
+
+        image_key
+    }
+
+    fn add_shared_image_mask(
+        &mut self,
+        memory: SharedMemoryDescriptor,
+        encoded: ImageEncodedData,
+        _: ImageResourceData,
+        orientation: ImageOrientation,
+        channel: MaskChannel
+    ) -> Self::ImageKey {
+        let image_key = DefaultImageKey(self.next_image_key);
+        self.next_image_key += 1;
+
+        let from = self.up.len();
+        self.up
+            .add_shared_image_mask(image_key, memory, encoded_format(encoded), orientation, channel);
+        self.stream.forward(&mut self.up, from);
+
+        image_key
+    }
+
+    fn add_image_array(&mut self, size: (u32, u32), layers: Vec<(ImageEncodedData, ImageResourceData)>) -> Option<Self::ImageKey> {
+        let image_key = DefaultImageKey(self.next_image_key);
+        self.next_image_key += 1;
+
+        let from = self.up.len();
+        self.up
+            .add_image_array(image_key, size, layers.len() as u32);
+        for (layer, (encoded, _)) in layers.into_iter().enumerate() {
+            self.up
+                .add_image_array_layer(image_key, layer as u32, image_data_uri(encoded));
+        }
+        self.stream.forward(&mut self.up, from);
+
+        Some(image_key)
+    }
+
+    fn supports_image_masks(&self) -> bool {
+        true
+    }
+
+    fn replay_image(
+        &mut self,
+        key: Self::ImageKey,
+        encoded: ImageEncodedData,
+        decoded: ImageResourceData,
+        orientation: ImageOrientation,
+        channel: Option<MaskChannel>
+    ) -> bool {
+        let from = self.up.len();
+        match channel {
+            Some(_) => self.up
+                .add_image_mask(key, decoded.size, mask_bytes(decoded), orientation),
+            None => self.up
+                .add_oriented_image(key, image_data_uri(encoded), orientation)
+        }
+        self.stream.forward(&mut self.up, from);
+        true
+    }
+
+    fn replay_image_array(&mut self, key: Self::ImageKey, size: (u32, u32), layers: Vec<(ImageEncodedData, ImageResourceData)>) -> bool {
+        let from = self.up.len();
+        self.up.add_image_array(key, size, layers.len() as u32);
+        for (layer, (encoded, _)) in layers.into_iter().enumerate() {
+            self.up
+                .add_image_array_layer(key, layer as u32, image_data_uri(encoded));
+        }
+        self.stream.forward(&mut self.up, from);
+        true
+    }
+
+    fn reserve_image_keys(&mut self, keys: &[Self::ImageKey]) {
+        if let Some(&DefaultImageKey(last)) = keys.iter().max() {
+            self.next_image_key = self.next_image_key.max(last + 1);
+        }
+    }
+
+    fn link_image_mask(&mut self, key: Self::ImageKey, mask_key: Self::ImageKey) {
+        let from = self.up.len();
+        self.up.link_image_mask(key, mask_key);
+        self.stream.forward(&mut self.up, from);
+    }
+
+    fn update_image(&mut self, key: Self::ImageKey, encoded: ImageEncodedData, _: ImageResourceData, orientation: ImageOrientation) -> bool {
+        let from = self.up.len();
+        self.up
+            .update_image(key, image_data_uri(encoded), orientation);
+        self.stream.forward(&mut self.up, from);
+        true
+    }
+
+    fn delete_image(&mut self, key: Self::ImageKey) {
+        let from = self.up.len();
+        self.up.delete_image(key);
+        self.stream.forward(&mut self.up, from);
+    }
+}
+
+fn image_data_uri(encoded: ImageEncodedData) -> Rc<String> {
+    match encoded {
+        ImageEncodedData::Bytes { format, bytes } => Rc::new(base64_util::to_image_data_uri(format.as_ref(), bytes)),
+        ImageEncodedData::DataUri { data_uri } => Rc::clone(data_uri)
+    }
+}
+
+fn encoded_format(encoded: ImageEncodedData) -> String {
+    match encoded {
+        ImageEncodedData::Bytes { format, .. } => format.as_ref().to_string(),
+        ImageEncodedData::DataUri { .. } => String::new()
+    }
+}
+
+// The coverage of an A8 mask with its rows packed, whatever row alignment the
+// cache decoded it with.
+fn mask_bytes(decoded: ImageResourceData) -> Rc<Vec<u8>> {
+    let (width, height) = (decoded.size.0 as usize, decoded.size.1 as usize);
+    let row_stride = decoded.pixels.len().checked_div(height).unwrap_or(0);
+    Rc::new(pixels::unpad_rows(&decoded.pixels[..], width, row_stride))
+}
+
+// Raw bytes are only encoded into data uris once the updates are taken, and
+// only for consumers not supporting them, see `supports_raw_font_bytes`. The
+// bytes are shared with the cache until then.
+fn add_font_data<ImageKey, FontKey, FontInstanceKey>(
+    updates: &mut ResourceUpdates<ImageKey, FontKey, FontInstanceKey>,
+    key: FontKey,
+    encoded: FontEncodedData
+) {
+    match encoded {
+        FontEncodedData::Bytes { bytes } => updates.add_font_bytes(key, Rc::clone(bytes)),
+        FontEncodedData::DataUri { data_uri } => updates.add_font(key, Rc::clone(data_uri))
     }
 }
 
 #[derive(Debug, PartialEq)]
 pub struct DefaultFontKeysAPI {
     up: <DefaultFontKeysAPI as TFontKeysAPI>::ResourceUpdates,
+    stream: UpdateStream<DefaultImageKey, DefaultFontKey, DefaultFontInstanceKey>,
     next_font_key: u64,
-    next_font_instance_key: u64
+    next_font_instance_key: u64,
+    raw_font_bytes: bool,
+    font_replacement: bool
+}
+
+impl DefaultFontKeysAPI {
+    // Declares that the consumer takes fonts as raw bytes, which skips the
+    // base64 data uris JSON consumers need, doubling the memory used by big
+    // fonts while serializing.
+    pub fn set_supports_raw_font_bytes(&mut self, enabled: bool) {
+        self.raw_font_bytes = enabled;
+    }
+
+    // Declares that the consumer replaces fonts added again under the same
+    // key, see `TFontKeysAPIExt::supports_font_replacement`.
+    pub fn set_supports_font_replacement(&mut self, enabled: bool) {
+        self.font_replacement = enabled;
+    }
+
+    // Subscribers get fonts the same way batches do.
+    fn forward_fonts(&mut self, from: usize) {
+        if !self.raw_font_bytes && self.stream.has_subscribers() {
+            self.up.encode_font_bytes(from);
+        }
+        self.stream.forward(&mut self.up, from);
+    }
+
+    pub fn subscribe(&mut self) -> UpdateReceiver<DefaultImageKey, DefaultFontKey, DefaultFontInstanceKey> {
+        self.stream.subscribe()
+    }
+
+    pub fn into_stream(mut self) -> (Self, UpdateReceiver<DefaultImageKey, DefaultFontKey, DefaultFontInstanceKey>) {
+        let receiver = self.stream.subscribe();
+        self.stream.batching = false;
+        (self, receiver)
+    }
 }
 
 impl TFontKeysAPI for DefaultFontKeysAPI {
@@ -106,8 +539,11 @@ impl TFontKeysAPI for DefaultFontKeysAPI {
     fn new(_: Self::RootRendererAPI) -> Self {
         DefaultFontKeysAPI {
             up: Self::ResourceUpdates::with_capacity(0),
+            stream: UpdateStream::new(),
             next_font_key: 0,
-            next_font_instance_key: 0
+            next_font_instance_key: 0,
+            raw_font_bytes: false,
+            font_replacement: false
         }
     }
 
@@ -115,28 +551,205 @@ impl TFontKeysAPI for DefaultFontKeysAPI {
         let font_key = DefaultFontKey(self.next_font_key);
         self.next_font_key += 1;
 
-        let uri = match encoded {
-            FontEncodedData::Bytes { bytes } => Rc::new(base64_util::to_font_data_uri(bytes)),
-            FontEncodedData::DataUri { data_uri } => Rc::clone(data_uri)
-        };
-
-        self.up.add_font(font_key, uri);
+        let from = self.up.len();
+        add_font_data(&mut self.up, font_key, encoded);
+        self.forward_fonts(from);
 
         font_key
     }
 
     fn add_font_instance(&mut self, font_key: Self::FontKey, resource: FontInstanceResourceData) -> Self::FontInstanceKey {
+        self.add_font_instance_with_transform(font_key, resource, GlyphTransform::IDENTITY)
+    }
+
+    // Batches are always handed out ordered, see `order`.
+    fn take_resource_updates(&mut self) -> Self::ResourceUpdates {
+        let mut updates = mem::replace(&mut self.up, Self::ResourceUpdates::with_capacity(0));
+        if !self.supports_raw_font_bytes() {
+            updates.encode_font_bytes(0);
+        }
+        updates.sort_dependencies();
+        updates
+    }
+}
+
+impl TFontKeysAPIExt for DefaultFontKeysAPI {
+    fn add_font_instance_with_transform(
+        &mut self,
+        font_key: Self::FontKey,
+        resource: FontInstanceResourceData,
+        transform: GlyphTransform
+    ) -> Self::FontInstanceKey {
         let font_instance_key = DefaultFontInstanceKey(self.next_font_instance_key);
         self.next_font_instance_key += 1;
 
         let size = resource.size;
-        self.up.add_font_instance(font_instance_key, font_key, size);
+        let from = self.up.len();
+        self.up
+            .add_transformed_font_instance(font_instance_key, font_key, size, transform);
+        self.stream.forward(&mut self.up, from);
 
         font_instance_key
     }
 
-    fn take_resource_updates(&mut self) -> Self::ResourceUpdates {
-        mem::replace(&mut self.up, Self::ResourceUpdates::with_capacity(0))
+    fn add_shared_font(&mut self, memory: SharedMemoryDescriptor, _: FontEncodedData, _: FontResourceData) -> Self::FontKey {
+        let font_key = DefaultFontKey(self.next_font_key);
+        self.next_font_key += 1;
+
+        let from = self.up.len();
+        self.up.add_shared_font(font_key, memory);
+        self.stream.forward(&mut self.up, from);
+
+        font_key
+    }
+
+    fn supports_raw_font_bytes(&self) -> bool {
+        self.raw_font_bytes
+    }
+
+    fn take_raw_resource_updates(&mut self) -> Self::ResourceUpdates {
+        let mut updates = mem::replace(&mut self.up, Self::ResourceUpdates::with_capacity(0));
+        updates.sort_dependencies();
+        updates
+    }
+
+    fn supports_font_replacement(&self) -> bool {
+        self.font_replacement
+    }
+
+    fn add_glyph_run(&mut self, instance_key: Self::FontInstanceKey, glyphs: Vec<u32>, source: GlyphRunSource) {
+        let from = self.up.len();
+        self.up.add_glyph_run(instance_key, glyphs, source);
+        self.stream.forward(&mut self.up, from);
+    }
+
+    fn replay_font(&mut self, key: Self::FontKey, encoded: FontEncodedData, _: FontResourceData) -> bool {
+        let from = self.up.len();
+        add_font_data(&mut self.up, key, encoded);
+        self.forward_fonts(from);
+        true
+    }
+
+    fn replay_font_instance(
+        &mut self,
+        instance_key: Self::FontInstanceKey,
+        key: Self::FontKey,
+        resource: FontInstanceResourceData,
+        transform: GlyphTransform
+    ) -> bool {
+        let from = self.up.len();
+        self.up
+            .add_transformed_font_instance(instance_key, key, resource.size, transform);
+        self.stream.forward(&mut self.up, from);
+        true
+    }
+
+    fn reserve_font_keys(&mut self, keys: &[Self::FontKey], instance_keys: &[Self::FontInstanceKey]) {
+        if let Some(&DefaultFontKey(last)) = keys.iter().max() {
+            self.next_font_key = self.next_font_key.max(last + 1);
+        }
+        if let Some(&DefaultFontInstanceKey(last)) = instance_keys.iter().max() {
+            self.next_font_instance_key = self.next_font_instance_key.max(last + 1);
+        }
+    }
+
+    fn delete_font(&mut self, key: Self::FontKey) {
+        let from = self.up.len();
+        self.up.delete_font(key);
+        self.stream.forward(&mut self.up, from);
+    }
+
+    fn delete_font_instance(&mut self, instance_key: Self::FontInstanceKey) {
+        let from = self.up.len();
+        self.up.delete_font_instance(instance_key);
+        self.stream.forward(&mut self.up, from);
+    }
+
+    fn add_glyph_atlas_page(&mut self, page: u32, size: (u32, u32), bytes_per_pixel: u32) {
+        let from = self.up.len();
+        self.up.add_glyph_atlas_page(page, size, bytes_per_pixel);
+        self.stream.forward(&mut self.up, from);
+    }
+
+    fn update_glyph_atlas_region(&mut self, page: u32, origin: (u32, u32), size: (u32, u32), pixels: &[u8]) {
+        let from = self.up.len();
+        self.up
+            .update_glyph_atlas_region(page, origin, size, Rc::new(base64_util::to_pixels_data_uri(pixels)));
+        self.stream.forward(&mut self.up, from);
+    }
+
+    fn delete_glyph_atlas_page(&mut self, page: u32) {
+        let from = self.up.len();
+        self.up.delete_glyph_atlas_page(page);
+        self.stream.forward(&mut self.up, from);
+    }
+}
+
+// Forwards individual updates to subscribers as soon as they're created. When
+// not batching, updates are only delivered through the subscribed channels,
+// until every receiver is dropped and batching resumes.
+pub struct UpdateStream<ImageKey, FontKey, FontInstanceKey> {
+    senders: Vec<Sender<SendUpdate<ImageKey, FontKey, FontInstanceKey>>>,
+    batching: bool
+}
+
+impl<ImageKey, FontKey, FontInstanceKey> UpdateStream<ImageKey, FontKey, FontInstanceKey>
+where
+    ImageKey: Clone,
+    FontKey: Clone,
+    FontInstanceKey: Clone
+{
+    pub fn new() -> Self {
+        UpdateStream {
+            senders: Vec::new(),
+            batching: true
+        }
+    }
+
+    pub fn subscribe(&mut self) -> UpdateReceiver<ImageKey, FontKey, FontInstanceKey> {
+        let (sender, receiver) = channel();
+        self.senders.push(sender);
+        UpdateReceiver { receiver }
+    }
+
+    pub fn has_subscribers(&self) -> bool {
+        !self.senders.is_empty()
+    }
+
+    pub fn is_batching(&self) -> bool {
+        self.batching
+    }
+
+    pub fn forward(&mut self, updates: &mut ResourceUpdates<ImageKey, FontKey, FontInstanceKey>, from: usize) {
+        if !self.senders.is_empty() {
+            for update in &updates.updates[from..] {
+                self.senders
+                    .retain(|sender| sender.send(SendUpdate(update.detached())).is_ok());
+            }
+            // Nobody's left to deliver to, so keep these updates batched
+            // instead of dropping them.
+            if self.senders.is_empty() {
+                self.batching = true;
+            }
+        }
+        if !self.batching {
+            updates.updates.truncate(from);
+        }
+    }
+}
+
+impl<ImageKey, FontKey, FontInstanceKey> fmt::Debug for UpdateStream<ImageKey, FontKey, FontInstanceKey> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("UpdateStream")
+            .field("subscribers", &self.senders.len())
+            .field("batching", &self.batching)
+            .finish()
+    }
+}
+
+impl<ImageKey, FontKey, FontInstanceKey> PartialEq for UpdateStream<ImageKey, FontKey, FontInstanceKey> {
+    fn eq(&self, other: &Self) -> bool {
+        self.senders.len() == other.senders.len() && self.batching == other.batching
     }
 }
 
@@ -153,21 +766,162 @@ impl<ImageKey, FontKey, FontInstanceKey> ResourceUpdates<ImageKey, FontKey, Font
     }
 
     pub fn add_image(&mut self, key: ImageKey, data_uri: Rc<String>) {
-        self.updates.push(Update::AddImage { key, data_uri });
+        self.add_oriented_image(key, data_uri, ImageOrientation::Identity);
+    }
+
+    pub fn add_oriented_image(&mut self, key: ImageKey, data_uri: Rc<String>, orientation: ImageOrientation) {
+        self.updates.push(Update::AddImage {
+            key,
+            data_uri,
+            orientation
+        });
+    }
+
+    // Carries the coverage of an A8 mask, one byte per pixel with the rows packed.
+    pub fn add_image_mask(&mut self, key: ImageKey, size: (u32, u32), bytes: Rc<Vec<u8>>, orientation: ImageOrientation) {
+        self.updates.push(Update::AddImageMask {
+            key,
+            width: size.0,
+            height: size.1,
+            bytes,
+            orientation
+        });
+    }
+
+    // Announces a texture array of `layers` equally sized images, which are
+    // then sent one by one with `add_image_array_layer`.
+    pub fn add_image_array(&mut self, key: ImageKey, size: (u32, u32), layers: u32) {
+        self.updates.push(Update::AddImageArray {
+            key,
+            width: size.0,
+            height: size.1,
+            layers
+        });
+    }
+
+    pub fn add_image_array_layer(&mut self, key: ImageKey, layer: u32, data_uri: Rc<String>) {
+        self.updates.push(Update::AddImageArrayLayer { key, layer, data_uri });
+    }
+
+    // Replaces the pixels of an image added before, keeping its key, e.g. once
+    // the final pass of a progressively decoded image is done.
+    pub fn update_image(&mut self, key: ImageKey, data_uri: Rc<String>, orientation: ImageOrientation) {
+        self.updates.push(Update::UpdateImage {
+            key,
+            data_uri,
+            orientation
+        });
+    }
+
+    // Pairs a color image with the mask drawn along with it, both added before.
+    pub fn link_image_mask(&mut self, key: ImageKey, mask_key: ImageKey) {
+        self.updates.push(Update::LinkImageMask { key, mask_key });
+    }
+
+    // Refers to the encoded bytes through shared memory instead of a data uri.
+    // The format is the same as in data uris, e.g. "png".
+    pub fn add_shared_image(&mut self, key: ImageKey, memory: SharedMemoryDescriptor, format: String, orientation: ImageOrientation) {
+        self.updates.push(Update::AddSharedImage {
+            key,
+            memory,
+            format,
+            orientation
+        });
+    }
+
+    // Like `add_shared_image`, the renderer then decoding the mapped bytes into
+    // an A8 mask taken from `channel`.
+    pub fn add_shared_image_mask(
+        &mut self,
+        key: ImageKey,
+        memory: SharedMemoryDescriptor,
+        format: String,
+        orientation: ImageOrientation,
+        channel: MaskChannel
+    ) {
+        self.updates.push(Update::AddSharedImageMask {
+            key,
+            memory,
+            format,
+            orientation,
+            channel
+        });
     }
 
     pub fn add_font(&mut self, key: FontKey, data_uri: Rc<String>) {
         self.updates.push(Update::AddFont { key, data_uri });
     }
 
+    // Carries the encoded font as is, for consumers not needing a data uri.
+    pub fn add_font_bytes(&mut self, key: FontKey, bytes: Rc<Vec<u8>>) {
+        self.updates.push(Update::AddFontBytes { key, bytes });
+    }
+
+    pub fn add_shared_font(&mut self, key: FontKey, memory: SharedMemoryDescriptor) {
+        self.updates.push(Update::AddSharedFont { key, memory });
+    }
+
     pub fn add_font_instance(&mut self, instance_key: FontInstanceKey, key: FontKey, size: u32) {
+        self.add_transformed_font_instance(instance_key, key, size, GlyphTransform::IDENTITY);
+    }
+
+    pub fn add_transformed_font_instance(&mut self, instance_key: FontInstanceKey, key: FontKey, size: u32, transform: GlyphTransform) {
         self.updates.push(Update::AddFontInstance {
             instance_key,
             key,
-            size
+            size,
+            transform
         });
     }
 
+    pub fn add_glyph_run(&mut self, instance_key: FontInstanceKey, glyphs: Vec<u32>, source: GlyphRunSource) {
+        self.updates.push(Update::AddGlyphRun {
+            instance_key,
+            glyphs,
+            source
+        });
+    }
+
+    // Announces a texture glyphs get packed into, each pixel taking
+    // `bytes_per_pixel` bytes: alpha coverage, subpixel coverage as RGB, or
+    // color in the canonical channel order and alpha mode.
+    pub fn add_glyph_atlas_page(&mut self, page: u32, size: (u32, u32), bytes_per_pixel: u32) {
+        self.updates.push(Update::AddGlyphAtlasPage {
+            page,
+            width: size.0,
+            height: size.1,
+            bytes_per_pixel
+        });
+    }
+
+    // Replaces a region of a page with tightly packed rows of pixels.
+    pub fn update_glyph_atlas_region(&mut self, page: u32, origin: (u32, u32), size: (u32, u32), data_uri: Rc<String>) {
+        self.updates.push(Update::UpdateGlyphAtlasRegion {
+            page,
+            x: origin.0,
+            y: origin.1,
+            width: size.0,
+            height: size.1,
+            data_uri
+        });
+    }
+
+    pub fn delete_glyph_atlas_page(&mut self, page: u32) {
+        self.updates.push(Update::DeleteGlyphAtlasPage { page });
+    }
+
+    pub fn delete_image(&mut self, key: ImageKey) {
+        self.updates.push(Update::DeleteImage { key });
+    }
+
+    pub fn delete_font(&mut self, key: FontKey) {
+        self.updates.push(Update::DeleteFont { key });
+    }
+
+    pub fn delete_font_instance(&mut self, instance_key: FontInstanceKey) {
+        self.updates.push(Update::DeleteFontInstance { instance_key });
+    }
+
     pub fn len(&self) -> usize {
         self.updates.len()
     }
@@ -176,25 +930,92 @@ impl<ImageKey, FontKey, FontInstanceKey> ResourceUpdates<ImageKey, FontKey, Font
         self.len() == 0
     }
 
+    // Appends `other` as is. Batches from different keys APIs never refer to
+    // each other, otherwise use `sort_dependencies` afterwards.
     pub fn merge(&mut self, mut other: Self) {
         self.updates.append(&mut other.updates);
     }
 }
 
-impl<ImageKey, FontKey, FontInstanceKey> Into<String> for ResourceUpdates<ImageKey, FontKey, FontInstanceKey>
+impl<ImageKey, FontKey, FontInstanceKey> ResourceUpdates<ImageKey, FontKey, FontInstanceKey>
+where
+    FontKey: Clone
+{
+    // Turns raw font bytes added from `from` onwards into data uris.
+    pub fn encode_font_bytes(&mut self, from: usize) {
+        for update in &mut self.updates[from..] {
+            let (key, data_uri) = match update {
+                &mut Update::AddFontBytes { ref key, ref bytes } => (key.clone(), Rc::new(base64_util::to_font_data_uri(bytes))),
+                _ => continue
+            };
+            *update = Update::AddFont { key, data_uri };
+        }
+    }
+}
+
+impl<ImageKey, FontKey, FontInstanceKey> ResourceUpdates<ImageKey, FontKey, FontInstanceKey>
 where
     ImageKey: Serialize,
     FontKey: Serialize,
     FontInstanceKey: Serialize
 {
-    #[cfg(not(feature = "pretty-json-mode"))]
-    fn into(self) -> String {
-        serde_json::to_string(&self.updates).unwrap_or_else(|_| "".to_string())
+    // Appends the updates as JSON to `buffer`, so that serializing every frame
+    // can reuse the same allocation instead of growing a fresh one, which is
+    // costly for frames carrying big data uris.
+    pub fn write_json_to(&self, buffer: &mut Vec<u8>) -> io::Result<()> {
+        buffer.reserve(self.json_size_hint());
+        write_json(buffer, &self.updates)
     }
 
-    #[cfg(feature = "pretty-json-mode")]
+    // Doesn't need to be exact, only close enough to avoid reallocating while
+    // writing the escaped strings.
+    pub fn json_size_hint(&self) -> usize {
+        self.updates
+            .iter()
+            .map(Update::json_size_hint)
+            .sum::<usize>() + 2
+    }
+
+    fn into_json_bytes(self) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        match self.write_json_to(&mut buffer) {
+            Ok(()) => buffer,
+            Err(_) => Vec::new()
+        }
+    }
+}
+
+// Numbers are already written through `itoa` and `ryu` by both serializers,
+// so the simd serializer mostly speeds up escaping long strings.
+#[cfg(all(not(feature = "simd-json"), not(feature = "pretty-json-mode")))]
+pub(crate) fn write_json<T: Serialize>(buffer: &mut Vec<u8>, value: &T) -> io::Result<()> {
+    Ok(serde_json::to_writer(buffer, value)?)
+}
+
+#[cfg(all(not(feature = "simd-json"), feature = "pretty-json-mode"))]
+pub(crate) fn write_json<T: Serialize>(buffer: &mut Vec<u8>, value: &T) -> io::Result<()> {
+    Ok(serde_json::to_writer_pretty(buffer, value)?)
+}
+
+#[cfg(all(feature = "simd-json", not(feature = "pretty-json-mode")))]
+pub(crate) fn write_json<T: Serialize>(buffer: &mut Vec<u8>, value: &T) -> io::Result<()> {
+    Ok(simd_json::serde::to_writer(buffer, value)?)
+}
+
+#[cfg(all(feature = "simd-json", feature = "pretty-json-mode"))]
+pub(crate) fn write_json<T: Serialize>(buffer: &mut Vec<u8>, value: &T) -> io::Result<()> {
+    Ok(simd_json::serde::to_writer_pretty(buffer, value)?)
+}
+
+impl<ImageKey, FontKey, FontInstanceKey> Into<String> for ResourceUpdates<ImageKey, FontKey, FontInstanceKey>
+where
+    ImageKey: Serialize,
+    FontKey: Serialize,
+    FontInstanceKey: Serialize
+{
     fn into(self) -> String {
-        serde_json::to_string_pretty(&self.updates).unwrap_or_else(|_| "".to_string())
+        // Serializers only ever write valid UTF-8.
+        unsafe { String::from_utf8_unchecked(self.into_json_bytes()) }
     }
 }
 
@@ -205,24 +1026,268 @@ where
     FontInstanceKey: Serialize
 {
     fn into(self) -> *mut c_char {
-        let string: String = self.into();
-        unsafe { CString::from_vec_unchecked(string.into_bytes()) }.into_raw()
+        unsafe { CString::from_vec_unchecked(self.into_json_bytes()) }.into_raw()
     }
 }
 
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub enum Update<ImageKey, FontKey, FontInstanceKey> {
     AddImage {
         key: ImageKey,
+        data_uri: Rc<String>,
+        orientation: ImageOrientation
+    },
+    AddImageMask {
+        key: ImageKey,
+        width: u32,
+        height: u32,
+        bytes: Rc<Vec<u8>>,
+        orientation: ImageOrientation
+    },
+    AddImageArray {
+        key: ImageKey,
+        width: u32,
+        height: u32,
+        layers: u32
+    },
+    AddImageArrayLayer {
+        key: ImageKey,
+        layer: u32,
         data_uri: Rc<String>
     },
+    AddSharedImage {
+        key: ImageKey,
+        memory: SharedMemoryDescriptor,
+        format: String,
+        orientation: ImageOrientation
+    },
+    AddSharedImageMask {
+        key: ImageKey,
+        memory: SharedMemoryDescriptor,
+        format: String,
+        orientation: ImageOrientation,
+        channel: MaskChannel
+    },
+    LinkImageMask {
+        key: ImageKey,
+        mask_key: ImageKey
+    },
+    UpdateImage {
+        key: ImageKey,
+        data_uri: Rc<String>,
+        orientation: ImageOrientation
+    },
     AddFont {
         key: FontKey,
         data_uri: Rc<String>
     },
+    AddFontBytes {
+        key: FontKey,
+        bytes: Rc<Vec<u8>>
+    },
+    AddSharedFont {
+        key: FontKey,
+        memory: SharedMemoryDescriptor
+    },
     AddFontInstance {
         key: FontKey,
         instance_key: FontInstanceKey,
-        size: u32
+        size: u32,
+        transform: GlyphTransform
+    },
+    AddGlyphRun {
+        instance_key: FontInstanceKey,
+        glyphs: Vec<u32>,
+        source: GlyphRunSource
+    },
+    AddGlyphAtlasPage {
+        page: u32,
+        width: u32,
+        height: u32,
+        bytes_per_pixel: u32
+    },
+    UpdateGlyphAtlasRegion {
+        page: u32,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+        data_uri: Rc<String>
+    },
+    DeleteGlyphAtlasPage {
+        page: u32
+    },
+    DeleteImage {
+        key: ImageKey
+    },
+    DeleteFont {
+        key: FontKey
+    },
+    DeleteFontInstance {
+        instance_key: FontInstanceKey
+    }
+}
+
+impl<ImageKey, FontKey, FontInstanceKey> Update<ImageKey, FontKey, FontInstanceKey> {
+    // Accounts for the variable length fields, plus a rough allowance for the
+    // keys, field names and punctuation.
+    pub fn json_size_hint(&self) -> usize {
+        let variable = match self {
+            &Update::AddImage { ref data_uri, .. }
+            | &Update::AddImageArrayLayer { ref data_uri, .. }
+            | &Update::UpdateImage { ref data_uri, .. }
+            | &Update::UpdateGlyphAtlasRegion { ref data_uri, .. }
+            | &Update::AddFont { ref data_uri, .. } => data_uri.len(),
+            &Update::AddSharedImage { ref format, .. } | &Update::AddSharedImageMask { ref format, .. } => format.len(),
+            &Update::AddImageMask { ref bytes, .. } | &Update::AddFontBytes { ref bytes, .. } => bytes.len() * 4,
+            &Update::AddGlyphRun {
+                ref glyphs,
+                ref source,
+                ..
+            } => (glyphs.len() + source.offsets.len()) * 11 + source.text().map_or(0, str::len),
+            _ => 0
+        };
+        variable + 128
+    }
+
+    // Clones the update without sharing any of its reference counted payloads,
+    // so that the copy can be handed over to another thread.
+    fn detached(&self) -> Self
+    where
+        ImageKey: Clone,
+        FontKey: Clone,
+        FontInstanceKey: Clone
+    {
+        let owned = |data_uri: &Rc<String>| Rc::new(String::clone(data_uri));
+        match self {
+            &Update::AddImage {
+                ref key,
+                ref data_uri,
+                orientation
+            } => Update::AddImage {
+                key: key.clone(),
+                data_uri: owned(data_uri),
+                orientation
+            },
+            &Update::AddImageMask {
+                ref key,
+                width,
+                height,
+                ref bytes,
+                orientation
+            } => Update::AddImageMask {
+                key: key.clone(),
+                width,
+                height,
+                bytes: Rc::new(Vec::clone(bytes)),
+                orientation
+            },
+            &Update::AddImageArrayLayer {
+                ref key,
+                layer,
+                ref data_uri
+            } => Update::AddImageArrayLayer {
+                key: key.clone(),
+                layer,
+                data_uri: owned(data_uri)
+            },
+            &Update::UpdateImage {
+                ref key,
+                ref data_uri,
+                orientation
+            } => Update::UpdateImage {
+                key: key.clone(),
+                data_uri: owned(data_uri),
+                orientation
+            },
+            &Update::AddFont { ref key, ref data_uri } => Update::AddFont {
+                key: key.clone(),
+                data_uri: owned(data_uri)
+            },
+            &Update::AddFontBytes { ref key, ref bytes } => Update::AddFontBytes {
+                key: key.clone(),
+                bytes: Rc::new(Vec::clone(bytes))
+            },
+            &Update::AddGlyphRun {
+                ref instance_key,
+                ref glyphs,
+                ref source
+            } => Update::AddGlyphRun {
+                instance_key: instance_key.clone(),
+                glyphs: glyphs.clone(),
+                source: GlyphRunSource {
+                    text: source.text.as_ref().map(owned),
+                    text_hash: source.text_hash,
+                    offsets: Rc::new(Vec::clone(&source.offsets))
+                }
+            },
+            &Update::UpdateGlyphAtlasRegion {
+                page,
+                x,
+                y,
+                width,
+                height,
+                ref data_uri
+            } => Update::UpdateGlyphAtlasRegion {
+                page,
+                x,
+                y,
+                width,
+                height,
+                data_uri: owned(data_uri)
+            },
+            // Listed rather than matched by a wildcard, so that variants added
+            // with reference counted payloads can't be cloned sharing them.
+            &Update::AddImageArray { .. }
+            | &Update::AddSharedImage { .. }
+            | &Update::AddSharedImageMask { .. }
+            | &Update::LinkImageMask { .. }
+            | &Update::AddSharedFont { .. }
+            | &Update::AddFontInstance { .. }
+            | &Update::AddGlyphAtlasPage { .. }
+            | &Update::DeleteGlyphAtlasPage { .. }
+            | &Update::DeleteImage { .. }
+            | &Update::DeleteFont { .. }
+            | &Update::DeleteFontInstance { .. } => self.clone()
+        }
+    }
+}
+
+// An update whose payloads aren't shared with anything else, see `detached`.
+struct SendUpdate<ImageKey, FontKey, FontInstanceKey>(Update<ImageKey, FontKey, FontInstanceKey>);
+
+// The reference counted payloads are uniquely owned by the wrapped update, so
+// moving it to another thread can't race on their counts.
+unsafe impl<ImageKey, FontKey, FontInstanceKey> Send for SendUpdate<ImageKey, FontKey, FontInstanceKey>
+where
+    ImageKey: Send,
+    FontKey: Send,
+    FontInstanceKey: Send
+{
+}
+
+// Receives the updates forwarded by an `UpdateStream`. Unlike the updates
+// themselves, receivers can be moved to other threads, e.g. a renderer's.
+pub struct UpdateReceiver<ImageKey, FontKey, FontInstanceKey> {
+    receiver: Receiver<SendUpdate<ImageKey, FontKey, FontInstanceKey>>
+}
+
+impl<ImageKey, FontKey, FontInstanceKey> UpdateReceiver<ImageKey, FontKey, FontInstanceKey> {
+    pub fn recv(&self) -> Result<Update<ImageKey, FontKey, FontInstanceKey>, RecvError> {
+        self.receiver.recv().map(|update| update.0)
+    }
+
+    pub fn try_recv(&self) -> Result<Update<ImageKey, FontKey, FontInstanceKey>, TryRecvError> {
+        self.receiver.try_recv().map(|update| update.0)
+    }
+
+    pub fn try_iter<'a>(&'a self) -> impl Iterator<Item = Update<ImageKey, FontKey, FontInstanceKey>> + 'a {
+        self.receiver.try_iter().map(|update| update.0)
+    }
+}
+
+impl<ImageKey, FontKey, FontInstanceKey> fmt::Debug for UpdateReceiver<ImageKey, FontKey, FontInstanceKey> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("UpdateReceiver").finish()
     }
 }
@@ -0,0 +1,121 @@
+/*
+Copyright 2016 Mozilla
+Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+this file except in compliance with the License. You may obtain a copy of the
+License at http://www.apache.org/licenses/LICENSE-2.0
+Unless required by applicable law or agreed to in writing, software distributed
+under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+CONDITIONS OF ANY KIND, either express or implied. See the License for the
+specific language governing permissions and limitations under the License.
+*/
+
+// Sizes, resolutions and pixel ratios are all plain numbers underneath, so
+// each gets its own type to keep one from being passed where another is
+// expected. They serialize as the numbers they wrap.
+
+use std::fmt;
+
+use rsx_shared::consts::{DEFAULT_FONT_DPI, DEFAULT_FONT_SIZE};
+
+// A font size in points, which depends on the resolution it's drawn at.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Pt(pub u32);
+
+// A font size in pixels, regardless of the resolution.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Px(pub u32);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Dpi(pub u32);
+
+// Device pixels per CSS pixel.
+#[derive(Debug, Copy, Clone, PartialEq, PartialOrd, Serialize, Deserialize)]
+pub struct Scale(pub f32);
+
+impl Pt {
+    pub const DEFAULT: Pt = Pt(DEFAULT_FONT_SIZE);
+
+    // Nominal size in 26.6 pixels at the given resolution.
+    pub fn to_px_64(self, dpi: Dpi) -> i64 {
+        i64::from(self.0) * i64::from(dpi.0) * 64 / 72
+    }
+}
+
+impl Px {
+    pub fn to_px_64(self) -> i64 {
+        i64::from(self.0) * 64
+    }
+
+    pub fn scale(self, scale: Scale) -> Px {
+        Px((self.0 as f32 * scale.0).round() as u32)
+    }
+}
+
+impl Dpi {
+    pub const DEFAULT: Dpi = Dpi(DEFAULT_FONT_DPI);
+
+    // Resolution at which a size in points equals the same size in pixels.
+    pub const PIXEL: Dpi = Dpi(72);
+
+    pub fn scale(self, scale: Scale) -> Dpi {
+        Dpi((self.0 as f32 * scale.0).round() as u32)
+    }
+}
+
+impl Scale {
+    pub const ONE: Scale = Scale(1.0);
+}
+
+impl Default for Scale {
+    fn default() -> Self {
+        Scale::ONE
+    }
+}
+
+impl From<Pt> for u32 {
+    fn from(size: Pt) -> Self {
+        size.0
+    }
+}
+
+impl From<Px> for u32 {
+    fn from(size: Px) -> Self {
+        size.0
+    }
+}
+
+impl From<Dpi> for u32 {
+    fn from(dpi: Dpi) -> Self {
+        dpi.0
+    }
+}
+
+impl From<Scale> for f32 {
+    fn from(scale: Scale) -> Self {
+        scale.0
+    }
+}
+
+impl fmt::Display for Pt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}pt", self.0)
+    }
+}
+
+impl fmt::Display for Px {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}px", self.0)
+    }
+}
+
+impl fmt::Display for Dpi {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}dpi", self.0)
+    }
+}
+
+impl fmt::Display for Scale {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}x", self.0)
+    }
+}
@@ -0,0 +1,107 @@
+/*
+Copyright 2016 Mozilla
+Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+this file except in compliance with the License. You may obtain a copy of the
+License at http://www.apache.org/licenses/LICENSE-2.0
+Unless required by applicable law or agreed to in writing, software distributed
+under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+CONDITIONS OF ANY KIND, either express or implied. See the License for the
+specific language governing permissions and limitations under the License.
+*/
+
+#ifndef RSX_RESOURCES_H
+#define RSX_RESOURCES_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct RsxResourceGroup RsxResourceGroup;
+typedef struct RsxFontInstance RsxFontInstance;
+
+typedef enum RsxStatus {
+    RSX_STATUS_OK = 0,
+    RSX_STATUS_NULL_POINTER = 1,
+    RSX_STATUS_INVALID_UTF8 = 2,
+    RSX_STATUS_NOT_FOUND = 3,
+    RSX_STATUS_ALREADY_ADDED = 4,
+    RSX_STATUS_DECODE_FAILED = 5,
+    RSX_STATUS_BUFFER_TOO_SMALL = 6,
+    RSX_STATUS_PANICKED = 7
+} RsxStatus;
+
+typedef struct RsxBuffer {
+    uint8_t *data;
+    size_t len;
+    size_t capacity;
+} RsxBuffer;
+
+typedef struct RsxImageInfo {
+    uint32_t width;
+    uint32_t height;
+    uint32_t display_width;
+    uint32_t display_height;
+    float scale;
+    uint64_t image_key;
+} RsxImageInfo;
+
+typedef struct RsxFontMetrics {
+    uint16_t nominal_width;
+    uint16_t nominal_height;
+    int32_t ascender_64;
+    int32_t descender_64;
+    int32_t height_64;
+    int32_t max_advance_64;
+    uint64_t font_key;
+    uint64_t font_instance_key;
+} RsxFontMetrics;
+
+typedef struct RsxGlyph {
+    uint32_t glyph_index;
+    int32_t x_64;
+    int32_t y_64;
+} RsxGlyph;
+
+typedef struct RsxShapedText {
+    int32_t width_64;
+    int32_t height_64;
+    size_t glyph_count;
+} RsxShapedText;
+
+RsxResourceGroup *rsx_resource_group_new(void);
+void rsx_resource_group_free(RsxResourceGroup *group);
+
+RsxStatus rsx_files_add_path(const RsxResourceGroup *group, const char *path);
+
+RsxStatus rsx_images_add_path(const RsxResourceGroup *group, const char *path);
+RsxStatus rsx_images_add_bytes(const RsxResourceGroup *group, const char *src, const uint8_t *bytes, size_t len);
+RsxStatus rsx_images_get_info(const RsxResourceGroup *group, const char *src, RsxImageInfo *out);
+
+RsxStatus rsx_fonts_add_path(const RsxResourceGroup *group, const char *font_name, const char *path, size_t face_index);
+RsxStatus rsx_fonts_add_bytes(const RsxResourceGroup *group, const char *font_name, const uint8_t *bytes, size_t len, size_t face_index);
+const RsxFontInstance *rsx_fonts_get_instance(const RsxResourceGroup *group, const char *family_name, uint32_t size, uint32_t dpi);
+void rsx_font_instance_release(const RsxFontInstance *instance);
+RsxStatus rsx_fonts_get_metrics(const RsxResourceGroup *group, const RsxFontInstance *instance, RsxFontMetrics *out);
+RsxStatus rsx_fonts_shape_text_h(
+    const RsxResourceGroup *group,
+    const RsxFontInstance *instance,
+    const char *text,
+    RsxGlyph *glyphs,
+    size_t capacity,
+    RsxShapedText *out);
+
+char *rsx_take_resource_updates_json(const RsxResourceGroup *group);
+RsxBuffer rsx_take_resource_updates_binary(const RsxResourceGroup *group);
+void rsx_string_free(char *string);
+uint32_t rsx_last_error_code(void);
+char *rsx_last_error_message(void);
+void rsx_buffer_free(RsxBuffer buffer);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
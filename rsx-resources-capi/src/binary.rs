@@ -0,0 +1,294 @@
+/*
+Copyright 2016 Mozilla
+Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+this file except in compliance with the License. You may obtain a copy of the
+License at http://www.apache.org/licenses/LICENSE-2.0
+Unless required by applicable law or agreed to in writing, software distributed
+under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+CONDITIONS OF ANY KIND, either express or implied. See the License for the
+specific language governing permissions and limitations under the License.
+*/
+
+use rsx_resources::updates::pixels::MaskChannel;
+use rsx_resources::updates::shmem::{SharedMemoryDescriptor, SharedMemoryKind};
+use rsx_resources::updates::types::{DefaultFontInstanceKey, DefaultFontKey, DefaultImageKey, ImageOrientation, ResourceUpdates, Update};
+
+pub type RsxResourceUpdates = ResourceUpdates<DefaultImageKey, DefaultFontKey, DefaultFontInstanceKey>;
+pub type RsxUpdate = Update<DefaultImageKey, DefaultFontKey, DefaultFontInstanceKey>;
+
+pub const TAG_ADD_IMAGE: u8 = 0;
+pub const TAG_ADD_FONT: u8 = 1;
+pub const TAG_ADD_FONT_INSTANCE: u8 = 2;
+pub const TAG_DELETE_IMAGE: u8 = 3;
+pub const TAG_DELETE_FONT: u8 = 4;
+pub const TAG_DELETE_FONT_INSTANCE: u8 = 5;
+pub const TAG_ADD_SHARED_IMAGE: u8 = 6;
+pub const TAG_ADD_SHARED_FONT: u8 = 7;
+pub const TAG_ADD_GLYPH_RUN: u8 = 8;
+pub const TAG_ADD_IMAGE_MASK: u8 = 9;
+pub const TAG_ADD_IMAGE_ARRAY: u8 = 10;
+pub const TAG_ADD_IMAGE_ARRAY_LAYER: u8 = 11;
+pub const TAG_LINK_IMAGE_MASK: u8 = 12;
+pub const TAG_ADD_GLYPH_ATLAS_PAGE: u8 = 13;
+pub const TAG_UPDATE_GLYPH_ATLAS_REGION: u8 = 14;
+pub const TAG_DELETE_GLYPH_ATLAS_PAGE: u8 = 15;
+pub const TAG_UPDATE_IMAGE: u8 = 16;
+pub const TAG_ADD_FONT_BYTES: u8 = 17;
+pub const TAG_ADD_SHARED_IMAGE_MASK: u8 = 18;
+
+// Little endian. A u32 update count, followed by each update as a u8 tag and
+// its fields in declaration order: keys as u64, sizes as u32, orientations and
+// mask channels (alpha, then luminance) as u8, transforms as four i32 and data
+// URIs as a u32 length and UTF-8 bytes. Image arrays are their width, height
+// and layer count, and each layer its index before its data URI. Mask links
+// are the key of the color image, then the key of its mask. Image updates are
+// laid out like image adds. Fonts taken as raw bytes are a u32 length and the
+// bytes, in place of a data URI.
+// Shared memory is a u8 kind, an i32 file descriptor, then u64 offset and length.
+// Glyph runs list glyph indices and offsets as a u32 count and u32 values, and
+// their source text as a u8 presence flag before the string. Glyph atlas pages
+// are a u32 id, then their size and bytes per pixel as u32, and regions the
+// page id, then their x, y, width and height before their data URI. Image
+// masks are their width and height, then their A8 coverage as a u32 length and
+// packed rows. Shared masks are laid out like shared images, with their mask
+// channel after the orientation.
+pub fn encode_updates(updates: &RsxResourceUpdates) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    write_u32(&mut bytes, updates.len() as u32);
+
+    for update in &updates.updates {
+        match update {
+            &Update::AddImage {
+                key,
+                ref data_uri,
+                orientation
+            } => {
+                bytes.push(TAG_ADD_IMAGE);
+                write_u64(&mut bytes, key.0);
+                bytes.push(orientation_tag(orientation));
+                write_str(&mut bytes, data_uri);
+            }
+            &Update::AddImageMask {
+                key,
+                width,
+                height,
+                bytes: ref coverage,
+                orientation
+            } => {
+                bytes.push(TAG_ADD_IMAGE_MASK);
+                write_u64(&mut bytes, key.0);
+                bytes.push(orientation_tag(orientation));
+                write_u32(&mut bytes, width);
+                write_u32(&mut bytes, height);
+                write_u32(&mut bytes, coverage.len() as u32);
+                bytes.extend_from_slice(coverage);
+            }
+            &Update::AddImageArray {
+                key,
+                width,
+                height,
+                layers
+            } => {
+                bytes.push(TAG_ADD_IMAGE_ARRAY);
+                write_u64(&mut bytes, key.0);
+                write_u32(&mut bytes, width);
+                write_u32(&mut bytes, height);
+                write_u32(&mut bytes, layers);
+            }
+            &Update::AddImageArrayLayer {
+                key,
+                layer,
+                ref data_uri
+            } => {
+                bytes.push(TAG_ADD_IMAGE_ARRAY_LAYER);
+                write_u64(&mut bytes, key.0);
+                write_u32(&mut bytes, layer);
+                write_str(&mut bytes, data_uri);
+            }
+            &Update::LinkImageMask { key, mask_key } => {
+                bytes.push(TAG_LINK_IMAGE_MASK);
+                write_u64(&mut bytes, key.0);
+                write_u64(&mut bytes, mask_key.0);
+            }
+            &Update::UpdateImage {
+                key,
+                ref data_uri,
+                orientation
+            } => {
+                bytes.push(TAG_UPDATE_IMAGE);
+                write_u64(&mut bytes, key.0);
+                bytes.push(orientation_tag(orientation));
+                write_str(&mut bytes, data_uri);
+            }
+            &Update::AddFont { key, ref data_uri } => {
+                bytes.push(TAG_ADD_FONT);
+                write_u64(&mut bytes, key.0);
+                write_str(&mut bytes, data_uri);
+            }
+            &Update::AddFontBytes { key, bytes: ref font_bytes } => {
+                bytes.push(TAG_ADD_FONT_BYTES);
+                write_u64(&mut bytes, key.0);
+                write_u32(&mut bytes, font_bytes.len() as u32);
+                bytes.extend_from_slice(font_bytes);
+            }
+            &Update::AddSharedImage {
+                key,
+                memory,
+                ref format,
+                orientation
+            } => {
+                bytes.push(TAG_ADD_SHARED_IMAGE);
+                write_u64(&mut bytes, key.0);
+                bytes.push(orientation_tag(orientation));
+                write_memory(&mut bytes, memory);
+                write_str(&mut bytes, format);
+            }
+            &Update::AddSharedImageMask {
+                key,
+                memory,
+                ref format,
+                orientation,
+                channel
+            } => {
+                bytes.push(TAG_ADD_SHARED_IMAGE_MASK);
+                write_u64(&mut bytes, key.0);
+                bytes.push(orientation_tag(orientation));
+                bytes.push(match channel {
+                    MaskChannel::Alpha => 0,
+                    MaskChannel::Luminance => 1
+                });
+                write_memory(&mut bytes, memory);
+                write_str(&mut bytes, format);
+            }
+            &Update::AddSharedFont { key, memory } => {
+                bytes.push(TAG_ADD_SHARED_FONT);
+                write_u64(&mut bytes, key.0);
+                write_memory(&mut bytes, memory);
+            }
+            &Update::AddFontInstance {
+                key,
+                instance_key,
+                size,
+                transform
+            } => {
+                bytes.push(TAG_ADD_FONT_INSTANCE);
+                write_u64(&mut bytes, key.0);
+                write_u64(&mut bytes, instance_key.0);
+                write_u32(&mut bytes, size);
+                for &value in &[transform.xx, transform.xy, transform.yx, transform.yy] {
+                    write_u32(&mut bytes, value as u32);
+                }
+            }
+            &Update::AddGlyphRun {
+                instance_key,
+                ref glyphs,
+                ref source
+            } => {
+                bytes.push(TAG_ADD_GLYPH_RUN);
+                write_u64(&mut bytes, instance_key.0);
+                write_u32s(&mut bytes, glyphs);
+                write_u64(&mut bytes, source.text_hash);
+                write_u32s(&mut bytes, &source.offsets);
+                match source.text() {
+                    Some(text) => {
+                        bytes.push(1);
+                        write_str(&mut bytes, text);
+                    }
+                    None => bytes.push(0)
+                }
+            }
+            &Update::AddGlyphAtlasPage {
+                page,
+                width,
+                height,
+                bytes_per_pixel
+            } => {
+                bytes.push(TAG_ADD_GLYPH_ATLAS_PAGE);
+                write_u32(&mut bytes, page);
+                write_u32(&mut bytes, width);
+                write_u32(&mut bytes, height);
+                write_u32(&mut bytes, bytes_per_pixel);
+            }
+            &Update::UpdateGlyphAtlasRegion {
+                page,
+                x,
+                y,
+                width,
+                height,
+                ref data_uri
+            } => {
+                bytes.push(TAG_UPDATE_GLYPH_ATLAS_REGION);
+                for &value in &[page, x, y, width, height] {
+                    write_u32(&mut bytes, value);
+                }
+                write_str(&mut bytes, data_uri);
+            }
+            &Update::DeleteGlyphAtlasPage { page } => {
+                bytes.push(TAG_DELETE_GLYPH_ATLAS_PAGE);
+                write_u32(&mut bytes, page);
+            }
+            &Update::DeleteImage { key } => {
+                bytes.push(TAG_DELETE_IMAGE);
+                write_u64(&mut bytes, key.0);
+            }
+            &Update::DeleteFont { key } => {
+                bytes.push(TAG_DELETE_FONT);
+                write_u64(&mut bytes, key.0);
+            }
+            &Update::DeleteFontInstance { instance_key } => {
+                bytes.push(TAG_DELETE_FONT_INSTANCE);
+                write_u64(&mut bytes, instance_key.0);
+            }
+        }
+    }
+
+    bytes
+}
+
+fn orientation_tag(orientation: ImageOrientation) -> u8 {
+    match orientation {
+        ImageOrientation::Identity => 0,
+        ImageOrientation::FlipHorizontal => 1,
+        ImageOrientation::FlipVertical => 2,
+        ImageOrientation::Rotate90 => 3,
+        ImageOrientation::Rotate180 => 4,
+        ImageOrientation::Rotate270 => 5,
+        ImageOrientation::Transpose => 6,
+        ImageOrientation::Transverse => 7
+    }
+}
+
+fn write_memory(bytes: &mut Vec<u8>, memory: SharedMemoryDescriptor) {
+    bytes.push(match memory.kind {
+        #[cfg(unix)]
+        SharedMemoryKind::Memfd => 0,
+        #[cfg(unix)]
+        SharedMemoryKind::Ashmem => 1
+    });
+    write_u32(bytes, memory.fd as u32);
+    write_u64(bytes, memory.offset);
+    write_u64(bytes, memory.len);
+}
+
+fn write_u32(bytes: &mut Vec<u8>, value: u32) {
+    for shift in 0..4 {
+        bytes.push((value >> (shift * 8)) as u8);
+    }
+}
+
+fn write_u64(bytes: &mut Vec<u8>, value: u64) {
+    write_u32(bytes, value as u32);
+    write_u32(bytes, (value >> 32) as u32);
+}
+
+fn write_u32s(bytes: &mut Vec<u8>, values: &[u32]) {
+    write_u32(bytes, values.len() as u32);
+    for &value in values {
+        write_u32(bytes, value);
+    }
+}
+
+fn write_str(bytes: &mut Vec<u8>, value: &str) {
+    write_u32(bytes, value.len() as u32);
+    bytes.extend_from_slice(value.as_bytes());
+}
@@ -0,0 +1,354 @@
+/*
+Copyright 2016 Mozilla
+Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+this file except in compliance with the License. You may obtain a copy of the
+License at http://www.apache.org/licenses/LICENSE-2.0
+Unless required by applicable law or agreed to in writing, software distributed
+under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+CONDITIONS OF ANY KIND, either express or implied. See the License for the
+specific language governing permissions and limitations under the License.
+*/
+
+use std::os::raw::c_char;
+use std::ptr;
+use std::rc::Rc;
+
+use rsx_resources::lookup::LookupError;
+use rsx_shared::traits::{TFontCache, TGlyphStore, TImageCache, TResourceGroup};
+
+use binary::{self, RsxResourceUpdates};
+use types::{
+    catch_panic,
+    guarded,
+    last_error,
+    to_bytes,
+    to_ref,
+    to_status,
+    to_str,
+    RsxBuffer,
+    RsxFontInstance,
+    RsxFontMetrics,
+    RsxGlyph,
+    RsxImageInfo,
+    RsxResourceGroup,
+    RsxShapedText,
+    RsxStatus
+};
+
+// All functions taking pointers expect them to either be null, which is
+// reported as `NullPointer`, or valid: handles obtained from this library and
+// not yet freed, NUL terminated UTF-8 strings and buffers of the given length.
+// None of them unwind into the caller: panics are reported as `Panicked`, or
+// as null and empty results where nothing else can be reported.
+
+// Returns null if the caches couldn't be created.
+#[no_mangle]
+pub extern "C" fn rsx_resource_group_new() -> *mut RsxResourceGroup {
+    match guarded(|| Ok(RsxResourceGroup::with_default_apis())) {
+        Ok(Some(group)) => Box::into_raw(Box::new(group)),
+        _ => ptr::null_mut()
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rsx_resource_group_free(group: *mut RsxResourceGroup) {
+    let _ = catch_panic(|| {
+        if !group.is_null() {
+            drop(Box::from_raw(group));
+        }
+        Ok(())
+    });
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rsx_files_add_path(group: *const RsxResourceGroup, path: *const c_char) -> RsxStatus {
+    to_status(guarded(|| {
+        let group = to_ref(group)?;
+        group.files().borrow_mut().add_file(to_str(path)?)?;
+        Ok(())
+    }))
+}
+
+// Reads the image through the file cache, keyed by its path.
+#[no_mangle]
+pub unsafe extern "C" fn rsx_images_add_path(group: *const RsxResourceGroup, path: *const c_char) -> RsxStatus {
+    to_status(guarded(|| {
+        let group = to_ref(group)?;
+        let path = to_str(path)?;
+        let bytes = load_file(group, path)?;
+        let id = group.images().borrow().image_id(path);
+        group
+            .images()
+            .borrow_mut()
+            .add_raw(id, bytes)?;
+        Ok(())
+    }))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rsx_images_add_bytes(
+    group: *const RsxResourceGroup,
+    src: *const c_char,
+    bytes: *const u8,
+    len: usize
+) -> RsxStatus {
+    to_status(guarded(|| {
+        let group = to_ref(group)?;
+        let bytes = to_bytes(bytes, len)?;
+        let id = group.images().borrow().image_id(to_str(src)?);
+        group
+            .images()
+            .borrow_mut()
+            .add_raw(id, bytes)?;
+        Ok(())
+    }))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rsx_images_get_info(group: *const RsxResourceGroup, src: *const c_char, out: *mut RsxImageInfo) -> RsxStatus {
+    to_status(guarded(|| {
+        let group = to_ref(group)?;
+        let out = out.as_mut().ok_or(RsxStatus::NullPointer)?;
+        let image = group
+            .images()
+            .get_image(to_str(src)?)
+            .ok_or(RsxStatus::NotFound)?;
+        *out = RsxImageInfo {
+            width: image.width(),
+            height: image.height(),
+            display_width: image.display_width(),
+            display_height: image.display_height(),
+            scale: image.scale(),
+            image_key: image.external_key().0
+        };
+        Ok(())
+    }))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rsx_fonts_add_path(
+    group: *const RsxResourceGroup,
+    font_name: *const c_char,
+    path: *const c_char,
+    face_index: usize
+) -> RsxStatus {
+    to_status(guarded(|| {
+        let group = to_ref(group)?;
+        let bytes = load_file(group, to_str(path)?)?;
+        let id = group.fonts().borrow().font_id(to_str(font_name)?);
+        group
+            .fonts()
+            .borrow_mut()
+            .add_raw(id, bytes, face_index)?;
+        Ok(())
+    }))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rsx_fonts_add_bytes(
+    group: *const RsxResourceGroup,
+    font_name: *const c_char,
+    bytes: *const u8,
+    len: usize,
+    face_index: usize
+) -> RsxStatus {
+    to_status(guarded(|| {
+        let group = to_ref(group)?;
+        let bytes = to_bytes(bytes, len)?;
+        let id = group.fonts().borrow().font_id(to_str(font_name)?);
+        group
+            .fonts()
+            .borrow_mut()
+            .add_raw(id, bytes, face_index)?;
+        Ok(())
+    }))
+}
+
+// Returns a new reference to the instance, or null if the family isn't
+// registered, which is then the last error. Released with
+// `rsx_font_instance_release`.
+#[no_mangle]
+pub unsafe extern "C" fn rsx_fonts_get_instance(
+    group: *const RsxResourceGroup,
+    family_name: *const c_char,
+    size: u32,
+    dpi: u32
+) -> *const RsxFontInstance {
+    let instance = guarded(|| {
+        let group = to_ref(group)?;
+        let family_name = to_str(family_name)?;
+        let instance = group
+            .fonts()
+            .get_font_with_size_and_dpi(family_name, size, dpi)
+            .ok_or_else(|| LookupError::FamilyNotRegistered(family_name.to_string()))?;
+        Ok(instance)
+    });
+    match instance {
+        Ok(instance) => Rc::into_raw(instance),
+        Err(_) => ptr::null()
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rsx_font_instance_release(instance: *const RsxFontInstance) {
+    let _ = catch_panic(|| {
+        if !instance.is_null() {
+            drop(Rc::from_raw(instance));
+        }
+        Ok(())
+    });
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rsx_fonts_get_metrics(
+    group: *const RsxResourceGroup,
+    instance: *const RsxFontInstance,
+    out: *mut RsxFontMetrics
+) -> RsxStatus {
+    to_status(guarded(|| {
+        let group = to_ref(group)?;
+        let instance = to_ref(instance)?;
+        let out = out.as_mut().ok_or(RsxStatus::NullPointer)?;
+        let metrics = group
+            .fonts()
+            .borrow()
+            .get_global_size_metrics(instance)?;
+        *out = RsxFontMetrics {
+            nominal_width: metrics.nominal_width,
+            nominal_height: metrics.nominal_height,
+            ascender_64: metrics.ascender_64,
+            descender_64: metrics.descender_64,
+            height_64: metrics.height_64,
+            max_advance_64: metrics.max_advance_64,
+            font_key: instance.external_key().0,
+            font_instance_key: instance.external_instance_key().0
+        };
+        Ok(())
+    }))
+}
+
+// Writes up to `capacity` glyphs into `glyphs`. The glyph count is always
+// reported, so callers can retry with a larger buffer on `BufferTooSmall`.
+#[no_mangle]
+pub unsafe extern "C" fn rsx_fonts_shape_text_h(
+    group: *const RsxResourceGroup,
+    instance: *const RsxFontInstance,
+    text: *const c_char,
+    glyphs: *mut RsxGlyph,
+    capacity: usize,
+    out: *mut RsxShapedText
+) -> RsxStatus {
+    to_status(guarded(|| {
+        let group = to_ref(group)?;
+        let instance = to_ref(instance)?;
+        let out = out.as_mut().ok_or(RsxStatus::NullPointer)?;
+        let shaped = group
+            .fonts()
+            .borrow()
+            .shape_text_h(instance, to_str(text)?)?;
+
+        *out = RsxShapedText {
+            width_64: shaped.width_64(),
+            height_64: shaped.height_64(),
+            glyph_count: shaped.glyphs().len()
+        };
+        if shaped.glyphs().len() > capacity {
+            Err(RsxStatus::BufferTooSmall)?;
+        }
+        if glyphs.is_null() && capacity > 0 {
+            Err(RsxStatus::NullPointer)?;
+        }
+        for (i, glyph) in shaped.glyphs().iter().enumerate() {
+            *glyphs.add(i) = RsxGlyph {
+                glyph_index: glyph.glyph_index,
+                x_64: glyph.x_64,
+                y_64: glyph.y_64
+            };
+        }
+        Ok(())
+    }))
+}
+
+// Drains the pending image and font updates as a JSON array, to be released
+// with `rsx_string_free`. Returns null for a null group.
+#[no_mangle]
+pub unsafe extern "C" fn rsx_take_resource_updates_json(group: *const RsxResourceGroup) -> *mut c_char {
+    guarded(|| {
+        let group = to_ref(group)?;
+        Ok(take_resource_updates(group).into())
+    })
+    .unwrap_or(ptr::null_mut())
+}
+
+// Drains the pending updates using the encoding described in `binary`, or
+// returns an empty buffer for a null group.
+#[no_mangle]
+pub unsafe extern "C" fn rsx_take_resource_updates_binary(group: *const RsxResourceGroup) -> RsxBuffer {
+    guarded(|| {
+        let group = to_ref(group)?;
+        Ok(binary::encode_updates(&take_raw_resource_updates(group)).into())
+    })
+    .unwrap_or_else(|_| RsxBuffer::from(Vec::new()))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rsx_string_free(string: *mut c_char) {
+    let _ = catch_panic(|| {
+        if !string.is_null() {
+            drop(::std::ffi::CString::from_raw(string));
+        }
+        Ok(())
+    });
+}
+
+// The stable code of the cache error behind the last failed call on this
+// thread, as listed in `codes`, or 0 if there is none. Panics are reported
+// as `CAPI_PANIC`.
+#[no_mangle]
+pub extern "C" fn rsx_last_error_code() -> u32 {
+    catch_panic(|| Ok(last_error().map_or(0, |(code, _)| code))).unwrap_or(0)
+}
+
+// The message of that same error, to be released with `rsx_string_free`.
+// Returns null if there is none.
+#[no_mangle]
+pub extern "C" fn rsx_last_error_message() -> *mut c_char {
+    let message = catch_panic(|| Ok(last_error().and_then(|(_, message)| ::std::ffi::CString::new(message).ok())));
+    match message {
+        Ok(Some(message)) => message.into_raw(),
+        _ => ptr::null_mut()
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rsx_buffer_free(buffer: RsxBuffer) {
+    let _ = catch_panic(|| {
+        if !buffer.data.is_null() {
+            drop(buffer.into_vec());
+        }
+        Ok(())
+    });
+}
+
+pub(crate) fn load_file(group: &RsxResourceGroup, path: &str) -> Result<Rc<Vec<u8>>, RsxStatus> {
+    let files = group.files();
+    if let Ok(bytes) = files.borrow().get_file(path) {
+        return Ok(bytes);
+    }
+    files.borrow_mut().add_file(path)?;
+    let bytes = files.borrow().get_file(path)?;
+    Ok(bytes)
+}
+
+pub(crate) fn take_resource_updates(group: &RsxResourceGroup) -> RsxResourceUpdates {
+    let mut updates = group.images().take_resource_updates();
+    updates.merge(group.fonts().take_resource_updates());
+    updates
+}
+
+// The binary encoding carries fonts as raw bytes, see `binary`.
+pub(crate) fn take_raw_resource_updates(group: &RsxResourceGroup) -> RsxResourceUpdates {
+    let mut updates = group.images().take_resource_updates();
+    updates.merge(group.fonts().borrow_mut().take_raw_resource_updates());
+    updates
+}
@@ -0,0 +1,26 @@
+/*
+Copyright 2016 Mozilla
+Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+this file except in compliance with the License. You may obtain a copy of the
+License at http://www.apache.org/licenses/LICENSE-2.0
+Unless required by applicable law or agreed to in writing, software distributed
+under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+CONDITIONS OF ANY KIND, either express or implied. See the License for the
+specific language governing permissions and limitations under the License.
+*/
+
+#![cfg_attr(feature = "cargo-clippy", allow(match_ref_pats, missing_safety_doc))]
+
+#[cfg(feature = "node-bindings")]
+extern crate napi;
+#[cfg(feature = "node-bindings")]
+#[macro_use]
+extern crate napi_derive;
+extern crate rsx_resources;
+extern crate rsx_shared;
+
+pub mod binary;
+pub mod group;
+#[cfg(feature = "node-bindings")]
+pub mod node;
+pub mod types;
@@ -0,0 +1,363 @@
+/*
+Copyright 2016 Mozilla
+Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+this file except in compliance with the License. You may obtain a copy of the
+License at http://www.apache.org/licenses/LICENSE-2.0
+Unless required by applicable law or agreed to in writing, software distributed
+under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+CONDITIONS OF ANY KIND, either express or implied. See the License for the
+specific language governing permissions and limitations under the License.
+*/
+
+use napi::bindgen_prelude::Uint8Array;
+use napi::{Error, Result, Status};
+use rsx_resources::files::types::FileCache;
+use rsx_resources::fonts::types::FontCache;
+use rsx_resources::images::types::ImageCache;
+use rsx_resources::updates::shmem::SharedMemoryDescriptor;
+use rsx_resources::updates::types::{DefaultFontKeysAPI, DefaultImageKeysAPI, Update};
+use rsx_shared::traits::{TFontKeysAPI, TImageKeysAPI, TResourceGroup};
+
+use binary::RsxUpdate;
+use group::{load_file, take_resource_updates};
+use types::{clear_last_error, last_error, RsxResourceGroup, RsxStatus};
+
+// Updates are handed to JS as plain objects discriminated by `kind`, carrying
+// only the fields relevant to it. Keys are allocated sequentially, so they
+// stay well within the range of integers representable by JS numbers. Text
+// hashes don't, so they're passed as hex strings.
+#[napi(object)]
+pub struct JsSharedMemory {
+    pub kind: String,
+    pub fd: i32,
+    pub offset: i64,
+    pub len: i64
+}
+
+impl From<SharedMemoryDescriptor> for JsSharedMemory {
+    fn from(memory: SharedMemoryDescriptor) -> Self {
+        JsSharedMemory {
+            kind: format!("{:?}", memory.kind),
+            fd: memory.fd,
+            offset: memory.offset as i64,
+            len: memory.len as i64
+        }
+    }
+}
+
+#[napi(object)]
+pub struct JsResourceUpdate {
+    pub kind: String,
+    pub key: Option<i64>,
+    pub mask_key: Option<i64>,
+    pub instance_key: Option<i64>,
+    pub data_uri: Option<String>,
+    pub bytes: Option<Vec<u8>>,
+    pub memory: Option<JsSharedMemory>,
+    pub format: Option<String>,
+    pub orientation: Option<String>,
+    pub channel: Option<String>,
+    pub size: Option<u32>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub layers: Option<u32>,
+    pub layer: Option<u32>,
+    pub transform: Option<Vec<i32>>,
+    pub glyphs: Option<Vec<u32>>,
+    pub offsets: Option<Vec<u32>>,
+    pub text: Option<String>,
+    pub text_hash: Option<String>,
+    pub page: Option<u32>,
+    pub x: Option<u32>,
+    pub y: Option<u32>,
+    pub bytes_per_pixel: Option<u32>
+}
+
+impl JsResourceUpdate {
+    fn new(kind: &str) -> Self {
+        JsResourceUpdate {
+            kind: kind.to_string(),
+            key: None,
+            mask_key: None,
+            instance_key: None,
+            data_uri: None,
+            bytes: None,
+            memory: None,
+            format: None,
+            orientation: None,
+            channel: None,
+            size: None,
+            width: None,
+            height: None,
+            layers: None,
+            layer: None,
+            transform: None,
+            glyphs: None,
+            offsets: None,
+            text: None,
+            text_hash: None,
+            page: None,
+            x: None,
+            y: None,
+            bytes_per_pixel: None
+        }
+    }
+}
+
+impl<'a> From<&'a RsxUpdate> for JsResourceUpdate {
+    fn from(update: &'a RsxUpdate) -> Self {
+        match update {
+            &Update::AddImage {
+                key,
+                ref data_uri,
+                orientation
+            } => JsResourceUpdate {
+                key: Some(key.0 as i64),
+                data_uri: Some(data_uri.to_string()),
+                orientation: Some(format!("{:?}", orientation)),
+                ..JsResourceUpdate::new("AddImage")
+            },
+            &Update::AddImageMask {
+                key,
+                width,
+                height,
+                ref bytes,
+                orientation
+            } => JsResourceUpdate {
+                key: Some(key.0 as i64),
+                width: Some(width),
+                height: Some(height),
+                bytes: Some(bytes.to_vec()),
+                orientation: Some(format!("{:?}", orientation)),
+                ..JsResourceUpdate::new("AddImageMask")
+            },
+            &Update::AddImageArray {
+                key,
+                width,
+                height,
+                layers
+            } => JsResourceUpdate {
+                key: Some(key.0 as i64),
+                width: Some(width),
+                height: Some(height),
+                layers: Some(layers),
+                ..JsResourceUpdate::new("AddImageArray")
+            },
+            &Update::AddImageArrayLayer {
+                key,
+                layer,
+                ref data_uri
+            } => JsResourceUpdate {
+                key: Some(key.0 as i64),
+                layer: Some(layer),
+                data_uri: Some(data_uri.to_string()),
+                ..JsResourceUpdate::new("AddImageArrayLayer")
+            },
+            &Update::LinkImageMask { key, mask_key } => JsResourceUpdate {
+                key: Some(key.0 as i64),
+                mask_key: Some(mask_key.0 as i64),
+                ..JsResourceUpdate::new("LinkImageMask")
+            },
+            &Update::UpdateImage {
+                key,
+                ref data_uri,
+                orientation
+            } => JsResourceUpdate {
+                key: Some(key.0 as i64),
+                data_uri: Some(data_uri.to_string()),
+                orientation: Some(format!("{:?}", orientation)),
+                ..JsResourceUpdate::new("UpdateImage")
+            },
+            &Update::AddFont { key, ref data_uri } => JsResourceUpdate {
+                key: Some(key.0 as i64),
+                data_uri: Some(data_uri.to_string()),
+                ..JsResourceUpdate::new("AddFont")
+            },
+            &Update::AddFontBytes { key, ref bytes } => JsResourceUpdate {
+                key: Some(key.0 as i64),
+                bytes: Some(bytes.to_vec()),
+                ..JsResourceUpdate::new("AddFontBytes")
+            },
+            &Update::AddSharedImage {
+                key,
+                memory,
+                ref format,
+                orientation
+            } => JsResourceUpdate {
+                key: Some(key.0 as i64),
+                memory: Some(memory.into()),
+                format: Some(format.clone()),
+                orientation: Some(format!("{:?}", orientation)),
+                ..JsResourceUpdate::new("AddSharedImage")
+            },
+            &Update::AddSharedImageMask {
+                key,
+                memory,
+                ref format,
+                orientation,
+                channel
+            } => JsResourceUpdate {
+                key: Some(key.0 as i64),
+                memory: Some(memory.into()),
+                format: Some(format.clone()),
+                orientation: Some(format!("{:?}", orientation)),
+                channel: Some(format!("{:?}", channel)),
+                ..JsResourceUpdate::new("AddSharedImageMask")
+            },
+            &Update::AddSharedFont { key, memory } => JsResourceUpdate {
+                key: Some(key.0 as i64),
+                memory: Some(memory.into()),
+                ..JsResourceUpdate::new("AddSharedFont")
+            },
+            &Update::AddFontInstance {
+                key,
+                instance_key,
+                size,
+                transform
+            } => JsResourceUpdate {
+                key: Some(key.0 as i64),
+                instance_key: Some(instance_key.0 as i64),
+                size: Some(size),
+                transform: Some(vec![transform.xx, transform.xy, transform.yx, transform.yy]),
+                ..JsResourceUpdate::new("AddFontInstance")
+            },
+            &Update::AddGlyphRun {
+                instance_key,
+                ref glyphs,
+                ref source
+            } => JsResourceUpdate {
+                instance_key: Some(instance_key.0 as i64),
+                glyphs: Some(glyphs.clone()),
+                offsets: Some(source.offsets.to_vec()),
+                text: source.text().map(str::to_string),
+                text_hash: Some(format!("{:016x}", source.text_hash)),
+                ..JsResourceUpdate::new("AddGlyphRun")
+            },
+            &Update::AddGlyphAtlasPage {
+                page,
+                width,
+                height,
+                bytes_per_pixel
+            } => JsResourceUpdate {
+                page: Some(page),
+                width: Some(width),
+                height: Some(height),
+                bytes_per_pixel: Some(bytes_per_pixel),
+                ..JsResourceUpdate::new("AddGlyphAtlasPage")
+            },
+            &Update::UpdateGlyphAtlasRegion {
+                page,
+                x,
+                y,
+                width,
+                height,
+                ref data_uri
+            } => JsResourceUpdate {
+                page: Some(page),
+                x: Some(x),
+                y: Some(y),
+                width: Some(width),
+                height: Some(height),
+                data_uri: Some(data_uri.to_string()),
+                ..JsResourceUpdate::new("UpdateGlyphAtlasRegion")
+            },
+            &Update::DeleteGlyphAtlasPage { page } => JsResourceUpdate {
+                page: Some(page),
+                ..JsResourceUpdate::new("DeleteGlyphAtlasPage")
+            },
+            &Update::DeleteImage { key } => JsResourceUpdate {
+                key: Some(key.0 as i64),
+                ..JsResourceUpdate::new("DeleteImage")
+            },
+            &Update::DeleteFont { key } => JsResourceUpdate {
+                key: Some(key.0 as i64),
+                ..JsResourceUpdate::new("DeleteFont")
+            },
+            &Update::DeleteFontInstance { instance_key } => JsResourceUpdate {
+                instance_key: Some(instance_key.0 as i64),
+                ..JsResourceUpdate::new("DeleteFontInstance")
+            }
+        }
+    }
+}
+
+// Includes the stable code of the underlying cache error, if any, so scripts
+// can branch on it.
+fn to_js_error(status: RsxStatus) -> Error {
+    let reason = match last_error() {
+        Some((code, message)) => format!("{:?} [{}]: {}", status, code, message),
+        None => format!("{:?}", status)
+    };
+    clear_last_error();
+    Error::new(Status::GenericFailure, reason)
+}
+
+#[napi(js_name = "ResourceGroup")]
+pub struct JsResourceGroup {
+    group: RsxResourceGroup
+}
+
+#[napi]
+impl JsResourceGroup {
+    #[napi(constructor)]
+    pub fn new() -> Result<Self> {
+        let files = FileCache::new().map_err(|err| to_js_error(err.into()))?;
+        let images = ImageCache::new(DefaultImageKeysAPI::new(())).map_err(|err| to_js_error(err.into()))?;
+        let fonts = FontCache::new(DefaultFontKeysAPI::new(())).map_err(|err| to_js_error(err.into()))?;
+        Ok(JsResourceGroup {
+            group: RsxResourceGroup::new(files, images, fonts)
+        })
+    }
+
+    #[napi]
+    pub fn add_image_path(&self, path: String) -> Result<()> {
+        let bytes = load_file(&self.group, &path).map_err(to_js_error)?;
+        let id = self.group.images().borrow().image_id(&path);
+        self.group
+            .images()
+            .borrow_mut()
+            .add_raw(id, bytes)
+            .map_err(|err| to_js_error(err.into()))
+    }
+
+    // Accepts any typed array view, including node Buffers.
+    #[napi]
+    pub fn add_image_bytes(&self, src: String, bytes: Uint8Array) -> Result<()> {
+        let id = self.group.images().borrow().image_id(src);
+        self.group
+            .images()
+            .borrow_mut()
+            .add_raw(id, bytes.to_vec())
+            .map_err(|err| to_js_error(err.into()))
+    }
+
+    #[napi]
+    pub fn add_font_path(&self, font_name: String, path: String, face_index: Option<u32>) -> Result<()> {
+        let bytes = load_file(&self.group, &path).map_err(to_js_error)?;
+        let id = self.group.fonts().borrow().font_id(font_name);
+        self.group
+            .fonts()
+            .borrow_mut()
+            .add_raw(id, bytes, face_index.unwrap_or(0) as usize)
+            .map_err(|err| to_js_error(err.into()))
+    }
+
+    #[napi]
+    pub fn add_font_bytes(&self, font_name: String, bytes: Uint8Array, face_index: Option<u32>) -> Result<()> {
+        let id = self.group.fonts().borrow().font_id(font_name);
+        self.group
+            .fonts()
+            .borrow_mut()
+            .add_raw(id, bytes.to_vec(), face_index.unwrap_or(0) as usize)
+            .map_err(|err| to_js_error(err.into()))
+    }
+
+    #[napi]
+    pub fn take_resource_updates(&self) -> Vec<JsResourceUpdate> {
+        take_resource_updates(&self.group)
+            .updates
+            .iter()
+            .map(JsResourceUpdate::from)
+            .collect()
+    }
+}
@@ -0,0 +1,222 @@
+/*
+Copyright 2016 Mozilla
+Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+this file except in compliance with the License. You may obtain a copy of the
+License at http://www.apache.org/licenses/LICENSE-2.0
+Unless required by applicable law or agreed to in writing, software distributed
+under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+CONDITIONS OF ANY KIND, either express or implied. See the License for the
+specific language governing permissions and limitations under the License.
+*/
+
+use std::any::Any;
+use std::cell::RefCell;
+use std::ffi::CStr;
+use std::os::raw::c_char;
+use std::panic::{self, AssertUnwindSafe};
+use std::rc::Rc;
+
+use rsx_resources::files::error::FileError;
+use rsx_resources::fonts::error::FontError;
+use rsx_resources::fonts::types::TFontInstance;
+use rsx_resources::images::error::ImageError;
+use rsx_resources::lookup::LookupError;
+use rsx_resources::types::DefaultResourceGroup;
+use rsx_resources::updates::codes::{self, ErrorCode};
+use rsx_resources::updates::types::DefaultFontKeysAPI;
+
+// Handles are opaque to C callers, who only ever see pointers to them.
+pub type RsxResourceGroup = DefaultResourceGroup;
+pub type RsxFontInstance = TFontInstance<DefaultFontKeysAPI>;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RsxStatus {
+    Ok = 0,
+    NullPointer = 1,
+    InvalidUtf8 = 2,
+    NotFound = 3,
+    AlreadyAdded = 4,
+    DecodeFailed = 5,
+    BufferTooSmall = 6,
+    Panicked = 7
+}
+
+// The code and message of the cache error behind the last failed call on this
+// thread, for hosts wanting more than the coarse status. Cleared as each call
+// using the caches starts, so it never outlives the call which set it.
+thread_local! {
+    static LAST_ERROR: RefCell<Option<(ErrorCode, String)>> = RefCell::new(None);
+}
+
+pub(crate) fn set_last_error(code: ErrorCode, message: String) {
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some((code, message)));
+}
+
+pub(crate) fn clear_last_error() {
+    LAST_ERROR.with(|last| *last.borrow_mut() = None);
+}
+
+pub(crate) fn last_error() -> Option<(ErrorCode, String)> {
+    LAST_ERROR.with(|last| last.borrow().clone())
+}
+
+impl From<FileError> for RsxStatus {
+    fn from(err: FileError) -> Self {
+        set_last_error(err.code(), err.message());
+        match err {
+            FileError::FileAlreadyAdded => RsxStatus::AlreadyAdded,
+            FileError::FileNotFound | FileError::IOError(_) => RsxStatus::NotFound
+        }
+    }
+}
+
+impl From<ImageError> for RsxStatus {
+    fn from(err: ImageError) -> Self {
+        set_last_error(err.code(), err.message());
+        match err {
+            ImageError::ImageAlreadyAdded => RsxStatus::AlreadyAdded,
+            ImageError::ImageNotFound | ImageError::IOError(_) => RsxStatus::NotFound,
+            _ => RsxStatus::DecodeFailed
+        }
+    }
+}
+
+impl From<LookupError> for RsxStatus {
+    fn from(err: LookupError) -> Self {
+        set_last_error(err.code(), err.message());
+        RsxStatus::NotFound
+    }
+}
+
+impl From<FontError> for RsxStatus {
+    fn from(err: FontError) -> Self {
+        set_last_error(err.code(), err.message());
+        match err {
+            FontError::FaceAlreadyAdded | FontError::FontInstanceAlreadyAdded => RsxStatus::AlreadyAdded,
+            FontError::FaceNotFound | FontError::FaceGlyphMissing | FontError::IOError(_) => RsxStatus::NotFound,
+            _ => RsxStatus::DecodeFailed
+        }
+    }
+}
+
+// Byte buffers allocated by this library, released with `rsx_buffer_free`.
+#[repr(C)]
+#[derive(Debug)]
+pub struct RsxBuffer {
+    pub data: *mut u8,
+    pub len: usize,
+    pub capacity: usize
+}
+
+impl From<Vec<u8>> for RsxBuffer {
+    fn from(mut bytes: Vec<u8>) -> Self {
+        let buffer = RsxBuffer {
+            data: bytes.as_mut_ptr(),
+            len: bytes.len(),
+            capacity: bytes.capacity()
+        };
+        ::std::mem::forget(bytes);
+        buffer
+    }
+}
+
+impl RsxBuffer {
+    pub unsafe fn into_vec(self) -> Vec<u8> {
+        Vec::from_raw_parts(self.data, self.len, self.capacity)
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct RsxImageInfo {
+    pub width: u32,
+    pub height: u32,
+    pub display_width: u32,
+    pub display_height: u32,
+    pub scale: f32,
+    pub image_key: u64
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct RsxFontMetrics {
+    pub nominal_width: u16,
+    pub nominal_height: u16,
+    pub ascender_64: i32,
+    pub descender_64: i32,
+    pub height_64: i32,
+    pub max_advance_64: i32,
+    pub font_key: u64,
+    pub font_instance_key: u64
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct RsxGlyph {
+    pub glyph_index: u32,
+    pub x_64: i32,
+    pub y_64: i32
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct RsxShapedText {
+    pub width_64: i32,
+    pub height_64: i32,
+    pub glyph_count: usize
+}
+
+pub(crate) unsafe fn to_str<'a>(value: *const c_char) -> Result<&'a str, RsxStatus> {
+    if value.is_null() {
+        Err(RsxStatus::NullPointer)?;
+    }
+    CStr::from_ptr(value)
+        .to_str()
+        .map_err(|_| RsxStatus::InvalidUtf8)
+}
+
+pub(crate) unsafe fn to_bytes(bytes: *const u8, len: usize) -> Result<Rc<Vec<u8>>, RsxStatus> {
+    if bytes.is_null() {
+        Err(RsxStatus::NullPointer)?;
+    }
+    Ok(Rc::new(::std::slice::from_raw_parts(bytes, len).to_vec()))
+}
+
+pub(crate) unsafe fn to_ref<'a, T>(value: *const T) -> Result<&'a T, RsxStatus> {
+    value.as_ref().ok_or(RsxStatus::NullPointer)
+}
+
+pub(crate) fn to_status(result: Result<(), RsxStatus>) -> RsxStatus {
+    result.err().unwrap_or(RsxStatus::Ok)
+}
+
+// Runs the body of an exported function, turning a panic into `Panicked` with
+// its message as the last error, since unwinding into C is undefined behavior.
+pub fn catch_panic<T, F>(body: F) -> Result<T, RsxStatus>
+where
+    F: FnOnce() -> Result<T, RsxStatus>
+{
+    panic::catch_unwind(AssertUnwindSafe(body)).unwrap_or_else(|payload| {
+        set_last_error(codes::CAPI_PANIC, panic_message(&*payload));
+        Err(RsxStatus::Panicked)
+    })
+}
+
+// Like `catch_panic`, for calls using the caches, which first clear the last
+// error so that it's only set when they fail.
+pub fn guarded<T, F>(body: F) -> Result<T, RsxStatus>
+where
+    F: FnOnce() -> Result<T, RsxStatus>
+{
+    clear_last_error();
+    catch_panic(body)
+}
+
+fn panic_message(payload: &(Any + Send)) -> String {
+    match (payload.downcast_ref::<&str>(), payload.downcast_ref::<String>()) {
+        (Some(message), _) => message.to_string(),
+        (_, Some(message)) => message.clone(),
+        _ => String::new()
+    }
+}
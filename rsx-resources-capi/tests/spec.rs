@@ -0,0 +1,193 @@
+/*
+Copyright 2016 Mozilla
+Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+this file except in compliance with the License. You may obtain a copy of the
+License at http://www.apache.org/licenses/LICENSE-2.0
+Unless required by applicable law or agreed to in writing, software distributed
+under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+CONDITIONS OF ANY KIND, either express or implied. See the License for the
+specific language governing permissions and limitations under the License.
+*/
+
+extern crate rsx_resources;
+extern crate rsx_resources_capi;
+
+use std::ffi::{CStr, CString};
+use std::ptr;
+
+use rsx_resources::updates::codes;
+use rsx_resources_capi::binary::*;
+use rsx_resources_capi::group::*;
+use rsx_resources_capi::types::*;
+
+fn c_str(value: &str) -> CString {
+    CString::new(value).unwrap()
+}
+
+#[test]
+fn test_capi_images() {
+    unsafe {
+        let group = rsx_resource_group_new();
+        assert!(!group.is_null());
+
+        let path = c_str("../rsx-resource-group/tests/fixtures/Quantum.png");
+        assert_eq!(rsx_images_add_path(group, path.as_ptr()), RsxStatus::Ok);
+        assert_eq!(rsx_images_add_path(group, path.as_ptr()), RsxStatus::AlreadyAdded);
+        assert_eq!(rsx_images_add_path(group, ptr::null()), RsxStatus::NullPointer);
+        assert_eq!(rsx_images_add_path(ptr::null(), path.as_ptr()), RsxStatus::NullPointer);
+
+        let mut info = RsxImageInfo::default();
+        assert_eq!(rsx_images_get_info(group, path.as_ptr(), &mut info), RsxStatus::Ok);
+        assert_eq!((info.width, info.height), (512, 529));
+        assert_eq!(rsx_images_get_info(group, c_str("Missing.png").as_ptr(), &mut info), RsxStatus::NotFound);
+
+        let garbage = [1, 2, 3];
+        let src = c_str("garbage");
+        assert_eq!(
+            rsx_images_add_bytes(group, src.as_ptr(), garbage.as_ptr(), garbage.len()),
+            RsxStatus::DecodeFailed
+        );
+        assert!(codes::name(rsx_last_error_code()).unwrap().starts_with("IMAGE_"));
+        let message = rsx_last_error_message();
+        assert!(!message.is_null());
+        rsx_string_free(message);
+
+        assert_eq!(rsx_images_get_info(group, path.as_ptr(), &mut info), RsxStatus::Ok);
+        assert_eq!(rsx_last_error_code(), 0);
+        assert!(rsx_last_error_message().is_null());
+
+        let buffer = rsx_take_resource_updates_binary(group);
+        let bytes = ::std::slice::from_raw_parts(buffer.data, buffer.len);
+        assert_eq!(&bytes[..4], &[1, 0, 0, 0]);
+        assert_eq!(bytes[4], TAG_ADD_IMAGE);
+        rsx_buffer_free(buffer);
+
+        let json = rsx_take_resource_updates_json(group);
+        assert_eq!(CStr::from_ptr(json).to_str().unwrap(), "[]");
+        rsx_string_free(json);
+
+        rsx_resource_group_free(group);
+    }
+}
+
+#[test]
+fn test_capi_fonts() {
+    unsafe {
+        let group = rsx_resource_group_new();
+        let name = c_str("FreeSans");
+        let path = c_str("../rsx-resource-group/tests/fixtures/FreeSans.ttf");
+        assert_eq!(rsx_fonts_add_path(group, name.as_ptr(), path.as_ptr(), 0), RsxStatus::Ok);
+
+        let instance = rsx_fonts_get_instance(group, name.as_ptr(), 32, 72);
+        assert!(!instance.is_null());
+        assert!(rsx_fonts_get_instance(group, c_str("Missing").as_ptr(), 32, 72).is_null());
+
+        let mut metrics = RsxFontMetrics::default();
+        assert_eq!(rsx_fonts_get_metrics(group, instance, &mut metrics), RsxStatus::Ok);
+        assert_eq!(metrics.nominal_height, 32);
+
+        let text = c_str("Hello");
+        let mut shaped = RsxShapedText::default();
+        let mut glyphs = vec![RsxGlyph::default(); 2];
+        assert_eq!(
+            rsx_fonts_shape_text_h(group, instance, text.as_ptr(), glyphs.as_mut_ptr(), glyphs.len(), &mut shaped),
+            RsxStatus::BufferTooSmall
+        );
+        assert_eq!(shaped.glyph_count, 5);
+
+        glyphs.resize(shaped.glyph_count, RsxGlyph::default());
+        assert_eq!(
+            rsx_fonts_shape_text_h(group, instance, text.as_ptr(), glyphs.as_mut_ptr(), glyphs.len(), &mut shaped),
+            RsxStatus::Ok
+        );
+        assert!(shaped.width_64 > 0);
+        assert_eq!(glyphs[0].x_64, 0);
+        assert!(glyphs[1].x_64 > 0);
+        rsx_font_instance_release(instance);
+
+        let json = rsx_take_resource_updates_json(group);
+        let updates = CStr::from_ptr(json).to_str().unwrap();
+        assert!(updates.contains("AddFont"));
+        assert!(updates.contains("AddFontInstance"));
+        assert!(!updates.contains("AddFontBytes"));
+        rsx_string_free(json);
+
+        // The binary encoding takes fonts as raw bytes, unlike JSON.
+        let name = c_str("Fira Mono");
+        let path = c_str("../rsx-resource-group/tests/fixtures/FiraMono-Regular.ttf");
+        assert_eq!(rsx_fonts_add_path(group, name.as_ptr(), path.as_ptr(), 0), RsxStatus::Ok);
+        let buffer = rsx_take_resource_updates_binary(group);
+        let bytes = ::std::slice::from_raw_parts(buffer.data, buffer.len);
+        assert_eq!(bytes[4], TAG_ADD_FONT_BYTES);
+        rsx_buffer_free(buffer);
+
+        rsx_resource_group_free(group);
+    }
+}
+
+#[test]
+fn test_capi_last_errors() {
+    unsafe {
+        let group = rsx_resource_group_new();
+        let name = c_str("FreeSans");
+        let path = c_str("../rsx-resource-group/tests/fixtures/FreeSans.ttf");
+        assert_eq!(rsx_fonts_add_path(group, name.as_ptr(), path.as_ptr(), 0), RsxStatus::Ok);
+
+        // Instances which aren't found are the last error, until a call succeeds.
+        assert!(rsx_fonts_get_instance(group, c_str("Missing").as_ptr(), 32, 72).is_null());
+        assert_eq!(rsx_last_error_code(), codes::LOOKUP_FAMILY_NOT_REGISTERED);
+        let instance = rsx_fonts_get_instance(group, name.as_ptr(), 32, 72);
+        assert!(!instance.is_null());
+        assert_eq!(rsx_last_error_code(), 0);
+        rsx_font_instance_release(instance);
+
+        // Taking updates clears errors from earlier calls.
+        assert!(rsx_fonts_get_instance(group, c_str("Missing").as_ptr(), 32, 72).is_null());
+        let json = rsx_take_resource_updates_json(group);
+        assert!(!json.is_null());
+        assert_eq!(rsx_last_error_code(), 0);
+        rsx_string_free(json);
+
+        assert!(rsx_fonts_get_instance(group, c_str("Missing").as_ptr(), 32, 72).is_null());
+        let buffer = rsx_take_resource_updates_binary(group);
+        assert_eq!(rsx_last_error_code(), 0);
+        rsx_buffer_free(buffer);
+
+        assert!(rsx_fonts_get_instance(group, c_str("Missing").as_ptr(), 32, 72).is_null());
+        assert!(rsx_take_resource_updates_json(ptr::null()).is_null());
+        assert_eq!(rsx_last_error_code(), 0);
+
+        rsx_resource_group_free(group);
+    }
+}
+
+#[test]
+fn test_capi_panics() {
+    let result: Result<(), RsxStatus> = guarded(|| panic!("Unexpected state"));
+    assert_eq!(result, Err(RsxStatus::Panicked));
+    assert_eq!(rsx_last_error_code(), codes::CAPI_PANIC);
+    assert_eq!(codes::name(rsx_last_error_code()), Some("CAPI_PANIC"));
+
+    let message = rsx_last_error_message();
+    assert_eq!(unsafe { CStr::from_ptr(message) }.to_str().unwrap(), "Unexpected state");
+    unsafe { rsx_string_free(message) };
+
+    // Panics don't outlive the next call either.
+    assert_eq!(guarded(|| Ok(1)), Ok(1));
+    assert_eq!(rsx_last_error_code(), 0);
+}
+
+#[test]
+#[cfg(feature = "node-bindings")]
+fn test_node_updates() {
+    use rsx_resources::updates::types::DefaultFontInstanceKey;
+    use rsx_resources_capi::node::*;
+
+    let update = RsxUpdate::DeleteFontInstance {
+        instance_key: DefaultFontInstanceKey(3)
+    };
+    let js_update = JsResourceUpdate::from(&update);
+    assert_eq!(js_update.kind, "DeleteFontInstance");
+    assert_eq!(js_update.instance_key, Some(3));
+    assert_eq!(js_update.key, None);
+}